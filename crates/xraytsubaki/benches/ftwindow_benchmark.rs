@@ -0,0 +1,53 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use ndarray::Array1;
+use xraytsubaki::xafs::xafsutils::{ftwindow, FTWindow};
+use xraytsubaki::xafs::xrayfft::ftwindow_cached;
+
+// Compare the window computed for every transform with the memoized one with
+// `cargo bench --bench ftwindow_benchmark`.
+fn ftwindow_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ftwindow");
+
+    // k grid of xftf with the default kstep
+    let k = Array1::range(0.0, 20.0, 0.05);
+
+    for window in [FTWindow::Hanning, FTWindow::KaiserBessel] {
+        group.bench_with_input(
+            BenchmarkId::new("ftwindow", format!("{:?}", window)),
+            &k,
+            |b, k| {
+                b.iter(|| {
+                    black_box(ftwindow(
+                        k,
+                        Some(2.0),
+                        Some(15.0),
+                        Some(1.0),
+                        Some(1.0),
+                        Some(window),
+                    ))
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("ftwindow_cached", format!("{:?}", window)),
+            &k,
+            |b, k| {
+                b.iter(|| {
+                    black_box(ftwindow_cached(
+                        k,
+                        Some(2.0),
+                        Some(15.0),
+                        Some(1.0),
+                        Some(1.0),
+                        Some(window),
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ftwindow_benchmark);
+criterion_main!(benches);
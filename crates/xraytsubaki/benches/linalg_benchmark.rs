@@ -0,0 +1,67 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use nalgebra::DMatrix;
+use xraytsubaki::prelude::*;
+use xraytsubaki::xafs::lmutils::{covariance_from_jacobian_nalgebra_f64, svd_nalgebra_f64};
+
+pub const TOP_DIR: &str = env!("CARGO_MANIFEST_DIR");
+
+// Compare the native and LAPACK backends with
+// `cargo bench --bench linalg_benchmark` and
+// `cargo bench --bench linalg_benchmark --features lapack-openblas`.
+// The autobk group times the whole background removal: the backend only changes the post-fit
+// covariance of the spline, the Levenberg-Marquardt iterations are the same in both runs.
+fn linalg_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("svd");
+
+    // Jacobian shapes of AUTOBK: 2 * irbkg + 2 * nclamp residuals, nspl + 4 coefficients
+    for (nrows, ncols) in [(64, 32), (256, 132), (1024, 132), (4096, 512)] {
+        let jac = DMatrix::from_fn(nrows, ncols, |i, j| {
+            ((i * ncols + j) as f64 * 0.37).sin() + if i == j { 2.0 } else { 0.0 }
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("svd", format!("{}x{}", nrows, ncols)),
+            &jac,
+            |b, jac| b.iter(|| black_box(svd_nalgebra_f64(jac))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("covariance", format!("{}x{}", nrows, ncols)),
+            &jac,
+            |b, jac| b.iter(|| black_box(covariance_from_jacobian_nalgebra_f64(jac))),
+        );
+    }
+    group.finish();
+
+    let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+    let spectrum = io::load_spectrum_QAS_trans(&path).unwrap();
+
+    let mut group = c.benchmark_group("autobk");
+    for (nfft, nknots) in [(2048, 32), (8192, 64), (16384, 128)] {
+        group.bench_function(
+            BenchmarkId::new("calc_background", format!("nfft{}_nspl{}", nfft, nknots)),
+            |b| {
+                b.iter(|| {
+                    let mut spectrum = spectrum.clone();
+                    spectrum
+                        .set_background_method(Some(BackgroundMethod::AUTOBK(AUTOBK {
+                            nfft: Some(nfft),
+                            nknots: Some(nknots),
+                            ..Default::default()
+                        })))
+                        .unwrap();
+                    black_box(spectrum.calc_background().unwrap().get_chi());
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = linalg_benchmark
+}
+
+criterion_main!(benches);
@@ -7,5 +7,7 @@ pub use crate::xafs::lmutils::LMParameters;
 // pub use crate::xafs::mathutils;
 pub use crate::xafs::normalization::{Normalization, NormalizationMethod};
 pub use crate::xafs::nshare::{ToNalgebra, ToNdarray1};
+pub use crate::xafs::plot::{PlotData, PlotKind, Space, SpectrumView};
+pub use crate::xafs::presets::Preset;
 pub use crate::xafs::xafsutils::{FTWindow, XAFSUtils};
 pub use crate::xafs::xrayfft::{FFTUtils, XrayFFTF, XrayFFTR};
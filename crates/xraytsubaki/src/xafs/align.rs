@@ -0,0 +1,564 @@
+//! Energy calibration and alignment of spectra.
+//!
+//! The calibration shifts the energy axis so that the maximum of dmu/dE lies at a given edge
+//! energy, e.g. the tabulated edge of a reference foil (XASSpectrum::calibrate), or at the
+//! maximum of dmu/dE of a reference spectrum (XASSpectrum::calibrate_to). XASGroup::calibrate
+//! applies the shift found for a reference foil to every spectrum measured with it.
+//!
+//! The correlation alignment compares the edge region of each spectrum, normalized mu or
+//! dmu/dE (see AlignmentSignal), with a reference spectrum and finds the energy shift
+//! maximizing their correlation. Unlike the alignment of derivative peaks, it uses the whole
+//! edge region and stays robust for noisy data.
+
+use std::error::Error;
+
+use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::mathutils::MathUtils;
+use super::xafsutils;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Metadata key of the total energy shift applied by XASSpectrum::shift_energy (eV)
+pub const ENERGY_SHIFT_KEY: &str = "energy_shift";
+
+/// Signal compared by the alignment by correlation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlignmentSignal {
+    /// Normalized mu, the spectra are normalized first if needed
+    #[default]
+    Normalized,
+    /// dmu/dE, independent of the normalization
+    Derivative,
+}
+
+/// Parameters of the alignment by correlation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorrelationAlignment {
+    /// Start of the compared region relative to e0 of the reference. Default = -20 eV.
+    pub emin: Option<f64>,
+    /// End of the compared region relative to e0 of the reference. Default = 50 eV.
+    pub emax: Option<f64>,
+    /// Largest shift searched in both directions. Default = 10 eV.
+    pub max_shift: Option<f64>,
+    /// Step of the energy grid and of the coarse shift search. Default = 0.1 eV.
+    pub step: Option<f64>,
+    /// Compared signal. Default = AlignmentSignal::Normalized.
+    pub signal: Option<AlignmentSignal>,
+}
+
+impl Default for CorrelationAlignment {
+    fn default() -> Self {
+        CorrelationAlignment {
+            emin: Some(-20.0),
+            emax: Some(50.0),
+            max_shift: Some(10.0),
+            step: Some(0.1),
+            signal: Some(AlignmentSignal::Normalized),
+        }
+    }
+}
+
+impl CorrelationAlignment {
+    pub fn new() -> CorrelationAlignment {
+        CorrelationAlignment::default()
+    }
+
+    pub fn set_signal(&mut self, signal: Option<AlignmentSignal>) -> &mut Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Energy shift to be added to the energy of `spectrum` to align it to `reference`.
+    ///
+    /// Both spectra have to be normalized if the normalized mu is compared. The region is
+    /// placed around e0 of the reference, or the maximum of its dmu/dE if e0 is not set.
+    pub fn find_shift(
+        &self,
+        reference: &XASSpectrum,
+        spectrum: &XASSpectrum,
+    ) -> Result<f64, Box<dyn Error>> {
+        let default = CorrelationAlignment::default();
+        let emin = self.emin.or(default.emin).unwrap();
+        let emax = self.emax.or(default.emax).unwrap();
+        let max_shift = self.max_shift.or(default.max_shift).unwrap().abs();
+        let step = self.step.or(default.step).unwrap().abs();
+        let signal = self.signal.or(default.signal).unwrap();
+
+        let e0 = match reference
+            .get_e0()
+            .or_else(|| reference.normalization.as_ref()?.get_e0())
+        {
+            Some(e0) => e0,
+            None => derivative_peak(reference)?,
+        };
+        let (ref_energy, ref_norm) = signal.of(reference)?;
+        let (energy, norm) = signal.of(spectrum)?;
+
+        let grid = Array1::range(
+            xafsutils::absolute_energy(e0, emin),
+            xafsutils::absolute_energy(e0, emax),
+            step,
+        );
+        if grid.len() < 3 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let ref_values = grid.interpolate(&ref_energy, &ref_norm)?;
+
+        let correlation = |shift: f64| -> Result<f64, Box<dyn Error>> {
+            let values = (&grid - shift).interpolate(&energy, &norm)?;
+            Ok(pearson_correlation(&ref_values, &values))
+        };
+
+        let nshift = (max_shift / step).round() as i64;
+        let shifts = (-nshift..=nshift)
+            .map(|i| i as f64 * step)
+            .collect::<Vec<f64>>();
+        let correlations = shifts
+            .iter()
+            .map(|shift| correlation(*shift))
+            .collect::<Result<Vec<f64>, _>>()?;
+
+        let ibest = correlations.argmax();
+
+        if ibest == 0 || ibest == shifts.len() - 1 {
+            return Ok(shifts[ibest]);
+        }
+
+        // Refine the maximum by a parabola through the neighbouring points
+        let (c0, c1, c2) = (
+            correlations[ibest - 1],
+            correlations[ibest],
+            correlations[ibest + 1],
+        );
+        let denominator = c0 - 2.0 * c1 + c2;
+
+        if denominator >= 0.0 {
+            return Ok(shifts[ibest]);
+        }
+
+        Ok(shifts[ibest] + 0.5 * step * (c0 - c2) / denominator)
+    }
+}
+
+impl XASGroup {
+    /// Align all spectra to the spectrum at `reference` by maximizing the correlation of their
+    /// edge regions.
+    ///
+    /// If the normalized mu is compared, spectra which are not normalized yet are normalized
+    /// first. The energies and e0 of each spectrum are shifted only if the shifts of all spectra
+    /// were found; normalization, background and FT have to be recalculated afterwards.
+    /// Returns the shift applied to each spectrum.
+    pub fn align_by_correlation(
+        &mut self,
+        reference: usize,
+        params: &CorrelationAlignment,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        if reference >= self.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        let normalize = params.signal.unwrap_or_default() == AlignmentSignal::Normalized;
+
+        if normalize {
+            self.spectra
+                .par_iter_mut()
+                .enumerate()
+                .filter(|(_, spectrum)| spectrum.get_normalized_spectrum().is_none())
+                .try_for_each(|(i, spectrum)| {
+                    spectrum
+                        .normalize()
+                        .map(|_| ())
+                        .map_err(|e| format!("spectrum {}: {}", i, e))
+                })?;
+        }
+
+        // All shifts are found before any spectrum is changed
+        let reference_spectrum = &self.spectra[reference];
+        let shifts = self
+            .spectra
+            .par_iter()
+            .enumerate()
+            .map(|(i, spectrum)| match i == reference {
+                true => Ok(0.0),
+                false => params
+                    .find_shift(reference_spectrum, spectrum)
+                    .map_err(|e| format!("spectrum {}: {}", i, e)),
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        self.shift_all(&shifts)?;
+
+        Ok(shifts)
+    }
+
+    /// Calibrate the spectrum at `reference`, typically a reference foil, to `edge_energy` and
+    /// apply the same shift to all other spectra.
+    ///
+    /// Returns the shift. The shift is checked before any spectrum is changed, so that on error
+    /// the group is left as it was. See XASSpectrum::calibrate.
+    pub fn calibrate(&mut self, reference: usize, edge_energy: f64) -> Result<f64, Box<dyn Error>> {
+        let shift = edge_energy
+            - derivative_peak(
+                self.spectra
+                    .get(reference)
+                    .ok_or(XAFSError::GroupIndexOutOfRange)?,
+            )?;
+
+        self.shift_all(&vec![shift; self.len()])?;
+
+        Ok(shift)
+    }
+
+    // Relabel the energies of each spectrum by its shift. The shifts are checked first, so
+    // that either all spectra are shifted or none.
+    fn shift_all(&mut self, shifts: &[f64]) -> Result<(), Box<dyn Error>> {
+        if let Some(shift) = shifts.iter().find(|shift| !shift.is_finite()) {
+            return Err(format!("invalid energy shift: {}", shift).into());
+        }
+
+        for (spectrum, shift) in self.spectra.iter_mut().zip(shifts) {
+            spectrum.shift_energy(*shift, false)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl AlignmentSignal {
+    fn of(&self, spectrum: &XASSpectrum) -> Result<(Vec<f64>, Vec<f64>), Box<dyn Error>> {
+        match self {
+            AlignmentSignal::Normalized => {
+                let (energy, norm) = spectrum
+                    .get_normalized_spectrum()
+                    .ok_or(XAFSError::NotEnoughData)?;
+                Ok((energy.to_vec(), norm.to_vec()))
+            }
+            AlignmentSignal::Derivative => {
+                let energy = spectrum.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+                let mu = spectrum.mu.as_ref().ok_or(XAFSError::NotEnoughData)?;
+                if energy.len() != mu.len() || energy.len() < 3 {
+                    return Err(Box::new(XAFSError::NotEnoughData));
+                }
+
+                let derivative = mu.gradient() / energy.gradient();
+                Ok((energy.to_vec(), derivative.to_vec()))
+            }
+        }
+    }
+}
+
+// Energy of the maximum of dmu/dE, found with the find_e0 options of the spectrum
+fn derivative_peak(spectrum: &XASSpectrum) -> Result<f64, Box<dyn Error>> {
+    let energy = spectrum.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+    let mu = spectrum.mu.clone().ok_or(XAFSError::NotEnoughData)?;
+
+    spectrum.processing_options.find_e0(energy, mu)
+}
+
+impl XASSpectrum {
+    /// Shift the energy axis so that the maximum of dmu/dE lies at `edge_energy`, e.g. the
+    /// tabulated edge energy of a reference foil.
+    ///
+    /// The spectrum is relabelled as by shift_energy without resampling. Returns the shift.
+    pub fn calibrate(&mut self, edge_energy: f64) -> Result<f64, Box<dyn Error>> {
+        let shift = edge_energy - derivative_peak(self)?;
+        self.shift_energy(shift, false)?;
+
+        Ok(shift)
+    }
+
+    /// Calibrate the spectrum so that the maxima of dmu/dE of the spectrum and `reference`
+    /// coincide. Returns the shift.
+    pub fn calibrate_to(&mut self, reference: &XASSpectrum) -> Result<f64, Box<dyn Error>> {
+        self.calibrate(derivative_peak(reference)?)
+    }
+
+    /// Shift the spectrum by `shift` eV, so that a feature at E moves to E + shift.
+    ///
+    /// Without `resample`, the energy axis is relabelled: raw_energy, energy and e0 are shifted
+    /// and mu is unchanged. With `resample`, the energies stay on their grid and mu, delta_mu
+    /// and the channels are interpolated from the shifted spectrum; points beyond the shifted
+    /// data take the value at its nearest end. e0 moves with the features in both cases.
+    ///
+    /// The total shift applied is recorded in the metadata under ENERGY_SHIFT_KEY.
+    /// Normalization, background and FT have to be recalculated afterwards.
+    pub fn shift_energy(
+        &mut self,
+        shift: f64,
+        resample: bool,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if !shift.is_finite() {
+            return Err(format!("invalid energy shift: {}", shift).into());
+        }
+
+        if resample {
+            if let (Some(raw_energy), Some(raw_mu)) =
+                (self.raw_energy.as_ref(), self.raw_mu.as_ref())
+            {
+                let raw_mu = resample_shifted(raw_energy, raw_mu, shift)?;
+                let channels = self
+                    .channels
+                    .iter()
+                    .map(|(name, channel)| {
+                        Ok((name.clone(), resample_shifted(raw_energy, channel, shift)?))
+                    })
+                    .collect::<Result<_, Box<dyn Error>>>()?;
+                self.raw_mu = Some(raw_mu);
+                self.channels = channels;
+            }
+
+            if let Some(energy) = self.energy.as_ref() {
+                let mu = match self.mu.as_ref() {
+                    Some(mu) => Some(resample_shifted(energy, mu, shift)?),
+                    None => None,
+                };
+                let delta_mu = match self.delta_mu.as_ref() {
+                    Some(delta_mu) => Some(resample_shifted(energy, delta_mu, shift)?),
+                    None => None,
+                };
+                self.mu = mu;
+                self.delta_mu = delta_mu;
+            }
+        } else {
+            if let Some(raw_energy) = self.raw_energy.as_mut() {
+                *raw_energy += shift;
+            }
+
+            if let Some(energy) = self.energy.as_mut() {
+                *energy += shift;
+            }
+        }
+
+        if let Some(e0) = self.e0 {
+            self.e0 = Some(e0 + shift);
+        }
+
+        if let Some(normalization) = self.normalization.as_mut() {
+            let e0 = normalization.get_e0().map(|e0| e0 + shift);
+            normalization.set_e0(e0);
+        }
+
+        let total = self
+            .get_metadata(ENERGY_SHIFT_KEY)
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.0)
+            + shift;
+        self.set_metadata(ENERGY_SHIFT_KEY, total);
+
+        Ok(self)
+    }
+}
+
+// values(E - shift) on `grid`, from the points of increasing energy with finite values
+fn resample_shifted(
+    grid: &Array1<f64>,
+    values: &Array1<f64>,
+    shift: f64,
+) -> Result<Array1<f64>, Box<dyn Error>> {
+    let mut knots = Vec::with_capacity(grid.len());
+    let mut knot_values = Vec::with_capacity(grid.len());
+    for (&e, &value) in grid.iter().zip(values.iter()) {
+        if e.is_finite() && value.is_finite() && knots.last().is_none_or(|&last| e > last) {
+            knots.push(e);
+            knot_values.push(value);
+        }
+    }
+
+    if knots.len() < 2 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    Ok((grid - shift).interpolate(&knots, &knot_values)?)
+}
+
+fn pearson_correlation(
+    x: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    y: &ArrayBase<OwnedRepr<f64>, Ix1>,
+) -> f64 {
+    let x = x - x.mean().unwrap_or(0.0);
+    let y = y - y.mean().unwrap_or(0.0);
+
+    let denominator = ((&x * &x).sum() * (&y * &y).sum()).sqrt();
+
+    if denominator == 0.0 {
+        return 0.0;
+    }
+
+    (&x * &y).sum() / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const SHIFT_TOL: f64 = 2e-2;
+
+    fn edge(energy: &Array1<f64>, e0: f64) -> Array1<f64> {
+        energy.mapv(|e| {
+            0.2 + 1.0 / (1.0 + (-(e - e0) / 1.5).exp())
+                + 0.1 * (-(e - e0 - 8.0).powi(2) / 8.0).exp()
+                - 2e-4 * (e - e0)
+        })
+    }
+
+    #[test]
+    fn test_align_by_correlation() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::range(8800.0, 9300.0, 0.5);
+
+        // deterministic noise
+        let noise =
+            Array1::from_iter((0..energy.len()).map(|i| 5e-3 * ((i * 7919) % 13) as f64 / 13.0));
+
+        let mut reference = XASSpectrum::new();
+        reference.set_spectrum(energy.clone(), edge(&energy, 8979.0));
+
+        let mut shifted = XASSpectrum::new();
+        shifted.set_spectrum(energy.clone(), edge(&energy, 8980.3) + &noise);
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(reference).add_spectrum(shifted);
+
+        let shifts = group.align_by_correlation(0, &CorrelationAlignment::new())?;
+
+        assert_eq!(shifts[0], 0.0);
+        assert_abs_diff_eq!(shifts[1], -1.3, epsilon = SHIFT_TOL);
+        assert_abs_diff_eq!(
+            group.spectra[1].energy.as_ref().unwrap()[0],
+            8800.0 + shifts[1],
+            epsilon = 1e-10
+        );
+
+        assert!(group
+            .align_by_correlation(5, &CorrelationAlignment::new())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_align_by_derivative() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::range(8800.0, 9300.0, 0.5);
+
+        let mut reference = XASSpectrum::new();
+        reference.set_spectrum(energy.clone(), edge(&energy, 8979.0));
+
+        // The derivative does not depend on the scale and offset of mu
+        let mut shifted = XASSpectrum::new();
+        shifted.set_spectrum(energy.clone(), edge(&energy, 8980.3) * 3.0 + 1.0);
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(reference).add_spectrum(shifted);
+
+        let mut params = CorrelationAlignment::new();
+        params.set_signal(Some(AlignmentSignal::Derivative));
+        let shifts = group.align_by_correlation(0, &params)?;
+
+        assert_abs_diff_eq!(shifts[1], -1.3, epsilon = SHIFT_TOL);
+        assert!(group.spectra.iter().all(|s| s.normalization.is_none()));
+
+        // No spectrum is shifted if the shift of one of them cannot be found
+        let mut short = XASSpectrum::new();
+        short.set_spectrum(Array1::from_vec(vec![8900.0, 8901.0]), Array1::zeros(2));
+        group.add_spectrum(short);
+        let energy = group.spectra[1].energy.clone();
+        let error = group.align_by_correlation(0, &params).unwrap_err();
+        assert!(error.to_string().starts_with("spectrum 2"));
+        assert_eq!(group.spectra[1].energy, energy);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calibrate() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::range(8900.0, 9100.0, 0.1);
+
+        let mut foil = XASSpectrum::new();
+        foil.set_spectrum(energy.clone(), edge(&energy, 8980.3));
+        let mut sample = XASSpectrum::new();
+        sample.set_spectrum(energy.clone(), edge(&energy, 8990.0));
+
+        let mut calibrated = foil.clone();
+        let shift = calibrated.calibrate(8979.0)?;
+        // find_e0 resolves the derivative maximum to about one step of the grid
+        assert_abs_diff_eq!(shift, -1.3, epsilon = 0.15);
+        assert_abs_diff_eq!(derivative_peak(&calibrated)?, 8979.0, epsilon = 1e-6);
+
+        let mut aligned = sample.clone();
+        let shift = aligned.calibrate_to(&calibrated)?;
+        assert_abs_diff_eq!(shift, -11.0, epsilon = 0.15);
+
+        // The shift of the foil is applied to the sample measured with it
+        let mut group = XASGroup::new();
+        group.add_spectrum(sample).add_spectrum(foil);
+        let shift = group.calibrate(1, 8979.0)?;
+        assert_abs_diff_eq!(derivative_peak(&group.spectra[1])?, 8979.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(
+            group.spectra[0].energy.as_ref().unwrap()[0],
+            8900.0 + shift,
+            epsilon = 1e-10
+        );
+
+        assert!(group.calibrate(2, 8979.0).is_err());
+
+        // A failed calibration leaves every spectrum where it was
+        let before = group.clone();
+        assert!(group.calibrate(1, f64::NAN).is_err());
+        for (spectrum, previous) in group.spectra.iter().zip(before.spectra.iter()) {
+            assert_eq!(spectrum.energy, previous.energy);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_energy() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::range(8800.0, 9300.0, 0.5);
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy.clone(), edge(&energy, 8979.0));
+        spectrum
+            .channels
+            .insert("i0".to_string(), energy.mapv(|e| e - 8800.0));
+        spectrum.set_e0(8979.0);
+
+        let mut relabelled = spectrum.clone();
+        relabelled.shift_energy(1.0, false)?;
+        assert_eq!(relabelled.energy.as_ref().unwrap()[0], 8801.0);
+        assert_eq!(relabelled.mu, spectrum.mu);
+        assert_eq!(relabelled.get_e0(), Some(8980.0));
+
+        let mut resampled = spectrum.clone();
+        resampled.shift_energy(1.0, true)?;
+        assert_eq!(resampled.energy, spectrum.energy);
+        assert_eq!(resampled.get_e0(), Some(8980.0));
+        let expected = edge(&(&energy - 1.0), 8979.0);
+        let mu = resampled.mu.as_ref().unwrap();
+        let max_diff = (2..energy.len())
+            .map(|i| (mu[i] - expected[i]).abs())
+            .fold(0.0, f64::max);
+        assert!(max_diff < 1e-12);
+        assert_abs_diff_eq!(
+            resampled.get_channel("i0").unwrap()[10],
+            4.0,
+            epsilon = 1e-12
+        );
+        // The first points take the value at the start of the data
+        assert_eq!(mu[0], spectrum.mu.as_ref().unwrap()[0]);
+
+        resampled.shift_energy(-0.25, true)?;
+        assert_eq!(
+            resampled.get_metadata(ENERGY_SHIFT_KEY),
+            Some(&serde_json::json!(0.75))
+        );
+
+        assert!(spectrum.shift_energy(f64::NAN, false).is_err());
+
+        Ok(())
+    }
+}
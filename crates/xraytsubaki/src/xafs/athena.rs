@@ -0,0 +1,258 @@
+//! Processing with the defaults of Athena.
+//!
+//! process_athena_like runs normalization, AUTOBK and the forward FT in one call with the
+//! parameters a new Athena project starts with, so that results can be compared with Athena
+//! without setting every parameter by hand. The parameters differ from the xraylarch defaults
+//! used elsewhere in the crate in the pre-edge range, the high-energy clamp and the k weights.
+//!
+//! | step          | parameter               | value            |
+//! |---------------|-------------------------|------------------|
+//! | normalization | pre-edge range          | -150 to -30 eV   |
+//! |               | normalization range     | 150 eV to end    |
+//! |               | post-edge polynomial    | quadratic        |
+//! | background    | rbkg                    | 1.0 Å            |
+//! |               | k weight                | 2                |
+//! |               | clamps (low, high)      | 0, 24 ("strong") |
+//! | forward FT    | k range                 | 2 to 15 Å^-1     |
+//! |               | dk, window              | 1, Hanning       |
+//! |               | k weight                | 2                |
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use super::background::{BackgroundMethod, AUTOBK};
+use super::normalization::{NormalizationMethod, PrePostEdge};
+use super::xafsutils::FTWindow;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::XrayFFTF;
+
+/// Parameters of the Athena-like pipeline. The defaults are those of Athena.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AthenaParameters {
+    /// Edge energy. Found with find_e0 if None.
+    pub e0: Option<f64>,
+    pub pre_edge_start: Option<f64>,
+    pub pre_edge_end: Option<f64>,
+    pub norm_start: Option<f64>,
+    /// End of the normalization range. The end of the data if None.
+    pub norm_end: Option<f64>,
+    pub norm_polyorder: Option<i32>,
+    pub rbkg: Option<f64>,
+    pub bkg_kweight: Option<i32>,
+    pub clamp_lo: Option<i32>,
+    pub clamp_hi: Option<i32>,
+    pub kmin: Option<f64>,
+    pub kmax: Option<f64>,
+    pub dk: Option<f64>,
+    pub window: Option<FTWindow>,
+    pub kweight: Option<f64>,
+}
+
+impl Default for AthenaParameters {
+    fn default() -> Self {
+        AthenaParameters {
+            e0: None,
+            pre_edge_start: Some(-150.0),
+            pre_edge_end: Some(-30.0),
+            norm_start: Some(150.0),
+            norm_end: None,
+            norm_polyorder: Some(2),
+            rbkg: Some(1.0),
+            bkg_kweight: Some(2),
+            clamp_lo: Some(0),
+            clamp_hi: Some(24),
+            kmin: Some(2.0),
+            kmax: Some(15.0),
+            dk: Some(1.0),
+            window: Some(FTWindow::Hanning),
+            kweight: Some(2.0),
+        }
+    }
+}
+
+impl AthenaParameters {
+    pub fn new() -> AthenaParameters {
+        AthenaParameters::default()
+    }
+
+    pub fn set_e0(&mut self, e0: Option<f64>) -> &mut Self {
+        self.e0 = e0;
+        self
+    }
+
+    pub fn set_pre_edge_range(&mut self, start: Option<f64>, end: Option<f64>) -> &mut Self {
+        self.pre_edge_start = start;
+        self.pre_edge_end = end;
+        self
+    }
+
+    pub fn set_norm_range(&mut self, start: Option<f64>, end: Option<f64>) -> &mut Self {
+        self.norm_start = start;
+        self.norm_end = end;
+        self
+    }
+
+    pub fn set_norm_polyorder(&mut self, norm_polyorder: Option<i32>) -> &mut Self {
+        self.norm_polyorder = norm_polyorder;
+        self
+    }
+
+    pub fn set_background(
+        &mut self,
+        rbkg: Option<f64>,
+        bkg_kweight: Option<i32>,
+        clamp_lo: Option<i32>,
+        clamp_hi: Option<i32>,
+    ) -> &mut Self {
+        self.rbkg = rbkg;
+        self.bkg_kweight = bkg_kweight;
+        self.clamp_lo = clamp_lo;
+        self.clamp_hi = clamp_hi;
+        self
+    }
+
+    pub fn set_fft(
+        &mut self,
+        kmin: Option<f64>,
+        kmax: Option<f64>,
+        dk: Option<f64>,
+        kweight: Option<f64>,
+    ) -> &mut Self {
+        self.kmin = kmin;
+        self.kmax = kmax;
+        self.dk = dk;
+        self.kweight = kweight;
+        self
+    }
+
+    /// Normalize, remove the background and Fourier transform the spectrum with these
+    /// parameters. Existing processing parameters of the spectrum are replaced.
+    pub fn process<'a>(
+        &self,
+        spectrum: &'a mut XASSpectrum,
+    ) -> Result<&'a mut XASSpectrum, Box<dyn Error>> {
+        let default = AthenaParameters::default();
+
+        if let Some(e0) = self.e0 {
+            spectrum.set_e0(e0);
+        }
+
+        let mut pre_post_edge = PrePostEdge::new();
+        pre_post_edge.pre_edge_start = self.pre_edge_start.or(default.pre_edge_start);
+        pre_post_edge.pre_edge_end = self.pre_edge_end.or(default.pre_edge_end);
+        pre_post_edge.norm_start = self.norm_start.or(default.norm_start);
+        pre_post_edge.norm_polyorder = self.norm_polyorder.or(default.norm_polyorder);
+        if self.norm_end.is_some() {
+            pre_post_edge.norm_end = self.norm_end;
+        }
+
+        let autobk = AUTOBK {
+            rbkg: self.rbkg.or(default.rbkg),
+            kweight: self.bkg_kweight.or(default.bkg_kweight),
+            clamp_lo: self.clamp_lo.or(default.clamp_lo),
+            clamp_hi: self.clamp_hi.or(default.clamp_hi),
+            ..AUTOBK::default()
+        };
+
+        let xftf = XrayFFTF {
+            kmin: self.kmin.or(default.kmin),
+            kmax: self.kmax.or(default.kmax),
+            dk: self.dk.or(default.dk),
+            window: self.window.or(default.window),
+            kweight: self.kweight.or(default.kweight),
+            ..XrayFFTF::default()
+        };
+
+        spectrum
+            .set_normalization_method(Some(NormalizationMethod::PrePostEdge(pre_post_edge)))?
+            .normalize()?
+            .set_background_method(Some(BackgroundMethod::AUTOBK(autobk)))?
+            .calc_background()?;
+
+        spectrum.xftf = Some(xftf);
+        spectrum.fft()?;
+
+        Ok(spectrum)
+    }
+}
+
+/// Process the spectrum end-to-end with the defaults of Athena (see AthenaParameters).
+///
+/// # Example
+/// ```no_run
+/// use xraytsubaki::xafs::athena::process_athena_like;
+/// use xraytsubaki::xafs::io;
+///
+/// let mut spectrum = io::load_spectrum_QAS_trans(&"Ru_QAS.dat".to_string()).unwrap();
+/// process_athena_like(&mut spectrum).unwrap();
+///
+/// let chir_mag = spectrum.get_chir_mag().unwrap();
+/// ```
+pub fn process_athena_like(spectrum: &mut XASSpectrum) -> Result<&mut XASSpectrum, Box<dyn Error>> {
+    AthenaParameters::default().process(spectrum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::mathutils::MathUtils;
+    use crate::xafs::tests::PARAM_LOADTXT;
+    use crate::xafs::tests::TOP_DIR;
+    use data_reader::reader::load_txt_f64;
+    use ndarray::Array1;
+
+    const CHI_MSE_TOL: f64 = 1e-4;
+
+    #[test]
+    fn test_process_athena_like() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut defaults = spectrum.clone();
+        process_athena_like(&mut defaults)?;
+        assert!(defaults.get_chir_mag().is_some());
+        assert_eq!(defaults.get_kweight(), Some(&2.0));
+
+        // Parameters from the header of the Athena export
+        let mut parameters = AthenaParameters::new();
+        parameters
+            .set_e0(Some(22118.8))
+            .set_pre_edge_range(Some(-200.0), Some(-65.0))
+            .set_norm_range(Some(25.0), Some(944.533172))
+            .set_norm_polyorder(Some(1))
+            .set_background(Some(1.0), Some(1), Some(0), Some(24))
+            .set_fft(Some(2.0), Some(15.0), Some(1.0), Some(1.0));
+
+        let mut athena_like = spectrum.clone();
+        parameters.process(&mut athena_like)?;
+
+        let athena_path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS_athena_k_chi.dat";
+        let athena = load_txt_f64(&athena_path, &PARAM_LOADTXT).unwrap();
+        let k_athena = athena.get_col(0);
+        let chi_athena = athena.get_col(1);
+
+        let edge_step = athena_like
+            .normalization
+            .as_ref()
+            .unwrap()
+            .get_edge_step()
+            .unwrap();
+        assert!((edge_step - 0.8614324).abs() / 0.8614324 < 0.01);
+
+        // k-weighted chi in the FT range
+        let k = Array1::from_iter(k_athena.iter().cloned().filter(|k| *k >= 2.0 && *k <= 15.0));
+        let expected = k.interpolate(&k_athena, &chi_athena)?;
+        let chi = k.interpolate(
+            &athena_like.get_k().unwrap().to_vec(),
+            &athena_like.get_chi().unwrap().to_vec(),
+        )?;
+        let mse = ((&chi - &expected) * &k).mapv(|x| x * x).sum() / k.len() as f64;
+
+        assert!(mse < CHI_MSE_TOL);
+
+        Ok(())
+    }
+}
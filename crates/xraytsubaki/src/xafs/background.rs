@@ -3,32 +3,41 @@
 #![allow(unused_variables)]
 
 // Import standard library dependencies
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt::Debug;
 use std::ops::Deref;
+use std::sync::RwLock;
 
 // Import external dependencies
+use easyfft::prelude::{DynRealFft, DynRealIfft};
 use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt};
 use nalgebra::{DMatrix, DVector, Dyn, Owned};
 use ndarray::{Array1, ArrayBase, Axis, Ix1, OwnedRepr, ViewRepr};
 use rusty_fitpack;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 // Import internal dependencies
-use super::lmutils::LMParameters;
+use super::config::UserConfig;
+use super::lmutils::{self, LMParameters, LMTrace, LMTraceStep};
 use super::mathutils::{self, splev_jacobian, MathUtils};
 use super::normalization::{self, Normalization};
 use super::nshare::{ToNalgebra, ToNdarray1};
+use super::units::{Angstrom, InverseAngstrom};
 use super::xafsutils::FTWindow;
-use super::xrayfft::{FFTUtils, XFFTReverse, XFFT};
+use super::xrayfft::{FFTScratch, FFTUtils, XFFTReverse, XFFT};
 use super::{xafsutils, xrayfft};
 
 /// Enum for background subtraction methods
 /// AUTOBK: M. Newville, P. Livins, Y. Yacoby, J. J. Rehr, and E. A. Stern. Near-edge x-ray-absorption fine structure of Pb: A comparison of theory and experiment. Phys. Rev. B, 47:14126–14131, Jun 1993. doi:10.1103/PhysRevB.47.14126.
-/// ILPBkg: To be implemented
+/// ILPBkg: iterative low-pass background with a penalized spline, see ILPBkg.
+/// Custom: user-defined method implementing BackgroundModel
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BackgroundMethod {
     AUTOBK(AUTOBK),
     ILPBkg(ILPBkg),
+    Custom(Box<dyn BackgroundModel>),
     None,
 }
 
@@ -51,20 +60,59 @@ impl BackgroundMethod {
         BackgroundMethod::ILPBkg(ILPBkg::new())
     }
 
+    pub fn new_custom<T: BackgroundModel + 'static>(model: T) -> BackgroundMethod {
+        BackgroundMethod::Custom(Box::new(model))
+    }
+
+    /// Copy of the processing parameters without the results and the spectrum dependent ek0.
+    pub fn copy_parameters(&self) -> BackgroundMethod {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => BackgroundMethod::AUTOBK(autobk.copy_parameters()),
+            BackgroundMethod::ILPBkg(ilpbkg) => BackgroundMethod::ILPBkg(ilpbkg.copy_parameters()),
+            BackgroundMethod::Custom(model) => BackgroundMethod::Custom(model.copy_parameters()),
+            BackgroundMethod::None => BackgroundMethod::None,
+        }
+    }
+
     pub fn calc_background(
         &mut self,
         energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
         mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
         normalization_param: &mut Option<normalization::NormalizationMethod>,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        self.calc_background_with_tolerance(energy, mu, normalization_param, (None, None))
+    }
+
+    // calc_background with the (tiny, frac) tolerance of remove_dups, see
+    // ProcessingOptions::remove_dups. Custom models get the energies as they are.
+    pub(crate) fn calc_background_with_tolerance(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        normalization_param: &mut Option<normalization::NormalizationMethod>,
+        dup_tolerance: (Option<f64>, Option<f64>),
     ) -> Result<&mut Self, Box<dyn Error>> {
         match self {
             BackgroundMethod::AUTOBK(autobk) => {
-                autobk.calc_background(energy, mu, normalization_param)?;
+                autobk.calc_background_with_tolerance(
+                    energy,
+                    mu,
+                    normalization_param,
+                    dup_tolerance,
+                )?;
                 Ok(self)
             }
             BackgroundMethod::ILPBkg(ilpbkg) => {
-                todo!("Implement ILPBkg");
-                // ilpbkg.calc_background(energy, mu, normalization_param);
+                ilpbkg.calc_background_with_tolerance(
+                    energy,
+                    mu,
+                    normalization_param,
+                    dup_tolerance,
+                )?;
+                Ok(self)
+            }
+            BackgroundMethod::Custom(model) => {
+                model.calc_background(energy, mu, normalization_param)?;
                 Ok(self)
             }
             BackgroundMethod::None => Ok(self),
@@ -74,7 +122,8 @@ impl BackgroundMethod {
     pub fn get_k(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         match self {
             BackgroundMethod::AUTOBK(autobk) => autobk.k.clone(),
-            BackgroundMethod::ILPBkg(ilpbkg) => None,
+            BackgroundMethod::ILPBkg(ilpbkg) => ilpbkg.k.clone(),
+            BackgroundMethod::Custom(model) => model.get_k(),
             BackgroundMethod::None => None,
         }
     }
@@ -82,10 +131,262 @@ impl BackgroundMethod {
     pub fn get_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         match self {
             BackgroundMethod::AUTOBK(autobk) => autobk.chi.clone(),
-            BackgroundMethod::ILPBkg(ilpbkg) => None,
+            BackgroundMethod::ILPBkg(ilpbkg) => ilpbkg.chi.clone(),
+            BackgroundMethod::Custom(model) => model.get_chi(),
+            BackgroundMethod::None => None,
+        }
+    }
+
+    /// Background mu0(E) on the energy grid of the spectrum.
+    pub fn get_bkg(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => autobk.bkg.clone(),
+            BackgroundMethod::ILPBkg(ilpbkg) => ilpbkg.bkg.clone(),
+            BackgroundMethod::Custom(model) => model.get_bkg(),
+            BackgroundMethod::None => None,
+        }
+    }
+
+    pub fn get_delta_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => autobk.delta_chi.clone(),
+            BackgroundMethod::ILPBkg(ilpbkg) => ilpbkg.delta_chi.clone(),
+            BackgroundMethod::Custom(model) => model.get_delta_chi(),
+            BackgroundMethod::None => None,
+        }
+    }
+
+    /// Uncertainty of the background mu0(E) on the energy grid of the spectrum.
+    /// ILPBkg has no covariance of its spline coefficients and gives no band.
+    pub fn get_delta_bkg(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => autobk.delta_bkg.clone(),
+            BackgroundMethod::ILPBkg(_) => None,
+            BackgroundMethod::Custom(model) => model.get_delta_bkg(),
             BackgroundMethod::None => None,
         }
     }
+
+    /// Iteration trace of the background fit, if it was requested (see AUTOBK::trace).
+    pub fn get_fit_trace(&self) -> Option<&LMTrace> {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => autobk.get_fit_trace(),
+            _ => None,
+        }
+    }
+
+    /// Propagate the uncertainty of mu(E) to chi(k) after the background was calculated.
+    pub fn propagate_std(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        delta_mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        edge_step: f64,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        match self {
+            BackgroundMethod::AUTOBK(autobk) => {
+                autobk.propagate_std(energy, delta_mu, edge_step)?;
+            }
+            BackgroundMethod::ILPBkg(ilpbkg) => {
+                ilpbkg.propagate_std(energy, delta_mu, edge_step)?;
+            }
+            BackgroundMethod::Custom(model) => {
+                model.propagate_std(energy, delta_mu, edge_step)?;
+            }
+            BackgroundMethod::None => {}
+        }
+
+        Ok(self)
+    }
+}
+
+/// Trait for user-defined background subtraction methods
+///
+/// A type implementing this trait can be used as `BackgroundMethod::Custom` and is then run by
+/// XASSpectrum::calc_background, the group processing functions and the plotting functions like
+/// the built-in methods. chi(k) is expected on a uniform k grid starting from 0, as produced by
+/// AUTOBK, so that the forward FT can be applied.
+///
+/// The model is serialized as its name and the value returned by `parameters`. To read it back,
+/// a constructor has to be registered under the same name with `register_background_model`.
+///
+/// # Example
+///
+/// ```
+/// use std::error::Error;
+///
+/// use ndarray::Array1;
+/// use xraytsubaki::xafs::background::{register_background_model, BackgroundMethod, BackgroundModel};
+/// use xraytsubaki::xafs::normalization::NormalizationMethod;
+///
+/// // Straight line through the first and the last point
+/// #[derive(Debug, Clone, Default)]
+/// struct LineBackground {
+///     bkg: Option<Array1<f64>>,
+/// }
+///
+/// impl BackgroundModel for LineBackground {
+///     fn name(&self) -> &str {
+///         "line"
+///     }
+///
+///     fn calc_background(
+///         &mut self,
+///         energy: &Array1<f64>,
+///         mu: &Array1<f64>,
+///         _normalization: &mut Option<NormalizationMethod>,
+///     ) -> Result<(), Box<dyn Error>> {
+///         let n = energy.len() - 1;
+///         let slope = (mu[n] - mu[0]) / (energy[n] - energy[0]);
+///         self.bkg = Some(energy.mapv(|e| mu[0] + slope * (e - energy[0])));
+///         Ok(())
+///     }
+///
+///     fn get_k(&self) -> Option<Array1<f64>> {
+///         None
+///     }
+///
+///     fn get_chi(&self) -> Option<Array1<f64>> {
+///         None
+///     }
+///
+///     fn get_bkg(&self) -> Option<Array1<f64>> {
+///         self.bkg.clone()
+///     }
+///
+///     fn parameters(&self) -> serde_json::Value {
+///         serde_json::Value::Null
+///     }
+///
+///     fn copy_parameters(&self) -> Box<dyn BackgroundModel> {
+///         Box::new(LineBackground::default())
+///     }
+///
+///     fn clone_box(&self) -> Box<dyn BackgroundModel> {
+///         Box::new(self.clone())
+///     }
+/// }
+///
+/// register_background_model("line", |_| Ok(Box::new(LineBackground::default())));
+///
+/// let mut method = BackgroundMethod::new_custom(LineBackground::default());
+/// let energy = Array1::linspace(0.0, 10.0, 11);
+/// let mu = energy.mapv(|e| 2.0 * e + 1.0);
+/// method.calc_background(&energy, &mu, &mut None).unwrap();
+/// assert_eq!(method.get_bkg(), Some(mu));
+///
+/// let json = serde_json::to_string(&method).unwrap();
+/// let method: BackgroundMethod = serde_json::from_str(&json).unwrap();
+/// assert!(matches!(method, BackgroundMethod::Custom(_)));
+/// ```
+pub trait BackgroundModel: Debug + Send + Sync {
+    /// Name under which the constructor of the model is registered.
+    fn name(&self) -> &str;
+
+    fn calc_background(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        normalization: &mut Option<normalization::NormalizationMethod>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    fn get_k(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>>;
+
+    fn get_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>>;
+
+    fn get_bkg(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        None
+    }
+
+    fn get_delta_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        None
+    }
+
+    fn get_delta_bkg(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        None
+    }
+
+    /// Propagate the uncertainty of mu(E) to chi(k). Does nothing by default.
+    fn propagate_std(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        delta_mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        edge_step: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Parameters of the model, passed to the registered constructor on deserialization.
+    fn parameters(&self) -> serde_json::Value;
+
+    /// Copy of the processing parameters without the results.
+    fn copy_parameters(&self) -> Box<dyn BackgroundModel>;
+
+    fn clone_box(&self) -> Box<dyn BackgroundModel>;
+}
+
+/// Constructor of a BackgroundModel from its serialized parameters
+pub type BackgroundModelConstructor =
+    fn(serde_json::Value) -> Result<Box<dyn BackgroundModel>, Box<dyn Error>>;
+
+lazy_static::lazy_static! {
+    static ref BACKGROUND_MODELS: RwLock<HashMap<String, BackgroundModelConstructor>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Register the constructor used to deserialize the BackgroundModel called `name`.
+pub fn register_background_model(name: &str, constructor: BackgroundModelConstructor) {
+    BACKGROUND_MODELS
+        .write()
+        .unwrap()
+        .insert(name.to_string(), constructor);
+}
+
+impl Clone for Box<dyn BackgroundModel> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl PartialEq for Box<dyn BackgroundModel> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+            && self.parameters() == other.parameters()
+            && self.get_chi() == other.get_chi()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedModel {
+    name: String,
+    parameters: serde_json::Value,
+}
+
+impl Serialize for Box<dyn BackgroundModel> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedModel {
+            name: self.name().to_string(),
+            parameters: self.parameters(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn BackgroundModel> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let model = SerializedModel::deserialize(deserializer)?;
+        let constructor = *BACKGROUND_MODELS
+            .read()
+            .unwrap()
+            .get(&model.name)
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "background model {} is not registered",
+                    model.name
+                ))
+            })?;
+
+        constructor(model.parameters).map_err(serde::de::Error::custom)
+    }
 }
 
 /// Struct for AUTOBK
@@ -118,20 +419,36 @@ pub struct AUTOBK {
     pub chi_std: Option<Array1<f64>>,
     /// Optional k array for standard chi(k).
     pub k_std: Option<Array1<f64>>,
+    /// Decimation factor of the energy grid used while fitting the spline. Default = None (full grid).
+    ///
+    /// The spline is fitted to block averages of `decimation` points and the background is
+    /// evaluated on the full grid afterwards. This keeps memory and time bounded for very long
+    /// (e.g. QEXAFS) scans. Averaging smears structure narrower than `decimation` energy points,
+    /// so the factor should stay small compared with the number of points per k step.
+    pub decimation: Option<usize>,
     /// k weight for FFT. Default = 1.
     pub kweight: Option<i32>,
     /// FFT window function name. Default = Hanning.
     pub window: FTWindow,
     /// FFT window window parameter. Default = 0.1.
     pub dk: Option<f64>,
+    /// Record the iteration trace of the spline fit in fit_trace. Default = false.
+    pub trace: Option<bool>,
     /// Background of mu(E)
     pub bkg: Option<Array1<f64>>,
+    /// Approximate standard deviation of bkg from the covariance of the spline coefficients.
+    /// Zero below ek0, where bkg is mu(E) itself.
+    pub delta_bkg: Option<Array1<f64>>,
     /// Edge normalized mu(E) - bkg
     pub chie: Option<Array1<f64>>,
     /// k grid
     pub k: Option<Array1<f64>>,
     /// chi(k)
     pub chi: Option<Array1<f64>>,
+    /// Uncertainty of chi(k), if the uncertainty of mu(E) was propagated
+    pub delta_chi: Option<Array1<f64>>,
+    /// Iteration trace of the spline fit, if trace is set
+    pub fit_trace: Option<LMTrace>,
 }
 
 impl Default for AUTOBK {
@@ -149,13 +466,18 @@ impl Default for AUTOBK {
             nfft: Some(2048),
             chi_std: None,
             k_std: None,
+            decimation: None,
             kweight: Some(1),
             window: FTWindow::Hanning,
             dk: Some(0.1),
+            trace: None,
             bkg: None,
+            delta_bkg: None,
             chie: None,
             k: None,
             chi: None,
+            delta_chi: None,
+            fit_trace: None,
         }
     }
 }
@@ -166,6 +488,50 @@ impl AUTOBK {
         AUTOBK::default()
     }
 
+    /// Default parameters with the background kweight of the user configuration, if it sets
+    /// one.
+    pub fn from_config(config: &UserConfig) -> AUTOBK {
+        let default = AUTOBK::default();
+        AUTOBK {
+            kweight: config.bkg_kweight.or(default.kweight),
+            ..default
+        }
+    }
+
+    /// Take the kweight from the user configuration if it is not set or still the default.
+    pub fn fill_from_config(&mut self, config: &UserConfig) -> &mut Self {
+        if self.kweight.is_none() || self.kweight == AUTOBK::default().kweight {
+            self.kweight = config.bkg_kweight.or(self.kweight);
+        }
+        self
+    }
+
+    pub fn set_rbkg<R: Into<Angstrom>>(&mut self, rbkg: R) -> &mut Self {
+        self.rbkg = Some(rbkg.into().value());
+        self
+    }
+
+    pub fn set_k_range<K: Into<InverseAngstrom>>(&mut self, kmin: K, kmax: K) -> &mut Self {
+        self.kmin = Some(kmin.into().value());
+        self.kmax = Some(kmax.into().value());
+        self
+    }
+
+    /// Copy of the processing parameters without the results and the spectrum dependent ek0.
+    pub fn copy_parameters(&self) -> AUTOBK {
+        AUTOBK {
+            ek0: None,
+            bkg: None,
+            delta_bkg: None,
+            chie: None,
+            k: None,
+            chi: None,
+            delta_chi: None,
+            fit_trace: None,
+            ..self.clone()
+        }
+    }
+
     /// Fill in default values for parameters that are not set
     pub fn fill_parameter(&mut self) -> Result<(), Box<dyn Error>> {
         if self.rbkg.is_none() {
@@ -224,45 +590,26 @@ impl AUTOBK {
         energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
         mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
         normalization_param: &mut Option<normalization::NormalizationMethod>,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        self.calc_background_with_tolerance(energy, mu, normalization_param, (None, None))
+    }
+
+    // calc_background with the (tiny, frac) tolerance of remove_dups
+    pub(crate) fn calc_background_with_tolerance(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        normalization_param: &mut Option<normalization::NormalizationMethod>,
+        dup_tolerance: (Option<f64>, Option<f64>),
     ) -> Result<&mut Self, Box<dyn Error>> {
         // Fill in default values for parameters that are not set
         self.fill_parameter()?;
 
-        let energy = xafsutils::remove_dups(energy.clone(), None, None, None);
-
-        // Perform normalization if necessary
-
-        let mut normalization_method: normalization::NormalizationMethod =
-            if normalization_param.is_none() {
-                let mut normalization_method = normalization::PrePostEdge::new();
-                let ek0 = self.ek0;
-
-                normalization_method.set_e0(ek0);
-                normalization::NormalizationMethod::PrePostEdge(normalization_method)
-            } else {
-                normalization_param.clone().unwrap()
-            };
-
-        if let Some(ek0) = self.ek0 {
-            if ek0 < energy.min() || ek0 > energy.max() {
-                self.ek0 = None;
-            }
-        }
+        let (tiny, frac) = dup_tolerance;
+        let energy = xafsutils::remove_dups(energy.clone(), tiny, frac, None);
 
-        let e0 = normalization_method.get_e0();
-        let mut edge_step = normalization_method.get_edge_step();
-        let ek0 = self.ek0;
-
-        if (ek0.is_none() && e0.is_none()) || edge_step.is_none() {
-            normalization_method.normalize(&energy, mu)?;
-            edge_step = normalization_method.get_edge_step();
-        }
-
-        self.ek0 = if self.ek0.is_none() {
-            normalization_method.get_e0()
-        } else {
-            self.ek0
-        };
+        let (ek0, edge_step) = edge_of(self.ek0, &energy, mu, normalization_param)?;
+        self.ek0 = Some(ek0);
 
         // Rbkg Algorithm
         let iek0 = mathutils::index_of(&energy.to_vec(), &self.ek0.unwrap())?;
@@ -301,7 +648,7 @@ impl AUTOBK {
         };
 
         let ftwin = &kout.mapv(|x| x.powi(self.kweight.unwrap()))
-            * xafsutils::ftwindow(
+            * xrayfft::ftwindow_cached(
                 &kout,
                 self.kmin,
                 Some(kmax),
@@ -310,9 +657,13 @@ impl AUTOBK {
                 Some(self.window),
             )?;
 
-        let mut nspl = 1
-            + (2.0 * self.rbkg.unwrap() * (kmax - self.kmin.unwrap()) / std::f64::consts::PI)
-                .round() as i32;
+        let mut nspl = xafsutils::independent_points(
+            self.kmin.unwrap(),
+            kmax,
+            0.0,
+            self.rbkg.unwrap(),
+        )
+        .round() as i32;
         let irbkg = (1.0
             + (nspl - 1) as f64 * std::f64::consts::PI
                 / (2.0 * rgrid * (kmax - self.kmin.unwrap())))
@@ -376,14 +727,30 @@ impl AUTOBK {
             None,
         );
 
+        let kraw_full = kraw
+            .slice_axis(Axis(0), ndarray::Slice::from(0..iemax - iek0 + 1))
+            .to_vec();
+        let mu_full = mu
+            .slice_axis(Axis(0), ndarray::Slice::from(iek0..iemax + 1))
+            .to_vec();
+
+        // Reduce the grid used in the optimization if decimation is requested
+        let decimation = self.decimation.unwrap_or(1).max(1);
+        let (kraw_fit, mu_fit) = if decimation > 1 && kraw_full.len() / decimation >= 2 {
+            (
+                mathutils::block_average(&kraw_full, decimation),
+                mathutils::block_average(&mu_full, decimation),
+            )
+        } else {
+            (kraw_full.clone(), mu_full)
+        };
+
         // Calculate the mu interpolated to the k grid
-        let mu_out = kout.to_vec().interpolate(
-            &kraw
-                .slice_axis(Axis(0), ndarray::Slice::from(0..iemax - iek0 + 1))
-                .to_vec(),
-            &mu.slice_axis(Axis(0), ndarray::Slice::from(iek0..iemax + 1))
-                .to_vec(),
-        )?;
+        let mu_out = kout.to_vec().interpolate(&kraw_fit, &mu_fit)?;
+
+        // Buffers of the fit, from the pool of this thread
+        let mut workspace = AUTOBKWorkspace::take(self.nfft.unwrap() as usize);
+        workspace.prepare(&knots, coefs.len(), order, kout.as_slice().unwrap());
 
         let spline_opt = AUTOBKSpline {
             coefs: DVector::from_vec(coefs),
@@ -391,11 +758,7 @@ impl AUTOBK {
             order: order,
             irbkg: irbkg as usize,
             nfft: self.nfft.unwrap() as usize,
-            kraw: kraw
-                .slice_axis(Axis(0), ndarray::Slice::from(0..iemax - iek0 + 1))
-                .clone()
-                .to_owned()
-                .into_nalgebra(),
+            kraw: DVector::from_vec(kraw_fit),
             mu: DVector::from_vec(mu_out),
             kout: kout.clone().into_nalgebra(),
             ftwin: ftwin.into_nalgebra(),
@@ -405,7 +768,12 @@ impl AUTOBK {
             clamp_lo: self.clamp_lo.unwrap(),
             clamp_hi: self.clamp_hi.unwrap(),
             kstep: self.kstep.unwrap(),
-            ..Default::default()
+            trace: self
+                .trace
+                .unwrap_or(false)
+                .then(|| RefCell::new(Vec::new())),
+            scale: 1.0,
+            workspace: RefCell::new(workspace),
         };
 
         let (fit_result, report) = LevenbergMarquardt::new()
@@ -415,7 +783,12 @@ impl AUTOBK {
             .with_stepbound(1.0e-6)
             .minimize(spline_opt);
 
-        let (bkg, chi) = spline_eval_nalgebra(
+        self.fit_trace = fit_result
+            .trace
+            .as_ref()
+            .map(|trace| LMTrace::new(trace.borrow().clone(), &report));
+
+        let (_, chi) = spline_eval_nalgebra(
             &fit_result.kraw,
             &fit_result.mu,
             &fit_result.knots,
@@ -424,17 +797,52 @@ impl AUTOBK {
             &fit_result.kout,
         );
 
-        let bkg = bkg.into_ndarray1();
+        // The background is always evaluated on the full energy grid
+        let delta_bkg = fit_result.spline_std(&kraw_full);
+        let bkg = Array1::from_vec(rusty_fitpack::splev(
+            fit_result.knots.data.as_vec().clone(),
+            fit_result.coefs.data.as_vec().clone(),
+            fit_result.order,
+            kraw_full,
+            3,
+        ));
         let chi = chi.into_ndarray1();
 
         let mut obkg = mu.clone();
         obkg.slice_mut(ndarray::s![iek0..iek0 + bkg.len()])
             .assign(&bkg);
 
+        self.delta_bkg = delta_bkg.map(|delta_bkg| {
+            let mut odelta_bkg = Array1::zeros(mu.len());
+            odelta_bkg
+                .slice_mut(ndarray::s![iek0..iek0 + delta_bkg.len()])
+                .assign(&delta_bkg.into_ndarray1());
+            odelta_bkg
+        });
         self.bkg = Some(obkg.clone());
-        self.chie = Some((mu - &obkg) / edge_step.unwrap());
+        self.chie = Some((mu - &obkg) / edge_step);
         self.k = Some(kout);
-        self.chi = Some(chi / edge_step.unwrap());
+        self.chi = Some(chi / edge_step);
+
+        fit_result.workspace.into_inner().release();
+
+        Ok(self)
+    }
+
+    /// Approximate propagation of the uncertainty of mu(E) to chi(k)
+    ///
+    /// The spline is treated as exact, so delta_chi(k) is delta_mu interpolated to E(k) = ek0 + k^2/ETOK,
+    /// divided by the edge step. calc_background() has to be called before.
+    pub fn propagate_std(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        delta_mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        edge_step: f64,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let ek0 = self.ek0.ok_or(super::XAFSError::NotEnoughData)?;
+        let k = self.k.as_ref().ok_or(super::XAFSError::NotEnoughData)?;
+
+        self.delta_chi = Some(delta_chi_of(ek0, k, energy, delta_mu, edge_step)?);
 
         Ok(self)
     }
@@ -515,6 +923,18 @@ impl AUTOBK {
         self.chi.as_ref().map(|x| x.view())
     }
 
+    pub fn get_delta_chi(&self) -> Option<ArrayBase<ViewRepr<&f64>, Ix1>> {
+        self.delta_chi.as_ref().map(|x| x.view())
+    }
+
+    pub fn get_delta_bkg(&self) -> Option<ArrayBase<ViewRepr<&f64>, Ix1>> {
+        self.delta_bkg.as_ref().map(|x| x.view())
+    }
+
+    pub fn get_fit_trace(&self) -> Option<&LMTrace> {
+        self.fit_trace.as_ref()
+    }
+
     pub fn get_chi_kweighted(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         let kweight = self.kweight?;
         let k = self.k.clone()?;
@@ -538,6 +958,62 @@ impl AUTOBK {
     }
 }
 
+/// ek0 and edge step used for the background of mu(E)
+///
+/// `ek0` is dropped if it is outside of the energy range. Without ek0, the e0 of the
+/// normalization is used. The spectrum is normalized with a copy of `normalization_param`
+/// (PrePostEdge with e0 = ek0 if None) if e0 or the edge step is not known.
+fn edge_of(
+    ek0: Option<f64>,
+    energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    normalization_param: &Option<normalization::NormalizationMethod>,
+) -> Result<(f64, f64), Box<dyn Error>> {
+    let mut normalization_method = match normalization_param {
+        Some(normalization_method) => normalization_method.clone(),
+        None => {
+            let mut normalization_method = normalization::PrePostEdge::new();
+            normalization_method.set_e0(ek0);
+            normalization::NormalizationMethod::PrePostEdge(normalization_method)
+        }
+    };
+
+    let ek0 = ek0.filter(|ek0| *ek0 >= energy.min() && *ek0 <= energy.max());
+
+    let e0 = normalization_method.get_e0();
+    let mut edge_step = normalization_method.get_edge_step();
+
+    if (ek0.is_none() && e0.is_none()) || edge_step.is_none() {
+        normalization_method.normalize(energy, mu)?;
+        edge_step = normalization_method.get_edge_step();
+    }
+
+    let ek0 = ek0.or(normalization_method.get_e0());
+
+    match (ek0, edge_step) {
+        (Some(ek0), Some(edge_step)) => Ok((ek0, edge_step)),
+        _ => Err(Box::new(super::XAFSError::NotEnoughData)),
+    }
+}
+
+// delta_mu interpolated to E(k) = ek0 + k^2/ETOK and divided by the edge step
+fn delta_chi_of(
+    ek0: f64,
+    k: &Array1<f64>,
+    energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    delta_mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    edge_step: f64,
+) -> Result<Array1<f64>, Box<dyn Error>> {
+    if energy.len() != delta_mu.len() {
+        return Err(Box::new(super::XAFSError::NotEnoughData));
+    }
+
+    let energy_k = k.mapv(|k| ek0 + k.powi(2) / xafsutils::constants::ETOK);
+    let delta_mu_k = energy_k.interpolate(&energy.to_vec(), &delta_mu.mapv(f64::abs).to_vec())?;
+
+    Ok(delta_mu_k / edge_step.max(1.0e-12))
+}
+
 /// Evaluation of the spline used in AUTOBK
 ///
 /// In puts and outputs are in DVector struct from nalgebra crate
@@ -580,8 +1056,54 @@ fn spline_eval_nalgebra(
     (bkg, chi.clone())
 }
 
+/// Buffers of an AUTOBK fit
+///
+/// Every thread keeps the workspace of its last fit, so that the fits of the spectra of a group
+/// on a worker thread reuse the spline basis and the FFT scratch instead of allocating them for
+/// every spectrum and every step of the fit.
+#[derive(Debug, Clone)]
+struct AUTOBKWorkspace {
+    /// d bkg(kout) / d coefs. The spline is linear in the coefficients, so the basis is
+    /// evaluated once per fit and bkg(kout) is basis * coefs.
+    basis: DMatrix<f64>,
+    /// chi(k) on kout, or a column of the Jacobian
+    chi: DVector<f64>,
+    fft: FFTScratch,
+}
+
+thread_local! {
+    static AUTOBK_WORKSPACE: RefCell<Option<AUTOBKWorkspace>> = const { RefCell::new(None) };
+}
+
+impl AUTOBKWorkspace {
+    /// The workspace of this thread if it has the same nfft, a new one otherwise
+    fn take(nfft: usize) -> AUTOBKWorkspace {
+        AUTOBK_WORKSPACE
+            .with(|workspace| workspace.borrow_mut().take())
+            .filter(|workspace| workspace.fft.nfft() == nfft)
+            .unwrap_or_else(|| AUTOBKWorkspace {
+                basis: DMatrix::zeros(0, 0),
+                chi: DVector::zeros(0),
+                fft: FFTScratch::new(nfft),
+            })
+    }
+
+    /// Give the workspace back to this thread for the next fit
+    fn release(self) {
+        AUTOBK_WORKSPACE.with(|workspace| *workspace.borrow_mut() = Some(self));
+    }
+
+    /// Evaluate the basis of the spline on kout
+    fn prepare(&mut self, knots: &[f64], ncoefs: usize, order: usize, kout: &[f64]) {
+        mathutils::splev_jacobian_into(knots, ncoefs, order, kout, 3, &mut self.basis);
+        if self.chi.len() != kout.len() {
+            self.chi = DVector::zeros(kout.len());
+        }
+    }
+}
+
 /// Struct for solving Levenberg-Marquardt optimization for AUTOBK
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 struct AUTOBKSpline {
     pub coefs: DVector<f64>,
     pub knots: DVector<f64>,
@@ -599,53 +1121,100 @@ struct AUTOBKSpline {
     pub clamp_hi: i32,
     pub kstep: f64,
     pub scale: f64,
+    /// Steps recorded by residuals() if the trace is requested
+    pub trace: Option<RefCell<Vec<LMTraceStep>>>,
+    workspace: RefCell<AUTOBKWorkspace>,
 }
 
-impl Default for AUTOBKSpline {
-    fn default() -> Self {
-        AUTOBKSpline {
-            coefs: DVector::zeros(0),
-            knots: DVector::zeros(0),
-            order: 3,
-            irbkg: 1,
-            nfft: 2048,
-            kraw: DVector::zeros(0),
-            mu: DVector::zeros(0),
-            kout: DVector::zeros(0),
-            ftwin: DVector::zeros(0),
-            kweight: 1,
-            chi_std: None,
-            nclamp: 0,
-            clamp_lo: 1,
-            clamp_hi: 1,
-            kstep: 0.05,
-            scale: 1.0,
+impl AUTOBKSpline {
+    /// Standard deviation of the spline at `k` from the covariance of the coefficients
+    ///
+    /// The covariance (J^T J)^-1 of the fit is scaled by the reduced chi-square of the
+    /// residuals and propagated through splev, which is linear in the coefficients. Returns None
+    /// if the fit has no degrees of freedom left or the Jacobian is rank deficient.
+    pub fn spline_std(&self, k: &[f64]) -> Option<DVector<f64>> {
+        let residuals = self.residual_vec(&self.coefs);
+        let jacobian = self.residual_jacobian(&self.coefs);
+
+        // splrep pads the coefficients with zeros that do not enter the spline
+        let active = (0..jacobian.ncols())
+            .filter(|&j| jacobian.column(j).norm() > 0.0)
+            .collect::<Vec<usize>>();
+        let dof = residuals.len() as i64 - active.len() as i64;
+
+        if active.is_empty() || dof <= 0 {
+            return None;
         }
-    }
-}
 
-impl AUTOBKSpline {
-    /// The Loss function in 1-d array for the Levenberg-Marquardt optimization
-    pub fn residual_vec(&self, coefs: &DVector<f64>) -> DVector<f64> {
-        let (bkg, chi) = spline_eval_nalgebra(
-            &self.kraw,
-            &self.mu,
-            &self.knots,
-            coefs,
+        let covariance =
+            lmutils::covariance_from_jacobian_nalgebra_f64(&jacobian.select_columns(&active))?
+                * (residuals.norm_squared() / dof as f64);
+
+        let basis = splev_jacobian(
+            self.knots.data.as_vec().clone(),
+            self.coefs.data.as_vec().clone(),
             self.order,
-            &self.kout,
+            k.to_vec(),
+            3,
+        )
+        .select_columns(&active);
+
+        Some(DVector::from_iterator(
+            k.len(),
+            basis
+                .row_iter()
+                .map(|b| (b * &covariance).dot(&b).max(0.0).sqrt()),
+        ))
+    }
+
+    // chi(kout) = mu - bkg(kout), less chi_std if given
+    fn eval_chi(&self, coefs: &DVector<f64>, basis: &DMatrix<f64>, chi: &mut DVector<f64>) {
+        chi.copy_from(&self.mu);
+        chi.gemv(-1.0, basis, coefs, 1.0);
+        if let Some(chi_std) = self.chi_std.as_ref() {
+            *chi -= chi_std;
+        }
+    }
+
+    // Real and imaginary parts of the transform of the windowed chi below irbkg
+    fn low_r(&self, chi: &[f64], fft: &mut FFTScratch) -> DVector<f64> {
+        let chir = fft.xftf(
+            chi.iter()
+                .zip(self.ftwin.iter())
+                .map(|(chi, win)| chi * win),
+            self.kstep,
         );
 
-        let chi: DVector<f64> = if self.chi_std.is_some() {
-            chi - self.chi_std.as_ref().unwrap()
-        } else {
-            chi
-        };
+        DVector::from_iterator(
+            2 * self.irbkg,
+            chir[..self.irbkg].iter().flat_map(|x| [x.re, x.im]),
+        )
+    }
+
+    // low_r followed by the ends of chi, weighted by clamp_lo and clamp_hi
+    fn with_clamps(&self, low_r: DVector<f64>, chi: &[f64], scale: f64) -> DVector<f64> {
+        let nclamp = self.nclamp as usize;
+        let n = chi.len();
+        let low = chi[..nclamp]
+            .iter()
+            .map(|chi| self.clamp_lo as f64 * scale * chi);
+        let high = chi[n - nclamp - 1..n - 1]
+            .iter()
+            .map(|chi| self.clamp_hi as f64 * scale * chi);
+
+        DVector::from_iterator(
+            low_r.len() + 2 * nclamp,
+            low_r.iter().copied().chain(low).chain(high),
+        )
+    }
+
+    /// The Loss function in 1-d array for the Levenberg-Marquardt optimization
+    pub fn residual_vec(&self, coefs: &DVector<f64>) -> DVector<f64> {
+        let mut workspace = self.workspace.borrow_mut();
+        let AUTOBKWorkspace { basis, chi, fft } = &mut *workspace;
 
-        let mut out: DVector<f64> = chi
-            .component_mul(&self.ftwin)
-            .xftf_fast(self.nfft, self.kstep)[..self.irbkg]
-            .realimg();
+        self.eval_chi(coefs, basis, chi);
+        let out = self.low_r(chi.as_slice(), fft);
 
         if self.nclamp == 0 {
             return out;
@@ -653,92 +1222,39 @@ impl AUTOBKSpline {
 
         let scale = 1.0 + 100.0 * out.dot(&out) / out.len() as f64;
 
-        let low_clamp = self.clamp_lo as f64 * scale * chi.view((0, 0), (self.nclamp as usize, 1));
-
-        let high_clamp = self.clamp_hi as f64
-            * scale
-            * chi.view(
-                (chi.len() - self.nclamp as usize - 1, 0),
-                (self.nclamp as usize, 1),
-            );
-
-        out.extend(low_clamp.data.as_vec().to_owned());
-
-        out.extend(high_clamp.data.as_vec().to_owned());
-
-        out
+        self.with_clamps(out, chi.as_slice(), scale)
     }
 
     pub fn residual_jacobian(&self, coefs: &DVector<f64>) -> DMatrix<f64> {
-        // just for calculating the scale
+        let mut workspace = self.workspace.borrow_mut();
+        let AUTOBKWorkspace { basis, chi, fft } = &mut *workspace;
 
+        // The clamps are scaled by the residual of the transform
         let scale = if self.nclamp != 0 {
-            let (_, chi) = spline_eval_nalgebra(
-                &self.kraw,
-                &self.mu,
-                &self.knots,
-                coefs,
-                self.order,
-                &self.kout,
-            );
-
-            let chi: DVector<f64> = if self.chi_std.is_some() {
-                chi - self.chi_std.as_ref().unwrap()
-            } else {
-                chi
-            };
-
-            let out: DVector<f64> = chi
-                .component_mul(&self.ftwin)
-                .xftf_fast(self.nfft, self.kstep)[..self.irbkg]
-                .realimg();
-
-            let scale = 1.0 + 100.0 * out.dot(&out) / out.len() as f64;
-
-            scale
+            self.eval_chi(coefs, basis, chi);
+            let out = self.low_r(chi.as_slice(), fft);
+            1.0 + 100.0 * out.dot(&out) / out.len() as f64
         } else {
             1.0
         };
 
-        let spline_jacobian = -splev_jacobian(
-            self.knots.data.as_vec().clone(),
-            self.coefs.data.as_vec().clone(),
-            self.order,
-            self.kout.data.as_vec().clone(),
-            3,
-        );
-        let num_cols = self.coefs.len();
-
-        let jacobian_columns = spline_jacobian
-            .column_iter()
-            .map(|chi_der| {
-                let mut out: DVector<f64> = chi_der
-                    .component_mul(&self.ftwin)
-                    .xftf_fast(self.nfft, self.kstep)[..self.irbkg]
-                    .realimg();
-
-                if self.nclamp == 0 {
-                    return out;
-                }
-
-                // let scale = 1.0 + 100.0 * out.dot(&out) / out.len() as f64;
-
-                let low_clamp =
-                    self.clamp_lo as f64 * scale * chi_der.view((0, 0), (self.nclamp as usize, 1));
-                let high_clamp = self.clamp_hi as f64
-                    * scale
-                    * chi_der.view(
-                        (chi_der.len() - self.nclamp as usize - 1, 0),
-                        (self.nclamp as usize, 1),
-                    );
-
-                out.extend(low_clamp.data.as_vec().to_owned());
-                out.extend(high_clamp.data.as_vec().to_owned());
-                out
-            })
-            .collect::<Vec<DVector<f64>>>();
+        let nresiduals = 2 * self.irbkg + 2 * self.nclamp as usize;
+        let mut jacobian = DMatrix::zeros(nresiduals, basis.ncols());
+
+        for j in 0..basis.ncols() {
+            // d chi / d coefs_j = -basis_j
+            chi.iter_mut()
+                .zip(basis.column(j).iter())
+                .for_each(|(chi, b)| *chi = -b);
 
-        DMatrix::from_columns(&jacobian_columns)
+            let mut out = self.low_r(chi.as_slice(), fft);
+            if self.nclamp != 0 {
+                out = self.with_clamps(out, chi.as_slice(), scale);
+            }
+            jacobian.set_column(j, &out);
+        }
+
+        jacobian
     }
 }
 
@@ -760,7 +1276,16 @@ impl LeastSquaresProblem<f64, Dyn, Dyn> for AUTOBKSpline {
     }
 
     fn residuals(&self) -> Option<DVector<f64>> {
-        Some(self.residual_vec(&self.coefs))
+        let residuals = self.residual_vec(&self.coefs);
+
+        if let Some(trace) = self.trace.as_ref() {
+            trace.borrow_mut().push(LMTraceStep {
+                params: self.coefs.data.as_vec().clone(),
+                residual_norm: residuals.norm(),
+            });
+        }
+
+        Some(residuals)
     }
 
     /// Jacobian matrix for the Levenberg-Marquardt optimization
@@ -795,16 +1320,283 @@ impl LeastSquaresProblem<f64, Dyn, Dyn> for AUTOBKSpline {
     }
 }
 
-/// TODO: Implement ILPBkg
-#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+/// Struct for ILPBkg, the iterative low-pass background
+///
+/// mu(k) above ek0 is fitted with a cubic penalized spline (P-spline, P. H. C. Eilers and
+/// B. D. Marx, Statist. Sci. 11, 89 (1996)) on uniform knots. The part of chi(k) = mu(k) -
+/// bkg(k) below rbkg in R is then moved into the background and the spline is fitted again,
+/// until the background stops changing. Every iteration is a linear solve with the same
+/// matrix, so unlike AUTOBK no nonlinear fit is needed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
-pub struct ILPBkg {}
+pub struct ILPBkg {
+    /// Edge energy in eV. Default = None, the e0 of the normalization.
+    pub ek0: Option<f64>,
+    /// R below which chi(R) is assigned to the background, in Å. Default = 1.0.
+    pub rbkg: Option<f64>,
+    /// Number of spline coefficients. Default = None, from rbkg and kmax as in AUTOBK.
+    pub nknots: Option<i32>,
+    /// Upper end of the background in k. Default = None, the end of the data.
+    pub kmax: Option<f64>,
+    /// k step of chi(k). Default = 0.05.
+    pub kstep: Option<f64>,
+    /// k weight of the fit and the low-pass filter. Default = 1.
+    pub kweight: Option<i32>,
+    /// Weight of the penalty on the second differences of the spline coefficients, relative
+    /// to the number of points per coefficient. Default = 0.1.
+    pub smoothing: Option<f64>,
+    /// Default = 100.
+    pub max_iterations: Option<usize>,
+    /// Change of the background between two iterations, relative to the edge step, below
+    /// which the iteration stops. Default = 1e-6.
+    pub tolerance: Option<f64>,
+    /// Background of mu(E)
+    pub bkg: Option<Array1<f64>>,
+    /// Edge normalized mu(E) - bkg
+    pub chie: Option<Array1<f64>>,
+    /// k grid
+    pub k: Option<Array1<f64>>,
+    /// chi(k)
+    pub chi: Option<Array1<f64>>,
+    /// Uncertainty of chi(k), if the uncertainty of mu(E) was propagated
+    pub delta_chi: Option<Array1<f64>>,
+    /// Number of iterations of the last calculation
+    pub iterations: Option<usize>,
+}
+
+impl Default for ILPBkg {
+    fn default() -> Self {
+        ILPBkg {
+            ek0: None,
+            rbkg: Some(1.0),
+            nknots: None,
+            kmax: None,
+            kstep: Some(0.05),
+            kweight: Some(1),
+            smoothing: Some(0.1),
+            max_iterations: Some(100),
+            tolerance: Some(1e-6),
+            bkg: None,
+            chie: None,
+            k: None,
+            chi: None,
+            delta_chi: None,
+            iterations: None,
+        }
+    }
+}
 
-/// TODO: Implement ILPBkg
 impl ILPBkg {
     pub fn new() -> ILPBkg {
         ILPBkg::default()
     }
+
+    pub fn set_rbkg<R: Into<Angstrom>>(&mut self, rbkg: R) -> &mut Self {
+        self.rbkg = Some(rbkg.into().value());
+        self
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f64) -> &mut Self {
+        self.smoothing = Some(smoothing);
+        self
+    }
+
+    /// Copy of the processing parameters without the results and the spectrum dependent ek0.
+    pub fn copy_parameters(&self) -> ILPBkg {
+        ILPBkg {
+            ek0: None,
+            bkg: None,
+            chie: None,
+            k: None,
+            chi: None,
+            delta_chi: None,
+            iterations: None,
+            ..self.clone()
+        }
+    }
+
+    /// Calculate background, see the documentation of the struct.
+    pub fn calc_background(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        normalization_param: &mut Option<normalization::NormalizationMethod>,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        self.calc_background_with_tolerance(energy, mu, normalization_param, (None, None))
+    }
+
+    // calc_background with the (tiny, frac) tolerance of remove_dups
+    pub(crate) fn calc_background_with_tolerance(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        normalization_param: &mut Option<normalization::NormalizationMethod>,
+        dup_tolerance: (Option<f64>, Option<f64>),
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let default = ILPBkg::default();
+        let rbkg = self.rbkg.or(default.rbkg).unwrap();
+        let kstep = self.kstep.or(default.kstep).unwrap();
+        let smoothing = self.smoothing.or(default.smoothing).unwrap();
+        let max_iterations = self.max_iterations.or(default.max_iterations).unwrap();
+        let tolerance = self.tolerance.or(default.tolerance).unwrap();
+        let kweight = self.kweight.or(default.kweight).unwrap();
+
+        let (tiny, frac) = dup_tolerance;
+        let energy = xafsutils::remove_dups(energy.clone(), tiny, frac, None);
+        let (ek0, edge_step) = edge_of(self.ek0, &energy, mu, normalization_param)?;
+        self.ek0 = Some(ek0);
+
+        let iek0 = mathutils::index_of(&energy.to_vec(), &ek0)?;
+        let kraw = energy.slice(ndarray::s![iek0..]).mapv(|e| {
+            let e = e - ek0;
+            e.signum() * (xafsutils::constants::ETOK * e.abs()).sqrt()
+        });
+        let kmax = match self.kmax {
+            Some(kmax) => kmax.min(kraw.max()).max(0.0),
+            None => kraw.max(),
+        };
+        let iemax = iek0 + kraw.iter().take_while(|k| **k <= kmax).count();
+        let kraw = kraw.slice(ndarray::s![..iemax - iek0]).to_owned();
+        let kout = kstep * &Array1::range(0.0, (1.01 + kmax / kstep).floor(), 1.0);
+        if kraw.len() < 4 || kout.len() < 4 {
+            return Err(Box::new(super::XAFSError::NotEnoughData));
+        }
+        let mu_k =
+            kout.interpolate(&kraw.to_vec(), &mu.slice(ndarray::s![iek0..iemax]).to_vec())?;
+
+        let ncoefs = self
+            .nknots
+            .unwrap_or(xafsutils::independent_points(0.0, kmax, 0.0, rbkg).round() as i32)
+            .clamp(5, 128) as usize;
+        let spline = UniformSpline::new(kmax, ncoefs);
+
+        // The fit and the low-pass filter are weighted by k^kweight, normalized to a mean
+        // square of 1, so that the steep edge at low k does not dominate the background.
+        let mut weight = kout.mapv(|k| k.powi(kweight));
+        weight /= weight.mapv(|w| w * w).mean().unwrap().sqrt();
+        let basis = spline.basis(&kout);
+        let mut weighted_basis = basis.clone();
+        for (mut row, w) in weighted_basis.row_iter_mut().zip(weight.iter()) {
+            row *= *w;
+        }
+        let penalty = smoothing * kout.len() as f64 / ncoefs as f64;
+        let solver = (weighted_basis.transpose() * &weighted_basis + spline.penalty() * penalty)
+            .cholesky()
+            .ok_or("ILPBkg: the spline fit is singular")?;
+        let fit = |y: Array1<f64>| solver.solve(&(weighted_basis.transpose() * y.into_nalgebra()));
+
+        let mut coefs = fit(&weight * &mu_k);
+        let mut bkg_k = (&basis * &coefs).into_ndarray1();
+        let mut iterations = 0;
+        while iterations < max_iterations {
+            iterations += 1;
+            let low = low_pass(&(&weight * &(&mu_k - &bkg_k)), kstep, rbkg);
+            coefs = fit(&weight * &bkg_k + low);
+            let next = (&basis * &coefs).into_ndarray1();
+            let change = (&next - &bkg_k).mapv(f64::abs).max();
+            bkg_k = next;
+            if change < tolerance * edge_step.abs() {
+                break;
+            }
+        }
+
+        let bkg = (spline.basis(&kraw) * &coefs).into_ndarray1();
+        let mut obkg = mu.clone();
+        obkg.slice_mut(ndarray::s![iek0..iemax]).assign(&bkg);
+
+        self.chie = Some((mu - &obkg) / edge_step);
+        self.bkg = Some(obkg);
+        self.chi = Some((mu_k - bkg_k) / edge_step);
+        self.k = Some(kout);
+        self.iterations = Some(iterations);
+
+        Ok(self)
+    }
+
+    /// Approximate propagation of the uncertainty of mu(E) to chi(k), as for AUTOBK.
+    pub fn propagate_std(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        delta_mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        edge_step: f64,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let ek0 = self.ek0.ok_or(super::XAFSError::NotEnoughData)?;
+        let k = self.k.as_ref().ok_or(super::XAFSError::NotEnoughData)?;
+
+        self.delta_chi = Some(delta_chi_of(ek0, k, energy, delta_mu, edge_step)?);
+
+        Ok(self)
+    }
+
+    pub fn get_ek0(&self) -> Option<&f64> {
+        self.ek0.as_ref()
+    }
+
+    pub fn get_iterations(&self) -> Option<usize> {
+        self.iterations
+    }
+}
+
+/// Cubic B-splines on uniform knots over [0, kmax], fitted by ILPBkg
+struct UniformSpline {
+    knots: Vec<f64>,
+    ncoefs: usize,
+}
+
+impl UniformSpline {
+    fn new(kmax: f64, ncoefs: usize) -> UniformSpline {
+        let spacing = kmax / (ncoefs - 3) as f64;
+        let knots = (0..ncoefs + 4)
+            .map(|i| (i as f64 - 3.0) * spacing)
+            .collect();
+
+        UniformSpline { knots, ncoefs }
+    }
+
+    /// Values of the B-splines at `k`, one column per coefficient
+    fn basis(&self, k: &Array1<f64>) -> DMatrix<f64> {
+        splev_jacobian(self.knots.clone(), vec![0.0; self.ncoefs], 3, k.to_vec(), 3)
+    }
+
+    /// D^T D of the second difference matrix D of the coefficients
+    fn penalty(&self) -> DMatrix<f64> {
+        let difference = DMatrix::from_fn(self.ncoefs - 2, self.ncoefs, |i, j| match j {
+            _ if j == i || j == i + 2 => 1.0,
+            _ if j == i + 1 => -2.0,
+            _ => 0.0,
+        });
+
+        difference.transpose() * difference
+    }
+}
+
+// Width in Å of the cosine taper of the low-pass filter of ILPBkg
+const LOW_PASS_TAPER: f64 = 0.1;
+
+// Part of chi(k), on a uniform k grid, below rmax in R. chi is mirrored at both ends before the
+// transform, so that the steps at the ends of the data do not leak into low R.
+fn low_pass(chi: &Array1<f64>, kstep: f64, rmax: f64) -> Array1<f64> {
+    let n = chi.len();
+    let mut mirrored = chi.to_vec();
+    mirrored.extend(chi.iter().rev().skip(1).take(n - 2));
+    let length = mirrored.len();
+
+    let mut freq = mirrored.real_fft();
+    let dr = std::f64::consts::PI / (length as f64 * kstep);
+    for (j, bin) in freq.get_frequency_bins_mut().iter_mut().enumerate() {
+        let r = (j + 1) as f64 * dr;
+        *bin *= if r >= rmax {
+            0.0
+        } else if r > rmax - LOW_PASS_TAPER {
+            (std::f64::consts::FRAC_PI_2 * (r - rmax + LOW_PASS_TAPER) / LOW_PASS_TAPER)
+                .cos()
+                .powi(2)
+        } else {
+            1.0
+        };
+    }
+
+    Array1::from_iter(freq.real_ifft().iter().take(n).map(|x| x / length as f64))
 }
 
 #[cfg(test)]
@@ -882,4 +1674,331 @@ mod tests {
         assert!(mse < CHI_MSE_TOL);
         Ok(())
     }
+
+    #[test]
+    fn test_autobk_decimation() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        xafs_test_group.normalize()?;
+
+        let energy = xafs_test_group.energy.clone().unwrap();
+        let mu = xafs_test_group.mu.clone().unwrap();
+
+        let mut autobk_full = AUTOBK::new();
+        autobk_full.calc_background(&energy, &mu, &mut xafs_test_group.normalization)?;
+
+        let mut autobk_decimated = AUTOBK {
+            decimation: Some(2),
+            ..Default::default()
+        };
+        autobk_decimated.calc_background(&energy, &mu, &mut xafs_test_group.normalization)?;
+
+        // The background is still evaluated on the full grid
+        assert_eq!(
+            autobk_decimated.get_bkg().unwrap().len(),
+            autobk_full.get_bkg().unwrap().len()
+        );
+
+        let ftwin = autobk_full.get_ftwin().unwrap();
+        let chi_full = autobk_full.get_chi_kweighted().unwrap() * &ftwin;
+        let chi_decimated = autobk_decimated.get_chi_kweighted().unwrap() * &ftwin;
+
+        let mse = (&chi_full - &chi_decimated).mapv(|x| x.powi(2)).sum() / chi_full.len() as f64;
+
+        assert!(mse < CHI_MSE_TOL);
+        Ok(())
+    }
+
+    // Custom model delegating to AUTOBK with a fixed rbkg
+    #[derive(Debug, Clone)]
+    struct FixedRbkg {
+        autobk: AUTOBK,
+    }
+
+    impl BackgroundModel for FixedRbkg {
+        fn name(&self) -> &str {
+            "fixed_rbkg"
+        }
+
+        fn calc_background(
+            &mut self,
+            energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+            mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+            normalization: &mut Option<normalization::NormalizationMethod>,
+        ) -> Result<(), Box<dyn Error>> {
+            self.autobk.calc_background(energy, mu, normalization)?;
+            Ok(())
+        }
+
+        fn get_k(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+            self.autobk.k.clone()
+        }
+
+        fn get_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+            self.autobk.chi.clone()
+        }
+
+        fn parameters(&self) -> serde_json::Value {
+            serde_json::json!({ "rbkg": self.autobk.rbkg })
+        }
+
+        fn copy_parameters(&self) -> Box<dyn BackgroundModel> {
+            Box::new(FixedRbkg {
+                autobk: self.autobk.copy_parameters(),
+            })
+        }
+
+        fn clone_box(&self) -> Box<dyn BackgroundModel> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn fixed_rbkg(
+        parameters: serde_json::Value,
+    ) -> Result<Box<dyn BackgroundModel>, Box<dyn Error>> {
+        let mut autobk = AUTOBK::new();
+        autobk.rbkg = parameters["rbkg"].as_f64();
+        Ok(Box::new(FixedRbkg { autobk }))
+    }
+
+    #[test]
+    fn test_autobk_delta_bkg() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        xafs_test_group.normalize()?.calc_background()?;
+
+        let energy = xafs_test_group.energy.clone().unwrap();
+        let delta_bkg = xafs_test_group.get_delta_bkg().unwrap();
+        let edge_step = xafs_test_group
+            .normalization
+            .as_ref()
+            .unwrap()
+            .get_edge_step()
+            .unwrap();
+        let ek0 = match xafs_test_group.background.as_ref().unwrap() {
+            BackgroundMethod::AUTOBK(autobk) => *autobk.get_ek0().unwrap(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(delta_bkg.len(), energy.len());
+        delta_bkg.iter().zip(energy.iter()).for_each(|(d, e)| {
+            if *e < ek0 {
+                assert_eq!(*d, 0.0);
+            } else {
+                assert!(d.is_finite() && *d >= 0.0);
+            }
+        });
+
+        // A small band, widest right above the edge where the spline is least constrained
+        let above = delta_bkg
+            .iter()
+            .zip(energy.iter())
+            .filter(|(_, e)| **e > ek0)
+            .map(|(d, _)| d / edge_step)
+            .collect::<Vec<f64>>();
+        assert!(above.iter().sum::<f64>() / (above.len() as f64) < 0.01);
+        assert!(above[0] > above[above.len() / 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_autobk_fit_trace() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        xafs_test_group.normalize()?.calc_background()?;
+        assert!(xafs_test_group.get_fit_trace().is_none());
+
+        let chi = xafs_test_group.get_chi().unwrap().to_owned();
+
+        let autobk = AUTOBK {
+            trace: Some(true),
+            ..AUTOBK::default()
+        };
+        xafs_test_group
+            .set_background_method(Some(BackgroundMethod::AUTOBK(autobk)))?
+            .calc_background()?;
+
+        // Tracing does not change the fit
+        assert_eq!(xafs_test_group.get_chi().unwrap(), chi);
+
+        let trace = xafs_test_group.get_fit_trace().unwrap();
+        assert!(trace.success);
+        assert_eq!(trace.steps.len(), trace.number_of_evaluations);
+        assert!(trace
+            .steps
+            .iter()
+            .all(|step| step.params.len() == trace.steps[0].params.len()));
+
+        let norms = trace.residual_norms();
+        let best = norms.iter().cloned().fold(f64::INFINITY, f64::min);
+        assert!(best < norms[0]);
+        assert_abs_diff_eq!(
+            best,
+            (2.0 * trace.objective_function).sqrt(),
+            epsilon = 1e-6 * best
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_autobk_workspace() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?;
+        let (energy, mu) = (
+            spectrum.energy.clone().unwrap(),
+            spectrum.mu.clone().unwrap(),
+        );
+
+        AUTOBK_WORKSPACE.with(|workspace| workspace.borrow_mut().take());
+        let mut first = AUTOBK::new();
+        first.calc_background(&energy, &mu, &mut spectrum.normalization)?;
+        let nfft = AUTOBK_WORKSPACE.with(|w| w.borrow().as_ref().map(|w| w.fft.nfft()));
+        assert_eq!(nfft, Some(2048));
+
+        // The second fit on this thread reuses the workspace of the first
+        let mut second = AUTOBK::new();
+        second.calc_background(&energy, &mu, &mut spectrum.normalization)?;
+        assert_eq!(first.get_chi(), second.get_chi());
+        assert_eq!(first.get_bkg(), second.get_bkg());
+
+        let mut other_nfft = AUTOBK::new();
+        other_nfft.nfft = Some(4096);
+        other_nfft.calc_background(&energy, &mu, &mut spectrum.normalization)?;
+        let nfft = AUTOBK_WORKSPACE.with(|w| w.borrow().as_ref().map(|w| w.fft.nfft()));
+        assert_eq!(nfft, Some(4096));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_background() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        let mut expected = spectrum.clone();
+
+        let mut autobk = AUTOBK::new();
+        autobk.rbkg = Some(1.2);
+        expected.set_background_method(Some(BackgroundMethod::AUTOBK(autobk.clone())))?;
+        expected.calc_background()?;
+
+        spectrum.set_background_method(Some(BackgroundMethod::new_custom(FixedRbkg { autobk })))?;
+        let mut group = crate::xafs::xasgroup::XASGroup::new();
+        group.add_spectrum(spectrum);
+        group.calc_background()?.fft()?;
+
+        let spectrum = &group.spectra[0];
+        assert_eq!(spectrum.get_chi(), expected.get_chi());
+        assert!(spectrum.get_chir_mag().is_some());
+
+        register_background_model("fixed_rbkg", fixed_rbkg);
+        let json = serde_json::to_string(&spectrum.background)?;
+        let background: Option<BackgroundMethod> = serde_json::from_str(&json)?;
+        match background {
+            Some(BackgroundMethod::Custom(model)) => {
+                assert_eq!(model.parameters(), serde_json::json!({ "rbkg": 1.2 }));
+            }
+            _ => panic!("custom background was not deserialized"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ilpbkg_synthetic() -> Result<(), Box<dyn Error>> {
+        // Edge with a sloping pre- and post-edge and a single shell at 2.5 Å
+        let e0 = 8979.0;
+        let energy = Array1::range(8800.0, 9700.0, 0.5);
+        let k = energy.mapv(|e: f64| (xafsutils::constants::ETOK * (e - e0).max(0.0)).sqrt());
+        let chi_true = k.mapv(|k| 0.1 * (5.0 * k).sin() * (-0.01 * k * k).exp());
+        let step = energy.mapv(|e| 0.5 + 0.5 * ((e - e0) / 2.0).tanh());
+        let mu = &step * &(1.0 + &chi_true - energy.mapv(|e| 1e-4 * (e - e0)))
+            + energy.mapv(|e| 2e-5 * (e - 8800.0));
+
+        let mut ilpbkg = ILPBkg::new();
+        ilpbkg.ek0 = Some(e0);
+        ilpbkg.calc_background(&energy, &mu, &mut None)?;
+        assert!(ilpbkg.get_iterations().unwrap() < 100);
+
+        let k_out = ilpbkg.k.clone().unwrap();
+        let chi = ilpbkg.chi.clone().unwrap();
+        let expected = k_out.mapv(|k| 0.1 * (5.0 * k).sin() * (-0.01 * k * k).exp());
+        for ((k, a), b) in k_out.iter().zip(chi.iter()).zip(expected.iter()) {
+            if (3.0..12.0).contains(k) {
+                assert_abs_diff_eq!(a, b, epsilon = 0.01);
+            }
+        }
+        assert_eq!(ilpbkg.bkg.as_ref().unwrap().len(), energy.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ilpbkg_autobk() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?;
+        let mut reference = spectrum.clone();
+        reference.calc_background()?;
+
+        spectrum
+            .set_background_method(Some(BackgroundMethod::new_ilpbkg()))?
+            .calc_background()?
+            .fft()?;
+
+        // The first shells of chi(R) agree with AUTOBK
+        reference.fft()?;
+        let r = spectrum.get_r().unwrap();
+        let chir_mag = spectrum.get_chir_mag().unwrap();
+        let chir_mag_autobk = reference.get_chir_mag().unwrap();
+        let range = r.mapv(|r| if (1.5..3.5).contains(&r) { 1.0 } else { 0.0 });
+        let difference = ((&chir_mag - &chir_mag_autobk) * &range)
+            .mapv(|x| x * x)
+            .sum();
+        let signal = (&chir_mag_autobk * &range).mapv(|x| x * x).sum();
+        assert!(difference < 0.2 * signal, "{} {}", difference, signal);
+
+        let json = serde_json::to_string(&spectrum.background)?;
+        let background: Option<BackgroundMethod> = serde_json::from_str(&json)?;
+        let Some(BackgroundMethod::ILPBkg(ilpbkg)) = background else {
+            panic!("ILPBkg expected, got {:?}", background);
+        };
+        assert_eq!(ilpbkg.rbkg, Some(1.0));
+        assert!(ilpbkg.get_iterations().is_some());
+        assert_eq!(ilpbkg.chi.unwrap().len(), spectrum.get_chi().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ilpbkg_reference() -> Result<(), Box<dyn Error>> {
+        // ILPBkg is not equivalent to Larch's autobk: k chi(k) differs from it by about 55% rms
+        // over 3 - 12 1/Å, and Larch has no ILPBkg. chi(k) is compared with the reference data
+        // of this implementation for the default parameters, see test_ilpbkg_autobk for the
+        // agreement of chi(R) with AUTOBK.
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum
+            .normalize()?
+            .set_background_method(Some(BackgroundMethod::new_ilpbkg()))?
+            .calc_background()?;
+
+        let reference_path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS_ilpbkg_k.txt";
+        let reference = load_txt_f64(&reference_path, &PARAM_LOADTXT).unwrap();
+        let k_expected = reference.get_col(0);
+        let chi_expected = reference.get_col(1);
+
+        let k = spectrum.get_k().unwrap();
+        let chi = spectrum.get_chi().unwrap();
+        assert_eq!(k.len(), k_expected.len());
+
+        for i in 0..k.len() {
+            assert_abs_diff_eq!(k[i], k_expected[i], epsilon = TEST_TOL);
+            assert_abs_diff_eq!(chi[i], chi_expected[i], epsilon = 1e-8);
+        }
+
+        Ok(())
+    }
 }
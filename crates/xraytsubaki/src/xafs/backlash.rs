@@ -0,0 +1,241 @@
+//! Energy offsets between scans of opposite direction.
+//!
+//! Repeated scans measured alternately with increasing and decreasing energy can be offset in
+//! energy by the backlash of the monochromator drive. The offset shows up as an e0 alternating
+//! between two values from scan to scan. It is estimated by a least-squares fit of the e0 of
+//! the scans with a linear drift over the scans and a constant offset between the directions,
+//! so that a slow drift of the energy is not mistaken for backlash.
+
+use std::error::Error;
+use std::fmt;
+
+use nalgebra::{DMatrix, DVector};
+use serde::{Deserialize, Serialize};
+
+use super::lmutils;
+use super::xasgroup::XASGroup;
+use super::XAFSError;
+
+/// Metadata key of the scan direction, "up" or "down"
+pub const DIRECTION_KEY: &str = "scan_direction";
+
+/// Direction of the energy scan of the monochromator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanDirection {
+    /// Increasing energy
+    Up,
+    /// Decreasing energy
+    Down,
+}
+
+impl ScanDirection {
+    pub fn from_metadata(value: &serde_json::Value) -> Option<ScanDirection> {
+        match value.as_str()?.to_lowercase().as_str() {
+            "up" | "ascending" => Some(ScanDirection::Up),
+            "down" | "descending" => Some(ScanDirection::Down),
+            _ => None,
+        }
+    }
+
+    fn sign(self) -> f64 {
+        match self {
+            ScanDirection::Up => 0.5,
+            ScanDirection::Down => -0.5,
+        }
+    }
+}
+
+impl fmt::Display for ScanDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScanDirection::Up => write!(f, "up"),
+            ScanDirection::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// Result of XASGroup::detect_backlash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacklashAnalysis {
+    /// Indices of the analysed scans in the group, in measurement order
+    pub indices: Vec<usize>,
+    pub directions: Vec<ScanDirection>,
+    pub e0: Vec<f64>,
+    /// e0 of the up scans minus e0 of the down scans (eV)
+    pub offset: f64,
+    /// Standard error of the offset (eV)
+    pub offset_std: f64,
+    /// Drift of e0 per scan (eV)
+    pub drift: f64,
+}
+
+impl BacklashAnalysis {
+    /// Whether the offset exceeds `nsigma` standard errors.
+    pub fn is_significant(&self, nsigma: f64) -> bool {
+        self.offset.abs() > nsigma * self.offset_std
+    }
+}
+
+impl XASGroup {
+    /// Direction of the scans at `indices`, read from the metadata DIRECTION_KEY.
+    ///
+    /// Scans without the key are assumed to alternate, starting with an up scan.
+    pub fn scan_directions(&self, indices: &[usize]) -> Vec<ScanDirection> {
+        indices
+            .iter()
+            .enumerate()
+            .map(|(position, &i)| {
+                self.spectra
+                    .get(i)
+                    .and_then(|spectrum| spectrum.get_metadata(DIRECTION_KEY))
+                    .and_then(ScanDirection::from_metadata)
+                    .unwrap_or(if position % 2 == 0 {
+                        ScanDirection::Up
+                    } else {
+                        ScanDirection::Down
+                    })
+            })
+            .collect()
+    }
+
+    /// Estimate the energy offset between the up and down scans among the repeated scans at
+    /// `indices`, given in measurement order.
+    ///
+    /// e0 of the spectra is used if set and found with find_e0 otherwise. At least four scans,
+    /// including both directions, are needed to fit the offset together with a linear drift.
+    pub fn detect_backlash(&self, indices: &[usize]) -> Result<BacklashAnalysis, Box<dyn Error>> {
+        if indices.iter().any(|&i| i >= self.len()) {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        let directions = self.scan_directions(indices);
+        if indices.len() < 4
+            || !directions.contains(&ScanDirection::Up)
+            || !directions.contains(&ScanDirection::Down)
+        {
+            return Err("at least four scans including both directions are needed".into());
+        }
+
+        let e0 = indices
+            .iter()
+            .map(|&i| match self.spectra[i].get_e0() {
+                Some(e0) => Ok(e0),
+                None => {
+                    let mut spectrum = self.spectra[i].clone();
+                    spectrum.find_e0()?;
+                    spectrum
+                        .get_e0()
+                        .ok_or_else(|| XAFSError::NotEnoughData.into())
+                }
+            })
+            .collect::<Result<Vec<f64>, Box<dyn Error>>>()?;
+
+        // e0 = a + drift * scan + offset * (+1/2 for up, -1/2 for down)
+        let n = indices.len();
+        let design = DMatrix::from_fn(n, 3, |row, col| match col {
+            0 => 1.0,
+            1 => row as f64,
+            _ => directions[row].sign(),
+        });
+        let values = DVector::from_column_slice(&e0);
+
+        let coefs =
+            lmutils::lstsq_nalgebra_f64(&design, &values).ok_or("backlash fit did not converge")?;
+        let covariance = lmutils::covariance_from_jacobian_nalgebra_f64(&design)
+            .ok_or("drift and backlash cannot be separated with this order of scans")?;
+
+        let residuals = &values - &design * &coefs;
+        let dof = (n - 3).max(1) as f64;
+        let variance = residuals.norm_squared() / dof;
+
+        Ok(BacklashAnalysis {
+            indices: indices.to_vec(),
+            directions,
+            e0,
+            offset: coefs[2],
+            offset_std: (covariance[(2, 2)] * variance).sqrt(),
+            drift: coefs[1],
+        })
+    }
+
+    /// Shift the scans of the other direction than `reference` by the offset of `analysis`,
+    /// e.g. before merging them.
+    ///
+    /// Normalization, background and FT have to be recalculated afterwards. Returns the shift
+    /// applied to each scan of the analysis.
+    pub fn correct_backlash(
+        &mut self,
+        analysis: &BacklashAnalysis,
+        reference: ScanDirection,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        if analysis.indices.iter().any(|&i| i >= self.len()) {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        let shifts = analysis
+            .directions
+            .iter()
+            .map(|&direction| match (direction, reference) {
+                (a, b) if a == b => 0.0,
+                (ScanDirection::Up, _) => -analysis.offset,
+                (ScanDirection::Down, _) => analysis.offset,
+            })
+            .collect::<Vec<f64>>();
+
+        for (&i, &shift) in analysis.indices.iter().zip(shifts.iter()) {
+            self.spectra[i].shift_energy(shift, false)?;
+        }
+
+        Ok(shifts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::xasspectrum::XASSpectrum;
+    use approx::assert_abs_diff_eq;
+    use ndarray::Array1;
+
+    #[test]
+    fn test_backlash() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::range(8900.0, 9100.0, 0.05);
+
+        // Up scans 0.4 eV above the down scans, with a drift of 0.05 eV per scan
+        let mut group = XASGroup::new();
+        for i in 0..6 {
+            let e0 = 8979.0 + 0.05 * i as f64 + if i % 2 == 0 { 0.2 } else { -0.2 };
+            let mu = energy.mapv(|e| 1.0 / (1.0 + (-(e - e0) / 1.5).exp()));
+            let mut spectrum = XASSpectrum::new();
+            spectrum.set_spectrum(energy.clone(), mu);
+            group.add_spectrum(spectrum);
+        }
+
+        let indices = (0..6).collect::<Vec<usize>>();
+        let analysis = group.detect_backlash(&indices)?;
+        assert_eq!(analysis.directions[1], ScanDirection::Down);
+        assert_abs_diff_eq!(analysis.offset, 0.4, epsilon = 0.02);
+        assert_abs_diff_eq!(analysis.drift, 0.05, epsilon = 0.01);
+        assert!(analysis.is_significant(3.0));
+
+        let shifts = group.correct_backlash(&analysis, ScanDirection::Up)?;
+        assert_eq!(shifts[0], 0.0);
+        assert_eq!(shifts[1], analysis.offset);
+
+        let corrected = group.detect_backlash(&indices)?;
+        assert_abs_diff_eq!(corrected.offset, 0.0, epsilon = 0.02);
+
+        // Directions from the metadata: two up scans followed by two down scans
+        for (i, direction) in ["up", "up", "down", "down"].iter().enumerate() {
+            group.spectra[i].set_metadata(DIRECTION_KEY, *direction);
+        }
+        let directions = group.scan_directions(&indices);
+        assert_eq!(directions[1], ScanDirection::Up);
+        assert_eq!(directions[4], ScanDirection::Up);
+
+        assert!(group.detect_backlash(&[0, 2, 4]).is_err());
+        assert!(group.detect_backlash(&[0, 1, 2, 10]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! Bond valence sums as a plausibility check of fitted coordination shells.
+//!
+//! The valence of a bond of length R is s = exp((R0 - R) / b), and the bond valence sum
+//! V = sum N_i exp((R0 - R_i) / b) over the first shell(s) of the absorber should be close to
+//! its oxidation state. A fitted N and R that give a sum far from the nominal oxidation state
+//! point to a correlated N/R/sigma2 solution or a wrong shell assignment.
+//!
+//! The built-in parameters are those of Brown & Altermatt, Acta Cryst. B41 (1985) 244 and
+//! Brese & O'Keeffe, Acta Cryst. B47 (1991) 192 for oxides and sulfides, with b = 0.37 Å.
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+/// Universal softness parameter b (Å)
+pub const BOND_VALENCE_B: f64 = 0.37;
+
+/// Bond valence parameters of a cation-anion pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BondValenceParameter {
+    pub cation: String,
+    pub oxidation_state: i32,
+    pub anion: String,
+    /// Bond length (Å) of unit valence
+    pub r0: f64,
+    pub b: f64,
+}
+
+impl BondValenceParameter {
+    pub fn new<S: Into<String>>(cation: S, oxidation_state: i32, anion: S, r0: f64) -> Self {
+        BondValenceParameter {
+            cation: cation.into(),
+            oxidation_state,
+            anion: anion.into(),
+            r0,
+            b: BOND_VALENCE_B,
+        }
+    }
+
+    /// Built-in parameter for the pair, if tabulated.
+    pub fn lookup(cation: &str, oxidation_state: i32, anion: &str) -> Option<Self> {
+        BOND_VALENCE_TABLE
+            .iter()
+            .find(|(c, ox, a, _)| {
+                c.eq_ignore_ascii_case(cation)
+                    && *ox == oxidation_state
+                    && a.eq_ignore_ascii_case(anion)
+            })
+            .map(|(c, ox, a, r0)| BondValenceParameter::new(*c, *ox, *a, *r0))
+    }
+
+    /// All built-in parameters of the pair, one per tabulated oxidation state.
+    pub fn lookup_all(cation: &str, anion: &str) -> Vec<Self> {
+        BOND_VALENCE_TABLE
+            .iter()
+            .filter(|(c, _, a, _)| c.eq_ignore_ascii_case(cation) && a.eq_ignore_ascii_case(anion))
+            .map(|(c, ox, a, r0)| BondValenceParameter::new(*c, *ox, *a, *r0))
+            .collect()
+    }
+
+    /// Valence of a single bond of length `r` (Å)
+    pub fn bond_valence(&self, r: f64) -> f64 {
+        ((self.r0 - r) / self.b).exp()
+    }
+
+    /// Coordination number giving a bond valence sum equal to the oxidation state at bond
+    /// length `r` (Å)
+    pub fn expected_coordination_number(&self, r: f64) -> f64 {
+        self.oxidation_state as f64 / self.bond_valence(r)
+    }
+
+    /// Bond length (Å) giving a bond valence sum equal to the oxidation state for `n` neighbors
+    pub fn expected_distance(&self, n: f64) -> f64 {
+        self.r0 - self.b * (self.oxidation_state as f64 / n).ln()
+    }
+}
+
+/// (cation, oxidation state, anion, R0)
+const BOND_VALENCE_TABLE: &[(&str, i32, &str, f64)] = &[
+    ("Ti", 3, "O", 1.791),
+    ("Ti", 4, "O", 1.815),
+    ("V", 3, "O", 1.743),
+    ("V", 4, "O", 1.784),
+    ("V", 5, "O", 1.803),
+    ("Cr", 3, "O", 1.724),
+    ("Cr", 6, "O", 1.794),
+    ("Mn", 2, "O", 1.790),
+    ("Mn", 3, "O", 1.760),
+    ("Mn", 4, "O", 1.753),
+    ("Fe", 2, "O", 1.734),
+    ("Fe", 3, "O", 1.759),
+    ("Co", 2, "O", 1.692),
+    ("Ni", 2, "O", 1.654),
+    ("Cu", 1, "O", 1.610),
+    ("Cu", 2, "O", 1.679),
+    ("Zn", 2, "O", 1.704),
+    ("Zn", 2, "S", 2.09),
+    ("Mo", 6, "O", 1.907),
+    ("W", 6, "O", 1.917),
+    ("Ce", 3, "O", 2.151),
+    ("Ce", 4, "O", 2.028),
+    ("U", 6, "O", 2.075),
+];
+
+/// Coordination shell of a fit, with optional uncertainties
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BondShell {
+    pub n: f64,
+    /// Distance (Å)
+    pub r: f64,
+    pub delta_n: Option<f64>,
+    pub delta_r: Option<f64>,
+}
+
+impl BondShell {
+    pub fn new(n: f64, r: f64) -> Self {
+        BondShell {
+            n,
+            r,
+            delta_n: None,
+            delta_r: None,
+        }
+    }
+
+    pub fn with_std(n: f64, r: f64, delta_n: f64, delta_r: f64) -> Self {
+        BondShell {
+            n,
+            r,
+            delta_n: Some(delta_n),
+            delta_r: Some(delta_r),
+        }
+    }
+}
+
+/// Bond valence sum of the shells and its uncertainty
+///
+/// The uncertainty is propagated from delta_n and delta_r assuming uncorrelated errors, and is
+/// 0 if none of the shells has uncertainties.
+pub fn bond_valence_sum(shells: &[BondShell], parameter: &BondValenceParameter) -> (f64, f64) {
+    let (sum, variance) = shells.iter().fold((0.0, 0.0), |(sum, variance), shell| {
+        let s = parameter.bond_valence(shell.r);
+        let dn = shell.delta_n.unwrap_or(0.0) * s;
+        let dr = shell.delta_r.unwrap_or(0.0) * shell.n * s / parameter.b;
+
+        (sum + shell.n * s, variance + dn * dn + dr * dr)
+    });
+
+    (sum, variance.sqrt())
+}
+
+/// Comparison of a bond valence sum with the nominal oxidation state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BondValenceCheck {
+    pub parameter: BondValenceParameter,
+    pub sum: f64,
+    pub sum_std: f64,
+    /// sum - oxidation state
+    pub deviation: f64,
+    /// Whether the deviation is within the tolerance, widened by the uncertainty of the sum
+    pub plausible: bool,
+}
+
+/// Check the shells against the oxidation state of `parameter`.
+///
+/// `tolerance` is the accepted deviation as a fraction of the oxidation state; 0.1 is typical
+/// for well-determined structures.
+pub fn check_bond_valence(
+    shells: &[BondShell],
+    parameter: &BondValenceParameter,
+    tolerance: f64,
+) -> Result<BondValenceCheck, Box<dyn Error>> {
+    if shells.is_empty() {
+        return Err("no shells to calculate the bond valence sum".into());
+    }
+
+    let (sum, sum_std) = bond_valence_sum(shells, parameter);
+    let deviation = sum - parameter.oxidation_state as f64;
+
+    Ok(BondValenceCheck {
+        parameter: parameter.clone(),
+        sum,
+        sum_std,
+        deviation,
+        plausible: deviation.abs()
+            <= tolerance * (parameter.oxidation_state as f64).abs() + sum_std,
+    })
+}
+
+/// Check the shells against every tabulated oxidation state of the pair, ordered from the
+/// most to the least consistent.
+pub fn estimate_oxidation_state(
+    shells: &[BondShell],
+    cation: &str,
+    anion: &str,
+    tolerance: f64,
+) -> Result<Vec<BondValenceCheck>, Box<dyn Error>> {
+    let parameters = BondValenceParameter::lookup_all(cation, anion);
+
+    if parameters.is_empty() {
+        return Err(format!("no bond valence parameters for {}-{}", cation, anion).into());
+    }
+
+    let mut checks = parameters
+        .iter()
+        .map(|parameter| check_bond_valence(shells, parameter, tolerance))
+        .collect::<Result<Vec<BondValenceCheck>, Box<dyn Error>>>()?;
+    checks.sort_by(|a, b| a.deviation.abs().total_cmp(&b.deviation.abs()));
+
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::tests::TEST_TOL;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_bond_valence() -> Result<(), Box<dyn Error>> {
+        let fe3 = BondValenceParameter::lookup("Fe", 3, "O").unwrap();
+        assert!(BondValenceParameter::lookup("Fe", 5, "O").is_none());
+
+        // Octahedral Fe(III) with the distance that gives exactly 3 v.u.
+        let r = fe3.expected_distance(6.0);
+        assert_abs_diff_eq!(fe3.expected_coordination_number(r), 6.0, epsilon = TEST_TOL);
+
+        let (sum, sum_std) = bond_valence_sum(&[BondShell::new(6.0, r)], &fe3);
+        assert_abs_diff_eq!(sum, 3.0, epsilon = TEST_TOL);
+        assert_eq!(sum_std, 0.0);
+
+        // Fe2O3-like shell, 3 O at 1.95 Å and 3 O at 2.10 Å
+        let shells = [
+            BondShell::with_std(3.0, 1.95, 0.5, 0.01),
+            BondShell::with_std(3.0, 2.10, 0.5, 0.01),
+        ];
+        let check = check_bond_valence(&shells, &fe3, 0.1)?;
+        assert!(check.plausible);
+        assert!(check.sum_std > 0.0);
+
+        // An unphysical fit with too many short bonds
+        let check = check_bond_valence(&[BondShell::new(9.0, 1.90)], &fe3, 0.1)?;
+        assert!(!check.plausible);
+        assert!(check.deviation > 0.0);
+
+        let checks = estimate_oxidation_state(&shells, "Fe", "O", 0.1)?;
+        assert_eq!(checks[0].parameter.oxidation_state, 3);
+        assert!(estimate_oxidation_state(&shells, "Xx", "O", 0.1).is_err());
+        assert!(check_bond_valence(&[], &fe3, 0.1).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Peaks of |chi(R)|.
+//!
+//! Local maxima of |chi(R)| in an R range are reported with their position, height and full
+//! width at half maximum, and labelled as shells in order of increasing R. Peaks lower than a
+//! fraction of the highest peak in the range, such as the side lobes of the window, are
+//! skipped. The half-maximum bounds of the first shell are a starting point for the R window
+//! of a first-shell fit.
+
+use std::error::Error;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Parameters of the peak search in |chi(R)|
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChiRPeakOptions {
+    /// Start of the searched R range. Default = 0.8 Å, above the residue of the background.
+    pub rmin: Option<f64>,
+    /// End of the searched R range. Default = 6 Å.
+    pub rmax: Option<f64>,
+    /// Smallest height relative to the highest peak in the range. Default = 0.1.
+    pub min_height: Option<f64>,
+}
+
+impl Default for ChiRPeakOptions {
+    fn default() -> Self {
+        ChiRPeakOptions {
+            rmin: Some(0.8),
+            rmax: Some(6.0),
+            min_height: Some(0.1),
+        }
+    }
+}
+
+impl ChiRPeakOptions {
+    pub fn new() -> ChiRPeakOptions {
+        ChiRPeakOptions::default()
+    }
+}
+
+/// Peak of |chi(R)|
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChiRPeak {
+    /// Shell number, 1 for the peak at the lowest R
+    pub shell: usize,
+    /// Position (Å), refined between the grid points
+    pub r: f64,
+    pub height: f64,
+    /// Full width at half maximum (Å), limited by the neighbouring minima
+    pub fwhm: f64,
+    /// Half-maximum bounds of the peak (Å)
+    pub rmin: f64,
+    pub rmax: f64,
+}
+
+/// Peaks of `chir_mag` on the grid `r`, sorted by R.
+pub fn find_chir_peaks(
+    r: &Array1<f64>,
+    chir_mag: &Array1<f64>,
+    options: &ChiRPeakOptions,
+) -> Result<Vec<ChiRPeak>, Box<dyn Error>> {
+    let default = ChiRPeakOptions::default();
+    let rmin = options.rmin.or(default.rmin).unwrap();
+    let rmax = options.rmax.or(default.rmax).unwrap();
+    let min_height = options.min_height.or(default.min_height).unwrap();
+
+    let n = r.len().min(chir_mag.len());
+    let index = (0..n)
+        .filter(|&i| r[i] >= rmin && r[i] <= rmax)
+        .collect::<Vec<usize>>();
+
+    if index.len() < 3 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    let (first, last) = (index[0], index[index.len() - 1]);
+    let maxima = (first.max(1)..last.min(n - 2) + 1)
+        .filter(|&i| chir_mag[i] > chir_mag[i - 1] && chir_mag[i] >= chir_mag[i + 1])
+        .collect::<Vec<usize>>();
+
+    let highest = maxima.iter().map(|&i| chir_mag[i]).fold(0.0, f64::max);
+
+    let peaks = maxima
+        .into_iter()
+        .filter(|&i| chir_mag[i] >= min_height * highest)
+        .enumerate()
+        .map(|(shell, i)| {
+            // Parabola through the neighbouring points
+            let (y0, y1, y2) = (chir_mag[i - 1], chir_mag[i], chir_mag[i + 1]);
+            let step = r[i + 1] - r[i];
+            let denominator = y0 - 2.0 * y1 + y2;
+            let (position, height) = if denominator < 0.0 {
+                let offset = 0.5 * (y0 - y2) / denominator;
+                (r[i] + offset * step, y1 - 0.25 * (y0 - y2) * offset)
+            } else {
+                (r[i], y1)
+            };
+
+            let low = half_maximum(r, chir_mag, i, height, -1);
+            let high = half_maximum(r, chir_mag, i, height, 1);
+
+            ChiRPeak {
+                shell: shell + 1,
+                r: position,
+                height,
+                fwhm: high - low,
+                rmin: low,
+                rmax: high,
+            }
+        })
+        .collect();
+
+    Ok(peaks)
+}
+
+// R where |chi(R)| falls to half of `height` going from the maximum at `i` in `direction`,
+// or the position of the minimum if it rises again before.
+fn half_maximum(
+    r: &Array1<f64>,
+    chir_mag: &Array1<f64>,
+    i: usize,
+    height: f64,
+    direction: isize,
+) -> f64 {
+    let half = 0.5 * height;
+    let n = r.len().min(chir_mag.len()) as isize;
+
+    let mut j = i as isize;
+    loop {
+        let next = j + direction;
+        if next < 0 || next >= n {
+            return r[j as usize];
+        }
+
+        let (a, b) = (j as usize, next as usize);
+        if chir_mag[b] <= half {
+            return r[a] + (r[b] - r[a]) * (chir_mag[a] - half) / (chir_mag[a] - chir_mag[b]);
+        }
+        if chir_mag[b] > chir_mag[a] {
+            return r[a];
+        }
+        j = next;
+    }
+}
+
+impl XASSpectrum {
+    /// Peaks of |chi(R)| of the forward FT, see find_chir_peaks.
+    pub fn chir_peaks(&self, options: &ChiRPeakOptions) -> Result<Vec<ChiRPeak>, Box<dyn Error>> {
+        let r = self.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+        let chir_mag = self.get_chir_mag().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+        find_chir_peaks(&r.to_owned(), &chir_mag.to_owned(), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::xrayfft::XrayFFTF;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_chir_peaks() -> Result<(), Box<dyn Error>> {
+        let r = Array1::range(0.0, 8.0, 0.01);
+        let gaussian = |r0: f64, height: f64, sigma: f64| {
+            r.mapv(|x| height * (-(x - r0).powi(2) / (2.0 * sigma * sigma)).exp())
+        };
+        let chir_mag = gaussian(0.3, 5.0, 0.1)
+            + gaussian(1.9, 2.0, 0.15)
+            + gaussian(3.1, 0.8, 0.2)
+            + gaussian(5.0, 0.1, 0.1);
+
+        let peaks = find_chir_peaks(&r, &chir_mag, &ChiRPeakOptions::new())?;
+        assert_eq!(peaks.len(), 2);
+        assert_eq!(peaks[0].shell, 1);
+        assert_abs_diff_eq!(peaks[0].r, 1.9, epsilon = 1e-3);
+        assert_abs_diff_eq!(peaks[0].height, 2.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(peaks[0].fwhm, 2.3548 * 0.15, epsilon = 1e-2);
+        assert_abs_diff_eq!(peaks[1].r, 3.1, epsilon = 1e-3);
+
+        // Single scattering at 2.5 Å: the peak of |chi(R)| is at R
+        let k = Array1::range(0.0, 16.0, 0.05);
+        let chi = k.mapv(|k: f64| (2.0 * k * 2.5).sin() * (-0.01 * k * k).exp() / (k + 1.0));
+        let mut xftf = XrayFFTF::new();
+        xftf.xftf(k.view(), chi.view());
+        let mut spectrum = XASSpectrum::new();
+        spectrum.xftf = Some(xftf);
+
+        let peaks = spectrum.chir_peaks(&ChiRPeakOptions::new())?;
+        assert_abs_diff_eq!(peaks[0].r, 2.5, epsilon = 0.05);
+        assert!(peaks[0].rmin < 2.5 && peaks[0].rmax > 2.5);
+
+        assert!(XASSpectrum::new()
+            .chir_peaks(&ChiRPeakOptions::new())
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Comparison of spectra from different groups on a shared grid.
+//!
+//! A sample and its standards usually live in different groups, were measured on different
+//! energy grids and may have slightly different e0. compare_spectra puts a product of all of
+//! them on the grid of the first (reference) spectrum, optionally shifting the energy of the
+//! others so that their e0 coincides with the reference, without touching the spectra themselves.
+
+use std::error::Error;
+
+use ndarray::{Array1, Array2};
+
+use super::plot::{PlotData, PlotKind};
+use super::statistics::common_grid_matrix;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// A product of several spectra interpolated on a shared grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub kind: PlotKind,
+    /// Names of the spectra, one per row of `y`
+    pub names: Vec<Option<String>>,
+    /// e0 of the spectra before alignment
+    pub e0: Vec<Option<f64>>,
+    /// Energy shift applied to each spectrum. Zero unless e0 alignment was requested.
+    pub shifts: Vec<f64>,
+    /// Shared grid, the x values of the reference covered by all spectra
+    pub x: Array1<f64>,
+    /// Product with shape (names.len(), x.len())
+    pub y: Array2<f64>,
+}
+
+impl Comparison {
+    pub fn len(&self) -> usize {
+        self.y.nrows()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.y.nrows() == 0
+    }
+
+    /// Difference of the spectra at `index` and `other`.
+    pub fn difference(&self, index: usize, other: usize) -> Result<Array1<f64>, Box<dyn Error>> {
+        if index >= self.len() || other >= self.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        Ok(&self.y.row(index) - &self.y.row(other))
+    }
+
+    /// Plot of the spectrum at `index` on the shared grid.
+    pub fn plot_data(&self, index: usize) -> Result<PlotData, Box<dyn Error>> {
+        if index >= self.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        Ok(PlotData::new(self.x.clone(), self.y.row(index).to_owned()))
+    }
+}
+
+/// Interpolate `kind` of `spectra` on the grid of the first spectrum.
+///
+/// The grid is limited to the range covered by all spectra. With `align_e0`, energy-space
+/// products (mu, norm, flat) of each spectrum are shifted by the difference between the e0 of
+/// the reference and its own e0, which then has to be known for every spectrum. k, R and q
+/// are already relative to e0 and are never shifted.
+pub fn compare_spectra(
+    spectra: &[&XASSpectrum],
+    kind: PlotKind,
+    align_e0: bool,
+) -> Result<Comparison, Box<dyn Error>> {
+    if spectra.is_empty() {
+        return Err(Box::new(XAFSError::GroupIsEmpty));
+    }
+
+    let e0 = spectra
+        .iter()
+        .map(|spectrum| {
+            spectrum
+                .get_e0()
+                .or_else(|| spectrum.normalization.as_ref()?.get_e0())
+        })
+        .collect::<Vec<Option<f64>>>();
+
+    let energy_space = matches!(kind, PlotKind::Mu | PlotKind::Norm | PlotKind::Flat);
+    let shifts = if align_e0 && energy_space {
+        let e0 = e0
+            .iter()
+            .map(|e0| e0.ok_or(XAFSError::NotEnoughData))
+            .collect::<Result<Vec<f64>, XAFSError>>()?;
+        e0.iter().map(|e0_i| e0[0] - e0_i).collect::<Vec<f64>>()
+    } else {
+        vec![0.0; spectra.len()]
+    };
+
+    let (x, y) = common_grid_matrix(spectra, kind, Some(&shifts))?;
+
+    Ok(Comparison {
+        kind,
+        names: spectra
+            .iter()
+            .map(|spectrum| spectrum.name.clone())
+            .collect(),
+        e0,
+        shifts,
+        x,
+        y,
+    })
+}
+
+/// compare_spectra for spectra picked from several groups as (group, index) pairs.
+pub fn compare_group_spectra(
+    spectra: &[(&XASGroup, usize)],
+    kind: PlotKind,
+    align_e0: bool,
+) -> Result<Comparison, Box<dyn Error>> {
+    let spectra = spectra
+        .iter()
+        .map(|(group, index)| {
+            if *index >= group.len() {
+                return Err(Box::new(XAFSError::GroupIndexOutOfRange) as Box<dyn Error>);
+            }
+            Ok(&group.spectra[*index])
+        })
+        .collect::<Result<Vec<&XASSpectrum>, Box<dyn Error>>>()?;
+
+    compare_spectra(&spectra, kind, align_e0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TEST_TOL;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_compare_spectra() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut sample = io::load_spectrum_QAS_trans(&path)?;
+        sample.set_name("sample").normalize()?;
+
+        // The same spectrum on a coarser grid, shifted by 2 eV
+        let energy = sample.energy.clone().unwrap();
+        let mu = sample.mu.clone().unwrap();
+        let e0 = sample.normalization.as_ref().unwrap().get_e0().unwrap();
+        let mut standard = XASSpectrum::new();
+        standard
+            .set_spectrum(
+                Array1::from_iter(energy.iter().step_by(2).map(|e| e + 2.0)),
+                Array1::from_iter(mu.iter().step_by(2).cloned()),
+            )
+            .set_name("standard")
+            .set_e0(e0 + 2.0);
+        standard.normalize()?;
+
+        let mut sample_group = XASGroup::new();
+        sample_group.add_spectrum(sample.clone());
+        let mut standards = XASGroup::new();
+        standards.add_spectrum(standard.clone());
+
+        let comparison =
+            compare_group_spectra(&[(&sample_group, 0), (&standards, 0)], PlotKind::Mu, true)?;
+        assert_eq!(comparison.len(), 2);
+        assert_eq!(comparison.names[1].as_deref(), Some("standard"));
+        assert_abs_diff_eq!(comparison.shifts[1], -2.0, epsilon = TEST_TOL);
+        assert!(comparison.x.iter().all(|x| energy.iter().any(|e| e == x)));
+
+        // Aligned, the standard matches the sample up to the interpolation error
+        let difference = comparison.difference(1, 0)?;
+        let mean = difference.mapv(f64::abs).sum() / difference.len() as f64;
+        assert!(mean < 1e-3);
+
+        let unaligned = compare_spectra(&[&sample, &standard], PlotKind::Mu, false)?;
+        let difference = unaligned.difference(1, 0)?;
+        assert!(difference.mapv(f64::abs).sum() / difference.len() as f64 > mean);
+
+        // The originals are left untouched
+        assert_eq!(standards.spectra[0], standard);
+
+        assert!(compare_group_spectra(&[(&standards, 1)], PlotKind::Mu, false).is_err());
+        assert!(compare_spectra(&[], PlotKind::Mu, false).is_err());
+        assert!(comparison.plot_data(2).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! Persistent user defaults.
+//!
+//! The configuration is a TOML file in the XDG config directory,
+//! `$XDG_CONFIG_HOME/xraytsubaki/config.toml` (`~/.config/xraytsubaki/config.toml` if
+//! XDG_CONFIG_HOME is not set, `%APPDATA%\xraytsubaki\config.toml` on Windows). The
+//! XRAYTSUBAKI_CONFIG environment variable points to another file.
+//!
+//! ```toml
+//! window = "KaiserBessel"
+//! kweight = 3.0
+//! bkg_kweight = 2
+//!
+//! [plot]
+//! line_width = 1.5
+//! colormap = "viridis"
+//!
+//! [directories]
+//! data = "/data/beamtime"
+//! export = "/home/user/results"
+//! ```
+//!
+//! Every field is optional. The window and kweight are the defaults of the forward and reverse
+//! FT, and bkg_kweight the one of AUTOBK. The library never applies them by itself, so that
+//! results do not depend on the machine they are computed on: a frontend opts in by calling
+//! fill_from_config, e.g. `group.fill_from_config(&user_config())`, which gives the spectra
+//! the user defaults for the parameters they do not set. The plot style and the directories
+//! are read by the frontends. The configuration is loaded on first use and can be replaced at
+//! runtime with set_user_config.
+
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::background::{BackgroundMethod, AUTOBK};
+use super::xafsutils::FTWindow;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::{XrayFFTF, XrayFFTR};
+
+/// Environment variable overriding the path of the configuration file
+pub const CONFIG_ENV: &str = "XRAYTSUBAKI_CONFIG";
+
+const CONFIG_DIR: &str = "xraytsubaki";
+const CONFIG_FILE: &str = "config.toml";
+
+/// User defaults, see the module documentation
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserConfig {
+    /// Preferred FT window
+    pub window: Option<FTWindow>,
+    /// Preferred kweight of the FT
+    pub kweight: Option<f64>,
+    /// Preferred kweight of the background removal (AUTOBK)
+    pub bkg_kweight: Option<i32>,
+    pub plot: PlotStyle,
+    pub directories: Directories,
+}
+
+/// Plot style of the frontends
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlotStyle {
+    pub line_width: Option<f64>,
+    /// Name of the colormap of multi-spectrum plots
+    pub colormap: Option<String>,
+    /// Width in pixels that plot data are decimated to (see PlotData::decimate)
+    pub pixels: Option<usize>,
+}
+
+/// Default directories of the file dialogs and exports
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Directories {
+    /// Directory data files are opened from
+    pub data: Option<PathBuf>,
+    /// Directory results are exported to
+    pub export: Option<PathBuf>,
+}
+
+lazy_static::lazy_static! {
+    static ref USER_CONFIG: RwLock<Option<UserConfig>> = RwLock::new(None);
+}
+
+impl UserConfig {
+    pub fn new() -> UserConfig {
+        UserConfig::default()
+    }
+
+    pub fn set_window(&mut self, window: Option<FTWindow>) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    pub fn set_kweight(&mut self, kweight: Option<f64>) -> &mut Self {
+        self.kweight = kweight;
+        self
+    }
+
+    pub fn set_bkg_kweight(&mut self, bkg_kweight: Option<i32>) -> &mut Self {
+        self.bkg_kweight = bkg_kweight;
+        self
+    }
+
+    pub fn set_export_dir<P: Into<PathBuf>>(&mut self, export: Option<P>) -> &mut Self {
+        self.directories.export = export.map(|p| p.into());
+        self
+    }
+
+    pub fn from_toml(text: &str) -> Result<UserConfig, Box<dyn Error>> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Read the configuration from `path`. A missing file gives the default configuration.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<UserConfig, Box<dyn Error>> {
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(text) => UserConfig::from_toml(&text)
+                .map_err(|e| format!("{}: {}", path.as_ref().display(), e).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(UserConfig::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the configuration to `path`, creating its directory if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_toml()?)?;
+
+        Ok(())
+    }
+
+    /// Configuration of the file at config_path(), default if there is none.
+    pub fn load_default() -> Result<UserConfig, Box<dyn Error>> {
+        match config_path() {
+            Some(path) => UserConfig::load(path),
+            None => Ok(UserConfig::default()),
+        }
+    }
+}
+
+/// Path of the configuration file, see the module documentation. None if neither the
+/// environment variables nor the home directory are set.
+pub fn config_path() -> Option<PathBuf> {
+    let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+
+    if let Some(path) = non_empty(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let dir = if cfg!(windows) {
+        non_empty("APPDATA").map(PathBuf::from)
+    } else {
+        non_empty("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+
+    Some(dir.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+/// The configuration in use. It is loaded from config_path() on first use; a file that can not
+/// be read gives the default configuration (see reload_user_config for the error).
+pub fn user_config() -> UserConfig {
+    if let Some(config) = USER_CONFIG.read().unwrap().as_ref() {
+        return config.clone();
+    }
+
+    let mut cached = USER_CONFIG.write().unwrap();
+    cached
+        .get_or_insert_with(|| UserConfig::load_default().unwrap_or_default())
+        .clone()
+}
+
+/// Replace the configuration in use, without writing it to the file.
+pub fn set_user_config(config: UserConfig) {
+    *USER_CONFIG.write().unwrap() = Some(config);
+}
+
+/// Read the configuration file again, e.g. after it has been edited.
+pub fn reload_user_config() -> Result<UserConfig, Box<dyn Error>> {
+    let config = UserConfig::load_default()?;
+    set_user_config(config.clone());
+
+    Ok(config)
+}
+
+impl XASSpectrum {
+    /// Apply the user defaults of `config`: FT parameters that are not set and an AUTOBK kweight
+    /// that is not set or still the default take its values, and the parameters of steps which
+    /// have not been set up are created with them.
+    pub fn fill_from_config(&mut self, config: &UserConfig) -> &mut Self {
+        match self.xftf.as_mut() {
+            Some(xftf) => {
+                xftf.fill_from_config(config);
+            }
+            None => self.xftf = Some(XrayFFTF::from_config(config)),
+        }
+        match self.xftr.as_mut() {
+            Some(xftr) => {
+                xftr.fill_from_config(config);
+            }
+            None => self.xftr = Some(XrayFFTR::from_config(config)),
+        }
+        match self.background.as_mut() {
+            Some(BackgroundMethod::AUTOBK(autobk)) => {
+                autobk.fill_from_config(config);
+            }
+            Some(_) => {}
+            None => self.background = Some(BackgroundMethod::AUTOBK(AUTOBK::from_config(config))),
+        }
+
+        self
+    }
+}
+
+impl XASGroup {
+    /// XASSpectrum::fill_from_config for every spectrum.
+    pub fn fill_from_config(&mut self, config: &UserConfig) -> &mut Self {
+        for spectrum in self.spectra.iter_mut() {
+            spectrum.fill_from_config(config);
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array1;
+
+    #[test]
+    fn test_user_config_toml() -> Result<(), Box<dyn Error>> {
+        let config = UserConfig::from_toml(
+            r#"
+            window = "KaiserBessel"
+            kweight = 3.0
+
+            [plot]
+            colormap = "viridis"
+
+            [directories]
+            export = "/tmp/results"
+            "#,
+        )?;
+        assert_eq!(config.window, Some(FTWindow::KaiserBessel));
+        assert_eq!(config.kweight, Some(3.0));
+        assert_eq!(config.plot.colormap.as_deref(), Some("viridis"));
+        assert_eq!(config.plot.line_width, None);
+        assert_eq!(
+            config.directories.export,
+            Some(PathBuf::from("/tmp/results"))
+        );
+
+        assert_eq!(UserConfig::from_toml(&config.to_toml()?)?, config);
+        assert_eq!(UserConfig::from_toml("")?, UserConfig::default());
+        assert!(UserConfig::from_toml("window = \"Square\"").is_err());
+
+        let path = std::env::temp_dir().join("xraytsubaki_test_config/config.toml");
+        config.save(&path)?;
+        assert_eq!(UserConfig::load(&path)?, config);
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+        assert_eq!(UserConfig::load(&path)?, UserConfig::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_from_config() {
+        let mut config = UserConfig::new();
+        config
+            .set_window(Some(FTWindow::Welch))
+            .set_kweight(Some(3.0));
+
+        // Only parameters that are not set are taken from the configuration
+        let mut xftf = XrayFFTF::new();
+        xftf.window = None;
+        xftf.kweight = None;
+        xftf.fill_from_config(&config);
+        assert_eq!(xftf.window, Some(FTWindow::Welch));
+        assert_eq!(xftf.kweight, Some(3.0));
+
+        let mut xftf = XrayFFTF::new();
+        xftf.fill_from_config(&config);
+        assert_eq!(xftf.window, Some(FTWindow::KaiserBessel));
+        assert_eq!(xftf.kweight, Some(2.0));
+
+        // Steps which are not set up yet get the user defaults
+        config.set_bkg_kweight(Some(2));
+        let mut spectrum = XASSpectrum::new();
+        spectrum.fill_from_config(&config);
+        assert_eq!(spectrum.xftf.as_ref().unwrap().kweight, Some(3.0));
+        assert_eq!(
+            spectrum.xftr.as_ref().unwrap().window,
+            Some(FTWindow::Welch)
+        );
+        let Some(BackgroundMethod::AUTOBK(autobk)) = spectrum.background.as_ref() else {
+            panic!("AUTOBK expected");
+        };
+        assert_eq!(autobk.kweight, Some(2));
+
+        // An AUTOBK with the default kweight takes the one of the configuration, a kweight set
+        // by the user is kept
+        let mut autobk = AUTOBK::new();
+        autobk.fill_from_config(&config);
+        assert_eq!(autobk.kweight, Some(2));
+        autobk.kweight = Some(3);
+        autobk.fill_from_config(&config);
+        assert_eq!(autobk.kweight, Some(3));
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_background_method(Some(BackgroundMethod::AUTOBK(AUTOBK::new())))
+            .unwrap()
+            .fill_from_config(&config);
+        let Some(BackgroundMethod::AUTOBK(autobk)) = spectrum.background.as_ref() else {
+            panic!("AUTOBK expected");
+        };
+        assert_eq!(autobk.kweight, Some(2));
+
+        // Without fill_from_config the configuration is not used
+        let previous = user_config();
+        set_user_config(config);
+        let mut xftf = XrayFFTF::new();
+        xftf.window = None;
+        xftf.fill_parameter(Array1::range(0.0, 10.0, 0.05).view());
+        assert_eq!(xftf.window, None);
+        set_user_config(previous);
+    }
+
+    #[test]
+    fn test_set_user_config() {
+        // The directories do not enter the processing, so the other tests are not affected
+        let previous = user_config();
+        let mut config = previous.clone();
+        config.set_export_dir(Some("/tmp/xraytsubaki_export"));
+
+        set_user_config(config.clone());
+        assert_eq!(user_config(), config);
+        set_user_config(previous);
+    }
+}
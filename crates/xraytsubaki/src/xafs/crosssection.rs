@@ -0,0 +1,304 @@
+//! Bare-atom absorption from tabulated cross sections.
+//!
+//! The element data are provided through the CrossSectionTable trait, so that different
+//! databases can be used. TabulatedCrossSections holds tables loaded by the user, e.g. from the
+//! Elam tables or from FFAST. The smooth mu0(E) of a composition is calculated by mu0.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::XAFSError;
+
+/// Source of elemental data
+pub trait CrossSectionTable {
+    /// Atomic mass of the element in amu.
+    fn atomic_mass(&self, symbol: &str) -> Option<f64>;
+
+    /// Total mass attenuation coefficient of the element in cm^2/g at `energy` (eV).
+    fn mu_elam(&self, symbol: &str, energy: &Array1<f64>) -> Result<Array1<f64>, Box<dyn Error>>;
+}
+
+/// Cross section table of a single element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElementCrossSection {
+    /// Atomic mass in amu
+    pub mass: f64,
+    /// Energy in eV, sorted in increasing order. An edge is given by repeating its energy.
+    pub energy: Vec<f64>,
+    /// Mass attenuation coefficient in cm^2/g
+    pub mu: Vec<f64>,
+}
+
+/// In-memory CrossSectionTable
+///
+/// The cross sections are interpolated linearly in log-log scale.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TabulatedCrossSections {
+    pub elements: BTreeMap<String, ElementCrossSection>,
+}
+
+impl TabulatedCrossSections {
+    pub fn new() -> TabulatedCrossSections {
+        TabulatedCrossSections::default()
+    }
+
+    /// Add or replace the table of an element.
+    pub fn add_element(
+        &mut self,
+        symbol: &str,
+        mass: f64,
+        energy: Vec<f64>,
+        mu: Vec<f64>,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if energy.len() != mu.len() || energy.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        if energy.windows(2).any(|w| w[1] < w[0]) {
+            return Err(format!("{}: energies are not sorted", symbol).into());
+        }
+
+        if energy.iter().chain(mu.iter()).any(|v| *v <= 0.0) {
+            return Err(format!("{}: energies and cross sections must be positive", symbol).into());
+        }
+
+        self.elements
+            .insert(symbol.to_string(), ElementCrossSection { mass, energy, mu });
+
+        Ok(self)
+    }
+}
+
+impl CrossSectionTable for TabulatedCrossSections {
+    fn atomic_mass(&self, symbol: &str) -> Option<f64> {
+        self.elements.get(symbol).map(|element| element.mass)
+    }
+
+    fn mu_elam(&self, symbol: &str, energy: &Array1<f64>) -> Result<Array1<f64>, Box<dyn Error>> {
+        let table = self
+            .elements
+            .get(symbol)
+            .ok_or_else(|| format!("no cross section table for {}", symbol))?;
+
+        let (emin, emax) = (table.energy[0], table.energy[table.energy.len() - 1]);
+
+        energy
+            .iter()
+            .map(|&e| {
+                if e < emin || e > emax {
+                    return Err(format!(
+                        "{}: energy {} eV outside of the table ({} - {} eV)",
+                        symbol, e, emin, emax
+                    )
+                    .into());
+                }
+
+                // Above a repeated edge energy, the upper branch is used.
+                let i = table
+                    .energy
+                    .partition_point(|x| *x <= e)
+                    .clamp(1, table.energy.len() - 1);
+                let (e1, e2) = (table.energy[i - 1], table.energy[i]);
+                let (mu1, mu2) = (table.mu[i - 1], table.mu[i]);
+
+                if e2 == e1 {
+                    return Ok(mu2);
+                }
+
+                let slope = (mu2 / mu1).ln() / (e2 / e1).ln();
+                Ok(mu1 * (e / e1).powf(slope))
+            })
+            .collect::<Result<Vec<f64>, Box<dyn Error>>>()
+            .map(Array1::from_vec)
+    }
+}
+
+/// Parse a chemical formula into the amount of each element.
+///
+/// Fractional amounts and parentheses are supported, e.g. "Fe2O3", "Li0.5CoO2" or "Ca3(PO4)2".
+///
+/// # Example
+///
+/// ```
+/// use xraytsubaki::xafs::crosssection::parse_formula;
+///
+/// let composition = parse_formula("Ca3(PO4)2").unwrap();
+///
+/// assert_eq!(composition["Ca"], 3.0);
+/// assert_eq!(composition["P"], 2.0);
+/// assert_eq!(composition["O"], 8.0);
+/// ```
+pub fn parse_formula(formula: &str) -> Result<BTreeMap<String, f64>, Box<dyn Error>> {
+    let chars = formula
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    let mut position = 0;
+
+    let composition = parse_group(&chars, &mut position)?;
+
+    if position != chars.len() {
+        return Err(format!("unexpected '{}' in formula {}", chars[position], formula).into());
+    }
+
+    if composition.is_empty() {
+        return Err(format!("empty formula {}", formula).into());
+    }
+
+    Ok(composition)
+}
+
+fn parse_group(
+    chars: &[char],
+    position: &mut usize,
+) -> Result<BTreeMap<String, f64>, Box<dyn Error>> {
+    let mut composition = BTreeMap::new();
+
+    while *position < chars.len() {
+        let c = chars[*position];
+
+        let (part, amount) = if c == '(' {
+            *position += 1;
+            let part = parse_group(chars, position)?;
+            if chars.get(*position) != Some(&')') {
+                return Err("unbalanced parentheses in formula".into());
+            }
+            *position += 1;
+            (part, parse_amount(chars, position)?)
+        } else if c.is_ascii_uppercase() {
+            let mut symbol = c.to_string();
+            *position += 1;
+            while *position < chars.len() && chars[*position].is_ascii_lowercase() {
+                symbol.push(chars[*position]);
+                *position += 1;
+            }
+            (
+                BTreeMap::from([(symbol, 1.0)]),
+                parse_amount(chars, position)?,
+            )
+        } else {
+            break;
+        };
+
+        for (symbol, n) in part {
+            *composition.entry(symbol).or_insert(0.0) += n * amount;
+        }
+    }
+
+    Ok(composition)
+}
+
+fn parse_amount(chars: &[char], position: &mut usize) -> Result<f64, Box<dyn Error>> {
+    let start = *position;
+    while *position < chars.len() && (chars[*position].is_ascii_digit() || chars[*position] == '.')
+    {
+        *position += 1;
+    }
+
+    if start == *position {
+        return Ok(1.0);
+    }
+
+    Ok(chars[start..*position].iter().collect::<String>().parse()?)
+}
+
+/// Bare-atom mass attenuation coefficient (cm^2/g) of `formula` at `energy` (eV).
+///
+/// If `density` (g/cm^3) is given, the linear attenuation coefficient (1/cm) is returned
+/// instead.
+pub fn mu0<T: CrossSectionTable>(
+    table: &T,
+    formula: &str,
+    energy: &Array1<f64>,
+    density: Option<f64>,
+) -> Result<Array1<f64>, Box<dyn Error>> {
+    let composition = parse_formula(formula)?;
+
+    let masses = composition
+        .iter()
+        .map(|(symbol, n)| {
+            table
+                .atomic_mass(symbol)
+                .map(|mass| n * mass)
+                .ok_or_else(|| format!("no atomic mass for {}", symbol).into())
+        })
+        .collect::<Result<Vec<f64>, Box<dyn Error>>>()?;
+    let total_mass = masses.iter().sum::<f64>();
+
+    let mut mu = Array1::zeros(energy.len());
+    for ((symbol, _), mass) in composition.iter().zip(masses) {
+        mu = mu + table.mu_elam(symbol, energy)? * (mass / total_mass);
+    }
+
+    Ok(mu * density.unwrap_or(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn power_law(energy: &[f64], a: f64) -> Vec<f64> {
+        energy.iter().map(|e| a * (e / 1000.0).powi(-3)).collect()
+    }
+
+    fn table() -> TabulatedCrossSections {
+        let energy_fe = vec![1000.0, 7112.0, 7112.0, 30000.0];
+        let mut mu_fe = power_law(&energy_fe[..2], 1.0e4);
+        mu_fe.extend(power_law(&energy_fe[2..], 8.0e4));
+
+        let energy_o = vec![1000.0, 30000.0];
+        let mu_o = power_law(&energy_o, 2.0e3);
+
+        let mut table = TabulatedCrossSections::new();
+        table
+            .add_element("Fe", 55.845, energy_fe, mu_fe)
+            .unwrap()
+            .add_element("O", 15.999, energy_o, mu_o)
+            .unwrap();
+        table
+    }
+
+    #[test]
+    fn test_parse_formula() {
+        let composition = parse_formula("Li0.5CoO2").unwrap();
+        assert_eq!(composition["Li"], 0.5);
+        assert_eq!(composition["Co"], 1.0);
+        assert_eq!(composition["O"], 2.0);
+
+        assert!(parse_formula("Fe2(O3").is_err());
+        assert!(parse_formula("fe").is_err());
+        assert!(parse_formula("").is_err());
+    }
+
+    #[test]
+    fn test_mu0() -> Result<(), Box<dyn Error>> {
+        let table = table();
+        let energy = Array1::from_vec(vec![5000.0, 7100.0, 7120.0, 9000.0]);
+
+        let mu_fe = table.mu_elam("Fe", &energy)?;
+        assert_abs_diff_eq!(mu_fe[0], 1.0e4 / 125.0, epsilon = 1e-8);
+        assert_abs_diff_eq!(mu_fe[3], 8.0e4 / 729.0, epsilon = 1e-8);
+        assert!(mu_fe[2] > 7.0 * mu_fe[1]);
+
+        let mu = mu0(&table, "Fe2O3", &energy, None)?;
+        let w_fe = 2.0 * 55.845 / (2.0 * 55.845 + 3.0 * 15.999);
+        let expected = &mu_fe * w_fe + table.mu_elam("O", &energy)? * (1.0 - w_fe);
+        for (a, b) in mu.iter().zip(expected.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-8);
+        }
+
+        let linear = mu0(&table, "Fe2O3", &energy, Some(5.24))?;
+        assert_abs_diff_eq!(linear[0], mu[0] * 5.24, epsilon = 1e-8);
+
+        assert!(mu0(&table, "FeS", &energy, None).is_err());
+        assert!(table
+            .mu_elam("Fe", &Array1::from_vec(vec![50000.0]))
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+//! Multi-channel time series of spectra as a single 3D array.
+//!
+//! Time-resolved measurements produce many spectra on (nearly) the same energy grid, each with
+//! several detector channels. Dataset3D stacks them into an array with the axes
+//! (energy, time, channel), so that operations along one axis, such as normalizing each
+//! spectrum or decomposing a channel by SVD, act on the whole series at once.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use ndarray::{s, Array1, Array2, Array3, ArrayView2, Axis};
+use serde::{Deserialize, Serialize};
+
+use super::lmutils;
+use super::mathutils::MathUtils;
+use super::nshare::{ToNalgebra, ToNdarray1};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Name of the channel holding mu(E) of the spectra
+pub const MU_CHANNEL: &str = "mu";
+
+/// SVD u, singular values and vt
+pub type SvdComponents = (Array2<f64>, Array1<f64>, Array2<f64>);
+
+/// Spectra stacked on a shared energy grid
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dataset3D {
+    /// Energy grid (eV), first axis of `data`
+    pub energy: Array1<f64>,
+    /// Time (or any other scan coordinate) of each spectrum, second axis of `data`
+    pub time: Array1<f64>,
+    /// Channel names, third axis of `data`
+    pub channels: Vec<String>,
+    /// Values with shape (energy.len(), time.len(), channels.len())
+    pub data: Array3<f64>,
+    /// Names of the spectra, one per time
+    pub names: Vec<Option<String>>,
+    /// Metadata shared by all spectra
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+impl Dataset3D {
+    pub fn new(
+        energy: Array1<f64>,
+        time: Array1<f64>,
+        channels: Vec<String>,
+        data: Array3<f64>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if data.dim() != (energy.len(), time.len(), channels.len()) {
+            return Err(format!(
+                "data with shape {:?} does not match the axes ({}, {}, {})",
+                data.shape(),
+                energy.len(),
+                time.len(),
+                channels.len()
+            )
+            .into());
+        }
+
+        let names = vec![None; time.len()];
+
+        Ok(Dataset3D {
+            energy,
+            time,
+            channels,
+            data,
+            names,
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    /// Stack `channels` of the spectra of `group`.
+    ///
+    /// The energy grid of the first spectrum is used, limited to the range covered by all
+    /// spectra, and the other spectra are interpolated onto it. MU_CHANNEL is mu(E) of the
+    /// spectrum; the other channels are taken from its detector channels, with the non-finite
+    /// policy of the spectrum applied. The time of each spectrum is read from the metadata
+    /// `time_key`, or is its index in the group if no key is given.
+    pub fn from_group(
+        group: &XASGroup,
+        channels: &[&str],
+        time_key: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if group.is_empty() {
+            return Err(Box::new(XAFSError::GroupIsEmpty));
+        }
+
+        let time = group
+            .spectra
+            .iter()
+            .enumerate()
+            .map(|(i, spectrum)| match time_key {
+                Some(key) => spectrum
+                    .get_metadata(key)
+                    .and_then(|t| t.as_f64())
+                    .ok_or_else(|| {
+                        format!("spectrum {} has no numeric metadata {}", i, key).into()
+                    }),
+                None => Ok(i as f64),
+            })
+            .collect::<Result<Array1<f64>, Box<dyn Error>>>()?;
+
+        let traces = group
+            .spectra
+            .iter()
+            .enumerate()
+            .map(|(i, spectrum)| {
+                channels
+                    .iter()
+                    .map(|name| {
+                        channel_trace(spectrum, name)
+                            .ok_or_else(|| format!("spectrum {} has no channel {}", i, name).into())
+                    })
+                    .collect::<Result<Vec<(Array1<f64>, Array1<f64>)>, Box<dyn Error>>>()
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let all_traces = traces.iter().flatten();
+        let emin = all_traces
+            .clone()
+            .map(|(e, _)| e.min())
+            .fold(f64::MIN, f64::max);
+        let emax = all_traces.map(|(e, _)| e.max()).fold(f64::MAX, f64::min);
+        let reference = group.spectra[0]
+            .energy
+            .as_ref()
+            .ok_or(XAFSError::NotEnoughData)?;
+        let energy = Array1::from_iter(
+            reference
+                .iter()
+                .cloned()
+                .filter(|e| *e >= emin && *e <= emax),
+        );
+
+        if energy.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let mut data = Array3::zeros((energy.len(), group.len(), channels.len()));
+        for (t, spectrum_traces) in traces.iter().enumerate() {
+            for (c, (x, y)) in spectrum_traces.iter().enumerate() {
+                data.slice_mut(s![.., t, c])
+                    .assign(&energy.interpolate(&x.to_vec(), &y.to_vec())?);
+            }
+        }
+
+        let first = &group.spectra[0].metadata;
+        let metadata = first
+            .iter()
+            .filter(|(key, value)| {
+                Some(key.as_str()) != time_key
+                    && group
+                        .spectra
+                        .iter()
+                        .all(|spectrum| spectrum.get_metadata(key) == Some(value))
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Ok(Dataset3D {
+            energy,
+            time,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            data,
+            names: group.spectra.iter().map(|s| s.name.clone()).collect(),
+            metadata,
+        })
+    }
+
+    /// One spectrum per time, with `mu_channel` as mu(E) and all channels as its detector
+    /// channels. The time is stored in the metadata `time_key`.
+    pub fn to_group(&self, mu_channel: &str, time_key: &str) -> Result<XASGroup, Box<dyn Error>> {
+        let mu_index = self.channel_index(mu_channel)?;
+
+        let mut group = XASGroup::new();
+        for t in 0..self.time.len() {
+            let slice = self.data.slice(s![.., t, ..]);
+            let channels = self
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(c, name)| (name.clone(), slice.column(c).to_owned()))
+                .collect();
+
+            let mut spectrum = XASSpectrum::new();
+            spectrum.set_spectrum_channels(
+                self.energy.clone(),
+                slice.column(mu_index).to_owned(),
+                channels,
+            )?;
+            if let Some(name) = self.names.get(t).cloned().flatten() {
+                spectrum.set_name(name);
+            }
+            for (key, value) in self.metadata.iter() {
+                spectrum.set_metadata(key.clone(), value.clone());
+            }
+            spectrum.set_metadata(time_key, self.time[t]);
+
+            group.add_spectrum(spectrum);
+        }
+
+        Ok(group)
+    }
+
+    pub fn channel_index(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        self.channels
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| format!("no channel {}", name).into())
+    }
+
+    /// Values of the channel `name` with shape (energy, time).
+    pub fn channel(&self, name: &str) -> Result<ArrayView2<'_, f64>, Box<dyn Error>> {
+        let index = self.channel_index(name)?;
+        Ok(self.data.index_axis(Axis(2), index))
+    }
+
+    /// Scale each lane along `axis` so that its largest absolute value is one.
+    ///
+    /// With Axis(0), every spectrum of every channel is scaled independently. Lanes of zeros
+    /// are left unchanged.
+    pub fn normalize_axis(&mut self, axis: Axis) -> &mut Self {
+        for mut lane in self.data.lanes_mut(axis) {
+            let max = lane.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+            if max > 0.0 {
+                lane /= max;
+            }
+        }
+
+        self
+    }
+
+    /// SVD `u * diag(s) * vt` of the (energy, time) matrix of the channel `name`.
+    ///
+    /// The columns of u are the energy components and the rows of vt their time evolution.
+    pub fn svd(&self, name: &str) -> Result<SvdComponents, Box<dyn Error>> {
+        let matrix = self.channel(name)?.to_owned().into_nalgebra();
+        let (u, s, vt) = lmutils::svd_nalgebra_f64(&matrix).ok_or("SVD did not converge")?;
+
+        let n = s.len();
+        let u = Array2::from_shape_fn((u.nrows(), n), |(i, j)| u[(i, j)]);
+        let vt = Array2::from_shape_fn((n, vt.ncols()), |(i, j)| vt[(i, j)]);
+
+        Ok((u, s.into_ndarray1(), vt))
+    }
+}
+
+// Energy grid and values of a channel of the spectrum
+fn channel_trace(spectrum: &XASSpectrum, name: &str) -> Option<(Array1<f64>, Array1<f64>)> {
+    if name == MU_CHANNEL {
+        return Some((spectrum.energy.clone()?, spectrum.mu.clone()?));
+    }
+
+    let channel = spectrum.get_channel(name)?;
+    let raw_energy = spectrum.raw_energy.as_ref()?;
+    Some(spectrum.processing_options.prepare(raw_energy, channel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::tests::TEST_TOL_LESS_ACC;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_dataset3d() -> Result<(), Box<dyn Error>> {
+        let energy = Array1::linspace(7000.0, 7200.0, 201);
+        let edge = energy.mapv(|e: f64| 1.0 / (1.0 + (-(e - 7100.0) / 5.0).exp()));
+
+        let mut group = XASGroup::new();
+        for t in 0..4 {
+            let i0 = Array1::from_elem(energy.len(), 1000.0 + t as f64);
+            let mut spectrum = XASSpectrum::new();
+            spectrum
+                .set_spectrum_channels(
+                    energy.clone(),
+                    &edge * (1.0 + t as f64),
+                    BTreeMap::from([("i0".to_string(), i0)]),
+                )?
+                .set_name(format!("scan{}", t))
+                .set_metadata("sample", "Fe foil")
+                .set_metadata("time", 10.0 * t as f64);
+            group.add_spectrum(spectrum);
+        }
+
+        let dataset = Dataset3D::from_group(&group, &[MU_CHANNEL, "i0"], Some("time"))?;
+        assert_eq!(dataset.data.dim(), (201, 4, 2));
+        assert_eq!(dataset.time.to_vec(), vec![0.0, 10.0, 20.0, 30.0]);
+        assert_eq!(dataset.metadata.len(), 1);
+        assert_abs_diff_eq!(
+            dataset.data[[200, 3, 1]],
+            1003.0,
+            epsilon = TEST_TOL_LESS_ACC
+        );
+
+        // All spectra are multiples of the same edge: a single component
+        let (u, s, vt) = dataset.svd(MU_CHANNEL)?;
+        assert_eq!((u.dim(), vt.dim()), ((201, 4), (4, 4)));
+        assert!(s[1] < s[0] * 1e-10);
+
+        let mut normalized = dataset.clone();
+        normalized.normalize_axis(Axis(0));
+        let mu = normalized.channel(MU_CHANNEL)?;
+        let difference = &mu.column(0) - &mu.column(3);
+        assert_abs_diff_eq!(
+            difference.mapv(f64::abs).max(),
+            0.0,
+            epsilon = TEST_TOL_LESS_ACC
+        );
+
+        let roundtrip = dataset.to_group("mu", "time")?;
+        assert_eq!(roundtrip.len(), 4);
+        assert_eq!(roundtrip.spectra[2].name.as_deref(), Some("scan2"));
+        assert_eq!(
+            roundtrip.spectra[2].get_metadata("time"),
+            Some(&serde_json::json!(20.0))
+        );
+        assert_eq!(roundtrip.spectra[2].mu, group.spectra[2].mu);
+        assert_eq!(
+            roundtrip.spectra[2].get_channel("i0"),
+            group.spectra[2].get_channel("i0")
+        );
+
+        assert!(Dataset3D::from_group(&group, &["it"], None).is_err());
+        assert!(dataset.to_group("it", "time").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+//! Total electron yield (TEY) data.
+//!
+//! In TEY the drain current of the sample is measured instead of a transmitted or fluorescence
+//! intensity. mu is the drain current over i0, and at beamlines where i0 is not a reliable
+//! monitor (or is missing) over the ring current. Insulating samples charge up during the scan:
+//! the drain current then drifts, and jumps where the charge is released, e.g. when the
+//! beam is refilled. remove_charging_jumps removes such jumps with a piecewise constant
+//! baseline. The current amplifiers of soft X-ray beamlines clip at their range, which shows up
+//! as a run of equal values at the extreme of a channel: these are reported as warnings.
+//!
+//! Preset::ElectronYield holds normalization defaults suited to soft X-ray edges.
+//!
+//! ```
+//! use ndarray::Array1;
+//! use xraytsubaki::xafs::electronyield::ElectronYieldOptions;
+//! use xraytsubaki::xafs::xasspectrum::XASSpectrum;
+//!
+//! let energy = Array1::linspace(690.0, 750.0, 601);
+//! let tey = energy.mapv(|e: f64| 1.0 + (e - 708.0).atan());
+//! let i0 = Array1::from_elem(601, 2.0);
+//!
+//! let mut spectrum = XASSpectrum::new();
+//! spectrum.set_spectrum_channels(
+//!     energy.clone(),
+//!     Array1::zeros(601),
+//!     [("tey".to_string(), tey), ("i0".to_string(), i0)].into(),
+//! )?;
+//! let warnings = spectrum.normalize_electron_yield(&ElectronYieldOptions::new())?;
+//! assert!(warnings.is_empty());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+
+use ndarray::{s, Array1};
+use serde::{Deserialize, Serialize};
+
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Channels and saturation checks of TEY data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ElectronYieldOptions {
+    /// Drain current of the sample. Default = "tey".
+    pub signal_channel: Option<String>,
+    /// Incident intensity. Default = "i0". Not used if the spectrum has no such channel.
+    pub i0_channel: Option<String>,
+    /// Ring current, divided out (relative to its mean) if set. Default = None.
+    pub ring_current_channel: Option<String>,
+    /// Level (in the units of the channels) above which the signal or i0 is saturated.
+    /// Default = None, to only look for clipping.
+    pub saturation_level: Option<f64>,
+    /// Consecutive equal values at the maximum or minimum of a channel reported as clipping.
+    /// Default = 3.
+    pub clipping_points: Option<usize>,
+}
+
+impl Default for ElectronYieldOptions {
+    fn default() -> Self {
+        ElectronYieldOptions {
+            signal_channel: Some("tey".to_string()),
+            i0_channel: Some("i0".to_string()),
+            ring_current_channel: None,
+            saturation_level: None,
+            clipping_points: Some(3),
+        }
+    }
+}
+
+impl ElectronYieldOptions {
+    pub fn new() -> ElectronYieldOptions {
+        ElectronYieldOptions::default()
+    }
+
+    pub fn set_signal_channel<S: Into<String>>(&mut self, channel: S) -> &mut Self {
+        self.signal_channel = Some(channel.into());
+        self
+    }
+
+    pub fn set_i0_channel<S: Into<String>>(&mut self, channel: S) -> &mut Self {
+        self.i0_channel = Some(channel.into());
+        self
+    }
+
+    pub fn set_ring_current_channel<S: Into<String>>(&mut self, channel: S) -> &mut Self {
+        self.ring_current_channel = Some(channel.into());
+        self
+    }
+
+    pub fn set_saturation_level(&mut self, level: Option<f64>) -> &mut Self {
+        self.saturation_level = level;
+        self
+    }
+
+    pub fn set_clipping_points(&mut self, points: usize) -> &mut Self {
+        self.clipping_points = Some(points);
+        self
+    }
+
+    pub(crate) fn signal_channel(&self) -> &str {
+        self.signal_channel.as_deref().unwrap_or("tey")
+    }
+
+    fn i0_channel(&self) -> &str {
+        self.i0_channel.as_deref().unwrap_or("i0")
+    }
+}
+
+/// Indices of the points of `values` that are saturated: above `level` if given, or in runs
+/// of at least `clipping_points` equal values at the maximum or minimum. Constant values are
+/// not reported.
+pub fn saturated_points(
+    values: &Array1<f64>,
+    level: Option<f64>,
+    clipping_points: usize,
+) -> Vec<usize> {
+    if let Some(level) = level {
+        return (0..values.len()).filter(|i| values[*i] >= level).collect();
+    }
+
+    let max = values.fold(f64::NEG_INFINITY, |m, v| m.max(*v));
+    let min = values.fold(f64::INFINITY, |m, v| m.min(*v));
+    let mut points = Vec::new();
+    if max == min {
+        return points;
+    }
+
+    let mut start = 0;
+    for end in 1..=values.len() {
+        if end < values.len() && values[end] == values[start] {
+            continue;
+        }
+        let extreme = values[start] == max || values[start] == min;
+        if extreme && end - start >= clipping_points.max(2) {
+            points.extend(start..end);
+        }
+        start = end;
+    }
+
+    points
+}
+
+impl XASSpectrum {
+    /// Set mu to the drain current over i0, and over the ring current relative to its mean
+    /// if a ring current channel is set. At least one of the two monitors must be present.
+    ///
+    /// Returns the saturation warnings of the signal and monitors, which are also added to
+    /// the notes of the spectrum.
+    pub fn normalize_electron_yield(
+        &mut self,
+        options: &ElectronYieldOptions,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let energy = self.raw_energy.clone().ok_or(XAFSError::NotEnoughData)?;
+        let signal = self
+            .get_channel(options.signal_channel())
+            .ok_or_else(|| format!("No channel {}", options.signal_channel()))?;
+
+        let mut mu = signal.clone();
+        let mut monitors = vec![options.signal_channel()];
+        if let Some(i0) = self.get_channel(options.i0_channel()) {
+            mu /= i0;
+            monitors.push(options.i0_channel());
+        }
+        if let Some(name) = options.ring_current_channel.as_deref() {
+            let ring = self
+                .get_channel(name)
+                .ok_or_else(|| format!("No channel {}", name))?;
+            let mean = ring.mean().ok_or(XAFSError::NotEnoughData)?;
+            mu = mu / ring * mean;
+            monitors.push(name);
+        }
+        if monitors.len() == 1 {
+            return Err(format!(
+                "No i0 ({}) or ring current channel to normalize {}",
+                options.i0_channel(),
+                options.signal_channel()
+            )
+            .into());
+        }
+
+        let clipping_points = options.clipping_points.unwrap_or(3);
+        let warnings = monitors
+            .iter()
+            .filter_map(|name| {
+                let values = self.get_channel(name)?;
+                let ring = options.ring_current_channel.as_deref() == Some(*name);
+                let level = options.saturation_level.filter(|_| !ring);
+                let points = saturated_points(values, level, clipping_points);
+                let (first, last) = (points.first()?, points.last()?);
+                Some(format!(
+                    "{} is saturated at {} points between {} and {} eV",
+                    name,
+                    points.len(),
+                    energy[*first],
+                    energy[*last]
+                ))
+            })
+            .collect::<Vec<String>>();
+
+        self.set_spectrum(energy, mu);
+        self.notes.extend(warnings.iter().cloned());
+
+        Ok(warnings)
+    }
+
+    /// Remove jumps of raw_mu at the energies `breaks`, e.g. from the discharge of the sample.
+    ///
+    /// The jump at each break is the difference of straight lines fitted to `points` points on
+    /// either side, extrapolated to the break. The parts of the spectrum after each break are
+    /// shifted by the jump, i.e. a piecewise constant baseline is subtracted. Returns the
+    /// jumps.
+    pub fn remove_charging_jumps(
+        &mut self,
+        breaks: &[f64],
+        points: usize,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        let (energy, mut mu) = match (self.raw_energy.as_ref(), self.raw_mu.as_ref()) {
+            (Some(energy), Some(mu)) => (energy.clone(), mu.clone()),
+            _ => return Err(Box::new(XAFSError::NotEnoughData)),
+        };
+        let points = points.max(2);
+
+        let mut jumps = Vec::with_capacity(breaks.len());
+        for &energy_break in breaks {
+            let index = energy.iter().take_while(|e| **e < energy_break).count();
+            if index < points || index + points > energy.len() {
+                return Err(format!(
+                    "Fewer than {} points on one side of the break at {} eV",
+                    points, energy_break
+                )
+                .into());
+            }
+
+            let before = s![index - points..index];
+            let after = s![index..index + points];
+            let jump = line_at(
+                &energy.slice(after).to_owned(),
+                &mu.slice(after).to_owned(),
+                energy_break,
+            ) - line_at(
+                &energy.slice(before).to_owned(),
+                &mu.slice(before).to_owned(),
+                energy_break,
+            );
+            mu.slice_mut(s![index..]).mapv_inplace(|x| x - jump);
+            jumps.push(jump);
+        }
+
+        self.set_spectrum(energy, mu);
+
+        Ok(jumps)
+    }
+}
+
+// Least-squares straight line through (x, y), evaluated at `at`
+fn line_at(x: &Array1<f64>, y: &Array1<f64>, at: f64) -> f64 {
+    let n = x.len() as f64;
+    let (x_mean, y_mean) = (x.sum() / n, y.sum() / n);
+    let sxx = x.mapv(|x| (x - x_mean).powi(2)).sum();
+    let sxy = ((x - x_mean) * (y - y_mean)).sum();
+    let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+
+    y_mean + slope * (at - x_mean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::collections::BTreeMap;
+
+    // Spectrum with the drain current of `edge` measured with a drifting i0 and ring current
+    fn tey_spectrum() -> Result<(XASSpectrum, Array1<f64>), Box<dyn Error>> {
+        let energy = Array1::linspace(690.0, 750.0, 601);
+        let edge = energy.mapv(|e: f64| 1.0 + 0.5 * (e - 708.0).atan());
+        let i0 = energy.mapv(|e| 2.0 + 0.01 * (e - 690.0));
+        let ring = energy.mapv(|e| 400.0 - 0.5 * (e - 690.0));
+        let tey = &edge * &i0 * &ring / 385.0;
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum_channels(
+            energy.clone(),
+            Array1::zeros(601),
+            BTreeMap::from([
+                ("tey".to_string(), tey),
+                ("i0".to_string(), i0),
+                ("ring".to_string(), ring),
+            ]),
+        )?;
+
+        Ok((spectrum, edge))
+    }
+
+    #[test]
+    fn test_normalize_electron_yield() -> Result<(), Box<dyn Error>> {
+        let (mut spectrum, edge) = tey_spectrum()?;
+        let mut options = ElectronYieldOptions::new();
+        options.set_ring_current_channel("ring");
+        assert!(spectrum.normalize_electron_yield(&options)?.is_empty());
+
+        let mu = spectrum.raw_mu.clone().unwrap();
+        assert_abs_diff_eq!(mu[0] / edge[0], mu[600] / edge[600], epsilon = 1e-12);
+
+        // A clipped amplifier
+        spectrum
+            .channels
+            .get_mut("tey")
+            .unwrap()
+            .slice_mut(s![300..305])
+            .fill(1e3);
+        let warnings = spectrum.normalize_electron_yield(&options)?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("tey is saturated at 5 points"));
+        assert_eq!(spectrum.notes, warnings);
+
+        spectrum.channels.remove("i0");
+        spectrum.channels.remove("ring");
+        assert!(spectrum.normalize_electron_yield(&options).is_err());
+        options.ring_current_channel = None;
+        assert!(spectrum.normalize_electron_yield(&options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_saturated_points() {
+        let values = Array1::from_vec(vec![1.0, 2.0, 5.0, 5.0, 5.0, 3.0, 1.0]);
+        assert_eq!(saturated_points(&values, None, 3), vec![2, 3, 4]);
+        assert!(saturated_points(&values, None, 4).is_empty());
+        assert_eq!(saturated_points(&values, Some(3.0), 3), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_remove_charging_jumps() -> Result<(), Box<dyn Error>> {
+        let energy = Array1::linspace(690.0, 750.0, 601);
+        let mu = energy.mapv(|e| 0.01 * e);
+        let jumped = Array1::from_iter(energy.iter().zip(mu.iter()).map(|(e, m)| {
+            m + if *e >= 700.0 { 0.3 } else { 0.0 } + if *e >= 740.0 { -0.1 } else { 0.0 }
+        }));
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy, jumped);
+        let jumps = spectrum.remove_charging_jumps(&[700.0, 740.0], 10)?;
+        assert_abs_diff_eq!(jumps[0], 0.3, epsilon = 1e-9);
+        assert_abs_diff_eq!(jumps[1], -0.1, epsilon = 1e-9);
+        for (a, b) in spectrum.raw_mu.unwrap().iter().zip(mu.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-9);
+        }
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(Array1::linspace(690.0, 750.0, 601), Array1::zeros(601));
+        assert!(spectrum.remove_charging_jumps(&[691.0], 20).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! Hooks called as spectra are processed.
+//!
+//! Processing a large group takes a while, and a GUI or a web service wants to show each
+//! spectrum as soon as it is ready rather than after the whole group. ProcessingHooks holds the
+//! callbacks run after each step of each spectrum. The callbacks are called from the rayon
+//! worker threads, so they have to be Send + Sync and should return quickly. An application
+//! with its own event loop can use ProcessingHooks::channel and receive ProcessingEvent
+//! messages instead, e.g. forwarding them to an async runtime.
+//!
+//! ```
+//! use std::sync::mpsc;
+//! use xraytsubaki::xafs::events::{ProcessingEvent, ProcessingHooks};
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let mut group = XASGroup::new();
+//! group.process_with_hooks(&ProcessingHooks::channel(sender))?;
+//!
+//! for event in receiver.try_iter() {
+//!     if let ProcessingEvent::FftDone(index) = event {
+//!         println!("spectrum {} is ready", index);
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::Sender;
+
+use rayon::prelude::*;
+
+use super::pipeline::{ProcessingParameters, ProcessingStep};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+
+/// Callback receiving the index of the spectrum in the group and the spectrum
+pub type SpectrumHook = Box<dyn Fn(usize, &XASSpectrum) + Send + Sync>;
+
+/// Callback receiving the index of the spectrum and the error
+pub type ErrorHook = Box<dyn Fn(usize, &str) + Send + Sync>;
+
+/// Step of the processing of a spectrum, with the index of the spectrum in the group
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessingEvent {
+    Normalized(usize),
+    BackgroundDone(usize),
+    FftDone(usize),
+    Failed(usize, String),
+}
+
+/// Callbacks run by process_with_hooks. Hooks which are not set are skipped.
+#[derive(Default)]
+pub struct ProcessingHooks {
+    on_normalized: Option<SpectrumHook>,
+    on_background_done: Option<SpectrumHook>,
+    on_fft_done: Option<SpectrumHook>,
+    on_error: Option<ErrorHook>,
+}
+
+impl fmt::Debug for ProcessingHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessingHooks")
+            .field("on_normalized", &self.on_normalized.is_some())
+            .field("on_background_done", &self.on_background_done.is_some())
+            .field("on_fft_done", &self.on_fft_done.is_some())
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+impl ProcessingHooks {
+    pub fn new() -> ProcessingHooks {
+        ProcessingHooks::default()
+    }
+
+    /// Hooks sending a ProcessingEvent for every step to `sender`. Events sent after the
+    /// receiver is dropped are discarded.
+    pub fn channel(sender: Sender<ProcessingEvent>) -> ProcessingHooks {
+        let send = |event: fn(usize) -> ProcessingEvent| {
+            let sender = sender.clone();
+            move |index: usize, _: &XASSpectrum| {
+                let _ = sender.send(event(index));
+            }
+        };
+
+        let mut hooks = ProcessingHooks::new();
+        hooks
+            .on_normalized(send(ProcessingEvent::Normalized))
+            .on_background_done(send(ProcessingEvent::BackgroundDone))
+            .on_fft_done(send(ProcessingEvent::FftDone))
+            .on_error(move |index, message| {
+                let _ = sender.send(ProcessingEvent::Failed(index, message.to_string()));
+            });
+        hooks
+    }
+
+    pub fn on_normalized<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(usize, &XASSpectrum) + Send + Sync + 'static,
+    {
+        self.on_normalized = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_background_done<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(usize, &XASSpectrum) + Send + Sync + 'static,
+    {
+        self.on_background_done = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_fft_done<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(usize, &XASSpectrum) + Send + Sync + 'static,
+    {
+        self.on_fft_done = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_error<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(usize, &str) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(hook));
+        self
+    }
+
+    // Run the processing sequence of ProcessingParameters::run with the parameters already
+    // set on the spectrum, calling the hook of each step.
+    fn run(&self, index: usize, spectrum: &mut XASSpectrum) -> Result<(), Box<dyn Error>> {
+        ProcessingParameters::new().run(spectrum, |step, spectrum, _| {
+            let hook = match step {
+                ProcessingStep::Normalization => &self.on_normalized,
+                ProcessingStep::Background => &self.on_background_done,
+                ProcessingStep::Xftf => &self.on_fft_done,
+            };
+            if let Some(hook) = hook {
+                hook(index, spectrum);
+            }
+        })
+    }
+
+    fn run_reporting(&self, index: usize, spectrum: &mut XASSpectrum) -> Result<(), String> {
+        self.run(index, spectrum).map_err(|e| {
+            let message = e.to_string();
+            if let Some(hook) = &self.on_error {
+                hook(index, &message);
+            }
+            message
+        })
+    }
+}
+
+impl XASSpectrum {
+    /// Normalize, remove the background and Fourier transform the spectrum, calling `hooks`
+    /// after each step with index 0.
+    pub fn process_with_hooks(
+        &mut self,
+        hooks: &ProcessingHooks,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        hooks.run_reporting(0, self)?;
+        Ok(self)
+    }
+}
+
+impl XASGroup {
+    /// Normalize, remove the background and Fourier transform all spectra in parallel, calling
+    /// `hooks` after each step of each spectrum.
+    ///
+    /// A failing spectrum is reported to the error hook and does not stop the others. The
+    /// first error is returned after all spectra have been processed.
+    pub fn process_with_hooks(
+        &mut self,
+        hooks: &ProcessingHooks,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let errors = self
+            .spectra
+            .par_iter_mut()
+            .enumerate()
+            .filter_map(|(index, spectrum)| hooks.run_reporting(index, spectrum).err())
+            .collect::<Vec<String>>();
+
+        if let Some(first) = errors.first() {
+            return Err(format!(
+                "{} of {} spectra failed, first error: {}",
+                errors.len(),
+                self.len(),
+                first
+            )
+            .into());
+        }
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use ndarray::Array1;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn test_processing_hooks() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum.clone()).add_spectrum(spectrum);
+
+        let (sender, receiver) = mpsc::channel();
+        group.process_with_hooks(&ProcessingHooks::channel(sender))?;
+
+        let events = receiver.try_iter().collect::<Vec<ProcessingEvent>>();
+        assert_eq!(events.len(), 6);
+        for index in 0..2 {
+            let steps = events
+                .iter()
+                .filter(|event| match event {
+                    ProcessingEvent::Normalized(i)
+                    | ProcessingEvent::BackgroundDone(i)
+                    | ProcessingEvent::FftDone(i) => *i == index,
+                    _ => false,
+                })
+                .cloned()
+                .collect::<Vec<ProcessingEvent>>();
+            assert_eq!(
+                steps,
+                vec![
+                    ProcessingEvent::Normalized(index),
+                    ProcessingEvent::BackgroundDone(index),
+                    ProcessingEvent::FftDone(index),
+                ]
+            );
+        }
+
+        // A spectrum too short to normalize fails without stopping the others
+        let mut short = XASSpectrum::new();
+        short.set_spectrum(Array1::range(0.0, 3.0, 1.0), Array1::zeros(3));
+        group.add_spectrum(short);
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(usize::MAX));
+        let mut hooks = ProcessingHooks::new();
+        {
+            let done = done.clone();
+            let failed = failed.clone();
+            hooks
+                .on_fft_done(move |_, spectrum| {
+                    assert!(spectrum.get_chir_mag().is_some());
+                    done.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_error(move |index, _| failed.store(index, Ordering::SeqCst));
+        }
+
+        assert!(group.process_with_hooks(&hooks).is_err());
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+        assert_eq!(failed.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+}
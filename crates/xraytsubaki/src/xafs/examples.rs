@@ -0,0 +1,236 @@
+//! Example spectra for documentation, tests and demonstrations (feature "examples_data").
+//!
+//! The measured Ru K-edge transmission spectrum of a Ru foil (QAS beamline, NSLS-II) used by
+//! the test suite is the only reference spectrum embedded in the library. The synthetic_*
+//! spectra of other edges are not measurements: they are generated from a toy EXAFS model with
+//! SyntheticSpectrum, so that workflows can be shown without data files. They have no phase
+//! shifts, backscattering amplitudes or near-edge structure, and the peaks of their |chi(R)|
+//! are at the distances of the shells, unlike in measured data. Do not use them as standards.
+//!
+//! ```
+//! use xraytsubaki::xafs::examples;
+//!
+//! let mut spectrum = examples::ru_k_qas()?;
+//! spectrum.normalize()?.calc_background()?.fft()?;
+//!
+//! let mut copper = examples::synthetic_cu_k().generate();
+//! copper.normalize()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::f64::consts::PI;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::io::xafs_bytes::load_spectrum_QAS_trans_from_bytes;
+use super::random::{SeededRng, NOISE_KEY};
+use super::xafsutils::XAFSUtils;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+
+const RU_QAS: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/testfiles/Ru_QAS.dat"
+));
+
+/// Measured Ru K-edge spectrum of a Ru foil, with the i0 and it channels.
+pub fn ru_k_qas() -> Result<XASSpectrum, Box<dyn Error>> {
+    let mut spectrum = load_spectrum_QAS_trans_from_bytes(RU_QAS)?;
+    spectrum
+        .set_name("Ru foil (Ru K, QAS NSLS-II)")
+        .set_metadata("element", "Ru")
+        .set_metadata("edge", "K");
+
+    Ok(spectrum)
+}
+
+/// Toy model of an Fe K-edge spectrum with the first two shells of bcc Fe.
+pub fn synthetic_fe_k() -> SyntheticSpectrum {
+    let mut model = SyntheticSpectrum::new("Fe foil (synthetic)", "Fe", "K", 7112.0);
+    model
+        .add_shell(8.0, 2.48, 0.005)
+        .add_shell(6.0, 2.87, 0.006);
+    model
+}
+
+/// Toy model of a Cu K-edge spectrum with the first shell of fcc Cu.
+pub fn synthetic_cu_k() -> SyntheticSpectrum {
+    let mut model = SyntheticSpectrum::new("Cu foil (synthetic)", "Cu", "K", 8979.0);
+    model.add_shell(12.0, 2.55, 0.0085);
+    model
+}
+
+/// Toy model of a Pt L3-edge spectrum with the first shell of fcc Pt.
+pub fn synthetic_pt_l3() -> SyntheticSpectrum {
+    let mut model = SyntheticSpectrum::new("Pt foil (synthetic)", "Pt", "L3", 11564.0);
+    model.add_shell(12.0, 2.77, 0.005);
+    model
+}
+
+/// The measured Ru spectrum followed by the synthetic Fe, Cu and Pt spectra.
+pub fn example_group() -> Result<XASGroup, Box<dyn Error>> {
+    let mut group = XASGroup::new();
+    group.add_spectrum(ru_k_qas()?).add_spectra(
+        [synthetic_fe_k(), synthetic_cu_k(), synthetic_pt_l3()]
+            .iter()
+            .map(SyntheticSpectrum::generate)
+            .collect(),
+    );
+
+    Ok(group)
+}
+
+/// Coordination shell of a synthetic spectrum
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyntheticShell {
+    /// Coordination number
+    pub n: f64,
+    /// Distance (Å)
+    pub r: f64,
+    /// Debye-Waller factor (Å²)
+    pub sigma2: f64,
+}
+
+/// Model of a spectrum: an arctangent edge of width `core_width` at e0, multiplied above the
+/// edge by 1 + chi(k) with
+///
+/// chi(k) = s02 * sum N / (k R²) * exp(-2 k² sigma2) * exp(-2 R / mean_free_path) * sin(2 k R)
+///
+/// and gaussian noise of standard deviation `noise` added to mu.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyntheticSpectrum {
+    pub name: String,
+    pub element: String,
+    pub edge: String,
+    /// Edge energy (eV)
+    pub e0: f64,
+    pub edge_step: f64,
+    /// Slope of the pre-edge line (per eV)
+    pub pre_edge_slope: f64,
+    /// Width of the arctangent edge (eV)
+    pub core_width: f64,
+    pub s02: f64,
+    /// Photoelectron mean free path (Å)
+    pub mean_free_path: f64,
+    pub shells: Vec<SyntheticShell>,
+    /// Energy range relative to e0 (eV)
+    pub emin: f64,
+    pub emax: f64,
+    /// Energy step (eV)
+    pub step: f64,
+    pub noise: f64,
+    /// Seed of the noise, so that the same spectrum is generated every time
+    pub seed: u64,
+}
+
+impl SyntheticSpectrum {
+    pub fn new<S: Into<String>>(name: S, element: &str, edge: &str, e0: f64) -> SyntheticSpectrum {
+        SyntheticSpectrum {
+            name: name.into(),
+            element: element.to_string(),
+            edge: edge.to_string(),
+            e0,
+            edge_step: 1.0,
+            pre_edge_slope: -1e-4,
+            core_width: 1.5,
+            s02: 0.9,
+            mean_free_path: 10.0,
+            shells: Vec::new(),
+            emin: -150.0,
+            emax: 800.0,
+            step: 0.5,
+            noise: 0.0,
+            seed: 0,
+        }
+    }
+
+    pub fn add_shell(&mut self, n: f64, r: f64, sigma2: f64) -> &mut Self {
+        self.shells.push(SyntheticShell { n, r, sigma2 });
+        self
+    }
+
+    pub fn set_noise(&mut self, noise: f64, seed: u64) -> &mut Self {
+        self.noise = noise;
+        self.seed = seed;
+        self
+    }
+
+    /// chi(k) of the shells
+    pub fn chi(&self, k: &Array1<f64>) -> Array1<f64> {
+        k.mapv(|k| {
+            if k <= 0.0 {
+                return 0.0;
+            }
+
+            self.shells
+                .iter()
+                .map(|shell| {
+                    self.s02 * shell.n / (k * shell.r * shell.r)
+                        * (-2.0 * k * k * shell.sigma2).exp()
+                        * (-2.0 * shell.r / self.mean_free_path).exp()
+                        * (2.0 * k * shell.r).sin()
+                })
+                .sum()
+        })
+    }
+
+    pub fn generate(&self) -> XASSpectrum {
+        let energy = Array1::range(self.emin, self.emax + 0.5 * self.step, self.step) + self.e0;
+        let relative = &energy - self.e0;
+        let chi = self.chi(&relative.mapv(|e| e.etok()));
+
+        let mut rng = SeededRng::new(self.seed);
+        let mu = Array1::from_iter(relative.iter().zip(chi.iter()).map(|(&e, &chi)| {
+            let edge = 0.5 + (e / self.core_width).atan() / PI;
+            let oscillation = if e > 0.0 { 1.0 + chi } else { 1.0 };
+            self.pre_edge_slope * e
+                + self.edge_step * edge * oscillation
+                + self.noise * rng.normal()
+        }));
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum(energy, mu)
+            .set_name(self.name.clone())
+            .set_metadata("element", self.element.clone())
+            .set_metadata("edge", self.edge.clone())
+            .set_metadata("synthetic", true);
+        if self.noise > 0.0 {
+            spectrum.set_metadata(
+                NOISE_KEY,
+                serde_json::json!([{ "sigma": self.noise, "seed": self.seed }]),
+            );
+        }
+
+        spectrum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_examples() -> Result<(), Box<dyn Error>> {
+        let group = example_group()?;
+        assert_eq!(group.len(), 4);
+        assert!(group.spectra[0].get_channel("i0").is_some());
+
+        let mut copper = synthetic_cu_k().generate();
+        copper.find_e0()?;
+        assert_abs_diff_eq!(copper.get_e0().unwrap(), 8979.0, epsilon = 1.0);
+
+        let mut model = synthetic_pt_l3();
+        model.set_noise(1e-3, 42);
+        assert_eq!(model.generate(), model.generate());
+        let noisy = model.generate().mu.unwrap();
+        let clean = synthetic_pt_l3().generate().mu.unwrap();
+        let rms = ((&noisy - &clean).mapv(|x| x * x).mean().unwrap()).sqrt();
+        assert_abs_diff_eq!(rms, 1e-3, epsilon = 2e-4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,678 @@
+//! Derived quantities of spectra defined by expressions.
+//!
+//! Trend analyses (e.g. the white-line height over the edge step against the temperature)
+//! combine results of the processing and metadata of each spectrum. An Expression is parsed
+//! once from text and evaluated for each spectrum of a group, giving one value per spectrum,
+//! so that such quantities can be plotted or exported without writing code for each of them.
+//!
+//! Expressions contain numbers, `+ - * / ^`, parentheses and function calls. Names refer to
+//! the spectrum:
+//!
+//! - `e0`, `e0_uncertainty`, `edge_step`, `kweight` and `index` (position in the group)
+//! - `norm_at(e)` and `mu_at(e)`: normalized and raw mu at e eV relative to e0
+//! - `chir_mag_at(r)`: |chi(R)| at r Å
+//! - `whiteline_height(emin, emax)` and `whiteline_area(emin, emax)`: white line between emin
+//!   and emax eV relative to e0, above a straight baseline
+//! - any other name, or any text between backquotes (e.g. `` `sample temperature` ``): numeric
+//!   metadata of the spectrum
+//!
+//! and `sqrt`, `ln`, `log10`, `exp`, `abs`, `min` and `max` are available. Unknown functions
+//! and wrong numbers of arguments are reported when the expression is parsed.
+//!
+//! ```
+//! use xraytsubaki::xafs::io;
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//!
+//! let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/testfiles/Ru_QAS.dat").to_string();
+//! let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+//! spectrum.set_metadata("temperature", 300.0);
+//!
+//! let mut group = XASGroup::new();
+//! group.add_spectrum(spectrum);
+//! group.normalize()?;
+//!
+//! let temperature = group.evaluate("temperature")?;
+//! let ratio = group.evaluate("whiteline_height(-10, 30) / edge_step")?;
+//! assert_eq!(temperature[0], 300.0);
+//! assert!(ratio[0] > 0.0);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use ndarray::{Array1, Array2};
+use rayon::prelude::*;
+
+use super::mathutils::MathUtils;
+use super::normalization::Normalization;
+use super::whiteline::WhitelineBaseline;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Quantities of the spectrum available by name, see the module documentation
+pub const SPECTRUM_VARIABLES: [&str; 5] = ["e0", "e0_uncertainty", "edge_step", "kweight", "index"];
+
+/// Functions of the spectrum and their number of arguments
+pub const SPECTRUM_FUNCTIONS: [(&str, usize); 5] = [
+    ("norm_at", 1),
+    ("mu_at", 1),
+    ("chir_mag_at", 1),
+    ("whiteline_height", 2),
+    ("whiteline_area", 2),
+];
+
+/// Mathematical functions and their number of arguments
+pub const MATH_FUNCTIONS: [(&str, usize); 7] = [
+    ("sqrt", 1),
+    ("ln", 1),
+    ("log10", 1),
+    ("exp", 1),
+    ("abs", 1),
+    ("min", 2),
+    ("max", 2),
+];
+
+/// A value needed by an expression is not available for a spectrum: missing or non-numeric
+/// metadata, a product which was not computed or a position outside the data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingValue(pub String);
+
+impl fmt::Display for MissingValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for MissingValue {}
+
+// Whether an evaluation error comes from data missing for the spectrum rather than from the
+// expression
+fn is_missing(error: &(dyn Error + 'static)) -> bool {
+    error.is::<MissingValue>() || error.is::<XAFSError>()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Variable(String),
+    Metadata(String),
+    Negate(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Quoted(String),
+    Symbol(char),
+}
+
+/// Parsed expression, see the module documentation for the syntax
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, Box<dyn Error>> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let root = parser.sum()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {:?} in \"{}\"", token, text).into());
+        }
+        check_calls(&root)?;
+
+        Ok(Expression {
+            text: text.to_string(),
+            root,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Metadata keys read by the expression
+    pub fn metadata_keys(&self) -> Vec<String> {
+        fn collect(node: &Node, keys: &mut Vec<String>) {
+            match node {
+                Node::Metadata(key) if !keys.contains(key) => keys.push(key.clone()),
+                Node::Negate(node) => collect(node, keys),
+                Node::Binary(_, left, right) => {
+                    collect(left, keys);
+                    collect(right, keys);
+                }
+                Node::Call(_, arguments) => arguments.iter().for_each(|a| collect(a, keys)),
+                _ => {}
+            }
+        }
+
+        let mut keys = Vec::new();
+        collect(&self.root, &mut keys);
+        keys
+    }
+
+    /// Value of the expression for `spectrum`, at position `index` of its group.
+    pub fn evaluate(&self, spectrum: &XASSpectrum, index: usize) -> Result<f64, Box<dyn Error>> {
+        evaluate(&self.root, &SpectrumScope { spectrum, index })
+    }
+
+    /// Value of the expression with every name, quoted or not, given by `value`, e.g. the
+    /// columns of a data file. Only the mathematical functions are available.
+    pub fn evaluate_with<F: Fn(&str) -> Option<f64>>(
+        &self,
+        value: F,
+    ) -> Result<f64, Box<dyn Error>> {
+        evaluate(&self.root, &NameScope(value))
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Box<dyn Error>;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Expression::parse(text)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start + c.len_utf8();
+                let mut previous = c;
+                while let Some(&(i, c)) = chars.peek() {
+                    let exponent_sign = (c == '+' || c == '-') && matches!(previous, 'e' | 'E');
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    previous = c;
+                    chars.next();
+                }
+                let number = &text[start..end];
+                let value = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number {}", number))?;
+                tokens.push(Token::Number(value));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Name(text[start..end].to_string()));
+            }
+            '`' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '`')) => break,
+                        Some((_, c)) => name.push(c),
+                        None => return Err(format!("Unclosed ` in \"{}\"", text).into()),
+                    }
+                }
+                tokens.push(Token::Quoted(name));
+            }
+            '+' | '-' | '*' | '/' | '^' | '(' | ')' | ',' => tokens.push(Token::Symbol(c)),
+            _ => return Err(format!("Unexpected character {} in \"{}\"", c, text).into()),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Recursive descent parser of
+//   sum = product (("+" | "-") product)*
+//   product = unary (("*" | "/") unary)*
+//   unary = "-" unary | power
+//   power = primary ("^" unary)?
+//   primary = number | name | name "(" arguments ")" | quoted | "(" sum ")"
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), Box<dyn Error>> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {} instead of {:?}", symbol, self.peek()).into())
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.product()?;
+        while let Some(Token::Symbol(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.position += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+        Ok(node)
+    }
+
+    fn product(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.unary()?;
+        while let Some(Token::Symbol(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.position += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, Box<dyn Error>> {
+        if self.accept('-') {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Node, Box<dyn Error>> {
+        let base = self.primary()?;
+        if self.accept('^') {
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, Box<dyn Error>> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Quoted(key)) => Ok(Node::Metadata(key)),
+            Some(Token::Name(name)) => {
+                if !self.accept('(') {
+                    return Ok(if SPECTRUM_VARIABLES.contains(&name.as_str()) {
+                        Node::Variable(name)
+                    } else {
+                        Node::Metadata(name)
+                    });
+                }
+
+                let mut arguments = Vec::new();
+                if !self.accept(')') {
+                    loop {
+                        arguments.push(self.sum()?);
+                        if self.accept(')') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Node::Call(name, arguments))
+            }
+            Some(Token::Symbol('(')) => {
+                let node = self.sum()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            token => Err(format!("Unexpected {:?}", token).into()),
+        }
+    }
+}
+
+// What the names of an expression refer to
+trait Scope {
+    fn variable(&self, name: &str) -> Result<f64, Box<dyn Error>>;
+    fn metadata(&self, key: &str) -> Result<f64, Box<dyn Error>>;
+    /// Functions other than the mathematical ones
+    fn call(&self, name: &str, arguments: &[f64]) -> Result<f64, Box<dyn Error>>;
+}
+
+struct SpectrumScope<'a> {
+    spectrum: &'a XASSpectrum,
+    index: usize,
+}
+
+impl Scope for SpectrumScope<'_> {
+    fn variable(&self, name: &str) -> Result<f64, Box<dyn Error>> {
+        variable(name, self.spectrum, self.index)
+    }
+
+    fn metadata(&self, key: &str) -> Result<f64, Box<dyn Error>> {
+        metadata(key, self.spectrum)
+    }
+
+    fn call(&self, name: &str, arguments: &[f64]) -> Result<f64, Box<dyn Error>> {
+        call(name, arguments, self.spectrum)
+    }
+}
+
+// Every name, quoted or not, resolved by a closure
+struct NameScope<F>(F);
+
+impl<F: Fn(&str) -> Option<f64>> Scope for NameScope<F> {
+    fn variable(&self, name: &str) -> Result<f64, Box<dyn Error>> {
+        (self.0)(name).ok_or_else(|| format!("Unknown name {}", name).into())
+    }
+
+    fn metadata(&self, key: &str) -> Result<f64, Box<dyn Error>> {
+        self.variable(key)
+    }
+
+    fn call(&self, name: &str, _arguments: &[f64]) -> Result<f64, Box<dyn Error>> {
+        Err(format!("Unknown function {}", name).into())
+    }
+}
+
+fn evaluate<S: Scope>(node: &Node, scope: &S) -> Result<f64, Box<dyn Error>> {
+    match node {
+        Node::Number(value) => Ok(*value),
+        Node::Negate(node) => Ok(-evaluate(node, scope)?),
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, scope)?;
+            let right = evaluate(right, scope)?;
+            Ok(match op {
+                '+' => left + right,
+                '-' => left - right,
+                '*' => left * right,
+                '/' => left / right,
+                _ => left.powf(right),
+            })
+        }
+        Node::Variable(name) => scope.variable(name),
+        Node::Metadata(key) => scope.metadata(key),
+        Node::Call(name, arguments) => {
+            let arguments = arguments
+                .iter()
+                .map(|argument| evaluate(argument, scope))
+                .collect::<Result<Vec<f64>, _>>()?;
+            match math_function(name, &arguments) {
+                Some(value) => value,
+                None => scope.call(name, &arguments),
+            }
+        }
+    }
+}
+
+// Fail on calls of unknown functions or with the wrong number of arguments
+fn check_calls(node: &Node) -> Result<(), Box<dyn Error>> {
+    match node {
+        Node::Negate(node) => check_calls(node),
+        Node::Binary(_, left, right) => {
+            check_calls(left)?;
+            check_calls(right)
+        }
+        Node::Call(name, arguments) => {
+            let (_, count) = MATH_FUNCTIONS
+                .iter()
+                .chain(SPECTRUM_FUNCTIONS.iter())
+                .find(|(function, _)| function == name)
+                .ok_or_else(|| format!("Unknown function {}", name))?;
+            if arguments.len() != *count {
+                return Err(format!(
+                    "{} takes {} arguments, {} given",
+                    name,
+                    count,
+                    arguments.len()
+                )
+                .into());
+            }
+            arguments.iter().try_for_each(check_calls)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Value of a mathematical function, None if `name` is not one. The number of arguments is
+// checked by Expression::parse.
+fn math_function(name: &str, arguments: &[f64]) -> Option<Result<f64, Box<dyn Error>>> {
+    let x = arguments.first().copied().unwrap_or_default();
+    Some(Ok(match name {
+        "sqrt" => x.sqrt(),
+        "ln" => x.ln(),
+        "log10" => x.log10(),
+        "exp" => x.exp(),
+        "abs" => x.abs(),
+        "min" => x.min(arguments[1]),
+        "max" => x.max(arguments[1]),
+        _ => return None,
+    }))
+}
+
+fn e0(spectrum: &XASSpectrum) -> Option<f64> {
+    spectrum
+        .get_e0()
+        .or_else(|| spectrum.normalization.as_ref()?.get_e0())
+}
+
+fn variable(name: &str, spectrum: &XASSpectrum, index: usize) -> Result<f64, Box<dyn Error>> {
+    let value = match name {
+        "e0" => e0(spectrum),
+        "e0_uncertainty" => spectrum.get_e0_uncertainty(),
+        "edge_step" => spectrum
+            .normalization
+            .as_ref()
+            .and_then(|n| n.get_edge_step()),
+        "kweight" => spectrum.get_kweight().copied(),
+        _ => Some(index as f64),
+    };
+
+    value.ok_or_else(|| MissingValue(format!("{} is not available", name)).into())
+}
+
+fn metadata(key: &str, spectrum: &XASSpectrum) -> Result<f64, Box<dyn Error>> {
+    let value = spectrum
+        .get_metadata(key)
+        .ok_or_else(|| MissingValue(format!("No metadata {}", key)))?;
+
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::Bool(flag) => Some(f64::from(u8::from(*flag))),
+        serde_json::Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| MissingValue(format!("Metadata {} is not a number: {}", key, value)).into())
+}
+
+// Value of a function of the spectrum. The name and the number of arguments are checked by
+// Expression::parse.
+fn call(name: &str, arguments: &[f64], spectrum: &XASSpectrum) -> Result<f64, Box<dyn Error>> {
+    let x = arguments[0];
+    match name {
+        "norm_at" | "mu_at" => {
+            let energy = spectrum.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+            let y = if name == "norm_at" {
+                spectrum
+                    .normalization
+                    .as_ref()
+                    .and_then(|n| n.get_norm())
+                    .ok_or(XAFSError::NotEnoughData)?
+            } else {
+                spectrum.mu.as_ref().ok_or(XAFSError::NotEnoughData)?
+            };
+            let e0 = e0(spectrum).ok_or(XAFSError::NotEnoughData)?;
+            sample(energy.to_vec(), y.to_vec(), x + e0)
+        }
+        "chir_mag_at" => {
+            let r = spectrum.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+            let chir_mag = spectrum
+                .get_chir_mag()
+                .ok_or(XAFSError::NotEnoughDataForXFTF)?;
+            sample(r.to_vec(), chir_mag.to_vec(), x)
+        }
+        "whiteline_height" | "whiteline_area" => {
+            let whiteline = spectrum.whiteline_area(x, arguments[1], WhitelineBaseline::Line)?;
+            Ok(if name == "whiteline_height" {
+                whiteline.height
+            } else {
+                whiteline.area
+            })
+        }
+        _ => Err(format!("Unknown function {}", name).into()),
+    }
+}
+
+// Linear interpolation of y(x) at `at`, which must lie within x
+fn sample(x: Vec<f64>, y: Vec<f64>, at: f64) -> Result<f64, Box<dyn Error>> {
+    let (first, last) = (x[0], x[x.len() - 1]);
+    if at < first.min(last) || at > first.max(last) {
+        return Err(Box::new(MissingValue(format!(
+            "{} is outside the data ({} to {})",
+            at, first, last
+        ))));
+    }
+
+    Ok(vec![at].interpolate(&x, &y)?[0])
+}
+
+impl XASGroup {
+    /// Value of `expression` for each spectrum, NaN for the spectra which lack data it needs
+    /// (e.g. missing metadata, see MissingValue). Other errors are returned.
+    pub fn evaluate(&self, expression: &str) -> Result<Array1<f64>, Box<dyn Error>> {
+        let expression = Expression::parse(expression)?;
+
+        let values = self
+            .spectra
+            .par_iter()
+            .enumerate()
+            .map(
+                |(index, spectrum)| match expression.evaluate(spectrum, index) {
+                    Ok(value) => Ok(value),
+                    Err(e) if is_missing(e.as_ref()) => Ok(f64::NAN),
+                    Err(e) => Err(e.to_string()),
+                },
+            )
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        Ok(values.into())
+    }
+
+    /// Values of several expressions, one row per spectrum and one column per expression,
+    /// e.g. for export.
+    pub fn evaluate_table(&self, expressions: &[&str]) -> Result<Array2<f64>, Box<dyn Error>> {
+        let mut table = Array2::zeros((self.len(), expressions.len()));
+        for (mut column, expression) in table.columns_mut().into_iter().zip(expressions) {
+            column.assign(&self.evaluate(expression)?);
+        }
+
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_expression() -> Result<(), Box<dyn Error>> {
+        let spectrum = XASSpectrum::new();
+        let value = |text: &str| Expression::parse(text)?.evaluate(&spectrum, 3);
+        assert_eq!(value("1 + 2 * 3")?, 7.0);
+        assert_eq!(value("-2^2")?, -4.0);
+        assert_eq!(value("2^3^2")?, 512.0);
+        assert_eq!(value("(1 + 2) * index / 1.5e1")?, 0.6);
+        assert_eq!(value("max(sqrt(4), -abs(-3))")?, 2.0);
+        assert!(value("edge_step").is_err());
+        assert!(Expression::parse("sqrt(1, 2)").is_err());
+        assert!(Expression::parse("2 * foo(1)").is_err());
+        assert!(Expression::parse("norm_at()").is_err());
+        assert!(Expression::parse("1 +").is_err());
+        assert!(Expression::parse("(1").is_err());
+        assert!(Expression::parse("1 2").is_err());
+
+        let expression = Expression::parse("ln(i0 / `i t`)")?;
+        let columns = |name: &str| match name {
+            "i0" => Some(std::f64::consts::E),
+            "i t" => Some(1.0),
+            _ => None,
+        };
+        assert_eq!(expression.evaluate_with(columns)?, 1.0);
+        assert!(Expression::parse("e0 + 1")?.evaluate_with(columns).is_err());
+        assert!(Expression::parse("norm_at(1)")?
+            .evaluate_with(columns)
+            .is_err());
+
+        let expression = Expression::parse("`sample temperature` - temperature * 2")?;
+        assert_eq!(
+            expression.metadata_keys(),
+            vec!["sample temperature", "temperature"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_evaluate() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut group = XASGroup::new();
+        for temperature in [serde_json::Value::from(100.0), "200".into(), "warm".into()] {
+            let mut spectrum = spectrum.clone();
+            spectrum.set_metadata("temperature", temperature);
+            group.add_spectrum(spectrum);
+        }
+        group.normalize()?;
+
+        let temperature = group.evaluate("temperature")?;
+        assert_eq!(
+            temperature.slice(ndarray::s![..2]).to_vec(),
+            vec![100.0, 200.0]
+        );
+        assert!(temperature[2].is_nan());
+
+        let table = group.evaluate_table(&[
+            "edge_step",
+            "norm_at(100)",
+            "whiteline_height(-10, 30) / edge_step",
+        ])?;
+        assert_eq!(table.dim(), (3, 3));
+        assert_eq!(table.row(0), table.row(2));
+        assert_abs_diff_eq!(table[[0, 1]], 1.0, epsilon = 0.2);
+        assert!(table[[0, 2]] > 0.0);
+
+        assert!(group.evaluate("norm_at(1e5)")?.iter().all(|x| x.is_nan()));
+        assert!(group.evaluate("chir_mag_at(2)")?.iter().all(|x| x.is_nan()));
+        assert!(group.evaluate("edge_step +").is_err());
+        assert!(group.evaluate("whiteline_height(-10)").is_err());
+        assert!(group.evaluate("mu_at(temperature)")?[2].is_nan());
+
+        Ok(())
+    }
+}
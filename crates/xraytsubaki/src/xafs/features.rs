@@ -0,0 +1,273 @@
+//! Fixed-length descriptors of spectra for machine learning.
+//!
+//! Spectra have different energy grids, e0 and lengths, while most learning methods need one
+//! vector of the same length per sample. XASSpectrum::features samples the normalized XANES
+//! on a grid relative to e0 and |chi(R)| at chosen distances, and appends a few statistics of
+//! the edge. XASGroup::feature_matrix stacks the vectors of a group into a matrix with one row
+//! per spectrum, and FeatureSpec::names labels its columns, e.g. for a dataframe.
+//!
+//! ```
+//! use xraytsubaki::xafs::features::FeatureSpec;
+//! use xraytsubaki::xafs::io;
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//!
+//! let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/testfiles/Ru_QAS.dat").to_string();
+//! let mut group = XASGroup::new();
+//! group.add_spectrum(io::load_spectrum_QAS_trans(&path)?);
+//! group.normalize()?;
+//!
+//! let spec = FeatureSpec::new();
+//! let matrix = group.feature_matrix(&spec)?;
+//! assert_eq!(matrix.ncols(), spec.names().len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+
+use ndarray::{Array1, Array2};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::groupview::XASGroupView;
+use super::mathutils::MathUtils;
+use super::normalization::Normalization;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Statistics of the edge appended to the features
+pub const EDGE_STATISTICS: [&str; 4] = ["e0", "edge_step", "norm_max", "norm_max_energy"];
+
+/// Content of the feature vector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureSpec {
+    /// Start of the XANES grid relative to e0 (eV). Default = -20.
+    pub xanes_emin: Option<f64>,
+    /// End of the XANES grid relative to e0 (eV). Default = 80.
+    pub xanes_emax: Option<f64>,
+    /// Points of the XANES grid, 0 to leave the XANES out. Default = 101.
+    pub xanes_points: Option<usize>,
+    /// Distances (Å) at which |chi(R)| is sampled. Default = none, so that XANES-only spectra
+    /// can be described.
+    pub chir_r: Option<Vec<f64>>,
+    /// Append e0, the edge step, and the maximum of the normalized XANES with its energy
+    /// relative to e0. Default = true.
+    pub edge_statistics: Option<bool>,
+}
+
+impl Default for FeatureSpec {
+    fn default() -> Self {
+        FeatureSpec {
+            xanes_emin: Some(-20.0),
+            xanes_emax: Some(80.0),
+            xanes_points: Some(101),
+            chir_r: None,
+            edge_statistics: Some(true),
+        }
+    }
+}
+
+impl FeatureSpec {
+    pub fn new() -> FeatureSpec {
+        FeatureSpec::default()
+    }
+
+    pub fn set_xanes(&mut self, emin: f64, emax: f64, points: usize) -> &mut Self {
+        self.xanes_emin = Some(emin);
+        self.xanes_emax = Some(emax);
+        self.xanes_points = Some(points);
+        self
+    }
+
+    pub fn set_chir_r(&mut self, r: &[f64]) -> &mut Self {
+        self.chir_r = Some(r.to_vec());
+        self
+    }
+
+    pub fn set_edge_statistics(&mut self, edge_statistics: bool) -> &mut Self {
+        self.edge_statistics = Some(edge_statistics);
+        self
+    }
+
+    /// Energies of the XANES grid relative to e0
+    pub fn xanes_grid(&self) -> Array1<f64> {
+        let default = FeatureSpec::default();
+        let emin = self.xanes_emin.or(default.xanes_emin).unwrap();
+        let emax = self.xanes_emax.or(default.xanes_emax).unwrap();
+        let points = self.xanes_points.or(default.xanes_points).unwrap();
+
+        match points {
+            0 => Array1::zeros(0),
+            1 => Array1::from_elem(1, emin),
+            _ => Array1::linspace(emin, emax, points),
+        }
+    }
+
+    fn chir_r(&self) -> &[f64] {
+        self.chir_r.as_deref().unwrap_or_default()
+    }
+
+    fn edge_statistics(&self) -> bool {
+        self.edge_statistics.unwrap_or(true)
+    }
+
+    /// Name of each feature, in the order of the feature vector
+    pub fn names(&self) -> Vec<String> {
+        let xanes = self.xanes_grid().into_iter().map(|e| format!("norm@{}", e));
+        let chir = self.chir_r().iter().map(|r| format!("chir_mag@{}", r));
+        let edge = EDGE_STATISTICS
+            .iter()
+            .filter(|_| self.edge_statistics())
+            .map(|name| name.to_string());
+
+        xanes.chain(chir).chain(edge).collect()
+    }
+}
+
+impl XASSpectrum {
+    /// Feature vector of the spectrum, see FeatureSpec::names for its content.
+    ///
+    /// The spectrum must be normalized, and Fourier transformed if chi(R) is sampled. The
+    /// grids must lie within the data: values are not extrapolated.
+    pub fn features(&self, spec: &FeatureSpec) -> Result<Array1<f64>, Box<dyn Error>> {
+        let normalization = self
+            .normalization
+            .as_ref()
+            .ok_or(XAFSError::NotEnoughData)?;
+        let energy = self.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let norm = normalization.get_norm().ok_or(XAFSError::NotEnoughData)?;
+        let e0 = self
+            .get_e0()
+            .or_else(|| normalization.get_e0())
+            .ok_or(XAFSError::NotEnoughData)?;
+
+        let mut features = Vec::with_capacity(spec.names().len());
+
+        let xanes = spec.xanes_grid() + e0;
+        if let (Some(first), Some(last)) = (xanes.first(), xanes.last()) {
+            if *first < energy[0] || *last > energy[energy.len() - 1] {
+                return Err(format!(
+                    "The XANES grid {} to {} eV is outside the data",
+                    first, last
+                )
+                .into());
+            }
+            features.extend(xanes.interpolate(&energy.to_vec(), &norm.to_vec())?);
+        }
+
+        if !spec.chir_r().is_empty() {
+            let r = self.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+            let chir_mag = self.get_chir_mag().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+            let r_max = r[r.len() - 1];
+            if let Some(outside) = spec.chir_r().iter().find(|x| **x < 0.0 || **x > r_max) {
+                return Err(format!("chi(R) is not available at R = {} Å", outside).into());
+            }
+            features.extend(
+                spec.chir_r()
+                    .to_vec()
+                    .interpolate(&r.to_vec(), &chir_mag.to_vec())?,
+            );
+        }
+
+        if spec.edge_statistics() {
+            let edge_step = normalization
+                .get_edge_step()
+                .ok_or(XAFSError::NotEnoughData)?;
+            let (lo, hi) = match (xanes.first(), xanes.last()) {
+                (Some(first), Some(last)) => (*first, *last),
+                _ => (f64::NEG_INFINITY, f64::INFINITY),
+            };
+            let (norm_max_energy, norm_max) = energy
+                .iter()
+                .zip(norm.iter())
+                .filter(|(e, _)| **e >= lo && **e <= hi)
+                .fold((f64::NAN, f64::NEG_INFINITY), |max, (e, n)| {
+                    if *n > max.1 {
+                        (*e, *n)
+                    } else {
+                        max
+                    }
+                });
+            features.extend([e0, edge_step, norm_max, norm_max_energy - e0]);
+        }
+
+        Ok(Array1::from_vec(features))
+    }
+}
+
+impl XASGroup {
+    /// Feature vectors of all spectra, one row per spectrum, computed in parallel.
+    pub fn feature_matrix(&self, spec: &FeatureSpec) -> Result<Array2<f64>, Box<dyn Error>> {
+        self.view_all().feature_matrix(spec)
+    }
+}
+
+impl XASGroupView<'_> {
+    /// Feature vectors of the spectra of the view, one row per spectrum, computed in parallel.
+    pub fn feature_matrix(&self, spec: &FeatureSpec) -> Result<Array2<f64>, Box<dyn Error>> {
+        let rows = self
+            .par_iter()
+            .map(|(index, spectrum)| {
+                spectrum
+                    .features(spec)
+                    .map_err(|e| format!("spectrum {}: {}", index, e))
+            })
+            .collect::<Result<Vec<Array1<f64>>, String>>()?;
+
+        let ncols = spec.names().len();
+        let mut matrix = Array2::zeros((rows.len(), ncols));
+        for (mut row, features) in matrix.rows_mut().into_iter().zip(rows) {
+            row.assign(&features);
+        }
+
+        Ok(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_features() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?;
+
+        let mut spec = FeatureSpec::new();
+        let features = spectrum.features(&spec)?;
+        let names = spec.names();
+        assert_eq!(features.len(), 105);
+        assert_eq!(names[20], "norm@0");
+        assert_eq!(names[104], "norm_max_energy");
+        let e0 = spectrum.normalization.as_ref().unwrap().get_e0().unwrap();
+        assert_abs_diff_eq!(features[101], e0, epsilon = 1e-9);
+        assert!(features[103] > 1.0);
+
+        spec.set_chir_r(&[1.0, 2.5]);
+        assert!(spectrum.features(&spec).is_err());
+        spectrum.calc_background()?.fft()?;
+        let features = spectrum.features(&spec)?;
+        assert_eq!(features.len(), 107);
+        assert!(features
+            .slice(ndarray::s![101..103])
+            .iter()
+            .all(|x| *x > 0.0));
+
+        spec.set_xanes(-20.0, 3000.0, 10);
+        assert!(spectrum.features(&spec).is_err());
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum.clone()).add_spectrum(spectrum);
+        spec.set_xanes(-10.0, 40.0, 51).set_edge_statistics(false);
+        let matrix = group.feature_matrix(&spec)?;
+        assert_eq!(matrix.dim(), (2, 53));
+        assert_eq!(matrix.row(0), matrix.row(1));
+
+        Ok(())
+    }
+}
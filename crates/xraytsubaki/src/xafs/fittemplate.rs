@@ -0,0 +1,405 @@
+//! Predefined EXAFS fit models of common coordination environments.
+//!
+//! Setting up a first-shell fit means choosing the paths, their degeneracies and distances,
+//! the parameters and sensible starting values and bounds for them. A FitTemplate does this for
+//! a few common environments and instantiates a FitModel with the paths and the parameter set.
+//!
+//! As in Larch and Artemis, the amplitude, e0 shift, ΔR and sigma2 of each path are
+//! expressions (see the expression module) of the fit parameters and of `reff`, the half path
+//! length of the path in the starting structure. The paths share `amp` (S0²) and `del_e0`, so
+//! that the coordination numbers stay fixed at their crystallographic values. Starting
+//! distances are taken from the bond valence parameters of the bondvalence module for
+//! oxides and sulfides, and from the lattice constant for fcc metals. The scattering amplitudes
+//! and phase shifts of the paths are not part of the model and have to be calculated (e.g. with
+//! FEFF) for the labels of the paths.
+//!
+//! ```
+//! use xraytsubaki::xafs::fittemplate::FitTemplate;
+//!
+//! let mut model = FitTemplate::octahedral_oxide("Fe", 3).instantiate()?;
+//! assert_eq!(model.paths[0].degeneracy, 6.0);
+//!
+//! model.set_parameter("delr", 0.02)?;
+//! let path = &model.path_values()?[0];
+//! assert!((path.r - 2.035).abs() < 1e-3);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use super::bondvalence::{BondShell, BondValenceParameter};
+use super::expression::Expression;
+
+/// Lattice constants (Å) of fcc metals at room temperature
+const FCC_LATTICE_CONSTANTS: &[(&str, f64)] = &[
+    ("Al", 4.0495),
+    ("Ni", 3.524),
+    ("Cu", 3.615),
+    ("Rh", 3.803),
+    ("Pd", 3.891),
+    ("Ag", 4.086),
+    ("Ir", 3.839),
+    ("Pt", 3.924),
+    ("Au", 4.078),
+    ("Pb", 4.950),
+];
+
+/// Lattice constant (Å) of the fcc metal `element`, if tabulated
+pub fn fcc_lattice_constant(element: &str) -> Option<f64> {
+    FCC_LATTICE_CONSTANTS
+        .iter()
+        .find(|(e, _)| e.eq_ignore_ascii_case(element))
+        .map(|(_, a)| *a)
+}
+
+/// Parameter of a fit with its starting value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FitParameter {
+    pub name: String,
+    pub value: f64,
+    /// Whether the parameter is refined, otherwise it is fixed at value
+    pub vary: bool,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl FitParameter {
+    /// Varied parameter without bounds
+    pub fn new<S: Into<String>>(name: S, value: f64) -> FitParameter {
+        FitParameter {
+            name: name.into(),
+            value,
+            vary: true,
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn fixed<S: Into<String>>(name: S, value: f64) -> FitParameter {
+        FitParameter {
+            vary: false,
+            ..FitParameter::new(name, value)
+        }
+    }
+
+    pub fn with_bounds(mut self, min: f64, max: f64) -> FitParameter {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+}
+
+/// Scattering path of a fit model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FitPath {
+    /// Label of the path, e.g. "Fe-O"
+    pub label: String,
+    pub absorber: String,
+    pub scatterer: String,
+    pub degeneracy: f64,
+    /// Half path length (Å) of the starting structure
+    pub reff: f64,
+    /// Expressions of the fit parameters and reff
+    pub s02: String,
+    pub e0: String,
+    pub deltar: String,
+    pub sigma2: String,
+}
+
+/// Values of the path parameters for the current values of the fit parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathValues {
+    pub label: String,
+    pub degeneracy: f64,
+    pub s02: f64,
+    pub e0: f64,
+    /// reff + ΔR (Å)
+    pub r: f64,
+    pub sigma2: f64,
+}
+
+impl FitPath {
+    /// Evaluate the path parameters with the values of `parameters`.
+    pub fn evaluate(&self, parameters: &[FitParameter]) -> Result<PathValues, Box<dyn Error>> {
+        let value = |text: &str| -> Result<f64, Box<dyn Error>> {
+            Expression::parse(text)?
+                .evaluate_with(|name| match name {
+                    "reff" => Some(self.reff),
+                    _ => parameters.iter().find(|p| p.name == name).map(|p| p.value),
+                })
+                .map_err(|e| format!("path {}: {}", self.label, e).into())
+        };
+
+        Ok(PathValues {
+            label: self.label.clone(),
+            degeneracy: self.degeneracy,
+            s02: value(&self.s02)?,
+            e0: value(&self.e0)?,
+            r: self.reff + value(&self.deltar)?,
+            sigma2: value(&self.sigma2)?,
+        })
+    }
+}
+
+/// Paths and parameters of an EXAFS fit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FitModel {
+    pub name: String,
+    pub parameters: Vec<FitParameter>,
+    pub paths: Vec<FitPath>,
+}
+
+impl FitModel {
+    pub fn get_parameter(&self, name: &str) -> Option<&FitParameter> {
+        self.parameters.iter().find(|p| p.name == name)
+    }
+
+    pub fn get_parameter_mut(&mut self, name: &str) -> Option<&mut FitParameter> {
+        self.parameters.iter_mut().find(|p| p.name == name)
+    }
+
+    /// Set the value of the parameter `name`, which has to exist.
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<&mut Self, Box<dyn Error>> {
+        match self.get_parameter_mut(name) {
+            Some(parameter) => parameter.value = value,
+            None => return Err(format!("No parameter {} in {}", name, self.name).into()),
+        }
+        Ok(self)
+    }
+
+    /// Parameters refined by the fit
+    pub fn varied_parameters(&self) -> Vec<&FitParameter> {
+        self.parameters.iter().filter(|p| p.vary).collect()
+    }
+
+    /// Path parameters for the current values of the fit parameters
+    pub fn path_values(&self) -> Result<Vec<PathValues>, Box<dyn Error>> {
+        self.paths
+            .iter()
+            .map(|path| path.evaluate(&self.parameters))
+            .collect()
+    }
+
+    /// Shells of the paths to `anion`, e.g. for bondvalence::check_bond_valence of the result
+    pub fn bond_shells(&self, anion: &str) -> Result<Vec<BondShell>, Box<dyn Error>> {
+        Ok(self
+            .paths
+            .iter()
+            .zip(self.path_values()?)
+            .filter(|(path, _)| path.scatterer.eq_ignore_ascii_case(anion))
+            .map(|(path, values)| BondShell::new(path.degeneracy, values.r))
+            .collect())
+    }
+}
+
+/// Predefined fit model of a coordination environment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FitTemplate {
+    /// First shell of an absorber octahedrally coordinated by oxygen, e.g. Fe2O3 or NiO
+    OctahedralOxide {
+        absorber: String,
+        oxidation_state: i32,
+    },
+    /// First and second shell of an fcc metal. The lattice constant is looked up if None.
+    FccMetal {
+        element: String,
+        lattice_constant: Option<f64>,
+    },
+    /// First shell of an absorber coordinated by sulfur, e.g. ZnS with 4 neighbors. The
+    /// distance is taken from the bond valence parameters if None.
+    Sulfide {
+        absorber: String,
+        oxidation_state: i32,
+        coordination: f64,
+        distance: Option<f64>,
+    },
+}
+
+impl FitTemplate {
+    pub fn octahedral_oxide(absorber: &str, oxidation_state: i32) -> FitTemplate {
+        FitTemplate::OctahedralOxide {
+            absorber: absorber.to_string(),
+            oxidation_state,
+        }
+    }
+
+    pub fn fcc_metal(element: &str) -> FitTemplate {
+        FitTemplate::FccMetal {
+            element: element.to_string(),
+            lattice_constant: None,
+        }
+    }
+
+    pub fn sulfide(absorber: &str, oxidation_state: i32, coordination: f64) -> FitTemplate {
+        FitTemplate::Sulfide {
+            absorber: absorber.to_string(),
+            oxidation_state,
+            coordination,
+            distance: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FitTemplate::OctahedralOxide { .. } => "octahedral oxide, first shell",
+            FitTemplate::FccMetal { .. } => "fcc metal, first and second shell",
+            FitTemplate::Sulfide { .. } => "sulfide, first shell",
+        }
+    }
+
+    /// Fit model with the paths of the environment and the starting values of its parameters
+    pub fn instantiate(&self) -> Result<FitModel, Box<dyn Error>> {
+        let mut parameters = vec![
+            FitParameter::new("amp", 0.9).with_bounds(0.5, 1.2),
+            FitParameter::new("del_e0", 0.0).with_bounds(-10.0, 10.0),
+        ];
+
+        let paths = match self {
+            FitTemplate::OctahedralOxide {
+                absorber,
+                oxidation_state,
+            } => {
+                let reff = bond_valence_distance(absorber, *oxidation_state, "O", 6.0)?;
+                parameters.extend(first_shell_parameters(0.005));
+                vec![first_shell_path(absorber, "O", 6.0, reff)]
+            }
+            FitTemplate::FccMetal {
+                element,
+                lattice_constant,
+            } => {
+                let a = lattice_constant
+                    .or_else(|| fcc_lattice_constant(element))
+                    .ok_or_else(|| format!("No lattice constant of fcc {}", element))?;
+                parameters.extend([
+                    FitParameter::new("alpha", 0.0).with_bounds(-0.05, 0.05),
+                    FitParameter::new("ss1", 0.006).with_bounds(0.001, 0.02),
+                    FitParameter::new("ss2", 0.008).with_bounds(0.001, 0.03),
+                ]);
+
+                // 12 neighbors at a/√2 and 6 at a, expanding isotropically with alpha
+                [(12.0, a / 2f64.sqrt(), "ss1"), (6.0, a, "ss2")]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (degeneracy, reff, sigma2))| FitPath {
+                        label: format!("{}-{}{}", element, element, i + 1),
+                        absorber: element.clone(),
+                        scatterer: element.clone(),
+                        degeneracy: *degeneracy,
+                        reff: *reff,
+                        s02: "amp".to_string(),
+                        e0: "del_e0".to_string(),
+                        deltar: "alpha * reff".to_string(),
+                        sigma2: sigma2.to_string(),
+                    })
+                    .collect()
+            }
+            FitTemplate::Sulfide {
+                absorber,
+                oxidation_state,
+                coordination,
+                distance,
+            } => {
+                let reff = match distance {
+                    Some(distance) => *distance,
+                    None => bond_valence_distance(absorber, *oxidation_state, "S", *coordination)?,
+                };
+                parameters.extend(first_shell_parameters(0.006));
+                vec![first_shell_path(absorber, "S", *coordination, reff)]
+            }
+        };
+
+        Ok(FitModel {
+            name: self.name().to_string(),
+            parameters,
+            paths,
+        })
+    }
+}
+
+// Distance at which `n` neighbors give a bond valence sum equal to the oxidation state
+fn bond_valence_distance(
+    cation: &str,
+    oxidation_state: i32,
+    anion: &str,
+    n: f64,
+) -> Result<f64, Box<dyn Error>> {
+    let parameter =
+        BondValenceParameter::lookup(cation, oxidation_state, anion).ok_or_else(|| {
+            format!(
+                "No bond valence parameter for {}({})-{}, give the distance",
+                cation, oxidation_state, anion
+            )
+        })?;
+
+    Ok(parameter.expected_distance(n))
+}
+
+fn first_shell_parameters(sigma2: f64) -> [FitParameter; 2] {
+    [
+        FitParameter::new("delr", 0.0).with_bounds(-0.2, 0.2),
+        FitParameter::new("ss", sigma2).with_bounds(0.001, 0.02),
+    ]
+}
+
+fn first_shell_path(absorber: &str, scatterer: &str, degeneracy: f64, reff: f64) -> FitPath {
+    FitPath {
+        label: format!("{}-{}", absorber, scatterer),
+        absorber: absorber.to_string(),
+        scatterer: scatterer.to_string(),
+        degeneracy,
+        reff,
+        s02: "amp".to_string(),
+        e0: "del_e0".to_string(),
+        deltar: "delr".to_string(),
+        sigma2: "ss".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::bondvalence::check_bond_valence;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_fit_templates() -> Result<(), Box<dyn Error>> {
+        let model = FitTemplate::octahedral_oxide("Fe", 3).instantiate()?;
+        assert_eq!(model.paths.len(), 1);
+        assert_eq!(model.varied_parameters().len(), 4);
+        assert_abs_diff_eq!(model.paths[0].reff, 2.0155, epsilon = 1e-4);
+        let parameter = BondValenceParameter::lookup("Fe", 3, "O").unwrap();
+        let check = check_bond_valence(&model.bond_shells("O")?, &parameter, 0.1)?;
+        assert_abs_diff_eq!(check.deviation, 0.0, epsilon = 1e-9);
+
+        let mut model = FitTemplate::fcc_metal("Cu").instantiate()?;
+        model
+            .set_parameter("alpha", 0.01)?
+            .set_parameter("ss2", 0.01)?;
+        let paths = model.path_values()?;
+        assert_eq!(paths[0].label, "Cu-Cu1");
+        assert_eq!((paths[0].degeneracy, paths[1].degeneracy), (12.0, 6.0));
+        assert_abs_diff_eq!(paths[0].r, 1.01 * 3.615 / 2f64.sqrt(), epsilon = 1e-12);
+        assert_abs_diff_eq!(paths[1].r, 1.01 * 3.615, epsilon = 1e-12);
+        assert_eq!((paths[0].sigma2, paths[1].sigma2), (0.006, 0.01));
+        assert!(model.set_parameter("delr", 0.0).is_err());
+
+        let model = FitTemplate::sulfide("Zn", 2, 4.0).instantiate()?;
+        assert_abs_diff_eq!(model.paths[0].reff, 2.346, epsilon = 1e-3);
+        assert!(FitTemplate::sulfide("Mo", 4, 6.0).instantiate().is_err());
+        assert!(FitTemplate::fcc_metal("Fe").instantiate().is_err());
+
+        let mut model = FitTemplate::Sulfide {
+            absorber: "Mo".to_string(),
+            oxidation_state: 4,
+            coordination: 6.0,
+            distance: Some(2.41),
+        }
+        .instantiate()?;
+        model.paths[0].sigma2 = "ss * unknown".to_string();
+        assert!(model.path_values().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Noise filter for chi(k) by iterative thresholding of the Fourier transform.
+//!
+//! k^w chi(k) is Fourier transformed, the components of chi(R) beyond `rmin` that are below the
+//! noise floor are set to zero, and the result is transformed back to k. Truncating the result
+//! to the measured k range leaks some of the suppressed noise back, so the procedure is
+//! repeated. The noise floor is the rms of |chi(R)| at high R, where no signal is expected,
+//! unless given explicitly.
+//!
+//! Filtered data no longer has the noise statistics of the measurement. The filtered spectrum
+//! is therefore renamed and carries the filter parameters in its metadata under FT_FILTER_KEY.
+
+use std::error::Error;
+
+use ndarray::{Array1, Axis};
+use serde::{Deserialize, Serialize};
+
+use super::background::BackgroundMethod;
+use super::mathutils::MathUtils;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::{xftf_fast, xftr_fast};
+use super::XAFSError;
+
+/// Metadata key of the parameters of the FT noise filter
+pub const FT_FILTER_KEY: &str = "ft_filter";
+
+/// Parameters of the FT noise filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FTNoiseFilter {
+    /// Components of chi(R) below this R (Å) are always kept.
+    pub rmin: Option<f64>,
+    /// R range (Å) used to estimate the noise floor.
+    pub noise_rmin: Option<f64>,
+    pub noise_rmax: Option<f64>,
+    /// Noise floor of |chi(R)|. Estimated from the noise R range if None.
+    pub noise_floor: Option<f64>,
+    /// Components below threshold * noise_floor are suppressed.
+    pub threshold: Option<f64>,
+    pub kweight: Option<f64>,
+    pub iterations: Option<usize>,
+    pub nfft: Option<usize>,
+}
+
+impl Default for FTNoiseFilter {
+    fn default() -> Self {
+        FTNoiseFilter {
+            rmin: Some(6.0),
+            noise_rmin: Some(15.0),
+            noise_rmax: Some(25.0),
+            noise_floor: None,
+            threshold: Some(2.0),
+            kweight: Some(2.0),
+            iterations: Some(5),
+            nfft: Some(2048),
+        }
+    }
+}
+
+/// Result of the FT noise filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilteredChi {
+    /// Parameters used, with the noise floor filled in
+    pub filter: FTNoiseFilter,
+    pub k: Array1<f64>,
+    pub chi: Array1<f64>,
+    /// Part of chi removed by the filter
+    pub removed: Array1<f64>,
+}
+
+impl FTNoiseFilter {
+    pub fn new() -> FTNoiseFilter {
+        FTNoiseFilter::default()
+    }
+
+    pub fn set_rmin(&mut self, rmin: Option<f64>) -> &mut Self {
+        self.rmin = rmin;
+        self
+    }
+
+    pub fn set_noise_range(
+        &mut self,
+        noise_rmin: Option<f64>,
+        noise_rmax: Option<f64>,
+    ) -> &mut Self {
+        self.noise_rmin = noise_rmin;
+        self.noise_rmax = noise_rmax;
+        self
+    }
+
+    pub fn set_noise_floor(&mut self, noise_floor: Option<f64>) -> &mut Self {
+        self.noise_floor = noise_floor;
+        self
+    }
+
+    pub fn set_threshold(&mut self, threshold: Option<f64>) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn set_kweight(&mut self, kweight: Option<f64>) -> &mut Self {
+        self.kweight = kweight;
+        self
+    }
+
+    pub fn set_iterations(&mut self, iterations: Option<usize>) -> &mut Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Filter chi(k). `k` has to be increasing.
+    pub fn filter(
+        &self,
+        k: &Array1<f64>,
+        chi: &Array1<f64>,
+    ) -> Result<FilteredChi, Box<dyn Error>> {
+        if k.len() < 2 || k.len() != chi.len() {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let default = FTNoiseFilter::default();
+        let rmin = self.rmin.or(default.rmin).unwrap();
+        let noise_rmin = self.noise_rmin.or(default.noise_rmin).unwrap();
+        let noise_rmax = self.noise_rmax.or(default.noise_rmax).unwrap();
+        let threshold = self.threshold.or(default.threshold).unwrap();
+        let kweight = self.kweight.or(default.kweight).unwrap();
+        let iterations = self.iterations.or(default.iterations).unwrap();
+
+        // Uniform grid from k = 0, as used by xftf
+        let kstep = (k[1] - k[0]).abs();
+        let npts = (1.01 + k[k.len() - 1] / kstep) as usize;
+        let nfft = self
+            .nfft
+            .or(default.nfft)
+            .unwrap()
+            .max(npts.next_power_of_two());
+        let k_ = Array1::from_iter((0..npts).map(|i| i as f64 * kstep));
+        let kw = k_.mapv(|k| k.powf(kweight));
+        let chi_0 = k_.interpolate(&k.to_vec(), &chi.to_vec())?;
+        let chi_ = &chi_0 * &kw;
+
+        let rstep = std::f64::consts::PI / kstep / nfft as f64;
+
+        let noise_floor = match self.noise_floor {
+            Some(noise_floor) => noise_floor,
+            None => {
+                let chir = xftf_fast(chi_.view(), nfft, kstep);
+                let noise = chir
+                    .get_frequency_bins()
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| {
+                        let r = (i + 1) as f64 * rstep;
+                        r >= noise_rmin && r <= noise_rmax
+                    })
+                    .map(|(_, c)| c.norm_sqr())
+                    .collect::<Vec<f64>>();
+
+                if noise.is_empty() {
+                    return Err(format!(
+                        "noise range {}-{} Å is beyond the R range of the transform",
+                        noise_rmin, noise_rmax
+                    )
+                    .into());
+                }
+
+                (noise.iter().sum::<f64>() / noise.len() as f64).sqrt()
+            }
+        };
+
+        let mut filtered = chi_.clone();
+        for _ in 0..iterations {
+            let mut chir = xftf_fast(filtered.view(), nfft, kstep);
+            chir.get_frequency_bins_mut()
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, c)| {
+                    (i + 1) as f64 * rstep > rmin && c.norm() < threshold * noise_floor
+                })
+                .for_each(|(_, c)| *c = 0.0.into());
+
+            filtered = xftr_fast(&chir, nfft, kstep)
+                .slice_axis(Axis(0), (0..npts).into())
+                .to_owned();
+        }
+
+        // Undo the k weight. The point at k = 0 is lost for kweight > 0 and is kept as is.
+        let filtered = Array1::from_iter(
+            filtered
+                .iter()
+                .zip(kw.iter())
+                .zip(chi_0.iter())
+                .map(|((chi, kw), chi_0)| if *kw > 0.0 { chi / kw } else { *chi_0 }),
+        );
+        let filtered = k.interpolate(&k_.to_vec(), &filtered.to_vec())?;
+
+        let mut filter = self.clone();
+        filter.noise_floor = Some(noise_floor);
+
+        Ok(FilteredChi {
+            filter,
+            k: k.clone(),
+            removed: chi - &filtered,
+            chi: filtered,
+        })
+    }
+}
+
+impl XASSpectrum {
+    /// Copy of the spectrum with chi(k) passed through the FT noise filter.
+    ///
+    /// The copy is named "<name> (FT filtered)" and stores the filter parameters under
+    /// FT_FILTER_KEY. Existing Fourier transforms are recalculated from the filtered chi.
+    /// Requires chi from AUTOBK.
+    pub fn ft_noise_filter(&self, filter: &FTNoiseFilter) -> Result<XASSpectrum, Box<dyn Error>> {
+        let k = self.get_k().ok_or(XAFSError::NotEnoughData)?;
+        let chi = self.get_chi().ok_or(XAFSError::NotEnoughData)?;
+        let filtered = filter.filter(&k, &chi)?;
+
+        let mut spectrum = self.clone();
+        match spectrum.background.as_mut() {
+            Some(BackgroundMethod::AUTOBK(autobk)) => autobk.chi = Some(filtered.chi),
+            _ => return Err("the FT noise filter requires chi from AUTOBK".into()),
+        }
+
+        let name = self.name.clone().unwrap_or_else(|| "spectrum".to_string());
+        spectrum
+            .set_name(format!("{} (FT filtered)", name))
+            .set_metadata(FT_FILTER_KEY, serde_json::to_value(&filtered.filter)?);
+
+        if spectrum.xftf.is_some() {
+            spectrum.fft()?;
+        }
+        if spectrum.xftr.is_some() {
+            spectrum.ifft()?;
+        }
+
+        Ok(spectrum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_ft_noise_filter() -> Result<(), Box<dyn Error>> {
+        // Two shells at 2 and 3 Å with noise spread over all R
+        let k = Array1::from_iter((0..321).map(|i| i as f64 * 0.05));
+        let chi = k.mapv(|k| {
+            (0.5 * (4.0 * k).sin() + 0.3 * (6.0 * k + 1.0).sin()) * (-0.01 * k * k).exp()
+                / (k + 1.0)
+        });
+        let noise = Array1::from_iter((0..k.len()).map(|i| 0.02 * ((i * i) as f64 * 0.7).sin()));
+        let noisy_chi = &chi + &noise;
+
+        let filter = FTNoiseFilter::new();
+        let filtered = filter.filter(&k, &noisy_chi)?;
+        assert!(filtered.filter.noise_floor.unwrap() > 0.0);
+        assert_eq!(filtered.removed, &noisy_chi - &filtered.chi);
+
+        // Compared in the usual k range, as the k weight amplifies the error at low k
+        let rms = |x: Array1<f64>| {
+            let x = x
+                .iter()
+                .zip(k.iter())
+                .filter(|(_, k)| **k >= 2.0)
+                .map(|(x, _)| x * x)
+                .collect::<Vec<f64>>();
+            (x.iter().sum::<f64>() / x.len() as f64).sqrt()
+        };
+        assert!(rms(&filtered.chi - &chi) < 0.5 * rms(noise));
+
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        assert!(spectrum.ft_noise_filter(&filter).is_err());
+        spectrum.calc_background()?.fft()?;
+
+        let filtered = spectrum.ft_noise_filter(&filter)?;
+        assert!(filtered.name.as_ref().unwrap().ends_with("(FT filtered)"));
+        assert_eq!(
+            filtered.get_metadata(FT_FILTER_KEY).unwrap()["rmin"],
+            serde_json::json!(6.0)
+        );
+        assert_ne!(filtered.get_chi(), spectrum.get_chi());
+        assert_ne!(filtered.get_chir_mag(), spectrum.get_chir_mag());
+
+        Ok(())
+    }
+}
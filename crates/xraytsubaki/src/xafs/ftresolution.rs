@@ -0,0 +1,254 @@
+//! Resolution and leakage of the forward Fourier transform.
+//!
+//! A single shell at R0 appears in |chi(R)| as the magnitude of the transform of the k window,
+//! centered at R0. Its full width at half maximum is the resolution in R: two shells closer
+//! than that are not separated. Its sidelobes leak part of a strong peak into neighbouring
+//! distances, where they can be mistaken for weak shells. The width is set by kmax - kmin,
+//! the middles of the tapers, and wide tapers (large dk) mainly lower the sidelobes. nfft only
+//! sets how finely |chi(R)| is sampled: the width does not change with zero-padding, but too
+//! few points per FWHM distort the peaks.
+//!
+//! ```
+//! use xraytsubaki::xafs::ftresolution::{compare_windows, FTResolution};
+//! use xraytsubaki::xafs::xrayfft::XrayFFTF;
+//!
+//! let table = compare_windows(&XrayFFTF::new())?;
+//! println!("{}", FTResolution::table(&table));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt::Write as _;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::xafsutils::{ftwindow, FTWindow};
+use super::xrayfft::XrayFFTF;
+
+/// k step used when the transform has none set (Å⁻¹)
+const DEFAULT_KSTEP: f64 = 0.05;
+/// Points of the evaluation of the window transform per sampling step of |chi(R)|
+const OVERSAMPLING: usize = 16;
+/// Sidelobes are searched up to this many FWHM from the center
+const SIDELOBE_RANGE: f64 = 20.0;
+
+/// Characteristics of the point spread function in R of a forward transform
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FTResolution {
+    pub window: FTWindow,
+    pub kmin: f64,
+    pub kmax: f64,
+    pub dk: f64,
+    pub nfft: usize,
+    /// Full width at half maximum of the peak of a single shell (Å)
+    pub fwhm: f64,
+    /// Sampling step of |chi(R)| (Å)
+    pub rstep: f64,
+    /// Height of the largest sidelobe relative to the main peak
+    pub sidelobe: f64,
+    /// Distance of the largest sidelobe from the main peak (Å)
+    pub sidelobe_position: f64,
+}
+
+impl FTResolution {
+    /// Sidelobe level in dB (negative)
+    pub fn sidelobe_db(&self) -> f64 {
+        20.0 * self.sidelobe.log10()
+    }
+
+    /// Samples of |chi(R)| per FWHM. Peaks are poorly represented below about 4.
+    pub fn points_per_fwhm(&self) -> f64 {
+        self.fwhm / self.rstep
+    }
+
+    /// Markdown table of the characteristics, one row per entry.
+    pub fn table(rows: &[FTResolution]) -> String {
+        let mut table = String::from(
+            "| window | k range (Å⁻¹) | dk | nfft | FWHM (Å) | points/FWHM | sidelobe (dB) | at (Å) |\n\
+             |---|---|---|---|---|---|---|---|\n",
+        );
+
+        for row in rows {
+            let _ = writeln!(
+                table,
+                "| {:?} | {} to {} | {} | {} | {:.3} | {:.1} | {:.1} | {:.2} |",
+                row.window,
+                row.kmin,
+                row.kmax,
+                row.dk,
+                row.nfft,
+                row.fwhm,
+                row.points_per_fwhm(),
+                row.sidelobe_db(),
+                row.sidelobe_position
+            );
+        }
+
+        table
+    }
+}
+
+impl XrayFFTF {
+    /// Resolution and leakage of the transform with the current parameters. Unset parameters
+    /// take the values xftf would use, with a k step of 0.05 Å⁻¹ if no data set it.
+    ///
+    /// The window is used without the k weight, which depends on the data.
+    pub fn resolution(&self) -> Result<FTResolution, Box<dyn Error>> {
+        let default = XrayFFTF::default();
+        let window = self.window.or(default.window).unwrap();
+        let kmin = self.kmin.or(default.kmin).unwrap();
+        let kmax = self.kmax.or(default.kmax).unwrap();
+        let dk = self.dk.or(default.dk).unwrap();
+        let dk2 = self.dk2.unwrap_or(dk);
+        let nfft = self.nfft.or(default.nfft).unwrap();
+        let kstep = self.kstep.unwrap_or(DEFAULT_KSTEP);
+
+        if kmax <= kmin || kstep <= 0.0 || nfft == 0 {
+            return Err(format!(
+                "No window for k from {} to {} with a step of {}",
+                kmin, kmax, kstep
+            )
+            .into());
+        }
+
+        let k = Array1::range(0.0, kmax + dk2 + kstep, kstep);
+        let win = ftwindow(
+            &k,
+            Some(kmin),
+            Some(kmax),
+            Some(dk),
+            Some(dk2),
+            Some(window),
+        )?;
+
+        // |sum w(k) exp(2ikR)|, normalized to 1 at R = 0
+        let response = |r: f64| {
+            let (re, im) = k
+                .iter()
+                .zip(win.iter())
+                .fold((0.0, 0.0), |(re, im), (k, w)| {
+                    let phase = 2.0 * k * r;
+                    (re + w * phase.cos(), im + w * phase.sin())
+                });
+            (re * re + im * im).sqrt()
+        };
+        let peak = response(0.0);
+        if peak.is_nan() || peak <= 0.0 {
+            return Err("The window is zero everywhere".into());
+        }
+
+        let rstep = PI / (kstep * nfft as f64);
+        let step = rstep / OVERSAMPLING as f64;
+        // The main lobe ends at about pi / (kmax - kmin), well within 4 times that
+        let r_end = 4.0 * PI / (kmax - kmin).max(kstep);
+
+        let mut r_previous = 0.0;
+        let mut previous = 1.0;
+        let mut half = None;
+        let mut r = step;
+        while r <= r_end {
+            let current = response(r) / peak;
+            if current <= 0.5 {
+                half = Some(r_previous + (previous - 0.5) / (previous - current) * step);
+                break;
+            }
+            (r_previous, previous) = (r, current);
+            r += step;
+        }
+        let half = half.ok_or("The main peak is wider than the search range")?;
+
+        // Walk down to the first minimum, then find the highest point beyond it
+        let mut r = half;
+        let mut previous = response(r) / peak;
+        loop {
+            let current = response(r + step) / peak;
+            if current >= previous {
+                break;
+            }
+            previous = current;
+            r += step;
+        }
+
+        let (mut sidelobe, mut sidelobe_position) = (0.0, r);
+        let r_max = r + SIDELOBE_RANGE * 2.0 * half;
+        while r <= r_max {
+            let current = response(r) / peak;
+            if current > sidelobe {
+                (sidelobe, sidelobe_position) = (current, r);
+            }
+            r += step;
+        }
+
+        Ok(FTResolution {
+            window,
+            kmin,
+            kmax,
+            dk,
+            nfft,
+            fwhm: 2.0 * half,
+            rstep,
+            sidelobe,
+            sidelobe_position,
+        })
+    }
+}
+
+/// resolution() of `xftf` with each window, other parameters unchanged. FHanning is left out,
+/// as it reads dk as a fraction of the k range.
+pub fn compare_windows(xftf: &XrayFFTF) -> Result<Vec<FTResolution>, Box<dyn Error>> {
+    FTWindow::ALL
+        .iter()
+        .filter(|window| **window != FTWindow::FHanning)
+        .map(|window| {
+            XrayFFTF {
+                window: Some(*window),
+                ..xftf.copy_parameters()
+            }
+            .resolution()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_ft_resolution() -> Result<(), Box<dyn Error>> {
+        // A box window of width dk_range has a sinc response: FWHM = 1.2067 pi / dk_range in R
+        // and a first sidelobe of -13.3 dB
+        let mut xftf = XrayFFTF::new();
+        xftf.window = Some(FTWindow::Parzen);
+        xftf.kmin = Some(2.0);
+        xftf.kmax = Some(12.0);
+        xftf.dk = Some(0.0);
+        xftf.kstep = Some(0.01);
+        let resolution = xftf.resolution()?;
+        assert_abs_diff_eq!(resolution.fwhm, 1.2067 * PI / 10.0, epsilon = 0.01);
+        assert_abs_diff_eq!(resolution.sidelobe_db(), -13.26, epsilon = 0.3);
+        assert_abs_diff_eq!(resolution.rstep, PI / (0.01 * 2048.0), epsilon = 1e-12);
+
+        // Tapers lower the sidelobes, the width stays close to that of the box
+        xftf.window = Some(FTWindow::Hanning);
+        xftf.dk = Some(3.0);
+        let tapered = xftf.resolution()?;
+        assert_abs_diff_eq!(
+            tapered.fwhm,
+            resolution.fwhm,
+            epsilon = 0.05 * resolution.fwhm
+        );
+        assert!(tapered.sidelobe < resolution.sidelobe);
+
+        let table = compare_windows(&xftf)?;
+        assert_eq!(table.len(), FTWindow::ALL.len() - 1);
+        assert_eq!(
+            FTResolution::table(&table).lines().count(),
+            FTWindow::ALL.len() + 1
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+//! Detection of glitches correlated with i0.
+//!
+//! Monochromator glitches are sharp features of the incident beam. They show up in i0, and
+//! because no detector normalizes them out perfectly, also in mu. Sharp features of the sample
+//! (e.g. narrow pre-edge peaks) are only in mu. Looking for a feature of mu at the same energy
+//! in i0 therefore tells the two apart much more reliably than the shape of mu alone.
+//!
+//! A feature is found from the derivative: a glitch of one point gives two consecutive
+//! derivative values of opposite sign, both far from the running median of the derivative,
+//! while an edge or a step only gives one. The score of a point is the smaller of the two
+//! deviations in units of the local robust standard deviation of the deviations.
+//!
+//! Glitches are removed from the raw spectrum before normalization, either by dropping the
+//! points or by interpolating over them: an energy range with XASSpectrum::deglitch, the
+//! monochromator glitches with remove_glitches, or any mask, e.g. of xafsutils::spike_mask,
+//! with remove_points and interpolate_points.
+
+use std::error::Error;
+
+use ndarray::{Array1, ArrayBase, Axis, Ix1, OwnedRepr};
+use serde::{Deserialize, Serialize};
+
+use super::mathutils::{window_filter, FilterEdge, MathUtils, WindowFilter};
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Size of the window of the local noise estimate, in units of the median window
+const NOISE_WINDOW_FACTOR: usize = 5;
+
+/// Origin of a glitch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GlitchKind {
+    /// Feature of mu also present in i0
+    Monochromator,
+    /// Feature of mu only, likely a real feature of the spectrum
+    Spectral,
+    /// Feature of mu of a spectrum without an i0 channel
+    Unclassified,
+}
+
+/// Glitch found by find_glitches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Glitch {
+    /// Index of the point in raw_energy
+    pub index: usize,
+    pub energy: f64,
+    pub kind: GlitchKind,
+    /// Score of the feature in mu
+    pub mu_score: f64,
+    /// Highest score of ln(i0) within one point of the glitch, None without i0
+    pub i0_score: Option<f64>,
+}
+
+/// Options of find_glitches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlitchOptions {
+    /// Points of the running median of the derivative. Default = 7.
+    pub window: Option<usize>,
+    /// Score of mu above which a point is a glitch. Default = 6.
+    pub threshold: Option<f64>,
+    /// Score of ln(i0) above which a glitch is attributed to the monochromator. Default = 4.
+    pub i0_threshold: Option<f64>,
+    /// Name of the i0 channel. Default = "i0".
+    pub i0_channel: Option<String>,
+}
+
+impl Default for GlitchOptions {
+    fn default() -> Self {
+        GlitchOptions {
+            window: Some(7),
+            threshold: Some(6.0),
+            i0_threshold: Some(4.0),
+            i0_channel: Some("i0".to_string()),
+        }
+    }
+}
+
+impl GlitchOptions {
+    pub fn new() -> GlitchOptions {
+        GlitchOptions::default()
+    }
+
+    pub fn set_window(&mut self, window: usize) -> &mut Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn set_threshold(&mut self, threshold: f64) -> &mut Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn set_i0_threshold(&mut self, i0_threshold: f64) -> &mut Self {
+        self.i0_threshold = Some(i0_threshold);
+        self
+    }
+
+    pub fn set_i0_channel<S: Into<String>>(&mut self, i0_channel: S) -> &mut Self {
+        self.i0_channel = Some(i0_channel.into());
+        self
+    }
+}
+
+/// Score of each point as a single point glitch of `y`, see the module documentation.
+/// The first and last points have a score of 0.
+pub fn spike_scores(
+    energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    y: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    window: usize,
+) -> Array1<f64> {
+    let n = energy.len().min(y.len());
+    let mut scores = Array1::zeros(n);
+    if n < 3 {
+        return scores;
+    }
+
+    let derivative = Array1::from_iter(
+        (0..n - 1).map(|j| (y[j + 1] - y[j]) / (energy[j + 1] - energy[j]).max(f64::EPSILON)),
+    );
+    let deviation = &derivative
+        - &window_filter(
+            &derivative,
+            window,
+            WindowFilter::Median,
+            FilterEdge::Reflect,
+        );
+
+    // Local robust standard deviation, as the noise of the derivative changes with the step
+    let sigma = window_filter(
+        &deviation.mapv(f64::abs),
+        NOISE_WINDOW_FACTOR * window.max(1) + 1,
+        WindowFilter::Median,
+        FilterEdge::Reflect,
+    ) * 1.4826;
+
+    for (i, pair) in deviation.to_vec().windows(2).enumerate() {
+        let noise = sigma[i].max(sigma[i + 1]);
+        if pair[0] * pair[1] < 0.0 && noise > 0.0 {
+            scores[i + 1] = pair[0].abs().min(pair[1].abs()) / noise;
+        }
+    }
+
+    scores
+}
+
+impl XASSpectrum {
+    /// Glitches of the raw spectrum, classified with the i0 channel if it is present.
+    pub fn find_glitches(&self, options: &GlitchOptions) -> Result<Vec<Glitch>, Box<dyn Error>> {
+        let default = GlitchOptions::default();
+        let window = options.window.or(default.window).unwrap();
+        let threshold = options.threshold.or(default.threshold).unwrap();
+        let i0_threshold = options.i0_threshold.or(default.i0_threshold).unwrap();
+        let i0_channel = options
+            .i0_channel
+            .as_ref()
+            .or(default.i0_channel.as_ref())
+            .unwrap();
+
+        let (energy, mu) = match (self.raw_energy.as_ref(), self.raw_mu.as_ref()) {
+            (Some(energy), Some(mu)) => (energy, mu),
+            _ => return Err(Box::new(XAFSError::NotEnoughData)),
+        };
+
+        let mu_scores = spike_scores(energy, mu, window);
+        let i0_scores = self.get_channel(i0_channel).map(|i0| {
+            spike_scores(
+                energy,
+                &i0.mapv(|x| x.abs().max(f64::MIN_POSITIVE).ln()),
+                window,
+            )
+        });
+
+        let glitches = mu_scores
+            .iter()
+            .enumerate()
+            .filter(|(_, score)| **score > threshold)
+            .map(|(index, &mu_score)| {
+                let i0_score = i0_scores.as_ref().map(|scores| {
+                    let lo = index.saturating_sub(1);
+                    let hi = (index + 2).min(scores.len());
+                    scores
+                        .slice(ndarray::s![lo..hi])
+                        .fold(0.0_f64, |max, s| max.max(*s))
+                });
+
+                let kind = match i0_score {
+                    None => GlitchKind::Unclassified,
+                    Some(score) if score > i0_threshold => GlitchKind::Monochromator,
+                    Some(_) => GlitchKind::Spectral,
+                };
+
+                Glitch {
+                    index,
+                    energy: energy[index],
+                    kind,
+                    mu_score,
+                    i0_score,
+                }
+            })
+            .collect();
+
+        Ok(glitches)
+    }
+
+    /// Mask of the points of raw_energy to remove: true at monochromator glitches, and at all
+    /// glitches if the spectrum has no i0 channel.
+    pub fn glitch_mask(&self, options: &GlitchOptions) -> Result<Array1<bool>, Box<dyn Error>> {
+        let n = self.raw_energy.as_ref().map_or(0, |e| e.len());
+        let mut mask = Array1::from_elem(n, false);
+
+        for glitch in self.find_glitches(options)? {
+            if glitch.kind != GlitchKind::Spectral {
+                mask[glitch.index] = true;
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Remove the points of the raw spectrum with energies between `emin` and `emax`.
+    pub fn deglitch(&mut self, emin: f64, emax: f64) -> Result<&mut Self, Box<dyn Error>> {
+        let (emin, emax) = (emin.min(emax), emin.max(emax));
+        let mask = self
+            .raw_energy
+            .as_ref()
+            .ok_or(XAFSError::NotEnoughData)?
+            .mapv(|e| e >= emin && e <= emax);
+
+        self.remove_points(&mask)
+    }
+
+    /// Remove or, with `interpolate`, interpolate over the points of glitch_mask. Returns the
+    /// energies of the points.
+    pub fn remove_glitches(
+        &mut self,
+        options: &GlitchOptions,
+        interpolate: bool,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mask = self.glitch_mask(options)?;
+        let energies = self
+            .raw_energy
+            .iter()
+            .flatten()
+            .zip(mask.iter())
+            .filter(|(_, masked)| **masked)
+            .map(|(e, _)| *e)
+            .collect();
+
+        if interpolate {
+            self.interpolate_points(&mask)?;
+        } else {
+            self.remove_points(&mask)?;
+        }
+
+        Ok(energies)
+    }
+
+    /// Remove the points of raw_energy where `mask` is true from the raw spectrum and the
+    /// channels. energy and mu are derived again as by set_spectrum; normalization, background
+    /// and FT have to be recalculated afterwards.
+    pub fn remove_points(&mut self, mask: &Array1<bool>) -> Result<&mut Self, Box<dyn Error>> {
+        let (energy, mu) = self.checked_raw(mask)?;
+        let keep = (0..mask.len())
+            .filter(|&i| !mask[i])
+            .collect::<Vec<usize>>();
+        if keep.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let energy = energy.select(Axis(0), &keep);
+        let mu = mu.select(Axis(0), &keep);
+        for channel in self.channels.values_mut() {
+            *channel = channel.select(Axis(0), &keep);
+        }
+
+        Ok(self.set_spectrum(energy, mu))
+    }
+
+    /// Replace raw mu and the channels at the points of raw_energy where `mask` is true by the
+    /// linear interpolation of the other points. Points beyond the first or last unmasked
+    /// point take its value.
+    pub fn interpolate_points(&mut self, mask: &Array1<bool>) -> Result<&mut Self, Box<dyn Error>> {
+        let (energy, mut mu) = self.checked_raw(mask)?;
+        let keep = (0..mask.len())
+            .filter(|&i| !mask[i])
+            .collect::<Vec<usize>>();
+        if keep.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let knots = keep.iter().map(|&i| energy[i]).collect::<Vec<f64>>();
+        let masked = Array1::from_iter((0..mask.len()).filter(|&i| mask[i]));
+        let points = masked.mapv(|i| energy[i]);
+        let fill = |values: &mut Array1<f64>| -> Result<(), Box<dyn Error>> {
+            let knot_values = keep.iter().map(|&i| values[i]).collect::<Vec<f64>>();
+            let interpolated = points.interpolate(&knots, &knot_values)?;
+            for (&i, value) in masked.iter().zip(interpolated.iter()) {
+                values[i] = *value;
+            }
+            Ok(())
+        };
+
+        fill(&mut mu)?;
+        for channel in self.channels.values_mut() {
+            fill(channel)?;
+        }
+
+        Ok(self.set_spectrum(energy, mu))
+    }
+
+    // Raw energy and mu, with a mask of the same length
+    fn checked_raw(
+        &self,
+        mask: &Array1<bool>,
+    ) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+        let (energy, mu) = match (self.raw_energy.as_ref(), self.raw_mu.as_ref()) {
+            (Some(energy), Some(mu)) => (energy.clone(), mu.clone()),
+            _ => return Err(Box::new(XAFSError::NotEnoughData)),
+        };
+        if mask.len() != energy.len() {
+            return Err(format!(
+                "Mask of {} points for a spectrum of {} points",
+                mask.len(),
+                energy.len()
+            )
+            .into());
+        }
+
+        Ok((energy, mu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_find_glitches() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let (monochromator, spectral) = (100, 500);
+        spectrum.raw_mu.as_mut().unwrap()[monochromator] += 0.05;
+        spectrum.raw_mu.as_mut().unwrap()[spectral] += 0.05;
+        spectrum.channels.get_mut("i0").unwrap()[monochromator] *= 0.7;
+
+        let glitches = spectrum.find_glitches(&GlitchOptions::new())?;
+        let kind = |index: usize| {
+            glitches
+                .iter()
+                .find(|glitch| glitch.index == index)
+                .map(|glitch| glitch.kind)
+        };
+        assert_eq!(kind(monochromator), Some(GlitchKind::Monochromator));
+        assert_eq!(kind(spectral), Some(GlitchKind::Spectral));
+
+        let mask = spectrum.glitch_mask(&GlitchOptions::new())?;
+        assert!(mask[monochromator]);
+        assert!(!mask[spectral]);
+
+        spectrum.channels.clear();
+        let glitches = spectrum.find_glitches(&GlitchOptions::new())?;
+        assert!(glitches
+            .iter()
+            .all(|glitch| glitch.kind == GlitchKind::Unclassified));
+        assert!(spectrum.glitch_mask(&GlitchOptions::new())?[spectral]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deglitch() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let reference = io::load_spectrum_QAS_trans(&path)?;
+        let energy = reference.raw_energy.clone().unwrap();
+
+        let mut spectrum = reference.clone();
+        spectrum.deglitch(energy[100], energy[102])?;
+        assert_eq!(spectrum.raw_energy.as_ref().unwrap().len(), 642);
+        assert_eq!(spectrum.get_channel("i0").unwrap().len(), 642);
+        assert_eq!(spectrum.raw_energy.as_ref().unwrap()[100], energy[103]);
+        assert_eq!(spectrum.mu.as_ref().unwrap().len(), 642);
+
+        let mut glitched = reference.clone();
+        glitched.raw_mu.as_mut().unwrap()[100] += 0.05;
+        glitched.channels.get_mut("i0").unwrap()[100] *= 0.7;
+
+        let mut interpolated = glitched.clone();
+        let energies = interpolated.remove_glitches(&GlitchOptions::new(), true)?;
+        assert!(energies.contains(&energy[100]));
+        let (mu, raw_mu) = (interpolated.raw_mu.unwrap(), reference.raw_mu.unwrap());
+        assert!((mu[100] - raw_mu[100]).abs() < 0.01);
+        assert_eq!(mu[99], raw_mu[99]);
+
+        glitched.remove_glitches(&GlitchOptions::new(), false)?;
+        assert_eq!(
+            glitched.raw_energy.as_ref().unwrap().len(),
+            645 - energies.len()
+        );
+
+        // The median residual detector finds the same point without i0
+        let mut mu = raw_mu.clone();
+        mu[100] += 0.05;
+        let mask = crate::xafs::xafsutils::spike_mask(&mu, None, None);
+        assert!(mask[100]);
+
+        assert!(spectrum
+            .remove_points(&Array1::from_elem(10, false))
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+//! Non-owning subsets of a group.
+//!
+//! A view holds a reference to an XASGroup and the indices of some of its spectra, so that a
+//! workflow like "process only the selected 50 of 5000 spectra" does not clone the spectra into
+//! a new group. XASGroupView borrows the group for reading, XASGroupViewMut for processing the
+//! spectra in place. The indices of a view are sorted and unique, and results refer to the
+//! spectra by their index in the parent group.
+//!
+//! ```
+//! use xraytsubaki::xafs::io;
+//! use xraytsubaki::xafs::normalization::Normalization;
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//!
+//! let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/testfiles/Ru_QAS.dat").to_string();
+//! let spectrum = io::load_spectrum_QAS_trans(&path)?;
+//! let mut group = XASGroup::new();
+//! group.add_spectra(vec![spectrum.clone(), spectrum.clone(), spectrum]);
+//! group.select(&[0, 2]);
+//!
+//! group.selected_view_mut().normalize()?;
+//! let norm = |i: usize| {
+//!     let normalization = group.spectra[i].normalization.as_ref();
+//!     normalization.and_then(|n| n.get_norm().cloned())
+//! };
+//! assert!(norm(0).is_some());
+//! assert!(norm(1).is_none());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+
+use rayon::prelude::*;
+
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Read-only view of some spectra of a group
+#[derive(Debug, Clone)]
+pub struct XASGroupView<'a> {
+    group: &'a XASGroup,
+    indices: Vec<usize>,
+}
+
+/// View of some spectra of a group which processes them in place
+#[derive(Debug)]
+pub struct XASGroupViewMut<'a> {
+    group: &'a mut XASGroup,
+    indices: Vec<usize>,
+}
+
+// Sort and dedup `indices`, which all have to be below `len`
+fn view_indices(indices: &[usize], len: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+    if indices.iter().any(|&i| i >= len) {
+        return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+    }
+
+    let mut indices = indices.to_vec();
+    indices.sort();
+    indices.dedup();
+    Ok(indices)
+}
+
+impl<'a> XASGroupView<'a> {
+    pub fn new(group: &'a XASGroup, indices: &[usize]) -> Result<XASGroupView<'a>, Box<dyn Error>> {
+        let indices = view_indices(indices, group.len())?;
+        Ok(XASGroupView { group, indices })
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Indices of the spectra in the parent group
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    pub fn group(&self) -> &'a XASGroup {
+        self.group
+    }
+
+    /// Spectrum at `position` in the view
+    pub fn get_spectrum(&self, position: usize) -> Result<&'a XASSpectrum, Box<dyn Error>> {
+        let index = self
+            .indices
+            .get(position)
+            .ok_or(XAFSError::GroupIndexOutOfRange)?;
+        Ok(&self.group.spectra[*index])
+    }
+
+    /// Spectra of the view with their indices in the parent group
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &'a XASSpectrum)> + '_ {
+        let group = self.group;
+        self.indices.iter().map(move |&i| (i, &group.spectra[i]))
+    }
+
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (usize, &'a XASSpectrum)> + '_ {
+        let group = self.group;
+        self.indices
+            .par_iter()
+            .map(move |&i| (i, &group.spectra[i]))
+    }
+
+    /// Spectra of the view
+    pub fn spectra(&self) -> Vec<&'a XASSpectrum> {
+        self.iter().map(|(_, spectrum)| spectrum).collect()
+    }
+
+    /// Copy the spectra of the view with their flags and weights into a new group.
+    pub fn to_group(&self) -> XASGroup {
+        let mut group = XASGroup::new();
+        for (i, spectrum) in self.iter() {
+            group.add_spectrum(spectrum.clone());
+            let last = group.len() - 1;
+            group.selected[last] = self.group.is_selected(i);
+            group.visible[last] = self.group.is_visible(i);
+            group.included[last] = self.group.is_included(i);
+            group.weights[last] = self.group.get_weight(i);
+        }
+        group
+    }
+}
+
+impl<'a> XASGroupViewMut<'a> {
+    pub fn new(
+        group: &'a mut XASGroup,
+        indices: &[usize],
+    ) -> Result<XASGroupViewMut<'a>, Box<dyn Error>> {
+        let indices = view_indices(indices, group.len())?;
+        Ok(XASGroupViewMut { group, indices })
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Indices of the spectra in the parent group
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    /// Read-only view of the same spectra
+    pub fn as_view(&self) -> XASGroupView<'_> {
+        XASGroupView {
+            group: &*self.group,
+            indices: self.indices.clone(),
+        }
+    }
+
+    /// Spectrum at `position` in the view
+    pub fn get_spectrum_mut(
+        &mut self,
+        position: usize,
+    ) -> Result<&mut XASSpectrum, Box<dyn Error>> {
+        let index = self
+            .indices
+            .get(position)
+            .ok_or(XAFSError::GroupIndexOutOfRange)?;
+        Ok(&mut self.group.spectra[*index])
+    }
+
+    /// Spectra of the view with their indices in the parent group
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut XASSpectrum)> {
+        let indices = &self.indices;
+        self.group
+            .spectra
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| indices.binary_search(i).is_ok())
+    }
+
+    /// Mutable references to the spectra of the view, e.g. for a parallel iterator
+    pub fn spectra_mut(&mut self) -> Vec<(usize, &mut XASSpectrum)> {
+        self.iter_mut().collect()
+    }
+
+    pub fn find_e0(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        self.try_for_each(GroupStep::FindE0)
+    }
+
+    pub fn normalize(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        self.try_for_each(GroupStep::Normalize)
+    }
+
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
+    pub fn calc_background(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        self.try_for_each(GroupStep::Background)
+    }
+
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
+    pub fn fft(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        self.try_for_each(GroupStep::Xftf)
+    }
+
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
+    pub fn ifft(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        self.try_for_each(GroupStep::Xftr)
+    }
+
+    // Run `step` on the spectra in parallel. The first error is returned with the index of its
+    // spectrum in the parent group.
+    fn try_for_each(&mut self, step: GroupStep) -> Result<&mut Self, Box<dyn Error>> {
+        self.spectra_mut()
+            .into_par_iter()
+            .try_for_each(|(index, spectrum)| {
+                step.run(spectrum)
+                    .map_err(|e| format!("spectrum {}: {}", index, e))
+            })
+            .map_err(|e| -> Box<dyn Error> { e.into() })?;
+
+        Ok(self)
+    }
+
+    // As try_for_each, one spectrum after the other
+    pub(crate) fn try_for_each_seq(
+        &mut self,
+        step: GroupStep,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        for (index, spectrum) in self.iter_mut() {
+            step.run(spectrum)
+                .map_err(|e| format!("spectrum {}: {}", index, e))?;
+        }
+
+        Ok(self)
+    }
+}
+
+/// Processing steps run on the spectra of a view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GroupStep {
+    FindE0,
+    Normalize,
+    Background,
+    Xftf,
+    Xftr,
+}
+
+impl GroupStep {
+    // XANES-only spectra have no EXAFS, so that the steps after normalization skip them
+    fn run(self, spectrum: &mut XASSpectrum) -> Result<(), Box<dyn Error>> {
+        match self {
+            GroupStep::FindE0 => spectrum.find_e0().map(|_| ()),
+            GroupStep::Normalize => spectrum.normalize().map(|_| ()),
+            _ if spectrum.is_xanes_only() => Ok(()),
+            GroupStep::Background => spectrum.calc_background().map(|_| ()),
+            GroupStep::Xftf => spectrum.fft().map(|_| ()),
+            GroupStep::Xftr => spectrum.ifft().map(|_| ()),
+        }
+    }
+}
+
+impl XASGroup {
+    /// Read-only view of the spectra at `indices`.
+    pub fn view(&self, indices: &[usize]) -> Result<XASGroupView<'_>, Box<dyn Error>> {
+        XASGroupView::new(self, indices)
+    }
+
+    /// View of the spectra at `indices` which processes them in place.
+    pub fn view_mut(&mut self, indices: &[usize]) -> Result<XASGroupViewMut<'_>, Box<dyn Error>> {
+        XASGroupViewMut::new(self, indices)
+    }
+
+    /// Read-only view of the whole group
+    pub fn view_all(&self) -> XASGroupView<'_> {
+        XASGroupView {
+            group: self,
+            indices: (0..self.len()).collect(),
+        }
+    }
+
+    /// View of the whole group which processes the spectra in place. The processing methods
+    /// of XASGroup run through it.
+    pub fn view_all_mut(&mut self) -> XASGroupViewMut<'_> {
+        let indices = (0..self.len()).collect();
+        XASGroupViewMut {
+            group: self,
+            indices,
+        }
+    }
+
+    pub fn selected_view(&self) -> XASGroupView<'_> {
+        XASGroupView {
+            group: self,
+            indices: self.selected_indices(),
+        }
+    }
+
+    pub fn selected_view_mut(&mut self) -> XASGroupViewMut<'_> {
+        let indices = self.selected_indices();
+        XASGroupViewMut {
+            group: self,
+            indices,
+        }
+    }
+
+    pub fn included_view(&self) -> XASGroupView<'_> {
+        XASGroupView {
+            group: self,
+            indices: self.included_indices(),
+        }
+    }
+
+    pub fn included_view_mut(&mut self) -> XASGroupViewMut<'_> {
+        let indices = self.included_indices();
+        XASGroupViewMut {
+            group: self,
+            indices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::pipeline::ProcessingParameters;
+    use crate::xafs::plot::PlotKind;
+    use crate::xafs::presets::Preset;
+    use crate::xafs::tests::TOP_DIR;
+    use ndarray::Array1;
+
+    #[test]
+    fn test_group_view() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut group = XASGroup::new();
+        for i in 0..4 {
+            let mut spectrum = spectrum.clone();
+            spectrum.set_name(format!("scan{}", i));
+            group.add_spectrum(spectrum);
+        }
+        group.set_weight(3, 2.0)?;
+
+        assert!(group.view(&[1, 4]).is_err());
+        let view = group.view(&[3, 1, 3])?;
+        assert_eq!(view.indices(), &[1, 3]);
+        assert_eq!(view.get_spectrum(1)?.name.as_deref(), Some("scan3"));
+        assert!(view.get_spectrum(2).is_err());
+        let copy = view.to_group();
+        assert_eq!(copy.len(), 2);
+        assert_eq!(copy.get_weight(1), 2.0);
+
+        let mut view = group.view_mut(&[1, 3])?;
+        view.normalize()?;
+        let summary = view.process_all(&Preset::ExafsStandard.into());
+        assert!(summary.is_success());
+        assert_eq!(summary.succeeded(), vec![1, 3]);
+
+        let statistics = view.as_view().statistics(PlotKind::Norm)?;
+        assert_eq!(statistics.n, 2);
+        assert!(group.spectra[3].get_chir_mag().is_some());
+        assert!(group.spectra[0].get_chir_mag().is_none());
+
+        let mut short = XASSpectrum::new();
+        short.set_spectrum(Array1::range(0.0, 3.0, 1.0), Array1::zeros(3));
+        group.add_spectrum(short).select(&[0, 4]);
+        let error = group.selected_view_mut().normalize().unwrap_err();
+        assert!(error.to_string().starts_with("spectrum 4"));
+
+        // The processing methods of the group run through the view of all its spectra
+        let error = group.normalize_seq().unwrap_err();
+        assert!(error.to_string().starts_with("spectrum 4"));
+        assert!(group.normalize().is_err());
+
+        let mut empty = XASGroup::new();
+        let summary = empty
+            .included_view_mut()
+            .process_all(&ProcessingParameters::new());
+        assert!(summary.outcomes.is_empty());
+
+        Ok(())
+    }
+}
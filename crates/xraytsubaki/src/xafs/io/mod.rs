@@ -2,16 +2,54 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+#[cfg(feature = "hdf5")]
+pub mod nexus;
+pub mod scanset;
+pub mod xafs_ascii;
+pub mod xafs_athena;
 pub mod xafs_bson;
+pub mod xafs_bytes;
+pub mod xafs_columns;
+#[cfg(feature = "hdf5")]
+pub mod xafs_hdf5;
 pub mod xafs_json;
+pub mod xafs_schema;
 pub mod xasdatatype;
 
+use crate::xafs::electronyield::ElectronYieldOptions;
+use crate::xafs::xasgroup::XASGroup;
 use crate::xafs::xasspectrum::XASSpectrum;
+use crate::xafs::XAFSError;
 use data_reader::reader::{load_txt_f64, Delimiter, ReaderParams};
+use ndarray::Array1;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
+#[cfg(feature = "hdf5")]
+pub use nexus::{load_nexus, write_nexus};
+pub use xafs_athena::{load_athena_project, read_athena_project, write_athena_project};
+pub use xafs_bytes::{
+    decompress, detect_format, load_group_from_bytes, load_group_from_reader,
+    load_spectrum_from_bytes, load_spectrum_from_reader, FileFormat,
+};
+pub use xafs_columns::{load_ascii, ColumnMapping};
+
+/// Load a QAS file as a transmission spectrum.
+///
+/// gzip (and bzip2 with the "bzip2" feature) compressed files are decompressed transparently.
+/// Irregular steps of the energy column (see find_energy_step_report) are added to the notes.
 #[allow(non_snake_case)]
 pub fn load_spectrum_QAS_trans(path: &String) -> Result<XASSpectrum, Box<dyn Error>> {
+    let mut header = [0u8; 3];
+    let n = std::fs::File::open(path)?.read(&mut header)?;
+    if xafs_bytes::is_compressed(&header[..n]) {
+        return xafs_bytes::load_spectrum_QAS_trans_from_bytes(&std::fs::read(path)?);
+    }
+
     let params = ReaderParams {
         comments: Some(b'#'),
         delimiter: Delimiter::WhiteSpace,
@@ -19,24 +57,199 @@ pub fn load_spectrum_QAS_trans(path: &String) -> Result<XASSpectrum, Box<dyn Err
     };
 
     let data = load_txt_f64(path, &params)?;
+
+    if data.get_num_fields() < 5 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
     let energy = data.get_col(0);
     let i0 = data.get_col(1);
     let it = data.get_col(2);
     let ir = data.get_col(3);
     let iff = data.get_col(4);
 
+    spectrum_from_QAS_trans(energy, &i0, &it)
+}
+
+#[allow(non_snake_case)]
+fn spectrum_from_QAS_trans(
+    energy: Vec<f64>,
+    i0: &[f64],
+    it: &[f64],
+) -> Result<XASSpectrum, Box<dyn Error>> {
+    let mu = i0
+        .iter()
+        .zip(it)
+        .map(|(i0, it)| (i0 / it).ln())
+        .collect::<Vec<_>>();
+    let channels = BTreeMap::from([
+        ("i0".to_string(), Array1::from_vec(i0.to_vec())),
+        ("it".to_string(), Array1::from_vec(it.to_vec())),
+    ]);
+
     let mut xafs_group = XASSpectrum::new();
-    xafs_group.set_spectrum(
-        energy,
-        i0.iter()
-            .zip(it)
-            .map(|(i0, it)| (i0 / it).ln())
-            .collect::<Vec<_>>(),
-    );
+    note_energy_steps(&mut xafs_group, &energy);
+    xafs_group.set_spectrum_channels(energy, mu, channels)?;
 
     Ok(xafs_group)
 }
 
+// Add the irregular steps of the energy column, in the order of the file, to the notes of the
+// spectrum. Negative steps are lost once the spectrum sorts its energy.
+pub(crate) fn note_energy_steps(spectrum: &mut XASSpectrum, energy: &[f64]) {
+    let report = spectrum
+        .processing_options
+        .find_energy_step_report(energy.to_vec());
+    spectrum.notes.extend(report.warnings());
+}
+
+/// Load a total electron yield spectrum from a column file with load_ascii.
+///
+/// The energy column and the names of the channels are taken from `mapping`; its mu is
+/// replaced by the one calculated from the channels with XASSpectrum::normalize_electron_yield,
+/// whose saturation warnings end up in the notes of the spectrum next to the irregular energy
+/// steps.
+///
+/// # Example
+///
+/// ```no_run
+/// use xraytsubaki::xafs::electronyield::ElectronYieldOptions;
+/// use xraytsubaki::xafs::io::{self, ColumnMapping};
+///
+/// let mut mapping = ColumnMapping::default();
+/// mapping.set_labels(&["energy", "i0", "tey"]);
+/// let spectrum =
+///     io::load_spectrum_electron_yield("Fe_L.dat", &mapping, &ElectronYieldOptions::new())
+///         .unwrap();
+/// ```
+pub fn load_spectrum_electron_yield<P: AsRef<Path>>(
+    path: P,
+    mapping: &ColumnMapping,
+    options: &ElectronYieldOptions,
+) -> Result<XASSpectrum, Box<dyn Error>> {
+    // mu is the signal until normalize_electron_yield, so that no other column is needed
+    let mapping = ColumnMapping {
+        mu_expression: Some(format!("`{}`", options.signal_channel())),
+        ..mapping.clone()
+    };
+
+    let mut spectrum = load_ascii(path, &mapping)?;
+    spectrum.normalize_electron_yield(options)?;
+
+    Ok(spectrum)
+}
+
+/// A file that could not be loaded by load_directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+/// Load all files in a directory whose name matches `pattern` in parallel.
+///
+/// `pattern` supports the wildcards `*` and `?`. Files are loaded with `loader` and returned
+/// in the order of their file names. Files that fail to load do not abort the whole load;
+/// they are reported in the returned list of LoadError instead.
+/// Spectra without a name are named after their file.
+///
+/// # Example
+///
+/// ```no_run
+/// use xraytsubaki::xafs::io;
+///
+/// let (group, errors) = io::load_directory("data", "*.dat", io::load_spectrum_QAS_trans).unwrap();
+/// for error in errors {
+///     println!("{}", error);
+/// }
+/// ```
+pub fn load_directory<P, F>(
+    path: P,
+    pattern: &str,
+    loader: F,
+) -> Result<(XASGroup, Vec<LoadError>), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: Fn(&String) -> Result<XASSpectrum, Box<dyn Error>> + Sync,
+{
+    let mut files = std::fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| wildcard_match(pattern, name))
+        })
+        .collect::<Vec<PathBuf>>();
+
+    files.sort();
+
+    let results = files
+        .par_iter()
+        .map(|path| {
+            let result = loader(&path.to_string_lossy().to_string())
+                .map_err(|error| error.to_string())
+                .map(|mut spectrum| {
+                    if spectrum.name.is_none() {
+                        if let Some(name) = path.file_name() {
+                            spectrum.set_name(name.to_string_lossy());
+                        }
+                    }
+                    spectrum
+                });
+            (path.clone(), result)
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = XASGroup::new();
+    let mut errors = Vec::new();
+
+    for (path, result) in results {
+        match result {
+            Ok(spectrum) => {
+                group.add_spectrum(spectrum);
+            }
+            Err(message) => errors.push(LoadError { path, message }),
+        }
+    }
+
+    Ok((group, errors))
+}
+
+/// Glob-like matching of a file name supporting `*` and `?`.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -48,4 +261,55 @@ mod tests {
         let result = load_spectrum_QAS_trans(&path).unwrap();
         println!("{:?}", result);
     }
+
+    #[test]
+    fn test_load_spectrum_electron_yield() {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let options = ElectronYieldOptions::new();
+        let mut mapping = ColumnMapping::default();
+        mapping.set_labels(&[
+            "energy", "i0", "tey", "ir", "iff", "aux1", "aux2", "aux3", "aux4",
+        ]);
+        let spectrum = load_spectrum_electron_yield(&path, &mapping, &options).unwrap();
+        let reference = load_spectrum_QAS_trans(&path).unwrap();
+
+        let expected = reference.get_channel("it").unwrap() / reference.get_channel("i0").unwrap();
+        assert_eq!(spectrum.raw_mu.unwrap(), expected);
+        assert_eq!(spectrum.channels.len(), 8);
+
+        // Columns named by the header of the file, which has no "tey"
+        assert!(load_spectrum_electron_yield(&path, &ColumnMapping::default(), &options).is_err());
+        let mut options = ElectronYieldOptions::new();
+        options.set_signal_channel("it");
+        let spectrum =
+            load_spectrum_electron_yield(&path, &ColumnMapping::default(), &options).unwrap();
+        assert_eq!(spectrum.raw_mu.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.dat", "Ru_QAS.dat"));
+        assert!(wildcard_match("Ru_???.dat", "Ru_QAS.dat"));
+        assert!(wildcard_match("*QAS*", "Ru_QAS_athena.prj"));
+        assert!(!wildcard_match("*.dat", "Ru_QAS.dat.gz"));
+        assert!(!wildcard_match("Ru_?.dat", "Ru_QAS.dat"));
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let path = String::from(TOP_DIR) + "/tests/testfiles";
+
+        let (group, errors) = load_directory(&path, "Ru_QAS.dat", load_spectrum_QAS_trans).unwrap();
+        assert_eq!(group.len(), 1);
+        assert!(errors.is_empty());
+        assert_eq!(group.spectra[0].name, Some("Ru_QAS.dat".to_string()));
+
+        // The gzipped Athena project cannot be read as a QAS file and is reported as an error
+        let (group, errors) = load_directory(&path, "Ru_QAS*", load_spectrum_QAS_trans).unwrap();
+        assert!(errors.iter().any(|e| e.path.ends_with("Ru_QAS_athena.prj")));
+        assert!(group
+            .spectra
+            .iter()
+            .any(|s| s.name == Some("Ru_QAS.dat".to_string())));
+    }
 }
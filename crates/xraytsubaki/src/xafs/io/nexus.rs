@@ -0,0 +1,418 @@
+//! NeXus files following the NXxas application definition (feature "hdf5").
+//!
+//! Each spectrum is an NXentry:
+//!
+//! ```text
+//! /entry_0000                     NXentry, attrs: metadata (JSON of the unmapped metadata)
+//!     definition = "NXxas", title, mode, element, edge, start_time
+//!     instrument                  NXinstrument, name
+//!         source                  NXsource, name
+//!         monochromator           NXmonochromator, energy (eV)
+//!             crystal             NXcrystal, type, d_spacing
+//!         incoming_beam           NXdetector, data (attr channel)
+//!         absorbed_beam           NXdetector, data (attr channel)
+//!         <channel>               NXdetector, data
+//!     sample                      NXsample, name
+//!     data                        NXdata, energy, absorbed_beam (links), mu
+//! ```
+//!
+//! The fields of METADATA_FIELDS are written from and read into the metadata of the spectrum.
+//! i0 is written as the incoming beam and the first of ABSORBED_CHANNELS present as the
+//! absorbed beam, and every other channel as a detector of its own. Only raw data are
+//! stored; processed arrays are exported with XASGroup::to_hdf5.
+//!
+//! Files from other programs are read without the mu field: mu is then ln(incoming /
+//! absorbed) in transmission mode and absorbed / incoming otherwise (fluorescence, electron
+//! yield). Energies in keV are converted to eV.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use hdf5::types::{VarLenAscii, VarLenUnicode};
+use hdf5::{Dataset, Group, Location};
+use ndarray::{arr0, Array1};
+
+use crate::xafs::xasgroup::XASGroup;
+use crate::xafs::xasspectrum::XASSpectrum;
+
+/// Metadata keys and the fields of the NXentry they are stored in
+pub const METADATA_FIELDS: [(&str, &str); 9] = [
+    ("mode", "mode"),
+    ("element", "element"),
+    ("edge", "edge"),
+    ("start_time", "start_time"),
+    ("beamline", "instrument/name"),
+    ("facility", "instrument/source/name"),
+    ("monochromator", "instrument/monochromator/crystal/type"),
+    ("d_spacing", "instrument/monochromator/crystal/d_spacing"),
+    ("sample", "sample/name"),
+];
+
+/// Channels written as the absorbed beam, in order of preference
+pub const ABSORBED_CHANNELS: [&str; 4] = ["it", "iff", "tey", "signal"];
+
+const INCOMING_CHANNEL: &str = "i0";
+
+const NX_GROUPS: [(&str, &str); 6] = [
+    ("instrument", "NXinstrument"),
+    ("instrument/source", "NXsource"),
+    ("instrument/monochromator", "NXmonochromator"),
+    ("instrument/monochromator/crystal", "NXcrystal"),
+    ("sample", "NXsample"),
+    ("data", "NXdata"),
+];
+
+/// Load every NXentry of a NeXus file as a spectrum.
+pub fn load_nexus<P: AsRef<Path>>(path: P) -> Result<XASGroup, Box<dyn Error>> {
+    let file = hdf5::File::open(path)?;
+
+    let mut group = XASGroup::new();
+    for name in file.member_names()? {
+        let Ok(entry) = file.group(&name) else {
+            continue;
+        };
+        let definition = read_string(&entry, "definition");
+        if read_string_attr(&entry, "NX_class").as_deref() == Some("NXentry")
+            || definition.as_deref() == Some("NXxas")
+        {
+            let spectrum = read_entry(&entry).map_err(|e| format!("{}: {}", name, e))?;
+            group.add_spectrum(spectrum);
+        }
+    }
+
+    if group.is_empty() {
+        return Err("No NXentry in the file".into());
+    }
+
+    Ok(group)
+}
+
+/// Write every spectrum of the group as an NXxas entry of a NeXus file.
+pub fn write_nexus<P: AsRef<Path>>(group: &XASGroup, path: P) -> Result<(), Box<dyn Error>> {
+    let file = hdf5::File::create(path)?;
+    write_string_attr(&file, "NX_class", "NXroot")?;
+    write_string_attr(&file, "default", "entry_0000")?;
+
+    for (i, spectrum) in group.spectra.iter().enumerate() {
+        let entry = file.create_group(&format!("entry_{:04}", i))?;
+        write_entry(&entry, spectrum)?;
+    }
+
+    Ok(())
+}
+
+fn write_entry(entry: &Group, spectrum: &XASSpectrum) -> Result<(), Box<dyn Error>> {
+    let (energy, mu) = match (spectrum.raw_energy.as_ref(), spectrum.raw_mu.as_ref()) {
+        (Some(energy), Some(mu)) => (energy, mu),
+        _ => return Err("Spectrum without data".into()),
+    };
+
+    write_string_attr(entry, "NX_class", "NXentry")?;
+    write_string_attr(entry, "default", "data")?;
+    write_string(entry, "definition", "NXxas")?;
+    if let Some(name) = spectrum.name.as_ref() {
+        write_string(entry, "title", name)?;
+    }
+    for (path, class) in NX_GROUPS.iter() {
+        write_string_attr(&entry.create_group(path)?, "NX_class", class)?;
+    }
+
+    let absorbed = ABSORBED_CHANNELS
+        .iter()
+        .find(|channel| spectrum.channels.contains_key(**channel));
+    let mode = match spectrum.get_metadata("mode").and_then(|m| m.as_str()) {
+        Some(mode) => mode,
+        None if absorbed == Some(&"iff") => "Fluorescence",
+        None if absorbed == Some(&"tey") => "Total electron yield",
+        None => "Transmission",
+    };
+    write_string(entry, "mode", mode)?;
+
+    let mut unmapped = BTreeMap::new();
+    for (key, value) in spectrum.metadata.iter() {
+        match METADATA_FIELDS.iter().find(|(k, _)| k == key) {
+            Some((_, field)) if *key != "mode" => write_value(entry, field, value)?,
+            Some(_) => {}
+            None => {
+                unmapped.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if !unmapped.is_empty() {
+        write_string_attr(entry, "metadata", &serde_json::to_string(&unmapped)?)?;
+    }
+
+    let instrument = entry.group("instrument")?;
+    let energy_dataset = instrument
+        .group("monochromator")?
+        .new_dataset_builder()
+        .with_data(energy)
+        .create("energy")?;
+    write_string_attr(&energy_dataset, "units", "eV")?;
+
+    for (name, values) in spectrum.channels.iter() {
+        let detector = match name.as_str() {
+            INCOMING_CHANNEL => "incoming_beam",
+            _ if absorbed == Some(&name.as_str()) => "absorbed_beam",
+            _ => name.as_str(),
+        };
+        let group = instrument.create_group(detector)?;
+        write_string_attr(&group, "NX_class", "NXdetector")?;
+        write_string_attr(&group, "channel", name)?;
+        group
+            .new_dataset_builder()
+            .with_data(values)
+            .create("data")?;
+    }
+
+    let data = entry.group("data")?;
+    data.link_soft(
+        &format!("{}/instrument/monochromator/energy", entry.name()),
+        "energy",
+    )?;
+    data.new_dataset_builder().with_data(mu).create("mu")?;
+    let signal = match absorbed {
+        Some(_) => {
+            let target = format!("{}/instrument/absorbed_beam/data", entry.name());
+            data.link_soft(&target, "absorbed_beam")?;
+            "absorbed_beam"
+        }
+        None => "mu",
+    };
+    write_string_attr(&data, "signal", signal)?;
+    write_string_attr(&data, "axes", "energy")?;
+
+    Ok(())
+}
+
+fn read_entry(entry: &Group) -> Result<XASSpectrum, Box<dyn Error>> {
+    let instrument = entry.group("instrument").ok();
+
+    let energy_dataset = instrument
+        .as_ref()
+        .and_then(|i| i.dataset("monochromator/energy").ok())
+        .or_else(|| entry.dataset("data/energy").ok())
+        .ok_or("No monochromator energy")?;
+    let mut energy = energy_dataset.read_1d::<f64>()?;
+    if read_string_attr(&energy_dataset, "units").as_deref() == Some("keV") {
+        energy *= 1000.0;
+    }
+
+    let mode = read_string(entry, "mode").unwrap_or_else(|| "Transmission".to_string());
+    let transmission = mode.to_lowercase().contains("transmission");
+
+    let mut channels = BTreeMap::new();
+    let (mut incoming, mut absorbed) = (None, None);
+    if let Some(instrument) = instrument.as_ref() {
+        for name in instrument.member_names()? {
+            let Ok(detector) = instrument.group(&name) else {
+                continue;
+            };
+            if read_string_attr(&detector, "NX_class").as_deref() != Some("NXdetector") {
+                continue;
+            }
+            let Ok(values) = detector.dataset("data").and_then(|d| d.read_1d::<f64>()) else {
+                continue;
+            };
+            let default_channel = match name.as_str() {
+                "incoming_beam" => INCOMING_CHANNEL,
+                "absorbed_beam" if transmission => "it",
+                "absorbed_beam" if mode.to_lowercase().contains("fluo") => "iff",
+                "absorbed_beam" => "signal",
+                _ => name.as_str(),
+            };
+            let channel = read_string_attr(&detector, "channel")
+                .unwrap_or_else(|| default_channel.to_string());
+            match name.as_str() {
+                "incoming_beam" => incoming = Some(values.clone()),
+                "absorbed_beam" => absorbed = Some(values.clone()),
+                _ => {}
+            }
+            channels.insert(channel, values);
+        }
+    }
+
+    let mu = match entry.dataset("data/mu") {
+        Ok(mu) => mu.read_1d::<f64>()?,
+        Err(_) => match (incoming, absorbed) {
+            (Some(i0), Some(signal)) if transmission => (i0 / signal).mapv(f64::ln),
+            (Some(i0), Some(signal)) => signal / i0,
+            _ => return Err("No mu and no incoming and absorbed beams".into()),
+        },
+    };
+
+    let mut spectrum = XASSpectrum::new();
+    spectrum.set_spectrum_channels(energy, mu, channels)?;
+    if let Some(title) = read_string(entry, "title") {
+        spectrum.set_name(title);
+    }
+
+    if let Some(json) = read_string_attr(entry, "metadata") {
+        let metadata: BTreeMap<String, serde_json::Value> = serde_json::from_str(&json)?;
+        spectrum.metadata.extend(metadata);
+    }
+    for (key, field) in METADATA_FIELDS.iter() {
+        if let Some(value) = read_value(entry, field) {
+            spectrum.set_metadata(*key, value);
+        }
+    }
+
+    Ok(spectrum)
+}
+
+// Numbers as scalar float datasets, strings as strings and anything else as JSON
+fn write_value(
+    entry: &Group,
+    field: &str,
+    value: &serde_json::Value,
+) -> Result<(), Box<dyn Error>> {
+    match value {
+        serde_json::Value::Number(number) => {
+            let number = number.as_f64().unwrap_or(f64::NAN);
+            entry
+                .new_dataset_builder()
+                .with_data(&arr0(number))
+                .create(field)?;
+        }
+        serde_json::Value::String(text) => write_string(entry, field, text)?,
+        _ => write_string(entry, field, &value.to_string())?,
+    }
+
+    Ok(())
+}
+
+fn read_value(entry: &Group, field: &str) -> Option<serde_json::Value> {
+    let dataset = entry.dataset(field).ok()?;
+    match dataset.read_scalar::<f64>() {
+        Ok(number) => Some(number.into()),
+        Err(_) => read_scalar_string(&dataset).map(|text| text.into()),
+    }
+}
+
+fn write_string(group: &Group, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let value = value.parse::<VarLenUnicode>()?;
+    group
+        .new_dataset_builder()
+        .with_data(&arr0(value))
+        .create(name)?;
+
+    Ok(())
+}
+
+fn read_string(group: &Group, name: &str) -> Option<String> {
+    read_scalar_string(&group.dataset(name).ok()?)
+}
+
+fn read_scalar_string(dataset: &Dataset) -> Option<String> {
+    match dataset.read_scalar::<VarLenUnicode>() {
+        Ok(value) => Some(value.to_string()),
+        Err(_) => Some(dataset.read_scalar::<VarLenAscii>().ok()?.to_string()),
+    }
+}
+
+fn write_string_attr(location: &Location, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let value = value.parse::<VarLenUnicode>()?;
+    location
+        .new_attr::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&value)?;
+
+    Ok(())
+}
+
+fn read_string_attr(location: &Location, name: &str) -> Option<String> {
+    let attr = location.attr(name).ok()?;
+    match attr.read_scalar::<VarLenUnicode>() {
+        Ok(value) => Some(value.to_string()),
+        Err(_) => Some(attr.read_scalar::<VarLenAscii>().ok()?.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_write_load_nexus() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum
+            .set_name("Ru foil")
+            .set_metadata("element", "Ru")
+            .set_metadata("edge", "K")
+            .set_metadata("d_spacing", 3.1356)
+            .set_metadata("temperature", 300.0);
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum.clone());
+
+        let save_path = std::env::temp_dir().join("xraytsubaki_test_nexus.h5");
+        write_nexus(&group, &save_path)?;
+
+        let file = hdf5::File::open(&save_path)?;
+        assert_eq!(
+            read_string(&file, "entry_0000/definition").unwrap(),
+            "NXxas"
+        );
+        assert_eq!(
+            read_string_attr(
+                &file.group("entry_0000/instrument/absorbed_beam")?,
+                "channel"
+            ),
+            Some("it".to_string())
+        );
+
+        let loaded = load_nexus(&save_path)?;
+        let reloaded = &loaded.spectra[0];
+        assert_eq!(reloaded.name, spectrum.name);
+        assert_eq!(reloaded.raw_energy, spectrum.raw_energy);
+        assert_eq!(reloaded.raw_mu, spectrum.raw_mu);
+        assert_eq!(reloaded.channels, spectrum.channels);
+        spectrum.set_metadata("mode", "Transmission");
+        assert_eq!(reloaded.metadata, spectrum.metadata);
+
+        std::fs::remove_file(save_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_nexus_without_mu() -> Result<(), Box<dyn Error>> {
+        let save_path = std::env::temp_dir().join("xraytsubaki_test_nexus_fluo.h5");
+        {
+            let file = hdf5::File::create(&save_path)?;
+            let entry = file.create_group("entry")?;
+            write_string_attr(&entry, "NX_class", "NXentry")?;
+            write_string(&entry, "mode", "Fluorescence")?;
+            let energy = entry
+                .create_group("instrument")?
+                .create_group("monochromator")?
+                .new_dataset_builder()
+                .with_data(&Array1::linspace(22.0, 22.2, 5))
+                .create("energy")?;
+            write_string_attr(&energy, "units", "keV")?;
+            for (name, value) in [("incoming_beam", 2.0), ("absorbed_beam", 1.0)] {
+                let detector = entry.group("instrument")?.create_group(name)?;
+                write_string_attr(&detector, "NX_class", "NXdetector")?;
+                detector
+                    .new_dataset_builder()
+                    .with_data(&Array1::from_elem(5, value))
+                    .create("data")?;
+            }
+        }
+
+        let spectrum = load_nexus(&save_path)?.spectra[0].clone();
+        assert_abs_diff_eq!(spectrum.raw_energy.unwrap()[4], 22200.0, epsilon = 1e-9);
+        assert_eq!(spectrum.raw_mu, Some(Array1::from_elem(5, 0.5)));
+        assert!(spectrum.get_channel("iff").is_some());
+        assert_eq!(spectrum.get_metadata("mode").unwrap(), "Fluorescence");
+
+        std::fs::remove_file(save_path)?;
+
+        Ok(())
+    }
+}
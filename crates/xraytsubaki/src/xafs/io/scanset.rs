@@ -0,0 +1,309 @@
+//! Scans split across several files.
+//!
+//! Some beamlines write one file per detector for each scan, e.g. `scan012_trans.dat`,
+//! `scan012_fluo.dat` and `scan012_ref.dat`. A ScanSet describes the files of one scan by
+//! filename patterns containing `{scan}`, and the columns of each file. Loading a scan
+//! reassembles the files into one XASSpectrum with every column attached as a channel, the
+//! columns of the other files being interpolated onto the energy grid of the first file.
+//!
+//! ```no_run
+//! use xraytsubaki::xafs::io::scanset::{MuDefinition, ScanFile, ScanSet};
+//!
+//! let mut scans = ScanSet::new(MuDefinition::transmission("i0", "it"));
+//! scans
+//!     .add_file(ScanFile::new("{scan}_trans.dat", &["energy", "i0", "it"]))
+//!     .add_file(ScanFile::new("{scan}_fluo.dat", &["energy", "iff"]));
+//!
+//! let spectrum = scans.load("data", "scan012")?;
+//! let (group, errors) = scans.load_all("data")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use ndarray::Array1;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::xafs_bytes::{decompress, parse_columns};
+use super::LoadError;
+use crate::xafs::mathutils::MathUtils;
+use crate::xafs::xasgroup::XASGroup;
+use crate::xafs::xasspectrum::XASSpectrum;
+
+/// Placeholder of the scan name in the filename patterns
+pub const SCAN_PLACEHOLDER: &str = "{scan}";
+
+type Channels = BTreeMap<String, Array1<f64>>;
+
+/// One file of a scan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanFile {
+    /// File name containing `{scan}`, e.g. "{scan}_fluo.dat"
+    pub pattern: String,
+    /// Label of each column, the first being the energy. Columns labelled "" are skipped.
+    pub columns: Vec<String>,
+}
+
+impl ScanFile {
+    pub fn new(pattern: &str, columns: &[&str]) -> ScanFile {
+        ScanFile {
+            pattern: pattern.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    pub fn file_name(&self, scan: &str) -> String {
+        self.pattern.replace(SCAN_PLACEHOLDER, scan)
+    }
+
+    /// Scan name of `file_name` if it matches the pattern
+    pub fn scan_name(&self, file_name: &str) -> Option<String> {
+        let (prefix, suffix) = self.pattern.split_once(SCAN_PLACEHOLDER)?;
+        let scan = file_name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        (!scan.is_empty()).then(|| scan.to_string())
+    }
+}
+
+/// How mu is calculated from the channels: ln(numerator / denominator) if `log`, otherwise
+/// numerator / denominator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MuDefinition {
+    pub numerator: String,
+    pub denominator: String,
+    pub log: bool,
+}
+
+impl MuDefinition {
+    /// mu = ln(i0 / it)
+    pub fn transmission(i0: &str, it: &str) -> MuDefinition {
+        MuDefinition {
+            numerator: i0.to_string(),
+            denominator: it.to_string(),
+            log: true,
+        }
+    }
+
+    /// mu = iff / i0
+    pub fn fluorescence(iff: &str, i0: &str) -> MuDefinition {
+        MuDefinition {
+            numerator: iff.to_string(),
+            denominator: i0.to_string(),
+            log: false,
+        }
+    }
+}
+
+/// Files making up each scan and the definition of mu
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanSet {
+    /// The first file gives the energy grid of the spectrum
+    pub files: Vec<ScanFile>,
+    pub mu: MuDefinition,
+}
+
+impl ScanSet {
+    pub fn new(mu: MuDefinition) -> ScanSet {
+        ScanSet {
+            files: Vec::new(),
+            mu,
+        }
+    }
+
+    pub fn add_file(&mut self, file: ScanFile) -> &mut Self {
+        self.files.push(file);
+        self
+    }
+
+    /// Load the scan `scan` from the files in `dir`. The spectrum is named after the scan.
+    pub fn load<P: AsRef<Path>>(&self, dir: P, scan: &str) -> Result<XASSpectrum, Box<dyn Error>> {
+        let first = self.files.first().ok_or("The scan set has no files")?;
+        let mut energy = Array1::zeros(0);
+        let mut channels = BTreeMap::new();
+
+        for (i, file) in self.files.iter().enumerate() {
+            let path = dir.as_ref().join(file.file_name(scan));
+            let (file_energy, file_channels) =
+                read_file(&path, file).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+            if i == 0 {
+                energy = file_energy;
+                channels = file_channels;
+                continue;
+            }
+
+            for (label, values) in file_channels {
+                if channels.contains_key(&label) {
+                    return Err(format!(
+                        "Column {} of {} is also in {}",
+                        label, file.pattern, first.pattern
+                    )
+                    .into());
+                }
+                let values = energy.interpolate(&file_energy.to_vec(), &values.to_vec())?;
+                channels.insert(label, values);
+            }
+        }
+
+        let channel = |name: &String| {
+            channels
+                .get(name)
+                .ok_or_else(|| format!("No column {} in the scan set", name))
+        };
+        let numerator = channel(&self.mu.numerator)?;
+        let denominator = channel(&self.mu.denominator)?;
+        let mu = if self.mu.log {
+            (numerator / denominator).mapv(f64::ln)
+        } else {
+            numerator / denominator
+        };
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum_channels(energy, mu, channels)?
+            .set_name(scan);
+
+        Ok(spectrum)
+    }
+
+    /// Scan names of the files in `dir` matching the pattern of the first file, sorted
+    pub fn scans<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<String>, Box<dyn Error>> {
+        let first = self.files.first().ok_or("The scan set has no files")?;
+
+        let mut scans = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| first.scan_name(&entry.file_name().to_string_lossy()))
+            .collect::<Vec<String>>();
+        scans.sort();
+
+        Ok(scans)
+    }
+
+    /// Load all scans of `dir` in parallel. Scans that fail to load, e.g. because one of their
+    /// files is missing, are reported in the list of LoadError with the path of the first file.
+    pub fn load_all<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<(XASGroup, Vec<LoadError>), Box<dyn Error>> {
+        let dir = dir.as_ref();
+        let results = self
+            .scans(dir)?
+            .par_iter()
+            .map(|scan| {
+                let path = dir.join(self.files[0].file_name(scan));
+                (path, self.load(dir, scan).map_err(|e| e.to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        let mut group = XASGroup::new();
+        let mut errors = Vec::new();
+        for (path, result) in results {
+            match result {
+                Ok(spectrum) => {
+                    group.add_spectrum(spectrum);
+                }
+                Err(message) => errors.push(LoadError { path, message }),
+            }
+        }
+
+        Ok((group, errors))
+    }
+}
+
+// Energy and labelled columns of one file, sorted by energy
+fn read_file(path: &Path, file: &ScanFile) -> Result<(Array1<f64>, Channels), Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let columns = parse_columns(std::str::from_utf8(&decompress(&bytes)?)?, '#')?;
+
+    if columns.len() < file.columns.len() {
+        return Err(format!("{} columns, {} expected", columns.len(), file.columns.len()).into());
+    }
+
+    let energy = Array1::from_vec(columns[0].clone());
+    let order = energy.argsort();
+    let energy = energy.select(ndarray::Axis(0), &order);
+
+    let channels = file
+        .columns
+        .iter()
+        .zip(columns)
+        .skip(1)
+        .filter(|(label, _)| !label.is_empty())
+        .map(|(label, values)| {
+            let values = Array1::from_vec(values).select(ndarray::Axis(0), &order);
+            (label.clone(), values)
+        })
+        .collect();
+
+    Ok((energy, channels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::load_spectrum_QAS_trans;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+    use std::fmt::Write as _;
+
+    #[test]
+    fn test_scan_set() -> Result<(), Box<dyn Error>> {
+        let text = std::fs::read_to_string(String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat")?;
+        let columns = parse_columns(&text, '#')?;
+
+        // Split the QAS file into a transmission and a fluorescence file, the latter with
+        // every other point in reverse order
+        let dir = std::env::temp_dir().join("xraytsubaki_test_scanset");
+        std::fs::create_dir_all(&dir)?;
+        let (mut trans, mut fluo) = (String::new(), String::new());
+        for ((energy, i0), it) in columns[0].iter().zip(&columns[1]).zip(&columns[2]) {
+            writeln!(trans, "{} {} {}", energy, i0, it)?;
+        }
+        for i in (0..columns[0].len()).rev().step_by(2) {
+            writeln!(fluo, "{} 0 {}", columns[0][i], columns[4][i])?;
+        }
+        std::fs::write(dir.join("scan1_trans.dat"), &trans)?;
+        std::fs::write(dir.join("scan1_fluo.dat"), &fluo)?;
+        std::fs::write(dir.join("scan2_trans.dat"), &trans)?;
+
+        let mut scans = ScanSet::new(MuDefinition::transmission("i0", "it"));
+        scans
+            .add_file(ScanFile::new("{scan}_trans.dat", &["energy", "i0", "it"]))
+            .add_file(ScanFile::new("{scan}_fluo.dat", &["energy", "", "iff"]));
+
+        assert_eq!(scans.scans(&dir)?, vec!["scan1", "scan2"]);
+
+        let spectrum = scans.load(&dir, "scan1")?;
+        let reference =
+            load_spectrum_QAS_trans(&(String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat"))?;
+        assert_eq!(spectrum.name.as_deref(), Some("scan1"));
+        assert_eq!(spectrum.raw_mu, reference.raw_mu);
+        assert_eq!(
+            spectrum.channels.keys().collect::<Vec<_>>(),
+            vec!["i0", "iff", "it"]
+        );
+        let iff = spectrum.get_channel("iff").unwrap();
+        assert_abs_diff_eq!(iff[2], columns[4][2], epsilon = 1e-9 * columns[4][2].abs());
+
+        let (group, errors) = scans.load_all(&dir)?;
+        assert_eq!(group.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].path.ends_with("scan2_trans.dat"));
+
+        let mut fluorescence = scans.clone();
+        fluorescence.mu = MuDefinition::fluorescence("iff", "i0");
+        let spectrum = fluorescence.load(&dir, "scan1")?;
+        assert_abs_diff_eq!(
+            spectrum.raw_mu.unwrap()[2],
+            columns[4][2] / columns[1][2],
+            epsilon = 1e-9
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
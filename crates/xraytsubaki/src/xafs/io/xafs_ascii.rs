@@ -0,0 +1,447 @@
+//! Plain-text exporters following the Ifeffit/FEFFIT data file conventions.
+//!
+//! The files consist of `#` comment lines followed by whitespace separated columns, so that
+//! chi(k) and chi(R) extracted here can be read by FEFFIT, Ifeffit and Larch. Programs with
+//! stricter readers can be served by AsciiFormat, which selects the columns and their order,
+//! the delimiter, the number format and how much of the processing history goes in the header.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use ndarray::{ArrayBase, Ix1, OwnedRepr};
+use serde::{Deserialize, Serialize};
+use version::version;
+
+use crate::xafs::xasspectrum::XASSpectrum;
+use crate::xafs::XAFSError;
+
+/// Amount of information written before the data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeaderVerbosity {
+    /// Data lines only
+    None,
+    /// The line of column labels only
+    Minimal,
+    /// Program version, name, e0 and the parameters of the exported transform
+    #[default]
+    Standard,
+    /// Standard, followed by the normalization, background and FT parameters, the metadata,
+    /// tags and notes of the spectrum
+    Full,
+}
+
+/// Layout of the exported columns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AsciiFormat {
+    /// Labels of the columns to write, in order, e.g. ["k", "chi"]. All columns if None.
+    pub columns: Option<Vec<String>>,
+    /// Column delimiter. Default = " ".
+    pub delimiter: Option<String>,
+    /// Digits after the decimal point. Default = 8.
+    pub precision: Option<usize>,
+    /// Scientific (1.23456789e0) or fixed (1.23456789) notation. Default = scientific.
+    pub scientific: Option<bool>,
+    pub header: Option<HeaderVerbosity>,
+    /// Prefix of the header lines. Default = "#".
+    pub comment: Option<String>,
+}
+
+impl Default for AsciiFormat {
+    fn default() -> Self {
+        AsciiFormat {
+            columns: None,
+            delimiter: Some(" ".to_string()),
+            precision: Some(8),
+            scientific: Some(true),
+            header: Some(HeaderVerbosity::Standard),
+            comment: Some("#".to_string()),
+        }
+    }
+}
+
+impl AsciiFormat {
+    pub fn new() -> AsciiFormat {
+        AsciiFormat::default()
+    }
+
+    pub fn set_columns(&mut self, columns: &[&str]) -> &mut Self {
+        self.columns = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: &str) -> &mut Self {
+        self.delimiter = Some(delimiter.to_string());
+        self
+    }
+
+    pub fn set_precision(&mut self, precision: usize, scientific: bool) -> &mut Self {
+        self.precision = Some(precision);
+        self.scientific = Some(scientific);
+        self
+    }
+
+    pub fn set_header(&mut self, header: HeaderVerbosity) -> &mut Self {
+        self.header = Some(header);
+        self
+    }
+
+    pub fn set_comment(&mut self, comment: &str) -> &mut Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        let default = AsciiFormat::default();
+        let precision = self.precision.or(default.precision).unwrap();
+        let scientific = self.scientific.or(default.scientific).unwrap();
+        let padded = self
+            .delimiter
+            .as_deref()
+            .is_none_or(|d| d.chars().all(char::is_whitespace));
+
+        match (scientific, padded) {
+            (true, true) => format!("{:>width$.precision$e}", value, width = precision + 8),
+            (true, false) => format!("{:.precision$e}", value),
+            (false, true) => format!("{:>width$.precision$}", value, width = precision + 8),
+            (false, false) => format!("{:.precision$}", value),
+        }
+    }
+}
+
+pub trait XASAscii {
+    fn write_chik(&self, filename: &str) -> Result<&Self, Box<dyn Error>>;
+
+    fn write_chir(&self, filename: &str) -> Result<&Self, Box<dyn Error>>;
+
+    fn write_chik_with(
+        &self,
+        filename: &str,
+        format: &AsciiFormat,
+    ) -> Result<&Self, Box<dyn Error>>;
+
+    fn write_chir_with(
+        &self,
+        filename: &str,
+        format: &AsciiFormat,
+    ) -> Result<&Self, Box<dyn Error>>;
+}
+
+impl XASAscii for XASSpectrum {
+    /// Write chi(k) as `k chi` columns (.chik).
+    fn write_chik(&self, filename: &str) -> Result<&Self, Box<dyn Error>> {
+        self.write_chik_with(filename, &AsciiFormat::default())
+    }
+
+    /// Write chi(R) as `r chir_re chir_im chir_mag chir_pha` columns (.chir).
+    fn write_chir(&self, filename: &str) -> Result<&Self, Box<dyn Error>> {
+        self.write_chir_with(filename, &AsciiFormat::default())
+    }
+
+    /// write_chik with the columns and layout of `format`.
+    fn write_chik_with(
+        &self,
+        filename: &str,
+        format: &AsciiFormat,
+    ) -> Result<&Self, Box<dyn Error>> {
+        let k = self.get_k().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+        let chi = self.get_chi().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+        let mut header = header_lines(self);
+        if let Some(kweight) = self.get_kweight() {
+            header.push(format!("kweight = {}", kweight));
+        }
+
+        write_columns(filename, self, &header, &["k", "chi"], &[k, chi], format)?;
+
+        Ok(self)
+    }
+
+    /// write_chir with the columns and layout of `format`.
+    fn write_chir_with(
+        &self,
+        filename: &str,
+        format: &AsciiFormat,
+    ) -> Result<&Self, Box<dyn Error>> {
+        let r = self
+            .get_r()
+            .ok_or(XAFSError::NotEnoughDataForXFTR)?
+            .to_owned();
+        let chir_re = self
+            .get_chir_real()
+            .ok_or(XAFSError::NotEnoughDataForXFTR)?;
+        let chir_im = self
+            .get_chir_imag()
+            .ok_or(XAFSError::NotEnoughDataForXFTR)?;
+        let chir_mag = self
+            .get_chir_mag()
+            .ok_or(XAFSError::NotEnoughDataForXFTR)?
+            .to_owned();
+        let chir_pha = ndarray::Zip::from(&chir_im)
+            .and(&chir_re)
+            .map_collect(|im, re| im.atan2(*re));
+
+        let mut header = header_lines(self);
+        if let Some(xftf) = self.xftf.as_ref() {
+            header.push(format!(
+                "kmin = {}, kmax = {}, dk = {}, kweight = {}, window = {:?}",
+                xftf.kmin.unwrap_or_default(),
+                xftf.kmax.unwrap_or_default(),
+                xftf.dk.unwrap_or_default(),
+                xftf.kweight.unwrap_or_default(),
+                xftf.window.unwrap_or_default()
+            ));
+        }
+
+        write_columns(
+            filename,
+            self,
+            &header,
+            &["r", "chir_re", "chir_im", "chir_mag", "chir_pha"],
+            &[r, chir_re, chir_im, chir_mag, chir_pha],
+            format,
+        )?;
+
+        Ok(self)
+    }
+}
+
+fn header_lines(spectrum: &XASSpectrum) -> Vec<String> {
+    let mut header = vec![format!("xraytsubaki {}", version!())];
+
+    if let Some(name) = spectrum.name.as_ref() {
+        header.push(format!("name = {}", name));
+    }
+
+    if let Some(e0) = spectrum.get_e0() {
+        header.push(format!("e0 = {}", e0));
+    }
+
+    header
+}
+
+// Processing parameters (as JSON), metadata, tags and notes of the spectrum
+fn provenance_lines(spectrum: &XASSpectrum) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut lines = Vec::new();
+
+    if let Some(normalization) = spectrum.normalization.as_ref() {
+        let parameters = serde_json::to_string(&normalization.copy_parameters())?;
+        lines.push(format!("normalization = {}", parameters));
+    }
+    if let Some(background) = spectrum.background.as_ref() {
+        let parameters = serde_json::to_string(&background.copy_parameters())?;
+        lines.push(format!("background = {}", parameters));
+    }
+    if let Some(xftf) = spectrum.xftf.as_ref() {
+        lines.push(format!(
+            "xftf = {}",
+            serde_json::to_string(&xftf.copy_parameters())?
+        ));
+    }
+    if let Some(xftr) = spectrum.xftr.as_ref() {
+        lines.push(format!(
+            "xftr = {}",
+            serde_json::to_string(&xftr.copy_parameters())?
+        ));
+    }
+    for (key, value) in spectrum.metadata.iter() {
+        lines.push(format!("metadata.{} = {}", key, value));
+    }
+    if !spectrum.tags.is_empty() {
+        lines.push(format!("tags = {}", spectrum.tags.join(", ")));
+    }
+    for note in spectrum.notes.iter() {
+        lines.push(format!("note = {}", note));
+    }
+
+    Ok(lines)
+}
+
+// Write a header line, prefixing every line of multi-line values (notes, names, ...) with
+// the comment so that the file can still be read as columns.
+fn write_comment<W: Write>(writer: &mut W, comment: &str, line: &str) -> std::io::Result<()> {
+    for part in line.split('\n') {
+        writeln!(writer, "{} {}", comment, part.trim_end_matches('\r'))?;
+    }
+
+    Ok(())
+}
+
+fn write_columns(
+    filename: &str,
+    spectrum: &XASSpectrum,
+    header: &[String],
+    labels: &[&str],
+    columns: &[ArrayBase<OwnedRepr<f64>, Ix1>],
+    format: &AsciiFormat,
+) -> Result<(), Box<dyn Error>> {
+    let default = AsciiFormat::default();
+    let delimiter = format
+        .delimiter
+        .as_ref()
+        .or(default.delimiter.as_ref())
+        .unwrap();
+    let comment = format
+        .comment
+        .as_ref()
+        .or(default.comment.as_ref())
+        .unwrap();
+    let verbosity = format.header.or(default.header).unwrap();
+
+    let selected = match format.columns.as_ref() {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                labels
+                    .iter()
+                    .position(|label| label == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown column {}, the columns are {}",
+                            name,
+                            labels.join(", ")
+                        )
+                    })
+            })
+            .collect::<Result<Vec<usize>, String>>()?,
+        None => (0..labels.len()).collect(),
+    };
+
+    let npts = columns.iter().map(|c| c.len()).min().unwrap_or(0);
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+
+    if matches!(verbosity, HeaderVerbosity::Standard | HeaderVerbosity::Full) {
+        for line in header {
+            write_comment(&mut writer, comment, line)?;
+        }
+        if verbosity == HeaderVerbosity::Full {
+            for line in provenance_lines(spectrum)? {
+                write_comment(&mut writer, comment, &line)?;
+            }
+        }
+        writeln!(writer, "{}------------------------", comment)?;
+    }
+    if verbosity != HeaderVerbosity::None {
+        let selected_labels = selected.iter().map(|&i| labels[i]).collect::<Vec<&str>>();
+        writeln!(writer, "{}  {}", comment, selected_labels.join("  "))?;
+    }
+
+    let selected_columns = selected.iter().map(|&c| &columns[c]).collect::<Vec<_>>();
+    for i in 0..npts {
+        let row = selected_columns
+            .iter()
+            .map(|c| format.format_value(c[i]))
+            .collect::<Vec<_>>()
+            .join(delimiter);
+        writeln!(writer, "{}", row)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use approx::assert_abs_diff_eq;
+    use data_reader::reader::load_txt_f64;
+
+    use crate::xafs::tests::PARAM_LOADTXT;
+    use crate::xafs::tests::TOP_DIR;
+
+    const ASCII_TOL: f64 = 1e-7;
+
+    #[test]
+    fn test_write_chik_chir() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let chik_path = String::from(TOP_DIR) + "/tests/testfiles/test.chik";
+        let chir_path = String::from(TOP_DIR) + "/tests/testfiles/test.chir";
+
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.calc_background()?.fft()?;
+
+        spectrum.write_chik(&chik_path)?.write_chir(&chir_path)?;
+
+        let chik = load_txt_f64(&chik_path, &PARAM_LOADTXT)?;
+        let k = spectrum.get_k().unwrap();
+        let chi = spectrum.get_chi().unwrap();
+
+        assert_eq!(chik.get_num_lines(), k.len());
+        k.iter()
+            .zip(chik.get_col(0).iter())
+            .for_each(|(x, y)| assert_abs_diff_eq!(x, y, epsilon = ASCII_TOL * x.abs().max(1.0)));
+        chi.iter()
+            .zip(chik.get_col(1).iter())
+            .for_each(|(x, y)| assert_abs_diff_eq!(x, y, epsilon = ASCII_TOL * x.abs().max(1.0)));
+
+        let chir = load_txt_f64(&chir_path, &PARAM_LOADTXT)?;
+        let chir_mag = spectrum.get_chir_mag().unwrap();
+        let chir_re = chir.get_col(1);
+        let chir_im = chir.get_col(2);
+
+        assert_eq!(chir.get_num_lines(), spectrum.get_r().unwrap().len());
+        chir_mag
+            .iter()
+            .zip(chir.get_col(3).iter())
+            .zip(chir_re.iter().zip(chir_im.iter()))
+            .for_each(|((mag, y), (re, im))| {
+                assert_abs_diff_eq!(mag, y, epsilon = ASCII_TOL * mag.abs().max(1.0));
+                assert_abs_diff_eq!(
+                    mag,
+                    &(re * re + im * im).sqrt(),
+                    epsilon = ASCII_TOL * mag.abs().max(1.0)
+                );
+            });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ascii_format() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let csv_path = String::from(TOP_DIR) + "/tests/testfiles/test_format.chir";
+        let full_path = String::from(TOP_DIR) + "/tests/testfiles/test_format.chik";
+
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.calc_background()?.fft()?;
+        spectrum.set_metadata("sample", "Ru foil");
+        spectrum.set_metadata("comment", "line 1\nline 2");
+        spectrum.add_note("gain changed\n1.0 2.0\r\nat scan 3");
+
+        let mut format = AsciiFormat::new();
+        format
+            .set_columns(&["chir_mag", "r"])
+            .set_delimiter(",")
+            .set_precision(4, false)
+            .set_header(HeaderVerbosity::Minimal);
+        spectrum.write_chir_with(&csv_path, &format)?;
+
+        let text = std::fs::read_to_string(&csv_path)?;
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("#  chir_mag  r"));
+        let row = lines.nth(1).unwrap().split(',').collect::<Vec<&str>>();
+        assert_eq!(row.len(), 2);
+        assert_eq!(row[1], format!("{:.4}", spectrum.get_r().unwrap()[1]));
+        assert_eq!(lines.count() + 2, spectrum.get_r().unwrap().len());
+
+        format.set_columns(&["chi", "q"]);
+        assert!(spectrum.write_chik_with(&full_path, &format).is_err());
+
+        let mut format = AsciiFormat::new();
+        format.set_header(HeaderVerbosity::Full);
+        spectrum.write_chik_with(&full_path, &format)?;
+        let text = std::fs::read_to_string(&full_path)?;
+        assert!(text.contains("# background = {\"AUTOBK\""));
+        assert!(text.contains("# metadata.sample = \"Ru foil\""));
+        assert!(text.contains("# note = gain changed\n# 1.0 2.0\n# at scan 3\n"));
+
+        let chik = load_txt_f64(&full_path, &PARAM_LOADTXT)?;
+        assert_eq!(chik.get_num_lines(), spectrum.get_k().unwrap().len());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,486 @@
+//! Athena project files (.prj).
+//!
+//! A project file of Demeter/Athena is a (usually gzip compressed) Perl script. Each group is
+//! written as a list of attributes, `@args`, followed by its arrays, `@x`, `@y`, `@i0` and
+//! `@signal`, and closed by a `[record]` line:
+//!
+//! ```text
+//! $old_group = 'jimwk';
+//! @args = ('label','Ru_QAS.dat','bkg_e0','22118.8','bkg_pre1','-200', ...);
+//! @x = ('21912.253421','21917.253421', ...);
+//! @y = ('-0.0564487164341871','-0.0565011518815115', ...);
+//! [record]   # create object and set arrays in ifeffit
+//! ```
+//!
+//! Groups of mu(E) are read with their label, e0, and the normalization, AUTOBK and forward FT
+//! parameters Athena shows. i0 and the signal are attached as the channels "i0" and "signal".
+//! The energy shift of a group is added to its energy (Athena shifts e0 with it). Groups of
+//! chi(k) are skipped, as are the attributes without counterpart in xraytsubaki (plot
+//! settings, journal, ...).
+//!
+//! Projects written by write_athena_project contain the same attributes and can be opened in
+//! Athena.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ndarray::Array1;
+use version::version;
+
+use super::xafs_bytes::decompress;
+use crate::xafs::background::{BackgroundMethod, AUTOBK};
+use crate::xafs::normalization::{Normalization, NormalizationMethod, PrePostEdge};
+use crate::xafs::xafsutils::FTWindow;
+use crate::xafs::xasgroup::XASGroup;
+use crate::xafs::xasspectrum::XASSpectrum;
+use crate::xafs::xrayfft::XrayFFTF;
+use crate::xafs::XAFSError;
+
+const RECORD: &str = "[record]   # create object and set arrays in ifeffit";
+
+/// Value of a Perl list: a string or number, or a nested list
+#[derive(Debug, Clone, PartialEq)]
+enum PerlValue {
+    Scalar(String),
+    List(Vec<PerlValue>),
+}
+
+impl PerlValue {
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            PerlValue::Scalar(value) => Some(value),
+            PerlValue::List(_) => None,
+        }
+    }
+}
+
+/// Attributes and arrays of one group of the project
+#[derive(Debug, Default)]
+struct Record {
+    args: Vec<(String, PerlValue)>,
+    x: Vec<f64>,
+    y: Vec<f64>,
+    i0: Vec<f64>,
+    signal: Vec<f64>,
+}
+
+impl Record {
+    fn arg(&self, key: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.as_str())
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.arg(key)?.trim().parse().ok()
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.number::<f64>(key).is_some_and(|x| x != 0.0)
+    }
+
+    fn window(&self, key: &str) -> Option<FTWindow> {
+        match self.arg(key)?.to_lowercase().as_str() {
+            "hanning" => Some(FTWindow::Hanning),
+            "parzen" => Some(FTWindow::Parzen),
+            "welch" => Some(FTWindow::Welch),
+            "gaussian" => Some(FTWindow::Gaussian),
+            "sine" => Some(FTWindow::Sine),
+            "kaiser-bessel" | "kaiser" => Some(FTWindow::KaiserBessel),
+            _ => None,
+        }
+    }
+
+    fn into_spectrum(self) -> Result<XASSpectrum, Box<dyn Error>> {
+        if self.x.len() != self.y.len() || self.x.is_empty() {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let eshift = self.number::<f64>("bkg_eshift").unwrap_or(0.0);
+        let energy = Array1::from_vec(self.x.clone()) + eshift;
+        let channels = [("i0", &self.i0), ("signal", &self.signal)]
+            .into_iter()
+            .filter(|(_, values)| values.len() == self.x.len())
+            .map(|(name, values)| (name.to_string(), Array1::from_vec(values.clone())))
+            .collect();
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum_channels(energy, self.y.clone(), channels)?;
+        if let Some(label) = self.arg("label").or(self.arg("tag")) {
+            spectrum.set_name(label);
+        }
+
+        let mut pre_post_edge = PrePostEdge::new();
+        pre_post_edge.pre_edge_start = self.number("bkg_pre1").or(pre_post_edge.pre_edge_start);
+        pre_post_edge.pre_edge_end = self.number("bkg_pre2").or(pre_post_edge.pre_edge_end);
+        pre_post_edge.norm_start = self.number("bkg_nor1").or(pre_post_edge.norm_start);
+        pre_post_edge.norm_end = self.number("bkg_nor2").or(pre_post_edge.norm_end);
+        // Athena counts the terms of the post-edge line: 1 constant, 2 linear, 3 quadratic
+        if let Some(nnorm) = self.number::<f64>("bkg_nnorm") {
+            pre_post_edge.norm_polyorder = Some(nnorm as i32 - 1);
+        }
+
+        let default = AUTOBK::default();
+        let autobk = AUTOBK {
+            rbkg: self.number("bkg_rbkg").or(default.rbkg),
+            kweight: self
+                .number::<f64>("bkg_kw")
+                .map(|x| x as i32)
+                .or(default.kweight),
+            kmin: self.number("bkg_spl1").or(default.kmin),
+            kmax: self.number("bkg_spl2").or(default.kmax),
+            dk: self.number("bkg_dk").or(default.dk),
+            window: self.window("bkg_kwindow").unwrap_or(default.window),
+            nclamp: self
+                .number::<f64>("bkg_nclamp")
+                .map(|x| x as i32)
+                .or(default.nclamp),
+            clamp_lo: self
+                .number::<f64>("bkg_clamp1")
+                .map(|x| x as i32)
+                .or(default.clamp_lo),
+            clamp_hi: self
+                .number::<f64>("bkg_clamp2")
+                .map(|x| x as i32)
+                .or(default.clamp_hi),
+            ..default
+        };
+
+        let default = XrayFFTF::default();
+        let xftf = XrayFFTF {
+            kmin: self.number("fft_kmin").or(default.kmin),
+            kmax: self.number("fft_kmax").or(default.kmax),
+            dk: self.number("fft_dk").or(default.dk),
+            window: self.window("fft_kwindow").or(default.window),
+            ..default
+        };
+
+        // Athena moves e0 together with the energy shift
+        if let Some(e0) = self.number::<f64>("bkg_e0") {
+            spectrum.set_e0(e0);
+        }
+        spectrum
+            .set_normalization_method(Some(NormalizationMethod::PrePostEdge(pre_post_edge)))?
+            .set_background_method(Some(BackgroundMethod::AUTOBK(autobk)))?;
+        spectrum.xftf = Some(xftf);
+
+        Ok(spectrum)
+    }
+}
+
+/// Groups of mu(E) of the text of an Athena project, see the module documentation.
+pub fn read_athena_project(text: &str) -> Result<XASGroup, Box<dyn Error>> {
+    if !text.trim_start().starts_with("# Athena project file") {
+        return Err("Not an Athena project file".into());
+    }
+
+    let mut group = XASGroup::new();
+    let mut record = Record::default();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("[record]") {
+            let record = std::mem::take(&mut record);
+            let is_chi = record.flag("is_chi") || record.arg("recordtype") == Some("chi(k)");
+            if !is_chi {
+                group.add_spectrum(record.into_spectrum()?);
+            }
+            continue;
+        }
+
+        let Some((variable, value)) = line.split_once('=') else {
+            continue;
+        };
+        let parse_array = || -> Result<Vec<f64>, Box<dyn Error>> {
+            parse_list(value)?
+                .iter()
+                .map(|v| {
+                    let v = v.as_str().ok_or("Nested list in an array")?;
+                    Ok(v.trim().parse::<f64>()?)
+                })
+                .collect()
+        };
+
+        match variable.trim() {
+            "@args" => {
+                let mut values = parse_list(value)?.into_iter();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    if let PerlValue::Scalar(key) = key {
+                        record.args.push((key, value));
+                    }
+                }
+            }
+            "@x" => record.x = parse_array()?,
+            "@y" => record.y = parse_array()?,
+            "@i0" => record.i0 = parse_array()?,
+            "@signal" => record.signal = parse_array()?,
+            _ => {}
+        }
+    }
+
+    Ok(group)
+}
+
+/// Load the groups of mu(E) of an Athena project, compressed or not.
+///
+/// # Example
+///
+/// ```no_run
+/// use xraytsubaki::xafs::io;
+///
+/// let mut group = io::load_athena_project("project.prj").unwrap();
+/// group.normalize().unwrap();
+/// io::write_athena_project(&group, "project_copy.prj").unwrap();
+/// ```
+pub fn load_athena_project<P: AsRef<Path>>(path: P) -> Result<XASGroup, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    read_athena_project(std::str::from_utf8(&decompress(&bytes)?)?)
+}
+
+/// Text of an Athena project containing the spectra of `group`, see the module documentation.
+pub fn to_athena_project(group: &XASGroup) -> Result<String, Box<dyn Error>> {
+    let mut text = format!(
+        "# Athena project file -- Demeter version 0.9.26\n\
+         # This file created by xraytsubaki {}\n\n",
+        version!()
+    );
+
+    for (index, spectrum) in group.spectra.iter().enumerate() {
+        let (energy, mu) = match (spectrum.raw_energy.as_ref(), spectrum.raw_mu.as_ref()) {
+            (Some(energy), Some(mu)) => (energy, mu),
+            _ => return Err(format!("Spectrum {} has no data", index).into()),
+        };
+        let tag = format!("xts{:02}", index);
+        let label = spectrum.name.clone().unwrap_or_else(|| tag.clone());
+
+        let number = |value: Option<f64>| value.map(|v| quote(&v.to_string()));
+        let e0 = spectrum
+            .get_e0()
+            .or_else(|| spectrum.normalization.as_ref()?.get_e0());
+
+        let mut args = vec![
+            ("label", Some(quote(&label))),
+            ("tag", Some(quote(&tag))),
+            ("datagroup", Some(quote(&tag))),
+            ("recordtype", Some(quote("mu(E)"))),
+            ("is_xmu", Some("1".to_string())),
+            ("npts", Some(energy.len().to_string())),
+            ("xmin", number(Some(energy[0]))),
+            ("xmax", number(Some(energy[energy.len() - 1]))),
+            ("bkg_eshift", Some("0".to_string())),
+            ("bkg_e0", number(e0)),
+        ];
+
+        if let Some(NormalizationMethod::PrePostEdge(p)) = spectrum.normalization.as_ref() {
+            args.extend([
+                ("bkg_pre1", number(p.pre_edge_start)),
+                ("bkg_pre2", number(p.pre_edge_end)),
+                ("bkg_nor1", number(p.norm_start)),
+                ("bkg_nor2", number(p.norm_end)),
+                (
+                    "bkg_nnorm",
+                    number(p.norm_polyorder.map(|o| o as f64 + 1.0)),
+                ),
+                ("bkg_step", number(p.get_edge_step())),
+            ]);
+        }
+
+        if let Some(BackgroundMethod::AUTOBK(autobk)) = spectrum.background.as_ref() {
+            args.extend([
+                ("bkg_rbkg", number(autobk.rbkg)),
+                ("bkg_kw", number(autobk.kweight.map(f64::from))),
+                ("bkg_spl1", number(autobk.kmin)),
+                ("bkg_spl2", number(autobk.kmax)),
+                ("bkg_dk", number(autobk.dk)),
+                ("bkg_nclamp", number(autobk.nclamp.map(f64::from))),
+                ("bkg_clamp1", number(autobk.clamp_lo.map(f64::from))),
+                ("bkg_clamp2", number(autobk.clamp_hi.map(f64::from))),
+                ("bkg_kwindow", Some(quote(autobk.window.name()))),
+            ]);
+        }
+
+        if let Some(xftf) = spectrum.xftf.as_ref() {
+            args.extend([
+                ("fft_kmin", number(xftf.kmin)),
+                ("fft_kmax", number(xftf.kmax)),
+                ("fft_dk", number(xftf.dk)),
+                ("fft_kwindow", xftf.window.map(|w| quote(w.name()))),
+            ]);
+        }
+
+        let args = args
+            .iter()
+            .filter_map(|(key, value)| Some(format!("'{}',{}", key, value.as_ref()?)))
+            .collect::<Vec<_>>();
+        text += &format!("$old_group = {};\n", quote(&tag));
+        text += &format!("@args = ({});\n", args.join(","));
+        text += &format!("@x = ({});\n", array(energy.iter()));
+        text += &format!("@y = ({});\n", array(mu.iter()));
+        for (variable, channel) in [("@i0", "i0"), ("@signal", "signal")] {
+            if let Some(values) = spectrum.get_channel(channel) {
+                text += &format!("{} = ({});\n", variable, array(values.iter()));
+            }
+        }
+        text += RECORD;
+        text += "\n\n";
+    }
+
+    text += "@journal = ();\n\n1;\n";
+
+    Ok(text)
+}
+
+/// Write the spectra of `group` as a gzip compressed Athena project.
+pub fn write_athena_project<P: AsRef<Path>>(
+    group: &XASGroup,
+    path: P,
+) -> Result<(), Box<dyn Error>> {
+    let text = to_athena_project(group)?;
+    let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    encoder.write_all(text.as_bytes())?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn array<'a, I: Iterator<Item = &'a f64>>(values: I) -> String {
+    values
+        .map(|v| format!("'{}'", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Elements of a Perl list such as ('a','b\'c',1,[],['d']);
+fn parse_list(text: &str) -> Result<Vec<PerlValue>, Box<dyn Error>> {
+    let text = text.trim().trim_end_matches(';').trim();
+    let inner = text
+        .strip_prefix('(')
+        .and_then(|t| t.strip_suffix(')'))
+        .ok_or_else(|| format!("Not a list: {:.40}", text))?;
+
+    let mut chars = inner.chars().peekable();
+    let values = parse_elements(&mut chars, None)?;
+
+    Ok(values)
+}
+
+fn parse_elements(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    close: Option<char>,
+) -> Result<Vec<PerlValue>, Box<dyn Error>> {
+    let mut values = Vec::new();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+
+        match chars.next() {
+            None if close.is_none() => return Ok(values),
+            None => return Err("Unclosed list".into()),
+            Some(c) if Some(c) == close => return Ok(values),
+            Some('\'') => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some('\'') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("Unclosed string".into()),
+                    }
+                }
+                values.push(PerlValue::Scalar(value));
+            }
+            Some('[') => values.push(PerlValue::List(parse_elements(chars, Some(']'))?)),
+            Some('{') => values.push(PerlValue::List(parse_elements(chars, Some('}'))?)),
+            Some(c) => {
+                let mut value = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if *c == ',' || Some(*c) == close || c.is_whitespace() {
+                        break;
+                    }
+                    value.push(*c);
+                    chars.next();
+                }
+                // Keys of hashes are separated by =>
+                if value != "=>" {
+                    values.push(PerlValue::Scalar(value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_parse_list() -> Result<(), Box<dyn Error>> {
+        let values = parse_list("('a','b\\'c', 1 ,[],['d'],{'e' => 2});")?;
+        assert_eq!(values.len(), 6);
+        assert_eq!(values[1], PerlValue::Scalar("b'c".to_string()));
+        assert_eq!(values[2], PerlValue::Scalar("1".to_string()));
+        assert_eq!(values[3], PerlValue::List(vec![]));
+        assert_eq!(values[5].as_str(), None);
+        assert!(parse_list("('a',[1)").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_athena_project() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS_athena.prj";
+        let mut group = load_athena_project(&path)?;
+        assert_eq!(group.len(), 1);
+
+        let spectrum = group.get_spectrum(0)?;
+        assert_eq!(spectrum.name.as_deref(), Some("Ru_QAS.dat"));
+        assert_eq!(spectrum.raw_energy.as_ref().unwrap().len(), 645);
+        assert_eq!(spectrum.get_channel("i0").unwrap().len(), 645);
+        assert_eq!(spectrum.get_e0(), Some(22118.8));
+        match spectrum.normalization.as_ref() {
+            Some(NormalizationMethod::PrePostEdge(p)) => {
+                assert_eq!(p.pre_edge_start, Some(-200.0));
+                assert_eq!(p.norm_polyorder, Some(1));
+            }
+            _ => panic!("PrePostEdge expected"),
+        }
+
+        group.normalize()?;
+        let normalization = group.get_spectrum(0)?.normalization.as_ref().unwrap();
+        assert_abs_diff_eq!(
+            normalization.get_edge_step().unwrap(),
+            0.8614324,
+            epsilon = 0.01
+        );
+
+        let copy = std::env::temp_dir().join("xraytsubaki_test_athena.prj");
+        write_athena_project(&group, &copy)?;
+        let reloaded = load_athena_project(&copy)?;
+        std::fs::remove_file(&copy)?;
+
+        let (spectrum, reloaded) = (group.get_spectrum(0)?, reloaded.get_spectrum(0)?);
+        assert_eq!(reloaded.name, spectrum.name);
+        assert_eq!(reloaded.raw_energy, spectrum.raw_energy);
+        assert_eq!(reloaded.raw_mu, spectrum.raw_mu);
+        assert_eq!(reloaded.channels, spectrum.channels);
+        assert_eq!(reloaded.get_e0(), spectrum.get_e0());
+        assert_eq!(reloaded.background, spectrum.background);
+        assert_eq!(reloaded.xftf, spectrum.xftf);
+
+        assert!(read_athena_project("1 2 3\n").is_err());
+
+        Ok(())
+    }
+}
@@ -10,7 +10,7 @@ use bson::Document;
 use serde::{Deserialize, Serialize};
 use version::version;
 
-use crate::xafs::io::xasdatatype::{XASDataType, XASGroupFile};
+use crate::xafs::io::xasdatatype::{XASDataType, XASGroupFile, SCHEMA_VERSION};
 use crate::xafs::xasgroup::XASGroup;
 use crate::xafs::xasspectrum::XASSpectrum;
 
@@ -28,13 +28,14 @@ impl XASBson for XASGroupFile {
 
         // let xas_group_file: XASGroupFile =
 
-        _ = mem::replace(self, bson::from_document(doc)?);
+        _ = mem::replace(self, XASGroupFile::from_value(bson::from_document(doc)?)?);
 
         Ok(self)
     }
 
     fn write_bson(&mut self, filename: &str) -> Result<&mut Self, Box<dyn Error>> {
         self.version = version!().to_string();
+        self.schema_version = SCHEMA_VERSION;
         self.datatype = XASDataType::XASGroup;
 
         let data_bson = bson::to_bson(&self)?;
@@ -0,0 +1,328 @@
+//! Loaders working on in-memory data instead of file paths.
+//!
+//! These are used where no filesystem is available, e.g. for files uploaded to the wasm GUI or
+//! received by a network service. The format is detected from the content of the data.
+//! gzip (and bzip2 with the "bzip2" feature) compressed data is decompressed transparently.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+
+use crate::xafs::io::xasdatatype::XASGroupFile;
+use crate::xafs::xasgroup::XASGroup;
+use crate::xafs::xasspectrum::XASSpectrum;
+use crate::xafs::XAFSError;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+
+/// Formats that can be detected by detect_format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// QAS beamline (NSLS-II) ascii file, loaded as transmission
+    QAS,
+    /// Whitespace or comma separated columns of energy and mu
+    Columns,
+    /// XASGroup stored by write_json
+    Json,
+    /// XASGroup stored by write_jsongz, or gzip compressed json
+    JsonGz,
+    /// XASGroup stored by write_bson
+    Bson,
+}
+
+/// Guess the format of a file from its content.
+///
+/// Compressed data is reported with the format of its decompressed content.
+pub fn detect_format(bytes: &[u8]) -> Option<FileFormat> {
+    if is_compressed(bytes) {
+        let decompressed = decompress(bytes).ok()?;
+        return match detect_format(&decompressed)? {
+            FileFormat::Json if bytes.starts_with(&GZIP_MAGIC) => Some(FileFormat::JsonGz),
+            format => Some(format),
+        };
+    }
+
+    if bytes.len() >= 5 && bytes[bytes.len() - 1] == 0 {
+        let doc_len = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if doc_len as usize == bytes.len() {
+            return Some(FileFormat::Bson);
+        }
+    }
+
+    let text = std::str::from_utf8(bytes).ok()?;
+    let trimmed = text.trim_start();
+
+    if trimmed.starts_with('{') {
+        return Some(FileFormat::Json);
+    }
+
+    let is_qas = text
+        .lines()
+        .take_while(|line| line.trim_start().starts_with('#'))
+        .any(|line| line.contains("Beamline: QAS"));
+
+    if is_qas {
+        Some(FileFormat::QAS)
+    } else if trimmed.is_empty() {
+        None
+    } else {
+        Some(FileFormat::Columns)
+    }
+}
+
+/// Whether the data is gzip or bzip2 compressed.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC) || bytes.starts_with(&BZIP2_MAGIC)
+}
+
+/// Decompress gzip or bzip2 compressed data. Other data is returned as it is.
+///
+/// bzip2 requires the "bzip2" feature.
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, Box<dyn Error>> {
+    let mut decompressed = Vec::new();
+
+    if bytes.starts_with(&GZIP_MAGIC) {
+        GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    } else if bytes.starts_with(&BZIP2_MAGIC) {
+        #[cfg(feature = "bzip2")]
+        bzip2::read::BzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+
+        #[cfg(not(feature = "bzip2"))]
+        return Err("bzip2 compressed data requires the bzip2 feature".into());
+    } else {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    Ok(Cow::Owned(decompressed))
+}
+
+/// Load a spectrum from the content of a file, detecting its format.
+///
+/// For XASGroup files (json, json.gz and bson) the first spectrum of the group is returned.
+///
+/// # Example
+///
+/// ```
+/// use xraytsubaki::xafs::io;
+///
+/// let data = b"# energy mu\n1.0 0.1\n2.0 0.2\n3.0 0.3\n";
+/// let spectrum = io::load_spectrum_from_bytes(data).unwrap();
+///
+/// assert_eq!(spectrum.raw_mu.unwrap().len(), 3);
+/// ```
+pub fn load_spectrum_from_bytes(bytes: &[u8]) -> Result<XASSpectrum, Box<dyn Error>> {
+    let bytes = decompress(bytes)?;
+
+    match detect_format(&bytes).ok_or(XAFSError::NotEnoughData)? {
+        FileFormat::QAS => load_spectrum_QAS_trans_from_bytes(&bytes),
+        FileFormat::Columns => load_spectrum_columns_from_bytes(&bytes),
+        _ => load_group_from_bytes(&bytes)?
+            .spectra
+            .into_iter()
+            .next()
+            .ok_or_else(|| Box::new(XAFSError::GroupIsEmpty) as Box<dyn Error>),
+    }
+}
+
+/// Read all the data from `reader` and load it with load_spectrum_from_bytes.
+pub fn load_spectrum_from_reader<R: Read>(mut reader: R) -> Result<XASSpectrum, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    load_spectrum_from_bytes(&bytes)
+}
+
+/// Load a group from the content of a file, detecting its format.
+///
+/// Single spectrum formats are returned as a group with one spectrum.
+pub fn load_group_from_bytes(bytes: &[u8]) -> Result<XASGroup, Box<dyn Error>> {
+    let bytes = decompress(bytes)?;
+
+    let group_file: XASGroupFile = match detect_format(&bytes).ok_or(XAFSError::NotEnoughData)? {
+        FileFormat::Json | FileFormat::JsonGz => {
+            XASGroupFile::from_value(serde_json::from_slice(&bytes)?)?
+        }
+        FileFormat::Bson => XASGroupFile::from_value(bson::from_slice(&bytes)?)?,
+        FileFormat::QAS | FileFormat::Columns => {
+            let mut group = XASGroup::new();
+            group.add_spectrum(load_spectrum_from_bytes(&bytes)?);
+            return Ok(group);
+        }
+    };
+
+    Ok(group_file.data)
+}
+
+/// Read all the data from `reader` and load it with load_group_from_bytes.
+pub fn load_group_from_reader<R: Read>(mut reader: R) -> Result<XASGroup, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    load_group_from_bytes(&bytes)
+}
+
+/// Load a QAS file from its content. Equivalent to load_spectrum_QAS_trans.
+#[allow(non_snake_case)]
+pub fn load_spectrum_QAS_trans_from_bytes(bytes: &[u8]) -> Result<XASSpectrum, Box<dyn Error>> {
+    let bytes = decompress(bytes)?;
+    let columns = parse_columns(std::str::from_utf8(&bytes)?, '#')?;
+
+    if columns.len() < 5 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    super::spectrum_from_QAS_trans(columns[0].clone(), &columns[1], &columns[2])
+}
+
+/// Load the first two columns as energy and mu.
+pub fn load_spectrum_columns_from_bytes(bytes: &[u8]) -> Result<XASSpectrum, Box<dyn Error>> {
+    let bytes = decompress(bytes)?;
+    let mut columns = parse_columns(std::str::from_utf8(&bytes)?, '#')?;
+
+    if columns.len() < 2 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    let mu = columns.swap_remove(1);
+    let energy = columns.swap_remove(0);
+
+    let mut spectrum = XASSpectrum::new();
+    spectrum.set_spectrum(energy, mu);
+
+    Ok(spectrum)
+}
+
+/// Parse whitespace or comma separated numeric columns, skipping empty and comment lines.
+///
+/// Returns the data column by column. All the rows must have the same number of fields.
+pub fn parse_columns(text: &str, comment: char) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+    let mut columns: Vec<Vec<f64>> = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(comment) {
+            continue;
+        }
+
+        let row = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|field| !field.is_empty())
+            .map(|field| field.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+
+        if columns.is_empty() {
+            columns = vec![Vec::new(); row.len()];
+        } else if row.len() != columns.len() {
+            return Err(format!(
+                "line {}: expected {} fields, found {}",
+                line_number + 1,
+                columns.len(),
+                row.len()
+            )
+            .into());
+        }
+
+        columns
+            .iter_mut()
+            .zip(row)
+            .for_each(|(column, value)| column.push(value));
+    }
+
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::load_spectrum_QAS_trans;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_detect_format() -> Result<(), Box<dyn Error>> {
+        let qas = std::fs::read(String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat")?;
+        let json = std::fs::read(String::from(TOP_DIR) + "/tests/testfiles/test.json")?;
+        let jsongz = std::fs::read(String::from(TOP_DIR) + "/tests/testfiles/test.json.gz")?;
+        let bson = std::fs::read(String::from(TOP_DIR) + "/tests/testfiles/test.bson")?;
+
+        assert_eq!(detect_format(&qas), Some(FileFormat::QAS));
+        assert_eq!(detect_format(&json), Some(FileFormat::Json));
+        assert_eq!(detect_format(&jsongz), Some(FileFormat::JsonGz));
+        assert_eq!(detect_format(&bson), Some(FileFormat::Bson));
+        assert_eq!(detect_format(b"1 2\n3 4\n"), Some(FileFormat::Columns));
+        assert_eq!(detect_format(b"  \n"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spectrum_from_bytes() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let expected = load_spectrum_QAS_trans(&path)?;
+
+        let spectrum = load_spectrum_from_reader(std::fs::File::open(&path)?)?;
+
+        assert_eq!(spectrum.raw_energy, expected.raw_energy);
+        assert_eq!(spectrum.raw_mu, expected.raw_mu);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_compressed() -> Result<(), Box<dyn Error>> {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let data = std::fs::read(&path)?;
+        let expected = load_spectrum_QAS_trans(&path)?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data)?;
+        let gz = encoder.finish()?;
+
+        assert!(is_compressed(&gz));
+        assert_eq!(detect_format(&gz), Some(FileFormat::QAS));
+        assert_eq!(decompress(&gz)?.as_ref(), data.as_slice());
+
+        let spectrum = load_spectrum_from_bytes(&gz)?;
+        assert_eq!(spectrum.raw_mu, expected.raw_mu);
+
+        let gz_path = std::env::temp_dir().join("xraytsubaki_test_Ru_QAS.dat.gz");
+        std::fs::write(&gz_path, &gz)?;
+        let spectrum = load_spectrum_QAS_trans(&gz_path.to_string_lossy().to_string())?;
+        std::fs::remove_file(&gz_path)?;
+        assert_eq!(spectrum.raw_mu, expected.raw_mu);
+
+        #[cfg(feature = "bzip2")]
+        {
+            let mut encoder =
+                bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder.write_all(&data)?;
+            let bz2 = encoder.finish()?;
+
+            assert_eq!(detect_format(&bz2), Some(FileFormat::QAS));
+            assert_eq!(load_spectrum_from_bytes(&bz2)?.raw_mu, expected.raw_mu);
+        }
+
+        #[cfg(not(feature = "bzip2"))]
+        assert!(decompress(b"BZh91AY&SY").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        let columns = parse_columns("# header\n1, 2 3\n\n4 5,6\n", '#').unwrap();
+        assert_eq!(
+            columns,
+            vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]
+        );
+
+        assert!(parse_columns("1 2\n3\n", '#').is_err());
+        assert!(parse_columns("1 a\n", '#').is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Spectra from arbitrary column files.
+//!
+//! Beamline ASCII files differ in the order of their columns and in how mu is obtained from
+//! them. A ColumnMapping describes both: the energy column, and mu as ln(numerator /
+//! denominator) for transmission, numerator / denominator for fluorescence, or an expression
+//! of the columns (see the expression module), e.g. "(if1 + if2 + if3) / i0".
+//!
+//! The columns are named by ColumnMapping::labels if given, otherwise by the header of the
+//! file: a line of labels before the data, commented or not, with one label per column (e.g.
+//! `# energy i0 it ir`). Without either, they are named col0, col1, ... Every column other
+//! than the energy is attached to the spectrum as a channel under its name.
+//!
+//! ```no_run
+//! use xraytsubaki::xafs::io::{self, ColumnMapping};
+//!
+//! let transmission = io::load_ascii("Ru_QAS.dat", &ColumnMapping::transmission(0, 1, 2))?;
+//! let fluorescence = io::load_ascii("Ru_QAS.dat", &ColumnMapping::fluorescence(0, 4, 1))?;
+//! let summed = io::load_ascii("fluo.dat", &ColumnMapping::expression(0, "(if1 + if2) / i0"))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::xafs_bytes::{decompress, parse_columns};
+use crate::xafs::expression::Expression;
+use crate::xafs::xasspectrum::XASSpectrum;
+
+const COMMENT: char = '#';
+
+/// Columns of a file and the labels of its header
+pub type LabelledColumns = (Vec<Vec<f64>>, Option<Vec<String>>);
+
+/// Columns of a data file making up the spectrum, see the module documentation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    /// Index of the energy column (eV)
+    pub energy: usize,
+    pub numerator: usize,
+    pub denominator: usize,
+    /// mu = numerator / denominator if true, ln(numerator / denominator) otherwise
+    pub fluorescence: bool,
+    /// Expression of the column names giving mu, used instead of the ratio if set
+    pub mu_expression: Option<String>,
+    /// Names of the columns, overriding those of the file
+    pub labels: Option<Vec<String>>,
+}
+
+impl Default for ColumnMapping {
+    /// Transmission with energy, i0 and it in the first three columns
+    fn default() -> Self {
+        ColumnMapping::transmission(0, 1, 2)
+    }
+}
+
+impl ColumnMapping {
+    /// mu = ln(i0 / it)
+    pub fn transmission(energy: usize, i0: usize, it: usize) -> ColumnMapping {
+        ColumnMapping {
+            energy,
+            numerator: i0,
+            denominator: it,
+            fluorescence: false,
+            mu_expression: None,
+            labels: None,
+        }
+    }
+
+    /// mu = iff / i0
+    pub fn fluorescence(energy: usize, iff: usize, i0: usize) -> ColumnMapping {
+        ColumnMapping {
+            fluorescence: true,
+            ..ColumnMapping::transmission(energy, iff, i0)
+        }
+    }
+
+    /// mu given by an expression of the column names
+    pub fn expression(energy: usize, mu: &str) -> ColumnMapping {
+        ColumnMapping {
+            energy,
+            mu_expression: Some(mu.to_string()),
+            ..ColumnMapping::default()
+        }
+    }
+
+    pub fn set_labels(&mut self, labels: &[&str]) -> &mut Self {
+        self.labels = Some(labels.iter().map(|l| l.to_string()).collect());
+        self
+    }
+
+    /// Spectrum from the columns and the column names of a file
+    pub fn spectrum(
+        &self,
+        columns: &[Vec<f64>],
+        file_labels: Option<Vec<String>>,
+    ) -> Result<XASSpectrum, Box<dyn Error>> {
+        let labels = match self.labels.clone().or(file_labels) {
+            Some(labels) if labels.len() == columns.len() => labels,
+            Some(labels) => {
+                return Err(format!("{} labels for {} columns", labels.len(), columns.len()).into())
+            }
+            None => (0..columns.len()).map(|i| format!("col{}", i)).collect(),
+        };
+
+        let column = |index: usize| {
+            columns.get(index).ok_or_else(|| {
+                format!(
+                    "Column {} requested, the file has {} columns",
+                    index,
+                    columns.len()
+                )
+            })
+        };
+        let energy = column(self.energy)?;
+
+        let mu = match self.mu_expression.as_deref() {
+            Some(text) => {
+                let expression = Expression::parse(text)?;
+                (0..energy.len())
+                    .map(|row| {
+                        expression.evaluate_with(|name| {
+                            let index = labels.iter().position(|label| label == name)?;
+                            Some(columns[index][row])
+                        })
+                    })
+                    .collect::<Result<Vec<f64>, _>>()?
+            }
+            None => {
+                let numerator = column(self.numerator)?;
+                let denominator = column(self.denominator)?;
+                numerator
+                    .iter()
+                    .zip(denominator)
+                    .map(|(n, d)| {
+                        if self.fluorescence {
+                            n / d
+                        } else {
+                            (n / d).ln()
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        let channels = labels
+            .iter()
+            .zip(columns)
+            .enumerate()
+            .filter(|(index, _)| *index != self.energy)
+            .map(|(_, (label, values))| (label.clone(), Array1::from_vec(values.clone())))
+            .collect::<BTreeMap<String, Array1<f64>>>();
+
+        let mut spectrum = XASSpectrum::new();
+        super::note_energy_steps(&mut spectrum, energy);
+        spectrum.set_spectrum_channels(energy.clone(), mu, channels)?;
+
+        Ok(spectrum)
+    }
+}
+
+/// Columns of a whitespace or comma separated text and the labels of its header, if any
+pub fn parse_labelled_columns(text: &str) -> Result<LabelledColumns, Box<dyn Error>> {
+    let mut header = None;
+    let mut data_start = text.len();
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let (content, comment) = match trimmed.strip_prefix(COMMENT) {
+            Some(content) => (content, true),
+            None => (trimmed, false),
+        };
+        let words = content
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_string())
+            .collect::<Vec<String>>();
+
+        if !comment && words.iter().all(|word| word.parse::<f64>().is_ok()) {
+            data_start = line.as_ptr() as usize - text.as_ptr() as usize;
+            break;
+        }
+        header = Some(words);
+    }
+
+    let columns = parse_columns(&text[data_start..], COMMENT)?;
+    let labels = header.filter(|labels| labels.len() == columns.len());
+
+    Ok((columns, labels))
+}
+
+/// Load a spectrum from a column file with `mapping`. gzip (and bzip2 with the "bzip2"
+/// feature) compressed files are decompressed transparently. Irregular steps of the energy
+/// column (see find_energy_step_report) are added to the notes.
+pub fn load_ascii<P: AsRef<Path>>(
+    path: P,
+    mapping: &ColumnMapping,
+) -> Result<XASSpectrum, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let (columns, labels) = parse_labelled_columns(std::str::from_utf8(&decompress(&bytes)?)?)?;
+
+    mapping.spectrum(&columns, labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::load_spectrum_QAS_trans;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_load_ascii() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let reference = load_spectrum_QAS_trans(&path)?;
+
+        // Columns named by the header "# energy  i0  it  ir  iff  aux1  aux2  aux3  aux4"
+        let spectrum = load_ascii(&path, &ColumnMapping::transmission(0, 1, 2))?;
+        assert_eq!(spectrum.raw_energy, reference.raw_energy);
+        assert_eq!(spectrum.raw_mu, reference.raw_mu);
+        assert_eq!(spectrum.channels.len(), 8);
+        assert_eq!(spectrum.get_channel("it"), reference.get_channel("it"));
+
+        let fluorescence = load_ascii(&path, &ColumnMapping::fluorescence(0, 4, 1))?;
+        let iff = fluorescence.get_channel("iff").unwrap();
+        let i0 = fluorescence.get_channel("i0").unwrap();
+        assert_eq!(fluorescence.raw_mu, Some(iff / i0));
+
+        let spectrum = load_ascii(&path, &ColumnMapping::expression(0, "ln(i0 / it) * 2"))?;
+        assert_eq!(
+            spectrum.raw_mu,
+            reference.raw_mu.as_ref().map(|mu| mu * 2.0)
+        );
+
+        let mut mapping = ColumnMapping::expression(0, "ln(a / b)");
+        mapping.set_labels(&["e", "a", "b", "c", "d", "f", "g", "h", "j"]);
+        let spectrum = load_ascii(&path, &mapping)?;
+        assert_eq!(spectrum.raw_mu, reference.raw_mu);
+        assert!(spectrum.get_channel("a").is_some());
+
+        mapping.set_labels(&["e", "a", "b"]);
+        assert!(load_ascii(&path, &mapping).is_err());
+        assert!(load_ascii(&path, &ColumnMapping::transmission(0, 1, 9)).is_err());
+        assert!(load_ascii(&path, &ColumnMapping::expression(0, "i0 / x")).is_err());
+        assert!(reference.notes.is_empty());
+
+        // A step back of the monochromator is noted before the energy is sorted
+        let columns = vec![
+            vec![1.0, 2.0, 3.0, 2.5, 4.0, 5.0],
+            vec![2.0; 6],
+            vec![1.0; 6],
+        ];
+        let spectrum = ColumnMapping::transmission(0, 1, 2).spectrum(&columns, None)?;
+        assert_eq!(spectrum.notes.len(), 1);
+        assert!(spectrum.notes[0].starts_with("1 negative energy steps between 3"));
+        assert!(spectrum.energy_step_report()?.is_regular());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_labelled_columns() -> Result<(), Box<dyn Error>> {
+        let (columns, labels) = parse_labelled_columns("# scan 12\n# E I0 IT\n1 2 3\n4 5 6\n")?;
+        assert_eq!(
+            columns,
+            vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]
+        );
+        assert_eq!(labels, Some(vec!["E".into(), "I0".into(), "IT".into()]));
+
+        let (_, labels) = parse_labelled_columns("energy,i0\n1,2\n")?;
+        assert_eq!(labels, Some(vec!["energy".into(), "i0".into()]));
+
+        let (_, labels) = parse_labelled_columns("# energy\n1 2\n")?;
+        assert_eq!(labels, None);
+
+        Ok(())
+    }
+}
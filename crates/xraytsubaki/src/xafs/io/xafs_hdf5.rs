@@ -0,0 +1,156 @@
+//! Export of the processed data of a group to HDF5 (feature "hdf5").
+//!
+//! Layout of the file:
+//!
+//! ```text
+//! /                       attrs: version, n_spectra
+//! /spectrum_0000          attrs: name, e0, edge_step, normalization, background, xftf, xftr
+//!     energy, mu, norm, flat, bkg, k, chi, r, chir_mag, chir_re, chir_im, q, chiq
+//! /spectrum_0001
+//!     ...
+//! ```
+//!
+//! Only the arrays that have been calculated are written. The processing parameters are stored
+//! as JSON strings, in the same form as in the JSON and BSON files.
+
+use std::error::Error;
+use std::path::Path;
+
+use hdf5::types::VarLenUnicode;
+use hdf5::{Group, Location};
+use ndarray::Array1;
+use version::version;
+
+use crate::xafs::xasgroup::XASGroup;
+use crate::xafs::xasspectrum::XASSpectrum;
+
+impl XASGroup {
+    /// Write the arrays and parameters of every spectrum of the group to an HDF5 file.
+    pub fn to_hdf5<P: AsRef<Path>>(&self, path: P) -> Result<&Self, Box<dyn Error>> {
+        let file = hdf5::File::create(path)?;
+
+        write_str_attr(&file, "version", version!())?;
+        file.new_attr::<u64>()
+            .create("n_spectra")?
+            .write_scalar(&(self.len() as u64))?;
+
+        for (i, spectrum) in self.spectra.iter().enumerate() {
+            let group = file.create_group(&format!("spectrum_{:04}", i))?;
+            write_spectrum(&group, spectrum)?;
+        }
+
+        Ok(self)
+    }
+}
+
+fn write_spectrum(group: &Group, spectrum: &XASSpectrum) -> Result<(), Box<dyn Error>> {
+    if let Some(name) = spectrum.name.as_ref() {
+        write_str_attr(group, "name", name)?;
+    }
+
+    let normalization = spectrum.normalization.as_ref();
+    let e0 = spectrum
+        .get_e0()
+        .or_else(|| normalization.and_then(|n| n.get_e0()));
+    if let Some(e0) = e0 {
+        group.new_attr::<f64>().create("e0")?.write_scalar(&e0)?;
+    }
+    if let Some(edge_step) = normalization.and_then(|n| n.get_edge_step()) {
+        group
+            .new_attr::<f64>()
+            .create("edge_step")?
+            .write_scalar(&edge_step)?;
+    }
+
+    if let Some(normalization) = normalization {
+        let json = serde_json::to_string(&normalization.copy_parameters())?;
+        write_str_attr(group, "normalization", &json)?;
+    }
+    if let Some(background) = spectrum.background.as_ref() {
+        let json = serde_json::to_string(&background.copy_parameters())?;
+        write_str_attr(group, "background", &json)?;
+    }
+    if let Some(xftf) = spectrum.xftf.as_ref() {
+        let json = serde_json::to_string(&xftf.copy_parameters())?;
+        write_str_attr(group, "xftf", &json)?;
+    }
+    if let Some(xftr) = spectrum.xftr.as_ref() {
+        let json = serde_json::to_string(&xftr.copy_parameters())?;
+        write_str_attr(group, "xftr", &json)?;
+    }
+
+    let arrays: [(&str, Option<Array1<f64>>); 13] = [
+        ("energy", spectrum.energy.clone()),
+        ("mu", spectrum.mu.clone()),
+        ("norm", normalization.and_then(|n| n.get_norm().cloned())),
+        ("flat", normalization.and_then(|n| n.get_flat().cloned())),
+        (
+            "bkg",
+            spectrum.background.as_ref().and_then(|b| b.get_bkg()),
+        ),
+        ("k", spectrum.get_k()),
+        ("chi", spectrum.get_chi()),
+        ("r", spectrum.get_r().map(|r| r.to_owned())),
+        ("chir_mag", spectrum.get_chir_mag().map(|c| c.to_owned())),
+        ("chir_re", spectrum.get_chir_real()),
+        ("chir_im", spectrum.get_chir_imag()),
+        ("q", spectrum.get_q().map(|q| q.to_owned())),
+        ("chiq", spectrum.get_chiq()),
+    ];
+
+    for (name, array) in arrays.iter() {
+        if let Some(array) = array {
+            group.new_dataset_builder().with_data(array).create(*name)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_str_attr(location: &Location, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let value = value.parse::<VarLenUnicode>()?;
+    location
+        .new_attr::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&value)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_to_hdf5() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.set_name("Ru");
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum.clone()).add_spectrum(spectrum);
+        group.normalize()?.calc_background()?.fft()?;
+
+        let save_path = std::env::temp_dir().join("xraytsubaki_test_group.h5");
+        group.to_hdf5(&save_path)?;
+
+        let file = hdf5::File::open(&save_path)?;
+        assert_eq!(file.attr("n_spectra")?.read_scalar::<u64>()?, 2);
+
+        let first = file.group("spectrum_0000")?;
+        let name = first.attr("name")?.read_scalar::<VarLenUnicode>()?;
+        assert_eq!(name.as_str(), "Ru");
+        assert_eq!(
+            first.dataset("chi")?.read_1d::<f64>()?,
+            group.spectra[0].get_chi().unwrap()
+        );
+        assert!(first.dataset("chir_mag").is_ok());
+        assert!(first.dataset("chiq").is_err());
+
+        std::fs::remove_file(save_path)?;
+
+        Ok(())
+    }
+}
@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::{self, json};
 use version::version;
 
-use crate::xafs::io::xasdatatype::{XASDataType, XASGroupFile};
+use crate::xafs::io::xasdatatype::{XASDataType, XASGroupFile, SCHEMA_VERSION};
 use crate::xafs::xasgroup::XASGroup;
 use crate::xafs::xasspectrum::XASSpectrum;
 
@@ -28,7 +28,7 @@ impl XASJson for XASGroupFile {
     fn read_json(&mut self, filename: &str) -> Result<&mut Self, Box<dyn Error>> {
         let f_buffer = File::open(filename)?;
 
-        let doc = serde_json::from_reader(f_buffer)?;
+        let doc = XASGroupFile::from_value(serde_json::from_reader(f_buffer)?)?;
         _ = mem::replace(self, doc);
 
         Ok(self)
@@ -36,6 +36,7 @@ impl XASJson for XASGroupFile {
 
     fn write_json(&mut self, filename: &str) -> Result<&mut Self, Box<dyn Error>> {
         self.version = version!().to_string();
+        self.schema_version = SCHEMA_VERSION;
         self.datatype = XASDataType::XASGroup;
 
         // let data_bson = bson::to_bson(&self)?;
@@ -54,7 +55,7 @@ impl XASJson for XASGroupFile {
 
         let f_buffer = File::open(filename)?;
         let f_buffer = GzDecoder::new(f_buffer);
-        let doc = serde_json::from_reader(f_buffer)?;
+        let doc = XASGroupFile::from_value(serde_json::from_reader(f_buffer)?)?;
 
         _ = mem::replace(self, doc);
 
@@ -67,6 +68,7 @@ impl XASJson for XASGroupFile {
         }
 
         self.version = version!().to_string();
+        self.schema_version = SCHEMA_VERSION;
         self.datatype = XASDataType::XASGroup;
 
         let mut data_file = File::create(filename)?;
@@ -0,0 +1,615 @@
+//! JSON Schema of the serialized spectra, groups and processing parameters.
+//!
+//! The schemas describe the JSON written by XASGroup::write_json (and the equivalent BSON
+//! documents) so that databases, web frontends or LIMS can validate the files without linking
+//! to the crate. They are generated here and published in the `schema` directory of the crate;
+//! `write_json_schemas` regenerates the published files.
+//!
+//! ndarray arrays are serialized as `{"v": 1, "dim": [n], "data": [...]}`, with NaN and
+//! infinite values written as null. Every field may be omitted, in which case the default of
+//! the parameter is used on deserialization.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+pub const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// File names of the published schemas, in the order of write_json_schemas.
+pub const SCHEMA_FILES: [&str; 3] = [
+    "xas_spectrum.schema.json",
+    "xas_group.schema.json",
+    "xas_group_file.schema.json",
+];
+
+/// Schema of a serialized XASSpectrum
+pub fn xas_spectrum_schema() -> Value {
+    root_schema(
+        "XASSpectrum",
+        "A single XAS spectrum with its processing parameters and results.",
+        "xas_spectrum",
+    )
+}
+
+/// Schema of a serialized XASGroup
+pub fn xas_group_schema() -> Value {
+    root_schema(
+        "XASGroup",
+        "A group of XAS spectra with their selection and visibility states.",
+        "xas_group",
+    )
+}
+
+/// Schema of the JSON and BSON files of XASGroup (XASGroupFile)
+pub fn xas_group_file_schema() -> Value {
+    root_schema(
+        "XASGroupFile",
+        "A JSON or BSON file of xraytsubaki containing a group of XAS spectra.",
+        "xas_group_file",
+    )
+}
+
+/// Write the schemas to `dir` (see SCHEMA_FILES) and return the paths of the files.
+pub fn write_json_schemas<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let schemas = [
+        xas_spectrum_schema(),
+        xas_group_schema(),
+        xas_group_file_schema(),
+    ];
+
+    std::fs::create_dir_all(dir.as_ref())?;
+
+    SCHEMA_FILES
+        .iter()
+        .zip(schemas.iter())
+        .map(|(name, schema)| {
+            let path = dir.as_ref().join(name);
+            std::fs::write(&path, serde_json::to_string_pretty(schema)? + "\n")?;
+            Ok(path)
+        })
+        .collect()
+}
+
+fn root_schema(title: &str, description: &str, root: &str) -> Value {
+    let mut schema = Map::new();
+    schema.insert("$schema".to_string(), json!(JSON_SCHEMA_DIALECT));
+    schema.insert("title".to_string(), json!(title));
+    schema.insert("description".to_string(), json!(description));
+    schema.insert("$ref".to_string(), json!(format!("#/$defs/{}", root)));
+    schema.insert("$defs".to_string(), definitions());
+
+    Value::Object(schema)
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/$defs/{}", name) })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+fn number() -> Value {
+    nullable(json!({ "type": "number" }))
+}
+
+fn integer() -> Value {
+    nullable(json!({ "type": "integer" }))
+}
+
+fn unsigned() -> Value {
+    nullable(json!({ "type": "integer", "minimum": 0 }))
+}
+
+fn array1() -> Value {
+    nullable(reference("array1"))
+}
+
+fn object(description: &str, properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "description": description,
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+/// Externally tagged enum variant `{"<name>": <schema>}`
+fn variant(name: &str, schema: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { name: schema },
+        "required": [name],
+        "additionalProperties": false,
+    })
+}
+
+fn definitions() -> Value {
+    json!({
+        "array1": {
+            "type": "object",
+            "description": "One-dimensional ndarray array. NaN and infinite values are null.",
+            "properties": {
+                "v": { "const": 1 },
+                "dim": {
+                    "type": "array",
+                    "items": { "type": "integer", "minimum": 0 },
+                    "minItems": 1,
+                    "maxItems": 1,
+                },
+                "data": { "type": "array", "items": { "type": ["number", "null"] } },
+            },
+            "required": ["v", "dim", "data"],
+            "additionalProperties": false,
+        },
+        "real_dft": {
+            "type": "object",
+            "description": "Spectrum of a real FFT of original_length points, as [re, im] pairs of its original_length / 2 + 1 frequency bins.",
+            "properties": {
+                "original_length": { "type": "integer", "minimum": 0 },
+                "inner": {
+                    "type": "array",
+                    "items": {
+                        "type": "array",
+                        "items": { "type": ["number", "null"] },
+                        "minItems": 2,
+                        "maxItems": 2,
+                    },
+                },
+            },
+            "required": ["original_length", "inner"],
+            "additionalProperties": false,
+        },
+        "ft_window": {
+            "enum": ["Hanning", "Parzen", "Welch", "Gaussian", "Sine", "KaiserBessel", "FHanning"],
+        },
+        "custom_model": {
+            "type": "object",
+            "description": "Model registered by name, with the parameters passed to its constructor.",
+            "properties": {
+                "name": { "type": "string" },
+                "parameters": {},
+            },
+            "required": ["name", "parameters"],
+            "additionalProperties": false,
+        },
+        "pre_post_edge": object(
+            "Pre-edge line and post-edge polynomial normalization. Ranges in eV relative to e0.",
+            json!({
+                "pre_edge_start": number(),
+                "pre_edge_end": number(),
+                "norm_start": number(),
+                "norm_end": number(),
+                "norm_polyorder": integer(),
+                "n_victoreen": integer(),
+                "e0": number(),
+                "edge_step": number(),
+                "pre_edge": array1(),
+                "post_edge": array1(),
+                "norm": array1(),
+                "flat": array1(),
+                "pre_coefficients": nullable(json!({ "type": "array", "items": { "type": "number" } })),
+                "norm_coefficients": nullable(json!({ "type": "array", "items": { "type": "number" } })),
+                "delta_norm": array1(),
+                "delta_flat": array1(),
+                "warnings": nullable(json!({ "type": "array", "items": { "type": "string" } })),
+                "fit_weighting": nullable(reference("fit_weighting")),
+            }),
+        ),
+        "fit_weighting": {
+            "description": "Weighting of the pre-edge and post-edge fits.",
+            "oneOf": [
+                { "const": "Uniform" },
+                variant("Weights", reference("array1")),
+                variant("Robust", object(
+                    "Tukey biweight fit with weights tapered towards the edge.",
+                    json!({ "edge_taper": { "type": "number" } }),
+                )),
+            ],
+        },
+        "mback": object(
+            "MBack normalization.",
+            json!({
+                "e0": number(),
+                "edge_step": number(),
+                "norm": array1(),
+                "flat": array1(),
+            }),
+        ),
+        "normalization_method": {
+            "oneOf": [
+                variant("PrePostEdge", reference("pre_post_edge")),
+                variant("MBack", reference("mback")),
+                variant("Custom", reference("custom_model")),
+            ],
+        },
+        "autobk": object(
+            "AUTOBK background removal.",
+            json!({
+                "ek0": number(),
+                "rbkg": number(),
+                "nknots": integer(),
+                "kmin": number(),
+                "kmax": number(),
+                "kstep": number(),
+                "nclamp": integer(),
+                "clamp_lo": integer(),
+                "clamp_hi": integer(),
+                "nfft": integer(),
+                "chi_std": array1(),
+                "k_std": array1(),
+                "decimation": unsigned(),
+                "kweight": integer(),
+                "window": reference("ft_window"),
+                "dk": number(),
+                "trace": nullable(json!({ "type": "boolean" })),
+                "bkg": array1(),
+                "delta_bkg": array1(),
+                "chie": array1(),
+                "k": array1(),
+                "chi": array1(),
+                "delta_chi": array1(),
+                "fit_trace": nullable(reference("lm_trace")),
+            }),
+        ),
+        "lm_trace": object(
+            "Iteration trace of a Levenberg-Marquardt fit.",
+            json!({
+                "steps": {
+                    "type": "array",
+                    "items": object(
+                        "Parameters and residual norm at one evaluation of the residuals.",
+                        json!({
+                            "params": { "type": "array", "items": { "type": "number" } },
+                            "residual_norm": { "type": "number" },
+                        }),
+                    ),
+                },
+                "termination": { "type": "string" },
+                "success": { "type": "boolean" },
+                "number_of_evaluations": { "type": "integer", "minimum": 0 },
+                "objective_function": { "type": "number" },
+            }),
+        ),
+        "ilpbkg": object(
+            "ILPBkg background removal.",
+            json!({
+                "ek0": number(),
+                "rbkg": number(),
+                "nknots": integer(),
+                "kmax": number(),
+                "kstep": number(),
+                "kweight": integer(),
+                "smoothing": number(),
+                "max_iterations": unsigned(),
+                "tolerance": number(),
+                "bkg": array1(),
+                "chie": array1(),
+                "k": array1(),
+                "chi": array1(),
+                "delta_chi": array1(),
+                "iterations": unsigned(),
+            }),
+        ),
+        "background_method": {
+            "oneOf": [
+                variant("AUTOBK", reference("autobk")),
+                variant("ILPBkg", reference("ilpbkg")),
+                variant("Custom", reference("custom_model")),
+                { "const": "None" },
+            ],
+        },
+        "xftf": object(
+            "Forward Fourier transform chi(k) -> chi(R).",
+            json!({
+                "rmax_out": number(),
+                "window": nullable(reference("ft_window")),
+                "dk": number(),
+                "dk2": number(),
+                "kmin": number(),
+                "kmax": number(),
+                "kweight": number(),
+                "nfft": unsigned(),
+                "kstep": number(),
+                "r": array1(),
+                "chir": nullable(reference("real_dft")),
+                "chir_mag": array1(),
+                "kwin": array1(),
+            }),
+        ),
+        "xftr": object(
+            "Reverse Fourier transform chi(R) -> chi(q).",
+            json!({
+                "qmax_out": number(),
+                "window": nullable(reference("ft_window")),
+                "dr": number(),
+                "dr2": number(),
+                "rmin": number(),
+                "rmax": number(),
+                "rweight": number(),
+                "nfft": unsigned(),
+                "kstep": number(),
+                "q": array1(),
+                "chiq": array1(),
+                "rwin": array1(),
+            }),
+        ),
+        "find_e0_options": object(
+            "Pre-filters of find_e0.",
+            json!({
+                "exclude_start": unsigned(),
+                "exclude_end": unsigned(),
+                "median_window": unsigned(),
+                "derivative": nullable(json!({
+                    "oneOf": [
+                        { "enum": ["Forward", "Central"] },
+                        variant("SavitzkyGolay", json!({
+                            "type": "object",
+                            "properties": {
+                                "window": { "type": "integer", "minimum": 0 },
+                                "order": { "type": "integer", "minimum": 0 },
+                            },
+                            "required": ["window", "order"],
+                            "additionalProperties": false,
+                        })),
+                    ],
+                })),
+                "smooth_width": number(),
+                "upsample": unsigned(),
+            }),
+        ),
+        "processing_options": object(
+            "Tolerances and non-finite policy used to prepare energy and mu.",
+            json!({
+                "tiny_energy": number(),
+                "dup_frac": number(),
+                "energy_step_frac_ignore": number(),
+                "energy_step_nave": unsigned(),
+                "energy_step_jump_factor": number(),
+                "exafs_min_kmax": number(),
+                "find_e0": reference("find_e0_options"),
+                "non_finite": nullable(json!({ "enum": ["Error", "Filter", "Repair"] })),
+                "keep_previous": nullable(json!({ "type": "boolean" })),
+                "plot_max_points": unsigned(),
+            }),
+        ),
+        "processing_snapshot": object(
+            "Processing results kept for comparison. Missing products were not kept.",
+            json!({
+                "normalization": nullable(reference("normalization_method")),
+                "background": nullable(reference("background_method")),
+                "xftf": nullable(reference("xftf")),
+                "xftr": nullable(reference("xftr")),
+            }),
+        ),
+        "xas_spectrum": xas_spectrum_definition(),
+        "xas_group": object(
+            "Group of XAS spectra. selected, visible, included and weights have one entry per spectrum.",
+            json!({
+                "spectra": { "type": "array", "items": reference("xas_spectrum") },
+                "selected": { "type": "array", "items": { "type": "boolean" } },
+                "visible": { "type": "array", "items": { "type": "boolean" } },
+                "included": { "type": "array", "items": { "type": "boolean" } },
+                "weights": { "type": "array", "items": { "type": "number", "minimum": 0 } },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "notes": { "type": "array", "items": { "type": "string" } },
+            }),
+        ),
+        "xas_group_file": object(
+            "JSON or BSON file of a group.",
+            json!({
+                "version": { "type": "string" },
+                "schema_version": { "type": "integer", "minimum": 1 },
+                "name": { "type": "string" },
+                "datatype": { "enum": ["XASGroup", "XASSpectrum"] },
+                "data": reference("xas_group"),
+            }),
+        ),
+    })
+}
+
+fn xas_spectrum_definition() -> Value {
+    object(
+        "XAS spectrum. Energies in eV, k in 1/Å and R in Å.",
+        json!({
+            "name": nullable(json!({ "type": "string" })),
+            "raw_energy": array1(),
+            "raw_mu": array1(),
+            "energy": array1(),
+            "mu": array1(),
+            "delta_mu": array1(),
+            "e0": number(),
+            "e0_uncertainty": number(),
+            "k": array1(),
+            "chi": array1(),
+            "chi_kweighted": array1(),
+            "chi_r": array1(),
+            "chi_r_mag": array1(),
+            "chi_r_re": array1(),
+            "chi_r_im": array1(),
+            "q": array1(),
+            "normalization": nullable(reference("normalization_method")),
+            "background": nullable(reference("background_method")),
+            "xftf": nullable(reference("xftf")),
+            "xftr": nullable(reference("xftr")),
+            "metadata": { "type": "object" },
+            "tags": { "type": "array", "items": { "type": "string" } },
+            "notes": { "type": "array", "items": { "type": "string" } },
+            "channels": {
+                "type": "object",
+                "additionalProperties": reference("array1"),
+            },
+            "content_hash": nullable(json!({ "type": "string" })),
+            "processing_options": reference("processing_options"),
+            "snapshots": {
+                "type": "object",
+                "additionalProperties": reference("processing_snapshot"),
+            },
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::io::xasdatatype::XASGroupFile;
+    use crate::xafs::tests::TOP_DIR;
+    use crate::xafs::xasgroup::XASGroup;
+    use crate::xafs::xasspectrum::XASSpectrum;
+
+    /// Minimal validator for the keywords used by the schemas above
+    fn validate(value: &Value, schema: &Value, root: &Value, path: &str) -> Result<(), String> {
+        let schema = schema.as_object().unwrap();
+
+        if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+            let name = reference.trim_start_matches("#/$defs/");
+            validate(value, &root["$defs"][name], root, path)?;
+        }
+
+        if let Some(any_of) = schema.get("anyOf").or(schema.get("oneOf")) {
+            let matches = any_of
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|s| validate(value, s, root, path).is_ok())
+                .count();
+            if matches == 0 {
+                return Err(format!("{}: no matching schema", path));
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return Err(format!("{}: expected {}", path, expected));
+            }
+        }
+
+        if let Some(values) = schema.get("enum") {
+            if !values.as_array().unwrap().contains(value) {
+                return Err(format!("{}: {} is not in the enum", path, value));
+            }
+        }
+
+        if let Some(types) = schema.get("type") {
+            let types = match types {
+                Value::Array(types) => types.iter().map(|t| t.as_str().unwrap()).collect(),
+                t => vec![t.as_str().unwrap()],
+            };
+            let matches = types.iter().any(|t| match *t {
+                "null" => value.is_null(),
+                "boolean" => value.is_boolean(),
+                "string" => value.is_string(),
+                "number" => value.is_number(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => false,
+            });
+            if !matches {
+                return Err(format!("{}: expected {:?}, got {}", path, types, value));
+            }
+        }
+
+        if let (Some(object), Some(properties)) = (value.as_object(), schema.get("properties")) {
+            for (key, item) in object.iter() {
+                match properties.get(key) {
+                    Some(property) => validate(item, property, root, &format!("{}/{}", path, key))?,
+                    None if schema.get("additionalProperties") == Some(&json!(false)) => {
+                        return Err(format!("{}: unknown property {}", path, key))
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        if let (Some(object), Some(additional)) = (
+            value.as_object(),
+            schema.get("additionalProperties").filter(|a| a.is_object()),
+        ) {
+            for (key, item) in object.iter() {
+                validate(item, additional, root, &format!("{}/{}", path, key))?;
+            }
+        }
+
+        if let Some(required) = schema.get("required") {
+            for key in required.as_array().unwrap() {
+                if value.get(key.as_str().unwrap()).is_none() {
+                    return Err(format!("{}: missing {}", path, key));
+                }
+            }
+        }
+
+        if let (Some(array), Some(items)) = (value.as_array(), schema.get("items")) {
+            for (i, item) in array.iter().enumerate() {
+                validate(item, items, root, &format!("{}/{}", path, i))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_schema() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.set_name("Ru").set_metadata("sample", "Ru foil");
+        spectrum.normalize()?.calc_background()?.fft()?.ifft()?;
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(io::load_spectrum_QAS_trans(&path)?);
+
+        let spectrum_schema = xas_spectrum_schema();
+        let value = serde_json::to_value(&spectrum)?;
+        validate(&value, &spectrum_schema, &spectrum_schema, "")?;
+
+        // The processed spectrum, including chi(R) of the forward FT, reads back unchanged
+        let read: XASSpectrum = serde_json::from_value(value.clone())?;
+        assert!(read
+            .xftf
+            .as_ref()
+            .and_then(|xftf| xftf.get_chir())
+            .is_some());
+        assert_eq!(serde_json::to_value(&read)?, value);
+
+        let group_schema = xas_group_schema();
+        let value = serde_json::to_value(&group)?;
+        validate(&value, &group_schema, &group_schema, "")?;
+
+        let mut file = XASGroupFile::new();
+        file.data = group;
+        let file_schema = xas_group_file_schema();
+        let value = serde_json::to_value(&file)?;
+        validate(&value, &file_schema, &file_schema, "")?;
+
+        let mut invalid = serde_json::to_value(&spectrum)?;
+        invalid["normalization"]["PrePostEdge"]["pre_edge_stop"] = json!(-30.0);
+        assert!(validate(&invalid, &spectrum_schema, &spectrum_schema, "").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_published_json_schemas() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join("xraytsubaki_test_schema");
+        let paths = write_json_schemas(&dir)?;
+
+        // Regenerate the published files with write_json_schemas if this fails.
+        for path in paths.iter() {
+            let published = Path::new(TOP_DIR)
+                .join("schema")
+                .join(path.file_name().unwrap());
+            assert_eq!(
+                std::fs::read_to_string(published)?,
+                std::fs::read_to_string(path)?
+            );
+        }
+
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+}
@@ -1,9 +1,18 @@
+use std::error::Error;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use version::version;
 
 use crate::xafs::xasgroup::XASGroup;
 use crate::xafs::xasspectrum::XASSpectrum;
 
+/// Version of the layout of XASGroupFile.
+///
+/// Increase it, and add a step to migrate, when a change of the data model requires files
+/// written by older versions to be converted. Files without a schema_version are version 1.
+pub const SCHEMA_VERSION: u32 = 2;
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub enum XASDataType {
     #[default]
@@ -12,22 +21,135 @@ pub enum XASDataType {
     XASSpectrum,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub struct XASGroupFile {
     pub version: String,
+    pub schema_version: u32,
     pub name: String,
     pub datatype: XASDataType,
     pub data: XASGroup,
 }
 
+impl Default for XASGroupFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl XASGroupFile {
     pub fn new() -> XASGroupFile {
         XASGroupFile {
             version: version!().to_string(),
+            schema_version: SCHEMA_VERSION,
             name: String::new(),
             datatype: XASDataType::XASGroup,
             data: XASGroup::new(),
         }
     }
+
+    /// File from its serialized form, converted with migrate if it has an older schema version.
+    pub fn from_value(value: Value) -> Result<XASGroupFile, Box<dyn Error>> {
+        Ok(serde_json::from_value(migrate(value)?)?)
+    }
+}
+
+/// Convert a serialized XASGroupFile to the current schema version.
+///
+/// Files of a newer schema version than SCHEMA_VERSION are rejected rather than read with
+/// missing fields.
+pub fn migrate(mut value: Value) -> Result<Value, Box<dyn Error>> {
+    let file = value.as_object_mut().ok_or("not an XASGroupFile")?;
+
+    let mut schema_version = match file.get("schema_version") {
+        Some(v) => v.as_u64().ok_or("invalid schema_version")?,
+        None => 1,
+    };
+
+    if schema_version > SCHEMA_VERSION as u64 {
+        return Err(format!(
+            "file has schema version {}, but this version of xraytsubaki reads up to {}",
+            schema_version, SCHEMA_VERSION
+        )
+        .into());
+    }
+
+    while schema_version < SCHEMA_VERSION as u64 {
+        if schema_version == 1 {
+            migrate_v1(file);
+        }
+        schema_version += 1;
+    }
+
+    file.insert("schema_version".to_string(), json!(SCHEMA_VERSION));
+
+    Ok(value)
+}
+
+// Version 1 groups have no selection, visibility, inclusion and weight per spectrum.
+fn migrate_v1(file: &mut Map<String, Value>) {
+    let Some(group) = file.get_mut("data").and_then(Value::as_object_mut) else {
+        return;
+    };
+
+    let n = group
+        .get("spectra")
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len);
+
+    for (key, default) in [
+        ("selected", json!(false)),
+        ("visible", json!(true)),
+        ("included", json!(true)),
+        ("weights", json!(1.0)),
+    ] {
+        if let Some(flags) = group.entry(key).or_insert_with(|| json!([])).as_array_mut() {
+            flags.resize(n, default);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::xafs_bytes::load_group_from_bytes;
+    use crate::xafs::io::xafs_json::XASJson;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_schema_migration() -> Result<(), Box<dyn Error>> {
+        // Version 1: written before the schema version and the group flags were added
+        let path = String::from(TOP_DIR) + "/tests/testfiles/group_v1.json";
+        let mut v1 = XASGroupFile::new();
+        v1.read_json(&path)?;
+        assert_eq!(v1.schema_version, SCHEMA_VERSION);
+        assert_eq!(v1.data.len(), 2);
+        assert_eq!(v1.data.spectra[1].name.as_deref(), Some("scan2"));
+        assert_eq!(v1.data.selected, vec![false, false]);
+        assert_eq!(v1.data.visible, vec![true, true]);
+        assert_eq!(v1.data.weights, vec![1.0, 1.0]);
+        assert_eq!(
+            load_group_from_bytes(&std::fs::read(&path)?)?,
+            v1.data.clone()
+        );
+
+        // Version 2: round trip
+        let save_path = std::env::temp_dir().join("xraytsubaki_test_schema_v2.json");
+        let save_path = save_path.to_str().ok_or("invalid path")?;
+        let mut v2 = XASGroupFile::new();
+        v2.data = v1.data.clone();
+        v2.data.set_selected(1, true)?.set_weight(0, 0.5)?;
+        v2.write_json(save_path)?;
+
+        let mut read = XASGroupFile::new();
+        read.read_json(save_path)?;
+        assert_eq!(read.data, v2.data);
+        std::fs::remove_file(save_path)?;
+
+        let mut future = serde_json::to_value(&v2)?;
+        future["schema_version"] = json!(SCHEMA_VERSION + 1);
+        assert!(XASGroupFile::from_value(future).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,237 @@
+//! Export of a spectrum with the attribute names of a Larch group.
+//!
+//! Larch keeps the results of pre_edge, autobk, xftf and xftr as attributes of a group
+//! (energy, mu, norm, flat, e0, edge_step, k, chi, r, chir_mag, ...). to_larch_dict gives the
+//! same names, so that plotting scripts written for Larch groups work on processed spectra.
+//! Only the results that have been calculated are included. The processing parameters are
+//! nested under pre_edge_details, autobk_details, xftf_details and xftr_details with the
+//! argument names of the Larch functions (pre1, pre2, norm1, norm2, nnorm, rbkg, ...).
+//!
+//! ```
+//! use xraytsubaki::xafs::io;
+//!
+//! let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/testfiles/Ru_QAS.dat").to_string();
+//! let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+//! spectrum.normalize()?;
+//!
+//! let group = spectrum.to_larch_dict();
+//! assert_eq!(group["norm"].as_array().unwrap().len(), 645);
+//! assert!(group["pre_edge_details"]["pre1"].is_number());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+
+use ndarray::{Array1, Zip};
+use serde_json::{json, Value};
+
+use super::background::BackgroundMethod;
+use super::normalization::NormalizationMethod;
+use super::xasspectrum::XASSpectrum;
+
+impl XASSpectrum {
+    /// Arrays of the Larch group by attribute name. Channels are included under their own
+    /// names unless they clash with a Larch attribute.
+    pub fn larch_arrays(&self) -> BTreeMap<String, Array1<f64>> {
+        let mut arrays = BTreeMap::new();
+        let mut insert = |name: &str, array: Option<Array1<f64>>| {
+            if let Some(array) = array {
+                arrays.insert(name.to_string(), array);
+            }
+        };
+
+        insert("energy", self.energy.clone());
+        insert("mu", self.mu.clone());
+
+        if let Some(normalization) = self.normalization.as_ref() {
+            insert("norm", normalization.get_norm().cloned());
+            insert("flat", normalization.get_flat().cloned());
+            if let NormalizationMethod::PrePostEdge(pre_post_edge) = normalization {
+                insert("pre_edge", pre_post_edge.get_pre_edge().cloned());
+                insert("post_edge", pre_post_edge.get_post_edge().cloned());
+            }
+        }
+
+        if let Some(background) = self.background.as_ref() {
+            insert("bkg", background.get_bkg());
+            if let BackgroundMethod::AUTOBK(autobk) = background {
+                insert("chie", autobk.get_chie().map(|c| c.to_owned()));
+            }
+        }
+        insert("k", self.get_k());
+        insert("chi", self.get_chi());
+
+        if let Some(xftf) = self.xftf.as_ref() {
+            insert("kwin", xftf.kwin.clone());
+        }
+        insert("r", self.get_r().map(|r| r.to_owned()));
+        insert("chir_mag", self.get_chir_mag().map(|c| c.to_owned()));
+        let (chir_re, chir_im) = (self.get_chir_real(), self.get_chir_imag());
+        if let (Some(re), Some(im)) = (chir_re.as_ref(), chir_im.as_ref()) {
+            insert(
+                "chir_pha",
+                Some(Zip::from(im).and(re).map_collect(|im, re| im.atan2(*re))),
+            );
+        }
+        insert("chir_re", chir_re);
+        insert("chir_im", chir_im);
+
+        if let Some(xftr) = self.xftr.as_ref() {
+            insert("rwin", xftr.rwin.clone());
+        }
+        insert("q", self.get_q().map(|q| q.to_owned()));
+        insert("chiq_re", self.get_chiq());
+
+        for (name, channel) in self.channels.iter() {
+            if !arrays.contains_key(name) {
+                arrays.insert(name.clone(), channel.clone());
+            }
+        }
+
+        arrays
+    }
+
+    /// Scalars and processing parameters of the Larch group, as a JSON object
+    pub fn larch_scalars(&self) -> Value {
+        let mut scalars = json!({
+            "groupname": self.name,
+            "e0": self.get_e0().or_else(|| self.normalization.as_ref()?.get_e0()),
+            "edge_step": self.normalization.as_ref().and_then(|n| n.get_edge_step()),
+        });
+
+        if let Some(NormalizationMethod::PrePostEdge(pre_post_edge)) = self.normalization.as_ref() {
+            scalars["pre_edge_details"] = json!({
+                "pre1": pre_post_edge.pre_edge_start,
+                "pre2": pre_post_edge.pre_edge_end,
+                "norm1": pre_post_edge.norm_start,
+                "norm2": pre_post_edge.norm_end,
+                "nnorm": pre_post_edge.norm_polyorder,
+                "nvict": pre_post_edge.n_victoreen,
+            });
+        }
+        if let Some(BackgroundMethod::AUTOBK(autobk)) = self.background.as_ref() {
+            scalars["autobk_details"] = json!({
+                "rbkg": autobk.rbkg,
+                "ek0": autobk.ek0,
+                "kmin": autobk.kmin,
+                "kmax": autobk.kmax,
+                "kweight": autobk.kweight,
+                "dk": autobk.dk,
+                "win": autobk.window.name(),
+                "nclamp": autobk.nclamp,
+                "clamp_lo": autobk.clamp_lo,
+                "clamp_hi": autobk.clamp_hi,
+                "nfft": autobk.nfft,
+                "kstep": autobk.kstep,
+            });
+        }
+        if let Some(xftf) = self.xftf.as_ref() {
+            scalars["xftf_details"] = json!({
+                "kmin": xftf.kmin,
+                "kmax": xftf.kmax,
+                "kweight": xftf.kweight,
+                "dk": xftf.dk,
+                "dk2": xftf.dk2,
+                "window": xftf.window.map(|w| w.name()),
+                "rmax_out": xftf.rmax_out,
+                "nfft": xftf.nfft,
+                "kstep": xftf.kstep,
+            });
+        }
+        if let Some(xftr) = self.xftr.as_ref() {
+            scalars["xftr_details"] = json!({
+                "rmin": xftr.rmin,
+                "rmax": xftr.rmax,
+                "rw": xftr.rweight,
+                "dr": xftr.dr,
+                "dr2": xftr.dr2,
+                "window": xftr.window.map(|w| w.name()),
+                "qmax_out": xftr.qmax_out,
+                "nfft": xftr.nfft,
+                "kstep": xftr.kstep,
+            });
+        }
+
+        remove_nulls(&mut scalars);
+        scalars
+    }
+
+    /// larch_scalars with the arrays of larch_arrays as lists, e.g. to be written with
+    /// serde_json and read into a Larch group.
+    pub fn to_larch_dict(&self) -> Value {
+        let mut dict = self.larch_scalars();
+        for (name, array) in self.larch_arrays() {
+            dict[name.as_str()] = array.to_vec().into();
+        }
+
+        dict
+    }
+}
+
+// Parameters that are not set are left out rather than exported as None
+fn remove_nulls(value: &mut Value) {
+    if let Value::Object(map) = value {
+        map.retain(|_, v| !v.is_null());
+        map.values_mut().for_each(remove_nulls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use std::error::Error;
+
+    #[test]
+    fn test_to_larch_dict() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.set_name("Ru");
+
+        let group = spectrum.to_larch_dict();
+        assert_eq!(group["groupname"], "Ru");
+        assert!(group.get("norm").is_none());
+        assert_eq!(group["i0"].as_array().unwrap().len(), 645);
+
+        spectrum.normalize()?.calc_background()?.fft()?.ifft()?;
+        let arrays = spectrum.larch_arrays();
+        for name in [
+            "energy",
+            "mu",
+            "norm",
+            "flat",
+            "pre_edge",
+            "post_edge",
+            "bkg",
+            "chie",
+            "k",
+            "chi",
+            "kwin",
+            "r",
+            "chir_mag",
+            "chir_re",
+            "chir_im",
+            "chir_pha",
+            "rwin",
+            "q",
+            "chiq_re",
+        ] {
+            assert!(arrays.contains_key(name), "{}", name);
+        }
+        assert_eq!(arrays["kwin"].len(), arrays["k"].len());
+        assert_eq!(arrays["chir_pha"].len(), arrays["r"].len());
+
+        let group = spectrum.to_larch_dict();
+        assert_eq!(
+            group["e0"].as_f64(),
+            spectrum.normalization.as_ref().unwrap().get_e0()
+        );
+        assert_eq!(group["autobk_details"]["win"], "hanning");
+        assert!(group["xftf_details"]["kmin"].is_number());
+        assert!(group["xftr_details"]["rmax"].is_number());
+        assert_eq!(group["chi"].as_array().unwrap().len(), arrays["chi"].len());
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt};
+use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt, MinimizationReport};
 use nalgebra::{DMatrix, DVector, Dyn, Owned};
+use serde::{Deserialize, Serialize};
 
 const EPS_F64: f64 = std::f64::EPSILON;
 
@@ -62,12 +63,120 @@ pub fn approx_hessian_nalgebra_f64(
 /// Calculation of the approximate covariance matrix.
 /// The covariance matrix is calculated as the inverse of the approximate Hessian matrix.
 /// This approximation is valid only when the residual function near the minimum.
+#[deprecated(note = "use covariance_from_jacobian_nalgebra_f64 with the Jacobian of the fit")]
 pub fn approx_covariance_matrix_nalgebra_f64(
     x: &DVector<f64>,
     fs: &dyn Fn(&DVector<f64>) -> DVector<f64>,
 ) -> Option<DMatrix<f64>> {
-    let hess = approx_hessian_nalgebra_f64(x, fs);
-    hess.try_inverse()
+    let jac = forward_jacobian_nalgebra_f64(x, fs);
+    covariance_from_jacobian_nalgebra_f64(&jac)
+}
+
+/// Singular value decomposition `m = u * diag(s) * vt`.
+///
+/// With the `lapack` feature the decomposition is delegated to the LAPACK backend,
+/// otherwise the native nalgebra implementation is used.
+/// Only the first `s.len()` columns of `u` are meaningful.
+///
+/// The backend is used by the post-fit algebra (covariances, spline_std, linear least squares).
+/// The Levenberg-Marquardt iterations of AUTOBK run in the levenberg_marquardt crate with its
+/// own QR decomposition and are not affected by the feature.
+#[cfg(feature = "lapack")]
+pub fn svd_nalgebra_f64(m: &DMatrix<f64>) -> Option<(DMatrix<f64>, DVector<f64>, DMatrix<f64>)> {
+    let svd = nalgebra_lapack::SVD::new(m.clone())?;
+    Some((svd.u, svd.singular_values, svd.vt))
+}
+
+/// Singular value decomposition `m = u * diag(s) * vt`.
+///
+/// With the `lapack` feature the decomposition is delegated to the LAPACK backend,
+/// otherwise the native nalgebra implementation is used.
+/// Only the first `s.len()` columns of `u` are meaningful.
+#[cfg(not(feature = "lapack"))]
+pub fn svd_nalgebra_f64(m: &DMatrix<f64>) -> Option<(DMatrix<f64>, DVector<f64>, DMatrix<f64>)> {
+    let svd = m.clone().svd(true, true);
+    Some((svd.u?, svd.singular_values, svd.v_t?))
+}
+
+/// Threshold below which singular values are treated as zero.
+fn singular_value_cutoff(s: &DVector<f64>, nrows: usize, ncols: usize) -> f64 {
+    s.max() * nrows.max(ncols) as f64 * EPS_F64
+}
+
+/// Covariance matrix (J^T J)^-1 calculated from the SVD of the Jacobian matrix.
+/// Returns None if the Jacobian is rank deficient.
+pub fn covariance_from_jacobian_nalgebra_f64(jac: &DMatrix<f64>) -> Option<DMatrix<f64>> {
+    let (_, s, vt) = svd_nalgebra_f64(jac)?;
+
+    if s.len() < jac.ncols() || s.min() <= singular_value_cutoff(&s, jac.nrows(), jac.ncols()) {
+        return None;
+    }
+
+    let vt = vt.rows(0, s.len());
+    let inv_s2 = DMatrix::from_diagonal(&s.map(|x| 1.0 / (x * x)));
+
+    Some(vt.transpose() * inv_s2 * vt)
+}
+
+/// Least-squares solution of `a * x = b` calculated from the SVD of `a`.
+/// Singular values below the numerical cutoff are discarded.
+pub fn lstsq_nalgebra_f64(a: &DMatrix<f64>, b: &DVector<f64>) -> Option<DVector<f64>> {
+    let (u, s, vt) = svd_nalgebra_f64(a)?;
+    let cutoff = singular_value_cutoff(&s, a.nrows(), a.ncols());
+
+    let u = u.columns(0, s.len());
+    let vt = vt.rows(0, s.len());
+    let utb = u.transpose() * b;
+    let inv_s_utb = DVector::from_iterator(
+        s.len(),
+        s.iter()
+            .zip(utb.iter())
+            .map(|(s, x)| if *s > cutoff { x / s } else { 0.0 }),
+    );
+
+    Some(vt.transpose() * inv_s_utb)
+}
+
+/// Parameters and residual norm at one evaluation of the residuals of a Levenberg-Marquardt fit.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LMTraceStep {
+    pub params: Vec<f64>,
+    pub residual_norm: f64,
+}
+
+/// Iteration trace of a Levenberg-Marquardt fit, for debugging its convergence.
+///
+/// The solver does not expose its iterations, so a step is recorded at every evaluation of the
+/// residuals. This includes the trial steps that the solver rejected.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LMTrace {
+    pub steps: Vec<LMTraceStep>,
+    /// Reason for the termination of the fit
+    pub termination: String,
+    /// Whether the termination is considered successful by the solver
+    pub success: bool,
+    pub number_of_evaluations: usize,
+    /// Final value of the objective function |residuals|^2 / 2
+    pub objective_function: f64,
+}
+
+impl LMTrace {
+    pub fn new(steps: Vec<LMTraceStep>, report: &MinimizationReport<f64>) -> LMTrace {
+        LMTrace {
+            steps,
+            termination: format!("{:?}", report.termination),
+            success: report.termination.was_successful(),
+            number_of_evaluations: report.number_of_evaluations,
+            objective_function: report.objective_function,
+        }
+    }
+
+    /// Residual norm of each recorded step
+    pub fn residual_norms(&self) -> Vec<f64> {
+        self.steps.iter().map(|step| step.residual_norm).collect()
+    }
 }
 
 /// Trait for Levenberg-Marquardt parameters.
@@ -87,8 +196,10 @@ impl LMParameters<&dyn Fn(&DVector<f64>) -> DVector<f64>> for DVector<f64> {
         approx_hessian_nalgebra_f64(self, f)
     }
 
+    /// Inverse of the approximate Hessian, obtained from the SVD of the Jacobian, which avoids
+    /// squaring its condition number. Valid only near the minimum of the residuals.
     fn covariance(&self, f: &dyn Fn(&DVector<f64>) -> DVector<f64>) -> Option<DMatrix<f64>> {
-        approx_covariance_matrix_nalgebra_f64(self, f)
+        covariance_from_jacobian_nalgebra_f64(&self.jacobian(f))
     }
 }
 
@@ -132,6 +243,33 @@ mod tests {
         assert_abs_diff_eq!(jac, jac_ref, epsilon = NUM_DIFF_TOL);
     }
 
+    #[test]
+    fn test_covariance_from_jacobian_nalgebra_f64() {
+        let jac = DMatrix::from_row_slice(4, 2, &[1.0, 0.5, 2.0, -1.0, 0.0, 3.0, 1.5, 1.0]);
+        let cov = covariance_from_jacobian_nalgebra_f64(&jac).unwrap();
+        let cov_ref = (jac.transpose() * &jac).try_inverse().unwrap();
+
+        assert_abs_diff_eq!(cov, cov_ref, epsilon = TEST_TOL);
+
+        let singular = DMatrix::from_row_slice(3, 2, &[1.0, 2.0, 2.0, 4.0, 3.0, 6.0]);
+        assert!(covariance_from_jacobian_nalgebra_f64(&singular).is_none());
+    }
+
+    #[test]
+    fn test_lstsq_nalgebra_f64() {
+        let x: DVector<f64> = DVector::from_vec(vec![0.0, 1.0, 2.0, 3.0, 4.0]);
+        let a = DMatrix::from_fn(5, 2, |i, j| x[i].powi(j as i32));
+        let b = x.map(|x| 1.5 - 0.25 * x);
+
+        let coefs = lstsq_nalgebra_f64(&a, &b).unwrap();
+
+        assert_abs_diff_eq!(
+            coefs,
+            DVector::from_vec(vec![1.5, -0.25]),
+            epsilon = TEST_TOL
+        );
+    }
+
     #[test]
     fn test_center_jacobian_nalgebra_f64() {
         let x = DVector::from_vec(vec![1.0, 2.0, 3.0]);
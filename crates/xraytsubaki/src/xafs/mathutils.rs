@@ -3,11 +3,13 @@ use enterpolation::{
     Generator,
 };
 use errorfunctions::ComplexErrorFunctions;
-use nalgebra::DMatrix;
+use nalgebra::{DMatrix, DVector};
 use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
 use num_complex::Complex64;
 use std::error::Error;
 
+use super::lmutils::lstsq_nalgebra_f64;
+
 #[deny(clippy::reversed_empty_ranges)]
 
 pub trait MathUtils {
@@ -309,9 +311,17 @@ where
     }
 }
 
+/// Indices sorting `v`. Values that are not comparable with themselves (NaN) are sorted last.
 fn argsort<T: PartialOrd>(v: &[T]) -> Vec<usize> {
+    let unordered = |x: &T| x.partial_cmp(x).is_none();
     let mut idx = (0..v.len()).collect::<Vec<_>>();
-    idx.sort_by(|a, b| v[*a].partial_cmp(&v[*b]).unwrap());
+    idx.sort_by(|a, b| {
+        let (a, b) = (&v[*a], &v[*b]);
+        match (unordered(a), unordered(b)) {
+            (false, false) => a.partial_cmp(b).unwrap(),
+            (a, b) => a.cmp(&b),
+        }
+    });
     idx
 }
 
@@ -394,6 +404,265 @@ pub fn index_nearest(array: &[f64], value: &f64) -> Result<usize, Box<dyn Error>
         .0)
 }
 
+/// Average consecutive blocks of `factor` points
+///
+/// The last block may contain fewer points. A factor of 0 or 1 returns a copy of the input.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::mathutils::block_average;
+/// let array = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+/// assert_eq!(block_average(&array, 2), vec![1.5, 3.5, 5.0]);
+/// ```
+pub fn block_average(array: &[f64], factor: usize) -> Vec<f64> {
+    if factor <= 1 {
+        return array.to_vec();
+    }
+
+    array
+        .chunks(factor)
+        .map(|chunk| chunk.iter().sum::<f64>() / chunk.len() as f64)
+        .collect()
+}
+
+/// Running median over `window` points centered on each point
+///
+/// The window is shrunk at the edges. A window of 0 or 1 returns a copy of the input.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::mathutils::median_filter;
+/// let array = vec![1.0, 9.0, 3.0, 4.0, 5.0];
+/// assert_eq!(median_filter(&array, 3), vec![5.0, 3.0, 4.0, 4.0, 4.5]);
+/// ```
+pub fn median_filter(array: &[f64], window: usize) -> Vec<f64> {
+    window_filter_slice(array, window, WindowFilter::Median, FilterEdge::Shrink)
+}
+
+/// Statistic taken over the window of window_filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFilter {
+    Median,
+    Min,
+    Max,
+}
+
+/// Treatment of the points of the window beyond the ends of the array
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterEdge {
+    /// Use only the points inside the array
+    #[default]
+    Shrink,
+    /// Repeat the end points
+    Nearest,
+    /// Mirror the array at its ends, (c b a | a b c)
+    Reflect,
+}
+
+/// Running median, minimum or maximum over `window` points centered on each point
+///
+/// An even window takes `window / 2` points on each side, like an odd window one point larger.
+/// A window of 0 or 1 returns a copy of the input. NaN is sorted above all numbers.
+///
+/// # Example
+/// ```
+/// use ndarray::array;
+/// use xraytsubaki::xafs::mathutils::{window_filter, FilterEdge, WindowFilter};
+/// let array = array![1.0, 9.0, 3.0, 4.0, 5.0];
+/// let max = window_filter(&array, 3, WindowFilter::Max, FilterEdge::Nearest);
+/// assert_eq!(max, array![9.0, 9.0, 9.0, 5.0, 5.0]);
+/// let min = window_filter(&array, 3, WindowFilter::Min, FilterEdge::Reflect);
+/// assert_eq!(min, array![1.0, 1.0, 3.0, 3.0, 4.0]);
+/// ```
+pub fn window_filter(
+    array: &Array1<f64>,
+    window: usize,
+    filter: WindowFilter,
+    edge: FilterEdge,
+) -> Array1<f64> {
+    Array1::from_vec(window_filter_slice(&array.to_vec(), window, filter, edge))
+}
+
+fn window_filter_slice(
+    array: &[f64],
+    window: usize,
+    filter: WindowFilter,
+    edge: FilterEdge,
+) -> Vec<f64> {
+    if window <= 1 || array.is_empty() {
+        return array.to_vec();
+    }
+
+    let n = array.len() as isize;
+    let half = (window / 2) as isize;
+    let index = |j: isize| -> Option<usize> {
+        let j = match edge {
+            _ if (0..n).contains(&j) => j,
+            FilterEdge::Shrink => return None,
+            FilterEdge::Nearest => j.clamp(0, n - 1),
+            FilterEdge::Reflect if j < 0 => (-j - 1).min(n - 1),
+            FilterEdge::Reflect => (2 * n - j - 1).max(0),
+        };
+        Some(j as usize)
+    };
+
+    let mut values = Vec::with_capacity(2 * half as usize + 1);
+    (0..n)
+        .map(|i| {
+            values.clear();
+            values.extend(
+                ((i - half)..=(i + half))
+                    .filter_map(index)
+                    .map(|j| array[j]),
+            );
+
+            match filter {
+                WindowFilter::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+                WindowFilter::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                WindowFilter::Median => {
+                    values.sort_by(|a, b| a.total_cmp(b));
+                    median_of_sorted(&values)
+                }
+            }
+        })
+        .collect()
+}
+
+fn median_of_sorted(values: &[f64]) -> f64 {
+    let m = values.len();
+    match m {
+        0 => f64::NAN,
+        _ if m % 2 == 1 => values[m / 2],
+        _ => 0.5 * (values[m / 2 - 1] + values[m / 2]),
+    }
+}
+
+/// Estimate of the standard deviation of the deviations from the running median in despike
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpikeSigma {
+    /// 1.4826 times the median absolute deviation of all points, or 1.2533 times their mean
+    /// absolute deviation if the median is zero
+    #[default]
+    Median,
+    /// 1.4826 times the median absolute deviation of the points off the running median,
+    /// leaving out the points that are the median of their window and non-finite deviations
+    OffMedian,
+}
+
+/// Replace spikes by the running median over `window` points.
+///
+/// A point is a spike if it deviates from the running median by more than `threshold` times
+/// the robust standard deviation of the deviations, estimated as given by `sigma`.
+/// Returns the despiked array and the indices of the spikes.
+///
+/// # Example
+/// ```
+/// use ndarray::array;
+/// use xraytsubaki::xafs::mathutils::{despike, SpikeSigma};
+/// let array = array![1.0, 1.1, 0.9, 8.0, 1.0, 1.05, 0.95];
+/// let (despiked, spikes) = despike(&array, 3, 5.0, SpikeSigma::Median);
+/// assert_eq!(spikes, vec![3]);
+/// assert_eq!(despiked[3], 1.0);
+/// ```
+pub fn despike(
+    array: &Array1<f64>,
+    window: usize,
+    threshold: f64,
+    sigma: SpikeSigma,
+) -> (Array1<f64>, Vec<usize>) {
+    let median = window_filter(array, window, WindowFilter::Median, FilterEdge::Reflect);
+    let deviation = array - &median;
+    let mut absolute = deviation
+        .iter()
+        .map(|d| d.abs())
+        .filter(|d| sigma == SpikeSigma::Median || (d.is_finite() && *d != 0.0))
+        .collect::<Vec<f64>>();
+    absolute.sort_by(|a, b| a.total_cmp(b));
+    let sigma = match 1.4826 * median_of_sorted(&absolute) {
+        // More than half of the points lie on the median, e.g. for piecewise linear data
+        0.0 => 1.2533 * absolute.iter().sum::<f64>() / absolute.len() as f64,
+        sigma => sigma,
+    };
+
+    let spikes = (0..array.len())
+        .filter(|&i| sigma > 0.0 && deviation[i].abs() > threshold * sigma)
+        .collect::<Vec<usize>>();
+
+    let mut despiked = array.clone();
+    for &i in spikes.iter() {
+        despiked[i] = median[i];
+    }
+
+    (despiked, spikes)
+}
+
+/// Weighted least-squares polynomial of degree `order`, with the coefficients in increasing
+/// powers of x as in polyfit_rs.
+///
+/// The fit is done in x centered and scaled to [-1, 1], which keeps quadratic fits at
+/// absolute energies well conditioned. Points with zero weight are ignored.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::mathutils::weighted_polyfit;
+/// let x = [0.0, 1.0, 2.0, 3.0];
+/// let y = [1.0, 3.0, 5.0, 100.0];
+/// let coefficients = weighted_polyfit(&x, &y, &[1.0, 1.0, 1.0, 0.0], 1).unwrap();
+/// assert!((coefficients[1] - 2.0).abs() < 1e-10);
+/// ```
+pub fn weighted_polyfit(
+    x: &[f64],
+    y: &[f64],
+    weights: &[f64],
+    order: usize,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    if x.len() != y.len() || x.len() != weights.len() {
+        return Err("x, y and weights must have the same length".into());
+    }
+
+    let used = (0..x.len())
+        .filter(|&i| weights[i] > 0.0 && weights[i].is_finite())
+        .collect::<Vec<usize>>();
+    if used.len() < order + 1 {
+        return Err(format!(
+            "{} points with a positive weight for a polynomial of order {}",
+            used.len(),
+            order
+        )
+        .into());
+    }
+
+    let (xmin, xmax) = used
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &i| {
+            (lo.min(x[i]), hi.max(x[i]))
+        });
+    let center = 0.5 * (xmin + xmax);
+    let scale = match 0.5 * (xmax - xmin) {
+        scale if scale > 0.0 => scale,
+        _ => 1.0,
+    };
+
+    let a = DMatrix::from_fn(used.len(), order + 1, |row, col| {
+        let i = used[row];
+        weights[i].sqrt() * ((x[i] - center) / scale).powi(col as i32)
+    });
+    let b = DVector::from_iterator(used.len(), used.iter().map(|&i| weights[i].sqrt() * y[i]));
+    let scaled = lstsq_nalgebra_f64(&a, &b).ok_or("weighted polynomial fit failed")?;
+
+    // sum_k c_k ((x - center) / scale)^k expanded in powers of x
+    let mut coefficients = vec![0.0; order + 1];
+    for (k, c) in scaled.iter().enumerate() {
+        let mut binomial = 1.0;
+        for (j, coefficient) in coefficients.iter_mut().enumerate().take(k + 1) {
+            *coefficient += c / scale.powi(k as i32) * binomial * (-center).powi((k - j) as i32);
+            binomial *= (k - j) as f64 / (j + 1) as f64;
+        }
+    }
+
+    Ok(coefficients)
+}
+
 #[allow(non_snake_case)]
 pub fn bessel_I0(x: f64) -> f64 {
     let base = x * x / 4.0;
@@ -414,14 +683,38 @@ pub fn bessel_I0(x: f64) -> f64 {
 ///
 ///
 pub fn splev_jacobian(t: Vec<f64>, c: Vec<f64>, k: usize, x: Vec<f64>, e: usize) -> DMatrix<f64> {
+    let mut jacobian = DMatrix::zeros(x.len(), c.len());
+    splev_jacobian_into(&t, c.len(), k, &x, e, &mut jacobian);
+
+    jacobian
+}
+
+/// splev_jacobian for `ncoefs` coefficients, written into `jacobian`
+///
+/// The matrix is resized to x.len() x ncoefs if needed, so that a matrix kept between calls
+/// is only allocated once.
+pub fn splev_jacobian_into(
+    t: &[f64],
+    ncoefs: usize,
+    k: usize,
+    x: &[f64],
+    e: usize,
+    jacobian: &mut DMatrix<f64>,
+) {
+    if jacobian.shape() != (x.len(), ncoefs) {
+        *jacobian = DMatrix::zeros(x.len(), ncoefs);
+    } else {
+        jacobian.fill(0.0);
+    }
+
+    // fpbspl takes the knots as a Vec
+    let t = t.to_vec();
     let k1: usize = k + 1;
     let k2: usize = k1 + 1;
     let nk1: usize = t.len() - k1;
     let tb: f64 = t[k1 - 1];
     let te: f64 = t[nk1];
 
-    let mut derivatives: Vec<Vec<f64>> = vec![vec![0.0; c.len()]; x.len()];
-
     for (i, &arg) in x.iter().enumerate() {
         let mut arg = arg;
         if arg < tb && e == 3 {
@@ -446,18 +739,11 @@ pub fn splev_jacobian(t: Vec<f64>, c: Vec<f64>, k: usize, x: Vec<f64>, e: usize)
         let mut ll = l - k1;
         for j in 1..=k1 {
             ll += 1;
-            if ll - 1 < c.len() {
-                derivatives[i][ll - 1] = h[j - 1];
+            if ll - 1 < ncoefs {
+                jacobian[(i, ll - 1)] = h[j - 1];
             }
         }
     }
-
-    DMatrix::from_vec(
-        derivatives[0].len(),
-        derivatives.len(),
-        derivatives.concat(),
-    )
-    .transpose()
 }
 
 #[cfg(test)]
@@ -738,4 +1024,115 @@ mod tests {
                 assert_abs_diff_eq!(a, b, epsilon = NUMERICAL_TEST_TOL);
             });
     }
+
+    // Deterministic pseudo-random arrays for the property tests of the window filters
+    fn random_arrays() -> Vec<Array1<f64>> {
+        let mut state: u64 = 0x2545f4914f6cdd1d;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        (1..40)
+            .map(|n| Array1::from_iter((0..n).map(|_| (next() * 20.0 - 10.0).round() / 2.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_window_filter_properties() {
+        let edges = [FilterEdge::Shrink, FilterEdge::Nearest, FilterEdge::Reflect];
+
+        for array in random_arrays() {
+            for window in 0..8 {
+                for edge in edges {
+                    let min = window_filter(&array, window, WindowFilter::Min, edge);
+                    let max = window_filter(&array, window, WindowFilter::Max, edge);
+                    let median = window_filter(&array, window, WindowFilter::Median, edge);
+                    assert_eq!(median.len(), array.len());
+
+                    // min <= median <= max, and the window contains the point itself
+                    for i in 0..array.len() {
+                        assert!(min[i] <= median[i] && median[i] <= max[i]);
+                        assert!(min[i] <= array[i] && array[i] <= max[i]);
+                    }
+
+                    // min is max of the negated array
+                    let negated = window_filter(&-&array, window, WindowFilter::Max, edge);
+                    assert_eq!(min, -negated);
+
+                    if window <= 1 {
+                        assert_eq!(median, array);
+                    }
+                }
+
+                // Brute force with shrinking windows
+                let half = window as isize / 2;
+                let expected = (0..array.len() as isize)
+                    .map(|i| {
+                        ((i - half).max(0)..=(i + half).min(array.len() as isize - 1))
+                            .map(|j| array[j as usize])
+                            .fold(f64::NEG_INFINITY, f64::max)
+                    })
+                    .collect::<Array1<f64>>();
+                if window > 1 {
+                    assert_eq!(
+                        window_filter(&array, window, WindowFilter::Max, FilterEdge::Shrink),
+                        expected
+                    );
+                }
+            }
+
+            // A constant array is left unchanged
+            let constant = Array1::from_elem(array.len(), 2.5);
+            for edge in edges {
+                assert_eq!(
+                    window_filter(&constant, 5, WindowFilter::Median, edge),
+                    constant
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_despike() {
+        // Approximately normal noise from sums of 12 uniform numbers
+        let uniform = random_arrays().into_iter().flatten().collect::<Vec<f64>>();
+        let normal = uniform
+            .chunks(12)
+            .map(|c| c.iter().sum::<f64>() / 5.0)
+            .collect::<Vec<f64>>();
+
+        for noise in normal
+            .chunks_exact(30)
+            .map(|c| Array1::from_vec(c.to_vec()))
+        {
+            let signal = Array1::linspace(0.0, 3.0, noise.len()).mapv(f64::sin) + &noise;
+            let (despiked, spikes) = despike(&signal, 5, 8.0, SpikeSigma::Median);
+            assert!(spikes.is_empty());
+            assert_eq!(despiked, signal);
+
+            let mut spiky = signal.clone();
+            let index = noise.len() / 2;
+            spiky[index] += 100.0;
+            let (despiked, spikes) = despike(&spiky, 5, 8.0, SpikeSigma::Median);
+            assert_eq!(spikes, vec![index]);
+            assert!((despiked[index] - signal[index]).abs() < 10.0);
+        }
+
+        // Piecewise linear data without noise
+        let mut line = Array1::linspace(0.0, 1.0, 11);
+        line[5] += 1.0;
+        assert_eq!(despike(&line, 3, 5.0, SpikeSigma::Median).1, vec![5]);
+
+        // Most of the deviations are zero, which leaves only the off median points for sigma
+        let mut steps = Array1::from_iter((0..48).map(|i| (i / 8) as f64));
+        for (index, step) in [(4, 0.1), (12, -0.1), (20, 0.12), (28, -0.08), (36, 1.0)] {
+            steps[index] += step;
+        }
+        let (despiked, spikes) = despike(&steps, 5, 5.0, SpikeSigma::OffMedian);
+        assert_eq!(spikes, vec![36]);
+        assert_eq!(despiked[36], 4.0);
+    }
 }
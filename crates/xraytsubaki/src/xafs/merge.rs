@@ -0,0 +1,357 @@
+//! Merging of repeated scans.
+//!
+//! The spectra are interpolated onto the energy grid of the master spectrum and averaged with
+//! the chosen weighting. The merged spectrum records the weighting and the weights in its
+//! metadata under MERGE_KEY.
+
+use std::error::Error;
+use std::fmt;
+
+use itertools::Itertools;
+use ndarray::{Array1, Array2, Axis};
+use serde::{Deserialize, Serialize};
+
+use super::mathutils::MathUtils;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Metadata key of the provenance of a merged spectrum
+pub const MERGE_KEY: &str = "merge";
+
+/// Weighting of the spectra in a merge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeWeighting {
+    /// Straight average
+    #[default]
+    Equal,
+    /// Mean i0 counts of each scan. Requires the "i0" channel.
+    I0,
+    /// Inverse variance, per point from delta_mu if every spectrum has it, otherwise per scan
+    /// from the scatter of each scan around the straight average.
+    InverseVariance,
+    /// Edge step of each scan. Requires the spectra to be normalized.
+    EdgeStep,
+}
+
+impl fmt::Display for MergeWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MergeWeighting::Equal => write!(f, "equal"),
+            MergeWeighting::I0 => write!(f, "i0"),
+            MergeWeighting::InverseVariance => write!(f, "inverse_variance"),
+            MergeWeighting::EdgeStep => write!(f, "edge_step"),
+        }
+    }
+}
+
+impl XASGroup {
+    /// Merge the spectra at `master` and `slave` with `weighting` and add the merged spectrum
+    /// to the end of the group. The original spectra are kept.
+    pub fn merge_weighted(
+        &mut self,
+        master: usize,
+        slave: &[usize],
+        weighting: MergeWeighting,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let merged = self.merged_spectrum(master, slave, weighting)?;
+        self.add_spectrum(merged);
+
+        Ok(self)
+    }
+
+    /// Return the merge of the spectra at `master` and `slave` on the energy grid of `master`.
+    ///
+    /// delta_mu of the merged spectrum is propagated from the inputs if they all have it, and
+    /// otherwise estimated from the weighted scatter of the scans.
+    ///
+    /// Spectra excluded from the group (see XASGroup::set_included) are left out, and the
+    /// energy grid is taken from the first included spectrum if `master` is excluded. The
+    /// weights of the group (see XASGroup::set_weight) multiply those of `weighting`.
+    pub fn merged_spectrum(
+        &self,
+        master: usize,
+        slave: &[usize],
+        weighting: MergeWeighting,
+    ) -> Result<XASSpectrum, Box<dyn Error>> {
+        let indices = std::iter::once(master)
+            .chain(slave.iter().cloned())
+            .unique()
+            .collect::<Vec<usize>>();
+
+        if indices.iter().any(|&i| i >= self.len()) {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        let indices = indices
+            .into_iter()
+            .filter(|&i| self.is_included(i))
+            .collect::<Vec<usize>>();
+
+        if indices.is_empty() {
+            return Err("all the spectra to merge are excluded".into());
+        }
+
+        let spectra = indices
+            .iter()
+            .map(|&i| &self.spectra[i])
+            .collect::<Vec<&XASSpectrum>>();
+
+        let grid = spectra[0].energy.clone().ok_or(XAFSError::NotEnoughData)?;
+
+        let mut mu = Array2::zeros((spectra.len(), grid.len()));
+        for (mut row, spectrum) in mu.outer_iter_mut().zip(spectra.iter()) {
+            row.assign(&interpolate_on(&grid, spectrum, spectrum.mu.as_ref())?);
+        }
+
+        let delta_mu = spectra
+            .iter()
+            .map(|spectrum| interpolate_on(&grid, spectrum, spectrum.delta_mu.as_ref()))
+            .collect::<Result<Vec<Array1<f64>>, Box<dyn Error>>>()
+            .ok();
+
+        let mut weights = weights(&spectra, &mu, delta_mu.as_deref(), weighting)?;
+        for (mut row, &i) in weights.outer_iter_mut().zip(indices.iter()) {
+            row *= self.get_weight(i);
+        }
+        let weight_sum = weights.sum_axis(Axis(0));
+
+        if weight_sum.iter().any(|w| !w.is_normal() || *w < 0.0) {
+            return Err(format!("invalid {} weights for merging", weighting).into());
+        }
+
+        let merged = (&mu * &weights).sum_axis(Axis(0)) / &weight_sum;
+
+        let merged_delta_mu = match delta_mu.as_ref() {
+            Some(delta_mu) => {
+                let mut variance = Array1::<f64>::zeros(grid.len());
+                for (w, d) in weights.outer_iter().zip(delta_mu.iter()) {
+                    variance = variance + (&w * d).mapv(|x| x * x);
+                }
+                variance.mapv(f64::sqrt) / &weight_sum
+            }
+            None => {
+                let n = spectra.len() as f64;
+                let scatter =
+                    ((&mu - &merged).mapv(|x| x * x) * &weights).sum_axis(Axis(0)) / &weight_sum;
+                if spectra.len() > 1 {
+                    (scatter * (n / (n - 1.0)) / n).mapv(f64::sqrt)
+                } else {
+                    scatter
+                }
+            }
+        };
+
+        let names = spectra
+            .iter()
+            .zip(indices.iter())
+            .map(|(spectrum, i)| spectrum.name.clone().unwrap_or_else(|| i.to_string()))
+            .collect::<Vec<String>>();
+        let scan_weights = weights
+            .mean_axis(Axis(1))
+            .ok_or(XAFSError::NotEnoughData)?
+            .to_vec();
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum(grid, merged)
+            .set_name(format!("merge of {}", names.join(", ")))
+            .set_metadata(
+                MERGE_KEY,
+                serde_json::json!({
+                    "spectra": names,
+                    "weighting": weighting.to_string(),
+                    "weights": scan_weights,
+                }),
+            )
+            .set_delta_mu(merged_delta_mu)?;
+
+        if let Some(e0) = spectra[0].get_e0() {
+            spectrum.set_e0(e0);
+        }
+
+        Ok(spectrum)
+    }
+}
+
+fn interpolate_on(
+    grid: &Array1<f64>,
+    spectrum: &XASSpectrum,
+    y: Option<&Array1<f64>>,
+) -> Result<Array1<f64>, Box<dyn Error>> {
+    let energy = spectrum.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+    let y = y.ok_or(XAFSError::NotEnoughData)?;
+
+    if energy.len() != y.len() {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    Ok(grid.interpolate(&energy.to_vec(), &y.to_vec())?)
+}
+
+/// Weight of each spectrum (rows) at each point of the grid (columns)
+fn weights(
+    spectra: &[&XASSpectrum],
+    mu: &Array2<f64>,
+    delta_mu: Option<&[Array1<f64>]>,
+    weighting: MergeWeighting,
+) -> Result<Array2<f64>, Box<dyn Error>> {
+    let scan_weights = |w: Vec<f64>| -> Array2<f64> {
+        let mut weights = Array2::ones(mu.raw_dim());
+        for (mut row, w) in weights.outer_iter_mut().zip(w) {
+            row.fill(w);
+        }
+        weights
+    };
+
+    match weighting {
+        MergeWeighting::Equal => Ok(Array2::ones(mu.raw_dim())),
+        MergeWeighting::I0 => spectra
+            .iter()
+            .map(|spectrum| {
+                spectrum
+                    .get_channel("i0")
+                    .and_then(|i0| i0.mean())
+                    .ok_or_else(|| "i0 weighting requires the i0 channel".into())
+            })
+            .collect::<Result<Vec<f64>, Box<dyn Error>>>()
+            .map(scan_weights),
+        MergeWeighting::EdgeStep => spectra
+            .iter()
+            .map(|spectrum| {
+                spectrum
+                    .normalization
+                    .as_ref()
+                    .and_then(|normalization| normalization.get_edge_step())
+                    .ok_or_else(|| "edge step weighting requires normalized spectra".into())
+            })
+            .collect::<Result<Vec<f64>, Box<dyn Error>>>()
+            .map(scan_weights),
+        MergeWeighting::InverseVariance => match delta_mu {
+            Some(delta_mu) => {
+                let mut weights = Array2::zeros(mu.raw_dim());
+                for (mut row, d) in weights.outer_iter_mut().zip(delta_mu) {
+                    row.assign(&d.mapv(|d| 1.0 / (d * d)));
+                }
+                Ok(weights)
+            }
+            None => {
+                let mean = mu.mean_axis(Axis(0)).ok_or(XAFSError::NotEnoughData)?;
+                let variances = mu
+                    .outer_iter()
+                    .map(|row| (&row - &mean).mapv(|x| x * x).mean().unwrap_or(0.0))
+                    .collect::<Vec<f64>>();
+                let floor = 1e-12 * variances.iter().cloned().fold(0.0, f64::max);
+
+                Ok(scan_weights(
+                    variances.iter().map(|v| 1.0 / v.max(floor)).collect(),
+                ))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TEST_TOL;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    fn group() -> XASGroup {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path).unwrap();
+
+        let mut noisy = spectrum.clone();
+        let noise = Array1::from_iter(
+            (0..spectrum.mu.as_ref().unwrap().len()).map(|i| 0.01 * (i as f64 * 1.3).sin()),
+        );
+        noisy.mu = Some(noisy.mu.unwrap() + &noise);
+        noisy
+            .channels
+            .insert("i0".to_string(), noisy.get_channel("i0").unwrap() * 0.25);
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(spectrum)
+            .add_spectrum(noisy);
+        group
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), Box<dyn Error>> {
+        let mut group = group();
+        let mu = group.spectra[0].mu.clone().unwrap();
+        let noisy = group.spectra[2].mu.clone().unwrap();
+        let i = 101;
+
+        group.merge(0, &[1, 2])?;
+        assert_eq!(group.len(), 4);
+        let merged = group.spectra[3].mu.as_ref().unwrap();
+        assert_abs_diff_eq!(
+            merged[i],
+            (2.0 * mu[i] + noisy[i]) / 3.0,
+            epsilon = TEST_TOL
+        );
+
+        let i0 = group.merged_spectrum(0, &[1, 2], MergeWeighting::I0)?;
+        let merged = i0.mu.as_ref().unwrap();
+        assert_abs_diff_eq!(
+            merged[i],
+            (2.0 * mu[i] + 0.25 * noisy[i]) / 2.25,
+            epsilon = TEST_TOL
+        );
+        assert_eq!(i0.get_metadata(MERGE_KEY).unwrap()["weighting"], "i0");
+
+        // The noisy scan scatters twice as much around the average and gets a quarter weight.
+        let inverse_variance =
+            group.merged_spectrum(0, &[1, 2], MergeWeighting::InverseVariance)?;
+        let merged = inverse_variance.mu.as_ref().unwrap();
+        assert_abs_diff_eq!(merged[i], (8.0 * mu[i] + noisy[i]) / 9.0, epsilon = 1e-6);
+
+        assert!(group
+            .merged_spectrum(0, &[1], MergeWeighting::EdgeStep)
+            .is_err());
+        group.normalize()?;
+        assert!(group
+            .merged_spectrum(0, &[1], MergeWeighting::EdgeStep)
+            .is_ok());
+
+        assert!(group.merge(0, &[5]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_included_and_weights() -> Result<(), Box<dyn Error>> {
+        let mut group = group();
+        let mu = group.spectra[0].mu.clone().unwrap();
+        let noisy = group.spectra[2].mu.clone().unwrap();
+        let i = 101;
+
+        group.set_weight(2, 2.0)?;
+        let weighted = group.merged_spectrum(0, &[1, 2], MergeWeighting::Equal)?;
+        assert_abs_diff_eq!(
+            weighted.mu.as_ref().unwrap()[i],
+            (2.0 * mu[i] + 2.0 * noisy[i]) / 4.0,
+            epsilon = TEST_TOL
+        );
+        assert!(group.set_weight(2, -1.0).is_err());
+        assert!(group.set_weight(2, f64::NAN).is_err());
+
+        group.set_weight(2, 1.0)?.set_included(2, false)?;
+        let excluded = group.merged_spectrum(0, &[1, 2], MergeWeighting::Equal)?;
+        assert_abs_diff_eq!(excluded.mu.as_ref().unwrap()[i], mu[i], epsilon = TEST_TOL);
+        assert!(!group.is_visible(2));
+        assert_eq!(group.included_indices(), vec![0, 1]);
+
+        group.set_included(0, false)?.set_included(1, false)?;
+        assert!(group
+            .merged_spectrum(0, &[1, 2], MergeWeighting::Equal)
+            .is_err());
+
+        Ok(())
+    }
+}
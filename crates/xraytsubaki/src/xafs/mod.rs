@@ -19,17 +19,57 @@ use easyfft::dyn_size::realfft::DynRealDft;
 use ndarray::{ArrayBase, Axis, Ix1, OwnedRepr};
 
 // load dependencies
+pub mod align;
+pub mod athena;
 pub mod background;
+pub mod backlash;
 pub mod bessel_i0;
+pub mod bondvalence;
+pub mod chirpeaks;
+pub mod compare;
+pub mod config;
+pub mod crosssection;
+pub mod dataset;
+pub mod electronyield;
+pub mod events;
+#[cfg(feature = "examples_data")]
+pub mod examples;
+pub mod expression;
+pub mod features;
+pub mod fittemplate;
+pub mod ftfilter;
+pub mod ftresolution;
+pub mod glitch;
+pub mod groupview;
 pub mod io;
+pub mod larch;
 pub mod lmutils;
 pub mod mathutils;
+pub mod merge;
 pub mod normalization;
 pub mod nshare;
+pub mod pca;
+pub mod pipeline;
+pub mod plot;
+pub mod presets;
+pub mod processing;
+pub mod quality;
+pub mod random;
+pub mod report;
+pub mod resolution;
+pub mod sigma2;
+pub mod snapshot;
+pub mod standards;
+pub mod statistics;
+pub mod sweep;
+pub mod units;
+pub mod whiteline;
 pub mod xafsutils;
 pub mod xasgroup;
 pub mod xasparameters;
 pub mod xasspectrum;
+#[cfg(feature = "xraydb")]
+pub mod xraydb;
 pub mod xrayfft;
 
 // Load local traits
@@ -44,6 +84,10 @@ pub enum XAFSError {
     NotEnoughDataForXFTR,
     GroupIndexOutOfRange,
     GroupIsEmpty,
+    DriftCorrectionFailed,
+    TooManyVariables,
+    NoValidNormalizationRange,
+    NonFiniteData,
 }
 
 impl Error for XAFSError {
@@ -54,6 +98,10 @@ impl Error for XAFSError {
             XAFSError::NotEnoughDataForXFTR => "Not enough data for XFTR",
             XAFSError::GroupIndexOutOfRange => "Group index out of range",
             XAFSError::GroupIsEmpty => "Group is empty",
+            XAFSError::DriftCorrectionFailed => "Drift correction failed",
+            XAFSError::TooManyVariables => "More variables than independent points",
+            XAFSError::NoValidNormalizationRange => "No valid normalization range",
+            XAFSError::NonFiniteData => "Data contains NaN or infinite values",
         }
     }
 
@@ -74,6 +122,22 @@ impl fmt::Display for XAFSError {
             XAFSError::NotEnoughDataForXFTR => write!(f, "Not enough data for XFTR"),
             XAFSError::GroupIndexOutOfRange => write!(f, "Group index out of range"),
             XAFSError::GroupIsEmpty => write!(f, "Group is empty"),
+            XAFSError::DriftCorrectionFailed => {
+                write!(
+                    f,
+                    "Drift correction failed: the pre-edge trend crosses zero"
+                )
+            }
+            XAFSError::TooManyVariables => {
+                write!(f, "More variables than independent points in the fit range")
+            }
+            XAFSError::NoValidNormalizationRange => {
+                write!(
+                    f,
+                    "No valid pre-edge or normalization range: too few data points on one side of e0"
+                )
+            }
+            XAFSError::NonFiniteData => write!(f, "Data contains NaN or infinite values"),
         }
     }
 }
@@ -3,16 +3,20 @@
 #![allow(unused_variables)]
 
 // Import standard library dependencies
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt::Debug;
+use std::sync::RwLock;
 
 // Import external dependencies
 use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
 use polyfit_rs::polyfit_rs;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 // Import internal dependencies
 use super::mathutils::{self, MathUtils};
 use super::xafsutils;
+use super::XAFSError;
 
 /// trait for Normalization
 /// it impliments some methods required for nomalization of XAFS data
@@ -31,12 +35,200 @@ pub trait Normalization {
     fn set_edge_step(&mut self, edge_step: Option<f64>) -> &mut Self;
 }
 
+/// Trait for user-defined normalization methods
+///
+/// A type implementing Normalization and this trait can be used as `NormalizationMethod::Custom`
+/// (see `NormalizationMethod::new_custom`), e.g. for a machine-learned flattening. It is then
+/// run by XASSpectrum::normalize, the group processing and AUTOBK like the built-in methods.
+///
+/// The method is serialized as its name and the value returned by `parameters`. To read it
+/// back, a constructor has to be registered under the same name with
+/// `register_normalization_model`.
+pub trait CustomNormalization: Normalization + Clone + Debug + Send + Sync + 'static {
+    /// Name under which the constructor of the method is registered.
+    fn name(&self) -> &str;
+
+    /// Parameters of the method, passed to the registered constructor on deserialization.
+    fn parameters(&self) -> serde_json::Value;
+
+    /// Copy of the processing parameters without the results and the spectrum dependent e0 and
+    /// edge step.
+    fn copy_parameters(&self) -> Self;
+
+    fn get_delta_norm(&self) -> Option<&Array1<f64>> {
+        None
+    }
+
+    fn get_delta_flat(&self) -> Option<&Array1<f64>> {
+        None
+    }
+
+    /// Propagate the uncertainty of mu(E) to norm and flat. Does nothing by default.
+    fn propagate_std(&mut self, delta_mu: &Array1<f64>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Object safe form of CustomNormalization stored in `NormalizationMethod::Custom`
+///
+/// It is implemented for all the types implementing CustomNormalization.
+pub trait NormalizationModel: Debug + Send + Sync {
+    fn name(&self) -> &str;
+    fn parameters(&self) -> serde_json::Value;
+    fn normalize(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    ) -> Result<(), Box<dyn Error>>;
+    fn get_norm(&self) -> Option<&Array1<f64>>;
+    fn get_flat(&self) -> Option<&Array1<f64>>;
+    fn get_edge_step(&self) -> Option<f64>;
+    fn get_e0(&self) -> Option<f64>;
+    fn set_e0(&mut self, e0: Option<f64>);
+    fn set_edge_step(&mut self, edge_step: Option<f64>);
+    fn get_delta_norm(&self) -> Option<&Array1<f64>>;
+    fn get_delta_flat(&self) -> Option<&Array1<f64>>;
+    fn propagate_std(&mut self, delta_mu: &Array1<f64>) -> Result<(), Box<dyn Error>>;
+    fn copy_parameters(&self) -> Box<dyn NormalizationModel>;
+    fn clone_box(&self) -> Box<dyn NormalizationModel>;
+}
+
+impl<T: CustomNormalization> NormalizationModel for T {
+    fn name(&self) -> &str {
+        CustomNormalization::name(self)
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        CustomNormalization::parameters(self)
+    }
+
+    fn normalize(
+        &mut self,
+        energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    ) -> Result<(), Box<dyn Error>> {
+        Normalization::normalize(self, energy, mu)?;
+        Ok(())
+    }
+
+    fn get_norm(&self) -> Option<&Array1<f64>> {
+        Normalization::get_norm(self)
+    }
+
+    fn get_flat(&self) -> Option<&Array1<f64>> {
+        Normalization::get_flat(self)
+    }
+
+    fn get_edge_step(&self) -> Option<f64> {
+        Normalization::get_edge_step(self)
+    }
+
+    fn get_e0(&self) -> Option<f64> {
+        Normalization::get_e0(self)
+    }
+
+    fn set_e0(&mut self, e0: Option<f64>) {
+        Normalization::set_e0(self, e0);
+    }
+
+    fn set_edge_step(&mut self, edge_step: Option<f64>) {
+        Normalization::set_edge_step(self, edge_step);
+    }
+
+    fn get_delta_norm(&self) -> Option<&Array1<f64>> {
+        CustomNormalization::get_delta_norm(self)
+    }
+
+    fn get_delta_flat(&self) -> Option<&Array1<f64>> {
+        CustomNormalization::get_delta_flat(self)
+    }
+
+    fn propagate_std(&mut self, delta_mu: &Array1<f64>) -> Result<(), Box<dyn Error>> {
+        CustomNormalization::propagate_std(self, delta_mu)
+    }
+
+    fn copy_parameters(&self) -> Box<dyn NormalizationModel> {
+        Box::new(CustomNormalization::copy_parameters(self))
+    }
+
+    fn clone_box(&self) -> Box<dyn NormalizationModel> {
+        Box::new(self.clone())
+    }
+}
+
+/// Constructor of a NormalizationModel from its serialized parameters
+pub type NormalizationModelConstructor =
+    fn(serde_json::Value) -> Result<Box<dyn NormalizationModel>, Box<dyn Error>>;
+
+lazy_static::lazy_static! {
+    static ref NORMALIZATION_MODELS: RwLock<HashMap<String, NormalizationModelConstructor>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Register the constructor used to deserialize the normalization method called `name`.
+pub fn register_normalization_model(name: &str, constructor: NormalizationModelConstructor) {
+    NORMALIZATION_MODELS
+        .write()
+        .unwrap()
+        .insert(name.to_string(), constructor);
+}
+
+impl Clone for Box<dyn NormalizationModel> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl PartialEq for Box<dyn NormalizationModel> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+            && self.parameters() == other.parameters()
+            && self.get_e0() == other.get_e0()
+            && self.get_norm() == other.get_norm()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedModel {
+    name: String,
+    parameters: serde_json::Value,
+}
+
+impl Serialize for Box<dyn NormalizationModel> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedModel {
+            name: self.name().to_string(),
+            parameters: self.parameters(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn NormalizationModel> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let model = SerializedModel::deserialize(deserializer)?;
+        let constructor = *NORMALIZATION_MODELS
+            .read()
+            .unwrap()
+            .get(&model.name)
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "normalization model {} is not registered",
+                    model.name
+                ))
+            })?;
+
+        constructor(model.parameters).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Enum for normalization method
 ///
-/// It has two variants, PrePostEdge and MBack.
+/// It has the variants PrePostEdge, MBack and Custom.
 /// PrePostEdge is the standard normalization method used in athena and larch.
 /// MBack is the normalization method described in the paper by Weng et al.
 /// Tsu-Chien Weng, Geoffrey S. Waldo, and James E. Penner-Hahn. A method for normalization of X-ray absorption spectra. Journal of Synchrotron Radiation, 12(4):506–510, Jul 2005. doi:10.1107/S0909049504034193.
+/// Custom holds a user-defined method implementing CustomNormalization.
 ///
 /// # Examples
 ///
@@ -49,6 +241,7 @@ pub trait Normalization {
 pub enum NormalizationMethod {
     PrePostEdge(PrePostEdge),
     MBack(MBack),
+    Custom(Box<dyn NormalizationModel>),
 }
 
 impl Default for NormalizationMethod {
@@ -75,6 +268,25 @@ impl NormalizationMethod {
         NormalizationMethod::MBack(MBack::new())
     }
 
+    pub fn new_custom<T: CustomNormalization>(method: T) -> NormalizationMethod {
+        NormalizationMethod::Custom(Box::new(method))
+    }
+
+    /// Copy of the processing parameters without the results and the spectrum dependent e0 and edge step.
+    pub fn copy_parameters(&self) -> NormalizationMethod {
+        match self {
+            NormalizationMethod::PrePostEdge(pre_post_edge) => {
+                NormalizationMethod::PrePostEdge(pre_post_edge.copy_parameters())
+            }
+            NormalizationMethod::MBack(mback) => {
+                NormalizationMethod::MBack(mback.copy_parameters())
+            }
+            NormalizationMethod::Custom(method) => {
+                NormalizationMethod::Custom(method.copy_parameters())
+            }
+        }
+    }
+
     pub fn fill_parameter(
         &mut self,
         energy: &Array1<f64>,
@@ -87,6 +299,7 @@ impl NormalizationMethod {
             NormalizationMethod::MBack(mback) => {
                 mback.fill_parameter();
             }
+            NormalizationMethod::Custom(_) => {}
         }
 
         Ok(self)
@@ -104,6 +317,9 @@ impl NormalizationMethod {
             NormalizationMethod::MBack(mback) => {
                 mback.normalize(energy, mu)?;
             }
+            NormalizationMethod::Custom(method) => {
+                method.normalize(energy, mu)?;
+            }
         }
 
         Ok(self)
@@ -113,6 +329,7 @@ impl NormalizationMethod {
         match self {
             NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_e0(),
             NormalizationMethod::MBack(mback) => mback.get_e0(),
+            NormalizationMethod::Custom(method) => method.get_e0(),
         }
     }
 
@@ -120,6 +337,7 @@ impl NormalizationMethod {
         match self {
             NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_edge_step(),
             NormalizationMethod::MBack(mback) => mback.get_edge_step(),
+            NormalizationMethod::Custom(method) => method.get_edge_step(),
         }
     }
 
@@ -127,6 +345,7 @@ impl NormalizationMethod {
         match self {
             NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_flat(),
             NormalizationMethod::MBack(mback) => mback.get_flat(),
+            NormalizationMethod::Custom(method) => method.get_flat(),
         }
     }
 
@@ -134,6 +353,39 @@ impl NormalizationMethod {
         match self {
             NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_norm(),
             NormalizationMethod::MBack(mback) => mback.get_norm(),
+            NormalizationMethod::Custom(method) => method.get_norm(),
+        }
+    }
+
+    /// Propagate the uncertainty of mu(E) to norm and flat.
+    /// MBack does not propagate uncertainties, its delta_norm and delta_flat stay None.
+    pub fn propagate_std(&mut self, delta_mu: &Array1<f64>) -> Result<&mut Self, Box<dyn Error>> {
+        match self {
+            NormalizationMethod::PrePostEdge(pre_post_edge) => {
+                pre_post_edge.propagate_std(delta_mu)?;
+            }
+            NormalizationMethod::MBack(_) => {}
+            NormalizationMethod::Custom(method) => {
+                method.propagate_std(delta_mu)?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn get_delta_norm(&self) -> Option<&Array1<f64>> {
+        match self {
+            NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_delta_norm(),
+            NormalizationMethod::MBack(_) => None,
+            NormalizationMethod::Custom(method) => method.get_delta_norm(),
+        }
+    }
+
+    pub fn get_delta_flat(&self) -> Option<&Array1<f64>> {
+        match self {
+            NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge.get_delta_flat(),
+            NormalizationMethod::MBack(_) => None,
+            NormalizationMethod::Custom(method) => method.get_delta_flat(),
         }
     }
 
@@ -145,6 +397,9 @@ impl NormalizationMethod {
             NormalizationMethod::MBack(mback) => {
                 mback.set_e0(e0);
             }
+            NormalizationMethod::Custom(method) => {
+                method.set_e0(e0);
+            }
         }
 
         self
@@ -158,12 +413,128 @@ impl NormalizationMethod {
             NormalizationMethod::MBack(mback) => {
                 mback.set_edge_step(edge_step);
             }
+            NormalizationMethod::Custom(method) => {
+                method.set_edge_step(edge_step);
+            }
         }
 
         self
     }
 }
 
+/// Weighting of the points in the pre-edge line and post-edge polynomial fits of PrePostEdge
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum FitWeighting {
+    /// Every point has the same weight
+    #[default]
+    Uniform,
+    /// Weight of each point of the energy array of the spectrum, e.g. 1 / delta_mu^2
+    Weights(Array1<f64>),
+    /// Iteratively reweighted fit with Tukey's biweight, which ignores glitches and spikes.
+    /// The weights also rise linearly from 0.1 to 1 over `edge_taper` eV from the end of each
+    /// range closest to the edge.
+    Robust { edge_taper: f64 },
+}
+
+impl FitWeighting {
+    const TUKEY_C: f64 = 4.685;
+    const MIN_TAPER_WEIGHT: f64 = 0.1;
+    const MAX_ITERATIONS: usize = 20;
+
+    /// Coefficients of the polynomial of `order` fitted to `y` over energy[p1..p2]. `edge` is
+    /// the energy of the end of the range closest to the edge.
+    fn fit(
+        &self,
+        energy: &Array1<f64>,
+        y: &[f64],
+        (p1, p2): (usize, usize),
+        edge: f64,
+        order: usize,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        let x = energy.slice(ndarray::s![p1..p2]).to_vec();
+
+        match self {
+            FitWeighting::Uniform => Ok(polyfit_rs::polyfit(&x, y, order)?),
+            FitWeighting::Weights(weights) => {
+                if weights.len() != energy.len() {
+                    return Err(format!(
+                        "{} fit weights for a spectrum of {} points",
+                        weights.len(),
+                        energy.len()
+                    )
+                    .into());
+                }
+
+                let weights = weights.slice(ndarray::s![p1..p2]).to_vec();
+                mathutils::weighted_polyfit(&x, y, &weights, order)
+            }
+            FitWeighting::Robust { edge_taper } => {
+                let taper = x
+                    .iter()
+                    .map(|e| {
+                        if *edge_taper > 0.0 {
+                            ((e - edge).abs() / edge_taper)
+                                .clamp(FitWeighting::MIN_TAPER_WEIGHT, 1.0)
+                        } else {
+                            1.0
+                        }
+                    })
+                    .collect::<Vec<f64>>();
+
+                let mut weights = taper.clone();
+                let mut coefficients = mathutils::weighted_polyfit(&x, y, &weights, order)?;
+
+                for _ in 0..FitWeighting::MAX_ITERATIONS {
+                    let residuals = x
+                        .iter()
+                        .zip(y.iter())
+                        .map(|(x, y)| {
+                            y - coefficients
+                                .iter()
+                                .enumerate()
+                                .map(|(j, c)| c * x.powi(j as i32))
+                                .sum::<f64>()
+                        })
+                        .collect::<Vec<f64>>();
+
+                    let mut absolute = residuals.iter().map(|r| r.abs()).collect::<Vec<f64>>();
+                    absolute.sort_by(|a, b| a.total_cmp(b));
+                    let sigma = 1.4826 * absolute[absolute.len() / 2];
+                    if sigma <= 0.0 {
+                        break;
+                    }
+
+                    let updated = residuals
+                        .iter()
+                        .zip(taper.iter())
+                        .map(|(r, t)| {
+                            let u = r / (FitWeighting::TUKEY_C * sigma);
+                            if u.abs() < 1.0 {
+                                t * (1.0 - u * u).powi(2)
+                            } else {
+                                0.0
+                            }
+                        })
+                        .collect::<Vec<f64>>();
+
+                    let change = updated
+                        .iter()
+                        .zip(weights.iter())
+                        .fold(0.0_f64, |max, (a, b)| max.max((a - b).abs()));
+                    weights = updated;
+                    coefficients = mathutils::weighted_polyfit(&x, y, &weights, order)?;
+
+                    if change < 1e-6 {
+                        break;
+                    }
+                }
+
+                Ok(coefficients)
+            }
+        }
+    }
+}
+
 /// PrePostEdge normalization method
 ///
 /// This is the standard normalization method used in athena and larch.
@@ -185,6 +556,12 @@ pub struct PrePostEdge {
     pub flat: Option<Array1<f64>>,
     pub pre_coefficients: Option<Vec<f64>>,
     pub norm_coefficients: Option<Vec<f64>>,
+    pub delta_norm: Option<Array1<f64>>,
+    pub delta_flat: Option<Array1<f64>>,
+    /// Adjustments of the ranges or the polynomial order made by the last normalize
+    pub warnings: Option<Vec<String>>,
+    /// Weighting of the pre-edge and post-edge fits. Default = uniform.
+    pub fit_weighting: Option<FitWeighting>,
 }
 
 impl Default for PrePostEdge {
@@ -204,6 +581,10 @@ impl Default for PrePostEdge {
             flat: None,
             norm_coefficients: None,
             pre_coefficients: None,
+            delta_norm: None,
+            delta_flat: None,
+            warnings: None,
+            fit_weighting: None,
         }
     }
 }
@@ -211,6 +592,20 @@ impl Default for PrePostEdge {
 impl PrePostEdge {
     const MAX_NORM_POLYORDER: i32 = 5;
 
+    /// Copy of the processing parameters without the results and the spectrum dependent e0 and edge step.
+    pub fn copy_parameters(&self) -> PrePostEdge {
+        PrePostEdge {
+            pre_edge_start: self.pre_edge_start,
+            pre_edge_end: self.pre_edge_end,
+            norm_start: self.norm_start,
+            norm_end: self.norm_end,
+            norm_polyorder: self.norm_polyorder,
+            n_victoreen: self.n_victoreen,
+            fit_weighting: self.fit_weighting.clone(),
+            ..PrePostEdge::new()
+        }
+    }
+
     pub fn new() -> PrePostEdge {
         PrePostEdge {
             pre_edge_start: None,
@@ -227,6 +622,10 @@ impl PrePostEdge {
             flat: None,
             norm_coefficients: None,
             pre_coefficients: None,
+            delta_norm: None,
+            delta_flat: None,
+            warnings: None,
+            fit_weighting: None,
         }
     }
 
@@ -245,6 +644,7 @@ impl PrePostEdge {
 
         let ie0 = mathutils::index_nearest(&energy.to_vec(), &self.e0.unwrap())?;
         let e0 = energy[ie0];
+        let relative = |energy: f64| xafsutils::relative_energy(e0, energy);
 
         if self.n_victoreen.is_none() {
             self.n_victoreen = Some(0);
@@ -252,11 +652,11 @@ impl PrePostEdge {
 
         if self.pre_edge_start.is_none() {
             let pre_edge_start = if ie0 > 20 {
-                5.0 * ((energy[1] - e0) / 5.0).round()
+                5.0 * (relative(energy[1]) / 5.0).round()
             } else {
-                2.0 * ((energy[1] - e0) / 2.0).round()
+                2.0 * (relative(energy[1]) / 2.0).round()
             }
-            .max(energy.min() - e0);
+            .max(relative(energy.min()));
 
             self.pre_edge_start = Some(pre_edge_start);
         }
@@ -271,13 +671,13 @@ impl PrePostEdge {
         }
 
         if self.norm_end.is_none() {
-            let norm_end = 5.0 * ((energy.max() - e0) / 5.0).round();
+            let norm_end = 5.0 * (relative(energy.max()) / 5.0).round();
             let norm_end = if norm_end < 0.0 {
-                energy.max() - e0 - norm_end
+                relative(energy.max()) - norm_end
             } else {
                 norm_end
             }
-            .min(energy.max() - e0);
+            .min(relative(energy.max()));
 
             self.norm_end = Some(norm_end);
         }
@@ -314,10 +714,37 @@ impl PrePostEdge {
         Ok(self)
     }
 
+    pub fn set_fit_weighting(&mut self, fit_weighting: Option<FitWeighting>) -> &mut Self {
+        self.fit_weighting = fit_weighting;
+        self
+    }
+
+    pub fn get_fit_weighting(&self) -> Option<&FitWeighting> {
+        self.fit_weighting.as_ref()
+    }
+
     pub fn get_pre_edge_start(&self) -> Option<f64> {
         self.pre_edge_start
     }
 
+    /// Pre-edge range (pre_edge_start, pre_edge_end) in absolute energy.
+    pub fn get_pre_edge_range(&self) -> Option<(f64, f64)> {
+        let e0 = self.e0?;
+        Some((
+            xafsutils::absolute_energy(e0, self.pre_edge_start?),
+            xafsutils::absolute_energy(e0, self.pre_edge_end?),
+        ))
+    }
+
+    /// Normalization range (norm_start, norm_end) in absolute energy.
+    pub fn get_norm_range(&self) -> Option<(f64, f64)> {
+        let e0 = self.e0?;
+        Some((
+            xafsutils::absolute_energy(e0, self.norm_start?),
+            xafsutils::absolute_energy(e0, self.norm_end?),
+        ))
+    }
+
     pub fn get_pre_edge_end(&self) -> Option<f64> {
         self.pre_edge_end
     }
@@ -353,6 +780,37 @@ impl PrePostEdge {
     pub fn get_pre_coefficients(&self) -> Option<&Vec<f64>> {
         self.pre_coefficients.as_ref()
     }
+
+    pub fn get_delta_norm(&self) -> Option<&Array1<f64>> {
+        self.delta_norm.as_ref()
+    }
+
+    pub fn get_delta_flat(&self) -> Option<&Array1<f64>> {
+        self.delta_flat.as_ref()
+    }
+
+    pub fn get_warnings(&self) -> Option<&Vec<String>> {
+        self.warnings.as_ref()
+    }
+
+    /// Propagate the per-point uncertainty of mu(E) through the pre-edge subtraction and flattening.
+    ///
+    /// The pre-edge line and post-edge polynomial are treated as exact, so the uncertainty of norm
+    /// and flat is delta_mu / edge_step. normalize() has to be called before.
+    pub fn propagate_std(&mut self, delta_mu: &Array1<f64>) -> Result<&mut Self, Box<dyn Error>> {
+        let edge_step = self.edge_step.ok_or(XAFSError::NotEnoughData)?;
+
+        if self.norm.as_ref().map(|norm| norm.len()) != Some(delta_mu.len()) {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let delta_norm = delta_mu.mapv(|x| x.abs() / edge_step);
+
+        self.delta_flat = Some(delta_norm.clone());
+        self.delta_norm = Some(delta_norm);
+
+        Ok(self)
+    }
 }
 
 impl Normalization for PrePostEdge {
@@ -361,30 +819,24 @@ impl Normalization for PrePostEdge {
         energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
         mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
     ) -> Result<&mut Self, Box<dyn Error>> {
-        // let (energy, mu): (Vec<f64>, Vec<f64>) = energy
-        //     .iter()
-        //     .zip(mu.iter())
-        //     .filter(|(e, m)| e.is_finite() && m.is_finite())
-        //     .unzip();
-
-        // let energy = Array1::from_vec(energy);
-        // let mu = Array1::from_vec(mu);
+        // Non-finite values are handled by the NonFinitePolicy of the spectrum beforehand.
+        if energy.iter().chain(mu.iter()).any(|x| !x.is_finite()) {
+            return Err(Box::new(XAFSError::NonFiniteData));
+        }
 
-        let (energy, mu) = xafsutils::remove_nan2(energy, mu);
+        let _ = self.fill_parameter(energy, mu)?;
 
-        let _ = self.fill_parameter(&energy, &mu)?;
+        let mut warnings = Vec::new();
+        let ie0 = mathutils::index_nearest(&energy.to_vec(), &self.e0.unwrap())?;
 
-        let p1 = mathutils::index_of(
-            &energy.to_vec(),
-            &(self.pre_edge_start.unwrap() + self.e0.unwrap()),
-        )?;
-        let mut p2 = mathutils::index_nearest(
-            &energy.to_vec(),
-            &(self.pre_edge_end.unwrap() + self.e0.unwrap()),
-        )?;
+        // The pre-edge line needs two points below e0
+        let (pre_edge_start, pre_edge_end) = self.get_pre_edge_range().unwrap();
+        let p1 = mathutils::index_of(&energy.to_vec(), &pre_edge_start)?;
+        let p2 = mathutils::index_nearest(&energy.to_vec(), &pre_edge_end)?;
+        let (p1, p2) = fit_range(energy, (p1, p2), 2, (0, ie0), "pre-edge", &mut warnings)?;
 
         if p2 - p1 < 2 {
-            p2 = energy.len().min(&p1 + 2);
+            return Err(Box::new(XAFSError::NoValidNormalizationRange));
         }
 
         let nvict = self.n_victoreen.unwrap_or(0);
@@ -393,46 +845,48 @@ impl Normalization for PrePostEdge {
         let omu = &mu.slice(ndarray::s![p1..p2])
             * &energy.slice(ndarray::s![p1..p2]).map(|e| e.powi(nvict));
 
-        let (energy_x, mu_x) =
-            xafsutils::remove_nan2(&energy.slice(ndarray::s![p1..p2]).to_owned(), &omu);
-
+        let uniform = FitWeighting::Uniform;
+        let fit_weighting = self.fit_weighting.as_ref().unwrap_or(&uniform);
         let pre_coefficients: Vec<f64> =
-            polyfit_rs::polyfit(&energy_x.to_vec(), &mu_x.to_vec(), 1)?;
+            fit_weighting.fit(energy, &omu.to_vec(), (p1, p2), pre_edge_end, 1)?;
 
         let pre_edge =
-            (&energy * pre_coefficients[1] + pre_coefficients[0]) * &energy.map(|e| e.powi(-nvict));
-
-        let mut p1 = mathutils::index_of(
-            &energy.to_vec(),
-            &(self.norm_start.unwrap() + self.e0.unwrap()),
-        )?;
-        let mut p2 = mathutils::index_nearest(
-            &energy.to_vec(),
-            &(self.norm_end.unwrap() + self.e0.unwrap()),
+            (energy * pre_coefficients[1] + pre_coefficients[0]) * &energy.map(|e| e.powi(-nvict));
+
+        // The post-edge polynomial needs norm_polyorder + 1 points above e0. The order is
+        // lowered if there are not enough of them.
+        let (norm_start, norm_end) = self.get_norm_range().unwrap();
+        let p1 = mathutils::index_of(&energy.to_vec(), &norm_start)?;
+        let p2 = mathutils::index_nearest(&energy.to_vec(), &norm_end)?;
+        let mut norm_polyorder = self.norm_polyorder.unwrap() as usize;
+        let (p1, p2) = fit_range(
+            energy,
+            (p1, p2),
+            norm_polyorder + 1,
+            (ie0 + 1, energy.len()),
+            "normalization",
+            &mut warnings,
         )?;
 
-        if p2 - p1 < 2 {
-            p2 = energy.len().min(&p1 + 2);
-            p1 = energy.len().min(&p1 + 1);
-        }
-
-        let presub = (&mu - &pre_edge)
-            .slice(ndarray::s![p1..p2])
-            .to_vec()
-            .clone();
-        let post_edge_energy = energy.slice(ndarray::s![p1..p2]);
-        let post_coefficients = polyfit_rs::polyfit(
-            &post_edge_energy.to_vec(),
-            &presub,
-            self.norm_polyorder.unwrap() as usize,
-        )?;
+        if p2 - p1 < norm_polyorder + 1 {
+            warnings.push(format!(
+                "normalization polynomial order lowered from {} to {}: only {} points in the normalization range",
+                norm_polyorder,
+                p2 - p1 - 1,
+                p2 - p1
+            ));
+            norm_polyorder = p2 - p1 - 1;
+        }
+
+        let presub = (mu - &pre_edge).slice(ndarray::s![p1..p2]).to_vec().clone();
+        let post_coefficients =
+            fit_weighting.fit(energy, &presub, (p1, p2), norm_start, norm_polyorder)?;
 
         let mut post_edge = pre_edge.clone();
 
         for (i, c) in post_coefficients.iter().enumerate() {
-            post_edge = &post_edge + &energy.map(|e| e.powi(i as i32)) * c.clone();
+            post_edge = &post_edge + energy.map(|e| e.powi(i as i32)) * c.clone();
         }
-        let ie0 = mathutils::index_nearest(&energy.to_vec(), &self.e0.unwrap())?;
         let edge_step = if self.edge_step.is_none() {
             post_edge[ie0] - pre_edge[ie0]
         } else {
@@ -440,7 +894,7 @@ impl Normalization for PrePostEdge {
         }
         .max(1.0e-12);
 
-        let norm = (&mu - &pre_edge) / edge_step;
+        let norm = (mu - &pre_edge) / edge_step;
 
         // let flat_diff = (&post_edge - &mu) / edge_step.clone();
         let flat_residue = (&post_edge - &pre_edge) / edge_step;
@@ -457,6 +911,7 @@ impl Normalization for PrePostEdge {
         self.flat = Some(flat);
         self.norm_coefficients = Some(post_coefficients);
         self.pre_coefficients = Some(pre_coefficients);
+        self.warnings = Some(warnings);
 
         Ok(self)
     }
@@ -490,6 +945,44 @@ impl Normalization for PrePostEdge {
     }
 }
 
+/// Indices [p1, p2) of a fit range with at least `min_points` points.
+///
+/// A range with too few points is expanded within `bounds`, first towards higher and then
+/// towards lower energy, and a warning with the range that is used instead is pushed. If the
+/// bounds hold no point at all, there is no valid range. If they hold fewer than `min_points`,
+/// the whole bounds are returned and the caller has to cope with fewer points.
+fn fit_range(
+    energy: &Array1<f64>,
+    (p1, p2): (usize, usize),
+    min_points: usize,
+    (lo, hi): (usize, usize),
+    name: &str,
+    warnings: &mut Vec<String>,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    if p2 >= p1 + min_points {
+        return Ok((p1, p2));
+    }
+
+    let hi = hi.min(energy.len());
+    if lo >= hi {
+        return Err(Box::new(XAFSError::NoValidNormalizationRange));
+    }
+
+    let p1 = p1.clamp(lo, hi - 1);
+    let p2 = (p1 + min_points).min(hi);
+    let p1 = p2.saturating_sub(min_points).max(lo);
+
+    warnings.push(format!(
+        "{} range expanded to {} .. {} eV: at least {} points are needed",
+        name,
+        energy[p1],
+        energy[p2 - 1],
+        min_points
+    ));
+
+    Ok((p1, p2))
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct MBack {
@@ -520,6 +1013,11 @@ impl MBack {
     pub fn fill_parameter(&mut self) {
         todo!("Implement MBack fill_parameter")
     }
+
+    /// Copy of the processing parameters without the results and the spectrum dependent e0 and edge step.
+    pub fn copy_parameters(&self) -> MBack {
+        MBack::new()
+    }
 }
 
 impl Normalization for MBack {
@@ -598,6 +1096,10 @@ mod tests {
             flat: None,
             norm_coefficients: None,
             pre_coefficients: None,
+            delta_norm: None,
+            delta_flat: None,
+            warnings: None,
+            fit_weighting: None,
         };
 
         assert_abs_diff_eq!(
@@ -637,6 +1139,149 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pre_post_edge_short_scan() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        let energy = xafs_test_group.energy.clone().unwrap();
+        let mu = xafs_test_group.mu.clone().unwrap();
+        let e0 = 22118.8;
+
+        let short = |emin: f64, emax: f64| {
+            let (energy, mu): (Vec<f64>, Vec<f64>) = energy
+                .iter()
+                .zip(mu.iter())
+                .filter(|(e, _)| **e >= e0 + emin && **e <= e0 + emax)
+                .unzip();
+            (Array1::from_vec(energy), Array1::from_vec(mu))
+        };
+
+        let mut pre_post_edge = PrePostEdge::default();
+        pre_post_edge.normalize(&energy, &mu)?;
+        assert_eq!(pre_post_edge.get_warnings(), Some(&vec![]));
+
+        // The default normalization range starts beyond the end of the scan
+        let (energy_short, mu_short) = short(-60.0, 80.0);
+        let mut pre_post_edge = PrePostEdge {
+            e0: Some(e0),
+            ..PrePostEdge::default()
+        };
+        pre_post_edge.normalize(&energy_short, &mu_short)?;
+        let warnings = pre_post_edge.get_warnings().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("normalization range expanded"));
+        assert!(pre_post_edge.get_edge_step().unwrap() > 0.0);
+        assert!(pre_post_edge
+            .get_flat()
+            .unwrap()
+            .iter()
+            .all(|x| x.is_finite()));
+        assert_eq!(pre_post_edge.norm_polyorder, Some(2));
+
+        // Two points above e0 are not enough for a quadratic
+        let n_above = energy_short.iter().filter(|e| **e > e0).count();
+        let (energy_short, mu_short) = (
+            energy_short
+                .slice(ndarray::s![..energy_short.len() - n_above + 2])
+                .to_owned(),
+            mu_short
+                .slice(ndarray::s![..mu_short.len() - n_above + 2])
+                .to_owned(),
+        );
+        let mut pre_post_edge = PrePostEdge {
+            e0: Some(e0),
+            ..PrePostEdge::default()
+        };
+        pre_post_edge.normalize(&energy_short, &mu_short)?;
+        assert!(pre_post_edge
+            .get_warnings()
+            .unwrap()
+            .iter()
+            .any(|w| w.starts_with("normalization polynomial order lowered from 2 to 1")));
+
+        // No pre-edge at all
+        let (energy_short, mu_short) = short(0.0, 400.0);
+        let mut pre_post_edge = PrePostEdge {
+            e0: Some(energy_short[0]),
+            ..PrePostEdge::default()
+        };
+        let error = pre_post_edge
+            .normalize(&energy_short, &mu_short)
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<XAFSError>(),
+            Some(XAFSError::NoValidNormalizationRange)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagate_std() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        let energy = xafs_test_group.energy.clone().unwrap();
+        let mu = xafs_test_group.mu.clone().unwrap();
+
+        let mut pre_post_edge = PrePostEdge::new();
+        pre_post_edge.normalize(&energy, &mu)?;
+        pre_post_edge.propagate_std(&Array1::from_elem(energy.len(), 1e-3))?;
+
+        let edge_step = pre_post_edge.edge_step.unwrap();
+        pre_post_edge
+            .get_delta_norm()
+            .unwrap()
+            .iter()
+            .chain(pre_post_edge.get_delta_flat().unwrap().iter())
+            .for_each(|x| assert_abs_diff_eq!(*x, 1e-3 / edge_step, epsilon = TEST_TOL));
+
+        assert!(pre_post_edge.propagate_std(&Array1::zeros(3)).is_err());
+
+        let mut mback = NormalizationMethod::MBack(MBack::new());
+        mback.propagate_std(&Array1::from_elem(energy.len(), 1e-3))?;
+        assert!(mback.get_delta_norm().is_none());
+        assert!(mback.get_delta_flat().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit_weighting() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        let energy = xafs_test_group.energy.clone().unwrap();
+        let mu = xafs_test_group.mu.clone().unwrap();
+
+        let edge_step = |mu: &Array1<f64>, fit_weighting: Option<FitWeighting>| {
+            let mut pre_post_edge = PrePostEdge::default();
+            pre_post_edge.set_fit_weighting(fit_weighting);
+            pre_post_edge.normalize(&energy, mu)?;
+            Ok::<f64, Box<dyn Error>>(pre_post_edge.get_edge_step().unwrap())
+        };
+
+        let uniform = edge_step(&mu, None)?;
+        let ones = FitWeighting::Weights(Array1::ones(energy.len()));
+        assert_abs_diff_eq!(edge_step(&mu, Some(ones))?, uniform, epsilon = 1e-6);
+        let robust = Some(FitWeighting::Robust { edge_taper: 20.0 });
+        assert_abs_diff_eq!(edge_step(&mu, robust.clone())?, uniform, epsilon = 2e-3);
+
+        // Glitches in the normalization range pull the uniform fit away
+        let mut glitched = mu.clone();
+        let e0 = 22118.8;
+        for (i, e) in energy.iter().enumerate() {
+            if *e > e0 + 200.0 && i % 25 == 0 {
+                glitched[i] += 0.5;
+            }
+        }
+        assert!((edge_step(&glitched, None)? - uniform).abs() > 1e-2);
+        assert_abs_diff_eq!(edge_step(&glitched, robust)?, uniform, epsilon = 2e-3);
+
+        let wrong_length = Some(FitWeighting::Weights(Array1::ones(3)));
+        assert!(edge_step(&mu, wrong_length).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_normalization() {
         let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
@@ -756,6 +1401,10 @@ mod tests {
                 8.446567483044725e-09,
             ]),
             pre_coefficients: Some(vec![-5.29888257e-02, -1.90394518e-07]),
+            delta_norm: None,
+            delta_flat: None,
+            warnings: None,
+            fit_weighting: None,
         };
 
         assert_abs_diff_eq!(
@@ -804,4 +1453,132 @@ mod tests {
                 assert_abs_diff_eq!(a, b, epsilon = ACCEPTABLE_MU_DIFF);
             });
     }
+
+    // Scales mu by the difference between the mean of the last and the first points
+    #[derive(Debug, Clone, Default)]
+    struct EndPointNormalization {
+        npoints: usize,
+        e0: Option<f64>,
+        edge_step: Option<f64>,
+        norm: Option<Array1<f64>>,
+    }
+
+    impl Normalization for EndPointNormalization {
+        fn normalize(
+            &mut self,
+            energy: &ArrayBase<OwnedRepr<f64>, Ix1>,
+            mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+        ) -> Result<&mut Self, Box<dyn Error>> {
+            let n = self.npoints.min(mu.len());
+            let pre = mu.iter().take(n).sum::<f64>() / n as f64;
+            let post = mu.iter().rev().take(n).sum::<f64>() / n as f64;
+
+            if self.e0.is_none() {
+                self.e0 = Some(xafsutils::find_e0(energy.clone(), mu.clone())?);
+            }
+            self.edge_step = Some(post - pre);
+            self.norm = Some(mu.mapv(|m| (m - pre) / (post - pre)));
+
+            Ok(self)
+        }
+
+        fn get_norm(&self) -> Option<&Array1<f64>> {
+            self.norm.as_ref()
+        }
+
+        fn get_flat(&self) -> Option<&Array1<f64>> {
+            self.norm.as_ref()
+        }
+
+        fn get_edge_step(&self) -> Option<f64> {
+            self.edge_step
+        }
+
+        fn get_e0(&self) -> Option<f64> {
+            self.e0
+        }
+
+        fn set_e0(&mut self, e0: Option<f64>) -> &mut Self {
+            self.e0 = e0;
+            self
+        }
+
+        fn set_edge_step(&mut self, edge_step: Option<f64>) -> &mut Self {
+            self.edge_step = edge_step;
+            self
+        }
+    }
+
+    impl CustomNormalization for EndPointNormalization {
+        fn name(&self) -> &str {
+            "end_point"
+        }
+
+        fn parameters(&self) -> serde_json::Value {
+            serde_json::json!({ "npoints": self.npoints })
+        }
+
+        fn copy_parameters(&self) -> Self {
+            EndPointNormalization {
+                npoints: self.npoints,
+                ..Default::default()
+            }
+        }
+    }
+
+    fn end_point(
+        parameters: serde_json::Value,
+    ) -> Result<Box<dyn NormalizationModel>, Box<dyn Error>> {
+        let npoints = parameters["npoints"].as_u64().ok_or("npoints is missing")? as usize;
+
+        Ok(Box::new(EndPointNormalization {
+            npoints,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_custom_normalization() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        spectrum.set_normalization_method(Some(NormalizationMethod::new_custom(
+            EndPointNormalization {
+                npoints: 5,
+                ..Default::default()
+            },
+        )))?;
+        spectrum.normalize()?.calc_background()?;
+
+        let norm = spectrum.normalization.as_ref().unwrap().get_norm().unwrap();
+        let n = norm.len();
+        assert_abs_diff_eq!(norm.slice(ndarray::s![..5]).sum(), 0.0, epsilon = TEST_TOL);
+        assert_abs_diff_eq!(
+            norm.slice(ndarray::s![n - 5..]).sum(),
+            5.0,
+            epsilon = TEST_TOL
+        );
+        assert!(spectrum.get_chi().is_some());
+        assert!(spectrum.normalization.as_ref().unwrap().get_e0().is_some());
+
+        let copy = spectrum.normalization.as_ref().unwrap().copy_parameters();
+        assert_eq!(copy.get_norm(), None);
+
+        register_normalization_model("end_point", end_point);
+        let json = serde_json::to_string(&spectrum.normalization)?;
+        let normalization: Option<NormalizationMethod> = serde_json::from_str(&json)?;
+        match normalization {
+            Some(NormalizationMethod::Custom(method)) => {
+                assert_eq!(method.parameters(), serde_json::json!({ "npoints": 5 }));
+            }
+            _ => panic!("custom normalization was not deserialized"),
+        }
+
+        assert!(serde_json::from_str::<NormalizationMethod>(
+            r#"{"Custom":{"name":"unknown","parameters":null}}"#
+        )
+        .is_err());
+
+        Ok(())
+    }
 }
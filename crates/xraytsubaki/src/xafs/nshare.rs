@@ -1,9 +1,11 @@
+use easyfft::dyn_size::realfft::DynRealDft;
 use nalgebra::{DMatrix, DVector, Dyn};
 use ndarray::{Array1, ArrayBase, Ix1, Ix2, OwnedRepr};
+use num_complex::Complex64;
 
 /// Trait for converting from ndarray to nalgebra
 ///
-/// It is specific to f64 and 1D or 2D arrays.
+/// It is specific to f64 and Complex64, and 1D or 2D arrays.
 /// For more general conversions, you should consider using nshare crate.
 pub trait ToNalgebra {
     type Out;
@@ -22,13 +24,24 @@ impl ToNalgebra for ArrayBase<OwnedRepr<f64>, Ix2> {
     fn into_nalgebra(self) -> Self::Out {
         let nrows = Dyn(self.nrows());
         let ncols = Dyn(self.ncols());
-        DMatrix::from_vec_generic(ncols, nrows, self.into_raw_vec()).transpose()
+        // The raw data is in row-major order only in the standard layout, which e.g. an array
+        // selected along its columns does not have
+        let data = self.as_standard_layout().into_owned().into_raw_vec();
+        DMatrix::from_vec_generic(ncols, nrows, data).transpose()
+    }
+}
+
+impl ToNalgebra for ArrayBase<OwnedRepr<Complex64>, Ix1> {
+    type Out = DVector<Complex64>;
+    fn into_nalgebra(self) -> DVector<Complex64> {
+        DVector::from_vec(self.to_vec())
     }
 }
 
 /// Trait for converting from nalgebra to ndarray
 ///
-/// It is specific to f64 and 1D arrays.
+/// It is specific to f64 and Complex64 1D arrays. The spectrum of a real FFT (DynRealDft)
+/// is converted to its complex frequency bins, from the zero frequency up to nfft/2.
 /// For more general conversions, you should consider using nshare crate.
 pub trait ToNdarray1 {
     type Out;
@@ -42,6 +55,20 @@ impl ToNdarray1 for DVector<f64> {
     }
 }
 
+impl ToNdarray1 for DVector<Complex64> {
+    type Out = ArrayBase<OwnedRepr<Complex64>, Ix1>;
+    fn into_ndarray1(self) -> Self::Out {
+        Array1::from_vec(self.data.as_vec().clone())
+    }
+}
+
+impl ToNdarray1 for &DynRealDft<f64> {
+    type Out = ArrayBase<OwnedRepr<Complex64>, Ix1>;
+    fn into_ndarray1(self) -> Self::Out {
+        Array1::from_iter(self.iter().copied())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,8 +88,30 @@ mod tests {
         assert_eq!(c, a_ref);
         assert_eq!(d, b_ref);
 
+        // Column-major data, as left by select along the columns
+        let f = b.t().as_standard_layout().into_owned().reversed_axes();
+        assert!(!f.is_standard_layout());
+        assert_eq!(f.into_nalgebra(), b_ref);
+
         let a_rev = c.into_ndarray1();
 
         assert_eq!(a, a_rev);
     }
+
+    #[test]
+    fn test_complex_conversion() {
+        use easyfft::prelude::DynRealFft;
+
+        let a = Array1::from_vec(vec![Complex64::new(1.0, 2.0), Complex64::new(3.0, -4.0)]);
+        let b = a.clone().into_nalgebra();
+        assert_eq!(b[1], Complex64::new(3.0, -4.0));
+        assert_eq!(b.into_ndarray1(), a);
+
+        let signal = [1.0, 2.0, 0.0, -1.0];
+        let dft = signal.real_fft();
+        let bins = (&dft).into_ndarray1();
+        assert_eq!(bins.len(), 3);
+        assert!((bins[0] - Complex64::new(2.0, 0.0)).norm() < 1e-12);
+        assert!((bins[1] - Complex64::new(1.0, -3.0)).norm() < 1e-12);
+    }
 }
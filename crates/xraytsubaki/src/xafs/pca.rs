@@ -0,0 +1,381 @@
+//! Principal component analysis of the spectra of a group.
+//!
+//! The spectra are interpolated onto a common grid (see the statistics module), limited to
+//! `xmin..xmax`, the mean spectrum is subtracted and the matrix with one row per spectrum is
+//! decomposed by SVD. The components are the right singular vectors, the scores the weights of
+//! the components in each spectrum and the eigenvalues the variances along the components.
+//!
+//! The number of components needed to describe the group is judged from the explained
+//! variances and the indicator function IND of Malinowski, Anal. Chem. 49 (1977) 612, which
+//! has its minimum at the number of significant components. A candidate standard is tested by
+//! a target transformation: it is projected on the first components, and a standard which is
+//! reproduced within the noise is a possible constituent of the spectra.
+//!
+//! ```
+//! use ndarray::Array1;
+//! use xraytsubaki::xafs::pca::PCAOptions;
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//! use xraytsubaki::xafs::xasspectrum::XASSpectrum;
+//!
+//! // Mixtures of two edges 10 eV apart
+//! let energy = Array1::linspace(8900.0, 9200.0, 601);
+//! let edge = |e0: f64| energy.mapv(|e| 0.5 + ((e - e0) / 3.0).atan() / std::f64::consts::PI);
+//! let (a, b) = (edge(8980.0), edge(8990.0));
+//!
+//! let mut group = XASGroup::new();
+//! for i in 0..5 {
+//!     let fraction = i as f64 / 4.0;
+//!     let mut spectrum = XASSpectrum::new();
+//!     spectrum.set_spectrum(energy.clone(), &a * fraction + &b * (1.0 - fraction));
+//!     group.add_spectrum(spectrum);
+//! }
+//! group.normalize()?;
+//!
+//! let pca = group.pca(&PCAOptions::new())?;
+//! assert!(pca.variances[0] > 0.99);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+
+use ndarray::{s, Array1, Array2, Axis};
+
+use super::groupview::XASGroupView;
+use super::lmutils;
+use super::mathutils::MathUtils;
+use super::nshare::ToNalgebra;
+use super::plot::PlotKind;
+use super::statistics::{common_grid_matrix, STATISTIC_KEY};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Data and range analysed by pca
+#[derive(Debug, Clone, PartialEq)]
+pub struct PCAOptions {
+    /// Data of the spectra, e.g. the normalized mu(E) or k-weighted chi(k). Default = Norm.
+    pub kind: PlotKind,
+    /// Range of x (eV for mu(E), 1/Å for chi(k)). Default = the range covered by all spectra.
+    pub xmin: Option<f64>,
+    pub xmax: Option<f64>,
+    /// Subtract the mean spectrum before the decomposition. Default = true.
+    pub center: Option<bool>,
+}
+
+impl Default for PCAOptions {
+    fn default() -> Self {
+        PCAOptions {
+            kind: PlotKind::Norm,
+            xmin: None,
+            xmax: None,
+            center: Some(true),
+        }
+    }
+}
+
+impl PCAOptions {
+    pub fn new() -> PCAOptions {
+        PCAOptions::default()
+    }
+
+    pub fn set_kind(&mut self, kind: PlotKind) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn set_range(&mut self, xmin: Option<f64>, xmax: Option<f64>) -> &mut Self {
+        self.xmin = xmin;
+        self.xmax = xmax;
+        self
+    }
+
+    pub fn set_center(&mut self, center: Option<bool>) -> &mut Self {
+        self.center = center;
+        self
+    }
+}
+
+/// Result of the principal component analysis of a group
+#[derive(Debug, Clone, PartialEq)]
+pub struct PCAResult {
+    pub kind: PlotKind,
+    /// Indices of the analysed spectra in the group, in the order of the rows of scores
+    pub indices: Vec<usize>,
+    pub x: Array1<f64>,
+    /// Mean spectrum subtracted before the decomposition, zeros if not centered
+    pub mean: Array1<f64>,
+    /// Variance along each component, in decreasing order
+    pub eigenvalues: Array1<f64>,
+    /// Fraction of the total variance explained by each component
+    pub variances: Array1<f64>,
+    /// Orthonormal components on the grid x, one per row
+    pub components: Array2<f64>,
+    /// Weight of each component in each spectrum, one row per spectrum
+    pub scores: Array2<f64>,
+    /// Indicator function IND for 1, 2, ... components
+    pub ind: Array1<f64>,
+}
+
+/// Result of the target transformation of a standard
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetTransform {
+    pub ncomponents: usize,
+    /// Standard on the grid of the PCA
+    pub target: Array1<f64>,
+    /// Projection of the standard on the first ncomponents components
+    pub fit: Array1<f64>,
+    /// Weights of the components in fit
+    pub coefficients: Array1<f64>,
+    /// Mean squared difference between target and fit
+    pub chi_square: f64,
+    /// Sum of the squared differences relative to the sum of the squared target
+    pub r_factor: f64,
+}
+
+impl PCAResult {
+    pub fn n_components(&self) -> usize {
+        self.components.nrows()
+    }
+
+    /// Number of components at the minimum of the indicator function
+    pub fn significant_components(&self) -> usize {
+        self.ind
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i + 1)
+            .unwrap_or(self.n_components())
+    }
+
+    /// Spectra rebuilt from the first `ncomponents` components, one row per spectrum
+    pub fn reconstruct(&self, ncomponents: usize) -> Array2<f64> {
+        let n = ncomponents.min(self.n_components());
+        let (nrows, ncols) = (self.scores.nrows(), self.components.ncols());
+        Array2::from_shape_fn((nrows, ncols), |(i, j)| {
+            (0..n)
+                .map(|k| self.scores[(i, k)] * self.components[(k, j)])
+                .sum::<f64>()
+        }) + &self.mean
+    }
+
+    /// Weights of the first `ncomponents` components in `y`, given on the grid x, and the
+    /// projection of `y` on them
+    pub fn project(
+        &self,
+        y: &Array1<f64>,
+        ncomponents: usize,
+    ) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+        if y.len() != self.x.len() {
+            return Err(format!("{} points for a grid of {}", y.len(), self.x.len()).into());
+        }
+
+        let n = ncomponents.min(self.n_components());
+        let components = self.components.slice(s![..n, ..]);
+        let centered = y - &self.mean;
+        let coefficients = Array1::from_iter(
+            components
+                .outer_iter()
+                .map(|component| (&component * &centered).sum()),
+        );
+        let fit = components
+            .outer_iter()
+            .zip(coefficients.iter())
+            .fold(self.mean.clone(), |fit, (component, weight)| {
+                fit + &component * *weight
+            });
+
+        Ok((coefficients, fit))
+    }
+
+    /// Project the data of the standard, which has to cover the grid, on the first
+    /// `ncomponents` components.
+    pub fn target_transform(
+        &self,
+        standard: &XASSpectrum,
+        ncomponents: usize,
+    ) -> Result<TargetTransform, Box<dyn Error>> {
+        let data = standard.plot_data(self.kind)?;
+        if data.x.min() > self.x.min() || data.x.max() < self.x.max() {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let target = self.x.interpolate(&data.x.to_vec(), &data.y.to_vec())?;
+        let (coefficients, fit) = self.project(&target, ncomponents)?;
+
+        let residual = (&target - &fit).mapv(|r| r * r).sum();
+        let norm = target.mapv(|t| t * t).sum();
+
+        Ok(TargetTransform {
+            ncomponents: coefficients.len(),
+            chi_square: residual / target.len() as f64,
+            r_factor: if norm > 0.0 {
+                residual / norm
+            } else {
+                f64::NAN
+            },
+            target,
+            fit,
+            coefficients,
+        })
+    }
+}
+
+impl XASGroup {
+    /// Principal component analysis of the included spectra. Synthetic spectra added by
+    /// add_statistics are ignored.
+    pub fn pca(&self, options: &PCAOptions) -> Result<PCAResult, Box<dyn Error>> {
+        self.included_view().pca(options)
+    }
+}
+
+impl XASGroupView<'_> {
+    /// Principal component analysis of the spectra of the view. Synthetic spectra added by
+    /// XASGroup::add_statistics are ignored.
+    pub fn pca(&self, options: &PCAOptions) -> Result<PCAResult, Box<dyn Error>> {
+        let (indices, spectra): (Vec<usize>, Vec<&XASSpectrum>) = self
+            .iter()
+            .filter(|(_, spectrum)| spectrum.get_metadata(STATISTIC_KEY).is_none())
+            .unzip();
+
+        if spectra.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let (x, values) = common_grid_matrix(&spectra, options.kind, None)?;
+        let columns = (0..x.len())
+            .filter(|&i| {
+                !matches!(options.xmin, Some(xmin) if x[i] < xmin)
+                    && !matches!(options.xmax, Some(xmax) if x[i] > xmax)
+            })
+            .collect::<Vec<usize>>();
+        if columns.len() < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let x = x.select(Axis(0), &columns);
+        let mut values = values.select(Axis(1), &columns);
+        let centered = options.center.unwrap_or(true);
+        let mean = if centered {
+            values.mean_axis(Axis(0)).ok_or(XAFSError::NotEnoughData)?
+        } else {
+            Array1::zeros(x.len())
+        };
+        values -= &mean;
+
+        let (nrows, ncols) = values.dim();
+        let (u, singular_values, vt) =
+            lmutils::svd_nalgebra_f64(&values.into_nalgebra()).ok_or("SVD did not converge")?;
+
+        let l = singular_values.len();
+        let components = Array2::from_shape_fn((l, ncols), |(i, j)| vt[(i, j)]);
+        let scores = Array2::from_shape_fn((nrows, l), |(i, j)| u[(i, j)] * singular_values[j]);
+
+        let squares = Array1::from_iter(singular_values.iter().map(|s| s * s));
+        let total = squares.sum();
+        let variances = if total > 0.0 {
+            &squares / total
+        } else {
+            Array1::zeros(l)
+        };
+
+        // IND = RE / (l - k)^2 with the real error RE = sqrt(sum of the remaining squared
+        // singular values / (max(nrows, ncols) (l - k))). Centering removes one dimension, whose
+        // singular value of zero is left out.
+        let l = match centered {
+            true => l.min(nrows - 1),
+            false => l,
+        };
+        let ind = Array1::from_iter((1..l).map(|k| {
+            let remaining = squares.slice(s![k..l]).sum();
+            let re = (remaining / (nrows.max(ncols) * (l - k)) as f64).sqrt();
+            re / ((l - k) * (l - k)) as f64
+        }));
+
+        Ok(PCAResult {
+            kind: options.kind,
+            indices,
+            x,
+            mean,
+            eigenvalues: &squares / (nrows - 1) as f64,
+            variances,
+            components,
+            scores,
+            ind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_pca() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let reference = io::load_spectrum_QAS_trans(&path)?;
+        let energy = reference.raw_energy.clone().unwrap();
+        let mu = reference.raw_mu.clone().unwrap();
+
+        // Mixtures of the spectrum and a copy shifted by 5 eV, with a little noise
+        let shifted_mu = energy.interpolate(&(&energy + 5.0).to_vec(), &mu.to_vec())?;
+
+        let mut group = XASGroup::new();
+        for i in 0..6 {
+            let fraction = i as f64 / 5.0;
+            let noise = energy.mapv(|e| 1e-4 * (e * (i + 1) as f64).sin());
+            let mut spectrum = XASSpectrum::new();
+            spectrum.set_spectrum(
+                energy.clone(),
+                &mu * fraction + &shifted_mu * (1.0 - fraction) + noise,
+            );
+            group.add_spectrum(spectrum);
+        }
+        group.set_included(5, false)?;
+        group.normalize()?;
+
+        let mut options = PCAOptions::new();
+        options.set_range(Some(22050.0), Some(22250.0));
+        let pca = group.pca(&options)?;
+
+        assert_eq!(pca.indices, vec![0, 1, 2, 3, 4]);
+        assert_eq!(pca.scores.dim(), (5, pca.n_components()));
+        assert!(pca.x[0] >= 22050.0 && pca.x[pca.x.len() - 1] <= 22250.0);
+        assert!(pca.variances[0] > 0.99);
+        assert_abs_diff_eq!(pca.variances.sum(), 1.0, epsilon = 1e-12);
+        assert!(pca.significant_components() <= 2);
+
+        let rebuilt = pca.reconstruct(pca.n_components());
+        let norm = group.spectra[2].plot_data(PlotKind::Norm)?;
+        let norm = pca.x.interpolate(&norm.x.to_vec(), &norm.y.to_vec())?;
+        for (rebuilt, norm) in rebuilt.row(2).iter().zip(norm.iter()) {
+            assert_abs_diff_eq!(rebuilt, norm, epsilon = 1e-6);
+        }
+
+        // The end members are in the space of the mean and the first component, the spectrum
+        // shifted by 50 eV is not
+        let mut standard = reference.clone();
+        standard.normalize()?;
+        let transform = pca.target_transform(&standard, 1)?;
+        assert!(transform.r_factor < 1e-4);
+
+        let shifted = |shift: f64| -> Result<XASSpectrum, Box<dyn Error>> {
+            let mut spectrum = XASSpectrum::new();
+            spectrum
+                .set_spectrum(&energy + shift, mu.clone())
+                .normalize()?;
+            Ok(spectrum)
+        };
+        let far = pca.target_transform(&shifted(50.0)?, 1)?;
+        assert!(far.r_factor > 10.0 * transform.r_factor);
+
+        // Not covering the grid
+        assert!(pca.target_transform(&shifted(300.0)?, 1).is_err());
+
+        assert!(group.view(&[0])?.pca(&options).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! One-call processing of a whole group.
+//!
+//! XASGroup::process_all runs normalization, background removal and the forward FT on every
+//! spectrum and, unlike the step by step methods of XASGroup, does not stop at the first
+//! failure. The returned ProcessingSummary records which spectra failed, why, and how long each
+//! step took, so that a batch of thousands of spectra can be logged and the failures looked at
+//! afterwards.
+//!
+//! ProcessingParameters::run is the processing sequence shared by process_all, Preset::apply
+//! and process_with_hooks, so that they skip the same steps and report the same errors.
+//!
+//! ```
+//! use xraytsubaki::xafs::pipeline::ProcessingParameters;
+//! use xraytsubaki::xafs::xasgroup::XASGroup;
+//!
+//! let mut group = XASGroup::new();
+//! let summary = group.process_all(&ProcessingParameters::new());
+//! println!("{}", summary);
+//! assert!(summary.is_success());
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::background::BackgroundMethod;
+use super::groupview::XASGroupViewMut;
+use super::normalization::NormalizationMethod;
+use super::presets::Preset;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::XrayFFTF;
+
+/// Parameters applied to every spectrum by process_all. A method which is None keeps the
+/// parameters already set on each spectrum (or the defaults if there are none).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingParameters {
+    pub normalization: Option<NormalizationMethod>,
+    pub background: Option<BackgroundMethod>,
+    pub xftf: Option<XrayFFTF>,
+    /// Stop after the normalization and clear the background and Fourier transforms of the
+    /// spectra, as done by the XANES presets.
+    pub normalize_only: bool,
+}
+
+/// Step of the processing sequence, see ProcessingParameters::run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessingStep {
+    Normalization,
+    Background,
+    Xftf,
+}
+
+impl ProcessingParameters {
+    pub fn new() -> ProcessingParameters {
+        ProcessingParameters::default()
+    }
+
+    pub fn set_normalization(&mut self, normalization: NormalizationMethod) -> &mut Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    pub fn set_background(&mut self, background: BackgroundMethod) -> &mut Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn set_xftf(&mut self, xftf: XrayFFTF) -> &mut Self {
+        self.xftf = Some(xftf);
+        self
+    }
+
+    pub fn set_normalize_only(&mut self, normalize_only: bool) -> &mut Self {
+        self.normalize_only = normalize_only;
+        self
+    }
+
+    /// Copy the parameters onto the spectrum, then normalize, remove the background and
+    /// Fourier transform it, calling `done` with the time taken after each step. The first
+    /// error stops the sequence.
+    ///
+    /// Background and FT are skipped for XANES-only spectra. With normalize_only, they are
+    /// skipped and the results of an earlier processing are cleared.
+    pub fn run<F>(&self, spectrum: &mut XASSpectrum, mut done: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(ProcessingStep, &XASSpectrum, Duration),
+    {
+        self.apply(spectrum)?;
+
+        let start = Instant::now();
+        spectrum.normalize()?;
+        done(ProcessingStep::Normalization, spectrum, start.elapsed());
+
+        if self.normalize_only {
+            spectrum.background = None;
+            spectrum.xftf = None;
+            spectrum.xftr = None;
+            return Ok(());
+        }
+
+        if spectrum.is_xanes_only() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        spectrum.calc_background()?;
+        done(ProcessingStep::Background, spectrum, start.elapsed());
+
+        let start = Instant::now();
+        spectrum.fft()?;
+        done(ProcessingStep::Xftf, spectrum, start.elapsed());
+
+        Ok(())
+    }
+
+    // Copy the parameters onto the spectrum. e0 and ek0 of the spectrum are kept.
+    fn apply(&self, spectrum: &mut XASSpectrum) -> Result<(), Box<dyn Error>> {
+        if let Some(normalization) = self.normalization.as_ref() {
+            spectrum.set_normalization_method(Some(normalization.copy_parameters()))?;
+        }
+        if let Some(background) = self.background.as_ref() {
+            spectrum.set_background_method(Some(background.copy_parameters()))?;
+        }
+        if let Some(xftf) = self.xftf.as_ref() {
+            spectrum.xftf = Some(xftf.copy_parameters());
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Preset> for ProcessingParameters {
+    fn from(preset: Preset) -> Self {
+        ProcessingParameters {
+            normalization: Some(NormalizationMethod::PrePostEdge(preset.normalization())),
+            background: preset.background().map(BackgroundMethod::AUTOBK),
+            xftf: preset.xftf(),
+            normalize_only: preset.background().is_none(),
+        }
+    }
+}
+
+/// Result of the processing of one spectrum. The durations of the steps which did not run are
+/// None.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpectrumOutcome {
+    /// Index of the spectrum in the group
+    pub index: usize,
+    pub name: Option<String>,
+    pub normalization: Option<Duration>,
+    pub background: Option<Duration>,
+    pub xftf: Option<Duration>,
+    /// Message of the error which stopped the processing, None on success
+    pub error: Option<String>,
+}
+
+impl SpectrumOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Time spent on all steps
+    pub fn duration(&self) -> Duration {
+        [self.normalization, self.background, self.xftf]
+            .iter()
+            .flatten()
+            .sum()
+    }
+}
+
+/// Outcome of XASGroup::process_all
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ProcessingSummary {
+    /// One outcome per spectrum, in the order of the group
+    pub outcomes: Vec<SpectrumOutcome>,
+    /// Wall time of the whole run
+    pub elapsed: Duration,
+}
+
+impl ProcessingSummary {
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(SpectrumOutcome::is_success)
+    }
+
+    pub fn succeeded(&self) -> Vec<usize> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.is_success())
+            .map(|outcome| outcome.index)
+            .collect()
+    }
+
+    pub fn failed(&self) -> Vec<&SpectrumOutcome> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| !outcome.is_success())
+            .collect()
+    }
+
+    /// The slowest spectrum, e.g. to find spectra with unusually many points
+    pub fn slowest(&self) -> Option<&SpectrumOutcome> {
+        self.outcomes
+            .iter()
+            .max_by_key(|outcome| outcome.duration())
+    }
+}
+
+impl fmt::Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failed = self.failed();
+        writeln!(
+            f,
+            "processed {} spectra in {:.3} s: {} succeeded, {} failed",
+            self.outcomes.len(),
+            self.elapsed.as_secs_f64(),
+            self.outcomes.len() - failed.len(),
+            failed.len()
+        )?;
+
+        for outcome in failed {
+            writeln!(
+                f,
+                "  [{}] {}: {}",
+                outcome.index,
+                outcome.name.as_deref().unwrap_or("(unnamed)"),
+                outcome.error.as_deref().unwrap_or_default()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Run the steps on one spectrum, recording the time taken by each and the error.
+fn process(
+    index: usize,
+    spectrum: &mut XASSpectrum,
+    parameters: &ProcessingParameters,
+) -> SpectrumOutcome {
+    let mut outcome = SpectrumOutcome {
+        index,
+        name: spectrum.name.clone(),
+        normalization: None,
+        background: None,
+        xftf: None,
+        error: None,
+    };
+
+    let result = parameters.run(spectrum, |step, _, duration| match step {
+        ProcessingStep::Normalization => outcome.normalization = Some(duration),
+        ProcessingStep::Background => outcome.background = Some(duration),
+        ProcessingStep::Xftf => outcome.xftf = Some(duration),
+    });
+
+    outcome.error = result.err().map(|e| e.to_string());
+    outcome
+}
+
+impl XASGroup {
+    /// Apply `parameters` to every spectrum and normalize, remove the background and Fourier
+    /// transform all spectra in parallel. Failures are recorded in the summary instead of
+    /// aborting the run.
+    pub fn process_all(&mut self, parameters: &ProcessingParameters) -> ProcessingSummary {
+        let start = Instant::now();
+
+        let outcomes = self
+            .spectra
+            .par_iter_mut()
+            .enumerate()
+            .map(|(index, spectrum)| process(index, spectrum, parameters))
+            .collect();
+
+        ProcessingSummary {
+            outcomes,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+impl XASGroupViewMut<'_> {
+    /// process_all over the spectra of the view. The outcomes refer to the spectra by their
+    /// index in the parent group.
+    pub fn process_all(&mut self, parameters: &ProcessingParameters) -> ProcessingSummary {
+        let start = Instant::now();
+
+        let outcomes = self
+            .spectra_mut()
+            .into_par_iter()
+            .map(|(index, spectrum)| process(index, spectrum, parameters))
+            .collect();
+
+        ProcessingSummary {
+            outcomes,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use ndarray::Array1;
+
+    #[test]
+    fn test_process_all() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut short = XASSpectrum::new();
+        short
+            .set_spectrum(Array1::range(0.0, 3.0, 1.0), Array1::zeros(3))
+            .set_name("short");
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(short)
+            .add_spectrum(spectrum);
+
+        let summary = group.process_all(&Preset::ExafsStandard.into());
+
+        assert!(!summary.is_success());
+        assert_eq!(summary.succeeded(), vec![0, 2]);
+        let failed = summary.failed();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].index, 1);
+        assert_eq!(failed[0].name.as_deref(), Some("short"));
+        assert!(failed[0].background.is_none());
+        assert!(summary.outcomes[2].xftf.is_some());
+        assert!(summary.to_string().contains("2 succeeded, 1 failed"));
+
+        assert!(group.spectra[2].get_chir_mag().is_some());
+        assert_eq!(group.spectra[0].get_kweight(), Some(&2.0));
+
+        // Same skip rules as Preset::apply
+        let summary = group.process_all(&Preset::XanesQuick.into());
+        assert!(summary.outcomes[0].normalization.is_some());
+        assert!(summary.outcomes[0].background.is_none());
+        assert!(group.spectra[0].get_chi().is_none());
+
+        Ok(())
+    }
+}
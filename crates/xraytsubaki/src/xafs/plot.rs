@@ -0,0 +1,555 @@
+//! Plot-ready arrays derived from an XASSpectrum.
+//!
+//! The arrays are computed the same way regardless of the frontend (GUI, Python bindings or
+//! exported files), so that a plot made with matplotlib matches the one drawn by the crate.
+//! SpectrumView gives the main curve of a space for any kweight, e.g. for interactive kweight
+//! switching.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::str::FromStr;
+
+use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
+
+use super::normalization::NormalizationMethod;
+use super::xafsutils::ftwindow;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::XrayFFTF;
+use super::XAFSError;
+
+/// Kind of data to be plotted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotKind {
+    /// mu(E), with the pre-edge, post-edge and background lines as extras.
+    Mu,
+    /// Normalized mu(E).
+    Norm,
+    /// Flattened mu(E).
+    Flat,
+    /// chi(k) weighted by k^n. `None` uses the kweight of the forward FT.
+    KChi(Option<i32>),
+    /// |chi(R)|
+    ChiRMag,
+    /// Re[chi(R)]
+    ChiRRe,
+    /// Im[chi(R)]
+    ChiRIm,
+    /// Back-transformed chi(q).
+    ChiQ,
+}
+
+impl FromStr for PlotKind {
+    type Err = Box<dyn Error>;
+
+    /// Parse the plot kind from strings such as "mu", "norm", "flat", "chi", "k2chi", "chik",
+    /// "chir_mag", "chir_re", "chir_im" and "chiq".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s.trim().to_lowercase().as_str() {
+            "mu" => PlotKind::Mu,
+            "norm" => PlotKind::Norm,
+            "flat" => PlotKind::Flat,
+            "chi" => PlotKind::KChi(Some(0)),
+            "kchi" => PlotKind::KChi(Some(1)),
+            "chik" => PlotKind::KChi(None),
+            "chir_mag" | "chir" => PlotKind::ChiRMag,
+            "chir_re" => PlotKind::ChiRRe,
+            "chir_im" => PlotKind::ChiRIm,
+            "chiq" => PlotKind::ChiQ,
+            other => match other
+                .strip_prefix('k')
+                .and_then(|x| x.strip_suffix("chi"))
+                .and_then(|x| x.parse::<i32>().ok())
+            {
+                Some(kweight) => PlotKind::KChi(Some(kweight)),
+                None => return Err(format!("Unknown plot kind: {}", s).into()),
+            },
+        };
+
+        Ok(kind)
+    }
+}
+
+/// Space in which a spectrum is viewed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    /// Normalized mu(E)
+    E,
+    /// k-weighted chi(k)
+    K,
+    /// |chi(R)|
+    R,
+    /// Back-transformed chi(q)
+    Q,
+}
+
+impl FromStr for Space {
+    type Err = Box<dyn Error>;
+
+    /// Parse the space from "E", "k", "R" or "q" (case insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "e" | "energy" => Ok(Space::E),
+            "k" => Ok(Space::K),
+            "r" => Ok(Space::R),
+            "q" => Ok(Space::Q),
+            _ => Err(format!("Unknown space: {}", s).into()),
+        }
+    }
+}
+
+/// Main curve of a spectrum in a space for a given kweight.
+///
+/// `kweight` is ignored in energy space. `None` uses the kweight of the forward FT. In R and q
+/// space, a kweight differing from the one of the forward FT recomputes the transforms from
+/// chi(k) with the stored FT parameters, leaving the spectrum untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectrumView {
+    pub space: Space,
+    pub kweight: Option<i32>,
+}
+
+impl SpectrumView {
+    pub fn new(space: Space, kweight: Option<i32>) -> SpectrumView {
+        SpectrumView { space, kweight }
+    }
+
+    /// kweight applied to chi(k) in this view.
+    pub fn get_kweight(&self, spectrum: &XASSpectrum) -> Result<i32, Box<dyn Error>> {
+        match self.kweight {
+            Some(kweight) => Ok(kweight),
+            None => Ok(*spectrum
+                .get_kweight()
+                .ok_or(XAFSError::NotEnoughDataForXFTF)? as i32),
+        }
+    }
+
+    /// Return the (x, y) arrays of the view.
+    pub fn data(
+        &self,
+        spectrum: &XASSpectrum,
+    ) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+        match self.space {
+            Space::E => {
+                let energy = spectrum.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+                let norm = spectrum
+                    .normalization
+                    .as_ref()
+                    .and_then(|normalization| normalization.get_norm())
+                    .ok_or(XAFSError::NotEnoughData)?;
+
+                Ok((energy, norm.clone()))
+            }
+            Space::K => {
+                let k = spectrum.get_k().ok_or(XAFSError::NotEnoughData)?;
+                let chi = spectrum.get_chi().ok_or(XAFSError::NotEnoughData)?;
+                let kweight = self.get_kweight(spectrum)?;
+                let chi = &chi * &k.mapv(|x| x.powi(kweight));
+
+                Ok((k, chi))
+            }
+            Space::R => {
+                let xftf = self.forward_transform(spectrum)?;
+                let r = xftf.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+                let chir_mag = xftf.get_chir_mag().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+                Ok((r.to_owned(), chir_mag.to_owned()))
+            }
+            Space::Q => {
+                let xftr = spectrum
+                    .xftr
+                    .as_ref()
+                    .ok_or(XAFSError::NotEnoughDataForXFTR)?;
+
+                let xftr = match self.forward_transform(spectrum)? {
+                    Cow::Borrowed(_) => Cow::Borrowed(xftr),
+                    Cow::Owned(xftf) => {
+                        let r = xftf.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+                        let chir = xftf.get_chir().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+                        let mut xftr = xftr.copy_parameters();
+                        xftr.xftr(r, chir);
+                        Cow::Owned(xftr)
+                    }
+                };
+
+                let q = xftr.get_q().ok_or(XAFSError::NotEnoughDataForXFTR)?;
+                let chiq = xftr.get_chiq().ok_or(XAFSError::NotEnoughDataForXFTR)?;
+
+                Ok((q.to_owned(), chiq))
+            }
+        }
+    }
+
+    /// Forward FT of the spectrum with the kweight of the view.
+    fn forward_transform<'a>(
+        &self,
+        spectrum: &'a XASSpectrum,
+    ) -> Result<Cow<'a, XrayFFTF>, Box<dyn Error>> {
+        let xftf = spectrum
+            .xftf
+            .as_ref()
+            .ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+        match self.kweight {
+            Some(kweight) if xftf.get_kweight() != Some(&(kweight as f64)) => {
+                let k = spectrum.get_k().ok_or(XAFSError::NotEnoughData)?;
+                let chi = spectrum.get_chi().ok_or(XAFSError::NotEnoughData)?;
+
+                let mut xftf = xftf.copy_parameters();
+                xftf.kweight = Some(kweight as f64);
+                xftf.xftf(k.view(), chi.view());
+
+                Ok(Cow::Owned(xftf))
+            }
+            _ => Ok(Cow::Borrowed(xftf)),
+        }
+    }
+}
+
+/// x and y arrays of a plot, and additional named curves sharing the same x axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlotData {
+    pub x: ArrayBase<OwnedRepr<f64>, Ix1>,
+    pub y: ArrayBase<OwnedRepr<f64>, Ix1>,
+    pub extras: Vec<(String, ArrayBase<OwnedRepr<f64>, Ix1>)>,
+}
+
+impl PlotData {
+    pub fn new(x: Array1<f64>, y: Array1<f64>) -> PlotData {
+        PlotData {
+            x,
+            y,
+            extras: Vec::new(),
+        }
+    }
+
+    fn push_extra<S: Into<String>>(&mut self, name: S, y: Option<Array1<f64>>) {
+        if let Some(y) = y.filter(|y| y.len() == self.x.len()) {
+            self.extras.push((name.into(), y));
+        }
+    }
+
+    pub fn get_extra(&self, name: &str) -> Option<&Array1<f64>> {
+        self.extras.iter().find(|(n, _)| n == name).map(|(_, y)| y)
+    }
+
+    /// Min/max decimation of the data for a plot `pixels` wide.
+    ///
+    /// The x range is split into `pixels` columns and only the first, last, minimum and maximum
+    /// points of y in each column are kept, so that glitches and peaks narrower than a pixel
+    /// stay visible. The extras are reduced to the same points. Data with at most 4 points per
+    /// pixel is returned unchanged.
+    pub fn decimate(&self, pixels: usize) -> PlotData {
+        let n = self.x.len();
+        let xmin = self.x.iter().cloned().fold(f64::INFINITY, f64::min);
+        let xmax = self.x.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        if pixels == 0 || n <= 4 * pixels || xmax <= xmin {
+            return self.clone();
+        }
+
+        let width = (xmax - xmin) / pixels as f64;
+        let column = |x: f64| (((x - xmin) / width) as usize).min(pixels - 1);
+
+        let mut indices = Vec::with_capacity(4 * pixels);
+        let mut start = 0;
+
+        while start < n {
+            let col = column(self.x[start]);
+            let mut end = start + 1;
+            while end < n && column(self.x[end]) == col {
+                end += 1;
+            }
+
+            let imin = (start..end)
+                .min_by(|&a, &b| self.y[a].total_cmp(&self.y[b]))
+                .unwrap();
+            let imax = (start..end)
+                .max_by(|&a, &b| self.y[a].total_cmp(&self.y[b]))
+                .unwrap();
+
+            let mut column_indices = [start, imin, imax, end - 1];
+            column_indices.sort_unstable();
+            indices.extend(
+                column_indices
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, index)| *i == 0 || column_indices[i - 1] != **index)
+                    .map(|(_, index)| *index),
+            );
+
+            start = end;
+        }
+
+        let select = |y: &Array1<f64>| Array1::from_iter(indices.iter().map(|&i| y[i]));
+
+        PlotData {
+            x: select(&self.x),
+            y: select(&self.y),
+            extras: self
+                .extras
+                .iter()
+                .map(|(name, y)| (name.clone(), select(y)))
+                .collect(),
+        }
+    }
+}
+
+impl XASSpectrum {
+    /// Return the data of the requested plot.
+    ///
+    /// Energy plots require `normalize`, k-space plots `calc_background`, and R/q-space plots
+    /// `fft`/`ifft` to have been called. k-space and R-space plots contain the FT window as the
+    /// "window" extra when the transform parameters are set.
+    pub fn plot_data(&self, kind: PlotKind) -> Result<PlotData, Box<dyn Error>> {
+        match kind {
+            PlotKind::Mu => {
+                let energy = self.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+                let mu = self.mu.clone().ok_or(XAFSError::NotEnoughData)?;
+                let mut data = PlotData::new(energy, mu);
+
+                if let Some(NormalizationMethod::PrePostEdge(pre_post_edge)) = &self.normalization {
+                    data.push_extra("pre_edge", pre_post_edge.get_pre_edge().cloned());
+                    data.push_extra("post_edge", pre_post_edge.get_post_edge().cloned());
+                }
+
+                data.push_extra("bkg", self.background.as_ref().and_then(|b| b.get_bkg()));
+
+                Ok(data)
+            }
+            PlotKind::Norm | PlotKind::Flat => {
+                let energy = self.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+                let normalization = self
+                    .normalization
+                    .as_ref()
+                    .ok_or(XAFSError::NotEnoughData)?;
+
+                let (y, delta) = if kind == PlotKind::Norm {
+                    (normalization.get_norm(), self.get_delta_norm())
+                } else {
+                    (normalization.get_flat(), self.get_delta_flat())
+                };
+
+                let mut data = PlotData::new(energy, y.ok_or(XAFSError::NotEnoughData)?.clone());
+                data.push_extra("delta", delta.cloned());
+
+                Ok(data)
+            }
+            PlotKind::KChi(kweight) => {
+                let view = SpectrumView::new(Space::K, kweight);
+                let kweight = view.get_kweight(self)?;
+                let (k, kchi) = view.data(self)?;
+
+                let mut data = PlotData::new(k.clone(), kchi);
+                data.push_extra(
+                    "delta",
+                    self.get_delta_chi()
+                        .map(|x| x * &k.mapv(|x| x.powi(kweight))),
+                );
+
+                if let Some(xftf) = self.xftf.as_ref() {
+                    let window =
+                        ftwindow(&k, xftf.kmin, xftf.kmax, xftf.dk, xftf.dk2, xftf.window)?;
+                    data.push_extra("window", Some(window));
+                }
+
+                Ok(data)
+            }
+            PlotKind::ChiRMag | PlotKind::ChiRRe | PlotKind::ChiRIm => {
+                let (r, chir_mag) = SpectrumView::new(Space::R, None).data(self)?;
+                let y = match kind {
+                    PlotKind::ChiRMag => Some(chir_mag),
+                    PlotKind::ChiRRe => self.get_chir_real(),
+                    _ => self.get_chir_imag(),
+                }
+                .ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+                let mut data = PlotData::new(r.clone(), y);
+
+                if let Some(xftr) = self.xftr.as_ref() {
+                    let window =
+                        ftwindow(&r, xftr.rmin, xftr.rmax, xftr.dr, xftr.dr2, xftr.window)?;
+                    data.push_extra("window", Some(window));
+                }
+
+                Ok(data)
+            }
+            PlotKind::ChiQ => {
+                let (q, chiq) = SpectrumView::new(Space::Q, None).data(self)?;
+
+                Ok(PlotData::new(q, chiq))
+            }
+        }
+    }
+
+    /// plot_data decimated for a plot `pixels` wide, see PlotData::decimate.
+    pub fn plot_data_decimated(
+        &self,
+        kind: PlotKind,
+        pixels: usize,
+    ) -> Result<PlotData, Box<dyn Error>> {
+        Ok(self.plot_data(kind)?.decimate(pixels))
+    }
+
+    /// plot_data for display. Data with more points than the plot_max_points of the processing
+    /// options is decimated to at most that many points, 4 per pixel of PlotData::decimate.
+    /// Analysis should use plot_data, which keeps every point.
+    pub fn display_data(&self, kind: PlotKind) -> Result<PlotData, Box<dyn Error>> {
+        let data = self.plot_data(kind)?;
+
+        match self.processing_options.plot_max_points {
+            Some(max_points) if data.x.len() > max_points => Ok(data.decimate(max_points / 4)),
+            _ => Ok(data),
+        }
+    }
+
+    /// Return the (x, y) arrays of the spectrum in `space` weighted by k^`kweight`.
+    ///
+    /// See SpectrumView.
+    pub fn view(
+        &self,
+        space: Space,
+        kweight: Option<i32>,
+    ) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+        SpectrumView::new(space, kweight).data(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TEST_TOL;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_plot_kind_from_str() {
+        assert_eq!("norm".parse::<PlotKind>().unwrap(), PlotKind::Norm);
+        assert_eq!("chi".parse::<PlotKind>().unwrap(), PlotKind::KChi(Some(0)));
+        assert_eq!(
+            "k2chi".parse::<PlotKind>().unwrap(),
+            PlotKind::KChi(Some(2))
+        );
+        assert_eq!("chik".parse::<PlotKind>().unwrap(), PlotKind::KChi(None));
+        assert_eq!("chir_mag".parse::<PlotKind>().unwrap(), PlotKind::ChiRMag);
+        assert!("k2".parse::<PlotKind>().is_err());
+    }
+
+    #[test]
+    fn test_plot_data() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        assert!(spectrum.plot_data(PlotKind::ChiQ).is_err());
+
+        spectrum.normalize()?.calc_background()?.fft()?;
+
+        let mu = spectrum.plot_data(PlotKind::Mu)?;
+        assert!(mu.get_extra("pre_edge").is_some());
+        assert!(mu.get_extra("bkg").is_some());
+
+        let k2chi = spectrum.plot_data(PlotKind::KChi(Some(2)))?;
+        let k = spectrum.get_k().unwrap();
+        let chi = spectrum.get_chi().unwrap();
+        k2chi
+            .y
+            .iter()
+            .zip(k.iter().zip(chi.iter()))
+            .for_each(|(y, (k, chi))| assert_abs_diff_eq!(y, &(chi * k * k), epsilon = TEST_TOL));
+        assert_eq!(k2chi.get_extra("window").unwrap().len(), k.len());
+
+        let chir_mag = spectrum.plot_data(PlotKind::ChiRMag)?;
+        assert_eq!(chir_mag.x.len(), chir_mag.y.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimate() -> Result<(), Box<dyn Error>> {
+        // QEXAFS-sized data with a single-point glitch
+        let n = 1_000_000;
+        let x = Array1::linspace(0.0, 100.0, n);
+        let mut y = x.mapv(|x: f64| x.sin());
+        y[123_457] = 5.0;
+        y[654_321] = -5.0;
+
+        let mut data = PlotData::new(x.clone(), y.clone());
+        data.push_extra("double", Some(&y * 2.0));
+
+        let decimated = data.decimate(800);
+        assert!(decimated.x.len() <= 4 * 800);
+        assert_eq!(decimated.y.len(), decimated.x.len());
+        assert_eq!(
+            decimated.get_extra("double").unwrap(),
+            &(&decimated.y * 2.0)
+        );
+
+        // Ends and extremes are kept
+        assert_eq!(decimated.x[0], x[0]);
+        assert_eq!(decimated.x[decimated.x.len() - 1], x[n - 1]);
+        assert!(decimated.y.iter().any(|y| *y == 5.0));
+        assert!(decimated.y.iter().any(|y| *y == -5.0));
+        assert!(decimated.x.windows(2).into_iter().all(|w| w[0] < w[1]));
+
+        let small = PlotData::new(Array1::linspace(0.0, 1.0, 100), Array1::zeros(100));
+        assert_eq!(small.decimate(800), small);
+
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?;
+        let mu = spectrum.plot_data_decimated(PlotKind::Mu, 100)?;
+        assert!(mu.x.len() <= 400);
+        assert_eq!(mu.get_extra("pre_edge").unwrap().len(), mu.x.len());
+
+        // Decimated automatically above plot_max_points
+        assert_eq!(
+            spectrum.display_data(PlotKind::Mu)?,
+            spectrum.plot_data(PlotKind::Mu)?
+        );
+        let mut options = spectrum.processing_options.clone();
+        options.set_plot_max_points(Some(400));
+        spectrum.processing_options = options;
+        assert!(spectrum.display_data(PlotKind::Mu)?.x.len() <= 400);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spectrum_view() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?.calc_background()?.fft()?.ifft()?;
+
+        assert_eq!("R".parse::<Space>()?, Space::R);
+        assert!("x".parse::<Space>().is_err());
+
+        let kweight = *spectrum.get_kweight().unwrap() as i32;
+        let (r, chir_mag) = spectrum.view(Space::R, None)?;
+        assert_eq!(
+            (r.view(), chir_mag.view()),
+            (spectrum.get_r().unwrap(), spectrum.get_chir_mag().unwrap())
+        );
+        assert_eq!(spectrum.view(Space::R, Some(kweight))?, (r, chir_mag));
+
+        let (_, chir_k3) = spectrum.view(Space::R, Some(3))?;
+        let (_, chiq_k3) = spectrum.view(Space::Q, Some(3))?;
+        assert_eq!(*spectrum.get_kweight().unwrap() as i32, kweight);
+
+        let mut reference = spectrum.clone();
+        reference.xftf.as_mut().unwrap().kweight = Some(3.0);
+        reference.fft()?.ifft()?;
+        chir_k3
+            .iter()
+            .zip(reference.get_chir_mag().unwrap().iter())
+            .for_each(|(a, b)| assert_abs_diff_eq!(a, b, epsilon = TEST_TOL));
+        chiq_k3
+            .iter()
+            .zip(reference.get_chiq().unwrap().iter())
+            .for_each(|(a, b)| assert_abs_diff_eq!(a, b, epsilon = TEST_TOL));
+
+        let (energy, norm) = spectrum.view(Space::E, Some(3))?;
+        assert_eq!(energy.len(), norm.len());
+
+        Ok(())
+    }
+}
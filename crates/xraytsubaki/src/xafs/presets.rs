@@ -0,0 +1,214 @@
+//! Named bundles of processing parameters.
+//!
+//! A preset replaces the normalization, background and forward FT parameters of a spectrum and
+//! runs the processing, so that spectra processed by different people with the same preset
+//! can be compared directly. Presets are selected by name, e.g. "exafs-standard".
+//!
+//! | preset         | pre-edge (eV) | norm (eV)     | rbkg, clamps     | FT k range, dk, window |
+//! |----------------|---------------|---------------|------------------|------------------------|
+//! | xanes-quick    | -150 to -30   | 50 to end, 1  | -                | -                      |
+//! | exafs-standard | -200 to -30   | 150 to end, 2 | 1.0 Å, 0 / 1     | 2 to 12, 1, Hanning    |
+//! | publication    | -200 to -50   | 150 to end, 2 | 1.0 Å, 1 / 50    | 3 to 12, 2, Kaiser     |
+//! | electron-yield | -20 to -5     | 30 to end, 0  | -                | -                      |
+//!
+//! The k weight of the background and of the FT is 2 for the EXAFS presets. electron-yield
+//! suits the short scans of soft X-ray edges (e.g. the L2,3 edges of 3d metals), normalized to
+//! a constant above both edges.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::background::AUTOBK;
+use super::normalization::PrePostEdge;
+use super::pipeline::ProcessingParameters;
+use super::xafsutils::FTWindow;
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::XrayFFTF;
+
+/// Named processing preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Normalization only, with a linear post-edge line starting close to the edge
+    XanesQuick,
+    /// The usual first look at EXAFS data
+    ExafsStandard,
+    /// Conservative ranges and stronger clamps for figures and fits
+    Publication,
+    /// Normalization only, with the short ranges of soft X-ray (e.g. electron yield) scans
+    ElectronYield,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [
+        Preset::XanesQuick,
+        Preset::ExafsStandard,
+        Preset::Publication,
+        Preset::ElectronYield,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::XanesQuick => "xanes-quick",
+            Preset::ExafsStandard => "exafs-standard",
+            Preset::Publication => "publication",
+            Preset::ElectronYield => "electron-yield",
+        }
+    }
+
+    pub fn normalization(&self) -> PrePostEdge {
+        let (pre_edge_end, norm_start, norm_polyorder) = match self {
+            Preset::XanesQuick => (-30.0, 50.0, 1),
+            Preset::ExafsStandard => (-30.0, 150.0, 2),
+            Preset::Publication => (-50.0, 150.0, 2),
+            Preset::ElectronYield => (-5.0, 30.0, 0),
+        };
+
+        let mut pre_post_edge = PrePostEdge::new();
+        pre_post_edge.pre_edge_start = Some(match self {
+            Preset::XanesQuick => -150.0,
+            Preset::ElectronYield => -20.0,
+            _ => -200.0,
+        });
+        pre_post_edge.pre_edge_end = Some(pre_edge_end);
+        pre_post_edge.norm_start = Some(norm_start);
+        pre_post_edge.norm_polyorder = Some(norm_polyorder);
+        pre_post_edge
+    }
+
+    /// Background parameters, None for XANES presets.
+    pub fn background(&self) -> Option<AUTOBK> {
+        let (clamp_lo, clamp_hi) = match self {
+            Preset::XanesQuick | Preset::ElectronYield => return None,
+            Preset::ExafsStandard => (0, 1),
+            Preset::Publication => (1, 50),
+        };
+
+        Some(AUTOBK {
+            rbkg: Some(1.0),
+            kweight: Some(2),
+            clamp_lo: Some(clamp_lo),
+            clamp_hi: Some(clamp_hi),
+            ..AUTOBK::default()
+        })
+    }
+
+    /// Forward FT parameters, None for XANES presets.
+    pub fn xftf(&self) -> Option<XrayFFTF> {
+        let (kmin, dk, window) = match self {
+            Preset::XanesQuick | Preset::ElectronYield => return None,
+            Preset::ExafsStandard => (2.0, 1.0, FTWindow::Hanning),
+            Preset::Publication => (3.0, 2.0, FTWindow::KaiserBessel),
+        };
+
+        Some(XrayFFTF {
+            kmin: Some(kmin),
+            kmax: Some(12.0),
+            dk: Some(dk),
+            window: Some(window),
+            kweight: Some(2.0),
+            ..XrayFFTF::default()
+        })
+    }
+
+    /// Replace the processing parameters of the spectrum with those of the preset and process
+    /// it. e0 of the spectrum is kept if set. The background and the Fourier transforms of an
+    /// already processed spectrum are cleared by the XANES presets, which do not compute them.
+    pub fn apply<'a>(
+        &self,
+        spectrum: &'a mut XASSpectrum,
+    ) -> Result<&'a mut XASSpectrum, Box<dyn Error>> {
+        ProcessingParameters::from(*self).run(spectrum, |_, _, _| ())?;
+
+        Ok(spectrum)
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = Box<dyn Error>;
+
+    /// Parse the preset from its name, ignoring case and accepting "_" or " " for "-".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase().replace(['_', ' '], "-");
+
+        Preset::ALL
+            .into_iter()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| format!("Unknown preset: {}", s).into())
+    }
+}
+
+impl XASGroup {
+    /// Process all spectra with the preset, see Preset::apply.
+    pub fn apply_preset(&mut self, preset: Preset) -> Result<&mut Self, Box<dyn Error>> {
+        self.spectra
+            .par_iter_mut()
+            .try_for_each(|spectrum| {
+                preset
+                    .apply(spectrum)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(|e| -> Box<dyn Error> { e.into() })?;
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_presets() -> Result<(), Box<dyn Error>> {
+        assert_eq!("EXAFS_standard".parse::<Preset>()?, Preset::ExafsStandard);
+        assert_eq!("xanes quick".parse::<Preset>()?, Preset::XanesQuick);
+        assert!("exafs".parse::<Preset>().is_err());
+        for preset in Preset::ALL {
+            assert_eq!(preset.to_string().parse::<Preset>()?, preset);
+        }
+        assert_eq!(
+            serde_json::to_string(&Preset::Publication)?,
+            "\"publication\""
+        );
+
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut xanes = spectrum.clone();
+        Preset::XanesQuick.apply(&mut xanes)?;
+        assert!(xanes.normalization.is_some());
+        assert!(xanes.get_chi().is_none());
+
+        // Results of an earlier EXAFS processing are not left behind
+        let mut processed = spectrum.clone();
+        Preset::ExafsStandard.apply(&mut processed)?;
+        processed.ifft()?;
+        Preset::XanesQuick.apply(&mut processed)?;
+        assert!(processed.get_chi().is_none());
+        assert!(processed.get_chir_mag().is_none());
+        assert!(processed.get_q().is_none());
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum.clone()).add_spectrum(spectrum);
+        group.apply_preset(Preset::Publication)?;
+        assert!(group.spectra[1].get_chir_mag().is_some());
+        assert_eq!(group.spectra[1].get_kweight(), Some(&2.0));
+        assert_eq!(group.spectra[0].get_chi(), group.spectra[1].get_chi());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! Options controlling how the data of a spectrum is prepared for processing.
+//!
+//! The defaults follow xraylarch, but they do not suit every instrument: a high-resolution
+//! spectrometer may record points closer than TINY_ENERGY on purpose. The options are carried by
+//! each XASSpectrum and stored with it.
+
+use std::error::Error;
+
+use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
+use serde::{Deserialize, Serialize};
+
+use super::xafsutils::{self, E0Estimate, EnergyStepReport, FindE0Options, TINY_ENERGY};
+use super::XAFSError;
+
+/// Treatment of NaN and infinite values in the energy and mu of a spectrum
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NonFinitePolicy {
+    /// Keep the data as it is and fail in find_e0, normalization, background removal and FT.
+    Error,
+    /// Drop the points where energy or mu is not finite.
+    #[default]
+    Filter,
+    /// Drop the points where energy is not finite and interpolate mu linearly over the
+    /// remaining gaps. Gaps at the ends take the nearest finite value.
+    Repair,
+}
+
+impl NonFinitePolicy {
+    /// Apply the policy to energy and mu sorted by energy.
+    ///
+    /// Error returns the data unchanged, see check. Repair leaves mu as it is if no value is finite.
+    pub fn apply(&self, energy: &Array1<f64>, mu: &Array1<f64>) -> (Array1<f64>, Array1<f64>) {
+        match self {
+            NonFinitePolicy::Error => (energy.clone(), mu.clone()),
+            NonFinitePolicy::Filter => xafsutils::remove_nan2(energy, mu),
+            NonFinitePolicy::Repair => {
+                let (energy, mut mu): (Vec<f64>, Vec<f64>) = energy
+                    .iter()
+                    .zip(mu.iter())
+                    .filter(|(e, _)| e.is_finite())
+                    .unzip();
+
+                let finite = (0..mu.len())
+                    .filter(|&i| mu[i].is_finite())
+                    .collect::<Vec<usize>>();
+
+                if !finite.is_empty() {
+                    for i in 0..mu.len() {
+                        if mu[i].is_finite() {
+                            continue;
+                        }
+
+                        let upper = finite.partition_point(|&j| j < i);
+                        mu[i] = match (upper.checked_sub(1), finite.get(upper)) {
+                            (Some(lo), Some(&hi)) => {
+                                let lo = finite[lo];
+                                let t = (energy[i] - energy[lo]) / (energy[hi] - energy[lo]);
+                                if t.is_finite() {
+                                    mu[lo] + t * (mu[hi] - mu[lo])
+                                } else {
+                                    mu[lo]
+                                }
+                            }
+                            (Some(lo), None) => mu[finite[lo]],
+                            (None, Some(&hi)) => mu[hi],
+                            (None, None) => unreachable!(),
+                        };
+                    }
+                }
+
+                (energy.into(), mu.into())
+            }
+        }
+    }
+
+    /// Fail with NonFiniteData if energy or mu holds a value that is not finite.
+    pub fn check(&self, energy: &Array1<f64>, mu: &Array1<f64>) -> Result<(), Box<dyn Error>> {
+        if energy.iter().chain(mu.iter()).all(|x| x.is_finite()) {
+            Ok(())
+        } else {
+            Err(Box::new(XAFSError::NonFiniteData))
+        }
+    }
+}
+
+/// Tolerances used when preparing the energy grid of a spectrum
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingOptions {
+    /// Energy points closer than this (eV) are treated as duplicates and shifted apart, when
+    /// the spectrum is prepared as well as in find_e0 and the background removal.
+    pub tiny_energy: Option<f64>,
+    /// Fraction of the difference added to a duplicate on top of `tiny_energy`.
+    pub dup_frac: Option<f64>,
+    /// Fraction of the smallest energy steps ignored when estimating the energy step.
+    pub energy_step_frac_ignore: Option<f64>,
+    /// Number of energy steps averaged when estimating the energy step.
+    pub energy_step_nave: Option<usize>,
+    /// Ratio to the local median step above which a step of the energy axis is reported as a
+    /// jump by find_energy_step_report.
+    pub energy_step_jump_factor: Option<f64>,
+    /// Minimum k (1/Å) reached above e0 for a scan to be treated as EXAFS. Shorter scans are
+    /// XANES-only and are skipped by the background removal and FT of a group.
+    pub exafs_min_kmax: Option<f64>,
+    /// Pre-filters of find_e0 against spikes near the ends of the scan.
+    pub find_e0: FindE0Options,
+    /// Treatment of NaN and infinite values in energy and mu.
+    pub non_finite: Option<NonFinitePolicy>,
+    /// Keep the results replaced by a reprocessing round (normalize, calc_background, fft and
+    /// ifft) together in the snapshot "previous" of the spectrum. Default = false.
+    pub keep_previous: Option<bool>,
+    /// Plot data with more points than this is min/max decimated to at most this number of
+    /// points by XASSpectrum::display_data, e.g. for QEXAFS scans. None disables the
+    /// decimation. Default = 20000.
+    pub plot_max_points: Option<usize>,
+}
+
+impl Default for ProcessingOptions {
+    fn default() -> Self {
+        ProcessingOptions {
+            tiny_energy: Some(TINY_ENERGY),
+            dup_frac: Some(1e-6),
+            energy_step_frac_ignore: Some(0.01),
+            energy_step_nave: Some(10),
+            energy_step_jump_factor: Some(5.0),
+            exafs_min_kmax: Some(4.0),
+            find_e0: FindE0Options::default(),
+            non_finite: Some(NonFinitePolicy::default()),
+            keep_previous: Some(false),
+            plot_max_points: Some(20_000),
+        }
+    }
+}
+
+impl ProcessingOptions {
+    pub fn new() -> ProcessingOptions {
+        ProcessingOptions::default()
+    }
+
+    pub fn set_tiny_energy(&mut self, tiny_energy: Option<f64>) -> &mut Self {
+        self.tiny_energy = tiny_energy;
+        self
+    }
+
+    pub fn set_dup_frac(&mut self, dup_frac: Option<f64>) -> &mut Self {
+        self.dup_frac = dup_frac;
+        self
+    }
+
+    pub fn set_energy_step(&mut self, frac_ignore: Option<f64>, nave: Option<usize>) -> &mut Self {
+        self.energy_step_frac_ignore = frac_ignore;
+        self.energy_step_nave = nave;
+        self
+    }
+
+    pub fn set_energy_step_jump_factor(&mut self, jump_factor: Option<f64>) -> &mut Self {
+        self.energy_step_jump_factor = jump_factor;
+        self
+    }
+
+    pub fn set_exafs_min_kmax(&mut self, exafs_min_kmax: Option<f64>) -> &mut Self {
+        self.exafs_min_kmax = exafs_min_kmax;
+        self
+    }
+
+    pub fn set_find_e0(&mut self, find_e0: FindE0Options) -> &mut Self {
+        self.find_e0 = find_e0;
+        self
+    }
+
+    pub fn set_non_finite(&mut self, non_finite: Option<NonFinitePolicy>) -> &mut Self {
+        self.non_finite = non_finite;
+        self
+    }
+
+    pub fn set_keep_previous(&mut self, keep_previous: Option<bool>) -> &mut Self {
+        self.keep_previous = keep_previous;
+        self
+    }
+
+    pub fn set_plot_max_points(&mut self, plot_max_points: Option<usize>) -> &mut Self {
+        self.plot_max_points = plot_max_points;
+        self
+    }
+
+    pub fn get_non_finite(&self) -> NonFinitePolicy {
+        self.non_finite.unwrap_or_default()
+    }
+
+    /// Energy and mu ready for processing: the non-finite policy is applied to the raw data,
+    /// sorted by energy, and duplicated energies are shifted apart.
+    pub fn prepare(
+        &self,
+        raw_energy: &Array1<f64>,
+        raw_mu: &Array1<f64>,
+    ) -> (Array1<f64>, Array1<f64>) {
+        let (energy, mu) = self.get_non_finite().apply(raw_energy, raw_mu);
+        (self.remove_dups(energy), mu)
+    }
+
+    /// Fail if energy or mu are not finite. With the Filter and Repair policies, this can only
+    /// happen if the data was not prepared with these options.
+    pub fn check_finite(
+        &self,
+        energy: &Array1<f64>,
+        mu: &Array1<f64>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.get_non_finite().check(energy, mu)
+    }
+
+    /// remove_dups with the tolerances of these options.
+    pub fn remove_dups<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(&self, energy: T) -> Array1<f64> {
+        let (tiny, frac) = self.dup_tolerance();
+
+        xafsutils::remove_dups(energy, tiny, frac, None)
+    }
+
+    /// find_energy_step with the parameters of these options.
+    pub fn find_energy_step<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(&self, energy: T) -> f64 {
+        let default = ProcessingOptions::default();
+
+        xafsutils::find_energy_step(
+            energy,
+            self.energy_step_frac_ignore
+                .or(default.energy_step_frac_ignore),
+            self.energy_step_nave.or(default.energy_step_nave),
+            None,
+        )
+    }
+
+    /// find_energy_step_report with the parameters of these options.
+    pub fn find_energy_step_report<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+        &self,
+        energy: T,
+    ) -> EnergyStepReport {
+        let default = ProcessingOptions::default();
+
+        xafsutils::find_energy_step_report(
+            energy,
+            self.energy_step_frac_ignore
+                .or(default.energy_step_frac_ignore),
+            self.energy_step_nave.or(default.energy_step_nave),
+            self.tiny_energy.or(default.tiny_energy),
+            self.energy_step_jump_factor
+                .or(default.energy_step_jump_factor),
+        )
+    }
+
+    /// find_e0 with the pre-filters and the duplicate tolerances of these options.
+    pub fn find_e0<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+        &self,
+        energy: T,
+        mu: T,
+    ) -> Result<f64, Box<dyn Error>> {
+        Ok(self.find_e0_estimate(energy, mu)?.e0)
+    }
+
+    /// find_e0 together with the uncertainty of e0.
+    pub fn find_e0_estimate<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+        &self,
+        energy: T,
+        mu: T,
+    ) -> Result<E0Estimate, Box<dyn Error>> {
+        xafsutils::find_e0_estimate_with_tolerance(energy, mu, &self.find_e0, self.dup_tolerance())
+    }
+
+    /// (tiny, frac) of remove_dups
+    pub(crate) fn dup_tolerance(&self) -> (Option<f64>, Option<f64>) {
+        let default = ProcessingOptions::default();
+
+        (
+            self.tiny_energy.or(default.tiny_energy),
+            self.dup_frac.or(default.dup_frac),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::xasspectrum::XASSpectrum;
+    use approx::assert_abs_diff_eq;
+    use ndarray::array;
+
+    #[test]
+    fn test_processing_options() {
+        let energy = array![0.0, 1.0, 1.002, 2.0, 3.0];
+        let mu = array![0.0, 1.0, 2.0, 3.0, 4.0];
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy.clone(), mu.clone());
+        let deduped = spectrum.energy.clone().unwrap();
+        assert_abs_diff_eq!(deduped[2], 1.002 + TINY_ENERGY, epsilon = 1e-9);
+        assert_eq!(spectrum.raw_energy, Some(energy.clone()));
+
+        let mut options = ProcessingOptions::new();
+        options.set_tiny_energy(Some(1e-4));
+        spectrum.set_processing_options(options.clone());
+        assert_eq!(spectrum.energy, Some(energy.clone()));
+        assert_eq!(spectrum.mu, Some(mu));
+
+        options.set_energy_step(Some(0.0), Some(1));
+        assert_abs_diff_eq!(options.find_energy_step(energy), 0.002, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_find_e0_dup_tolerance() -> Result<(), Box<dyn Error>> {
+        // High-resolution scan with 1 meV steps
+        let energy = Array1::linspace(8975.0, 8985.0, 10001);
+        let mu = energy.mapv(|e: f64| ((e - 8980.0) / 0.5).atan());
+
+        let mut options = ProcessingOptions::new();
+        options.set_tiny_energy(Some(1e-4));
+        let e0 = options.find_e0(energy.clone(), mu.clone())?;
+        assert_abs_diff_eq!(e0, 8980.0, epsilon = 0.01);
+
+        // With the default tolerance of 5 meV every point counts as a duplicate and the
+        // energies are stretched
+        let e0 = ProcessingOptions::new().find_e0(energy, mu)?;
+        assert!(e0 - 8980.0 > 0.3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_finite_policy() -> Result<(), Box<dyn Error>> {
+        let energy = array![0.0, 1.0, 2.0, f64::NAN, 3.0, 4.0];
+        let mu = array![0.0, 1.0, f64::NAN, 5.0, 3.0, f64::INFINITY];
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy.clone(), mu.clone());
+        assert_eq!(spectrum.energy, Some(array![0.0, 1.0, 3.0]));
+        assert_eq!(spectrum.mu, Some(array![0.0, 1.0, 3.0]));
+        // The raw data is kept, sorted with NaN last
+        assert!(spectrum.raw_energy.as_ref().unwrap()[5].is_nan());
+
+        let mut options = ProcessingOptions::new();
+        options.set_non_finite(Some(NonFinitePolicy::Repair));
+        spectrum.set_processing_options(options.clone());
+        assert_eq!(spectrum.energy, Some(array![0.0, 1.0, 2.0, 3.0, 4.0]));
+        assert_eq!(spectrum.mu, Some(array![0.0, 1.0, 2.0, 3.0, 3.0]));
+
+        options.set_non_finite(Some(NonFinitePolicy::Error));
+        spectrum.set_processing_options(options);
+        assert_eq!(spectrum.mu.as_ref().unwrap().len(), energy.len());
+        assert!(spectrum.find_e0().is_err());
+        assert!(spectrum.normalize().is_err());
+        assert!(spectrum.calc_background().is_err());
+        assert!(spectrum.interpolate_spectrum(array![0.5, 1.5]).is_err());
+
+        let (energy, mu) =
+            NonFinitePolicy::Repair.apply(&array![0.0, 1.0], &array![f64::NAN, f64::NAN]);
+        assert_eq!(energy, array![0.0, 1.0]);
+        assert!(mu.iter().all(|m| m.is_nan()));
+        assert!(NonFinitePolicy::Filter.check(&energy, &mu).is_err());
+
+        Ok(())
+    }
+}
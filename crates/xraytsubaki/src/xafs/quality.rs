@@ -0,0 +1,249 @@
+//! Data quality diagnostics for automated QA of measured spectra.
+
+use std::error::Error;
+use std::fmt;
+
+use nalgebra::{DMatrix, DVector};
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::lmutils::lstsq_nalgebra_f64;
+use super::normalization::{NormalizationMethod, PrePostEdge};
+use super::xafsutils;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Nonlinearity between i0 and it at which the score reaches 1
+const NONLINEARITY_LIMIT: f64 = 0.02;
+/// Negative offset of chi(k) at high k (relative to its rms) at which the score reaches 1
+const HIGH_K_OFFSET_LIMIT: f64 = 0.5;
+/// Relative fluctuation of i0 below which the linearity between i0 and it can not be tested
+const MIN_I0_VARIATION: f64 = 1e-4;
+const MIN_POINTS: usize = 10;
+
+/// Recommendation given by the harmonic contamination diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HarmonicRecommendation {
+    /// No sign of harmonic contamination
+    Ok,
+    /// Borderline; check the detuning or the harmonic rejection mirrors
+    CheckDetuning,
+    /// Clear sign of harmonic contamination; increase the detuning
+    IncreaseDetuning,
+}
+
+impl fmt::Display for HarmonicRecommendation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HarmonicRecommendation::Ok => write!(f, "No harmonic contamination detected"),
+            HarmonicRecommendation::CheckDetuning => {
+                write!(f, "Possible harmonic contamination: check the detuning")
+            }
+            HarmonicRecommendation::IncreaseDetuning => {
+                write!(f, "Harmonic contamination: increase the detuning")
+            }
+        }
+    }
+}
+
+/// Result of the harmonic contamination diagnostic
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarmonicDiagnostic {
+    /// Deviation from 1 of the exponent of it ∝ i0^n in the pre-edge region, which is about the
+    /// fraction of it not scaling with i0. None if the i0 and it channels are missing or i0 does
+    /// not fluctuate.
+    pub nonlinearity: Option<f64>,
+    /// Negative mean of chi(k) in the upper third of the k range, relative to its rms.
+    /// None if chi(k) is not calculated.
+    pub high_k_offset: Option<f64>,
+    /// Combined score. Values above 1 indicate harmonic contamination.
+    pub score: f64,
+    pub recommendation: HarmonicRecommendation,
+}
+
+impl XASSpectrum {
+    /// Estimate the harmonic contamination of a transmission spectrum.
+    ///
+    /// Harmonics pass the sample with little absorption, so that a part of it stops following
+    /// i0 and the edge is compressed. The linearity between the "i0" and "it" channels in the
+    /// pre-edge region is tested by fitting ln(it) = c + n ln(i0) + a smooth function of E,
+    /// which gives n = 1 for a clean beam. chi(k) is checked for a systematic negative offset
+    /// at high k. Either test is skipped if its data is missing.
+    pub fn harmonic_diagnostic(&self) -> Result<HarmonicDiagnostic, Box<dyn Error>> {
+        let nonlinearity = self.i0_it_nonlinearity()?;
+        let high_k_offset = self.high_k_chi_offset();
+
+        if nonlinearity.is_none() && high_k_offset.is_none() {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let score = f64::max(
+            nonlinearity.unwrap_or(0.0) / NONLINEARITY_LIMIT,
+            high_k_offset.unwrap_or(0.0) / HIGH_K_OFFSET_LIMIT,
+        );
+
+        let recommendation = if score < 0.5 {
+            HarmonicRecommendation::Ok
+        } else if score < 1.0 {
+            HarmonicRecommendation::CheckDetuning
+        } else {
+            HarmonicRecommendation::IncreaseDetuning
+        };
+
+        Ok(HarmonicDiagnostic {
+            nonlinearity,
+            high_k_offset,
+            score,
+            recommendation,
+        })
+    }
+
+    fn i0_it_nonlinearity(&self) -> Result<Option<f64>, Box<dyn Error>> {
+        let (i0, it) = match (self.get_channel("i0"), self.get_channel("it")) {
+            (Some(i0), Some(it)) => (i0, it),
+            _ => return Ok(None),
+        };
+        let energy = self.raw_energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let mu = self.raw_mu.as_ref().ok_or(XAFSError::NotEnoughData)?;
+
+        let e0 = match self
+            .get_e0()
+            .or_else(|| self.normalization.as_ref()?.get_e0())
+        {
+            Some(e0) => e0,
+            None => self
+                .processing_options
+                .find_e0(energy.clone(), mu.clone())?,
+        };
+
+        let mut pre_post_edge = match &self.normalization {
+            Some(NormalizationMethod::PrePostEdge(p)) => p.copy_parameters(),
+            _ => PrePostEdge::new(),
+        };
+        pre_post_edge.e0 = Some(e0);
+        pre_post_edge.fill_parameter(energy, mu)?;
+
+        let (pre_edge_start, pre_edge_end) = pre_post_edge.get_pre_edge_range().unwrap();
+
+        let rows = (0..energy.len())
+            .filter(|&i| energy[i] >= pre_edge_start && energy[i] <= pre_edge_end)
+            .filter(|&i| i0[i].is_finite() && it[i].is_finite())
+            .collect::<Vec<usize>>();
+
+        if rows.len() < MIN_POINTS {
+            return Ok(None);
+        }
+
+        let i0_pre = Array1::from_iter(rows.iter().map(|&i| i0[i]));
+        if rows.iter().any(|&i| i0[i] <= 0.0 || it[i] <= 0.0)
+            || i0_pre.std(0.0) / i0_pre.mean().unwrap() < MIN_I0_VARIATION
+        {
+            return Ok(None);
+        }
+
+        // ln(it) = c0 + c1 ln(i0) + c2 x + c3 x^2 with x = (E - e0) scaled to the pre-edge width
+        let width = pre_edge_end - pre_edge_start;
+        let a = DMatrix::from_fn(rows.len(), 4, |row, col| {
+            let i = rows[row];
+            let x = xafsutils::relative_energy(e0, energy[i]) / width;
+            match col {
+                0 => 1.0,
+                1 => i0[i].ln(),
+                2 => x,
+                _ => x * x,
+            }
+        });
+        let b = DVector::from_iterator(rows.len(), rows.iter().map(|&i| it[i].ln()));
+
+        let coefficients = lstsq_nalgebra_f64(&a, &b).ok_or(XAFSError::NotEnoughData)?;
+
+        Ok(Some((1.0 - coefficients[1]).abs()))
+    }
+
+    fn high_k_chi_offset(&self) -> Option<f64> {
+        let k = self.get_k()?;
+        let chi = self.get_chi()?;
+
+        let kmax = k.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let chi_high = Array1::from_iter(
+            k.iter()
+                .zip(chi.iter())
+                .filter(|(k, _)| **k >= 2.0 * kmax / 3.0)
+                .map(|(_, chi)| *chi),
+        );
+
+        if chi_high.len() < MIN_POINTS {
+            return None;
+        }
+
+        let rms = chi_high.mapv(|x| x * x).mean()?.sqrt();
+        if rms == 0.0 {
+            return Some(0.0);
+        }
+
+        Some((-chi_high.mean()? / rms).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::load_spectrum_QAS_trans;
+    use crate::xafs::tests::TOP_DIR;
+    use std::collections::BTreeMap;
+
+    fn transmission(offset: f64) -> XASSpectrum {
+        let energy: Array1<f64> = Array1::range(8700.0, 9500.0, 1.0);
+        let i0 = Array1::from_iter(
+            (0..energy.len()).map(|i| 1e5 * (1.0 + 0.05 * (i as f64 * 0.7).sin())),
+        );
+        let absorption =
+            energy.mapv(|e| 0.5 - 1e-4 * (e - 8979.0) + 1.0 / (1.0 + (-(e - 8979.0)).exp()));
+        let it = &i0 * &absorption.mapv(|m| (-m).exp());
+        let it = &it + offset * it.mean().unwrap();
+        let mu = (&i0 / &it).mapv(f64::ln);
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum_channels(
+                energy,
+                mu,
+                BTreeMap::from([("i0".to_string(), i0), ("it".to_string(), it)]),
+            )
+            .unwrap()
+            .set_e0(8979.0);
+        spectrum
+    }
+
+    #[test]
+    fn test_harmonic_diagnostic() -> Result<(), Box<dyn Error>> {
+        let clean = transmission(0.0).harmonic_diagnostic()?;
+        assert!(clean.nonlinearity.unwrap() < 1e-6);
+        assert_eq!(clean.high_k_offset, None);
+        assert_eq!(clean.recommendation, HarmonicRecommendation::Ok);
+
+        let contaminated = transmission(0.05).harmonic_diagnostic()?;
+        assert!(contaminated.nonlinearity.unwrap() > NONLINEARITY_LIMIT);
+        assert_eq!(
+            contaminated.recommendation,
+            HarmonicRecommendation::IncreaseDetuning
+        );
+
+        assert!(XASSpectrum::new().harmonic_diagnostic().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_harmonic_diagnostic_qas() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?.calc_background()?;
+
+        let diagnostic = spectrum.harmonic_diagnostic()?;
+        assert!(diagnostic.score.is_finite());
+        assert!(diagnostic.high_k_offset.is_some());
+
+        Ok(())
+    }
+}
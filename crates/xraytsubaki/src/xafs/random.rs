@@ -0,0 +1,135 @@
+//! Seeded pseudo-random numbers.
+//!
+//! Stochastic features take an explicit seed and draw their numbers from SeededRng, so that
+//! an analysis run again with the same seed gives bit-for-bit the same result, on any
+//! platform and with any number of threads. The seed is recorded in the metadata of the
+//! spectra it was used for (see NOISE_KEY), so that published results can be reproduced.
+//!
+//! The generator is a 64 bit linear congruential generator. It is not suitable for
+//! cryptography, but more than good enough for noise and resampling.
+//!
+//! ```
+//! use xraytsubaki::xafs::random::SeededRng;
+//!
+//! let mut a = SeededRng::new(42);
+//! let mut b = SeededRng::new(42);
+//! assert_eq!(a.normal(), b.normal());
+//! ```
+
+use std::error::Error;
+use std::f64::consts::PI;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Metadata key of the list of noise added to a spectrum, each entry with its sigma and seed
+pub const NOISE_KEY: &str = "noise";
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+/// Deterministic random number generator, see the module documentation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeededRng {
+    seed: u64,
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng { seed, state: seed }
+    }
+
+    /// Seed the generator was created with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        self.state
+    }
+
+    /// Uniform deviate in (0, 1)
+    pub fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    /// Standard normal deviate (Box-Muller)
+    pub fn normal(&mut self) -> f64 {
+        let (u1, u2) = (self.uniform(), self.uniform());
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+
+    /// Array of `n` normal deviates with standard deviation `sigma`
+    pub fn normal_array(&mut self, n: usize, sigma: f64) -> Array1<f64> {
+        Array1::from_shape_fn(n, |_| sigma * self.normal())
+    }
+}
+
+impl XASSpectrum {
+    /// Add normal noise of standard deviation `sigma` to raw_mu, e.g. to test how robust a
+    /// result is. The noise and its seed are appended to the metadata under NOISE_KEY.
+    /// Processing results are not recalculated.
+    pub fn add_noise(&mut self, sigma: f64, seed: u64) -> Result<&mut Self, Box<dyn Error>> {
+        let (energy, mu) = match (self.raw_energy.as_ref(), self.raw_mu.as_ref()) {
+            (Some(energy), Some(mu)) => (energy.clone(), mu.clone()),
+            _ => return Err(Box::new(XAFSError::NotEnoughData)),
+        };
+
+        let noise = SeededRng::new(seed).normal_array(mu.len(), sigma);
+        self.set_spectrum(energy, mu + noise);
+
+        let mut entries = match self.metadata.remove(NOISE_KEY) {
+            Some(serde_json::Value::Array(entries)) => entries,
+            _ => Vec::new(),
+        };
+        entries.push(serde_json::json!({ "sigma": sigma, "seed": seed }));
+        self.set_metadata(NOISE_KEY, entries);
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_seeded_rng() {
+        let mut rng = SeededRng::new(7);
+        let first = rng.normal_array(10000, 2.0);
+        assert_eq!(rng.seed(), 7);
+        assert_eq!(SeededRng::new(7).normal_array(10000, 2.0), first);
+        assert_ne!(SeededRng::new(8).normal_array(10000, 2.0), first);
+
+        assert_abs_diff_eq!(first.mean().unwrap(), 0.0, epsilon = 0.05);
+        assert_abs_diff_eq!(first.std(0.0), 2.0, epsilon = 0.05);
+        assert!((0..1000).map(|_| rng.uniform()).all(|u| u > 0.0 && u < 1.0));
+    }
+
+    #[test]
+    fn test_add_noise() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut a = spectrum.clone();
+        let mut b = spectrum.clone();
+        a.add_noise(1e-3, 1)?.add_noise(1e-3, 2)?;
+        b.add_noise(1e-3, 1)?.add_noise(1e-3, 2)?;
+        assert_eq!(a.raw_mu, b.raw_mu);
+        assert_ne!(a.raw_mu, spectrum.raw_mu);
+        assert_eq!(
+            a.get_metadata(NOISE_KEY).unwrap(),
+            &serde_json::json!([{"sigma": 1e-3, "seed": 1}, {"sigma": 1e-3, "seed": 2}])
+        );
+
+        Ok(())
+    }
+}
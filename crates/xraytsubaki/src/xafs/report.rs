@@ -0,0 +1,519 @@
+//! HTML and Markdown summaries of a group.
+//!
+//! The report lists the processing parameters and quality metrics of each spectrum together
+//! with small SVG plots, e.g. to be shared at the end of a beamtime.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use super::background::BackgroundMethod;
+use super::normalization::NormalizationMethod;
+use super::plot::{PlotData, PlotKind};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+
+const THUMBNAIL_WIDTH: f64 = 240.0;
+const THUMBNAIL_HEIGHT: f64 = 150.0;
+const THUMBNAIL_POINTS: usize = 400;
+
+/// Output format of XASGroup::report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+impl ReportFormat {
+    /// Format given by the extension of `path` (.html, .htm or .md).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ReportFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "html" | "htm" => Some(ReportFormat::Html),
+            "md" | "markdown" => Some(ReportFormat::Markdown),
+            _ => None,
+        }
+    }
+}
+
+// Values of one spectrum shown in the report, formatted as text.
+struct SpectrumSummary {
+    name: String,
+    parameters: Vec<(&'static str, String)>,
+    metadata: Vec<(String, String)>,
+    tags: Vec<String>,
+    notes: Vec<String>,
+    thumbnails: Vec<(&'static str, String)>,
+}
+
+impl SpectrumSummary {
+    fn new(index: usize, spectrum: &XASSpectrum) -> SpectrumSummary {
+        let name = spectrum
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("spectrum {}", index));
+
+        let mut parameters = Vec::new();
+        let mut push = |key: &'static str, value: Option<String>| {
+            parameters.push((key, value.unwrap_or_else(|| "-".to_string())));
+        };
+
+        let e0 = spectrum
+            .get_e0()
+            .or_else(|| spectrum.normalization.as_ref()?.get_e0());
+        push("e0 (eV)", e0.map(|x| format!("{:.2}", x)));
+        push(
+            "edge step",
+            spectrum
+                .normalization
+                .as_ref()
+                .and_then(|n| n.get_edge_step())
+                .map(|x| format!("{:.4}", x)),
+        );
+
+        if let Some(NormalizationMethod::PrePostEdge(p)) = &spectrum.normalization {
+            push("pre-edge (eV)", range(p.pre_edge_start, p.pre_edge_end));
+            push("normalization (eV)", range(p.norm_start, p.norm_end));
+        }
+
+        if let Some(BackgroundMethod::AUTOBK(autobk)) = &spectrum.background {
+            push("rbkg (Å)", autobk.get_rbkg().map(|x| format!("{:.2}", x)));
+            push(
+                "bkg k range (Å⁻¹)",
+                range(autobk.get_kmin().cloned(), autobk.get_kmax().cloned()),
+            );
+        }
+
+        if let Some(xftf) = &spectrum.xftf {
+            push("FT k range (Å⁻¹)", range(xftf.kmin, xftf.kmax));
+            push("FT k-weight", xftf.kweight.map(|x| format!("{}", x)));
+        }
+
+        push(
+            "energy resolution (eV)",
+            spectrum
+                .get_energy_resolution()
+                .map(|x| format!("{:.2}", x)),
+        );
+
+        if let Ok(diagnostic) = spectrum.harmonic_diagnostic() {
+            push(
+                "harmonic score",
+                Some(format!(
+                    "{:.2} ({})",
+                    diagnostic.score, diagnostic.recommendation
+                )),
+            );
+        }
+
+        let metadata = spectrum
+            .metadata
+            .iter()
+            .map(|(key, value)| {
+                let value = value
+                    .as_str()
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| value.to_string());
+                (key.clone(), value)
+            })
+            .collect();
+
+        let thumbnails = [
+            ("norm(E)", PlotKind::Norm),
+            ("k-weighted chi(k)", PlotKind::KChi(None)),
+            ("|chi(R)|", PlotKind::ChiRMag),
+        ]
+        .into_iter()
+        .filter_map(|(title, kind)| {
+            Some((title, svg_thumbnail(&spectrum.display_data(kind).ok()?)))
+        })
+        .collect();
+
+        SpectrumSummary {
+            name,
+            parameters,
+            metadata,
+            tags: spectrum.tags.clone(),
+            notes: spectrum.notes.clone(),
+            thumbnails,
+        }
+    }
+
+    fn parameter(&self, key: &str) -> &str {
+        self.parameters
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or("-", |(_, value)| value.as_str())
+    }
+}
+
+impl XASGroup {
+    /// Write a summary of the group to `path`.
+    ///
+    /// The format is chosen from the extension: .html/.htm for HTML and .md for Markdown.
+    pub fn report<P: AsRef<Path>>(&self, path: P) -> Result<&Self, Box<dyn Error>> {
+        let format = ReportFormat::from_path(&path).ok_or_else(|| {
+            format!(
+                "unknown report format: {} (use .html or .md)",
+                path.as_ref().display()
+            )
+        })?;
+
+        std::fs::write(path, self.report_string(format))?;
+
+        Ok(self)
+    }
+
+    /// Summary of the group as a string in the given format.
+    pub fn report_string(&self, format: ReportFormat) -> String {
+        let summaries = self
+            .spectra
+            .iter()
+            .enumerate()
+            .map(|(i, spectrum)| SpectrumSummary::new(i, spectrum))
+            .collect::<Vec<SpectrumSummary>>();
+
+        match format {
+            ReportFormat::Html => html_report(self, &summaries),
+            ReportFormat::Markdown => markdown_report(self, &summaries),
+        }
+    }
+}
+
+// Parameter names of all the spectra, in order of appearance.
+fn parameter_keys(summaries: &[SpectrumSummary]) -> Vec<&'static str> {
+    let mut keys = Vec::new();
+    for (key, _) in summaries.iter().flat_map(|s| s.parameters.iter()) {
+        if !keys.contains(key) {
+            keys.push(*key);
+        }
+    }
+    keys
+}
+
+fn range(start: Option<f64>, end: Option<f64>) -> Option<String> {
+    Some(format!("{:.2} - {:.2}", start?, end?))
+}
+
+fn html_report(group: &XASGroup, summaries: &[SpectrumSummary]) -> String {
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>XAS group report</title>\n<style>\n");
+    html.push_str("body { font-family: sans-serif; margin: 2em; }\n");
+    html.push_str("table { border-collapse: collapse; margin-bottom: 1em; }\n");
+    html.push_str("td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }\n");
+    html.push_str("figure { display: inline-block; margin: 0 1em 1em 0; }\n");
+    html.push_str("</style>\n</head>\n<body>\n<h1>XAS group report</h1>\n");
+
+    let _ = writeln!(html, "<p>{} spectra</p>", summaries.len());
+    html_tags_and_notes(&mut html, &group.tags, &group.notes);
+
+    if !summaries.is_empty() {
+        let keys = parameter_keys(summaries);
+
+        html.push_str("<table>\n<tr><th>name</th>");
+        for key in &keys {
+            let _ = write!(html, "<th>{}</th>", escape_html(key));
+        }
+        html.push_str("</tr>\n");
+
+        for summary in summaries {
+            let _ = write!(html, "<tr><td>{}</td>", escape_html(&summary.name));
+            for key in &keys {
+                let _ = write!(html, "<td>{}</td>", escape_html(summary.parameter(key)));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+
+    for summary in summaries {
+        let _ = writeln!(html, "<h2>{}</h2>", escape_html(&summary.name));
+
+        if !summary.metadata.is_empty() {
+            html.push_str("<table>\n");
+            for (key, value) in &summary.metadata {
+                let _ = writeln!(
+                    html,
+                    "<tr><th>{}</th><td>{}</td></tr>",
+                    escape_html(key),
+                    escape_html(value)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html_tags_and_notes(&mut html, &summary.tags, &summary.notes);
+
+        for (title, svg) in &summary.thumbnails {
+            let _ = writeln!(
+                html,
+                "<figure>{}<figcaption>{}</figcaption></figure>",
+                svg,
+                escape_html(title)
+            );
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+fn markdown_report(group: &XASGroup, summaries: &[SpectrumSummary]) -> String {
+    let mut markdown = String::new();
+
+    markdown.push_str("# XAS group report\n\n");
+    let _ = writeln!(markdown, "{} spectra\n", summaries.len());
+    markdown_tags_and_notes(&mut markdown, &group.tags, &group.notes);
+
+    if !summaries.is_empty() {
+        let keys = parameter_keys(summaries);
+
+        markdown.push_str("| name |");
+        for key in &keys {
+            let _ = write!(markdown, " {} |", escape_markdown(key));
+        }
+        markdown.push_str("\n|---|");
+        markdown.push_str(&"---|".repeat(keys.len()));
+        markdown.push('\n');
+
+        for summary in summaries {
+            let _ = write!(markdown, "| {} |", escape_markdown(&summary.name));
+            for key in &keys {
+                let _ = write!(markdown, " {} |", escape_markdown(summary.parameter(key)));
+            }
+            markdown.push('\n');
+        }
+        markdown.push('\n');
+    }
+
+    for summary in summaries {
+        let _ = writeln!(markdown, "## {}\n", escape_markdown(&summary.name));
+
+        for (key, value) in &summary.metadata {
+            let _ = writeln!(
+                markdown,
+                "- {}: {}",
+                escape_markdown(key),
+                escape_markdown(value)
+            );
+        }
+        if !summary.metadata.is_empty() {
+            markdown.push('\n');
+        }
+
+        markdown_tags_and_notes(&mut markdown, &summary.tags, &summary.notes);
+
+        for (title, svg) in &summary.thumbnails {
+            let _ = writeln!(
+                markdown,
+                "![{}](data:image/svg+xml;utf8,{})",
+                escape_markdown(title),
+                encode_data_uri(svg)
+            );
+        }
+        if !summary.thumbnails.is_empty() {
+            markdown.push('\n');
+        }
+    }
+
+    markdown
+}
+
+fn html_tags_and_notes(html: &mut String, tags: &[String], notes: &[String]) {
+    if !tags.is_empty() {
+        let tags = tags
+            .iter()
+            .map(|tag| escape_html(tag))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let _ = writeln!(html, "<p>Tags: {}</p>", tags);
+    }
+
+    if !notes.is_empty() {
+        html.push_str("<ul>\n");
+        for note in notes {
+            let _ = writeln!(html, "<li>{}</li>", escape_html(note));
+        }
+        html.push_str("</ul>\n");
+    }
+}
+
+fn markdown_tags_and_notes(markdown: &mut String, tags: &[String], notes: &[String]) {
+    if !tags.is_empty() {
+        let tags = tags
+            .iter()
+            .map(|tag| escape_markdown(tag))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let _ = writeln!(markdown, "Tags: {}\n", tags);
+    }
+
+    // One blockquote per note, with every line of the note quoted
+    for note in notes {
+        for line in note.lines() {
+            let _ = writeln!(markdown, "> {}", escape_markdown(line));
+        }
+        markdown.push('\n');
+    }
+}
+
+// Polyline of the main curve of a plot, scaled to the thumbnail size.
+fn svg_thumbnail(data: &PlotData) -> String {
+    let points = data
+        .x
+        .iter()
+        .zip(data.y.iter())
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .map(|(x, y)| (*x, *y))
+        .collect::<Vec<(f64, f64)>>();
+
+    let step = (points.len() / THUMBNAIL_POINTS).max(1);
+    let points = points
+        .into_iter()
+        .step_by(step)
+        .collect::<Vec<(f64, f64)>>();
+
+    let (xmin, xmax) = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (x, _)| {
+            (lo.min(*x), hi.max(*x))
+        });
+    let (ymin, ymax) = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (_, y)| {
+            (lo.min(*y), hi.max(*y))
+        });
+    let xscale = if xmax > xmin { xmax - xmin } else { 1.0 };
+    let yscale = if ymax > ymin { ymax - ymin } else { 1.0 };
+
+    let polyline = points
+        .iter()
+        .map(|(x, y)| {
+            format!(
+                "{:.1},{:.1}",
+                (x - xmin) / xscale * THUMBNAIL_WIDTH,
+                (1.0 - (y - ymin) / yscale) * THUMBNAIL_HEIGHT
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!(
+        "<svg xmlns='http://www.w3.org/2000/svg' width='{w}' height='{h}' viewBox='-2 -2 {vw} {vh}'><polyline fill='none' stroke='steelblue' stroke-width='1.5' points='{p}'/></svg>",
+        w = THUMBNAIL_WIDTH,
+        h = THUMBNAIL_HEIGHT,
+        vw = THUMBNAIL_WIDTH + 4.0,
+        vh = THUMBNAIL_HEIGHT + 4.0,
+        p = polyline
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Escape the characters with a meaning in Markdown inline text and table cells. Line breaks are
+// replaced by spaces, so that the text stays in its heading, list item or cell.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '!' | '~' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // A leading list or heading marker
+            '-' | '+' | '=' if i == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' => {}
+            '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn encode_data_uri(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            ' ' => "%20".to_string(),
+            '#' => "%23".to_string(),
+            '%' => "%25".to_string(),
+            '<' => "%3C".to_string(),
+            '>' => "%3E".to_string(),
+            '(' => "%28".to_string(),
+            ')' => "%29".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io::load_spectrum_QAS_trans;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_report() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = load_spectrum_QAS_trans(&path)?;
+        spectrum
+            .set_name("Ru <foil> #1")
+            .set_metadata("sample", "Ru | foil")
+            .add_tag("reference")
+            .add_note("i0 <unstable>")
+            .add_note("gain changed\n# at scan 3");
+        spectrum.normalize()?.calc_background()?.fft()?;
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum)
+            .add_spectrum(XASSpectrum::new())
+            .add_note("calibrated");
+
+        let html = group.report_string(ReportFormat::Html);
+        assert!(html.contains("<h2>Ru &lt;foil&gt; #1</h2>"));
+        assert!(html.contains("<p>Tags: reference</p>"));
+        assert!(html.contains("<li>i0 &lt;unstable&gt;</li>"));
+        assert!(html.contains("<li>calibrated</li>"));
+        assert_eq!(html.matches("<svg").count(), 3);
+
+        let markdown = group.report_string(ReportFormat::Markdown);
+        assert!(markdown.contains("## Ru \\<foil\\> \\#1\n"));
+        assert!(markdown.contains("- sample: Ru \\| foil"));
+        assert!(markdown.contains("Tags: reference\n"));
+        assert!(markdown.contains("> i0 \\<unstable\\>\n\n> gain changed\n> \\# at scan 3\n"));
+        assert_eq!(markdown.matches("data:image/svg+xml").count(), 3);
+        assert!(markdown.contains("| spectrum 1 |"));
+
+        let report_path = std::env::temp_dir().join("xraytsubaki_test_report.md");
+        group.report(&report_path)?;
+        assert_eq!(std::fs::read_to_string(&report_path)?, markdown);
+        std::fs::remove_file(&report_path)?;
+
+        assert!(group.report("report.txt").is_err());
+        assert_eq!(
+            ReportFormat::from_path("a/b.HTML"),
+            Some(ReportFormat::Html)
+        );
+
+        assert_eq!(
+            escape_markdown("- a*b_c\r\n[d](e)"),
+            "\\- a\\*b\\_c \\[d\\](e)"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Estimation of the effective energy resolution from reference foil spectra.
+//!
+//! The derivative of a measured foil edge is compared with the derivative of a reference
+//! (tabulated or high resolution) spectrum broadened by a Gaussian. The width giving the best
+//! match is the effective resolution of the measurement (core-hole and instrument broadening
+//! not contained in the reference).
+
+use std::error::Error;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::align::CorrelationAlignment;
+use super::mathutils::MathUtils;
+use super::xafsutils::{absolute_energy, smooth, ConvolveForm};
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Metadata key of the estimated energy resolution (Gaussian FWHM in eV)
+pub const ENERGY_RESOLUTION_KEY: &str = "energy_resolution";
+
+/// Conversion factor from the standard deviation to the FWHM of a Gaussian
+pub const SIGMA_TO_FWHM: f64 = 2.354_820_045_030_949;
+
+const GOLDEN_RATIO: f64 = 0.618_033_988_749_895;
+
+/// Parameters of the energy resolution estimation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnergyResolution {
+    /// Start of the compared region relative to e0 of the measurement. Default = -20 eV.
+    pub emin: Option<f64>,
+    /// End of the compared region relative to e0 of the measurement. Default = 40 eV.
+    pub emax: Option<f64>,
+    /// Largest FWHM searched. Default = 20 eV.
+    pub max_fwhm: Option<f64>,
+    /// Precision of the FWHM. Default = 0.01 eV.
+    pub tolerance: Option<f64>,
+}
+
+impl Default for EnergyResolution {
+    fn default() -> Self {
+        EnergyResolution {
+            emin: Some(-20.0),
+            emax: Some(40.0),
+            max_fwhm: Some(20.0),
+            tolerance: Some(0.01),
+        }
+    }
+}
+
+impl EnergyResolution {
+    pub fn new() -> EnergyResolution {
+        EnergyResolution::default()
+    }
+
+    /// Gaussian FWHM (eV) to be applied to `reference` to match `measured`.
+    ///
+    /// Both spectra have to be normalized. The reference is aligned to the measurement first.
+    pub fn estimate(
+        &self,
+        measured: &XASSpectrum,
+        reference: &XASSpectrum,
+    ) -> Result<f64, Box<dyn Error>> {
+        let default = EnergyResolution::default();
+        let emin = self.emin.or(default.emin).unwrap();
+        let emax = self.emax.or(default.emax).unwrap();
+        let max_sigma = self.max_fwhm.or(default.max_fwhm).unwrap() / SIGMA_TO_FWHM;
+        let tolerance = self.tolerance.or(default.tolerance).unwrap() / SIGMA_TO_FWHM;
+
+        let e0 = measured
+            .get_e0()
+            .or_else(|| measured.normalization.as_ref()?.get_e0())
+            .ok_or(XAFSError::NotEnoughData)?;
+
+        let (energy, norm) = measured
+            .get_normalized_spectrum()
+            .ok_or(XAFSError::NotEnoughData)?;
+        let (ref_energy, ref_norm) = reference
+            .get_normalized_spectrum()
+            .ok_or(XAFSError::NotEnoughData)?;
+
+        let shift = CorrelationAlignment {
+            emin: Some(emin),
+            emax: Some(emax),
+            ..CorrelationAlignment::new()
+        }
+        .find_shift(measured, reference)?;
+        let ref_energy = ref_energy + shift;
+
+        let derivative = norm.gradient() / energy.gradient();
+        let (window_start, window_end) = (absolute_energy(e0, emin), absolute_energy(e0, emax));
+        let window = energy
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| **e >= window_start && **e <= window_end)
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+
+        if window.len() < 5 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let window_energy = Array1::from_iter(window.iter().map(|&i| energy[i]));
+        let window_derivative = Array1::from_iter(window.iter().map(|&i| derivative[i]));
+
+        let residual = |sigma: f64| -> Result<f64, Box<dyn Error>> {
+            let broadened = smooth(
+                ref_energy.clone(),
+                ref_norm.clone(),
+                Some(sigma),
+                None,
+                None,
+                None,
+                ConvolveForm::Gaussian,
+            )?;
+            let ref_derivative = broadened.gradient() / ref_energy.gradient();
+            let ref_derivative =
+                window_energy.interpolate(&ref_energy.to_vec(), &ref_derivative.to_vec())?;
+
+            // The amplitude is a linear parameter and is solved analytically
+            let norm2 = (&ref_derivative * &ref_derivative).sum();
+            if norm2 == 0.0 {
+                return Ok(f64::INFINITY);
+            }
+            let scale = (&window_derivative * &ref_derivative).sum() / norm2;
+            let diff = &window_derivative - &(ref_derivative * scale);
+
+            Ok((&diff * &diff).sum())
+        };
+
+        // Golden section search of the Gaussian width
+        let (mut a, mut b) = (tolerance.min(max_sigma), max_sigma);
+        let mut c = b - GOLDEN_RATIO * (b - a);
+        let mut d = a + GOLDEN_RATIO * (b - a);
+        let (mut fc, mut fd) = (residual(c)?, residual(d)?);
+
+        while (b - a).abs() > tolerance {
+            if fc < fd {
+                b = d;
+                d = c;
+                fd = fc;
+                c = b - GOLDEN_RATIO * (b - a);
+                fc = residual(c)?;
+            } else {
+                a = c;
+                c = d;
+                fc = fd;
+                d = a + GOLDEN_RATIO * (b - a);
+                fd = residual(d)?;
+            }
+        }
+
+        Ok((a + b) / 2.0 * SIGMA_TO_FWHM)
+    }
+}
+
+impl XASSpectrum {
+    /// Estimate the energy resolution of this (foil) spectrum against `reference` and store it
+    /// in the metadata as "energy_resolution".
+    ///
+    /// Spectra which are not normalized yet are normalized first.
+    pub fn estimate_energy_resolution(
+        &mut self,
+        reference: &XASSpectrum,
+        params: &EnergyResolution,
+    ) -> Result<f64, Box<dyn Error>> {
+        if self.get_normalized_spectrum().is_none() {
+            self.normalize()?;
+        }
+
+        let mut reference = reference.clone();
+        if reference.get_normalized_spectrum().is_none() {
+            reference.normalize()?;
+        }
+
+        let fwhm = params.estimate(self, &reference)?;
+        self.set_metadata(ENERGY_RESOLUTION_KEY, fwhm);
+
+        Ok(fwhm)
+    }
+
+    /// Energy resolution (Gaussian FWHM in eV) stored in the metadata.
+    pub fn get_energy_resolution(&self) -> Option<f64> {
+        self.get_metadata(ENERGY_RESOLUTION_KEY)?.as_f64()
+    }
+
+    /// Broaden mu(E) by a Gaussian of the given FWHM.
+    ///
+    /// If `fwhm` is None, the energy resolution stored in the metadata is used.
+    pub fn broaden(&mut self, fwhm: Option<f64>) -> Result<&mut Self, Box<dyn Error>> {
+        let fwhm = fwhm
+            .or_else(|| self.get_energy_resolution())
+            .ok_or(XAFSError::NotEnoughData)?;
+
+        let energy = self.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+        let mu = self.mu.clone().ok_or(XAFSError::NotEnoughData)?;
+
+        self.mu = Some(smooth(
+            energy,
+            mu,
+            Some(fwhm / SIGMA_TO_FWHM),
+            None,
+            None,
+            None,
+            ConvolveForm::Gaussian,
+        )?);
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn foil(energy: &Array1<f64>) -> Array1<f64> {
+        energy.mapv(|e| {
+            let x = e - 8979.0;
+            0.1 + 0.5
+                + x.atan() / std::f64::consts::PI
+                + 0.6 * (-(x - 4.0).powi(2) / 2.0).exp()
+                + 0.3 * (-(x - 15.0).powi(2) / 10.0).exp()
+        })
+    }
+
+    #[test]
+    fn test_estimate_energy_resolution() -> Result<(), Box<dyn Error>> {
+        let fwhm = 3.0;
+
+        let ref_energy: Array1<f64> = Array1::range(8700.0, 9400.0, 0.1);
+        let mut reference = XASSpectrum::new();
+        reference.set_spectrum(ref_energy.clone(), foil(&ref_energy));
+
+        let energy: Array1<f64> = Array1::range(8700.0, 9400.0, 0.5);
+        let mut measured = XASSpectrum::new();
+        measured.set_spectrum(energy.clone(), foil(&energy));
+        measured.broaden(Some(fwhm))?;
+
+        let estimated =
+            measured.estimate_energy_resolution(&reference, &EnergyResolution::new())?;
+
+        assert_abs_diff_eq!(estimated, fwhm, epsilon = 0.1 * fwhm);
+        assert_eq!(measured.get_energy_resolution(), Some(estimated));
+
+        Ok(())
+    }
+}
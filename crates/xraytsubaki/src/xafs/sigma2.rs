@@ -0,0 +1,215 @@
+//! Temperature dependent sigma2 (Debye-Waller factor) models.
+//!
+//! Each model gives sigma2(T) of a scattering path from a characteristic temperature, so that
+//! a temperature series can be refined with a single theta per path instead of one sigma2 per
+//! temperature.
+
+use serde::{Deserialize, Serialize};
+
+use super::xafsutils::constants;
+
+/// hbar^2 / (2 kB amu) in Å^2 K
+pub const EINSTEIN_FACTOR: f64 =
+    1.0e20 * constants::hbar * constants::hbar / (2.0 * constants::k_B * constants::amu);
+
+const DEBYE_INTEGRATION_STEPS: usize = 256;
+
+/// sigma2 model of a scattering path
+///
+/// Masses are given in amu, distances in Å and temperatures in K.
+///
+/// # Example
+///
+/// ```
+/// use xraytsubaki::xafs::sigma2::{reduced_mass, Sigma2Model};
+///
+/// // Cu-Cu first shell
+/// let model = Sigma2Model::Einstein {
+///     theta: 232.0,
+///     reduced_mass: reduced_mass(63.546, 63.546),
+/// };
+///
+/// let sigma2 = model.sigma2_series(&[10.0, 100.0, 300.0]);
+/// assert!(sigma2[0] < sigma2[1] && sigma2[1] < sigma2[2]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Sigma2Model {
+    /// Einstein model with the Einstein temperature `theta`.
+    Einstein { theta: f64, reduced_mass: f64 },
+    /// Correlated Debye model for a single scattering path of length `r`.
+    ///
+    /// `rs` is the Wigner-Seitz radius of the material, which gives the Debye wavenumber
+    /// kD = (9π/2)^(1/3) / rs.
+    ///
+    /// E. Sevillano, H. Meuth, and J. J. Rehr. Extended x-ray absorption fine structure Debye-Waller factors. I. Monatomic crystals. Phys. Rev. B, 20:4908–4911, Dec 1979. doi:10.1103/PhysRevB.20.4908.
+    CorrelatedDebye {
+        theta: f64,
+        reduced_mass: f64,
+        r: f64,
+        rs: f64,
+    },
+}
+
+impl Sigma2Model {
+    /// sigma2 in Å^2 at `temperature`.
+    pub fn sigma2(&self, temperature: f64) -> f64 {
+        match *self {
+            Sigma2Model::Einstein {
+                theta,
+                reduced_mass,
+            } => sigma2_einstein(temperature, theta, reduced_mass),
+            Sigma2Model::CorrelatedDebye {
+                theta,
+                reduced_mass,
+                r,
+                rs,
+            } => sigma2_correlated_debye(temperature, theta, reduced_mass, r, rs),
+        }
+    }
+
+    /// sigma2 at each of the measurement temperatures of a temperature series.
+    pub fn sigma2_series(&self, temperatures: &[f64]) -> Vec<f64> {
+        temperatures.iter().map(|t| self.sigma2(*t)).collect()
+    }
+
+    pub fn get_theta(&self) -> f64 {
+        match *self {
+            Sigma2Model::Einstein { theta, .. } => theta,
+            Sigma2Model::CorrelatedDebye { theta, .. } => theta,
+        }
+    }
+
+    /// Same model with another characteristic temperature, e.g. for the trial values of a fit.
+    pub fn with_theta(&self, theta: f64) -> Sigma2Model {
+        let mut model = *self;
+        match &mut model {
+            Sigma2Model::Einstein { theta: t, .. } => *t = theta,
+            Sigma2Model::CorrelatedDebye { theta: t, .. } => *t = theta,
+        }
+        model
+    }
+}
+
+/// Reduced mass of an absorber-scatterer pair.
+pub fn reduced_mass(mass1: f64, mass2: f64) -> f64 {
+    mass1 * mass2 / (mass1 + mass2)
+}
+
+/// Einstein model: sigma2 = hbar^2 / (2 mu kB thetaE) coth(thetaE / 2T)
+pub fn sigma2_einstein(temperature: f64, theta: f64, reduced_mass: f64) -> f64 {
+    let prefactor = EINSTEIN_FACTOR / (reduced_mass * theta);
+
+    if temperature <= 0.0 {
+        return prefactor;
+    }
+
+    prefactor / (theta / (2.0 * temperature)).tanh()
+}
+
+/// Correlated Debye model of a single scattering path.
+///
+/// sigma2 = 3 hbar^2 / (2 mu kB thetaD) ∫_0^1 x (1 - sin(x kD r) / (x kD r)) coth(x thetaD / 2T) dx
+pub fn sigma2_correlated_debye(
+    temperature: f64,
+    theta: f64,
+    reduced_mass: f64,
+    r: f64,
+    rs: f64,
+) -> f64 {
+    let kd_r = (4.5 * std::f64::consts::PI).cbrt() / rs * r;
+
+    let integrand = |x: f64| {
+        if x == 0.0 {
+            return 0.0;
+        }
+
+        let correlation = 1.0 - (x * kd_r).sin() / (x * kd_r);
+        let occupation = if temperature <= 0.0 {
+            1.0
+        } else {
+            1.0 / (x * theta / (2.0 * temperature)).tanh()
+        };
+
+        x * correlation * occupation
+    };
+
+    // Simpson's rule on [0, 1]
+    let n = DEBYE_INTEGRATION_STEPS;
+    let h = 1.0 / n as f64;
+    let integral = (0..=n)
+        .map(|i| {
+            let weight = if i == 0 || i == n {
+                1.0
+            } else if i % 2 == 1 {
+                4.0
+            } else {
+                2.0
+            };
+            weight * integrand(i as f64 * h)
+        })
+        .sum::<f64>()
+        * h
+        / 3.0;
+
+    3.0 * EINSTEIN_FACTOR / (reduced_mass * theta) * integral
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const SIGMA2_TOL: f64 = 1e-6;
+
+    #[test]
+    fn test_sigma2_einstein() {
+        assert_abs_diff_eq!(EINSTEIN_FACTOR, 24.254, epsilon = 1e-3);
+
+        let mu = reduced_mass(63.546, 63.546);
+
+        // zero point motion
+        assert_abs_diff_eq!(
+            sigma2_einstein(0.0, 232.0, mu),
+            EINSTEIN_FACTOR / (mu * 232.0),
+            epsilon = SIGMA2_TOL
+        );
+
+        // classical limit
+        assert_abs_diff_eq!(
+            sigma2_einstein(5000.0, 232.0, mu),
+            2.0 * EINSTEIN_FACTOR * 5000.0 / (mu * 232.0 * 232.0),
+            epsilon = 1e-4
+        );
+
+        // Cu first shell at room temperature
+        assert_abs_diff_eq!(sigma2_einstein(300.0, 232.0, mu), 0.00894, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn test_sigma2_correlated_debye() {
+        let mu = reduced_mass(63.546, 63.546);
+        let model = Sigma2Model::CorrelatedDebye {
+            theta: 315.0,
+            reduced_mass: mu,
+            r: 2.553,
+            rs: 1.41,
+        };
+
+        let sigma2 = model.sigma2_series(&[0.0, 80.0, 300.0]);
+        assert!(sigma2[0] > 0.0 && sigma2[0] < sigma2[1] && sigma2[1] < sigma2[2]);
+
+        // Cu first shell at room temperature is about 0.008 Å^2
+        assert!(sigma2[2] > 0.006 && sigma2[2] < 0.011);
+
+        // Motions of distant atoms are less correlated
+        let far = Sigma2Model::CorrelatedDebye {
+            theta: 315.0,
+            reduced_mass: mu,
+            r: 5.0,
+            rs: 1.41,
+        };
+        assert!(far.sigma2(300.0) > sigma2[2]);
+
+        assert_eq!(model.with_theta(300.0).get_theta(), 300.0);
+    }
+}
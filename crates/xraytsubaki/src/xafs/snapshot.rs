@@ -0,0 +1,234 @@
+//! Named copies of the processing results of a spectrum.
+//!
+//! A snapshot keeps the normalization, background and FT of a spectrum, parameters and
+//! results, so that the result of a parameter change can be compared with the one before
+//! without reloading or recomputing anything. With ProcessingOptions::keep_previous set, the
+//! results replaced by a reprocessing round (normalize, calc_background, fft and ifft, or any
+//! tail of that sequence) are kept together in the snapshot "previous", and
+//! swap_snapshot(PREVIOUS_SNAPSHOT) toggles between before and after.
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use super::background::BackgroundMethod;
+use super::normalization::{Normalization, NormalizationMethod};
+use super::xasspectrum::XASSpectrum;
+use super::xrayfft::{XrayFFTF, XrayFFTR};
+
+/// Name of the snapshot filled in when ProcessingOptions::keep_previous is set
+pub const PREVIOUS_SNAPSHOT: &str = "previous";
+
+/// Product replaced by a processing step, in processing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Product {
+    Normalization,
+    Background,
+    Xftf,
+    Xftr,
+}
+
+/// Processing results of a spectrum. Products which are None were not kept, and are taken from
+/// the spectrum by snapshot_view and swap_snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingSnapshot {
+    pub normalization: Option<NormalizationMethod>,
+    pub background: Option<BackgroundMethod>,
+    pub xftf: Option<XrayFFTF>,
+    pub xftr: Option<XrayFFTR>,
+}
+
+impl XASSpectrum {
+    /// Keep the current processing results under `name`, replacing a snapshot of that name.
+    pub fn take_snapshot<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let snapshot = ProcessingSnapshot {
+            normalization: self.normalization.clone(),
+            background: self.background.clone(),
+            xftf: self.xftf.clone(),
+            xftr: self.xftr.clone(),
+        };
+        self.snapshots.insert(name.into(), snapshot);
+        self
+    }
+
+    pub fn get_snapshot(&self, name: &str) -> Option<&ProcessingSnapshot> {
+        self.snapshots.get(name)
+    }
+
+    pub fn snapshot_names(&self) -> Vec<&String> {
+        self.snapshots.keys().collect()
+    }
+
+    pub fn remove_snapshot(&mut self, name: &str) -> Option<ProcessingSnapshot> {
+        self.snapshots.remove(name)
+    }
+
+    /// Copy of the spectrum with the results of the snapshot, e.g. to plot it next to the
+    /// spectrum. The snapshots are not copied.
+    pub fn snapshot_view(&self, name: &str) -> Result<XASSpectrum, Box<dyn Error>> {
+        let snapshot = self.snapshot_or_error(name)?;
+
+        let mut view = XASSpectrum {
+            snapshots: Default::default(),
+            ..self.clone()
+        };
+        if let Some(normalization) = snapshot.normalization.as_ref() {
+            view.normalization = Some(normalization.clone());
+        }
+        if let Some(background) = snapshot.background.as_ref() {
+            view.background = Some(background.clone());
+        }
+        if let Some(xftf) = snapshot.xftf.as_ref() {
+            view.xftf = Some(xftf.clone());
+        }
+        if let Some(xftr) = snapshot.xftr.as_ref() {
+            view.xftr = Some(xftr.clone());
+        }
+
+        Ok(view)
+    }
+
+    /// Exchange the current results with those of the snapshot. Calling it twice restores
+    /// the spectrum.
+    pub fn swap_snapshot(&mut self, name: &str) -> Result<&mut Self, Box<dyn Error>> {
+        self.snapshot_or_error(name)?;
+        let snapshot = self.snapshots.get_mut(name).unwrap();
+
+        if snapshot.normalization.is_some() {
+            std::mem::swap(&mut self.normalization, &mut snapshot.normalization);
+        }
+        if snapshot.background.is_some() {
+            std::mem::swap(&mut self.background, &mut snapshot.background);
+        }
+        if snapshot.xftf.is_some() {
+            std::mem::swap(&mut self.xftf, &mut snapshot.xftf);
+        }
+        if snapshot.xftr.is_some() {
+            std::mem::swap(&mut self.xftr, &mut snapshot.xftr);
+        }
+
+        Ok(self)
+    }
+
+    fn snapshot_or_error(&self, name: &str) -> Result<&ProcessingSnapshot, Box<dyn Error>> {
+        self.snapshots
+            .get(name)
+            .ok_or_else(|| format!("No snapshot named {}", name).into())
+    }
+
+    // Called by normalize, calc_background, fft and ifft before replacing `product`. A step
+    // which does not follow an earlier step of the current round starts a new round: the
+    // snapshot "previous" is replaced by the computed results of `product` and of every
+    // product after it, all taken before the round changes any of them.
+    pub(crate) fn keep_previous(&mut self, product: Product) {
+        let new_round = self.last_replaced.is_none_or(|last| last >= product);
+        self.last_replaced = Some(product);
+
+        if !new_round || !self.processing_options.keep_previous.unwrap_or(false) {
+            return;
+        }
+
+        let normalized = self
+            .normalization
+            .as_ref()
+            .and_then(|n| n.get_norm())
+            .is_some();
+        let kept = |kept_product: Product, computed: bool| product <= kept_product && computed;
+
+        let snapshot = ProcessingSnapshot {
+            normalization: self
+                .normalization
+                .clone()
+                .filter(|_| kept(Product::Normalization, normalized)),
+            background: self
+                .background
+                .clone()
+                .filter(|_| kept(Product::Background, self.get_chi().is_some())),
+            xftf: self
+                .xftf
+                .clone()
+                .filter(|_| kept(Product::Xftf, self.get_chir_mag().is_some())),
+            xftr: self
+                .xftr
+                .clone()
+                .filter(|_| kept(Product::Xftr, self.get_q().is_some())),
+        };
+
+        if snapshot == ProcessingSnapshot::default() {
+            self.snapshots.remove(PREVIOUS_SNAPSHOT);
+        } else {
+            self.snapshots
+                .insert(PREVIOUS_SNAPSHOT.to_string(), snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_snapshots() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.calc_background()?.fft()?;
+        let before = spectrum.get_chi();
+
+        // Without keep_previous nothing is kept
+        spectrum.calc_background()?;
+        assert!(spectrum.snapshot_names().is_empty());
+
+        spectrum.processing_options.keep_previous = Some(true);
+        if let Some(BackgroundMethod::AUTOBK(autobk)) = spectrum.background.as_mut() {
+            autobk.rbkg = Some(1.4);
+        }
+        spectrum.calc_background()?.fft()?;
+        let after = spectrum.get_chi();
+        assert_ne!(before, after);
+
+        let previous = spectrum.get_snapshot(PREVIOUS_SNAPSHOT).unwrap();
+        assert!(previous.normalization.is_none());
+        assert!(previous.background.is_some());
+        assert_eq!(spectrum.snapshot_view(PREVIOUS_SNAPSHOT)?.get_chi(), before);
+
+        spectrum.swap_snapshot(PREVIOUS_SNAPSHOT)?;
+        assert_eq!(spectrum.get_chi(), before);
+        spectrum.swap_snapshot(PREVIOUS_SNAPSHOT)?;
+        assert_eq!(spectrum.get_chi(), after);
+
+        // A round starting at normalize keeps all products of the same earlier round,
+        // including xftr, and later steps of the round do not replace them
+        spectrum.normalize()?.calc_background()?.fft()?.ifft()?;
+        let chi = spectrum.get_chi();
+        let chiq = spectrum.get_chiq();
+        let chir_mag = spectrum.get_chir_mag().map(|c| c.to_owned());
+        if let Some(NormalizationMethod::PrePostEdge(pre_post_edge)) =
+            spectrum.normalization.as_mut()
+        {
+            pre_post_edge.norm_polyorder = Some(1);
+        }
+        spectrum.normalize()?.calc_background()?.fft()?.ifft()?;
+        let previous = spectrum.get_snapshot(PREVIOUS_SNAPSHOT).unwrap();
+        assert!(previous.normalization.is_some());
+        assert_eq!(previous.background.as_ref().unwrap().get_chi(), chi);
+        assert_eq!(
+            previous
+                .xftf
+                .as_ref()
+                .unwrap()
+                .get_chir_mag()
+                .map(|c| c.to_owned()),
+            chir_mag
+        );
+        assert_eq!(previous.xftr.as_ref().unwrap().get_chiq(), chiq);
+
+        spectrum.take_snapshot("rbkg 1.4");
+        assert_eq!(spectrum.snapshot_names(), vec!["previous", "rbkg 1.4"]);
+        assert!(spectrum.swap_snapshot("rbkg 1.0").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+//! Library of normalized reference spectra.
+//!
+//! Standards are kept as normalized mu(E) together with the absorbing element and edge, so
+//! that they can be looked up for a sample. Collections exported from Athena as .nor files
+//! can be imported in one call with StandardsLibrary::import_athena_nor.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::io::{self, LoadError};
+use super::mathutils::MathUtils;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+const ELEMENTS: [&str; 98] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl",
+    "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As",
+    "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In",
+    "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd", "Pm", "Sm", "Eu", "Gd", "Tb",
+    "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir", "Pt", "Au", "Hg", "Tl",
+    "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk",
+    "Cf",
+];
+
+const EDGES: [&str; 9] = ["K", "L1", "L2", "L3", "M1", "M2", "M3", "M4", "M5"];
+
+/// Normalized reference spectrum
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Standard {
+    pub name: String,
+    /// Symbol of the absorbing element, e.g. "Fe"
+    pub element: Option<String>,
+    /// Absorption edge, e.g. "K" or "L3"
+    pub edge: Option<String>,
+    pub e0: Option<f64>,
+    pub energy: Array1<f64>,
+    /// Normalized mu(E)
+    pub norm: Array1<f64>,
+    /// File the standard was imported from
+    pub source: Option<PathBuf>,
+}
+
+impl Standard {
+    pub fn new<S: Into<String>>(name: S, energy: Array1<f64>, norm: Array1<f64>) -> Standard {
+        Standard {
+            name: name.into(),
+            element: None,
+            edge: None,
+            e0: None,
+            energy,
+            norm,
+            source: None,
+        }
+    }
+
+    /// Spectrum with the normalized mu(E) of the standard as mu, named after the standard.
+    pub fn to_spectrum(&self) -> XASSpectrum {
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum(self.energy.clone(), self.norm.clone())
+            .set_name(self.name.clone());
+        if let Some(e0) = self.e0 {
+            spectrum.set_e0(e0);
+        }
+        if let Some(element) = self.element.as_ref() {
+            spectrum.set_metadata("element", element.clone());
+        }
+        if let Some(edge) = self.edge.as_ref() {
+            spectrum.set_metadata("edge", edge.clone());
+        }
+        spectrum
+    }
+}
+
+/// Standards by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StandardsLibrary {
+    pub standards: BTreeMap<String, Standard>,
+}
+
+impl StandardsLibrary {
+    pub fn new() -> StandardsLibrary {
+        StandardsLibrary::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.standards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.standards.is_empty()
+    }
+
+    /// Add a standard, replacing a standard with the same name.
+    pub fn add(&mut self, standard: Standard) -> &mut Self {
+        self.standards.insert(standard.name.clone(), standard);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Standard> {
+        self.standards.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Standard> {
+        self.standards.remove(name)
+    }
+
+    /// Standards of `element`, optionally restricted to `edge`. Symbols and edges are compared
+    /// ignoring case.
+    pub fn find(&self, element: &str, edge: Option<&str>) -> Vec<&Standard> {
+        let matches = |value: &Option<String>, expected: &str| {
+            value
+                .as_deref()
+                .is_some_and(|value| value.eq_ignore_ascii_case(expected))
+        };
+
+        self.standards
+            .values()
+            .filter(|standard| matches(&standard.element, element))
+            .filter(|standard| edge.is_none_or(|edge| matches(&standard.edge, edge)))
+            .collect()
+    }
+
+    /// Import all files in `dir` matching `pattern` (e.g. "*.nor") as Athena-exported
+    /// normalized spectra (see load_athena_nor).
+    ///
+    /// Files that cannot be read are reported instead of aborting the import. Returns the
+    /// names of the imported standards and the errors.
+    pub fn import_athena_nor<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        pattern: &str,
+    ) -> Result<(Vec<String>, Vec<LoadError>), Box<dyn Error>> {
+        let mut files = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| io::wildcard_match(pattern, name))
+            })
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+
+        let mut names = Vec::new();
+        let mut errors = Vec::new();
+        for path in files {
+            match load_athena_nor(&path) {
+                Ok(standard) => {
+                    names.push(standard.name.clone());
+                    self.add(standard);
+                }
+                Err(error) => errors.push(LoadError {
+                    path,
+                    message: error.to_string(),
+                }),
+            }
+        }
+
+        Ok((names, errors))
+    }
+}
+
+/// Read a normalized spectrum exported by Athena.
+///
+/// The file has `#` comment lines and whitespace separated columns. The energy is the first
+/// column and the normalized mu(E) the column labelled "norm" in the last comment line, or the
+/// second column if there is no such label. The element, edge and e0 are read from header
+/// lines such as "# Element.symbol: Fe", "# Element.edge: K" and "# e0 = 7112", and the
+/// element and edge otherwise from the file name, e.g. "FeO_Fe_K.nor".
+pub fn load_athena_nor<P: AsRef<Path>>(path: P) -> Result<Standard, Box<dyn Error>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+
+    let mut header = BTreeMap::new();
+    let mut labels = Vec::new();
+    let mut rows = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let comment = comment.trim();
+            match comment.split_once(':').or_else(|| comment.split_once('=')) {
+                Some((key, value)) => {
+                    header.insert(key.trim().to_lowercase(), value.trim().to_string());
+                }
+                None if !comment.starts_with('-') && !comment.is_empty() => {
+                    labels = comment
+                        .split_whitespace()
+                        .map(str::to_lowercase)
+                        .collect::<Vec<String>>();
+                }
+                None => {}
+            }
+            continue;
+        }
+
+        rows.push(
+            line.split_whitespace()
+                .map(str::parse::<f64>)
+                .collect::<Result<Vec<f64>, _>>()?,
+        );
+    }
+
+    let column = labels.iter().position(|label| label == "norm").unwrap_or(1);
+    if rows.len() < 2 || rows.iter().any(|row| row.len() <= column) {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    let energy = rows.iter().map(|row| row[0]).collect::<Array1<f64>>();
+    let norm = rows.iter().map(|row| row[column]).collect::<Array1<f64>>();
+    let (energy, norm) = if energy.is_sorted() {
+        (energy, norm)
+    } else {
+        let order = energy.argsort();
+        (
+            energy.select(ndarray::Axis(0), &order),
+            norm.select(ndarray::Axis(0), &order),
+        )
+    };
+
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .ok_or("file without a name")?;
+    let field = |keys: &[&str]| keys.iter().find_map(|key| header.get(*key).cloned());
+    let (name_element, name_edge) = element_edge_from_name(&name).unzip();
+
+    let mut standard = Standard::new(name, energy, norm);
+    standard.element = field(&["element.symbol", "element"])
+        .and_then(|symbol| element_symbol(&symbol))
+        .or(name_element);
+    standard.edge = field(&["element.edge", "edge"])
+        .and_then(|edge| edge_name(&edge))
+        .or(name_edge);
+    standard.e0 = field(&["e0", "bkg_e0", "athena.e0"]).and_then(|e0| e0.parse().ok());
+    standard.source = Some(path.to_path_buf());
+
+    Ok(standard)
+}
+
+fn element_symbol(symbol: &str) -> Option<String> {
+    ELEMENTS
+        .iter()
+        .find(|element| element.eq_ignore_ascii_case(symbol))
+        .map(|element| element.to_string())
+}
+
+fn edge_name(edge: &str) -> Option<String> {
+    EDGES
+        .iter()
+        .find(|e| e.eq_ignore_ascii_case(edge))
+        .map(|e| e.to_string())
+}
+
+// The first element symbol followed by an edge in the words of the name, e.g. "FeO_Fe_K"
+fn element_edge_from_name(name: &str) -> Option<(String, String)> {
+    let words = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>();
+
+    words
+        .windows(2)
+        .find_map(|pair| Some((element_symbol(pair[0])?, edge_name(pair[1])?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_nor(path: &Path, header: &[&str]) -> Result<(), Box<dyn Error>> {
+        let mut file = std::fs::File::create(path)?;
+        for line in header {
+            writeln!(file, "# {}", line)?;
+        }
+        writeln!(file, "# ---------------")?;
+        writeln!(file, "#  e  norm  der_norm")?;
+        for i in 0..20 {
+            let e = 7100.0 + i as f64;
+            writeln!(file, "{} {} 0.0", e, (i as f64 / 10.0).min(1.0))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_athena_nor() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join("xraytsubaki_test_standards");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        write_nor(&dir.join("FeO_Fe_K.nor"), &["Athena data file"])?;
+        write_nor(
+            &dir.join("hematite.nor"),
+            &["Element.symbol: Fe", "Element.edge: K", "e0 = 7112.0"],
+        )?;
+        write_nor(&dir.join("Cu2O_Cu-K.nor"), &[])?;
+        std::fs::write(dir.join("broken.nor"), "# e norm\n7100 x\n")?;
+        std::fs::write(dir.join("notes.txt"), "not a standard")?;
+
+        let mut library = StandardsLibrary::new();
+        let (names, errors) = library.import_athena_nor(&dir, "*.nor")?;
+        assert_eq!(names, vec!["Cu2O_Cu-K", "FeO_Fe_K", "hematite"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].path.ends_with("broken.nor"));
+
+        let iron = library.find("fe", Some("K"));
+        assert_eq!(iron.len(), 2);
+        let hematite = library.get("hematite").unwrap();
+        assert_eq!(hematite.e0, Some(7112.0));
+        assert_eq!(hematite.norm[15], 1.0);
+        assert_eq!(library.find("Cu", None)[0].element.as_deref(), Some("Cu"));
+
+        let spectrum = hematite.to_spectrum();
+        assert_eq!(spectrum.get_e0(), Some(7112.0));
+        assert_eq!(
+            spectrum.get_metadata("element"),
+            Some(&serde_json::json!("Fe"))
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Statistics over the spectra of a group.
+//!
+//! The mean, median, standard deviation and min/max envelope of a product (norm, flat, chi, ...)
+//! are calculated on a common grid. They can be plotted as a band or added to the group as
+//! synthetic spectra.
+
+use std::error::Error;
+
+use ndarray::{Array1, Array2, Axis};
+
+use super::groupview::XASGroupView;
+use super::mathutils::MathUtils;
+use super::plot::{PlotData, PlotKind};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Metadata key marking a synthetic spectrum added by XASGroup::add_statistics
+pub const STATISTIC_KEY: &str = "statistic";
+
+/// Statistics of a product over the spectra of a group
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupStatistics {
+    pub kind: PlotKind,
+    /// Number of spectra
+    pub n: usize,
+    /// Mean e0 of the spectra for which it is known
+    pub e0: Option<f64>,
+    /// Common grid, the x values of the first spectrum covered by all spectra
+    pub x: Array1<f64>,
+    pub mean: Array1<f64>,
+    pub median: Array1<f64>,
+    /// Sample standard deviation
+    pub std: Array1<f64>,
+    pub min: Array1<f64>,
+    pub max: Array1<f64>,
+}
+
+impl GroupStatistics {
+    /// Plot of the mean, with the median, the ±1 std band ("lower", "upper") and the envelope
+    /// ("min", "max") as extras.
+    pub fn plot_data(&self) -> PlotData {
+        PlotData {
+            x: self.x.clone(),
+            y: self.mean.clone(),
+            extras: vec![
+                ("median".to_string(), self.median.clone()),
+                ("lower".to_string(), &self.mean - &self.std),
+                ("upper".to_string(), &self.mean + &self.std),
+                ("min".to_string(), self.min.clone()),
+                ("max".to_string(), self.max.clone()),
+            ],
+        }
+    }
+
+    /// Synthetic spectra of the mean, median, min and max.
+    ///
+    /// Only products in energy space (mu, norm, flat) can be stored as spectra. The std is set
+    /// as delta_mu of the mean, and each spectrum is marked with the STATISTIC_KEY metadata.
+    pub fn to_spectra(&self) -> Result<Vec<XASSpectrum>, Box<dyn Error>> {
+        let product = match self.kind {
+            PlotKind::Mu => "mu",
+            PlotKind::Norm => "norm",
+            PlotKind::Flat => "flat",
+            _ => {
+                return Err(format!(
+                    "{:?}: only energy-space products can be stored as spectra",
+                    self.kind
+                )
+                .into())
+            }
+        };
+
+        [
+            ("mean", &self.mean),
+            ("median", &self.median),
+            ("min", &self.min),
+            ("max", &self.max),
+        ]
+        .iter()
+        .map(|(statistic, y)| {
+            let mut spectrum = XASSpectrum::new();
+            spectrum
+                .set_spectrum(self.x.clone(), (*y).clone())
+                .set_name(format!("{} of {} ({} spectra)", statistic, product, self.n))
+                .set_metadata(STATISTIC_KEY, *statistic);
+
+            if let Some(e0) = self.e0 {
+                spectrum.set_e0(e0);
+            }
+
+            if *statistic == "mean" {
+                spectrum.set_delta_mu(self.std.clone())?;
+            }
+
+            Ok(spectrum)
+        })
+        .collect()
+    }
+}
+
+impl XASGroup {
+    /// Calculate the statistics of `kind` over the spectra of the group.
+    ///
+    /// Synthetic spectra added by add_statistics and spectra excluded from the group are
+    /// ignored. Each spectrum has to be processed up to the requested product.
+    pub fn statistics(&self, kind: PlotKind) -> Result<GroupStatistics, Box<dyn Error>> {
+        self.included_view().statistics(kind)
+    }
+
+    /// Calculate the statistics of `kind` and add the mean, median, min and max to the group as
+    /// synthetic spectra (see GroupStatistics::to_spectra).
+    pub fn add_statistics(&mut self, kind: PlotKind) -> Result<GroupStatistics, Box<dyn Error>> {
+        let statistics = self.statistics(kind)?;
+        self.add_spectra(statistics.to_spectra()?);
+
+        Ok(statistics)
+    }
+}
+
+impl XASGroupView<'_> {
+    /// Calculate the statistics of `kind` over the spectra of the view, ignoring synthetic
+    /// spectra added by XASGroup::add_statistics.
+    pub fn statistics(&self, kind: PlotKind) -> Result<GroupStatistics, Box<dyn Error>> {
+        let spectra = self
+            .spectra()
+            .into_iter()
+            .filter(|spectrum| spectrum.get_metadata(STATISTIC_KEY).is_none())
+            .collect::<Vec<&XASSpectrum>>();
+
+        let (x, values) = common_grid_matrix(&spectra, kind, None)?;
+
+        let n = spectra.len();
+        let ddof = if n > 1 { 1.0 } else { 0.0 };
+        let median = Array1::from_iter(values.axis_iter(Axis(1)).map(|column| {
+            let mut column = column.to_vec();
+            column.sort_by(|a, b| a.total_cmp(b));
+            if n % 2 == 1 {
+                column[n / 2]
+            } else {
+                0.5 * (column[n / 2 - 1] + column[n / 2])
+            }
+        }));
+
+        let e0s = spectra
+            .iter()
+            .filter_map(|spectrum| {
+                spectrum
+                    .get_e0()
+                    .or_else(|| spectrum.normalization.as_ref()?.get_e0())
+            })
+            .collect::<Vec<f64>>();
+        let e0 = if e0s.is_empty() {
+            None
+        } else {
+            Some(e0s.iter().sum::<f64>() / e0s.len() as f64)
+        };
+
+        Ok(GroupStatistics {
+            kind,
+            n,
+            e0,
+            mean: values.mean_axis(Axis(0)).ok_or(XAFSError::NotEnoughData)?,
+            median,
+            std: values.std_axis(Axis(0), ddof),
+            min: values.fold_axis(Axis(0), f64::INFINITY, |a, b| a.min(*b)),
+            max: values.fold_axis(Axis(0), f64::NEG_INFINITY, |a, b| a.max(*b)),
+            x,
+        })
+    }
+}
+
+/// Data of `kind` of the spectra interpolated onto a common grid, one row per spectrum.
+///
+/// The grid is made of the x values of the first spectrum covered by all spectra. With
+/// `shifts`, one per spectrum, the x values of each spectrum are shifted before interpolation.
+pub(crate) fn common_grid_matrix(
+    spectra: &[&XASSpectrum],
+    kind: PlotKind,
+    shifts: Option<&[f64]>,
+) -> Result<(Array1<f64>, Array2<f64>), Box<dyn Error>> {
+    if spectra.is_empty() {
+        return Err(Box::new(XAFSError::GroupIsEmpty));
+    }
+
+    if shifts.is_some_and(|shifts| shifts.len() != spectra.len()) {
+        return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+    }
+
+    let data = spectra
+        .iter()
+        .enumerate()
+        .map(|(i, spectrum)| {
+            let mut data = spectrum.plot_data(kind)?;
+            if let Some(shifts) = shifts {
+                data.x += shifts[i];
+            }
+            Ok(data)
+        })
+        .collect::<Result<Vec<PlotData>, Box<dyn Error>>>()?;
+
+    let xmin = data.iter().map(|d| d.x.min()).fold(f64::MIN, f64::max);
+    let xmax = data.iter().map(|d| d.x.max()).fold(f64::MAX, f64::min);
+    let x = Array1::from_iter(
+        data[0]
+            .x
+            .iter()
+            .cloned()
+            .filter(|x| *x >= xmin && *x <= xmax),
+    );
+
+    if x.len() < 2 {
+        return Err(Box::new(XAFSError::NotEnoughData));
+    }
+
+    let mut values = Array2::zeros((data.len(), x.len()));
+    for (mut row, d) in values.outer_iter_mut().zip(data.iter()) {
+        row.assign(&x.interpolate(&d.x.to_vec(), &d.y.to_vec())?);
+    }
+
+    Ok((x, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TEST_TOL;
+    use crate::xafs::tests::TOP_DIR;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_statistics() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+        let energy = spectrum.raw_energy.clone().unwrap();
+        let mu = spectrum.raw_mu.clone().unwrap();
+
+        let mut group = XASGroup::new();
+        for scale in [1.0, 1.1, 1.3] {
+            let mut scaled = XASSpectrum::new();
+            scaled.set_spectrum(energy.clone(), &mu * scale);
+            group.add_spectrum(scaled);
+        }
+
+        assert!(group.statistics(PlotKind::Norm).is_err());
+
+        group.normalize()?.calc_background()?;
+
+        let statistics = group.add_statistics(PlotKind::Norm)?;
+        assert_eq!(statistics.n, 3);
+        assert_eq!(group.len(), 7);
+
+        // Normalization removes the scale, so that the spread is negligible.
+        let norm = group.spectra[0].normalization.as_ref().unwrap().get_norm();
+        statistics
+            .mean
+            .iter()
+            .zip(norm.unwrap().iter())
+            .for_each(|(a, b)| assert_abs_diff_eq!(a, b, epsilon = TEST_TOL));
+        assert!(statistics.std.iter().all(|s| *s < TEST_TOL));
+
+        let plot = statistics.plot_data();
+        assert_eq!(plot.get_extra("upper").unwrap().len(), plot.x.len());
+
+        // Synthetic spectra are not counted again.
+        let chi = group.statistics(PlotKind::KChi(Some(2)))?;
+        assert_eq!(chi.n, 3);
+        assert!(chi
+            .max
+            .iter()
+            .zip(chi.min.iter())
+            .all(|(max, min)| max >= min));
+        assert!(chi.to_spectra().is_err());
+
+        let mean = &group.spectra[3];
+        assert_eq!(mean.get_metadata(STATISTIC_KEY).unwrap(), "mean");
+        assert_eq!(mean.delta_mu, Some(statistics.std));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+//! Sweeps of a single processing parameter for sensitivity maps.
+//!
+//! A representative spectrum is processed once for each value of the swept parameter while all
+//! other parameters, including e0, are kept at the values of the spectrum. The results are
+//! stacked into a matrix with one row per value, ready for a contour or heat-map plot of
+//! |chi(R)| or the flattened spectrum against the parameter.
+
+use std::error::Error;
+
+use ndarray::{Array1, Array2};
+use serde::{Deserialize, Serialize};
+
+use super::background::{BackgroundMethod, AUTOBK};
+use super::mathutils::MathUtils;
+use super::normalization::{NormalizationMethod, PrePostEdge};
+use super::xasgroup::XASGroup;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Processing parameter varied by a sweep
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SweepParameter {
+    /// Edge energy (eV)
+    E0,
+    /// Start of the pre-edge range (eV, relative to e0)
+    PreEdgeStart,
+    /// End of the pre-edge range (eV, relative to e0)
+    PreEdgeEnd,
+    /// Start of the normalization range (eV, relative to e0)
+    NormStart,
+    /// End of the normalization range (eV, relative to e0)
+    NormEnd,
+    /// Rbkg (Å) of AUTOBK
+    Rbkg,
+}
+
+/// Result stacked into the rows of the sweep
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SweepOutput {
+    /// Flattened spectrum against energy
+    Flat,
+    /// |chi(R)| against R
+    ChirMag,
+}
+
+/// Results of a parameter sweep
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSweep {
+    pub parameter: SweepParameter,
+    pub output: SweepOutput,
+    /// Values of the swept parameter, one per row of `z`
+    pub values: Array1<f64>,
+    /// Energy or R, one per column of `z`
+    pub x: Array1<f64>,
+    /// Flat or |chi(R)| with shape (values.len(), x.len())
+    pub z: Array2<f64>,
+}
+
+impl XASSpectrum {
+    /// Process a copy of the spectrum for each of `values` of `parameter` and stack the
+    /// resulting `output`.
+    ///
+    /// The normalization, background and forward FT parameters of the spectrum are used for
+    /// everything that is not swept, with the defaults if they are not set. Results with a
+    /// different grid than the first value are interpolated onto its grid.
+    pub fn parameter_sweep(
+        &self,
+        parameter: SweepParameter,
+        values: &[f64],
+        output: SweepOutput,
+    ) -> Result<ParameterSweep, Box<dyn Error>> {
+        if values.is_empty() {
+            return Err("no values to sweep".into());
+        }
+
+        let e0 = self
+            .get_e0()
+            .or_else(|| self.normalization.as_ref().and_then(|n| n.get_e0()));
+
+        let mut x: Option<Array1<f64>> = None;
+        let mut rows: Vec<Array1<f64>> = Vec::with_capacity(values.len());
+
+        for &value in values {
+            let spectrum = self.process_sweep_value(parameter, value, e0, output)?;
+
+            let (xi, zi) = match output {
+                SweepOutput::Flat => (
+                    spectrum.energy.clone().ok_or(XAFSError::NotEnoughData)?,
+                    spectrum
+                        .normalization
+                        .as_ref()
+                        .and_then(|n| n.get_flat().cloned())
+                        .ok_or(XAFSError::NotEnoughData)?,
+                ),
+                SweepOutput::ChirMag => (
+                    spectrum
+                        .get_r()
+                        .ok_or(XAFSError::NotEnoughDataForXFTF)?
+                        .to_owned(),
+                    spectrum
+                        .get_chir_mag()
+                        .ok_or(XAFSError::NotEnoughDataForXFTF)?
+                        .to_owned(),
+                ),
+            };
+
+            match x.as_ref() {
+                None => {
+                    x = Some(xi);
+                    rows.push(zi);
+                }
+                Some(x) if x == xi => rows.push(zi),
+                Some(x) => rows.push(x.interpolate(&xi.to_vec(), &zi.to_vec())?),
+            }
+        }
+
+        let x = x.unwrap();
+        let mut z = Array2::zeros((rows.len(), x.len()));
+        for (mut row, values) in z.rows_mut().into_iter().zip(rows.iter()) {
+            row.assign(values);
+        }
+
+        Ok(ParameterSweep {
+            parameter,
+            output,
+            values: Array1::from_vec(values.to_vec()),
+            x,
+            z,
+        })
+    }
+
+    fn process_sweep_value(
+        &self,
+        parameter: SweepParameter,
+        value: f64,
+        e0: Option<f64>,
+        output: SweepOutput,
+    ) -> Result<XASSpectrum, Box<dyn Error>> {
+        let mut spectrum = self.clone();
+
+        let mut normalization = self
+            .normalization
+            .as_ref()
+            .map(|n| n.copy_parameters())
+            .unwrap_or_else(|| NormalizationMethod::PrePostEdge(PrePostEdge::new()));
+        let mut background = self
+            .background
+            .as_ref()
+            .map(|b| b.copy_parameters())
+            .unwrap_or_else(|| BackgroundMethod::AUTOBK(AUTOBK::new()));
+
+        match parameter {
+            SweepParameter::E0 => {
+                spectrum.set_e0(value);
+            }
+            SweepParameter::Rbkg => match &mut background {
+                BackgroundMethod::AUTOBK(autobk) => autobk.rbkg = Some(value),
+                _ => return Err("Rbkg can only be swept for the AUTOBK background".into()),
+            },
+            _ => {
+                let pre_post_edge = match &mut normalization {
+                    NormalizationMethod::PrePostEdge(pre_post_edge) => pre_post_edge,
+                    _ => {
+                        return Err(format!(
+                            "{:?} can only be swept for the PrePostEdge normalization",
+                            parameter
+                        )
+                        .into())
+                    }
+                };
+
+                match parameter {
+                    SweepParameter::PreEdgeStart => pre_post_edge.pre_edge_start = Some(value),
+                    SweepParameter::PreEdgeEnd => pre_post_edge.pre_edge_end = Some(value),
+                    SweepParameter::NormStart => pre_post_edge.norm_start = Some(value),
+                    SweepParameter::NormEnd => pre_post_edge.norm_end = Some(value),
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        if parameter != SweepParameter::E0 {
+            if let Some(e0) = e0 {
+                spectrum.set_e0(e0);
+            }
+        }
+
+        spectrum
+            .set_normalization_method(Some(normalization))?
+            .normalize()?;
+
+        if output == SweepOutput::ChirMag {
+            spectrum
+                .set_background_method(Some(background))?
+                .calc_background()?;
+            spectrum.xftf = Some(
+                self.xftf
+                    .as_ref()
+                    .map(|xftf| xftf.copy_parameters())
+                    .unwrap_or_default(),
+            );
+            spectrum.fft()?;
+        }
+
+        Ok(spectrum)
+    }
+}
+
+impl XASGroup {
+    /// Parameter sweep of the spectrum at `index` (see XASSpectrum::parameter_sweep).
+    pub fn parameter_sweep(
+        &self,
+        index: usize,
+        parameter: SweepParameter,
+        values: &[f64],
+        output: SweepOutput,
+    ) -> Result<ParameterSweep, Box<dyn Error>> {
+        self.get_spectrum(index)?
+            .parameter_sweep(parameter, values, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::io;
+    use crate::xafs::tests::TOP_DIR;
+
+    #[test]
+    fn test_parameter_sweep() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let rbkg = [0.8, 1.0, 1.2, 1.4];
+        let sweep = spectrum.parameter_sweep(SweepParameter::Rbkg, &rbkg, SweepOutput::ChirMag)?;
+        assert_eq!(sweep.z.dim(), (rbkg.len(), sweep.x.len()));
+
+        // A larger rbkg removes more of the low-R signal
+        let r_low = (0..sweep.x.len())
+            .filter(|&i| sweep.x[i] < 0.8)
+            .collect::<Vec<usize>>();
+        let low_r = |row: usize| r_low.iter().map(|&i| sweep.z[[row, i]]).sum::<f64>();
+        assert!(low_r(3) < low_r(0));
+
+        let mut processed = spectrum.clone();
+        processed.normalize()?;
+        let pre_edge_end = [-60.0, -45.0, -30.0];
+        let sweep = processed.parameter_sweep(
+            SweepParameter::PreEdgeEnd,
+            &pre_edge_end,
+            SweepOutput::Flat,
+        )?;
+        assert_eq!(sweep.z.nrows(), 3);
+        assert_eq!(sweep.x, processed.energy.clone().unwrap());
+        assert_ne!(sweep.z.row(0), sweep.z.row(2));
+
+        let mut group = XASGroup::new();
+        assert!(group
+            .parameter_sweep(0, SweepParameter::E0, &[22117.0], SweepOutput::Flat)
+            .is_err());
+
+        group.add_spectrum(spectrum);
+        let sweep = group.parameter_sweep(
+            0,
+            SweepParameter::E0,
+            &[22115.0, 22120.0],
+            SweepOutput::Flat,
+        )?;
+        assert_eq!(sweep.values.to_vec(), vec![22115.0, 22120.0]);
+        assert!(group
+            .parameter_sweep(0, SweepParameter::E0, &[], SweepOutput::Flat)
+            .is_err());
+
+        Ok(())
+    }
+}
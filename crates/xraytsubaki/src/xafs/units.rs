@@ -0,0 +1,118 @@
+//! Physical units of energies, wavenumbers and distances.
+//!
+//! Most parameters are plain f64, and a kmin given in eV or an e0 given in Å⁻¹ is accepted
+//! silently. The newtypes below are accepted by the setters of these parameters next to f64,
+//! so that code passing typed quantities cannot mix them up. They are transparent in
+//! serialized files and convert from any number that converts to f64 (f32, i32, u32, ...), as
+//! the plain f64 setters did.
+
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use serde::{Deserialize, Serialize};
+
+use super::xafsutils::XAFSUtils;
+
+/// Energy in eV
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ElectronVolt(pub f64);
+
+/// Wavenumber in Å⁻¹, e.g. the photoelectron k
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InverseAngstrom(pub f64);
+
+/// Distance in Å, e.g. R of chi(R)
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Angstrom(pub f64);
+
+macro_rules! unit {
+    ($unit:ident, $symbol:expr) => {
+        impl $unit {
+            pub fn value(self) -> f64 {
+                self.0
+            }
+        }
+
+        impl<T: Into<f64>> From<T> for $unit {
+            fn from(value: T) -> Self {
+                $unit(value.into())
+            }
+        }
+
+        impl Add for $unit {
+            type Output = $unit;
+
+            fn add(self, other: $unit) -> $unit {
+                $unit(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $unit {
+            type Output = $unit;
+
+            fn sub(self, other: $unit) -> $unit {
+                $unit(self.0 - other.0)
+            }
+        }
+
+        impl fmt::Display for $unit {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)?;
+                write!(f, " {}", $symbol)
+            }
+        }
+    };
+}
+
+unit!(ElectronVolt, "eV");
+unit!(InverseAngstrom, "Å⁻¹");
+unit!(Angstrom, "Å");
+
+impl ElectronVolt {
+    /// Photoelectron wavenumber of an energy above the edge. Energies below the edge give 0.
+    pub fn to_k(self) -> InverseAngstrom {
+        InverseAngstrom(self.0.etok())
+    }
+}
+
+impl InverseAngstrom {
+    /// Energy above the edge of a photoelectron wavenumber.
+    pub fn to_energy(self) -> ElectronVolt {
+        ElectronVolt(self.0.ktoe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::background::AUTOBK;
+    use crate::xafs::tests::TEST_TOL;
+    use crate::xafs::xrayfft::XrayFFTF;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_units() {
+        let e0 = ElectronVolt(7112.0);
+        let k = (ElectronVolt(7512.0) - e0).to_k();
+        assert_abs_diff_eq!(k.value(), 10.2463, epsilon = 1e-4);
+        assert_abs_diff_eq!(k.to_energy().value(), 400.0, epsilon = TEST_TOL);
+        assert_eq!(ElectronVolt(-5.0).to_k(), InverseAngstrom(0.0));
+
+        assert_eq!(format!("{:.1}", Angstrom(1.26)), "1.3 Å");
+        assert_eq!(serde_json::to_string(&InverseAngstrom(2.5)).unwrap(), "2.5");
+        assert!(InverseAngstrom(2.0) < InverseAngstrom::from(3.0));
+        assert_eq!(ElectronVolt::from(7112.5f32), ElectronVolt(7112.5));
+        assert_eq!(ElectronVolt::from(7112), ElectronVolt(7112.0));
+
+        let mut xftf = XrayFFTF::new();
+        xftf.set_k_range(InverseAngstrom(3.0), InverseAngstrom(12.0));
+        assert_eq!((xftf.kmin, xftf.kmax), (Some(3.0), Some(12.0)));
+
+        let mut autobk = AUTOBK::new();
+        autobk.set_rbkg(Angstrom(1.1)).set_k_range(0.5, 14.0);
+        assert_eq!((autobk.rbkg, autobk.kmax), (Some(1.1), Some(14.0)));
+    }
+}
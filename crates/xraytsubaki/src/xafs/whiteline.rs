@@ -0,0 +1,190 @@
+//! White-line area and height of normalized spectra.
+//!
+//! The baseline under the white line is subtracted from the normalized spectrum and the
+//! difference is integrated with the trapezoidal rule. The uncertainty of the normalized
+//! spectrum (delta_norm) is propagated to the area and height if it is known; the uncertainty
+//! of the baseline itself is not included.
+
+use std::error::Error;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use super::xafsutils;
+use super::xasspectrum::XASSpectrum;
+use super::XAFSError;
+
+/// Baseline subtracted before integrating the white line
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WhitelineBaseline {
+    /// Straight line through the first and last points of the integration range
+    Line,
+    /// Normalized arctangent edge step centered at e0 with the given width (eV)
+    Arctan { width: f64 },
+    /// Normalized atomic background mu0(E) of the background removal
+    Spline,
+}
+
+/// White-line area and height
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Whiteline {
+    /// Area (eV) of the normalized spectrum above the baseline
+    pub area: f64,
+    pub area_std: Option<f64>,
+    /// Maximum of the normalized spectrum above the baseline
+    pub height: f64,
+    pub height_std: Option<f64>,
+    /// Energy (eV) of the maximum
+    pub position: f64,
+    pub energy: Array1<f64>,
+    pub baseline: Array1<f64>,
+}
+
+impl XASSpectrum {
+    /// White-line area and height between `emin` and `emax` (eV, relative to e0).
+    ///
+    /// Requires the spectrum to be normalized, and the Spline baseline requires the background
+    /// to be calculated.
+    pub fn whiteline_area(
+        &self,
+        emin: f64,
+        emax: f64,
+        baseline: WhitelineBaseline,
+    ) -> Result<Whiteline, Box<dyn Error>> {
+        let normalization = self
+            .normalization
+            .as_ref()
+            .ok_or(XAFSError::NotEnoughData)?;
+        let energy = self.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let norm = normalization.get_norm().ok_or(XAFSError::NotEnoughData)?;
+        let e0 = self
+            .get_e0()
+            .or_else(|| normalization.get_e0())
+            .ok_or(XAFSError::NotEnoughData)?;
+
+        let (lo, hi) = (
+            xafsutils::absolute_energy(e0, emin),
+            xafsutils::absolute_energy(e0, emax),
+        );
+        let index = (0..energy.len())
+            .filter(|&i| energy[i] >= lo && energy[i] <= hi)
+            .collect::<Vec<usize>>();
+
+        if index.len() < 3 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let x = Array1::from_iter(index.iter().map(|&i| energy[i]));
+        let y = Array1::from_iter(index.iter().map(|&i| norm[i]));
+        let n = x.len();
+
+        let base = match baseline {
+            WhitelineBaseline::Line => {
+                let slope = (y[n - 1] - y[0]) / (x[n - 1] - x[0]);
+                x.mapv(|e| y[0] + slope * (e - x[0]))
+            }
+            WhitelineBaseline::Arctan { width } => {
+                if width <= 0.0 {
+                    return Err("the width of the arctan baseline must be positive".into());
+                }
+                x.mapv(|e| 0.5 + ((e - e0) / width).atan() / std::f64::consts::PI)
+            }
+            WhitelineBaseline::Spline => {
+                let bkg = self
+                    .background
+                    .as_ref()
+                    .and_then(|b| b.get_bkg())
+                    .ok_or(XAFSError::NotEnoughData)?;
+                let mu = self.mu.as_ref().ok_or(XAFSError::NotEnoughData)?;
+                let edge_step = normalization
+                    .get_edge_step()
+                    .ok_or(XAFSError::NotEnoughData)?;
+
+                // norm = (mu - pre_edge) / edge_step, so the normalized bkg is
+                // (bkg - pre_edge) / edge_step = norm + (bkg - mu) / edge_step.
+                Array1::from_iter(
+                    index
+                        .iter()
+                        .map(|&i| norm[i] + (bkg[i] - mu[i]) / edge_step),
+                )
+            }
+        };
+
+        let diff = &y - &base;
+
+        // Trapezoidal weights, so that area = sum(weights * diff)
+        let weights = Array1::from_iter((0..n).map(|i| {
+            let left = if i > 0 { x[i] - x[i - 1] } else { 0.0 };
+            let right = if i + 1 < n { x[i + 1] - x[i] } else { 0.0 };
+            0.5 * (left + right)
+        }));
+        let area = (&weights * &diff).sum();
+
+        let imax = (0..n).max_by(|&a, &b| diff[a].total_cmp(&diff[b])).unwrap();
+
+        let delta_norm = normalization
+            .get_delta_norm()
+            .map(|d| Array1::from_iter(index.iter().map(|&i| d[i])));
+
+        Ok(Whiteline {
+            area,
+            area_std: delta_norm
+                .as_ref()
+                .map(|d| (&weights * d).mapv(|x| x * x).sum().sqrt()),
+            height: diff[imax],
+            height_std: delta_norm.as_ref().map(|d| d[imax]),
+            position: x[imax],
+            energy: x,
+            baseline: base,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_whiteline_area() -> Result<(), Box<dyn Error>> {
+        // Unit arctan edge at 10000 eV with a Gaussian white line of area 6 eV
+        let (e0, width, sigma, area) = (10000.0, 1.0, 2.0, 6.0);
+        let energy = Array1::linspace(9800.0, 10600.0, 1601);
+        let peak = |e: f64| {
+            area / (sigma * (2.0 * std::f64::consts::PI).sqrt())
+                * (-0.5 * ((e - e0 - 5.0) / sigma).powi(2)).exp()
+        };
+        let mu =
+            energy.mapv(|e: f64| 0.5 + ((e - e0) / width).atan() / std::f64::consts::PI + peak(e));
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum
+            .set_spectrum(energy.clone(), mu)
+            .set_e0(e0)
+            .set_delta_mu(Array1::from_elem(energy.len(), 0.01))?;
+
+        let arctan = WhitelineBaseline::Arctan { width };
+        assert!(spectrum.whiteline_area(-10.0, 30.0, arctan).is_err());
+
+        spectrum.normalize()?;
+        let whiteline = spectrum.whiteline_area(-10.0, 30.0, arctan)?;
+
+        // Within the error of the pre- and post-edge lines fitted to the slow arctan tails
+        assert_abs_diff_eq!(whiteline.area, area, epsilon = 0.05 * area);
+        assert_abs_diff_eq!(whiteline.position, e0 + 5.0, epsilon = 0.5);
+        assert_abs_diff_eq!(whiteline.height, peak(e0 + 5.0), epsilon = 0.05);
+        assert!(whiteline.area_std.unwrap() > 0.0);
+        assert!(whiteline.height_std.unwrap() > 0.0);
+
+        // The line through the ends of the range runs below the edge step and adds part of it.
+        let line = spectrum.whiteline_area(-10.0, 30.0, WhitelineBaseline::Line)?;
+        assert!(line.area > whiteline.area);
+
+        assert!(spectrum
+            .whiteline_area(-10.0, 30.0, WhitelineBaseline::Spline)
+            .is_err());
+        assert!(spectrum.whiteline_area(5.0, 5.1, arctan).is_err());
+
+        Ok(())
+    }
+}
@@ -38,6 +38,8 @@ pub mod constants {
     pub const hbar: f64 = h / (2.0 * std::f64::consts::PI); // reduced Planck constant
     pub const m_e: f64 = 9.1093837015e-31; // electron mass
     pub const e: f64 = 1.602176634e-19; // elementary charge
+    pub const k_B: f64 = 1.380649e-23; // Boltzmann constant
+    pub const amu: f64 = 1.66053906660e-27; // atomic mass unit
     pub const KTOE: f64 = 1.0e20 * hbar * hbar / (2.0 * m_e * e); // convert wavenumber to energy
     pub const ETOK: f64 = 1.0 / KTOE; // convert energy to wavenumber
 }
@@ -45,7 +47,10 @@ pub mod constants {
 /// Trait for xafs utilities
 /// functions for f64, Vec<f64>, and ArrayBase<OwnedRepr<f64>, Ix1>
 pub trait XAFSUtils {
+    /// Wavenumber k (Å⁻¹) of an energy above the edge (eV), k = sqrt(E * ETOK). Energies
+    /// below the edge give 0.
     fn etok(&self) -> Self;
+    /// Energy above the edge (eV) of a wavenumber k (Å⁻¹), E = k^2 * KTOE.
     fn ktoe(&self) -> Self;
 }
 
@@ -55,11 +60,11 @@ impl XAFSUtils for f64 {
             return 0.0;
         }
 
-        self.sqrt() * constants::KTOE
+        (self * constants::ETOK).sqrt()
     }
 
     fn ktoe(&self) -> Self {
-        self.powi(2) * constants::ETOK
+        self.powi(2) * constants::KTOE
     }
 }
 
@@ -75,11 +80,11 @@ impl XAFSUtils for Vec<f64> {
 
 impl XAFSUtils for ArrayBase<OwnedRepr<f64>, Ix1> {
     fn etok(&self) -> Self {
-        self.mapv(|x| x.sqrt() * constants::KTOE)
+        self.mapv(|x| x.etok())
     }
 
     fn ktoe(&self) -> Self {
-        self.mapv(|x| x.powi(2) * constants::ETOK)
+        self.mapv(|x| x.ktoe())
     }
 }
 
@@ -266,6 +271,54 @@ pub fn remove_nan2(
     (arr1.into(), arr2.into())
 }
 
+/// Mask of single point spikes of mu, e.g. monochromator glitches.
+///
+/// The residual of mu from its running median over `window` points is compared with the robust
+/// standard deviation of the residuals, as in mathutils::despike with SpikeSigma::OffMedian.
+/// Points whose residual exceeds `threshold` standard deviations are marked. The running median
+/// follows edges and other monotonic features, so that mainly narrow features leave a residual;
+/// a sharp white line sampled with few points may be marked as well, which find_glitches of the
+/// glitch module avoids with i0.
+/// The first and last points, which have neighbours on one side only, are never marked.
+///
+/// # Arguments
+/// * `mu` - Array of mu values
+/// * `window` - Points of the running median (default 5)
+/// * `threshold` - Residual in standard deviations above which a point is a spike (default 8)
+///
+/// # Returns
+/// * `mask` - true at the spikes
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::spike_mask;
+/// use ndarray::Array1;
+///
+/// let mut mu = Array1::from_iter((0..100).map(|i| (i as f64 * 0.05).atan() + 0.01 * (i as f64).powi(2).sin()));
+/// mu[40] += 0.1;
+/// let mask = spike_mask(&mu, None, None);
+/// assert!(mask[40]);
+/// assert_eq!(mask.iter().filter(|m| **m).count(), 1);
+/// ```
+pub fn spike_mask(
+    mu: &ArrayBase<OwnedRepr<f64>, Ix1>,
+    window: Option<usize>,
+    threshold: Option<f64>,
+) -> Array1<bool> {
+    let (_, spikes) = super::mathutils::despike(
+        mu,
+        window.unwrap_or(5),
+        threshold.unwrap_or(8.0),
+        super::mathutils::SpikeSigma::OffMedian,
+    );
+
+    let mut mask = Array1::from_elem(mu.len(), false);
+    for i in spikes.into_iter().filter(|&i| i > 0 && i + 1 < mu.len()) {
+        mask[i] = true;
+    }
+    mask
+}
+
 /// Function to find the energy step of an array of energies.
 /// It ignores the smallest fraction of energy steps (frac_ignore) and then averages the next nave steps.
 ///
@@ -315,6 +368,182 @@ pub fn find_energy_step<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
 
     return ediff[nskip..ediff_end].iter().sum::<f64>() / (ediff_end - nskip) as f64;
 }
+
+/// Kind of an irregular step of an energy axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnergyStepAnomalyKind {
+    /// Step below the tiny energy, e.g. a repeated point of a stalled monochromator
+    Zero,
+    /// Step back in energy, e.g. from a backlash of the monochromator
+    Negative,
+    /// Step much larger than its neighbours, a dead band without data
+    LargeJump,
+}
+
+/// Irregular step between the points `index` and `index + 1` of an energy axis
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnergyStepAnomaly {
+    pub index: usize,
+    /// Energy of the point before the step
+    pub energy: f64,
+    pub step: f64,
+    pub kind: EnergyStepAnomalyKind,
+}
+
+/// All steps of an energy axis with the irregular ones, returned by find_energy_step_report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnergyStepReport {
+    /// Average energy step of the sorted axis as returned by find_energy_step, NaN for fewer
+    /// than 2 points
+    pub step: f64,
+    /// Steps in the order of the axis
+    pub steps: Array1<f64>,
+    pub anomalies: Vec<EnergyStepAnomaly>,
+}
+
+impl EnergyStepReport {
+    /// Whether no step is irregular
+    pub fn is_regular(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+    pub fn anomalies_of(&self, kind: EnergyStepAnomalyKind) -> Vec<&EnergyStepAnomaly> {
+        self.anomalies.iter().filter(|a| a.kind == kind).collect()
+    }
+
+    /// Histogram of the steps with `nbins` bins between the smallest and the largest step.
+    /// Returns the `nbins + 1` bin edges and the counts.
+    pub fn histogram(&self, nbins: usize) -> (Array1<f64>, Vec<usize>) {
+        let nbins = nbins.max(1);
+        let mut counts = vec![0; nbins];
+        if self.steps.is_empty() {
+            return (Array1::zeros(nbins + 1), counts);
+        }
+
+        let (min, max) = (self.steps.min(), self.steps.max());
+
+        let width = (max - min) / nbins as f64;
+        for step in self.steps.iter() {
+            let bin = match width > 0.0 {
+                true => ((step - min) / width) as usize,
+                false => 0,
+            };
+            counts[bin.min(nbins - 1)] += 1;
+        }
+
+        (Array1::linspace(min, max, nbins + 1), counts)
+    }
+
+    /// One line per kind of anomaly, e.g. for the notes of a spectrum
+    pub fn warnings(&self) -> Vec<String> {
+        [
+            (EnergyStepAnomalyKind::Zero, "zero energy steps"),
+            (EnergyStepAnomalyKind::Negative, "negative energy steps"),
+            (EnergyStepAnomalyKind::LargeJump, "large energy jumps"),
+        ]
+        .iter()
+        .filter_map(|(kind, label)| {
+            let anomalies = self.anomalies_of(*kind);
+            let (first, last) = (anomalies.first()?, anomalies.last()?);
+            Some(format!(
+                "{} {} between {} and {} eV",
+                anomalies.len(),
+                label,
+                first.energy,
+                last.energy
+            ))
+        })
+        .collect()
+    }
+}
+
+/// Function to find the energy step of an array of energies together with all steps and the
+/// irregular ones.
+///
+/// find_energy_step averages the small steps and hides acquisition problems. The report keeps
+/// the steps in the order of the array and flags steps below `tiny_energy`, negative steps and
+/// steps larger than `jump_factor` times the median of the 5 steps around them, which follows
+/// the change of step size between the regions of a step scan.
+///
+/// # Arguments
+/// * `energy` - Array of energies, in the order of acquisition
+/// * `frac_ignore` - Fraction of energy steps to ignore (default 0.01)
+/// * `nave` - Number of energy steps to average (default 10)
+/// * `tiny_energy` - Steps below this are zero steps (default TINY_ENERGY)
+/// * `jump_factor` - Ratio to the local median step above which a step is a jump (default 5)
+///
+/// # Returns
+/// * `report` - Average step, steps and anomalies
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::{find_energy_step_report, EnergyStepAnomalyKind};
+/// use ndarray::array;
+///
+/// let energy = array![0.0, 1.0, 2.0, 2.0, 3.0, 4.0, 20.0, 21.0, 22.0, 21.5, 23.0];
+/// let report = find_energy_step_report(energy, None, None, None, None);
+/// let kinds = report.anomalies.iter().map(|a| a.kind).collect::<Vec<_>>();
+/// assert_eq!(
+///     kinds,
+///     vec![
+///         EnergyStepAnomalyKind::Zero,
+///         EnergyStepAnomalyKind::LargeJump,
+///         EnergyStepAnomalyKind::Negative
+///     ]
+/// );
+/// assert_eq!(report.anomalies[1].energy, 4.0);
+/// ```
+pub fn find_energy_step_report<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+    energy: T,
+    frac_ignore: Option<f64>,
+    nave: Option<usize>,
+    tiny_energy: Option<f64>,
+    jump_factor: Option<f64>,
+) -> EnergyStepReport {
+    let energy = energy.into();
+    let tiny_energy = tiny_energy.unwrap_or(TINY_ENERGY);
+    let jump_factor = jump_factor.unwrap_or(5.0);
+
+    if energy.len() < 2 {
+        return EnergyStepReport {
+            step: f64::NAN,
+            steps: Array1::zeros(0),
+            anomalies: Vec::new(),
+        };
+    }
+
+    let steps = (&energy.slice(ndarray::s![1..]) - &energy.slice(ndarray::s![..-1])).to_owned();
+    let local = super::mathutils::median_filter(&steps.mapv(f64::abs).to_vec(), 5);
+
+    let anomalies = steps
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &step)| {
+            let kind = if step.abs() < tiny_energy {
+                EnergyStepAnomalyKind::Zero
+            } else if step < 0.0 {
+                EnergyStepAnomalyKind::Negative
+            } else if local[index] >= tiny_energy && step > jump_factor * local[index] {
+                EnergyStepAnomalyKind::LargeJump
+            } else {
+                return None;
+            };
+
+            Some(EnergyStepAnomaly {
+                index,
+                energy: energy[index],
+                step,
+                kind,
+            })
+        })
+        .collect();
+
+    EnergyStepReport {
+        step: find_energy_step(energy, frac_ignore, nave, Some(true)),
+        steps,
+        anomalies,
+    }
+}
 /// Calculate the $E_0$, the energy threshold of absoption, or the edge energy, given $\mu(E)$.
 ///
 /// $E_0$ is found as the point with maximum derivative with some checks to avoid spurious glitches.
@@ -344,25 +573,338 @@ pub fn find_e0<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
     energy: T,
     mu: T,
 ) -> Result<f64, Box<dyn Error>> {
+    find_e0_with_options(energy, mu, &FindE0Options::default())
+}
+
+/// Robustness options of find_e0 for data with spikes or glitches near the ends of the scan
+///
+/// The defaults disable both filters, which gives the same result as xraylarch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FindE0Options {
+    /// Number of points excluded at the start of the scan
+    pub exclude_start: Option<usize>,
+    /// Number of points excluded at the end of the scan
+    pub exclude_end: Option<usize>,
+    /// Width in points of the median filter applied to the derivative. Suppresses single-point
+    /// spikes, which would otherwise be taken as the edge.
+    pub median_window: Option<usize>,
+    /// Estimator of the derivative (default Central, as xraylarch)
+    pub derivative: Option<DerivativeMethod>,
+    /// Width of the Lorentzian smoothing of the derivative near the edge, in units of the
+    /// energy step (default 3). Zero disables the smoothing.
+    pub smooth_width: Option<f64>,
+    /// Upsampling factor of the edge region. If above 1, mu around the edge is interpolated
+    /// with a cubic spline on a grid `upsample` times finer, and e0 is the fitted position of
+    /// the maximum of its derivative instead of the nearest energy point. For coarse-step
+    /// quick scans.
+    pub upsample: Option<usize>,
+}
+
+/// Estimator of the derivative dmu/dE used by find_e0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DerivativeMethod {
+    /// Forward difference, the last point repeats the last step
+    Forward,
+    /// Central difference as numpy.gradient
+    #[default]
+    Central,
+    /// Slope of a local least-squares polynomial of `order` over `window` points
+    /// (Savitzky-Golay). Energy steps do not need to be even.
+    SavitzkyGolay { window: usize, order: usize },
+}
+
+/// e0 with the uncertainty estimated from the width of the derivative peak
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct E0Estimate {
+    pub e0: f64,
+    /// Standard error of the position of the derivative peak: its FWHM converted to a Gaussian
+    /// standard deviation and divided by the square root of the number of points above half
+    /// maximum.
+    pub uncertainty: f64,
+}
+
+/// Derivative dy/dx with `method`.
+///
+/// # Example
+/// ```
+/// use ndarray::Array1;
+/// use xraytsubaki::xafs::xafsutils::{derivative, DerivativeMethod};
+///
+/// let x: Array1<f64> = Array1::linspace(0.0, 2.0, 21);
+/// let y = x.mapv(|x| x * x);
+/// let method = DerivativeMethod::SavitzkyGolay { window: 5, order: 2 };
+/// let dy = derivative(&x, &y, method).unwrap();
+/// assert!((dy[10] - 2.0).abs() < 1e-10);
+/// ```
+pub fn derivative(
+    x: &Array1<f64>,
+    y: &Array1<f64>,
+    method: DerivativeMethod,
+) -> Result<Array1<f64>, Box<dyn Error>> {
+    if x.len() != y.len() {
+        return Err(Box::new(super::XAFSError::NotEnoughData));
+    }
+
+    let n = x.len();
+    match method {
+        DerivativeMethod::Central => Ok(y.gradient() / x.gradient()),
+        DerivativeMethod::Forward => {
+            if n < 2 {
+                return Err(Box::new(super::XAFSError::NotEnoughData));
+            }
+            Ok(Array1::from_shape_fn(n, |i| {
+                let i = i.min(n - 2);
+                (y[i + 1] - y[i]) / (x[i + 1] - x[i])
+            }))
+        }
+        DerivativeMethod::SavitzkyGolay { window, order } => {
+            if order == 0 || window <= order || window > n {
+                return Err(format!(
+                    "Savitzky-Golay derivative needs 0 < order < window <= {} points",
+                    n
+                )
+                .into());
+            }
+
+            (0..n)
+                .map(|i| {
+                    // The window is shifted inward at the ends to keep its size
+                    let lo = i.saturating_sub(window / 2).min(n - window);
+                    let hi = lo + window;
+                    let scale = (x[hi - 1] - x[lo]).abs().max(f64::MIN_POSITIVE);
+                    let t = x
+                        .slice(ndarray::s![lo..hi])
+                        .mapv(|xj| (xj - x[i]) / scale)
+                        .to_vec();
+                    let coefficients = polyfit_rs::polyfit_rs::polyfit(
+                        &t,
+                        &y.slice(ndarray::s![lo..hi]).to_vec(),
+                        order,
+                    )?;
+                    Ok(coefficients[1] / scale)
+                })
+                .collect()
+        }
+    }
+}
+
+impl FindE0Options {
+    pub fn new() -> FindE0Options {
+        FindE0Options::default()
+    }
+
+    pub fn set_exclude(&mut self, start: Option<usize>, end: Option<usize>) -> &mut Self {
+        self.exclude_start = start;
+        self.exclude_end = end;
+        self
+    }
+
+    pub fn set_median_window(&mut self, median_window: Option<usize>) -> &mut Self {
+        self.median_window = median_window;
+        self
+    }
+
+    pub fn set_derivative(&mut self, derivative: Option<DerivativeMethod>) -> &mut Self {
+        self.derivative = derivative;
+        self
+    }
+
+    pub fn set_smooth_width(&mut self, smooth_width: Option<f64>) -> &mut Self {
+        self.smooth_width = smooth_width;
+        self
+    }
+
+    pub fn set_upsample(&mut self, upsample: Option<usize>) -> &mut Self {
+        self.upsample = upsample;
+        self
+    }
+
+    /// Whether any of the options differs from xraylarch
+    pub fn is_active(&self) -> bool {
+        self.exclude_start.unwrap_or(0) > 0
+            || self.exclude_end.unwrap_or(0) > 0
+            || self.median_window.unwrap_or(0) > 1
+            || self
+                .derivative
+                .is_some_and(|d| d != DerivativeMethod::Central)
+            || self.smooth_width.is_some_and(|w| w != 3.0)
+            || self.upsample.unwrap_or(1) > 1
+    }
+}
+
+/// find_e0 with the pre-filters of `options`.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::{find_e0, find_e0_with_options, FindE0Options};
+/// use ndarray::Array1;
+///
+/// let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 1001);
+/// let mut mu = energy.mapv(|x| (0.5 * (x - 60.0)).atan());
+/// mu[100] += 10.0;
+///
+/// let mut options = FindE0Options::new();
+/// options.set_median_window(Some(5));
+/// let e0 = find_e0_with_options(energy.clone(), mu.clone(), &options).unwrap();
+/// assert!((e0 - 60.0).abs() < 0.5);
+/// ```
+pub fn find_e0_with_options<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+    energy: T,
+    mu: T,
+    options: &FindE0Options,
+) -> Result<f64, Box<dyn Error>> {
+    Ok(find_e0_estimate(energy, mu, options)?.e0)
+}
+
+/// find_e0_with_options, together with the uncertainty of e0.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::{find_e0_estimate, DerivativeMethod, FindE0Options};
+/// use ndarray::Array1;
+///
+/// let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 1001);
+/// let mu = energy.mapv(|x| (0.5 * (x - 60.0)).atan());
+///
+/// let mut options = FindE0Options::new();
+/// options.set_derivative(Some(DerivativeMethod::SavitzkyGolay { window: 7, order: 2 }));
+/// let estimate = find_e0_estimate(energy, mu, &options).unwrap();
+/// assert!((estimate.e0 - 60.0).abs() < 0.2);
+/// assert!(estimate.uncertainty > 0.0 && estimate.uncertainty < 2.0);
+/// ```
+pub fn find_e0_estimate<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+    energy: T,
+    mu: T,
+    options: &FindE0Options,
+) -> Result<E0Estimate, Box<dyn Error>> {
+    find_e0_estimate_with_tolerance(energy, mu, options, (None, None))
+}
+
+// find_e0_estimate with the (tiny, frac) tolerance of remove_dups on the energies, see
+// ProcessingOptions::find_e0_estimate.
+pub(crate) fn find_e0_estimate_with_tolerance<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+    energy: T,
+    mu: T,
+    options: &FindE0Options,
+    dup_tolerance: (Option<f64>, Option<f64>),
+) -> Result<E0Estimate, Box<dyn Error>> {
     let energy: ArrayBase<OwnedRepr<f64>, Ix1> = energy.into();
     let mu: ArrayBase<OwnedRepr<f64>, Ix1> = mu.into();
 
-    let (e1, ie0, estep) = _find_e0(energy.clone(), mu.clone(), None, None)?;
+    let start = options.exclude_start.unwrap_or(0);
+    let stop = energy
+        .len()
+        .min(mu.len())
+        .saturating_sub(options.exclude_end.unwrap_or(0));
+
+    if stop < start + 10 {
+        return Err(Box::new(super::XAFSError::NotEnoughData));
+    }
+
+    let energy = energy.slice(ndarray::s![start..stop]).to_owned();
+    let mu = mu.slice(ndarray::s![start..stop]).to_owned();
+
+    let (e1, ie0, estep, _) = find_e0_derivative(
+        energy.clone(),
+        mu.clone(),
+        None,
+        None,
+        options,
+        dup_tolerance,
+    )?;
     let istart = (ie0 as i32 - 75).max(2) as usize;
     let istop = (ie0 + 75).min(energy.len() - 2);
 
-    let (mut e0, ix, ex) = _find_e0(
+    let smooth_width = options.smooth_width.unwrap_or(3.0);
+    let (mut e0, ix, ex, (en, dmu)) = find_e0_derivative(
         energy.slice(ndarray::s![istart..istop]).to_owned(),
         mu.slice(ndarray::s![istart..istop]).to_owned(),
         Some(estep),
-        Some(true),
+        Some(smooth_width),
+        options,
+        dup_tolerance,
     )?;
 
     if ix < 1 {
         e0 = energy[istart + 2];
     }
 
-    Ok(e0)
+    // Points above half of the derivative peak
+    let half = 0.5 * dmu[ix];
+    let lower = (0..=ix)
+        .rev()
+        .take_while(|&i| dmu[i] > half)
+        .last()
+        .unwrap_or(ix);
+    let upper = (ix..dmu.len())
+        .take_while(|&i| dmu[i] > half)
+        .last()
+        .unwrap_or(ix);
+    let fwhm = (en[upper] - en[lower]).max(estep);
+    let npts = (upper - lower + 1) as f64;
+
+    // The spline goes a few points beyond the derivative peak on each side
+    if let Some(factor) = options.upsample.filter(|&f| f > 1 && ix >= 1) {
+        let lo = lower.min(ix.saturating_sub(4)).saturating_sub(3);
+        let hi = (upper.max(ix + 4) + 3).min(en.len() - 1);
+        let mu = mu.slice(ndarray::s![istart..istop]);
+        if let Some(peak) = upsampled_derivative_peak(
+            en.slice(ndarray::s![lo..=hi]).as_slice().unwrap(),
+            &mu.slice(ndarray::s![lo..=hi]).to_vec(),
+            factor,
+        ) {
+            e0 = peak;
+        }
+    }
+
+    Ok(E0Estimate {
+        e0,
+        uncertainty: fwhm / (2.0 * (2.0 * 2.0_f64.ln()).sqrt()) / npts.sqrt(),
+    })
+}
+
+// Maximum of the derivative of the cubic spline through the points, evaluated on a grid
+// `factor` times finer and refined by the vertex of the parabola through the maximum and its
+// neighbours. None if there are too few points or the maximum is at the ends.
+fn upsampled_derivative_peak(energy: &[f64], mu: &[f64], factor: usize) -> Option<f64> {
+    if energy.len() < 5 || energy.windows(2).any(|e| e[1] <= e[0]) {
+        return None;
+    }
+
+    let (t, c, k) = rusty_fitpack::splrep(
+        energy.to_vec(),
+        mu.to_vec(),
+        None,
+        None,
+        None,
+        Some(3),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let n = (energy.len() - 1) * factor + 1;
+    let fine = Array1::linspace(energy[0], energy[energy.len() - 1], n);
+    let mu_fine = Array1::from_vec(rusty_fitpack::splev(t, c, k, fine.to_vec(), 3));
+    let dmu = mu_fine.gradient() / fine.gradient();
+
+    let imax = (0..n).max_by(|&a, &b| dmu[a].total_cmp(&dmu[b]))?;
+    if imax == 0 || imax == n - 1 {
+        return None;
+    }
+
+    let (y0, y1, y2) = (dmu[imax - 1], dmu[imax], dmu[imax + 1]);
+    let curvature = y0 - 2.0 * y1 + y2;
+    let shift = if curvature < 0.0 {
+        0.5 * (y0 - y2) / curvature
+    } else {
+        0.0
+    };
+
+    Some(fine[imax] + shift * (fine[1] - fine[0]))
 }
 
 /// Internal function used for find_e0.
@@ -398,27 +940,70 @@ pub fn _find_e0<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>> + Clone>(
     estep: Option<f64>,
     use_smooth: Option<bool>,
 ) -> Result<(f64, usize, f64), Box<dyn Error>> {
-    let en: ArrayBase<OwnedRepr<f64>, Ix1> = remove_dups(energy.clone().into(), None, None, None);
+    _find_e0_filtered(energy, mu, estep, use_smooth, None)
+}
+
+/// _find_e0 with an optional median filter of `median_window` points on the derivative.
+pub fn _find_e0_filtered<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>> + Clone>(
+    energy: T,
+    mu: T,
+    estep: Option<f64>,
+    use_smooth: Option<bool>,
+    median_window: Option<usize>,
+) -> Result<(f64, usize, f64), Box<dyn Error>> {
+    let mut options = FindE0Options::new();
+    options.set_median_window(median_window);
+    let smooth_width = match use_smooth {
+        Some(true) => Some(3.0),
+        _ => None,
+    };
+
+    let (e0, imax, estep, _) =
+        find_e0_derivative(energy, mu, estep, smooth_width, &options, (None, None))?;
+    Ok((e0, imax, estep))
+}
+
+// One pass of find_e0 on the derivative estimated with the options, smoothed with a
+// Lorentzian of `smooth_width` energy steps. Duplicated energies are shifted apart with the
+// (tiny, frac) `dup_tolerance`. Also returns the energy and the scaled derivative.
+#[allow(clippy::type_complexity)]
+fn find_e0_derivative<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>> + Clone>(
+    energy: T,
+    mu: T,
+    estep: Option<f64>,
+    smooth_width: Option<f64>,
+    options: &FindE0Options,
+    dup_tolerance: (Option<f64>, Option<f64>),
+) -> Result<(f64, usize, f64, (Array1<f64>, Array1<f64>)), Box<dyn Error>> {
+    let median_window = options.median_window;
+    let (tiny, frac) = dup_tolerance;
+    let en: ArrayBase<OwnedRepr<f64>, Ix1> = remove_dups(energy.clone().into(), tiny, frac, None);
     let mu: ArrayBase<OwnedRepr<f64>, Ix1> = mu.into();
 
     let estep = estep.unwrap_or(find_energy_step(energy.clone(), None, None, Some(false)) / 2.0);
 
     let nmin = 2.max(en.len() / 100);
 
-    let dmu: ArrayBase<OwnedRepr<f64>, Ix1> = if let Some(true) = use_smooth {
-        // todo!("smooth not implemented yet");
-        smooth(
+    let dmu = derivative(&en, &mu, options.derivative.unwrap_or_default())?;
+    let dmu: ArrayBase<OwnedRepr<f64>, Ix1> = match smooth_width {
+        Some(width) if width > 0.0 => smooth(
             energy.into(),
-            mu.gradient() / en.gradient(),
-            Some(3.0 * estep),
+            dmu,
+            Some(width * estep),
             None,
             Some(estep),
             None,
             ConvolveForm::Lorentzian,
         )
-        .unwrap()
-    } else {
-        mu.gradient() / en.gradient()
+        .unwrap(),
+        _ => dmu,
+    };
+
+    let dmu = match median_window {
+        Some(window) if window > 1 => {
+            Array1::from_vec(super::mathutils::median_filter(&dmu.to_vec(), window))
+        }
+        _ => dmu,
     };
 
     let dmin = dmu
@@ -484,10 +1069,10 @@ pub fn _find_e0<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>> + Clone>(
         }
     }
 
-    Ok((en[imax], imax, estep))
+    Ok((en[imax], imax, estep, (en, dmu)))
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FTWindow {
     #[default]
     Hanning, // Hanning window, cosine-squared tamper
@@ -500,6 +1085,28 @@ pub enum FTWindow {
 }
 
 impl FTWindow {
+    pub const ALL: [FTWindow; 7] = [
+        FTWindow::Hanning,
+        FTWindow::Parzen,
+        FTWindow::Welch,
+        FTWindow::Gaussian,
+        FTWindow::Sine,
+        FTWindow::KaiserBessel,
+        FTWindow::FHanning,
+    ];
+
+    /// Name of the window in Larch and Athena
+    pub fn name(&self) -> &'static str {
+        match self {
+            FTWindow::Parzen => "parzen",
+            FTWindow::Welch => "welch",
+            FTWindow::Gaussian => "gaussian",
+            FTWindow::Sine => "sine",
+            FTWindow::KaiserBessel => "kaiser-bessel",
+            FTWindow::Hanning | FTWindow::FHanning => "hanning",
+        }
+    }
+
     pub fn window(
         &self,
         x: &ArrayBase<OwnedRepr<f64>, Ix1>,
@@ -783,118 +1390,325 @@ pub fn ftwindow(
 //                 __name__=newname)
 // return
 
+/// Absolute energy of `relative_energy` given relative to `e0`.
+///
+/// Energy ranges such as the pre-edge and normalization ranges are given relative to e0.
+pub fn absolute_energy(e0: f64, relative_energy: f64) -> f64 {
+    e0 + relative_energy
+}
+
+/// Energy relative to `e0`.
+pub fn relative_energy(e0: f64, energy: f64) -> f64 {
+    energy - e0
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub enum RebinMethod {
+    /// Mean of the points in each bin
     Boxcar,
+    /// Energy-weighted mean of the points in each bin
     #[default]
     Centroid,
+    /// Integral of the linearly interpolated mu(E) over each bin divided by the bin width,
+    /// which preserves the integrated intensity and the area of sharp features.
+    FluxConserving,
 }
 
+/// Resample mu(E) onto `grid`, each bin extending halfway to the neighbouring grid points.
+///
+/// Returns the resampled mu and its uncertainty, the standard error std/sqrt(n) of the n points
+/// in each bin (zero for bins with less than 2 points).
+/// Boxcar and Centroid interpolate linearly in bins with fewer than 3 points. `energy` and
+/// `grid` have to be sorted in increasing order.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::{resample, RebinMethod};
+/// use ndarray::Array1;
+///
+/// let energy = Array1::linspace(0.0, 10.0, 1001);
+/// let mu = energy.mapv(|e: f64| (-(e - 5.0).powi(2) / 0.02).exp());
+/// let grid = Array1::linspace(0.5, 9.5, 10);
+///
+/// let (rebinned, _) = resample(&energy, &mu, &grid, RebinMethod::FluxConserving).unwrap();
+///
+/// // The area of the narrow peak is kept.
+/// assert!((rebinned.sum() - (0.02 * std::f64::consts::PI).sqrt()).abs() < 1e-4);
+/// ```
+pub fn resample(
+    energy: &Array1<f64>,
+    mu: &Array1<f64>,
+    grid: &Array1<f64>,
+    method: RebinMethod,
+) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+    if energy.len() != mu.len() || energy.len() < 2 || grid.len() < 2 {
+        return Err(Box::new(super::XAFSError::NotEnoughData));
+    }
+
+    if !energy.is_sorted() || !grid.is_sorted() {
+        return Err("energy and grid have to be sorted".into());
+    }
+
+    let n = grid.len();
+    let mut edges = Vec::with_capacity(n + 1);
+    edges.push(grid[0] - 0.5 * (grid[1] - grid[0]));
+    edges.extend(grid.windows(2).into_iter().map(|w| 0.5 * (w[0] + w[1])));
+    edges.push(grid[n - 1] + 0.5 * (grid[n - 1] - grid[n - 2]));
+
+    let energy = energy.as_slice().ok_or("energy is not contiguous")?;
+    let mu = mu.as_slice().ok_or("mu is not contiguous")?;
+    let (emin, emax) = (energy[0], energy[energy.len() - 1]);
+
+    let mut mu_out = Array1::zeros(n);
+    let mut err_out = Array1::zeros(n);
+
+    for i in 0..n {
+        let j0 = energy.partition_point(|e| *e < edges[i]);
+        let j1 = energy.partition_point(|e| *e < edges[i + 1]);
+        let (e_bin, mu_bin) = (&energy[j0..j1], &mu[j0..j1]);
+
+        mu_out[i] = match method {
+            RebinMethod::FluxConserving => {
+                let (lo, hi) = (edges[i].clamp(emin, emax), edges[i + 1].clamp(emin, emax));
+                if hi > lo {
+                    integrate_linear(energy, mu, lo, hi) / (hi - lo)
+                } else {
+                    interpolate_linear(energy, mu, grid[i])
+                }
+            }
+            _ if e_bin.len() < 3 => interpolate_linear(energy, mu, grid[i]),
+            RebinMethod::Boxcar => mu_bin.iter().sum::<f64>() / mu_bin.len() as f64,
+            RebinMethod::Centroid => {
+                mu_bin.iter().zip(e_bin).map(|(m, e)| m * e).sum::<f64>()
+                    / e_bin.iter().sum::<f64>()
+            }
+        };
+
+        // Standard error of the mean of the bin, the uncertainty of mu_out for
+        // XASSpectrum::delta_mu. The spread of the points is sqrt(n) times larger.
+        if mu_bin.len() > 1 {
+            err_out[i] = Array1::from_vec(mu_bin.to_vec()).std(1.0) / (mu_bin.len() as f64).sqrt();
+        }
+    }
+
+    Ok((mu_out, err_out))
+}
+
+/// Linear interpolation of sorted (x, y) at `x0`, extrapolating from the end segments.
+fn interpolate_linear(x: &[f64], y: &[f64], x0: f64) -> f64 {
+    let i = x.partition_point(|x| *x <= x0).clamp(1, x.len() - 1);
+
+    if x[i] == x[i - 1] {
+        return y[i];
+    }
+
+    y[i - 1] + (y[i] - y[i - 1]) * (x0 - x[i - 1]) / (x[i] - x[i - 1])
+}
+
+/// Integral of the linear interpolation of sorted (x, y) from `lo` to `hi`.
+fn integrate_linear(x: &[f64], y: &[f64], lo: f64, hi: f64) -> f64 {
+    let j0 = x.partition_point(|x| *x <= lo);
+    let j1 = x.partition_point(|x| *x < hi);
+
+    let points = std::iter::once((lo, interpolate_linear(x, y, lo)))
+        .chain((j0..j1).map(|j| (x[j], y[j])))
+        .chain(std::iter::once((hi, interpolate_linear(x, y, hi))))
+        .collect::<Vec<(f64, f64)>>();
+
+    points
+        .windows(2)
+        .map(|w| 0.5 * (w[1].0 - w[0].0) * (w[0].1 + w[1].1))
+        .sum()
+}
+
+/// Parameters of rebin, the "standard 3 region XAFS scan" of xraylarch's rebin_xafs
+///
+/// Energies are relative to e0. The pre-edge from pre1 to pre2 is spaced by pre_step, the
+/// XANES from pre2 to exafs1 by xanes_step and the EXAFS from exafs1 to exafs2 by exafs_kstep
+/// in k.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RebinOptions {
+    /// Start of the pre-edge region (default: first energy point, rounded to pre_step)
+    pub pre1: Option<f64>,
+    /// End of the pre-edge region, start of the XANES region (default -30)
+    pub pre2: Option<f64>,
+    /// Energy step of the pre-edge region (default 2)
+    pub pre_step: Option<f64>,
+    /// Energy step of the XANES region (default e0 / 25000 rounded down to 0.05)
+    pub xanes_step: Option<f64>,
+    /// End of the XANES region, start of the EXAFS region (default 15)
+    pub exafs1: Option<f64>,
+    /// End of the EXAFS region (default: last energy point)
+    pub exafs2: Option<f64>,
+    /// k step of the EXAFS region (default 0.05)
+    pub exafs_kstep: Option<f64>,
+    pub method: RebinMethod,
+}
+
+impl Default for RebinOptions {
+    fn default() -> Self {
+        RebinOptions {
+            pre1: None,
+            pre2: Some(-30.0),
+            pre_step: Some(2.0),
+            xanes_step: None,
+            exafs1: Some(15.0),
+            exafs2: None,
+            exafs_kstep: Some(0.05),
+            method: RebinMethod::Centroid,
+        }
+    }
+}
+
+impl RebinOptions {
+    pub fn new() -> RebinOptions {
+        RebinOptions::default()
+    }
+
+    pub fn set_pre_edge(&mut self, pre1: Option<f64>, pre2: Option<f64>) -> &mut Self {
+        self.pre1 = pre1;
+        self.pre2 = pre2;
+        self
+    }
+
+    pub fn set_steps(
+        &mut self,
+        pre_step: Option<f64>,
+        xanes_step: Option<f64>,
+        exafs_kstep: Option<f64>,
+    ) -> &mut Self {
+        self.pre_step = pre_step;
+        self.xanes_step = xanes_step;
+        self.exafs_kstep = exafs_kstep;
+        self
+    }
+
+    pub fn set_exafs(&mut self, exafs1: Option<f64>, exafs2: Option<f64>) -> &mut Self {
+        self.exafs1 = exafs1;
+        self.exafs2 = exafs2;
+        self
+    }
+
+    pub fn set_method(&mut self, method: RebinMethod) -> &mut Self {
+        self.method = method;
+        self
+    }
+
+    /// The 3 region energy grid for `energy` with the edge at `e0`.
+    ///
+    /// The end point of each region is left out as in xraylarch, and regions that are empty
+    /// for the energy range of the data are skipped.
+    pub fn grid(&self, energy: &Array1<f64>, e0: f64) -> Result<Array1<f64>, Box<dyn Error>> {
+        if energy.len() < 2 {
+            return Err(Box::new(super::XAFSError::NotEnoughData));
+        }
+
+        let default = RebinOptions::default();
+        let pre2 = self.pre2.or(default.pre2).unwrap();
+        let pre_step = self.pre_step.or(default.pre_step).unwrap();
+        let exafs1 = self.exafs1.or(default.exafs1).unwrap();
+        let exafs_kstep = self.exafs_kstep.or(default.exafs_kstep).unwrap();
+        let pre1 = self
+            .pre1
+            .unwrap_or(pre_step * (relative_energy(e0, energy.min()) / pre_step).trunc());
+        let exafs2 = self.exafs2.unwrap_or(relative_energy(e0, energy.max()));
+        let xanes_step = self
+            .xanes_step
+            .unwrap_or(0.05 * (e0 / 1250.0).floor().max(1.0));
+
+        if pre_step <= 0.0 || xanes_step <= 0.0 || exafs_kstep <= 0.0 {
+            return Err("rebin steps have to be positive".into());
+        }
+
+        let mut grid = Vec::new();
+        for (start, stop, step, is_k) in [
+            (pre1, pre2, pre_step, false),
+            (pre2, exafs1, xanes_step, false),
+            (exafs1, exafs2, exafs_kstep, true),
+        ] {
+            if stop <= start {
+                continue;
+            }
+            let (start, stop) = if is_k {
+                (start.etok(), stop.etok())
+            } else {
+                (start, stop)
+            };
+
+            let npts = 1 + (0.1 + (stop - start) / step) as usize;
+            let region = Array1::linspace(start, stop, npts);
+            let region = if is_k { region.ktoe() } else { region };
+            grid.extend(
+                region
+                    .iter()
+                    .take(npts - 1)
+                    .map(|e| absolute_energy(e0, *e)),
+            );
+        }
+
+        if grid.len() < 2 {
+            return Err(Box::new(super::XAFSError::NotEnoughData));
+        }
+
+        Ok(Array1::from_vec(grid))
+    }
+}
+
+/// Rebin mu(E) to the 3 region grid of `options` (see RebinOptions::grid) with the edge at
+/// `e0`, each input point contributing to one bin (see resample).
+///
+/// Returns the new energy, mu and its standard error in each bin.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::{rebin, RebinOptions};
+/// use ndarray::Array1;
+///
+/// let energy = Array1::range(7000.0, 8000.0, 0.2);
+/// let mu = energy.mapv(|e: f64| (0.5 * (e - 7112.0)).atan());
+///
+/// let (energy_out, mu_out, _) = rebin(&energy, &mu, 7112.0, &RebinOptions::new()).unwrap();
+/// assert!(energy_out.len() < energy.len() / 2);
+/// assert_eq!(energy_out[1] - energy_out[0], 2.0);
+/// assert_eq!(mu_out.len(), energy_out.len());
+/// ```
 pub fn rebin(
-    energy: ArrayBase<OwnedRepr<f64>, Ix1>,
-    mu: ArrayBase<OwnedRepr<f64>, Ix1>,
+    energy: &Array1<f64>,
+    mu: &Array1<f64>,
     e0: f64,
-    pre1: Option<f64>,
-    pre2: Option<f64>,
-    pre_step: Option<f64>,
-    xanes_step: Option<f64>,
-    exafs1: Option<f64>,
-    exafs2: Option<f64>,
-    exafs_kstep: Option<f64>,
-    method: RebinMethod,
+    options: &RebinOptions,
 ) -> Result<(Array1<f64>, Array1<f64>, Array1<f64>), Box<dyn Error>> {
-    let pre2: f64 = pre2.unwrap_or(-30.0);
-    let pre_step = pre_step.unwrap_or(2.0);
-    let exafs1 = exafs1.unwrap_or(15.0);
-    let exafs_kstep = exafs_kstep.unwrap_or(0.05);
-
-    let pre1 = pre1.unwrap_or(pre_step * ((energy.min() - e0) / pre_step).floor());
-    let exafs2 = exafs2.unwrap_or(energy.max() - e0);
-
-    // let xanes_step = if xanes_step.is_none() {
-    //     let xanes_x1 = index_of(&energy.to_vec(), &(e0 - 10.0));
-    //     let xanes_x2 = index_of(&energy.to_vec(), &(e0 + 10.0));
-
-    //     let de_mean = (&energy.slice(ndarray::s![xanes_x1..xanes_x2]).to_owned() - e0).mean();
-
-    //     0.05 * f64::max(1.0, (e0 / 1250.0).floor())
-    // } else {
-    //     xanes_step.unwrap()
-    // };
-
-    // let mut en = Array1::zeros(0);
-
-    // for (start, stop, step, is_kspace) in [
-    //     (pre1, pre2, pre_step, false),
-    //     (pre2, exafs1, xanes_step, false),
-    //     (exafs1, exafs2, exafs_kstep, true),
-    // ] {
-    //     let (start, stop) = if is_kspace {
-    //         (etok(start), etok(stop))
-    //     } else {
-    //         (start, stop)
-    //     };
-
-    //     let npts = 1 + ((stop - start) / step + 0.1).abs().floor() as usize;
-    //     let reg = Array1::linspace(start, stop, npts);
-    //     let reg = if is_kspace { ktoe(reg) } else { reg };
-
-    //     en.extend(e0 + &reg.slice(ndarray::s![..-1]));
-    // }
-
-    // let bounds = en
-    //     .iter()
-    //     .map(|e| index_of(&energy.to_vec(), e))
-    //     .collect::<Vec<usize>>();
-
-    // let mut mu_out = Array1::zeros(0);
-    // let mut err_out = Array1::zeros(0);
-
-    // let mut j0 = 0;
-
-    todo!("finish rebin function")
-
-    // for i in 0..en.len() {
-    //     let j1 = if i == en.len() - 1 {
-    //         energy.len() - 1
-    //     } else {
-    //         ((bounds[i] + bounds[i + 1] + 1) / 2).floor() as usize
-    //     };
-
-    //     if i == 0 && j0 == 0 {
-    //         j0 = index_of(&energy.to_vec(), &(en[0] - 5.0));
-    //     }
-
-    //     if (j1 - j0) < 3 {
-    //         let jx = j1 + 1;
-    //         let jx = if (jx - j0) < 3 {
-    //             jx + 1
-    //         } else {
-    //             jx
-    //         };
-
-    //         let val = interp1d(
-    //             &energy.slice(ndarray:: s![j0..jx]).to_owned(),
-    //             &mu.slice(ndarray::s![j0..jx]).to_owned(),
-    //             en[i],
-    //         )?;
-
-    //         let err = mu.slice(ndarray::s![j0..jx]).to_owned().std_axis(Axis(0));
-
-    //         if val.is_nan() {
-    //             j0 = f64::max(0.0, j0 as f64 - 1.0) as usize;
-    //             let jx = f64::min(energy.len() as f64, jx as f64 + 1.0) as usize;
-    //             let val = interp1d(
-    //                 &energy.slice(ndarray:: s![j0..jx]).to_owned(),
-    //                 &mu.slice(ndarray::s![j0..jx]).to_owned(),
-    //                 en[i],
-    //             )?;
-    //             let err = mu.slice(ndarray::s![j0..jx]).to_owned().std_axis(Axis(0));
-    //         }
-
-    //         mu_out.push(val);
-    //         err_out.push(err);
-    //     } else {
+    let grid = options.grid(energy, e0)?;
+    let (mu_out, err_out) = resample(energy, mu, &grid, options.method)?;
+
+    Ok((grid, mu_out, err_out))
+}
+
+/// Number of independent points in the fitting range, 2ΔkΔR/π + 1
+///
+/// E. A. Stern. Number of relevant independent points in x-ray-absorption fine-structure spectra. Phys. Rev. B, 48:9825–9827, Oct 1993. doi:10.1103/PhysRevB.48.9825.
+///
+/// # Example
+/// ```
+/// use xraytsubaki::xafs::xafsutils::independent_points;
+///
+/// let nidp = independent_points(3.0, 13.0, 1.0, 3.0);
+/// assert!((nidp - (40.0 / std::f64::consts::PI + 1.0)).abs() < 1e-12);
+/// ```
+pub fn independent_points(kmin: f64, kmax: f64, rmin: f64, rmax: f64) -> f64 {
+    2.0 * (kmax - kmin).abs() * (rmax - rmin).abs() / std::f64::consts::PI + 1.0
+}
+
+/// Guard against over-fitting: returns the number of degrees of freedom (nidp - nvarys),
+/// or XAFSError::TooManyVariables if the fit has more variables than independent points.
+pub fn check_independent_points(nvarys: usize, nidp: f64) -> Result<f64, Box<dyn Error>> {
+    let nfree = nidp - nvarys as f64;
+
+    if nfree < 0.0 {
+        return Err(Box::new(super::XAFSError::TooManyVariables));
+    }
+
+    Ok(nfree)
 }
 
 #[cfg(test)]
@@ -971,6 +1785,51 @@ mod tests {
         assert_ne!(arr, Array1::from_vec(vec![0., 1.1, 2.2, 2.2000001, 3.3]));
     }
 
+    #[test]
+    fn test_resample() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 2001);
+        let mu = energy.mapv(|e| 1.0 + (-(e - 50.0).powi(2) / 0.5).exp());
+        let grid = Array1::range(2.5, 100.0, 5.0);
+
+        // A bin of 5 eV is 10 times wider than the peak, so that boxcar averaging and decimation
+        // give different peak heights, while the integrated intensity is kept.
+        let (flux, err) = resample(&energy, &mu, &grid, RebinMethod::FluxConserving)?;
+        let (boxcar, _) = resample(&energy, &mu, &grid, RebinMethod::Boxcar)?;
+        let (centroid, _) = resample(&energy, &mu, &grid, RebinMethod::Centroid)?;
+
+        let area = integrate_linear(
+            energy.as_slice().unwrap(),
+            mu.as_slice().unwrap(),
+            0.0,
+            100.0,
+        );
+        assert_abs_diff_eq!(flux.sum() * 5.0, area, epsilon = 1e-9);
+        assert_abs_diff_eq!(flux[0], 1.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(boxcar[0], 1.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(centroid[0], 1.0, epsilon = 1e-9);
+        assert!(err[10] > 0.0);
+        assert_eq!(err[0], 0.0);
+
+        let (_, err) = resample(
+            &Array1::from_vec(vec![0.0, 1.0, 2.0, 3.0]),
+            &Array1::from_vec(vec![0.0, 2.0, 0.0, 2.0]),
+            &Array1::from_vec(vec![1.5, 5.5]),
+            RebinMethod::Boxcar,
+        )?;
+        assert_abs_diff_eq!(err[0], (1.0_f64 / 3.0).sqrt(), epsilon = 1e-12);
+        assert_eq!(err[1], 0.0);
+
+        assert!(resample(
+            &grid,
+            &grid,
+            &energy.slice(ndarray::s![..;-1]).to_owned(),
+            RebinMethod::Boxcar
+        )
+        .is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_find_energy_step() {
         let energy = Array1::from_vec(vec![0.0, 1.0, 2.0, 3.0, 4.0]);
@@ -992,6 +1851,42 @@ mod tests {
         assert_eq!(step, 0.75);
     }
 
+    #[test]
+    fn test_find_energy_step_report() {
+        // 1 eV steps with a repeated point, a backlash and a 6 eV dead band
+        let mut energy = (0..40).map(|i| i as f64).collect::<Vec<f64>>();
+        energy.insert(10, 10.0);
+        energy.insert(21, 18.0);
+        energy.drain(30..35);
+
+        let report = find_energy_step_report(energy.clone(), None, None, None, None);
+        assert_eq!(report.steps.len(), energy.len() - 1);
+        assert_eq!(
+            report.step,
+            find_energy_step(energy, None, None, Some(true))
+        );
+        assert_eq!(report.anomalies.len(), 3);
+        assert_eq!(
+            report.anomalies_of(EnergyStepAnomalyKind::Zero)[0].energy,
+            10.0
+        );
+        assert_eq!(
+            report.anomalies_of(EnergyStepAnomalyKind::Negative)[0].step,
+            -1.0
+        );
+        let jump = report.anomalies_of(EnergyStepAnomalyKind::LargeJump)[0];
+        assert_eq!((jump.energy, jump.step), (27.0, 6.0));
+        assert_eq!(report.warnings().len(), 3);
+
+        let (edges, counts) = report.histogram(4);
+        assert_eq!(edges.len(), 5);
+        assert_eq!(counts.iter().sum::<usize>(), report.steps.len());
+        assert_eq!(counts[3], 1);
+
+        let report = find_energy_step_report(vec![1.0], None, None, None, None);
+        assert!(report.step.is_nan() && report.is_regular());
+    }
+
     #[test]
     fn test_find_e0() {
         let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 1000);
@@ -1001,6 +1896,113 @@ mod tests {
         assert_abs_diff_eq!(result.unwrap(), 0.4004004004004004, epsilon = TEST_TOL);
     }
 
+    #[test]
+    fn test_find_e0_with_options() -> Result<(), Box<dyn Error>> {
+        let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 1001);
+        let mut mu = energy.mapv(|x| (0.5 * (x - 60.0)).atan());
+        mu[100] += 10.0;
+
+        // The spike is taken as the edge without the pre-filter.
+        assert!((find_e0(energy.clone(), mu.clone())? - 10.0).abs() < 0.5);
+
+        let mut options = FindE0Options::new();
+        assert!(!options.is_active());
+        options.set_median_window(Some(5));
+        let e0 = find_e0_with_options(energy.clone(), mu.clone(), &options)?;
+        assert!((e0 - 60.0).abs() < 0.5);
+
+        let mut options = FindE0Options::new();
+        options.set_exclude(Some(120), None);
+        let e0 = find_e0_with_options(energy.clone(), mu.clone(), &options)?;
+        assert!((e0 - 60.0).abs() < 0.5);
+
+        options.set_exclude(Some(600), Some(600));
+        assert!(find_e0_with_options(energy, mu, &options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_e0_upsample() -> Result<(), Box<dyn Error>> {
+        // Quick scan with 2 eV steps, the edge between two points
+        let energy: Array1<f64> = Array1::range(0.0, 200.0, 2.0);
+        let mu = energy.mapv(|x| (0.3 * (x - 100.7)).atan());
+
+        let coarse = find_e0(energy.clone(), mu.clone())?;
+        assert_eq!(coarse % 2.0, 0.0);
+
+        let mut options = FindE0Options::new();
+        options.set_upsample(Some(10));
+        assert!(options.is_active());
+        let fine = find_e0_with_options(energy.clone(), mu.clone(), &options)?;
+        assert!((fine - 100.7).abs() < 0.1, "{}", fine);
+        assert!((fine - 100.7).abs() < (coarse - 100.7).abs());
+
+        options.set_upsample(Some(1));
+        assert_eq!(find_e0_with_options(energy, mu, &options)?, coarse);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_e0_derivative() -> Result<(), Box<dyn Error>> {
+        // Uneven grid: Savitzky-Golay is exact for polynomials up to its order
+        let x = Array1::linspace(0.0, 1.0, 30).mapv(|t: f64| 2.0 * t * t + t);
+        let y = x.mapv(|x| x.powi(3) - 2.0 * x);
+        let method = DerivativeMethod::SavitzkyGolay {
+            window: 5,
+            order: 3,
+        };
+        let dy = derivative(&x, &y, method)?;
+        dy.iter()
+            .zip(x.iter())
+            .for_each(|(d, x)| assert_abs_diff_eq!(*d, 3.0 * x * x - 2.0, epsilon = 1e-8));
+
+        let forward = derivative(&x, &y, DerivativeMethod::Forward)?;
+        assert_abs_diff_eq!(
+            forward[0],
+            (y[1] - y[0]) / (x[1] - x[0]),
+            epsilon = TEST_TOL
+        );
+        assert_eq!(forward[29], forward[28]);
+        assert!(derivative(
+            &x,
+            &y,
+            DerivativeMethod::SavitzkyGolay {
+                window: 3,
+                order: 3
+            }
+        )
+        .is_err());
+
+        // A noisy edge: the smoothed estimators stay close to e0
+        let energy: Array1<f64> = Array1::linspace(0.0, 100.0, 1001);
+        let noise = energy.mapv(|x| 0.02 * (37.0 * x).sin());
+        let mu = energy.mapv(|x| (0.5 * (x - 60.0)).atan()) + &noise;
+        let mut options = FindE0Options::new();
+        options.set_derivative(Some(DerivativeMethod::SavitzkyGolay {
+            window: 11,
+            order: 2,
+        }));
+        assert!(options.is_active());
+        let sharp = find_e0_estimate(energy.clone(), mu, &options)?;
+        assert!((sharp.e0 - 60.0).abs() < 0.2);
+
+        // A broader edge gives a larger uncertainty
+        let mu = energy.mapv(|x| (0.1 * (x - 60.0)).atan()) + &noise;
+        let broad = find_e0_estimate(energy.clone(), mu.clone(), &options)?;
+        assert!((broad.e0 - 60.0).abs() < 1.0);
+        assert!(broad.uncertainty > 1.5 * sharp.uncertainty);
+
+        let mut spectrum = crate::xafs::xasspectrum::XASSpectrum::new();
+        spectrum.set_spectrum(energy, mu).find_e0()?;
+        assert!(spectrum.get_e0_uncertainty().unwrap() > 0.0);
+        spectrum.set_e0(60.0);
+        assert_eq!(spectrum.get_e0_uncertainty(), None);
+
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     #[test]
     fn test_KTOE() {
@@ -1009,6 +2011,19 @@ mod tests {
         assert_abs_diff_eq!(constants::KTOE, expected_KTOE, epsilon = TEST_TOL);
     }
 
+    #[test]
+    fn test_etok_ktoe() {
+        assert_abs_diff_eq!(100.0.etok(), 5.123167, epsilon = 1e-6);
+        assert_abs_diff_eq!(5.123167.ktoe(), 100.0, epsilon = 1e-4);
+        assert_eq!((-1.0).etok(), 0.0);
+
+        let energy = Array1::from_vec(vec![-1.0, 0.0, 50.0, 400.0]);
+        let k = energy.etok();
+        assert_eq!(k[0], 0.0);
+        assert_abs_diff_eq!(k.ktoe()[3], 400.0, epsilon = TEST_TOL);
+        assert_eq!(vec![50.0, 400.0].etok(), vec![k[2], k[3]]);
+    }
+
     #[test]
     fn test_ftwindow_hanning() {
         let expected_filepath = String::from(TOP_DIR) + "/tests/testfiles/window_Hanning.txt";
@@ -1136,4 +2151,22 @@ mod tests {
             .zip(y_expected.iter())
             .for_each(|(a, b)| assert_abs_diff_eq!(a, &b, epsilon = TEST_TOL_FTWINDOW));
     }
+
+    #[test]
+    fn test_independent_points() {
+        let nidp = independent_points(2.0, 12.0, 1.0, 3.0);
+        assert_abs_diff_eq!(nidp, 40.0 / std::f64::consts::PI + 1.0, epsilon = TEST_TOL);
+        assert_abs_diff_eq!(
+            independent_points(12.0, 2.0, 3.0, 1.0),
+            nidp,
+            epsilon = TEST_TOL
+        );
+
+        assert_abs_diff_eq!(
+            check_independent_points(10, nidp).unwrap(),
+            nidp - 10.0,
+            epsilon = TEST_TOL
+        );
+        assert!(check_independent_points(14, nidp).is_err());
+    }
 }
@@ -3,6 +3,7 @@
 
 #[cfg_attr(debug_assertions, allow(dead_code, unused_imports))]
 // Standard library dependencies
+use std::cmp::Ordering;
 use std::error::Error;
 use std::mem;
 
@@ -11,20 +12,57 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 // load dependencies
+use super::groupview::GroupStep;
 use super::xasspectrum;
 use super::XAFSError;
 
 use itertools::Itertools;
 
 // Load local traits
+use crate::xafs::background::{BackgroundMethod, AUTOBK};
 use crate::xafs::io::xasdatatype::XASGroupFile;
 use crate::xafs::io::{xafs_bson::XASBson, xafs_json::XASJson};
+use crate::xafs::merge::MergeWeighting;
 use crate::xafs::xasspectrum::XASSpectrum;
 
+/// Spectrum found to be a duplicate by XASGroup::find_duplicates
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateSpectrum {
+    /// Index of the duplicate in the group before it is removed
+    pub index: usize,
+    pub name: Option<String>,
+    /// Index of the first spectrum with the same raw data, which is kept
+    pub duplicate_of: usize,
+}
+
+/// Spectra of a group sharing the same values of some metadata keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataGroup {
+    /// Value of each key, None if the key is missing
+    pub values: Vec<Option<serde_json::Value>>,
+    /// Indices of the spectra in the original group
+    pub indices: Vec<usize>,
+    /// Copy of the spectra with their flags and weights
+    pub group: XASGroup,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 pub struct XASGroup {
     pub spectra: Vec<XASSpectrum>,
+    /// Selection state of each spectrum, kept in the same order as `spectra`
+    pub selected: Vec<bool>,
+    /// Visibility of each spectrum in plots, kept in the same order as `spectra`
+    pub visible: Vec<bool>,
+    /// Whether each spectrum takes part in merging, statistics and plots, kept in the same
+    /// order as `spectra`. Bad scans can be excluded without removing them from the group.
+    pub included: Vec<bool>,
+    /// Weight of each spectrum in merging, kept in the same order as `spectra`
+    pub weights: Vec<f64>,
+    /// Labels of the whole group
+    pub tags: Vec<String>,
+    /// Free-form notes on the group, e.g. on decisions made during the analysis
+    pub notes: Vec<String>,
 }
 
 impl Default for XASGroup {
@@ -37,6 +75,12 @@ impl XASGroup {
     pub fn new() -> Self {
         Self {
             spectra: Vec::new(),
+            selected: Vec::new(),
+            visible: Vec::new(),
+            included: Vec::new(),
+            weights: Vec::new(),
+            tags: Vec::new(),
+            notes: Vec::new(),
         }
     }
 
@@ -50,15 +94,28 @@ impl XASGroup {
 
     pub fn add_spectrum(&mut self, spectrum: XASSpectrum) -> &mut Self {
         self.spectra.push(spectrum);
+        self.sync_flags();
         self
     }
 
     pub fn add_spectra(&mut self, spectra: Vec<XASSpectrum>) -> &mut Self {
         self.spectra.extend(spectra);
+        self.sync_flags();
         self
     }
 
     pub fn add_group(&mut self, group: XASGroup) -> &mut Self {
+        self.sync_flags();
+
+        let n = group.len();
+        self.selected
+            .extend((0..n).map(|i| group.selected.get(i).copied().unwrap_or(false)));
+        self.visible
+            .extend((0..n).map(|i| group.visible.get(i).copied().unwrap_or(true)));
+        self.included
+            .extend((0..n).map(|i| group.included.get(i).copied().unwrap_or(true)));
+        self.weights
+            .extend((0..n).map(|i| group.weights.get(i).copied().unwrap_or(1.0)));
         self.spectra.extend(group.spectra);
         self
     }
@@ -68,7 +125,12 @@ impl XASGroup {
             return Err(Box::new(XAFSError::GroupIndexOutOfRange));
         }
 
+        self.sync_flags();
         self.spectra.remove(index);
+        self.selected.remove(index);
+        self.visible.remove(index);
+        self.included.remove(index);
+        self.weights.remove(index);
         Ok(self)
     }
 
@@ -76,91 +138,342 @@ impl XASGroup {
         let mut indices = indices.to_vec();
         indices.sort();
         indices.dedup();
-        let mut remove_index_iter = (0..self.len()).map(|index| !indices.contains(&index));
-        self.spectra.retain(|_| remove_index_iter.next().unwrap());
+
+        self.sync_flags();
+        retain_indices(&mut self.spectra, &indices);
+        retain_indices(&mut self.selected, &indices);
+        retain_indices(&mut self.visible, &indices);
+        retain_indices(&mut self.included, &indices);
+        retain_indices(&mut self.weights, &indices);
         Ok(self)
     }
 
+    /// Remove all the selected spectra from the group.
+    pub fn remove_selected(&mut self) -> Result<&mut Self, Box<dyn Error>> {
+        let indices = self.selected_indices();
+        self.remove_spectra(&indices)
+    }
+
+    /// Find spectra with the same raw data as an earlier spectrum of the group.
+    ///
+    /// Spectra are compared by their content hash, and the raw arrays are compared when the
+    /// hashes match. Spectra without raw data are ignored.
+    pub fn find_duplicates(&self) -> Vec<DuplicateSpectrum> {
+        let hashes = self
+            .spectra
+            .iter()
+            .map(|spectrum| spectrum.get_content_hash())
+            .collect::<Vec<Option<String>>>();
+
+        let mut duplicates = Vec::new();
+
+        for (index, spectrum) in self.spectra.iter().enumerate() {
+            if hashes[index].is_none() {
+                continue;
+            }
+
+            let original = (0..index).find(|&i| {
+                hashes[i] == hashes[index]
+                    && self.spectra[i].raw_energy == spectrum.raw_energy
+                    && self.spectra[i].raw_mu == spectrum.raw_mu
+            });
+
+            if let Some(original) = original {
+                duplicates.push(DuplicateSpectrum {
+                    index,
+                    name: spectrum.name.clone(),
+                    duplicate_of: original,
+                });
+            }
+        }
+
+        duplicates
+    }
+
+    /// Remove duplicated scans, keeping the first occurrence of each.
+    ///
+    /// Returns the removed spectra, with indices before the removal.
+    pub fn dedupe(&mut self) -> Result<Vec<DuplicateSpectrum>, Box<dyn Error>> {
+        let duplicates = self.find_duplicates();
+        let indices = duplicates.iter().map(|d| d.index).collect::<Vec<usize>>();
+        self.remove_spectra(&indices)?;
+
+        Ok(duplicates)
+    }
+
     pub fn move_spectrum(&mut self, from: usize, to: usize) -> &mut Self {
-        // TODO: check if it is fast enough
+        self.sync_flags();
+        move_item(&mut self.spectra, from, to);
+        move_item(&mut self.selected, from, to);
+        move_item(&mut self.visible, from, to);
+        move_item(&mut self.included, from, to);
+        move_item(&mut self.weights, from, to);
+        self
+    }
 
-        let from_index = if from < self.spectra.len() {
-            from
-        } else {
-            self.spectra.len() - 1
-        };
+    pub fn move_spectra(&mut self, from: &[usize], to: usize) -> &mut Self {
+        self.sync_flags();
+        move_items(&mut self.spectra, from, to);
+        move_items(&mut self.selected, from, to);
+        move_items(&mut self.visible, from, to);
+        move_items(&mut self.included, from, to);
+        move_items(&mut self.weights, from, to);
+        self
+    }
 
-        let to_index = if to <= self.spectra.len() {
-            to
-        } else {
-            self.spectra.len()
-        };
+    pub fn rename_spectrum<S: Into<String>>(
+        &mut self,
+        index: usize,
+        name: S,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        self.spectra
+            .get_mut(index)
+            .ok_or(XAFSError::GroupIndexOutOfRange)?
+            .set_name(name);
+        Ok(self)
+    }
+
+    // Keep the selection, visibility and inclusion flags in step with `spectra`, which may
+    // have been modified directly through the public field.
+    fn sync_flags(&mut self) {
+        self.selected.resize(self.spectra.len(), false);
+        self.visible.resize(self.spectra.len(), true);
+        self.included.resize(self.spectra.len(), true);
+        self.weights.resize(self.spectra.len(), 1.0);
+    }
+
+    pub fn set_selected(
+        &mut self,
+        index: usize,
+        selected: bool,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if index >= self.spectra.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        self.sync_flags();
+        self.selected[index] = selected;
+        Ok(self)
+    }
+
+    pub fn toggle_selected(&mut self, index: usize) -> Result<&mut Self, Box<dyn Error>> {
+        let selected = self.is_selected(index);
+        self.set_selected(index, !selected)
+    }
 
-        if from_index + 1 == to_index {
-            return self;
+    /// Replace the selection by the given set of indices. Indices out of range are ignored.
+    pub fn select(&mut self, indices: &[usize]) -> &mut Self {
+        self.sync_flags();
+        self.selected.iter_mut().for_each(|x| *x = false);
+        for &index in indices.iter().filter(|&&index| index < self.spectra.len()) {
+            self.selected[index] = true;
         }
+        self
+    }
+
+    pub fn select_all(&mut self) -> &mut Self {
+        self.sync_flags();
+        self.selected.iter_mut().for_each(|x| *x = true);
+        self
+    }
+
+    pub fn clear_selection(&mut self) -> &mut Self {
+        self.sync_flags();
+        self.selected.iter_mut().for_each(|x| *x = false);
+        self
+    }
+
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.selected.get(index).copied().unwrap_or(false)
+    }
 
-        let tmp_spectrum = mem::take(&mut self.spectra[from_index]);
-        self.spectra.insert(to_index, tmp_spectrum);
+    pub fn selected_indices(&self) -> Vec<usize> {
+        (0..self.len()).filter(|&i| self.is_selected(i)).collect()
+    }
 
-        if from_index > to_index {
-            self.spectra.remove(from_index + 1);
-        } else {
-            self.spectra.remove(from_index);
+    pub fn set_visible(
+        &mut self,
+        index: usize,
+        visible: bool,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if index >= self.spectra.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
         }
 
+        self.sync_flags();
+        self.visible[index] = visible;
+        Ok(self)
+    }
+
+    /// Show only the selected spectra in plots.
+    pub fn show_selected_only(&mut self) -> &mut Self {
+        self.sync_flags();
+        self.visible = self.selected.clone();
         self
     }
 
-    pub fn move_spectra(&mut self, from: &[usize], to: usize) -> &mut Self {
-        let to_index = if to <= self.spectra.len() {
-            to
-        } else {
-            self.spectra.len()
-        };
+    /// Excluded spectra are never visible.
+    pub fn is_visible(&self, index: usize) -> bool {
+        index < self.len()
+            && self.visible.get(index).copied().unwrap_or(true)
+            && self.is_included(index)
+    }
 
-        // Remove the duplicate index from the from list
-        let mut from_index: Vec<usize> = from
-            .as_ref()
-            .iter()
-            .filter(|&index| *index < self.spectra.len())
-            .map(|&index| index)
-            .collect::<Vec<usize>>();
+    pub fn visible_indices(&self) -> Vec<usize> {
+        (0..self.len()).filter(|&i| self.is_visible(i)).collect()
+    }
 
-        from_index.sort();
-        from_index.dedup();
+    /// Include or exclude the spectrum from merging, statistics and plots.
+    pub fn set_included(
+        &mut self,
+        index: usize,
+        included: bool,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if index >= self.spectra.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
+        }
+
+        self.sync_flags();
+        self.included[index] = included;
+        Ok(self)
+    }
+
+    pub fn is_included(&self, index: usize) -> bool {
+        index < self.len() && self.included.get(index).copied().unwrap_or(true)
+    }
 
-        // Create a temporary vector to store the spectra to be moved
-        // It is moved by mem::take() to avoid cloning
-        let mut tmp_spectra = Vec::with_capacity(from_index.len());
+    pub fn included_indices(&self) -> Vec<usize> {
+        (0..self.len()).filter(|&i| self.is_included(i)).collect()
+    }
 
-        for index in from_index.iter() {
-            tmp_spectra.push(mem::take(&mut self.spectra[*index]));
+    /// Set the weight of the spectrum in merging. The weight multiplies the weight of the
+    /// merge weighting and must be finite and non-negative.
+    pub fn set_weight(&mut self, index: usize, weight: f64) -> Result<&mut Self, Box<dyn Error>> {
+        if index >= self.spectra.len() {
+            return Err(Box::new(XAFSError::GroupIndexOutOfRange));
         }
 
-        // Create a iterator to remove the spectra from the group
-        let mut remove_index_iter = (0..self.len()).map(|index| !from_index.contains(&index));
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(format!("invalid weight {} of spectrum {}", weight, index).into());
+        }
+
+        self.sync_flags();
+        self.weights[index] = weight;
+        Ok(self)
+    }
+
+    pub fn get_weight(&self, index: usize) -> f64 {
+        self.weights.get(index).copied().unwrap_or(1.0)
+    }
 
-        // Calculate the shift of the insert index
-        let insert_index_shift = from_index.iter().filter(|&index| *index < to_index).count();
+    /// Sorted union of the metadata keys of all spectra, e.g. for the columns of a table.
+    pub fn metadata_keys(&self) -> Vec<String> {
+        self.spectra
+            .iter()
+            .flat_map(|spectrum| spectrum.metadata.keys().cloned())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Add a tag to the group, unless it already has it.
+    pub fn add_tag<S: Into<String>>(&mut self, tag: S) -> &mut Self {
+        let tag = tag.into();
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        self
+    }
 
-        let insert_index = to_index - insert_index_shift;
+    pub fn add_note<S: Into<String>>(&mut self, note: S) -> &mut Self {
+        self.notes.push(note.into());
+        self
+    }
 
-        self.spectra.retain(|_| remove_index_iter.next().unwrap());
+    /// Indices of the spectra with the tag.
+    pub fn tagged_indices(&self, tag: &str) -> Vec<usize> {
+        (0..self.len())
+            .filter(|&i| self.spectra[i].has_tag(tag))
+            .collect()
+    }
 
-        let (left_spectra, right_spectra) = self.spectra.split_at_mut(insert_index);
+    /// Indices of the spectra whose name, tags, notes or string metadata contain `text`,
+    /// ignoring case.
+    pub fn search(&self, text: &str) -> Vec<usize> {
+        (0..self.len())
+            .filter(|&i| self.spectra[i].matches_text(text))
+            .collect()
+    }
 
-        // I think this part is not very efficient
-        // TODO: check if it is fast enough
-        self.spectra = left_spectra
-            .iter_mut()
-            .chain(tmp_spectra.iter_mut())
-            .chain(right_spectra.iter_mut())
-            .map(|spectrum| mem::take(spectrum))
-            .collect::<Vec<XASSpectrum>>();
+    /// Sort the spectra by the metadata `keys`, the first key taking precedence.
+    ///
+    /// Numbers are compared by value and sorted before strings, which are sorted before other
+    /// values. Spectra missing a key come last. The sort is stable and the flags and weights
+    /// follow their spectra.
+    pub fn sort_by_metadata(&mut self, keys: &[&str]) -> &mut Self {
+        self.sync_flags();
+
+        let order = self.metadata_order(keys);
+        self.spectra = permute(mem::take(&mut self.spectra), &order);
+        self.selected = permute(mem::take(&mut self.selected), &order);
+        self.visible = permute(mem::take(&mut self.visible), &order);
+        self.included = permute(mem::take(&mut self.included), &order);
+        self.weights = permute(mem::take(&mut self.weights), &order);
         self
     }
 
+    /// Split the group into sub-groups of spectra with equal values of the metadata `keys`.
+    ///
+    /// The sub-groups are ordered as by sort_by_metadata, and the spectra keep their order in
+    /// the group. Numbers are equal if their values are, so 300 and 300.0 end up together;
+    /// noisy values such as measured temperatures should be rounded into a metadata key first.
+    pub fn group_by_metadata(&self, keys: &[&str]) -> Vec<MetadataGroup> {
+        let order = self.metadata_order(keys);
+
+        order
+            .into_iter()
+            .group_by(|&i| self.metadata_values(i, keys))
+            .into_iter()
+            .map(|(_, indices)| {
+                let indices = indices.collect::<Vec<usize>>();
+                let mut group = XASGroup::new();
+                for &i in indices.iter() {
+                    group.spectra.push(self.spectra[i].clone());
+                    group.selected.push(self.is_selected(i));
+                    group
+                        .visible
+                        .push(self.visible.get(i).copied().unwrap_or(true));
+                    group.included.push(self.is_included(i));
+                    group.weights.push(self.get_weight(i));
+                }
+
+                MetadataGroup {
+                    values: self.metadata_values(indices[0], keys).0,
+                    indices,
+                    group,
+                }
+            })
+            .collect()
+    }
+
+    fn metadata_values(&self, index: usize, keys: &[&str]) -> MetadataValues {
+        MetadataValues(
+            keys.iter()
+                .map(|key| self.spectra[index].get_metadata(key).cloned())
+                .collect(),
+        )
+    }
+
+    fn metadata_order(&self, keys: &[&str]) -> Vec<usize> {
+        let values = (0..self.len())
+            .map(|i| self.metadata_values(i, keys))
+            .collect::<Vec<MetadataValues>>();
+
+        (0..self.len())
+            .sorted_by(|&a, &b| values[a].cmp(&values[b]))
+            .collect()
+    }
+
     pub fn get_spectrum(&self, index: usize) -> Result<&XASSpectrum, Box<dyn Error>> {
         if self.spectra.is_empty() {
             return Err(Box::new(XAFSError::GroupIsEmpty));
@@ -185,131 +498,126 @@ impl XASGroup {
         Ok(&mut self.spectra[index])
     }
 
+    /// Average the spectra at `master` and `slave` on the energy grid of `master` and add the
+    /// result to the group. See merge_weighted for other weightings.
     pub fn merge(&mut self, master: usize, slave: &[usize]) -> Result<&mut Self, Box<dyn Error>> {
-        todo!("merge");
-
-        // self.spectra.extend(other.spectra.clone());
-        Ok(self)
+        self.merge_weighted(master, slave, MergeWeighting::Equal)
     }
 
     pub fn find_e0(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.find_e0().unwrap();
-        });
-
+        self.view_all_mut().find_e0()?;
         Ok(self)
     }
 
     pub fn find_e0_seq(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.iter_mut().for_each(|spectrum| {
-            spectrum.find_e0().unwrap();
-        });
-
+        self.view_all_mut().try_for_each_seq(GroupStep::FindE0)?;
         Ok(self)
     }
 
     pub fn find_e0_par(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.find_e0().unwrap();
-        });
-
-        Ok(self)
+        self.find_e0()
     }
 
     pub fn normalize(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.normalize().unwrap();
-        });
-
+        self.view_all_mut().normalize()?;
         Ok(self)
     }
 
     pub fn normalize_seq(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.iter_mut().for_each(|spectrum| {
-            spectrum.normalize().unwrap();
-        });
-
+        self.view_all_mut().try_for_each_seq(GroupStep::Normalize)?;
         Ok(self)
     }
 
     pub fn normalize_par(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.normalize().unwrap();
-        });
-
-        Ok(self)
+        self.normalize()
     }
 
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
     pub fn calc_background(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.calc_background().unwrap();
-        });
-
+        self.view_all_mut().calc_background()?;
         Ok(self)
     }
 
     pub fn calc_background_seq(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.iter_mut().for_each(|spectrum| {
-            spectrum.calc_background().unwrap();
-        });
-
+        self.view_all_mut()
+            .try_for_each_seq(GroupStep::Background)?;
         Ok(self)
     }
 
     pub fn calc_background_par(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.calc_background().unwrap();
-        });
+        self.calc_background()
+    }
 
-        Ok(self)
+    /// AUTOBK of the spectra at `indices` with a common ek0, taken as e0 of their merge on the
+    /// energy grid of the first spectrum.
+    ///
+    /// For a series of the same sample, a shared ek0 keeps the k grid and the phase of chi(k)
+    /// consistent, while the ek0 of each spectrum would follow the noise of its edge. The AUTOBK
+    /// parameters of each spectrum are kept, other background methods are replaced by AUTOBK.
+    /// Returns the shared ek0.
+    pub fn calc_background_shared_ek0(&mut self, indices: &[usize]) -> Result<f64, Box<dyn Error>> {
+        let (&master, slave) = indices.split_first().ok_or(XAFSError::GroupIsEmpty)?;
+
+        let mut merged = self.merged_spectrum(master, slave, MergeWeighting::Equal)?;
+        merged.find_e0()?;
+        let ek0 = merged.get_e0().ok_or(XAFSError::NotEnoughData)?;
+
+        let mut spectra = self
+            .spectra
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| indices.contains(i))
+            .map(|(_, spectrum)| spectrum)
+            .collect::<Vec<&mut XASSpectrum>>();
+
+        spectra
+            .par_iter_mut()
+            .try_for_each(|spectrum| {
+                let mut autobk = match spectrum.background.as_ref() {
+                    Some(BackgroundMethod::AUTOBK(autobk)) => autobk.copy_parameters(),
+                    _ => AUTOBK::new(),
+                };
+                autobk.ek0 = Some(ek0);
+
+                spectrum
+                    .set_background_method(Some(BackgroundMethod::AUTOBK(autobk)))
+                    .and_then(|spectrum| spectrum.calc_background())
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(|e| -> Box<dyn Error> { e.into() })?;
+
+        Ok(ek0)
     }
 
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
     pub fn fft(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.fft().unwrap();
-        });
-
+        self.view_all_mut().fft()?;
         Ok(self)
     }
 
     pub fn fft_seq(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.iter_mut().for_each(|spectrum| {
-            spectrum.fft().unwrap();
-        });
-
+        self.view_all_mut().try_for_each_seq(GroupStep::Xftf)?;
         Ok(self)
     }
 
     pub fn fft_par(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.fft().unwrap();
-        });
-
-        Ok(self)
+        self.fft()
     }
 
+    /// XANES-only spectra (see XASSpectrum::scan_type) are skipped.
     pub fn ifft(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.ifft().unwrap();
-        });
-
+        self.view_all_mut().ifft()?;
         Ok(self)
     }
 
     pub fn ifft_seq(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.iter_mut().for_each(|spectrum| {
-            spectrum.ifft().unwrap();
-        });
-
+        self.view_all_mut().try_for_each_seq(GroupStep::Xftr)?;
         Ok(self)
     }
 
     pub fn ifft_par(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.spectra.par_iter_mut().for_each(|spectrum| {
-            spectrum.ifft().unwrap();
-        });
-
-        Ok(self)
+        self.ifft()
     }
 
     pub fn read_bson(&mut self, filename: &str) -> Result<&mut Self, Box<dyn Error>> {
@@ -341,6 +649,130 @@ impl XASGroup {
     }
 }
 
+// Metadata values of a spectrum, ordered as described in XASGroup::sort_by_metadata
+#[derive(Debug, Clone)]
+struct MetadataValues(Vec<Option<serde_json::Value>>);
+
+impl MetadataValues {
+    fn rank(value: &Option<serde_json::Value>) -> u8 {
+        match value {
+            Some(serde_json::Value::Number(_)) => 0,
+            Some(serde_json::Value::String(_)) => 1,
+            Some(_) => 2,
+            None => 3,
+        }
+    }
+
+    fn cmp_value(a: &Option<serde_json::Value>, b: &Option<serde_json::Value>) -> Ordering {
+        use serde_json::Value;
+
+        Self::rank(a)
+            .cmp(&Self::rank(b))
+            .then_with(|| match (a, b) {
+                (Some(Value::Number(a)), Some(Value::Number(b))) => a
+                    .as_f64()
+                    .unwrap_or(f64::NAN)
+                    .total_cmp(&b.as_f64().unwrap_or(f64::NAN)),
+                (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+                (Some(a), Some(b)) => a.to_string().cmp(&b.to_string()),
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+impl PartialEq for MetadataValues {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MetadataValues {}
+
+impl PartialOrd for MetadataValues {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MetadataValues {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| Self::cmp_value(a, b))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn permute<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut items = items.into_iter().map(Some).collect::<Vec<Option<T>>>();
+    order.iter().map(|&i| items[i].take().unwrap()).collect()
+}
+
+fn retain_indices<T>(items: &mut Vec<T>, remove: &[usize]) {
+    let mut remove_index_iter = (0..items.len()).map(|index| !remove.contains(&index));
+    items.retain(|_| remove_index_iter.next().unwrap());
+}
+
+fn move_item<T: Default>(items: &mut Vec<T>, from: usize, to: usize) {
+    // TODO: check if it is fast enough
+
+    let from_index = if from < items.len() {
+        from
+    } else {
+        items.len() - 1
+    };
+
+    let to_index = if to <= items.len() { to } else { items.len() };
+
+    if from_index + 1 == to_index {
+        return;
+    }
+
+    let tmp_item = mem::take(&mut items[from_index]);
+    items.insert(to_index, tmp_item);
+
+    if from_index > to_index {
+        items.remove(from_index + 1);
+    } else {
+        items.remove(from_index);
+    }
+}
+
+fn move_items<T: Default>(items: &mut Vec<T>, from: &[usize], to: usize) {
+    let to_index = if to <= items.len() { to } else { items.len() };
+
+    // Remove the duplicate index from the from list
+    let mut from_index: Vec<usize> = from
+        .iter()
+        .filter(|&index| *index < items.len())
+        .copied()
+        .collect::<Vec<usize>>();
+
+    from_index.sort();
+    from_index.dedup();
+
+    // Create a temporary vector to store the items to be moved
+    // It is moved by mem::take() to avoid cloning
+    let mut tmp_items = Vec::with_capacity(from_index.len());
+
+    for index in from_index.iter() {
+        tmp_items.push(mem::take(&mut items[*index]));
+    }
+
+    // Calculate the shift of the insert index
+    let insert_index_shift = from_index.iter().filter(|&index| *index < to_index).count();
+
+    let insert_index = to_index - insert_index_shift;
+
+    retain_indices(items, &from_index);
+
+    let right_items = items.split_off(insert_index);
+    items.extend(tmp_items);
+    items.extend(right_items);
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -363,6 +795,41 @@ mod tests {
         assert_eq!(group.len(), 0);
     }
 
+    #[test]
+    fn test_dedupe() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+        assert!(spectrum.content_hash.is_some());
+
+        let mut other = spectrum.clone();
+        other.set_spectrum(
+            spectrum.raw_energy.clone().unwrap(),
+            spectrum.raw_mu.clone().unwrap() * 2.0,
+        );
+        assert_ne!(other.content_hash, spectrum.content_hash);
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(other)
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(XASSpectrum::new())
+            .add_spectrum(spectrum);
+        group.rename_spectrum(2, "resaved")?;
+
+        let removed = group.dedupe()?;
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].index, 2);
+        assert_eq!(removed[0].name, Some("resaved".to_string()));
+        assert_eq!(removed[0].duplicate_of, 0);
+        assert_eq!(removed[1].index, 4);
+        assert_eq!(group.len(), 3);
+        assert!(group.find_duplicates().is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_add_spectrum() {
         let mut group = XASGroup::new();
@@ -418,4 +885,201 @@ mod tests {
         group.move_spectra(&[0, 1], 3);
         assert_eq!(group.spectra[2].name.as_ref().unwrap(), "spectrum2");
     }
+
+    #[test]
+    fn test_selection_and_visibility() -> Result<(), Box<dyn Error>> {
+        let mut group = XASGroup::new();
+        let spectrum = XASSpectrum::new();
+        group.add_spectrum(spectrum.clone().set_name("spectrum1").to_owned());
+        group.add_spectrum(spectrum.clone().set_name("spectrum2").to_owned());
+        group.add_spectrum(spectrum.clone().set_name("spectrum3").to_owned());
+
+        group.select(&[0, 2, 10]);
+        assert_eq!(group.selected_indices(), vec![0, 2]);
+        assert_eq!(group.visible_indices(), vec![0, 1, 2]);
+
+        group.set_visible(1, false)?;
+        group.move_spectrum(1, 0);
+        assert_eq!(group.spectra[0].name.as_ref().unwrap(), "spectrum2");
+        assert!(!group.is_visible(0));
+        assert_eq!(group.selected_indices(), vec![1, 2]);
+
+        group.move_spectra(&[1], 3);
+        assert_eq!(group.spectra[2].name.as_ref().unwrap(), "spectrum1");
+        assert_eq!(group.selected_indices(), vec![1, 2]);
+
+        group.remove_selected()?;
+        assert_eq!(group.len(), 1);
+        assert_eq!(group.spectra[0].name.as_ref().unwrap(), "spectrum2");
+        assert!(group.selected_indices().is_empty());
+        assert!(group.set_selected(1, true).is_err());
+
+        group.rename_spectrum(0, "renamed")?;
+        assert_eq!(group.spectra[0].name.as_ref().unwrap(), "renamed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_keys() {
+        let mut group = XASGroup::new();
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_metadata("sample", "Ru foil");
+        group.add_spectrum(spectrum.clone());
+        spectrum.set_metadata("temperature", 300.0);
+        group.add_spectrum(spectrum);
+
+        assert_eq!(group.metadata_keys(), vec!["sample", "temperature"]);
+        assert_eq!(
+            group.spectra[1].get_metadata("temperature"),
+            Some(&serde_json::json!(300.0))
+        );
+    }
+
+    #[test]
+    fn test_group_by_metadata() -> Result<(), Box<dyn Error>> {
+        let mut group = XASGroup::new();
+        for (name, sample, temperature) in [
+            ("a", "Ru", Some(serde_json::json!(300))),
+            ("b", "Pt", Some(serde_json::json!(77.0))),
+            ("c", "Ru", None),
+            ("d", "Ru", Some(serde_json::json!(77))),
+            ("e", "Ru", Some(serde_json::json!(300.0))),
+        ] {
+            let mut spectrum = XASSpectrum::new();
+            spectrum.set_name(name).set_metadata("sample", sample);
+            if let Some(temperature) = temperature {
+                spectrum.set_metadata("temperature", temperature);
+            }
+            group.add_spectrum(spectrum);
+        }
+        group.set_selected(3, true)?.set_weight(4, 2.0)?;
+
+        let names = |group: &XASGroup| -> Vec<String> {
+            group
+                .spectra
+                .iter()
+                .map(|s| s.name.clone().unwrap())
+                .collect()
+        };
+
+        let subgroups = group.group_by_metadata(&["temperature"]);
+        assert_eq!(subgroups.len(), 3);
+        assert_eq!(subgroups[0].indices, vec![1, 3]);
+        assert_eq!(subgroups[0].values, vec![Some(serde_json::json!(77.0))]);
+        assert!(subgroups[0].group.is_selected(1));
+        assert_eq!(names(&subgroups[1].group), vec!["a", "e"]);
+        assert_eq!(subgroups[1].group.get_weight(1), 2.0);
+        assert_eq!(subgroups[2].values, vec![None]);
+
+        let subgroups = group.group_by_metadata(&["sample", "temperature"]);
+        assert_eq!(
+            subgroups
+                .iter()
+                .map(|g| g.indices.clone())
+                .collect::<Vec<_>>(),
+            vec![vec![1], vec![3], vec![0, 4], vec![2]]
+        );
+
+        group.sort_by_metadata(&["temperature", "sample"]);
+        assert_eq!(names(&group), vec!["b", "d", "a", "e", "c"]);
+        assert_eq!(group.selected_indices(), vec![1]);
+        assert_eq!(group.get_weight(3), 2.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags_and_notes() -> Result<(), Box<dyn Error>> {
+        let mut group = XASGroup::new();
+        for (name, tag) in [
+            ("Pt foil", "reference"),
+            ("PtO2", "sample"),
+            ("Pt/C", "sample"),
+        ] {
+            let mut spectrum = XASSpectrum::new();
+            spectrum.set_name(name).add_tag(tag).add_tag(tag);
+            group.add_spectrum(spectrum);
+        }
+        group.spectra[2]
+            .add_note("bad i0 after 15:00")
+            .set_metadata("beamline", "BL01");
+        group
+            .add_tag("beamtime 2023")
+            .add_note("energy calibrated with Pt foil");
+
+        assert_eq!(group.spectra[1].tags, vec!["sample"]);
+        assert_eq!(group.tagged_indices("sample"), vec![1, 2]);
+        assert_eq!(group.search("BAD I0"), vec![2]);
+        assert_eq!(group.search("bl01"), vec![2]);
+        assert_eq!(group.search("pt"), vec![0, 1, 2]);
+
+        group.spectra[2].remove_tag("sample");
+        assert_eq!(group.tagged_indices("sample"), vec![1]);
+
+        let roundtrip: XASGroup = serde_json::from_str(&serde_json::to_string(&group)?)?;
+        assert_eq!(roundtrip, group);
+        assert_eq!(roundtrip.spectra[2].notes, vec!["bad i0 after 15:00"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calc_background_par() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.normalize()?;
+
+        let mut group = XASGroup::new();
+        for i in 0..8 {
+            let mut shifted = spectrum.clone();
+            shifted.shift_energy(0.1 * i as f64, false)?;
+            group.add_spectrum(shifted);
+        }
+        let mut sequential = group.clone();
+
+        // The worker threads reuse their AUTOBK workspaces across the spectra
+        group.calc_background_par()?;
+        sequential.calc_background_seq()?;
+        for (par, seq) in group.spectra.iter().zip(sequential.spectra.iter()) {
+            assert_eq!(par.get_chi(), seq.get_chi());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calc_background_shared_ek0() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        let mut shifted = spectrum.clone();
+        shifted.shift_energy(0.5, false)?;
+
+        let mut group = XASGroup::new();
+        group
+            .add_spectrum(spectrum.clone())
+            .add_spectrum(shifted)
+            .add_spectrum(spectrum);
+
+        let ek0 = group.calc_background_shared_ek0(&[0, 1])?;
+
+        let ek0_of = |i: usize| match group.spectra[i].background.as_ref() {
+            Some(BackgroundMethod::AUTOBK(autobk)) => autobk.get_ek0().cloned(),
+            _ => None,
+        };
+        assert_eq!(ek0_of(0), Some(ek0));
+        assert_eq!(ek0_of(1), Some(ek0));
+        assert!(group.spectra[2].background.is_none());
+        assert_eq!(group.spectra[0].get_k(), group.spectra[1].get_k());
+
+        let mut single = group.spectra[2].clone();
+        single.find_e0()?;
+        assert!((ek0 - single.get_e0().unwrap()).abs() < 0.5);
+
+        assert!(group.calc_background_shared_ek0(&[]).is_err());
+        assert!(group.calc_background_shared_ek0(&[0, 5]).is_err());
+
+        Ok(())
+    }
 }
@@ -2,35 +2,53 @@
 #![allow(unused_imports)]
 
 use std::borrow::Borrow;
+use std::collections::BTreeMap;
 #[cfg_attr(debug_assertions, allow(dead_code, unused_imports))]
 // Standard library dependencies
 use std::error::Error;
 
 // External dependencies
 use easyfft::dyn_size::realfft::DynRealDft;
-use ndarray::{ArrayBase, Axis, Ix1, OwnedRepr, ViewRepr};
+use ndarray::{Array1, ArrayBase, ArrayView1, Axis, Ix1, OwnedRepr, ViewRepr};
+use num_complex::Complex64;
 use serde::{Deserialize, Serialize};
 
+use derivative::Derivative;
+
 // load dependencies
 use super::background;
 use super::io;
-use super::lmutils;
+use super::lmutils::{self, LMTrace};
 use super::mathutils;
 use super::normalization;
 use super::nshare;
+use super::processing::ProcessingOptions;
+use super::snapshot::{ProcessingSnapshot, Product};
+use super::units::ElectronVolt;
 use super::xafsutils;
 use super::xrayfft;
 
 // Load local traits
+use super::XAFSError;
 use mathutils::MathUtils;
 use normalization::Normalization;
 
+/// Type of a scan, judged from the energy range measured above e0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanType {
+    /// The scan ends before the EXAFS region; only normalization is meaningful.
+    Xanes,
+    /// The scan extends far enough above e0 for background removal and FT.
+    Exafs,
+}
+
 /// XASGroup is a struct that contains all the data and parameters for a single XAS spectrum.
 ///
 /// # Examples
 ///
 /// TODO: Add examples
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Derivative, Debug, Clone, Serialize, Deserialize)]
+#[derivative(PartialEq)]
 #[serde(default)]
 pub struct XASSpectrum {
     pub name: Option<String>,
@@ -38,7 +56,11 @@ pub struct XASSpectrum {
     pub raw_mu: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
     pub energy: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
     pub mu: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
+    /// Per-point uncertainty of mu on the energy grid
+    pub delta_mu: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
     pub e0: Option<f64>,
+    /// Uncertainty of e0 found by find_e0, from the width of the derivative peak
+    pub e0_uncertainty: Option<f64>,
     pub k: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
     pub chi: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
     pub chi_kweighted: Option<ArrayBase<OwnedRepr<f64>, Ix1>>,
@@ -51,6 +73,24 @@ pub struct XASSpectrum {
     pub background: Option<background::BackgroundMethod>,
     pub xftf: Option<xrayfft::XrayFFTF>,
     pub xftr: Option<xrayfft::XrayFFTR>,
+    /// Free-form information about the measurement (sample, temperature, beamline, ...)
+    pub metadata: BTreeMap<String, serde_json::Value>,
+    /// Labels used to find spectra in a group, e.g. "reference" or "bad i0"
+    pub tags: Vec<String>,
+    /// Free-form notes on the analysis, e.g. "bad i0 after 15:00"
+    pub notes: Vec<String>,
+    /// Detector channels (i0, it, ...) on the raw energy grid
+    pub channels: BTreeMap<String, ArrayBase<OwnedRepr<f64>, Ix1>>,
+    /// Hash of the raw data when it was set (hexadecimal), used to detect duplicated scans
+    pub content_hash: Option<String>,
+    /// Tolerances used to prepare the energy grid
+    pub processing_options: ProcessingOptions,
+    /// Processing results kept for comparison, by name
+    pub snapshots: BTreeMap<String, ProcessingSnapshot>,
+    // Last product replaced in the current reprocessing round, see keep_previous
+    #[serde(skip)]
+    #[derivative(PartialEq = "ignore")]
+    pub(crate) last_replaced: Option<Product>,
 }
 
 impl Default for XASSpectrum {
@@ -61,7 +101,9 @@ impl Default for XASSpectrum {
             raw_mu: None,
             energy: None,
             mu: None,
+            delta_mu: None,
             e0: None,
+            e0_uncertainty: None,
             k: None,
             chi: None,
             chi_kweighted: None,
@@ -74,20 +116,182 @@ impl Default for XASSpectrum {
             background: None,
             xftf: None,
             xftr: None,
+            metadata: BTreeMap::new(),
+            tags: Vec::new(),
+            notes: Vec::new(),
+            channels: BTreeMap::new(),
+            content_hash: None,
+            processing_options: ProcessingOptions::default(),
+            snapshots: BTreeMap::new(),
+            last_replaced: None,
         }
     }
 }
 
+/// One-dimensional data accepted by XASSpectrum::from_arrays
+pub trait IntoArray1 {
+    fn into_array1(self) -> Array1<f64>;
+}
+
+impl IntoArray1 for Array1<f64> {
+    fn into_array1(self) -> Array1<f64> {
+        self
+    }
+}
+
+impl IntoArray1 for &Array1<f64> {
+    fn into_array1(self) -> Array1<f64> {
+        self.clone()
+    }
+}
+
+impl IntoArray1 for ArrayView1<'_, f64> {
+    fn into_array1(self) -> Array1<f64> {
+        self.to_owned()
+    }
+}
+
+impl IntoArray1 for Vec<f64> {
+    fn into_array1(self) -> Array1<f64> {
+        Array1::from_vec(self)
+    }
+}
+
+impl IntoArray1 for &Vec<f64> {
+    fn into_array1(self) -> Array1<f64> {
+        Array1::from_vec(self.clone())
+    }
+}
+
+impl IntoArray1 for &[f64] {
+    fn into_array1(self) -> Array1<f64> {
+        Array1::from_vec(self.to_vec())
+    }
+}
+
+impl<const N: usize> IntoArray1 for [f64; N] {
+    fn into_array1(self) -> Array1<f64> {
+        Array1::from_vec(self.to_vec())
+    }
+}
+
 impl XASSpectrum {
     pub fn new() -> XASSpectrum {
         XASSpectrum::default()
     }
 
+    /// Spectrum from energy (eV) and mu arrays, as Vec, slice or ndarray.
+    ///
+    /// Unlike set_spectrum, the arrays are checked: they must have the same length and leave at
+    /// least two points after the non-finite policy is applied. Duplicated energies are kept
+    /// and shifted by the tiny energy of the processing options, as done by set_spectrum.
+    pub fn from_arrays<E: IntoArray1, M: IntoArray1>(
+        energy: E,
+        mu: M,
+    ) -> Result<XASSpectrum, Box<dyn Error>> {
+        XASSpectrum::from_arrays_with_metadata(energy, mu, None, BTreeMap::new())
+    }
+
+    /// from_arrays with an optional name and the metadata of the spectrum.
+    pub fn from_arrays_with_metadata<E: IntoArray1, M: IntoArray1>(
+        energy: E,
+        mu: M,
+        name: Option<&str>,
+        metadata: BTreeMap<String, serde_json::Value>,
+    ) -> Result<XASSpectrum, Box<dyn Error>> {
+        let energy = energy.into_array1();
+        let mu = mu.into_array1();
+
+        if energy.len() != mu.len() {
+            return Err(format!(
+                "energy and mu have different lengths ({} and {})",
+                energy.len(),
+                mu.len()
+            )
+            .into());
+        }
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy, mu);
+        if spectrum.energy.as_ref().map_or(0, |e| e.len()) < 2 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        spectrum.name = name.map(str::to_owned);
+        spectrum.metadata = metadata;
+
+        Ok(spectrum)
+    }
+
     pub fn set_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
         self.name = Some(name.into());
         self
     }
 
+    /// Copy the normalization, background and Fourier transform parameters of `other`.
+    ///
+    /// The data, e0 and the results of `self` are left as they are or cleared, so that a
+    /// template spectrum tuned interactively can be applied to the rest of a dataset.
+    /// e0, ek0 and the edge step are specific to each spectrum and are not copied.
+    pub fn copy_parameters_from(&mut self, other: &XASSpectrum) -> &mut Self {
+        self.normalization = other.normalization.as_ref().map(|x| x.copy_parameters());
+        self.background = other.background.as_ref().map(|x| x.copy_parameters());
+        self.xftf = other.xftf.as_ref().map(|x| x.copy_parameters());
+        self.xftr = other.xftr.as_ref().map(|x| x.copy_parameters());
+        self
+    }
+
+    pub fn set_metadata<K: Into<String>, V: Into<serde_json::Value>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn get_metadata(&self, key: &str) -> Option<&serde_json::Value> {
+        self.metadata.get(key)
+    }
+
+    /// Add a tag, unless the spectrum already has it.
+    pub fn add_tag<S: Into<String>>(&mut self, tag: S) -> &mut Self {
+        let tag = tag.into();
+        if !self.has_tag(&tag) {
+            self.tags.push(tag);
+        }
+        self
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) -> &mut Self {
+        self.tags.retain(|t| t != tag);
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn add_note<S: Into<String>>(&mut self, note: S) -> &mut Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Whether `text` appears in the name, tags, notes or string metadata of the spectrum,
+    /// ignoring case.
+    pub fn matches_text(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        let contains = |value: &str| value.to_lowercase().contains(&text);
+
+        self.name.as_deref().is_some_and(contains)
+            || self.tags.iter().any(|tag| contains(tag))
+            || self.notes.iter().any(|note| contains(note))
+            || self
+                .metadata
+                .values()
+                .any(|value| value.as_str().is_some_and(contains))
+    }
+
     pub fn set_spectrum<
         T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>,
         M: Into<ArrayBase<OwnedRepr<f64>, Ix1>>,
@@ -107,12 +311,103 @@ impl XASSpectrum {
             self.raw_energy = Some(raw_energy);
             self.raw_mu = Some(raw_mu);
         }
-        self.energy = self.raw_energy.clone();
-        self.mu = self.raw_mu.clone();
+        self.prepare_spectrum();
+        self.content_hash = self.calc_content_hash();
 
         self
     }
 
+    /// Set the tolerances used to prepare the energy grid.
+    ///
+    /// energy and mu are derived again from the raw data, as done by set_spectrum, so that
+    /// spectra returned by the loaders can be prepared with other tolerances.
+    pub fn set_processing_options(&mut self, options: ProcessingOptions) -> &mut Self {
+        self.processing_options = options;
+        self.prepare_spectrum();
+
+        self
+    }
+
+    /// Derive energy and mu from the raw data with the processing options.
+    fn prepare_spectrum(&mut self) {
+        if let (Some(raw_energy), Some(raw_mu)) = (self.raw_energy.as_ref(), self.raw_mu.as_ref()) {
+            let (energy, mu) = self.processing_options.prepare(raw_energy, raw_mu);
+            self.energy = Some(energy);
+            self.mu = Some(mu);
+        }
+    }
+
+    /// Energy and mu after the checks of the non-finite policy.
+    fn checked_spectrum(&self) -> Result<(Array1<f64>, Array1<f64>), Box<dyn Error>> {
+        let energy = self.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+        let mu = self.mu.clone().ok_or(XAFSError::NotEnoughData)?;
+        self.processing_options.check_finite(&energy, &mu)?;
+
+        Ok((energy, mu))
+    }
+
+    /// Hash of raw_energy and raw_mu (64 bit FNV-1a of the values) as a hexadecimal string.
+    ///
+    /// Unlike std's hasher, it is stable between builds, so that it can be stored in files.
+    pub fn calc_content_hash(&self) -> Option<String> {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let raw_energy = self.raw_energy.as_ref()?;
+        let raw_mu = self.raw_mu.as_ref()?;
+
+        let hash = std::iter::once(raw_energy.len() as u64)
+            .chain(raw_energy.iter().chain(raw_mu.iter()).map(|x| x.to_bits()))
+            .flat_map(|x| x.to_le_bytes())
+            .fold(FNV_OFFSET, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            });
+
+        Some(format!("{:016x}", hash))
+    }
+
+    /// Hash of the raw data as loaded, or of the current raw data if it was not recorded.
+    pub fn get_content_hash(&self) -> Option<String> {
+        self.content_hash
+            .clone()
+            .or_else(|| self.calc_content_hash())
+    }
+
+    /// Set the spectrum together with the detector channels it was calculated from.
+    ///
+    /// The channels must have the same length as `energy` and are sorted with it.
+    pub fn set_spectrum_channels<
+        T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>,
+        M: Into<ArrayBase<OwnedRepr<f64>, Ix1>>,
+    >(
+        &mut self,
+        energy: T,
+        mu: M,
+        channels: BTreeMap<String, ArrayBase<OwnedRepr<f64>, Ix1>>,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let energy = energy.into();
+
+        if channels.values().any(|c| c.len() != energy.len()) {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        self.channels = if !energy.is_sorted() {
+            let sort_idx = energy.argsort();
+            channels
+                .into_iter()
+                .map(|(name, c)| (name, c.select(ndarray::Axis(0), &sort_idx)))
+                .collect()
+        } else {
+            channels
+        };
+
+        Ok(self.set_spectrum(energy, mu))
+    }
+
+    pub fn get_channel(&self, name: &str) -> Option<&ArrayBase<OwnedRepr<f64>, Ix1>> {
+        self.channels.get(name)
+    }
+
     pub fn interpolate_spectrum<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
         &mut self,
         energy: T,
@@ -120,32 +415,251 @@ impl XASSpectrum {
         self.energy = Some(energy.into());
 
         let energy = self.energy.clone().unwrap();
-        let mu = self.raw_mu.clone().unwrap().to_vec();
-        let knot = self.raw_energy.clone().unwrap().to_vec();
+        let (knot, mu) = self.processing_options.prepare(
+            self.raw_energy.as_ref().unwrap(),
+            self.raw_mu.as_ref().unwrap(),
+        );
+        self.processing_options.check_finite(&knot, &mu)?;
+        let (knot, mu) = (knot.to_vec(), mu.to_vec());
 
         self.mu = Some(energy.interpolate(&knot, &mu).unwrap());
 
+        if let Some(delta_mu) = self.delta_mu.take() {
+            if delta_mu.len() == knot.len() {
+                self.delta_mu = Some(energy.interpolate(&knot, &delta_mu.to_vec())?);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Resample the raw spectrum onto `energy` with `method` (see xafsutils::resample).
+    ///
+    /// Unlike interpolate_spectrum, each point represents a bin of the raw data, which suits
+    /// reducing the point density of quick-scan data. The standard error of the mean of each bin
+    /// is set as delta_mu, so that it is propagated as a measurement uncertainty.
+    pub fn resample_spectrum<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+        &mut self,
+        energy: T,
+        method: xafsutils::RebinMethod,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let energy = energy.into();
+        let raw_energy = self.raw_energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let raw_mu = self.raw_mu.as_ref().ok_or(XAFSError::NotEnoughData)?;
+
+        let (raw_energy, raw_mu) = self.processing_options.prepare(raw_energy, raw_mu);
+        self.processing_options.check_finite(&raw_energy, &raw_mu)?;
+
+        let (mu, delta_mu) = xafsutils::resample(&raw_energy, &raw_mu, &energy, method)?;
+
+        self.energy = Some(energy);
+        self.mu = Some(mu);
+        self.delta_mu = Some(delta_mu);
+
+        Ok(self)
+    }
+
+    /// Rebin the raw spectrum to the 3 region grid of `options` (see xafsutils::rebin), with
+    /// the edge at e0. e0 is found first if it is not set.
+    ///
+    /// The rebinned spectrum replaces energy and mu, and the standard error of each bin is set
+    /// as delta_mu, as with resample_spectrum.
+    pub fn rebin(
+        &mut self,
+        options: &xafsutils::RebinOptions,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        if self.e0.is_none() {
+            self.find_e0()?;
+        }
+        let raw_energy = self.raw_energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let grid = options.grid(raw_energy, self.e0.unwrap())?;
+
+        self.resample_spectrum(grid, options.method)
+    }
+
+    /// Set the per-point uncertainty of mu(E).
+    ///
+    /// The array has to be on the same energy grid as mu. It is propagated to norm, flat and chi(k)
+    /// by normalize() and calc_background().
+    pub fn set_delta_mu<T: Into<ArrayBase<OwnedRepr<f64>, Ix1>>>(
+        &mut self,
+        delta_mu: T,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let delta_mu = delta_mu.into();
+
+        if self.mu.as_ref().map(|mu| mu.len()) != Some(delta_mu.len()) {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        self.delta_mu = Some(delta_mu);
+
         Ok(self)
     }
 
-    pub fn set_e0<S: Into<f64>>(&mut self, e0: S) -> &mut Self {
-        self.e0 = Some(e0.into());
+    pub fn set_e0<S: Into<ElectronVolt>>(&mut self, e0: S) -> &mut Self {
+        self.e0 = Some(e0.into().value());
+        self.e0_uncertainty = None;
 
         self
     }
 
+    /// Energy relative to e0.
+    pub fn get_relative_energy(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        let e0 = self.e0?;
+        Some(
+            self.energy
+                .as_ref()?
+                .mapv(|e| xafsutils::relative_energy(e0, e)),
+        )
+    }
+
+    /// Convert an absolute energy to an energy relative to e0.
+    pub fn to_relative_energy(&self, energy: f64) -> Option<f64> {
+        Some(xafsutils::relative_energy(self.e0?, energy))
+    }
+
+    /// Convert an energy relative to e0 to an absolute energy.
+    pub fn to_absolute_energy(&self, relative_energy: f64) -> Option<f64> {
+        Some(xafsutils::absolute_energy(self.e0?, relative_energy))
+    }
+
+    /// Classify the scan as XANES-only or EXAFS from the k reached above e0.
+    ///
+    /// The limit is ProcessingOptions::exafs_min_kmax. e0 is taken from the spectrum or its
+    /// normalization, and searched for if neither is set.
+    pub fn scan_type(&self) -> Result<ScanType, Box<dyn Error>> {
+        let energy = self.energy.as_ref().ok_or(XAFSError::NotEnoughData)?;
+        let mu = self.mu.as_ref().ok_or(XAFSError::NotEnoughData)?;
+
+        let e0 = match self
+            .get_e0()
+            .or_else(|| self.normalization.as_ref()?.get_e0())
+        {
+            Some(e0) => e0,
+            None => self
+                .processing_options
+                .find_e0(energy.clone(), mu.clone())?,
+        };
+
+        let exafs_min_kmax = self
+            .processing_options
+            .exafs_min_kmax
+            .or(ProcessingOptions::default().exafs_min_kmax)
+            .unwrap();
+        let emax = xafsutils::relative_energy(e0, energy.max());
+        let kmax = (xafsutils::constants::ETOK * emax.max(0.0)).sqrt();
+
+        if kmax >= exafs_min_kmax {
+            Ok(ScanType::Exafs)
+        } else {
+            Ok(ScanType::Xanes)
+        }
+    }
+
+    /// Whether the scan is classified as XANES-only. Spectra which can not be classified are not.
+    pub fn is_xanes_only(&self) -> bool {
+        matches!(self.scan_type(), Ok(ScanType::Xanes))
+    }
+
+    /// Find e0 with the find_e0 options of the processing options.
     pub fn find_e0(&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        self.e0 = Some(xafsutils::find_e0(
-            self.energy.clone().unwrap(),
-            self.mu.clone().unwrap(),
-        )?);
+        let (energy, mu) = self.checked_spectrum()?;
+        let estimate = self.processing_options.find_e0_estimate(energy, mu)?;
+        self.e0 = Some(estimate.e0);
+        self.e0_uncertainty = Some(estimate.uncertainty);
 
         Ok(self)
     }
 
+    /// Remove a slow multiplicative drift of i0 from mu(E).
+    ///
+    /// A polynomial of `polynomial_order` in (E - e0) is fitted to the pre-edge region and mu is
+    /// divided by the trend normalized to its value at e0. This is meant for signals proportional
+    /// to 1/i0 (fluorescence, electron yield) and should be called before normalization.
+    /// The pre-edge range is taken from the PrePostEdge parameters if present, otherwise defaults are used.
+    /// If the trend is not positive over energy and raw_energy, DriftCorrectionFailed is returned
+    /// and neither mu nor raw_mu is changed.
+    pub fn correct_i0_drift(
+        &mut self,
+        polynomial_order: usize,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let energy = self.energy.clone().ok_or(XAFSError::NotEnoughData)?;
+        let mu = self.mu.clone().ok_or(XAFSError::NotEnoughData)?;
+
+        if self.e0.is_none() {
+            self.find_e0()?;
+        }
+        let e0 = self.e0.unwrap();
+
+        let mut pre_post_edge = match &self.normalization {
+            Some(normalization::NormalizationMethod::PrePostEdge(p)) => p.clone(),
+            _ => normalization::PrePostEdge::new(),
+        };
+        pre_post_edge.set_e0(Some(e0));
+        pre_post_edge.fill_parameter(&energy, &mu)?;
+
+        let (pre_edge_start, pre_edge_end) = pre_post_edge.get_pre_edge_range().unwrap();
+
+        let (x, y): (Vec<f64>, Vec<f64>) = energy
+            .iter()
+            .zip(mu.iter())
+            .filter(|(e, m)| **e >= pre_edge_start && **e <= pre_edge_end && m.is_finite())
+            .map(|(e, m)| (xafsutils::relative_energy(e0, *e), *m))
+            .unzip();
+
+        if x.len() <= polynomial_order + 1 {
+            return Err(Box::new(XAFSError::NotEnoughData));
+        }
+
+        let coefficients = polyfit_rs::polyfit_rs::polyfit(&x, &y, polynomial_order)?;
+        let trend = |e: f64| -> f64 {
+            coefficients
+                .iter()
+                .rev()
+                .fold(0.0, |acc, c| acc * xafsutils::relative_energy(e0, e) + c)
+        };
+
+        let trend_e0 = trend(e0);
+        let drift = energy.mapv(|e| trend(e) / trend_e0);
+
+        // raw_energy may extend beyond the range of energy, so its drift is checked as well
+        // before anything is changed
+        let raw_drift = self
+            .raw_energy
+            .as_ref()
+            .map(|raw_energy| raw_energy.mapv(|e| trend(e) / trend_e0));
+        let drift_is_valid = |d: &f64| *d > 0.0 && d.is_finite();
+        if !trend_e0.is_normal()
+            || !drift.iter().all(drift_is_valid)
+            || raw_drift.iter().flatten().any(|d| !drift_is_valid(d))
+        {
+            return Err(Box::new(XAFSError::DriftCorrectionFailed));
+        }
+
+        self.mu = Some(&mu / &drift);
+        if let (Some(raw_mu), Some(raw_drift)) = (self.raw_mu.as_ref(), raw_drift) {
+            self.raw_mu = Some(raw_mu / &raw_drift);
+        }
+
+        Ok(self)
+    }
+
+    /// find_energy_step_report of raw_energy with the processing options. raw_energy is sorted,
+    /// so that negative steps of the acquisition are only reported by the loaders, which add
+    /// the anomalies of the energy column to the notes.
+    pub fn energy_step_report(&self) -> Result<xafsutils::EnergyStepReport, Box<dyn Error>> {
+        let energy = self.raw_energy.clone().ok_or(XAFSError::NotEnoughData)?;
+        Ok(self.processing_options.find_energy_step_report(energy))
+    }
+
     fn find_energy_step(&mut self, frac_ignore: Option<f64>, nave: Option<usize>) -> f64 {
         let energy = self.energy.clone().unwrap();
-        xafsutils::find_energy_step(energy, frac_ignore, nave, None)
+        xafsutils::find_energy_step(
+            energy,
+            frac_ignore.or(self.processing_options.energy_step_frac_ignore),
+            nave.or(self.processing_options.energy_step_nave),
+            None,
+        )
     }
 
     pub fn set_normalization_method(
@@ -172,14 +686,31 @@ impl XASSpectrum {
             self.set_normalization_method(None)?;
         }
 
-        let energy = self.energy.clone().unwrap();
-        let mu = self.mu.clone().unwrap();
+        let (energy, mu) = self.checked_spectrum()?;
+        self.keep_previous(Product::Normalization);
+
+        // The normalization finds e0 without the find_e0 options of the spectrum.
+        if self.processing_options.find_e0.is_active()
+            && self.normalization.as_ref().unwrap().get_e0().is_none()
+        {
+            let e0 = self
+                .processing_options
+                .find_e0(energy.clone(), mu.clone())?;
+            self.normalization.as_mut().unwrap().set_e0(Some(e0));
+        }
 
         self.normalization
             .as_mut()
             .unwrap()
             .normalize(&energy, &mu)?;
 
+        if let Some(delta_mu) = self.delta_mu.as_ref() {
+            self.normalization
+                .as_mut()
+                .unwrap()
+                .propagate_std(delta_mu)?;
+        }
+
         Ok(self)
     }
 
@@ -202,13 +733,36 @@ impl XASSpectrum {
             self.set_background_method(None)?;
         }
 
-        let energy = self.energy.clone().unwrap();
-        let mu = self.mu.clone().unwrap();
+        let (energy, mu) = self.checked_spectrum()?;
+
+        if self.delta_mu.is_some() && self.normalization.is_none() {
+            self.normalize()?;
+        }
+
+        self.keep_previous(Product::Background);
 
         self.background
             .as_mut()
             .unwrap()
-            .calc_background(&energy, &mu, &mut self.normalization)?;
+            .calc_background_with_tolerance(
+                &energy,
+                &mu,
+                &mut self.normalization,
+                self.processing_options.dup_tolerance(),
+            )?;
+
+        if let Some(delta_mu) = self.delta_mu.as_ref() {
+            let edge_step = self
+                .normalization
+                .as_ref()
+                .and_then(|n| n.get_edge_step())
+                .ok_or(XAFSError::NotEnoughData)?;
+
+            self.background
+                .as_mut()
+                .unwrap()
+                .propagate_std(&energy, delta_mu, edge_step)?;
+        }
 
         Ok(self)
     }
@@ -224,11 +778,13 @@ impl XASSpectrum {
 
         let k = k.unwrap();
         let chi = chi.unwrap();
+        self.processing_options.check_finite(&k, &chi)?;
 
         if self.xftf.is_none() {
             self.xftf = Some(xrayfft::XrayFFTF::new());
         }
 
+        self.keep_previous(Product::Xftf);
         self.xftf.as_mut().unwrap().xftf(k.view(), chi.view());
 
         Ok(self)
@@ -240,22 +796,21 @@ impl XASSpectrum {
             todo!("Implement Error Type")
         }
 
-        let r = self.xftf.as_ref().unwrap().get_r();
-        let chi_r = self.xftf.as_ref().unwrap().get_chir();
-
-        if r.is_none() || chi_r.is_none() {
+        let xftf = self.xftf.as_ref().unwrap();
+        if xftf.get_r().is_none() || xftf.get_chir().is_none() {
             panic!("Need to calculate r and chi_r first, Error type");
             todo!("Implement Error type");
         }
 
-        let r = r.unwrap();
-        let chi_r = chi_r.unwrap();
-
         if self.xftr.is_none() {
             self.xftr = Some(xrayfft::XrayFFTR::new());
         }
 
-        self.xftr.as_mut().unwrap().xftr(r.view(), chi_r);
+        self.keep_previous(Product::Xftr);
+
+        let r = self.xftf.as_ref().unwrap().get_r().unwrap();
+        let chi_r = self.xftf.as_ref().unwrap().get_chir().unwrap();
+        self.xftr.as_mut().unwrap().try_xftr(r.view(), chi_r)?;
 
         Ok(self)
     }
@@ -264,6 +819,10 @@ impl XASSpectrum {
         self.e0
     }
 
+    pub fn get_e0_uncertainty(&self) -> Option<f64> {
+        self.e0_uncertainty
+    }
+
     pub fn get_k(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         self.background.as_ref()?.get_k()
     }
@@ -272,6 +831,45 @@ impl XASSpectrum {
         self.background.as_ref()?.get_chi()
     }
 
+    /// Energy and normalized mu, None if the spectrum is not normalized on its energy grid
+    pub fn get_normalized_spectrum(
+        &self,
+    ) -> Option<(
+        ArrayBase<OwnedRepr<f64>, Ix1>,
+        ArrayBase<OwnedRepr<f64>, Ix1>,
+    )> {
+        let energy = self.energy.as_ref()?;
+        let norm = self.normalization.as_ref()?.get_norm()?;
+
+        (energy.len() == norm.len()).then(|| (energy.clone(), norm.clone()))
+    }
+
+    pub fn get_delta_mu(&self) -> Option<ArrayBase<ViewRepr<&f64>, Ix1>> {
+        Some(self.delta_mu.as_ref()?.view())
+    }
+
+    pub fn get_delta_norm(&self) -> Option<&ArrayBase<OwnedRepr<f64>, Ix1>> {
+        self.normalization.as_ref()?.get_delta_norm()
+    }
+
+    pub fn get_delta_flat(&self) -> Option<&ArrayBase<OwnedRepr<f64>, Ix1>> {
+        self.normalization.as_ref()?.get_delta_flat()
+    }
+
+    pub fn get_delta_chi(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        self.background.as_ref()?.get_delta_chi()
+    }
+
+    /// Approximate uncertainty band of the background, see AUTOBK::delta_bkg.
+    pub fn get_delta_bkg(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
+        self.background.as_ref()?.get_delta_bkg()
+    }
+
+    /// Iteration trace of the background fit, see AUTOBK::trace.
+    pub fn get_fit_trace(&self) -> Option<&LMTrace> {
+        self.background.as_ref()?.get_fit_trace()
+    }
+
     pub fn get_kweight(&self) -> Option<&f64> {
         self.xftf.as_ref()?.get_kweight()
     }
@@ -300,6 +898,10 @@ impl XASSpectrum {
         self.xftf.as_ref()?.get_chir_imag()
     }
 
+    pub fn get_chir_complex(&self) -> Option<ArrayBase<OwnedRepr<Complex64>, Ix1>> {
+        self.xftf.as_ref()?.get_chir_complex()
+    }
+
     pub fn get_r(&self) -> Option<ArrayBase<ViewRepr<&f64>, Ix1>> {
         self.xftf.as_ref()?.get_r()
     }
@@ -311,12 +913,24 @@ impl XASSpectrum {
     pub fn get_chiq(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         self.xftr.as_ref()?.get_chiq()
     }
-}
 
-pub enum XAFSError {
-    NotEnoughData,
-    NotEnoughDataForXFTF,
-    NotEnoughDataForXFTR,
+    /// chiq sampled on the k grid of chi(k) (see XrayFFTR::xftr_at), for residuals between the
+    /// Fourier-filtered and the raw data. The parameters of the back transform are taken from
+    /// xftr, or the defaults if it is not set; the spectrum is not modified.
+    pub fn get_chiq_on_k(&self) -> Result<ArrayBase<OwnedRepr<f64>, Ix1>, Box<dyn Error>> {
+        let k = self.get_k().ok_or(XAFSError::NotEnoughData)?;
+        let xftf = self.xftf.as_ref().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+        let r = xftf.get_r().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+        let chir = xftf.get_chir().ok_or(XAFSError::NotEnoughDataForXFTF)?;
+
+        let mut xftr = self
+            .xftr
+            .as_ref()
+            .map(|xftr| xftr.copy_parameters())
+            .unwrap_or_default();
+
+        xftr.xftr_at(r, chir, &k)
+    }
 }
 
 // Simple unit tests for this file.
@@ -330,6 +944,7 @@ pub mod tests {
     use crate::xafs::tests::TEST_TOL;
     use crate::xafs::tests::TEST_TOL_LESS_ACC;
     use crate::xafs::tests::TOP_DIR;
+    use crate::xafs::xasgroup::XASGroup;
     use data_reader::reader::{load_txt_f64, Delimiter, ReaderParams};
     use ndarray::{Array1, ArrayBase, Ix1, OwnedRepr};
 
@@ -373,6 +988,37 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_from_arrays() -> Result<(), Box<dyn std::error::Error>> {
+        let energy = vec![3.0, 1.0, 2.0];
+        let spectrum = XASSpectrum::from_arrays(&energy, [6.0, 4.0, 5.0])?;
+        assert_eq!(
+            spectrum.raw_energy,
+            Some(Array1::from_vec(vec![1.0, 2.0, 3.0]))
+        );
+        assert_eq!(spectrum.mu, Some(Array1::from_vec(vec![4.0, 5.0, 6.0])));
+        assert!(spectrum.get_content_hash().is_some());
+
+        let mu = Array1::from_vec(vec![4.0, 5.0, 6.0]);
+        let spectrum = XASSpectrum::from_arrays_with_metadata(
+            &energy[..],
+            mu.view(),
+            Some("Cu foil"),
+            BTreeMap::from([("temperature".to_string(), serde_json::json!(300.0))]),
+        )?;
+        assert_eq!(spectrum.name.as_deref(), Some("Cu foil"));
+        assert_eq!(
+            spectrum.get_metadata("temperature"),
+            Some(&serde_json::json!(300.0))
+        );
+
+        assert!(XASSpectrum::from_arrays(energy.clone(), vec![1.0, 2.0]).is_err());
+        assert!(XASSpectrum::from_arrays(vec![1.0, f64::NAN], vec![1.0, 2.0]).is_err());
+        assert!(XASSpectrum::from_arrays(Vec::new(), Vec::new()).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_xafs_group_normalization() {
         let test_file = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
@@ -397,4 +1043,263 @@ pub mod tests {
             .zip(expected_norm.iter())
             .for_each(|(x, y)| assert_abs_diff_eq!(x, y, epsilon = TEST_TOL_LESS_ACC));
     }
+
+    #[test]
+    fn test_delta_mu_propagation() -> Result<(), Box<dyn std::error::Error>> {
+        let test_file = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_group = io::load_spectrum_QAS_trans(&test_file).unwrap();
+
+        let npts = xafs_group.mu.as_ref().unwrap().len();
+        assert!(xafs_group.set_delta_mu(Array1::zeros(npts + 1)).is_err());
+
+        xafs_group.set_delta_mu(Array1::from_elem(npts, 2e-3))?;
+        xafs_group.calc_background()?;
+
+        let edge_step = xafs_group
+            .normalization
+            .as_ref()
+            .unwrap()
+            .get_edge_step()
+            .unwrap();
+
+        let delta_norm = xafs_group.get_delta_norm().unwrap();
+        let delta_chi = xafs_group.get_delta_chi().unwrap();
+
+        assert_eq!(delta_norm.len(), npts);
+        assert_eq!(delta_chi.len(), xafs_group.get_k().unwrap().len());
+        delta_chi
+            .iter()
+            .for_each(|x| assert_abs_diff_eq!(*x, 2e-3 / edge_step, epsilon = TEST_TOL));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_correct_i0_drift() -> Result<(), Box<dyn std::error::Error>> {
+        let e0: f64 = 8979.0;
+        let energy: Array1<f64> = Array1::range(8800.0, 9600.0, 0.5);
+        let mu_true = energy.mapv(|e| 0.5 + 0.5 * (1.0 + ((e - e0) / 2.0).tanh()));
+        let drift = energy.mapv(|e| 1.0 + 2e-4 * (e - e0) + 1e-7 * (e - e0).powi(2));
+
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy.clone(), &mu_true * &drift);
+        spectrum.set_e0(e0);
+        spectrum.set_normalization_method(Some(
+            normalization::NormalizationMethod::PrePostEdge(normalization::PrePostEdge {
+                pre_edge_start: Some(-170.0),
+                pre_edge_end: Some(-60.0),
+                ..normalization::PrePostEdge::new()
+            }),
+        ))?;
+
+        spectrum.correct_i0_drift(2)?;
+
+        spectrum
+            .mu
+            .as_ref()
+            .unwrap()
+            .iter()
+            .zip(mu_true.iter())
+            .for_each(|(x, y)| assert_abs_diff_eq!(x, y, epsilon = TEST_TOL_LESS_ACC));
+
+        // The trend crosses zero below 8479 eV, which is only covered by the raw data
+        let drift = energy.mapv(|e| 1.0 + 2e-3 * (e - e0));
+        let mut spectrum = XASSpectrum::new();
+        spectrum.set_spectrum(energy.clone(), &mu_true * &drift);
+        spectrum.set_e0(e0);
+        let mu = spectrum.mu.clone();
+        let mut raw_energy = spectrum.raw_energy.clone().unwrap().to_vec();
+        let mut raw_mu = spectrum.raw_mu.clone().unwrap().to_vec();
+        raw_energy.insert(0, 8400.0);
+        raw_mu.insert(0, 0.1);
+        spectrum.raw_energy = Some(Array1::from_vec(raw_energy));
+        spectrum.raw_mu = Some(Array1::from_vec(raw_mu));
+
+        assert!(spectrum.correct_i0_drift(1).is_err());
+        assert_eq!(spectrum.mu, mu);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_parameters_from() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut template = io::load_spectrum_QAS_trans(&path)?;
+        let mut spectrum = template.clone();
+
+        template.set_background_method(Some(background::BackgroundMethod::AUTOBK(
+            background::AUTOBK {
+                rbkg: Some(1.2),
+                kweight: Some(2),
+                ..background::AUTOBK::new()
+            },
+        )))?;
+        template.normalize()?.calc_background()?.fft()?;
+
+        spectrum.copy_parameters_from(&template);
+
+        match spectrum.background.as_ref().unwrap() {
+            background::BackgroundMethod::AUTOBK(autobk) => {
+                assert_eq!(autobk.rbkg, Some(1.2));
+                assert_eq!(autobk.kweight, Some(2));
+                assert!(autobk.ek0.is_none());
+                assert!(autobk.chi.is_none());
+            }
+            _ => panic!("background method was not copied"),
+        }
+        assert!(spectrum.get_chir_mag().is_none());
+        assert!(spectrum.normalization.as_ref().unwrap().get_e0().is_none());
+
+        spectrum.normalize()?.calc_background()?.fft()?;
+
+        spectrum
+            .get_chi()
+            .unwrap()
+            .iter()
+            .zip(template.get_chi().unwrap().iter())
+            .for_each(|(x, y)| assert_abs_diff_eq!(x, y, epsilon = TEST_TOL));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_energy() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+
+        assert!(spectrum.get_relative_energy().is_none());
+
+        spectrum.set_e0(22117.0).normalize()?;
+
+        let relative = spectrum.get_relative_energy().unwrap();
+        let energy = spectrum.energy.as_ref().unwrap();
+        assert_abs_diff_eq!(relative[0], energy[0] - 22117.0, epsilon = TEST_TOL);
+        assert_eq!(spectrum.to_relative_energy(22150.0), Some(33.0));
+        assert_eq!(spectrum.to_absolute_energy(-33.0), Some(22084.0));
+
+        match spectrum.normalization.as_ref().unwrap() {
+            normalization::NormalizationMethod::PrePostEdge(pre_post_edge) => {
+                let (pre_edge_start, _) = pre_post_edge.get_pre_edge_range().unwrap();
+                assert_abs_diff_eq!(
+                    pre_edge_start,
+                    pre_post_edge.get_e0().unwrap() + pre_post_edge.get_pre_edge_start().unwrap(),
+                    epsilon = TEST_TOL
+                );
+            }
+            _ => panic!("unexpected normalization method"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebin() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        spectrum.find_e0()?;
+        let e0 = spectrum.get_e0().unwrap();
+
+        let mut options = xafsutils::RebinOptions::new();
+        options.set_steps(Some(5.0), Some(1.0), Some(0.1));
+        spectrum.rebin(&options)?;
+
+        let energy = spectrum.energy.clone().unwrap();
+        let mu = spectrum.mu.as_ref().unwrap();
+        assert_eq!(mu.len(), energy.len());
+        assert_eq!(spectrum.delta_mu.as_ref().unwrap().len(), energy.len());
+        assert!(energy.len() < spectrum.raw_energy.as_ref().unwrap().len());
+
+        // Steps of 5 eV below e0 - 30, 1 eV up to e0 + 15 and about 0.1 1/Å in k above, the
+        // EXAFS region being divided into a whole number of steps
+        let step = |e: f64| {
+            let i = energy.iter().position(|x| *x > e).unwrap();
+            energy[i] - energy[i - 1]
+        };
+        assert_abs_diff_eq!(step(e0 - 100.0), 5.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(step(e0), 1.0, epsilon = 1e-9);
+        let k = |e: f64| xafsutils::XAFSUtils::etok(&(e - e0));
+        let i = energy.iter().position(|x| *x > e0 + 300.0).unwrap();
+        assert_abs_diff_eq!(k(energy[i]) - k(energy[i - 1]), 0.1, epsilon = 1e-3);
+
+        // The rebinned spectrum follows the raw data
+        let raw = energy.interpolate(
+            &spectrum.raw_energy.as_ref().unwrap().to_vec(),
+            &spectrum.raw_mu.as_ref().unwrap().to_vec(),
+        )?;
+        let edge_step = raw[raw.len() - 1] - raw[0];
+        assert!((mu - &raw).iter().all(|d| d.abs() < 0.05 * edge_step.abs()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_type() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let spectrum = io::load_spectrum_QAS_trans(&path)?;
+        assert_eq!(spectrum.scan_type()?, ScanType::Exafs);
+
+        let e0 = xafsutils::find_e0(
+            spectrum.energy.clone().unwrap(),
+            spectrum.mu.clone().unwrap(),
+        )?;
+        let (energy, mu): (Vec<f64>, Vec<f64>) = spectrum
+            .energy
+            .as_ref()
+            .unwrap()
+            .iter()
+            .zip(spectrum.mu.as_ref().unwrap().iter())
+            .filter(|(e, _)| **e < e0 + 50.0)
+            .unzip();
+
+        let mut xanes = XASSpectrum::new();
+        xanes.set_spectrum(energy, mu).set_e0(e0);
+        assert_eq!(xanes.scan_type()?, ScanType::Xanes);
+        assert!(xanes.is_xanes_only());
+
+        let mut group = XASGroup::new();
+        group.add_spectrum(spectrum).add_spectrum(xanes);
+        group.normalize()?.calc_background()?.fft()?.ifft()?;
+        assert!(group.spectra[0].get_chiq().is_some());
+        assert!(group.spectra[1].get_k().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chiq_on_k() -> Result<(), Box<dyn Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut spectrum = io::load_spectrum_QAS_trans(&path)?;
+        assert!(spectrum.get_chiq_on_k().is_err());
+
+        spectrum.normalize()?.calc_background()?.fft()?;
+        let chiq_on_k = spectrum.get_chiq_on_k()?;
+        let k = spectrum.get_k().unwrap();
+        assert_eq!(chiq_on_k.len(), k.len());
+
+        spectrum.ifft()?;
+        let q = spectrum.get_q().unwrap().to_owned();
+        let chiq = spectrum.get_chiq().unwrap();
+
+        // The k grid of AUTOBK coincides with the q grid of the FFT
+        let n = q.len().min(k.len());
+        let max_diff = (0..n)
+            .map(|i| {
+                assert_abs_diff_eq!(k[i], q[i], epsilon = TEST_TOL_LESS_ACC);
+                (chiq_on_k[i] - chiq[i]).abs()
+            })
+            .fold(0.0, f64::max);
+        assert!(max_diff < TEST_TOL_LESS_ACC);
+
+        // Between the grid points the sum interpolates smoothly
+        let halfway = Array1::from_iter((0..n - 1).map(|i| 0.5 * (q[i] + q[i + 1])));
+        let mut xftr = spectrum.xftr.as_ref().unwrap().copy_parameters();
+        let xftf = spectrum.xftf.as_ref().unwrap();
+        let between = xftr.xftr_at(xftf.get_r().unwrap(), xftf.get_chir().unwrap(), &halfway)?;
+        let scale = chiq.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        assert!(
+            (0..n - 1).all(|i| (between[i] - 0.5 * (chiq[i] + chiq[i + 1])).abs() < 0.1 * scale)
+        );
+
+        Ok(())
+    }
 }
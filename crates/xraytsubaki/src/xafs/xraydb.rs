@@ -0,0 +1,324 @@
+//! Access to a local xraydb SQLite database (feature "xraydb").
+//!
+//! xraydb (<https://github.com/xraypy/XrayDB>) bundles the Elam cross sections, the Chantler
+//! form factors and the X-ray edge energies of the elements in a single SQLite file. XrayDB
+//! implements CrossSectionTable, so that mu0 and the other users of tabulated cross sections
+//! can use the published tables instead of user supplied ones.
+
+use std::error::Error;
+use std::path::Path;
+
+use ndarray::Array1;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::crosssection::CrossSectionTable;
+
+/// Part of the Elam cross section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ElamKind {
+    Photo,
+    Coherent,
+    Incoherent,
+    /// Sum of the photoabsorption and the coherent and incoherent scattering
+    Total,
+}
+
+/// X-ray absorption edge of an element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XrayEdge {
+    /// Edge energy in eV
+    pub energy: f64,
+    pub fluorescence_yield: f64,
+    pub jump_ratio: f64,
+}
+
+/// Read-only connection to an xraydb SQLite file
+#[derive(Debug)]
+pub struct XrayDB {
+    connection: Connection,
+}
+
+impl XrayDB {
+    /// Open the xraydb SQLite file at `path` read-only.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<XrayDB, Box<dyn Error>> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        Ok(XrayDB { connection })
+    }
+
+    pub fn atomic_number(&self, symbol: &str) -> Result<u32, Box<dyn Error>> {
+        self.element_value(symbol, "atomic_number")
+    }
+
+    /// Molar mass in g/mol
+    pub fn molar_mass(&self, symbol: &str) -> Result<f64, Box<dyn Error>> {
+        self.element_value(symbol, "molar_mass")
+    }
+
+    /// Density of the pure element in g/cm^3
+    pub fn density(&self, symbol: &str) -> Result<f64, Box<dyn Error>> {
+        self.element_value(symbol, "density")
+    }
+
+    /// Absorption edge `edge` ("K", "L3", ...) of the element.
+    pub fn xray_edge(&self, symbol: &str, edge: &str) -> Result<XrayEdge, Box<dyn Error>> {
+        self.connection
+            .query_row(
+                "SELECT absorption_edge, fluorescence_yield, jump_ratio FROM xray_levels \
+                 WHERE element = ?1 AND iupac_symbol = ?2",
+                [symbol, edge],
+                |row| {
+                    Ok(XrayEdge {
+                        energy: row.get(0)?,
+                        fluorescence_yield: row.get(1)?,
+                        jump_ratio: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| format!("xraydb: no {} edge for {}", edge, symbol).into())
+    }
+
+    /// Elam mass attenuation coefficient (cm^2/g) at `energy` (eV).
+    ///
+    /// The tables are interpolated with the cubic splines in log-log scale stored in xraydb.
+    pub fn mu_elam(
+        &self,
+        symbol: &str,
+        energy: &Array1<f64>,
+        kind: ElamKind,
+    ) -> Result<Array1<f64>, Box<dyn Error>> {
+        let (table, column) = match kind {
+            ElamKind::Photo => ("photoabsorption", "photoabsorption"),
+            ElamKind::Coherent => ("scattering", "coherent_scatter"),
+            ElamKind::Incoherent => ("scattering", "incoherent_scatter"),
+            ElamKind::Total => {
+                return Ok(self.mu_elam(symbol, energy, ElamKind::Photo)?
+                    + self.mu_elam(symbol, energy, ElamKind::Coherent)?
+                    + self.mu_elam(symbol, energy, ElamKind::Incoherent)?)
+            }
+        };
+
+        let [log_energy, log_mu, spline] = self.json_arrays(
+            &format!(
+                "SELECT log_energy, log_{column}, log_{column}_spline FROM {table} \
+                 WHERE element = ?1"
+            ),
+            symbol,
+        )?;
+
+        Ok(energy.mapv(|e| elam_spline(&log_energy, &log_mu, &spline, e.ln()).exp()))
+    }
+
+    /// Chantler f' (f1 - Z) at `energy` (eV).
+    pub fn f1_chantler(
+        &self,
+        symbol: &str,
+        energy: &Array1<f64>,
+    ) -> Result<Array1<f64>, Box<dyn Error>> {
+        let [table_energy, f1] =
+            self.json_arrays("SELECT energy, f1 FROM Chantler WHERE element = ?1", symbol)?;
+        let z = self.atomic_number(symbol)? as f64;
+
+        Ok(energy.mapv(|e| interpolate(&table_energy, &f1, e) - z))
+    }
+
+    /// Chantler f'' at `energy` (eV).
+    pub fn f2_chantler(
+        &self,
+        symbol: &str,
+        energy: &Array1<f64>,
+    ) -> Result<Array1<f64>, Box<dyn Error>> {
+        let [table_energy, f2] =
+            self.json_arrays("SELECT energy, f2 FROM Chantler WHERE element = ?1", symbol)?;
+
+        Ok(energy.mapv(|e| interpolate(&table_energy, &f2, e)))
+    }
+
+    /// Chantler total mass attenuation coefficient (cm^2/g) at `energy` (eV), interpolated in
+    /// log-log scale.
+    pub fn mu_chantler(
+        &self,
+        symbol: &str,
+        energy: &Array1<f64>,
+    ) -> Result<Array1<f64>, Box<dyn Error>> {
+        let [table_energy, mu] = self.json_arrays(
+            "SELECT energy, mu_total FROM Chantler WHERE element = ?1",
+            symbol,
+        )?;
+        let log_energy = table_energy.iter().map(|e| e.ln()).collect::<Vec<f64>>();
+        let log_mu = mu.iter().map(|m| m.ln()).collect::<Vec<f64>>();
+
+        Ok(energy.mapv(|e| interpolate(&log_energy, &log_mu, e.ln()).exp()))
+    }
+
+    fn element_value<T: rusqlite::types::FromSql>(
+        &self,
+        symbol: &str,
+        column: &str,
+    ) -> Result<T, Box<dyn Error>> {
+        self.connection
+            .query_row(
+                &format!("SELECT {column} FROM elements WHERE element = ?1"),
+                [symbol],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| format!("xraydb: unknown element {}", symbol).into())
+    }
+
+    /// Columns of a row holding arrays stored as JSON text, as done by xraydb.
+    fn json_arrays<const N: usize>(
+        &self,
+        query: &str,
+        symbol: &str,
+    ) -> Result<[Vec<f64>; N], Box<dyn Error>> {
+        let columns = self
+            .connection
+            .query_row(query, [symbol], |row| {
+                (0..N)
+                    .map(|i| row.get::<_, String>(i))
+                    .collect::<Result<Vec<String>, rusqlite::Error>>()
+            })
+            .optional()?
+            .ok_or_else(|| format!("xraydb: no table for {}", symbol))?;
+
+        let arrays = columns
+            .iter()
+            .map(|column| serde_json::from_str::<Vec<f64>>(column))
+            .collect::<Result<Vec<Vec<f64>>, serde_json::Error>>()?;
+
+        if arrays
+            .iter()
+            .any(|a| a.len() != arrays[0].len() || a.len() < 2)
+        {
+            return Err(format!("xraydb: malformed table for {}", symbol).into());
+        }
+
+        Ok(arrays.try_into().unwrap())
+    }
+}
+
+impl CrossSectionTable for XrayDB {
+    fn atomic_mass(&self, symbol: &str) -> Option<f64> {
+        self.molar_mass(symbol).ok()
+    }
+
+    fn mu_elam(&self, symbol: &str, energy: &Array1<f64>) -> Result<Array1<f64>, Box<dyn Error>> {
+        XrayDB::mu_elam(self, symbol, energy, ElamKind::Total)
+    }
+}
+
+/// Index of the lower point of the segment of the sorted `x` containing `x0`, clamped to the
+/// first and last segments.
+fn segment(x: &[f64], x0: f64) -> usize {
+    x.partition_point(|x| *x <= x0).clamp(1, x.len() - 1) - 1
+}
+
+fn interpolate(x: &[f64], y: &[f64], x0: f64) -> f64 {
+    let x0 = x0.clamp(x[0], x[x.len() - 1]);
+    let i = segment(x, x0);
+
+    y[i] + (y[i + 1] - y[i]) * (x0 - x[i]) / (x[i + 1] - x[i])
+}
+
+/// Cubic spline interpolation from the second derivatives `spline`, as done by xraydb.
+fn elam_spline(x: &[f64], y: &[f64], spline: &[f64], x0: f64) -> f64 {
+    let x0 = x0.clamp(x[0], x[x.len() - 1]);
+    let (lo, hi) = (segment(x, x0), segment(x, x0) + 1);
+
+    let h = x[hi] - x[lo];
+    let a = (x[hi] - x0) / h;
+    let b = (x0 - x[lo]) / h;
+
+    a * y[lo]
+        + b * y[hi]
+        + ((a * a * a - a) * spline[lo] + (b * b * b - b) * spline[hi]) * h * h / 6.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xafs::crosssection::mu0;
+    use approx::assert_abs_diff_eq;
+
+    /// Minimal database with the xraydb schema
+    fn database() -> Result<std::path::PathBuf, Box<dyn Error>> {
+        let path =
+            std::env::temp_dir().join(format!("xraytsubaki_test_xraydb_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path)?;
+
+        // mu = 1e6 E^-2 (log-log linear, zero second derivatives)
+        let energy = [1000.0_f64, 10000.0, 100000.0];
+        let log_energy = energy.iter().map(|e| e.ln()).collect::<Vec<f64>>();
+        let log_mu = energy
+            .iter()
+            .map(|e| (1e6 / (e * e)).ln())
+            .collect::<Vec<f64>>();
+        let log_scatter = energy.iter().map(|e| (1e-3 * e).ln()).collect::<Vec<f64>>();
+        let zeros = vec![0.0; 3];
+        let json = |v: &[f64]| serde_json::to_string(v).unwrap();
+
+        connection.execute_batch(
+            "CREATE TABLE elements (atomic_number INTEGER, element TEXT, molar_mass REAL, density REAL);
+             CREATE TABLE xray_levels (id INTEGER, element TEXT, iupac_symbol TEXT, siegbahn_symbol TEXT, absorption_edge REAL, fluorescence_yield REAL, jump_ratio REAL);
+             CREATE TABLE photoabsorption (id INTEGER, element TEXT, log_energy TEXT, log_photoabsorption TEXT, log_photoabsorption_spline TEXT);
+             CREATE TABLE scattering (id INTEGER, element TEXT, log_energy TEXT, log_coherent_scatter TEXT, log_coherent_scatter_spline TEXT, log_incoherent_scatter TEXT, log_incoherent_scatter_spline TEXT);
+             CREATE TABLE Chantler (id INTEGER, element TEXT, energy TEXT, f1 TEXT, f2 TEXT, mu_total TEXT);
+             INSERT INTO elements VALUES (26, 'Fe', 55.845, 7.86);
+             INSERT INTO xray_levels VALUES (1, 'Fe', 'K', 'K', 7112.0, 0.35, 8.1);",
+        )?;
+        connection.execute(
+            "INSERT INTO photoabsorption VALUES (1, 'Fe', ?1, ?2, ?3)",
+            [json(&log_energy), json(&log_mu), json(&zeros)],
+        )?;
+        connection.execute(
+            "INSERT INTO scattering VALUES (1, 'Fe', ?1, ?2, ?3, ?2, ?3)",
+            [json(&log_energy), json(&log_scatter), json(&zeros)],
+        )?;
+        connection.execute(
+            "INSERT INTO Chantler VALUES (1, 'Fe', ?1, ?2, ?3, ?4)",
+            [
+                json(&energy),
+                json(&[25.0, 26.0, 27.0]),
+                json(&[4.0, 3.0, 2.0]),
+                json(&[1.0, 0.01, 0.0001]),
+            ],
+        )?;
+
+        Ok(path)
+    }
+
+    #[test]
+    fn test_xraydb() -> Result<(), Box<dyn Error>> {
+        let path = database()?;
+        let db = XrayDB::open(&path)?;
+        let energy = Array1::from_vec(vec![2000.0, 5000.0, 10000.0]);
+
+        assert_eq!(db.atomic_number("Fe")?, 26);
+        assert_eq!(db.xray_edge("Fe", "K")?.energy, 7112.0);
+        assert!(db.xray_edge("Fe", "L3").is_err());
+        assert!(db.molar_mass("Xx").is_err());
+
+        let photo = db.mu_elam("Fe", &energy, ElamKind::Photo)?;
+        let total = db.mu_elam("Fe", &energy, ElamKind::Total)?;
+        for (i, e) in energy.iter().enumerate() {
+            assert_abs_diff_eq!(photo[i], 1e6 / (e * e), epsilon = 1e-9);
+            assert_abs_diff_eq!(total[i], 1e6 / (e * e) + 2e-3 * e, epsilon = 1e-9);
+        }
+
+        assert_abs_diff_eq!(db.f1_chantler("Fe", &energy)?[2], 0.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(db.f2_chantler("Fe", &energy)?[2], 3.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(db.mu_chantler("Fe", &energy)?[2], 0.01, epsilon = 1e-12);
+
+        let mu = mu0(&db, "Fe", &energy, Some(7.86))?;
+        assert_abs_diff_eq!(mu[0], total[0] * 7.86, epsilon = 1e-9);
+
+        drop(db);
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+}
@@ -1,13 +1,17 @@
 // Standard library dependencies
+use std::collections::HashMap;
+use std::sync::RwLock;
 
 // External dependencies
 use easyfft::prelude::{DynRealFft, DynRealIfft};
 use easyfft::{dyn_size::realfft::DynRealDft, num_complex::Complex};
 use nalgebra::{DVector, Owned};
 use ndarray::{
-    Array, Array1, ArrayBase, ArrayView, ArrayView1, Axis, Ix, Ix1, OwnedRepr, ViewRepr,
+    Array, Array1, Array2, ArrayBase, ArrayView, ArrayView1, ArrayView2, Axis, Ix, Ix1, OwnedRepr,
+    ViewRepr,
 };
 use num_complex::Complex64;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use derivative::Derivative;
@@ -16,7 +20,10 @@ use derivative::Derivative;
 
 // Load local traits
 use super::mathutils::MathUtils;
+use super::nshare::ToNdarray1;
+use super::units::{Angstrom, InverseAngstrom};
 use super::xafsutils::ftwindow;
+use crate::xafs::config::UserConfig;
 use crate::xafs::xafsutils::FTWindow;
 
 #[derive(Derivative, Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +72,41 @@ impl XrayFFTF {
         XrayFFTF::default()
     }
 
+    pub fn set_k_range<K: Into<InverseAngstrom>>(&mut self, kmin: K, kmax: K) -> &mut Self {
+        self.kmin = Some(kmin.into().value());
+        self.kmax = Some(kmax.into().value());
+        self
+    }
+
+    /// Copy of the transform parameters without the results.
+    pub fn copy_parameters(&self) -> XrayFFTF {
+        XrayFFTF {
+            r: None,
+            chir: None,
+            chir_mag: None,
+            kwin: None,
+            ..self.clone()
+        }
+    }
+
+    /// Default parameters with the window and kweight of the user configuration, where it
+    /// sets them.
+    pub fn from_config(config: &UserConfig) -> XrayFFTF {
+        let default = XrayFFTF::default();
+        XrayFFTF {
+            window: config.window.or(default.window),
+            kweight: config.kweight.or(default.kweight),
+            ..default
+        }
+    }
+
+    /// Take the window and kweight that are not set from the user configuration.
+    pub fn fill_from_config(&mut self, config: &UserConfig) -> &mut Self {
+        self.window = self.window.or(config.window);
+        self.kweight = self.kweight.or(config.kweight);
+        self
+    }
+
     pub fn fill_parameter(&mut self, k: ArrayBase<ViewRepr<&f64>, Ix1>) -> &mut Self {
         if self.kweight.is_none() {
             self.kweight = Some(2.0);
@@ -114,25 +156,36 @@ impl XrayFFTF {
         ),
         Box<dyn std::error::Error>,
     > {
+        let (k_, win, npts) = self.xftf_grid(k)?;
+        let chi_ = kweighted_chi(&k_, npts, self.kweight.unwrap() as i32, k, chi)?;
+
+        Ok((chi_, win))
+    }
+
+    // Uniform k grid of the transform, the window on it, and the number of points covered by
+    // the data
+    fn xftf_grid(
+        &mut self,
+        k: ArrayBase<ViewRepr<&f64>, Ix1>,
+    ) -> Result<(Array1<f64>, Array1<f64>, usize), Box<dyn std::error::Error>> {
         self.fill_parameter(k);
-        let kweight = self.kweight.unwrap() as i32;
         let k_max = k.iter().max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
         let npts = (1.01 + k_max / self.kstep.unwrap()) as usize;
         let k_max = k_max.max(self.kmax.unwrap() + self.dk2.unwrap());
         let k_ = Array1::range(0.0, k_max + self.kstep.unwrap(), self.kstep.unwrap());
 
-        let chi_ = k_.interpolate(&k.to_vec(), &chi.to_vec())?;
-        let win = self
-            .window
-            .unwrap()
-            .window(&k_, self.kmin, self.kmax, self.dk, self.dk2)?;
+        let win = ftwindow_cached(&k_, self.kmin, self.kmax, self.dk, self.dk2, self.window)?;
         let win = (win).slice_axis(Axis(0), (0..npts).into()).to_owned();
-        let chi_ = &chi_.slice_axis(Axis(0), (0..npts).into())
-            * &k_
-                .slice_axis(Axis(0), (0..npts).into())
-                .map(|x| x.powi(kweight));
 
-        Ok((chi_, win))
+        Ok((k_, win, npts))
+    }
+
+    // Number of points of r up to rmax_out
+    fn irmax(&self) -> usize {
+        let rstep = std::f64::consts::PI / self.kstep.unwrap() / self.nfft.unwrap() as f64;
+
+        // The length of r is different by 1 between xraylarch and xraytsubaki. This is due to the implementation of FFT.
+        (self.nfft.unwrap() / 2 + 1).min((1.01 + self.rmax_out.unwrap() / rstep) as usize)
     }
 
     pub fn xftf(
@@ -145,10 +198,7 @@ impl XrayFFTF {
         let cchi_fft = xftf_fast(cchi.view(), self.nfft.unwrap(), self.kstep.unwrap());
 
         let rstep = std::f64::consts::PI / self.kstep.unwrap() / self.nfft.unwrap() as f64;
-
-        // The length of r is different by 1 between xraylarch and xraytsubaki. This is due to the implementation of FFT.
-        let irmax =
-            (self.nfft.unwrap() / 2 + 1).min((1.01 + self.rmax_out.unwrap() / rstep) as usize);
+        let irmax = self.irmax();
 
         self.r = Some(Array1::range(0.0, irmax as f64 * rstep, rstep));
 
@@ -159,6 +209,58 @@ impl XrayFFTF {
         self
     }
 
+    /// Forward transform of many chi(k) sharing the k grid `k`, one per row of `chi`.
+    ///
+    /// Returns r and |chi(R)| with one row per row of `chi`, each equal to the chir_mag that
+    /// xftf gives for that row. The grid and the window are set up once for the whole batch
+    /// and the rows are transformed in parallel, which avoids the overhead of one XASSpectrum
+    /// per scan for the thousands of chi(k) of a QEXAFS run. self is filled in like by xftf,
+    /// and r and kwin are stored, but chir and chir_mag are left untouched.
+    ///
+    /// This runs on the CPU only, one rayon task per row. There is no GPU backend and no
+    /// feature flag for one yet; the rows are not transformed as a single matrix either.
+    pub fn xftf_batch(
+        &mut self,
+        k: ArrayBase<ViewRepr<&f64>, Ix1>,
+        chi: ArrayView2<f64>,
+    ) -> Result<(Array1<f64>, Array2<f64>), Box<dyn std::error::Error>> {
+        if chi.ncols() != k.len() {
+            return Err(format!(
+                "chi has {} columns but k has {} points",
+                chi.ncols(),
+                k.len()
+            )
+            .into());
+        }
+
+        let (k_, win, npts) = self.xftf_grid(k)?;
+        let (nfft, kstep) = (self.nfft.unwrap(), self.kstep.unwrap());
+        let kweight = self.kweight.unwrap() as i32;
+        let irmax = self.irmax();
+        let rstep = std::f64::consts::PI / kstep / nfft as f64;
+
+        let rows = (0..chi.nrows())
+            .into_par_iter()
+            .map(|i| {
+                let cchi =
+                    kweighted_chi(&k_, npts, kweight, k, chi.row(i)).map_err(|e| e.to_string())?;
+                let chir_mag: Array1<f64> = xftf_fast(cchi.view(), nfft, kstep)[0..irmax].norm();
+                Ok(chir_mag)
+            })
+            .collect::<Result<Vec<Array1<f64>>, String>>()?;
+
+        let mut chir_mag = Array2::zeros((rows.len(), irmax));
+        for (mut row, values) in chir_mag.outer_iter_mut().zip(rows.iter()) {
+            row.assign(values);
+        }
+
+        let r = Array1::range(0.0, irmax as f64 * rstep, rstep);
+        self.r = Some(r.clone());
+        self.kwin = Some(win);
+
+        Ok((r, chir_mag))
+    }
+
     pub fn get_rmax_out(&self) -> Option<&f64> {
         self.rmax_out.as_ref()
     }
@@ -206,7 +308,15 @@ impl XrayFFTF {
     pub fn get_chir_imag(&self) -> Option<ArrayBase<OwnedRepr<f64>, Ix1>> {
         let len_r = self.r.as_ref()?.len();
 
-        let chir: Array1<f64> = self.chir.clone()?.re();
+        let chir: Array1<f64> = self.chir.clone()?.im();
+
+        Some(chir.slice_axis(Axis(0), (0..len_r).into()).to_owned())
+    }
+
+    /// chi(R) as complex values on the R grid
+    pub fn get_chir_complex(&self) -> Option<ArrayBase<OwnedRepr<Complex64>, Ix1>> {
+        let len_r = self.r.as_ref()?.len();
+        let chir = self.chir.as_ref()?.into_ndarray1();
 
         Some(chir.slice_axis(Axis(0), (0..len_r).into()).to_owned())
     }
@@ -264,6 +374,31 @@ impl XrayFFTR {
         XrayFFTR::default()
     }
 
+    /// Copy of the transform parameters without the results.
+    pub fn copy_parameters(&self) -> XrayFFTR {
+        XrayFFTR {
+            q: None,
+            chiq: None,
+            rwin: None,
+            ..self.clone()
+        }
+    }
+
+    /// Default parameters with the window of the user configuration, if it sets one.
+    pub fn from_config(config: &UserConfig) -> XrayFFTR {
+        let default = XrayFFTR::default();
+        XrayFFTR {
+            window: config.window.or(default.window),
+            ..default
+        }
+    }
+
+    /// Take the window, if it is not set, from the user configuration.
+    pub fn fill_from_config(&mut self, config: &UserConfig) -> &mut Self {
+        self.window = self.window.or(config.window);
+        self
+    }
+
     pub fn fill_parameter(&mut self, r: ArrayBase<ViewRepr<&f64>, Ix1>) -> &mut Self {
         if self.rweight.is_none() {
             self.rweight = Some(0.0);
@@ -297,6 +432,57 @@ impl XrayFFTR {
         self
     }
 
+    /// Window of the reverse transform, independent of the window of the forward transform.
+    pub fn set_window(&mut self, window: Option<FTWindow>) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    /// Widths of the low-R (`dr`) and high-R (`dr2`) tapers of the window. `dr2` defaults to
+    /// `dr`, so different values give an asymmetric window.
+    pub fn set_dr(&mut self, dr: Option<f64>, dr2: Option<f64>) -> &mut Self {
+        self.dr = dr;
+        self.dr2 = dr2;
+        self
+    }
+
+    pub fn set_r_range(&mut self, rmin: Option<f64>, rmax: Option<f64>) -> &mut Self {
+        self.rmin = rmin;
+        self.rmax = rmax;
+        self
+    }
+
+    /// Set the R window from typed distances; see set_r_range.
+    pub fn set_r_window<R: Into<Angstrom>>(&mut self, rmin: R, rmax: R) -> &mut Self {
+        self.set_r_range(Some(rmin.into().value()), Some(rmax.into().value()))
+    }
+
+    /// Check the window parameters against the R range `[0, r_available]` of chi(R).
+    pub fn validate(&self, r_available: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let (rmin, rmax) = match (self.rmin, self.rmax) {
+            (Some(rmin), Some(rmax)) => (rmin, rmax),
+            _ => return Ok(()),
+        };
+
+        if rmin < 0.0 || rmin >= rmax {
+            return Err(format!("invalid R range for xftr: rmin {} rmax {}", rmin, rmax).into());
+        }
+
+        if rmax > r_available {
+            return Err(format!(
+                "rmax {} of xftr is beyond the available R range (0 - {:.3})",
+                rmax, r_available
+            )
+            .into());
+        }
+
+        if self.dr.unwrap_or(0.0) < 0.0 || self.dr2.unwrap_or(0.0) < 0.0 {
+            return Err("the window tapers dr and dr2 of xftr must not be negative".into());
+        }
+
+        Ok(())
+    }
+
     pub fn xftr_prep(
         &mut self,
         r: ArrayBase<ViewRepr<&f64>, Ix1>,
@@ -308,12 +494,14 @@ impl XrayFFTR {
         let r_len = chir.len();
         let rstep = std::f64::consts::PI / self.kstep.unwrap() / nfft as f64;
 
+        self.validate((r_len - 1) as f64 * rstep)?;
+
         let r_ = Array1::range(0.0, r_len as f64 * rstep, rstep);
 
         let win = if rweight == 0 {
-            ftwindow(&r_, self.rmin, self.rmax, self.dr, self.dr2, self.window)?
+            ftwindow_cached(&r_, self.rmin, self.rmax, self.dr, self.dr2, self.window)?
         } else {
-            ftwindow(&r_, self.rmin, self.rmax, self.dr, self.dr2, self.window)?
+            ftwindow_cached(&r_, self.rmin, self.rmax, self.dr, self.dr2, self.window)?
                 * &r_.map(|x| x.powi(rweight))
         };
 
@@ -329,7 +517,16 @@ impl XrayFFTR {
     }
 
     pub fn xftr(&mut self, r: ArrayBase<ViewRepr<&f64>, Ix1>, chir: &DynRealDft<f64>) -> &mut Self {
-        let (chir_win, win) = self.xftr_prep(r, chir).unwrap();
+        self.try_xftr(r, chir).unwrap()
+    }
+
+    /// xftr returning an error for invalid window parameters instead of panicking.
+    pub fn try_xftr(
+        &mut self,
+        r: ArrayBase<ViewRepr<&f64>, Ix1>,
+        chir: &DynRealDft<f64>,
+    ) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        let (chir_win, win) = self.xftr_prep(r, chir)?;
         let nfft = self.nfft.unwrap();
         let out = xftr_fast(&chir_win, nfft, self.kstep.unwrap());
 
@@ -343,7 +540,47 @@ impl XrayFFTR {
         self.rwin = Some(win);
         self.chiq = Some(out);
 
-        self
+        Ok(self)
+    }
+
+    /// Back-transform of `chir` windowed in R like xftr, evaluated at the given `k` instead of
+    /// the q grid of the FFT.
+    ///
+    /// The inverse FFT is a trigonometric sum over the R bins, which is evaluated directly at
+    /// each k. The result equals chiq at q = n * kstep and interpolates it exactly in between,
+    /// so filtered and raw chi(k) can be compared point by point on the grid of the data. As
+    /// chiq, it is k-weighted and multiplied by the k window of the forward FT.
+    pub fn xftr_at(
+        &mut self,
+        r: ArrayBase<ViewRepr<&f64>, Ix1>,
+        chir: &DynRealDft<f64>,
+        k: &Array1<f64>,
+    ) -> Result<Array1<f64>, Box<dyn std::error::Error>> {
+        let (chir_win, _) = self.xftr_prep(r, chir)?;
+        let nfft = self.nfft.unwrap();
+        let kstep = self.kstep.unwrap();
+        let rstep = std::f64::consts::PI / kstep / nfft as f64;
+
+        // The offset and the Nyquist bin appear once in the sum, the other bins twice
+        let terms = chir_win
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.norm() > 0.0)
+            .map(|(m, c)| {
+                let factor = if m == 0 || 2 * m == nfft { 1.0 } else { 2.0 };
+                (2.0 * m as f64 * rstep, c * factor)
+            })
+            .collect::<Vec<(f64, Complex<f64>)>>();
+
+        let scale = std::f64::consts::PI.sqrt() / kstep / nfft as f64;
+
+        Ok(k.mapv(|k| {
+            scale
+                * terms
+                    .iter()
+                    .map(|(frequency, c)| (c * Complex::from_polar(1.0, frequency * k)).re)
+                    .sum::<f64>()
+        }))
     }
 
     pub fn get_q(&self) -> Option<ArrayBase<ViewRepr<&f64>, Ix1>> {
@@ -382,6 +619,84 @@ impl XrayFFTR {
     }
 }
 
+/// Maximum number of windows kept by ftwindow_cached
+pub const WINDOW_CACHE_SIZE: usize = 64;
+
+// Window parameters and the length and limits of the grid. Floats are keyed by their bits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct WindowKey {
+    window: Option<FTWindow>,
+    params: [Option<u64>; 4],
+    len: usize,
+    first: u64,
+    last: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref WINDOW_CACHE: RwLock<HashMap<WindowKey, Array1<f64>>> = RwLock::new(HashMap::new());
+}
+
+/// ftwindow with memoization, for the uniform grids of the transforms.
+///
+/// The window is looked up by its parameters and the length and limits of `x`, so `x` has to
+/// be uniform: two grids with the same length and limits are treated as the same grid. The
+/// cache is emptied once it holds WINDOW_CACHE_SIZE windows.
+pub fn ftwindow_cached(
+    x: &Array1<f64>,
+    xmin: Option<f64>,
+    xmax: Option<f64>,
+    dx: Option<f64>,
+    dx2: Option<f64>,
+    window: Option<FTWindow>,
+) -> Result<Array1<f64>, Box<dyn std::error::Error>> {
+    if x.is_empty() {
+        return ftwindow(x, xmin, xmax, dx, dx2, window);
+    }
+
+    let key = WindowKey {
+        window,
+        params: [xmin, xmax, dx, dx2].map(|p| p.map(f64::to_bits)),
+        len: x.len(),
+        first: x[0].to_bits(),
+        last: x[x.len() - 1].to_bits(),
+    };
+
+    if let Some(win) = WINDOW_CACHE.read().unwrap().get(&key) {
+        return Ok(win.clone());
+    }
+
+    let win = ftwindow(x, xmin, xmax, dx, dx2, window)?;
+
+    let mut cache = WINDOW_CACHE.write().unwrap();
+    if cache.len() >= WINDOW_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(key, win.clone());
+
+    Ok(win)
+}
+
+/// Empty the cache of ftwindow_cached.
+pub fn clear_window_cache() {
+    WINDOW_CACHE.write().unwrap().clear();
+}
+
+// chi interpolated on the grid of XrayFFTF::xftf_grid and weighted by k^kweight
+fn kweighted_chi(
+    k_: &Array1<f64>,
+    npts: usize,
+    kweight: i32,
+    k: ArrayBase<ViewRepr<&f64>, Ix1>,
+    chi: ArrayBase<ViewRepr<&f64>, Ix1>,
+) -> Result<Array1<f64>, Box<dyn std::error::Error>> {
+    let chi_ = k_.interpolate(&k.to_vec(), &chi.to_vec())?;
+
+    Ok(&chi_.slice_axis(Axis(0), (0..npts).into())
+        * &k_
+            .slice_axis(Axis(0), (0..npts).into())
+            .map(|x| x.powi(kweight)))
+}
+
 pub fn xftf_fast(chi: ArrayBase<ViewRepr<&f64>, Ix1>, nfft: usize, kstep: f64) -> DynRealDft<f64> {
     let mut cchi = vec![0.0 as f64; nfft];
     cchi[..chi.len()].copy_from_slice(&chi.to_vec()[..]);
@@ -440,6 +755,39 @@ pub fn xftr_fast_nalgebra(chir: &DynRealDft<f64>, nfft: usize, kstep: f64) -> DV
     chi
 }
 
+/// Buffers of xftf_fast for repeated transforms of length `nfft`
+///
+/// A fit evaluates the transform of a windowed chi(k) at every step; with the scratch, the
+/// zero padded input and the output are allocated once instead of at every call.
+#[derive(Debug, Clone)]
+pub struct FFTScratch {
+    input: Vec<f64>,
+    output: DynRealDft<f64>,
+}
+
+impl FFTScratch {
+    pub fn new(nfft: usize) -> FFTScratch {
+        FFTScratch {
+            input: vec![0.0; nfft],
+            output: vec![0.0; nfft].real_fft(),
+        }
+    }
+
+    pub fn nfft(&self) -> usize {
+        self.input.len()
+    }
+
+    /// xftf_fast of `chi`, zero padded to nfft. The result is kept until the next call.
+    pub fn xftf<I: IntoIterator<Item = f64>>(&mut self, chi: I, kstep: f64) -> &[Complex<f64>] {
+        self.input.fill(0.0);
+        self.input.iter_mut().zip(chi).for_each(|(x, c)| *x = c);
+        self.input.real_fft_using(&mut self.output);
+        self.output *= kstep / std::f64::consts::PI.sqrt();
+
+        &self.output
+    }
+}
+
 pub trait XFFT {
     fn xftf_fast(&self, nfft: usize, kstep: f64) -> DynRealDft<f64>;
 }
@@ -747,6 +1095,17 @@ mod tests {
         // println!("mse: {}", mse);
         assert!(mse < CHI_MSE_TOL);
 
+        let chir_complex = xafs_test_group.get_chir_complex().unwrap();
+        assert_eq!(chir_complex.len(), r.len());
+        assert_eq!(
+            Some(chir_complex.mapv(|c| c.re)),
+            xafs_test_group.get_chir_real()
+        );
+        assert_eq!(
+            Some(chir_complex.mapv(|c| c.im)),
+            xafs_test_group.get_chir_imag()
+        );
+
         // chir.iter().zip(chir_expected.iter()).for_each(|(x, y)| {
         //     println!("[{}, {}],", x, y);
         // });
@@ -816,4 +1175,120 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_XrayFFTR_window() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        xafs_test_group.calc_background()?;
+
+        xafs_test_group.xftf = Some(XrayFFTF {
+            window: Some(FTWindow::Hanning),
+            dk: Some(1.0),
+            kmin: Some(2.0),
+            kmax: Some(15.0),
+            kweight: Some(2.0),
+            ..Default::default()
+        });
+        xafs_test_group.fft()?;
+
+        // Asymmetric window with a different shape than the forward transform
+        let mut xftr = XrayFFTR::new();
+        xftr.set_window(Some(FTWindow::Welch))
+            .set_dr(Some(0.2), Some(1.0))
+            .set_r_range(Some(1.0), Some(3.0));
+        xafs_test_group.xftr = Some(xftr);
+        xafs_test_group.ifft()?;
+
+        let xftr = xafs_test_group.xftr.as_ref().unwrap();
+        let rwin = xftr.rwin.clone().unwrap();
+        let rstep = std::f64::consts::PI / xftr.get_kstep().unwrap() / 2048.0;
+        let r = Array1::from_iter((0..rwin.len()).map(|i| i as f64 * rstep));
+        let rising = r
+            .iter()
+            .zip(rwin.iter())
+            .filter(|(r, w)| **r < 2.0 && **w > 0.0 && **w < 1.0);
+        let falling = r
+            .iter()
+            .zip(rwin.iter())
+            .filter(|(r, w)| **r > 2.0 && **w > 0.0 && **w < 1.0);
+        assert!(falling.count() > rising.count());
+
+        let mut xftr = XrayFFTR::new();
+        xftr.set_r_range(Some(3.0), Some(1.0));
+        xafs_test_group.xftr = Some(xftr);
+        assert!(xafs_test_group.ifft().is_err());
+
+        let mut xftr = XrayFFTR::new();
+        xftr.set_r_range(Some(1.0), Some(1000.0));
+        xafs_test_group.xftr = Some(xftr);
+        assert!(xafs_test_group.ifft().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xftf_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let path = String::from(TOP_DIR) + "/tests/testfiles/Ru_QAS.dat";
+        let mut xafs_test_group = io::load_spectrum_QAS_trans(&path).unwrap();
+        xafs_test_group.calc_background()?;
+
+        let k = xafs_test_group.get_k().unwrap().to_owned();
+        let chi = xafs_test_group.get_chi().unwrap().to_owned();
+
+        let n = 5;
+        let mut chis = Array2::zeros((n, k.len()));
+        for (i, mut row) in chis.outer_iter_mut().enumerate() {
+            row.assign(&(&chi * (1.0 + 0.1 * i as f64)));
+        }
+
+        let mut xftf = XrayFFTF::new();
+        let (r, chir_mag) = xftf.xftf_batch(k.view(), chis.view())?;
+        assert_eq!(chir_mag.dim(), (n, r.len()));
+
+        for (i, row) in chir_mag.outer_iter().enumerate() {
+            let mut single = XrayFFTF::new();
+            single.xftf(k.view(), chis.row(i));
+            assert_eq!(single.get_r().unwrap(), r);
+            assert_eq!(single.get_chir_mag().unwrap(), row);
+        }
+
+        assert!(xftf
+            .xftf_batch(k.view(), chis.slice(ndarray::s![.., 1..]))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ftwindow_cached() -> Result<(), Box<dyn std::error::Error>> {
+        let k = Array1::range(0.0, 20.0, 0.05);
+
+        for window in [FTWindow::Hanning, FTWindow::KaiserBessel, FTWindow::Welch] {
+            for (kmin, dk) in [(2.0, 1.0), (3.0, 0.5)] {
+                let expected = ftwindow(&k, Some(kmin), Some(15.0), Some(dk), None, Some(window))?;
+                for _ in 0..2 {
+                    let win =
+                        ftwindow_cached(&k, Some(kmin), Some(15.0), Some(dk), None, Some(window))?;
+                    assert_eq!(win, expected);
+                }
+            }
+        }
+
+        // Same parameters on a different grid
+        let r = Array1::range(0.0, 10.0, 0.05);
+        assert_eq!(
+            ftwindow_cached(&r, Some(2.0), Some(15.0), Some(1.0), None, None)?,
+            ftwindow(&r, Some(2.0), Some(15.0), Some(1.0), None, None)?
+        );
+
+        clear_window_cache();
+        assert_eq!(
+            ftwindow_cached(&k, Some(2.0), Some(15.0), Some(1.0), None, None)?,
+            ftwindow(&k, Some(2.0), Some(15.0), Some(1.0), None, None)?
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,39 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use xraytsubaki::xafs::examples;
+
+use crate::xasgroup::PyXASGroup;
+use crate::xasspectrum::PyXASSpectrum;
+
+fn to_pyerr(err: Box<dyn std::error::Error>) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Example spectrum `name`: "ru_k_qas", the measured Ru K-edge spectrum of a Ru foil, or one
+/// of the toy models "synthetic_fe_k", "synthetic_cu_k" and "synthetic_pt_l3", which are not
+/// measurements and must not be used as standards.
+#[pyfunction]
+pub fn example_spectrum(name: &str) -> PyResult<PyXASSpectrum> {
+    let xasspectrum = match name {
+        "ru_k_qas" => examples::ru_k_qas().map_err(to_pyerr)?,
+        "synthetic_fe_k" => examples::synthetic_fe_k().generate(),
+        "synthetic_cu_k" => examples::synthetic_cu_k().generate(),
+        "synthetic_pt_l3" => examples::synthetic_pt_l3().generate(),
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown example spectrum \"{}\"",
+                name
+            )))
+        }
+    };
+
+    Ok(PyXASSpectrum { xasspectrum })
+}
+
+/// The measured Ru spectrum followed by the synthetic Fe, Cu and Pt spectra.
+#[pyfunction]
+pub fn example_group() -> PyResult<PyXASGroup> {
+    Ok(PyXASGroup {
+        xasgroup: examples::example_group().map_err(to_pyerr)?,
+    })
+}
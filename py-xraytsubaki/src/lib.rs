@@ -1,6 +1,7 @@
 use pyo3::prelude::*;
 use xraytsubaki::prelude::*;
 
+pub mod examples;
 pub mod xasgroup;
 pub mod xasspectrum;
 
@@ -14,5 +15,9 @@ fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
 #[pymodule]
 fn py_xraytsubaki(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
+    m.add_class::<xasspectrum::PyXASSpectrum>()?;
+    m.add_class::<xasgroup::PyXASGroup>()?;
+    m.add_function(wrap_pyfunction!(examples::example_spectrum, m)?)?;
+    m.add_function(wrap_pyfunction!(examples::example_group, m)?)?;
     Ok(())
 }
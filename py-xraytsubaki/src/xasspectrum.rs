@@ -1,7 +1,10 @@
 use std::mem;
 
-use numpy::{IntoPyArray, PyArray1, PyReadonlyArray, PyReadonlyArray1};
+use numpy::{Complex64, IntoPyArray, PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use xraytsubaki::xafs::config::user_config;
 use xraytsubaki::{prelude::*, xafs::xasspectrum};
 
 #[pyclass]
@@ -11,6 +14,142 @@ pub struct PyXASSpectrum {
     pub xasspectrum: XASSpectrum,
 }
 
+fn to_pyerr(err: Box<dyn std::error::Error>) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+#[pymethods]
+impl PyXASSpectrum {
+    /// Spectrum from energy and mu, with the FT and background defaults of the user
+    /// configuration file.
+    #[new]
+    pub fn new(energy: PyReadonlyArray1<f64>, mu: PyReadonlyArray1<f64>) -> PyResult<Self> {
+        let mut xasspectrum = XASSpectrum::new();
+        xasspectrum
+            .set_spectrum(energy.as_array().to_owned(), mu.as_array().to_owned())
+            .fill_from_config(&user_config());
+
+        Ok(PyXASSpectrum { xasspectrum })
+    }
+
+    pub fn find_e0(&mut self) -> PyResult<()> {
+        self.xasspectrum.find_e0().map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    pub fn normalize(&mut self) -> PyResult<()> {
+        self.xasspectrum.normalize().map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    pub fn calc_background(&mut self) -> PyResult<()> {
+        self.xasspectrum.calc_background().map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    pub fn fft(&mut self) -> PyResult<()> {
+        self.xasspectrum.fft().map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    pub fn ifft(&mut self) -> PyResult<()> {
+        self.xasspectrum.ifft().map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    /// Shift the spectrum by `delta_e` eV. With `resample=True`, mu is interpolated back onto
+    /// the original energy grid instead of relabelling the energies.
+    #[pyo3(signature = (delta_e, resample = false))]
+    pub fn shift_energy(&mut self, delta_e: f64, resample: bool) -> PyResult<()> {
+        self.xasspectrum
+            .shift_energy(delta_e, resample)
+            .map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    /// Process the spectrum with the named preset ("xanes-quick", "exafs-standard",
+    /// "publication" or "electron-yield"), replacing its processing parameters.
+    pub fn apply_preset(&mut self, name: &str) -> PyResult<()> {
+        let preset = name.parse::<Preset>().map_err(to_pyerr)?;
+        preset.apply(&mut self.xasspectrum).map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    /// Return `(x, y, extras)` for the plot `kind` ("mu", "norm", "flat", "chi", "k2chi",
+    /// "chik", "chir_mag", "chir_re", "chir_im" or "chiq").
+    ///
+    /// `extras` is a dict of additional curves on the same x axis, e.g. "window" for k- and
+    /// R-space plots or "pre_edge"/"post_edge"/"bkg" for "mu". With `pixels`, the data is
+    /// min/max decimated for a plot of that width; otherwise data with more points than the
+    /// plot_max_points processing option is decimated to that many points.
+    #[pyo3(signature = (kind, pixels = None))]
+    pub fn plot_data<'py>(
+        &self,
+        py: Python<'py>,
+        kind: &str,
+        pixels: Option<usize>,
+    ) -> PyResult<(&'py PyArray1<f64>, &'py PyArray1<f64>, &'py PyDict)> {
+        let kind = kind.parse::<PlotKind>().map_err(to_pyerr)?;
+        let data = match pixels {
+            Some(pixels) => self.xasspectrum.plot_data_decimated(kind, pixels),
+            None => self.xasspectrum.display_data(kind),
+        }
+        .map_err(to_pyerr)?;
+
+        let extras = PyDict::new(py);
+        for (name, y) in data.extras {
+            extras.set_item(name, y.into_pyarray(py))?;
+        }
+
+        Ok((data.x.into_pyarray(py), data.y.into_pyarray(py), extras))
+    }
+
+    /// Return `(x, y)` of the spectrum in `space` ("E", "k", "R" or "q") weighted by
+    /// k^`kweight`.
+    ///
+    /// `kweight=None` uses the kweight of the forward FT. Changing the kweight does not modify
+    /// the spectrum.
+    #[pyo3(signature = (space, kweight = None))]
+    pub fn view<'py>(
+        &self,
+        py: Python<'py>,
+        space: &str,
+        kweight: Option<i32>,
+    ) -> PyResult<(&'py PyArray1<f64>, &'py PyArray1<f64>)> {
+        let space = space.parse::<Space>().map_err(to_pyerr)?;
+        let (x, y) = self.xasspectrum.view(space, kweight).map_err(to_pyerr)?;
+
+        Ok((x.into_pyarray(py), y.into_pyarray(py)))
+    }
+
+    /// Return chi(R) as a complex numpy array on the R grid, or None before the forward FT.
+    pub fn chir<'py>(&self, py: Python<'py>) -> Option<&'py PyArray1<Complex64>> {
+        self.xasspectrum
+            .get_chir_complex()
+            .map(|chir| chir.into_pyarray(py))
+    }
+
+    /// Return the results as a dict with the attribute names of a Larch group ("energy",
+    /// "mu", "norm", "e0", "k", "chi", "r", "chir", "chir_mag", ...), with numpy arrays, and the
+    /// processing parameters under "pre_edge_details", "autobk_details", "xftf_details" and
+    /// "xftr_details".
+    pub fn to_larch_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let scalars = self.xasspectrum.larch_scalars().to_string();
+        let dict: &PyDict = py
+            .import("json")?
+            .call_method1("loads", (scalars,))?
+            .downcast()?;
+        for (name, array) in self.xasspectrum.larch_arrays() {
+            dict.set_item(name, array.into_pyarray(py))?;
+        }
+        if let Some(chir) = self.chir(py) {
+            dict.set_item("chir", chir)?;
+        }
+
+        Ok(dict)
+    }
+}
+
 // #[pymethods]
 // #[allow(clippy::should_implement_trait)]
 // impl PyXASSpectrum {
@@ -0,0 +1,146 @@
+use dioxus::prelude::*;
+use xraytsubaki::prelude::*;
+use xraytsubaki::xafs::config::user_config;
+
+// Snapshot of a row, so that the group is not borrowed while rendering.
+#[derive(PartialEq, Clone)]
+struct GroupRow {
+    index: usize,
+    name: String,
+    selected: bool,
+    visible: bool,
+    metadata: Vec<String>,
+}
+
+fn group_rows(group: &XASGroup, metadata_keys: &[String]) -> Vec<GroupRow> {
+    group
+        .spectra
+        .iter()
+        .enumerate()
+        .map(|(index, spectrum)| GroupRow {
+            index,
+            name: spectrum.name.clone().unwrap_or_default(),
+            selected: group.is_selected(index),
+            visible: group.is_visible(index),
+            metadata: metadata_keys
+                .iter()
+                .map(|key| match spectrum.get_metadata(key) {
+                    Some(value) => value
+                        .as_str()
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| value.to_string()),
+                    None => String::new(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Table of the spectra loaded in the shared XASGroup.
+///
+/// Requires an `XASGroup` to be provided with `use_shared_state_provider`.
+pub fn GroupManager(cx: Scope) -> Element {
+    let group = use_shared_state::<XASGroup>(cx)?;
+
+    let metadata_keys = group.read().metadata_keys();
+    let rows = group_rows(&group.read(), &metadata_keys);
+    let n_spectra = rows.len();
+
+    let button_class = "px-2 py-1 border border-gray-300 rounded hover:bg-gray-100";
+
+    render! {
+        div { class: "w-full p-2",
+            div { class: "flex items-center gap-2 mb-2",
+                button { class: button_class, onclick: move |_| { group.write().select_all(); }, "Select all" }
+                button { class: button_class, onclick: move |_| { group.write().clear_selection(); }, "Clear selection" }
+                button { class: button_class, onclick: move |_| { group.write().show_selected_only(); }, "Show selected" }
+                button {
+                    class: button_class,
+                    title: "Set the FT and background parameters that are not set to the defaults of the configuration file",
+                    onclick: move |_| { group.write().fill_from_config(&user_config()); },
+                    "Apply user defaults"
+                }
+                button {
+                    class: button_class,
+                    onclick: move |_| {
+                        if let Err(e) = group.write().remove_selected() {
+                            log::error!("failed to remove spectra: {}", e);
+                        }
+                    },
+                    "Delete selected"
+                }
+            }
+
+            table { class: "w-full text-sm text-left table-auto",
+                thead { class: "border-b border-gray-200",
+                    tr {
+                        th { "" }
+                        th { "Show" }
+                        th { "Name" }
+                        metadata_keys.iter().map(|key| rsx! { th { key: "{key}", "{key}" } })
+                        th { "" }
+                    }
+                }
+                tbody {
+                    rows.into_iter().map(|row| {
+                        let index = row.index;
+                        rsx! {
+                            tr { key: "{index}", class: if row.selected { "bg-sky-100" } else { "" },
+                                td {
+                                    input {
+                                        r#type: "checkbox",
+                                        checked: row.selected,
+                                        onclick: move |_| {
+                                            let _ = group.write().toggle_selected(index);
+                                        }
+                                    }
+                                }
+                                td {
+                                    input {
+                                        r#type: "checkbox",
+                                        checked: row.visible,
+                                        onclick: move |_| {
+                                            let visible = group.read().is_visible(index);
+                                            let _ = group.write().set_visible(index, !visible);
+                                        }
+                                    }
+                                }
+                                td {
+                                    input {
+                                        class: "w-full bg-transparent",
+                                        value: "{row.name}",
+                                        onchange: move |evt| {
+                                            let _ = group.write().rename_spectrum(index, evt.value.clone());
+                                        }
+                                    }
+                                }
+                                row.metadata.iter().enumerate().map(|(i, value)| rsx! { td { key: "{i}", "{value}" } })
+                                td { class: "flex gap-1",
+                                    button {
+                                        class: button_class,
+                                        disabled: index == 0,
+                                        onclick: move |_| { group.write().move_spectrum(index, index - 1); },
+                                        "↑"
+                                    }
+                                    button {
+                                        class: button_class,
+                                        disabled: index + 1 >= n_spectra,
+                                        onclick: move |_| { group.write().move_spectrum(index, index + 2); },
+                                        "↓"
+                                    }
+                                    button {
+                                        class: button_class,
+                                        onclick: move |_| {
+                                            let _ = group.write().remove_spectrum(index);
+                                        },
+                                        "Delete"
+                                    }
+                                }
+                            }
+                        }
+                    })
+                }
+            }
+        }
+    }
+}
@@ -19,6 +19,13 @@ use menu::Navibar;
 mod footer;
 use footer::Footer;
 
+#[cfg(feature = "desktop")]
+mod group_manager;
+#[cfg(feature = "desktop")]
+use group_manager::GroupManager;
+#[cfg(feature = "desktop")]
+use xraytsubaki::prelude::XASGroup;
+
 #[cfg(target_arch = "wasm32")]
 const TOP_DIR: &'static str = "./";
 
@@ -105,6 +112,25 @@ fn Home(cx: Scope) -> Element {
     })
 }
 
+#[cfg(feature = "desktop")]
+fn XAS(cx: Scope) -> Element {
+    use_shared_state_provider(cx, XASGroup::new);
+
+    cx.render(rsx! {
+        div { class: "bg-[#fafafa]",
+
+            Navibar {}
+
+            div { h1 { "XAS" } }
+
+            GroupManager {}
+
+            Footer {}
+        }
+    })
+}
+
+#[cfg(not(feature = "desktop"))]
 fn XAS(cx: Scope) -> Element {
     cx.render(rsx! {
         div { class: "bg-[#fafafa]",